@@ -96,6 +96,7 @@ pub(crate) async fn build_cargo(name: &str, rust_build: &OwnedRustBuild) -> Resu
         service_name: name.to_string(),
         success: true,
         output_path: if output_path.exists() { Some(output_path) } else { None },
+        attestation_path: None,
         stdout,
         stderr,
     })
@@ -1,17 +1,22 @@
 mod cargo;
+mod provenance;
 
 use crate::cargo::build_cargo;
+use degov_crypto::StackKey;
 use dgv_core::v1::service::ServiceBuild;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use thiserror::Error;
 
 pub use cargo::CargoBuildError;
+pub use provenance::ProvenanceError;
 
 /// Error types for the application builder
 #[derive(Debug, Error)]
 pub enum BuildError {
     #[error("Cargo build error: {0}")]
     Cargo(#[from] CargoBuildError),
+    #[error("Provenance error: {0}")]
+    Provenance(#[from] ProvenanceError),
     #[error("Build failed for service: {0}")]
     ServiceFailed(String),
 }
@@ -22,6 +27,7 @@ pub type BuildResult<T> = Result<T, BuildError>;
 /// Application builder that can build multiple services concurrently
 pub struct AppBuilder {
     services: Vec<(String, OwnedServiceBuild)>,
+    signing_key: Option<StackKey>,
 }
 
 /// Owned version of RustBuild for internal use
@@ -54,9 +60,16 @@ impl AppBuilder {
     pub fn new() -> Self {
         Self {
             services: Vec::new(),
+            signing_key: None,
         }
     }
 
+    /// Sign every built artifact with the stack key and emit its provenance
+    pub fn with_signing_key(mut self, key: StackKey) -> Self {
+        self.signing_key = Some(key);
+        self
+    }
+
     /// Add a service to be built
     pub fn add_service<'a>(&mut self, name: String, build: ServiceBuild<'a>) {
         self.services.push((name, build.into()));
@@ -69,8 +82,9 @@ impl AppBuilder {
         for (name, service_build) in &self.services {
             let name = name.clone();
             let build = service_build.clone();
+            let key = self.signing_key.clone();
             tasks.push(tokio::spawn(async move {
-                build_service(&name, &build).await
+                build_service(&name, &build, key.as_ref()).await
             }));
         }
 
@@ -99,15 +113,22 @@ pub struct BuildOutput {
     pub service_name: String,
     pub success: bool,
     pub output_path: Option<PathBuf>,
+    /// Signed provenance of the artifact, when built with a signing key
+    pub attestation_path: Option<PathBuf>,
     pub stdout: String,
     pub stderr: String,
 }
 
 /// Build a single service based on its build configuration
-async fn build_service(name: &str, build: &OwnedServiceBuild) -> BuildResult<BuildOutput> {
+async fn build_service(name: &str, build: &OwnedServiceBuild, key: Option<&StackKey>) -> BuildResult<BuildOutput> {
     match build {
         OwnedServiceBuild::Rust(rust_build) => {
-            let output = build_cargo(name, &rust_build).await?;
+            let mut output = build_cargo(name, &rust_build).await?;
+            if let (Some(key), Some(artifact_path)) = (key, &output.output_path) {
+                let work_dir = rust_build.path.as_deref().unwrap_or_else(|| Path::new("."));
+                output.attestation_path =
+                    Some(provenance::attest(name, rust_build, work_dir, artifact_path, key).await?);
+            }
             Ok(output)
         }
     }
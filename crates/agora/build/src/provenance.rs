@@ -0,0 +1,160 @@
+use crate::OwnedRustBuild;
+use degov_crypto::{CryptoError, Envelope, StackKey, Statement, Subject};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+use tokio::process::Command;
+
+/// Predicate type of SLSA build provenance
+const SLSA_PROVENANCE_V1: &str = "https://slsa.dev/provenance/v1";
+
+/// Build type recorded for Cargo builds
+const CARGO_BUILD_TYPE: &str = "https://github.com/linlogge/degov/agora-build/cargo@v1";
+
+/// Error types for provenance attestation
+#[derive(Debug, Error)]
+pub enum ProvenanceError {
+    #[error("Failed to read build inputs: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Failed to query toolchain: {0}")]
+    Toolchain(String),
+    #[error("Failed to sign provenance: {0}")]
+    Signing(#[from] CryptoError),
+}
+
+/// Sign a built artifact and write its provenance next to it
+///
+/// The attestation is a DSSE envelope around an in-toto statement with an
+/// SLSA v1 predicate recording the source commit, the toolchain and a digest
+/// of the build inputs. It is written to `<artifact>.intoto.json`.
+pub(crate) async fn attest(
+    name: &str,
+    rust_build: &OwnedRustBuild,
+    work_dir: &Path,
+    artifact_path: &Path,
+    key: &StackKey,
+) -> Result<PathBuf, ProvenanceError> {
+    let artifact = tokio::fs::read(artifact_path).await?;
+    let artifact_name = artifact_path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| name.to_string());
+
+    let rustc = tool_version(work_dir, "rustc").await?;
+    let cargo = tool_version(work_dir, "cargo").await?;
+    let commit = source_commit(work_dir).await;
+    let inputs = inputs_digest(work_dir)?;
+
+    let mut dependencies = vec![serde_json::json!({
+        "name": "inputs",
+        "digest": { "sha256": inputs },
+    })];
+    if let Some(commit) = commit {
+        dependencies.push(serde_json::json!({
+            "name": "source",
+            "digest": { "gitCommit": commit },
+        }));
+    }
+
+    let predicate = serde_json::json!({
+        "buildDefinition": {
+            "buildType": CARGO_BUILD_TYPE,
+            "externalParameters": {
+                "service": name,
+                "target": rust_build.target,
+            },
+            "internalParameters": {
+                "rustc": rustc,
+                "cargo": cargo,
+            },
+            "resolvedDependencies": dependencies,
+        },
+        "runDetails": {
+            "builder": { "id": key.did().to_string() },
+        },
+    });
+
+    let statement = Statement::new(
+        vec![Subject::sha256(artifact_name.clone(), &artifact)],
+        SLSA_PROVENANCE_V1,
+        predicate,
+    );
+    let envelope = Envelope::sign_statement(&statement, key)?;
+
+    let attestation_path = artifact_path.with_file_name(format!("{}.intoto.json", artifact_name));
+    let json = serde_json::to_vec_pretty(&envelope).map_err(std::io::Error::other)?;
+    tokio::fs::write(&attestation_path, json).await?;
+
+    tracing::info!("Signed '{}' as {}", name, key.did());
+    Ok(attestation_path)
+}
+
+/// Version line of a toolchain binary, as resolved in the build directory
+async fn tool_version(work_dir: &Path, tool: &str) -> Result<String, ProvenanceError> {
+    let output = Command::new(tool)
+        .arg("--version")
+        .current_dir(work_dir)
+        .output()
+        .await
+        .map_err(|e| ProvenanceError::Toolchain(format!("Failed to spawn {}: {}", tool, e)))?;
+    if !output.status.success() {
+        return Err(ProvenanceError::Toolchain(format!("{} --version failed", tool)));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Commit checked out in the build directory, if it is in a git repository
+async fn source_commit(work_dir: &Path) -> Option<String> {
+    let output = Command::new("git")
+        .args(["rev-parse", "HEAD"])
+        .current_dir(work_dir)
+        .output()
+        .await
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Digest over the manifest, lockfile, build script and sources of a crate
+///
+/// Files are hashed in path order together with their relative path, so
+/// moving or renaming a source file changes the digest.
+fn inputs_digest(work_dir: &Path) -> Result<String, ProvenanceError> {
+    let mut files = Vec::new();
+    for name in ["Cargo.toml", "Cargo.lock", "build.rs"] {
+        let path = work_dir.join(name);
+        if path.is_file() {
+            files.push(path);
+        }
+    }
+    let src = work_dir.join("src");
+    if src.is_dir() {
+        collect_files(&src, &mut files)?;
+    }
+    files.sort();
+
+    let mut hasher = Sha256::new();
+    for path in files {
+        let relative = path.strip_prefix(work_dir).unwrap_or(&path);
+        let contents = std::fs::read(&path)?;
+        hasher.update(relative.to_string_lossy().as_bytes());
+        hasher.update([0]);
+        hasher.update((contents.len() as u64).to_be_bytes());
+        hasher.update(&contents);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,110 @@
+//! Per-process CPU accounting.
+//!
+//! Every Wasm process gets a [`ProcessUsage`] that is updated while it runs: the time spent
+//! polling the process future (guest code plus host calls) and the wasmtime epochs it consumed
+//! before being forced to yield. Snapshots are exposed through
+//! [`Environment::cpu_usage`](crate::env::Environment::cpu_usage).
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+/// Live CPU counters of one process.
+#[derive(Debug, Default)]
+pub struct ProcessUsage {
+    cpu_nanos: AtomicU64,
+    epochs: AtomicU64,
+    yields: AtomicU64,
+}
+
+impl ProcessUsage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records that the process used up an epoch budget and was made to yield.
+    pub(crate) fn record_yield(&self, epochs: u64) {
+        self.epochs.fetch_add(epochs, Ordering::Relaxed);
+        self.yields.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn record_cpu(&self, elapsed: Duration) {
+        let nanos = u64::try_from(elapsed.as_nanos()).unwrap_or(u64::MAX);
+        self.cpu_nanos.fetch_add(nanos, Ordering::Relaxed);
+    }
+
+    /// Returns the current counter values.
+    pub fn snapshot(&self) -> CpuUsage {
+        CpuUsage {
+            cpu_time: Duration::from_nanos(self.cpu_nanos.load(Ordering::Relaxed)),
+            epochs: self.epochs.load(Ordering::Relaxed),
+            yields: self.yields.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// CPU consumed by a process so far.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CpuUsage {
+    /// Time spent running the process, including host calls.
+    pub cpu_time: Duration,
+    /// Epochs consumed, counted in whole budgets at each forced yield.
+    pub epochs: u64,
+    /// Number of times the process ran out of budget and was made to yield.
+    pub yields: u64,
+}
+
+/// Wraps a process future and adds the time spent polling it to the process' usage.
+pub(crate) struct Metered<F> {
+    inner: Pin<Box<F>>,
+    usage: Arc<ProcessUsage>,
+}
+
+impl<F: Future> Metered<F> {
+    pub(crate) fn new(inner: F, usage: Arc<ProcessUsage>) -> Self {
+        Self {
+            inner: Box::pin(inner),
+            usage,
+        }
+    }
+}
+
+impl<F: Future> Future for Metered<F> {
+    type Output = F::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let start = Instant::now();
+        let poll = self.inner.as_mut().poll(cx);
+        self.usage.record_cpu(start.elapsed());
+        poll
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn metered_future_records_cpu_time() {
+        let usage = Arc::new(ProcessUsage::new());
+        let busy = async {
+            let start = Instant::now();
+            while start.elapsed() < Duration::from_millis(5) {
+                std::hint::spin_loop();
+            }
+            tokio::task::yield_now().await;
+            42
+        };
+
+        assert_eq!(Metered::new(busy, usage.clone()).await, 42);
+        usage.record_yield(10);
+
+        let snapshot = usage.snapshot();
+        assert!(snapshot.cpu_time >= Duration::from_millis(5));
+        assert_eq!(snapshot.epochs, 10);
+        assert_eq!(snapshot.yields, 1);
+    }
+}
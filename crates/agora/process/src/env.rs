@@ -6,7 +6,7 @@ use std::sync::{
     Arc,
 };
 
-use crate::{Process, Signal};
+use crate::{Process, Signal, accounting::CpuUsage};
 
 #[async_trait]
 pub trait Environment: Send + Sync {
@@ -16,6 +16,8 @@ pub trait Environment: Send + Sync {
     fn add_process(&self, id: u64, proc: Arc<dyn Process>);
     fn remove_process(&self, id: u64);
     fn process_count(&self) -> usize;
+    /// CPU consumed so far by each accounted process, by process ID.
+    fn cpu_usage(&self) -> Vec<(u64, CpuUsage)>;
     async fn can_spawn_next_process(&self) -> Result<Option<()>>;
     fn send(&self, id: u64, signal: Signal);
 }
@@ -63,6 +65,13 @@ impl Environment for DegovEnvironment {
         self.processes.len()
     }
 
+    fn cpu_usage(&self) -> Vec<(u64, CpuUsage)> {
+        self.processes
+            .iter()
+            .filter_map(|entry| Some((*entry.key(), entry.value().cpu_usage()?)))
+            .collect()
+    }
+
     fn send(&self, id: u64, signal: Signal) {
         if let Some(proc) = self.processes.get(&id) {
             proc.send(signal);
@@ -0,0 +1,198 @@
+//! Host API offered to service components.
+//!
+//! The API is defined by the versioned WIT package in `wit/host.wit` ([`WIT`]), which service
+//! authors code against. Each capability is backed by an implementation the embedder hands to the
+//! process state through [`HostApi`]; a capability without a backend answers with
+//! `host-error::unavailable`, so a component can run on nodes that only offer part of the API.
+
+use std::sync::Arc;
+
+use anyhow::Result;
+use async_trait::async_trait;
+use tracing::{debug, error, info, trace, warn};
+use wasmtime::component::{HasSelf, Linker};
+
+use crate::state::DefaultProcessState;
+
+wasmtime::component::bindgen!({
+    path: "wit",
+    world: "service",
+    imports: { default: async },
+});
+
+use degov::host::credentials::VerifiedCredential;
+use degov::host::logging::Level;
+use degov::host::storage::ProvenValue;
+use degov::host::types::HostError;
+
+/// WIT package name and version of the host API.
+pub const WIT_PACKAGE: &str = "degov:host@0.1.0";
+
+/// WIT source of the host API, published for service authors.
+pub const WIT: &str = include_str!("../wit/host.wit");
+
+/// Workflow instance a component runs for.
+#[async_trait]
+pub trait WorkflowContext: Send + Sync {
+    fn instance_id(&self) -> String;
+    async fn get(&self, key: &str) -> Result<Option<serde_json::Value>>;
+    async fn set(&self, key: &str, value: serde_json::Value) -> Result<()>;
+}
+
+/// Key-value storage that proves what it returns.
+#[async_trait]
+pub trait ProvenStore: Send + Sync {
+    async fn get(&self, key: &str) -> Result<StoreValue>;
+    async fn put(&self, key: &str, value: Vec<u8>) -> Result<StoreValue>;
+}
+
+/// A stored value with the proof made against the store's root.
+#[derive(Debug, Clone)]
+pub struct StoreValue {
+    pub value: Option<Vec<u8>>,
+    pub root: Vec<u8>,
+    pub proof: Vec<u8>,
+}
+
+/// Checks credentials presented by components.
+///
+/// An error means the credential was rejected; the message is passed on to the component.
+#[async_trait]
+pub trait CredentialVerifier: Send + Sync {
+    async fn verify(&self, credential: &str) -> Result<Credential>;
+}
+
+/// A verified credential.
+#[derive(Debug, Clone)]
+pub struct Credential {
+    pub issuer: String,
+    pub subject: Option<String>,
+    pub claims: serde_json::Value,
+}
+
+/// Backends of the host API available to a process.
+#[derive(Clone, Default)]
+pub struct HostApi {
+    workflow: Option<Arc<dyn WorkflowContext>>,
+    storage: Option<Arc<dyn ProvenStore>>,
+    credentials: Option<Arc<dyn CredentialVerifier>>,
+}
+
+impl HostApi {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_workflow_context(mut self, workflow: Arc<dyn WorkflowContext>) -> Self {
+        self.workflow = Some(workflow);
+        self
+    }
+
+    pub fn with_storage(mut self, storage: Arc<dyn ProvenStore>) -> Self {
+        self.storage = Some(storage);
+        self
+    }
+
+    pub fn with_credential_verifier(mut self, credentials: Arc<dyn CredentialVerifier>) -> Self {
+        self.credentials = Some(credentials);
+        self
+    }
+}
+
+/// Registers the host API with a linker of [`DefaultProcessState`] processes.
+pub fn add_to_linker(linker: &mut Linker<DefaultProcessState>) -> Result<()> {
+    Service::add_to_linker::<_, HasSelf<_>>(linker, |state| state)
+}
+
+fn internal(e: anyhow::Error) -> HostError {
+    HostError::Internal(e.to_string())
+}
+
+impl degov::host::types::Host for DefaultProcessState {}
+
+impl degov::host::logging::Host for DefaultProcessState {
+    async fn log(&mut self, level: Level, message: String) {
+        let process = self.id;
+        match level {
+            Level::Trace => trace!(target: "agora::guest", process, "{message}"),
+            Level::Debug => debug!(target: "agora::guest", process, "{message}"),
+            Level::Info => info!(target: "agora::guest", process, "{message}"),
+            Level::Warn => warn!(target: "agora::guest", process, "{message}"),
+            Level::Error => error!(target: "agora::guest", process, "{message}"),
+        }
+    }
+}
+
+impl degov::host::workflow::Host for DefaultProcessState {
+    async fn instance_id(&mut self) -> Option<String> {
+        self.host.workflow.as_ref().map(|workflow| workflow.instance_id())
+    }
+
+    async fn get_context(&mut self, key: String) -> Result<Option<String>, HostError> {
+        let workflow = self.host.workflow.as_ref().ok_or(HostError::Unavailable)?;
+        let value = workflow.get(&key).await.map_err(internal)?;
+        Ok(value.map(|value| value.to_string()))
+    }
+
+    async fn set_context(&mut self, key: String, value: String) -> Result<(), HostError> {
+        let workflow = self.host.workflow.as_ref().ok_or(HostError::Unavailable)?;
+        let value = serde_json::from_str(&value)
+            .map_err(|e| HostError::Invalid(format!("value is not JSON: {e}")))?;
+        workflow.set(&key, value).await.map_err(internal)
+    }
+}
+
+impl From<StoreValue> for ProvenValue {
+    fn from(value: StoreValue) -> Self {
+        Self {
+            value: value.value,
+            root: value.root,
+            proof: value.proof,
+        }
+    }
+}
+
+impl degov::host::storage::Host for DefaultProcessState {
+    async fn get(&mut self, key: String) -> Result<ProvenValue, HostError> {
+        let storage = self.host.storage.as_ref().ok_or(HostError::Unavailable)?;
+        storage.get(&key).await.map(Into::into).map_err(internal)
+    }
+
+    async fn put(&mut self, key: String, value: Vec<u8>) -> Result<ProvenValue, HostError> {
+        let storage = self.host.storage.as_ref().ok_or(HostError::Unavailable)?;
+        storage.put(&key, value).await.map(Into::into).map_err(internal)
+    }
+}
+
+impl degov::host::credentials::Host for DefaultProcessState {
+    async fn verify(&mut self, credential: String) -> Result<VerifiedCredential, HostError> {
+        let credentials = self.host.credentials.as_ref().ok_or(HostError::Unavailable)?;
+        let verified = credentials
+            .verify(&credential)
+            .await
+            .map_err(|e| HostError::Invalid(e.to_string()))?;
+        Ok(VerifiedCredential {
+            issuer: verified.issuer,
+            subject: verified.subject,
+            claims: verified.claims.to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime::wasmtime::default_config;
+
+    #[test]
+    fn wit_declares_the_published_version() {
+        assert!(WIT.contains(&format!("package {WIT_PACKAGE};")));
+    }
+
+    #[test]
+    fn host_api_links() {
+        let engine = wasmtime::Engine::new(&default_config()).unwrap();
+        let mut linker = Linker::<DefaultProcessState>::new(&engine);
+        add_to_linker(&mut linker).unwrap();
+    }
+}
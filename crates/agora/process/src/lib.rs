@@ -8,14 +8,17 @@ use std::sync::Arc;
 use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
 use tracing::{debug, trace, warn};
 
+pub mod accounting;
 pub mod config;
 pub mod env;
+pub mod host;
 mod mailbox;
 mod message;
 pub mod runtime;
 pub mod state;
 pub mod wasm;
 
+use crate::accounting::{CpuUsage, ProcessUsage};
 use crate::env::Environment;
 use crate::mailbox::MessageMailbox;
 use crate::message::Message;
@@ -34,6 +37,10 @@ use runtime::wasmtime::WasmtimeRuntime;
 pub trait Process: Send + Sync {
     fn id(&self) -> u64;
     fn send(&self, signal: Signal);
+    /// CPU consumed by the process so far, if it is accounted.
+    fn cpu_usage(&self) -> Option<CpuUsage> {
+        None
+    }
 }
 
 impl Debug for dyn Process {
@@ -114,12 +121,22 @@ pub enum Finished<T> {
 pub struct WasmProcess {
     id: u64,
     signal_mailbox: UnboundedSender<Signal>,
+    usage: Arc<ProcessUsage>,
 }
 
 impl WasmProcess {
     /// Create a new WasmProcess
     pub fn new(id: u64, signal_mailbox: UnboundedSender<Signal>) -> Self {
-        Self { id, signal_mailbox }
+        Self {
+            id,
+            signal_mailbox,
+            usage: Arc::new(ProcessUsage::new()),
+        }
+    }
+
+    /// Returns the live CPU counters of the process.
+    pub fn usage(&self) -> &Arc<ProcessUsage> {
+        &self.usage
     }
 }
 
@@ -135,6 +152,10 @@ impl Process for WasmProcess {
         // to relay on it and could signal wrong guarantees to users.
         let _ = self.signal_mailbox.send(signal);
     }
+
+    fn cpu_usage(&self) -> Option<CpuUsage> {
+        Some(self.usage.snapshot())
+    }
 }
 
 /// Enum containing a process name if available, otherwise its ID.
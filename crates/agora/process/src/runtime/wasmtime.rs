@@ -1,25 +1,60 @@
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::{Context, Result};
 use wasmtime::{
-    Engine, ResourceLimiter,
+    Engine, ResourceLimiter, UpdateDeadline,
     component::{Component, Val},
 };
 
 use crate::{
-    ExecutionResult, ResultValue, config::{ProcessConfig, UNIT_OF_COMPUTE_IN_INSTRUCTIONS}, runtime::RawWasm,
-    state::ProcessState,
+    ExecutionResult, ResultValue, accounting::ProcessUsage,
+    config::{ProcessConfig, UNIT_OF_COMPUTE_IN_INSTRUCTIONS}, runtime::RawWasm, state::ProcessState,
 };
 
+/// Interval at which the engine epoch is incremented.
+pub const EPOCH_TICK: Duration = Duration::from_millis(1);
+
+/// Epochs a process may run before it has to yield to other processes (10ms).
+pub const DEFAULT_EPOCH_BUDGET: u64 = 10;
+
 #[derive(Clone)]
 pub struct WasmtimeRuntime {
     engine: wasmtime::Engine,
+    epoch_budget: u64,
 }
 
 impl WasmtimeRuntime {
+    /// Creates a runtime and starts the thread driving its epochs.
+    ///
+    /// Processes only yield at epoch deadlines if `config` enables epoch interruption, as
+    /// [`default_config`] does. The epoch thread exits once the runtime is dropped.
     pub fn try_new(config: &wasmtime::Config) -> Result<Self> {
         let engine = wasmtime::Engine::new(config)?;
-        Ok(Self { engine })
+        let weak = engine.weak();
+        std::thread::Builder::new()
+            .name("wasmtime-epoch".to_string())
+            .spawn(move || {
+                while let Some(engine) = weak.upgrade() {
+                    engine.increment_epoch();
+                    drop(engine);
+                    std::thread::sleep(EPOCH_TICK);
+                }
+            })?;
+
+        Ok(Self {
+            engine,
+            epoch_budget: DEFAULT_EPOCH_BUDGET,
+        })
+    }
+
+    /// Sets how many epochs a process may run before it has to yield.
+    ///
+    /// A busy-looping process is suspended at the end of every budget, giving all other
+    /// processes on the same executor a chance to run before it continues.
+    pub fn with_epoch_budget(mut self, epochs: u64) -> Self {
+        self.epoch_budget = epochs.max(1);
+        self
     }
 
     pub fn compile_component<T>(&self, data: RawWasm) -> Result<WasmtimeCompiledComponent<T>>
@@ -39,6 +74,7 @@ impl WasmtimeRuntime {
         &self,
         compiled_component: &WasmtimeCompiledComponent<T>,
         state: T,
+        usage: Arc<ProcessUsage>,
     ) -> Result<WasmtimeInstance<T>>
     where
         T: ProcessState + Send + ResourceLimiter + 'static,
@@ -49,6 +85,13 @@ impl WasmtimeRuntime {
         store.limiter(|state| state);
         // Trap if out of fuel
         //store.set_fuel(max_fuel)?;
+        // Yield back to the executor each time the epoch budget is used up
+        let budget = self.epoch_budget;
+        store.set_epoch_deadline(budget);
+        store.epoch_deadline_callback(move |_| {
+            usage.record_yield(budget);
+            Ok(UpdateDeadline::Yield(budget))
+        });
 
         // Create instance
         let instance = compiled_component
@@ -231,6 +274,8 @@ pub fn default_config() -> wasmtime::Config {
         .debug_info(true)
         // The behavior of fuel running out is defined on the Store
         //.consume_fuel(true)
+        // Lets the runtime preempt processes that don't yield on their own
+        .epoch_interruption(true)
         .wasm_reference_types(true)
         .wasm_bulk_memory(true)
         .wasm_multi_value(true)
@@ -14,6 +14,7 @@ use crate::{
     Signal, WasmtimeRuntime,
     config::{DefaultProcessConfig, ProcessConfig},
     env::{DegovEnvironment, Environment},
+    host::{self, HostApi},
     mailbox::MessageMailbox,
     message::Message,
     runtime::wasmtime::WasmtimeCompiledComponent,
@@ -94,6 +95,8 @@ pub struct DefaultProcessState {
     registry: Arc<RwLock<HashMap<String, (u64, u64)>>>,
     wasi: std::sync::Mutex<WasiCtx>,
     table: std::sync::Mutex<ResourceTable>,
+    // Backends of the degov host API
+    pub(crate) host: HostApi,
 }
 
 impl DefaultProcessState {
@@ -119,9 +122,16 @@ impl DefaultProcessState {
             registry: Arc::new(RwLock::new(HashMap::new())),
             wasi: std::sync::Mutex::new(WasiCtxBuilder::new().inherit_stdio().build()),
             table: std::sync::Mutex::new(ResourceTable::default()),
+            host: HostApi::default(),
         };
         Ok(state)
     }
+
+    /// Sets the backends of the degov host API available to the process.
+    pub fn with_host_api(mut self, host: HostApi) -> Self {
+        self.host = host;
+        self
+    }
 }
 
 impl WasiView for DefaultProcessState {
@@ -147,6 +157,7 @@ impl ProcessState for DefaultProcessState {
 
     fn register(linker: &mut Linker<Self>) -> Result<()> {
         wasmtime_wasi::p2::add_to_linker_async(linker)?;
+        host::add_to_linker(linker)?;
         Ok(())
     }
     
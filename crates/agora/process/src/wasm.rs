@@ -5,6 +5,7 @@ use tracing::trace;
 use tokio::task::JoinHandle;
 use wasmtime::{ResourceLimiter, component::Val};
 
+use crate::accounting::Metered;
 use crate::env::Environment;
 use crate::runtime::wasmtime::{WasmtimeCompiledComponent, WasmtimeRuntime};
 use crate::state::ProcessState;
@@ -36,11 +37,13 @@ where
     let signal_mailbox = state.signal_mailbox().clone();
     let message_mailbox = state.message_mailbox().clone();
 
-    let instance = runtime.instantiate(component, state).await?;
+    let child_process_handle = Arc::new(WasmProcess::new(id, signal_mailbox.0.clone()));
+    let usage = child_process_handle.usage().clone();
+
+    let instance = runtime.instantiate(component, state, usage.clone()).await?;
     let function = function.to_string();
-    let fut = async move { instance.call(&function, params).await };
+    let fut = Metered::new(async move { instance.call(&function, params).await }, usage);
     let child_process = crate::new(fut, id, env.clone(), signal_mailbox.1, message_mailbox);
-    let child_process_handle = Arc::new(WasmProcess::new(id, signal_mailbox.0.clone()));
 
     env.add_process(id, child_process_handle.clone());
 
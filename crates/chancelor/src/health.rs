@@ -0,0 +1,100 @@
+//! Active health probing of registered services
+
+use crate::registry::ServiceRegistry;
+use std::time::{Duration, SystemTime};
+
+/// How registered services are probed
+#[derive(Debug, Clone)]
+pub struct HealthConfig {
+    /// Time between probe rounds
+    pub interval: Duration,
+    /// Time a single probe may take before it counts as failed
+    pub timeout: Duration,
+    /// Consecutive failed probes before a service is reported unhealthy
+    pub unhealthy_threshold: u32,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(10),
+            timeout: Duration::from_secs(2),
+            unhealthy_threshold: 3,
+        }
+    }
+}
+
+/// Probes the health endpoint of every registered service
+pub struct HealthProber {
+    registry: ServiceRegistry,
+    config: HealthConfig,
+    client: reqwest::Client,
+}
+
+impl HealthProber {
+    pub fn new(registry: ServiceRegistry, config: HealthConfig) -> anyhow::Result<Self> {
+        let client = reqwest::Client::builder().timeout(config.timeout).build()?;
+        Ok(Self {
+            registry,
+            config,
+            client,
+        })
+    }
+
+    /// Probe all services every interval, forever
+    pub async fn run(self) {
+        let mut interval = tokio::time::interval(self.config.interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            self.probe_all().await;
+        }
+    }
+
+    /// Probe all services with a health endpoint once, concurrently
+    pub async fn probe_all(&self) {
+        let targets: Vec<(String, String)> = self
+            .registry
+            .services()
+            .into_iter()
+            .filter_map(|entry| {
+                let url = entry.registration.health_url?;
+                Some((entry.registration.id, url))
+            })
+            .collect();
+
+        let mut probes = tokio::task::JoinSet::new();
+        for (id, url) in targets {
+            let client = self.client.clone();
+            probes.spawn(async move {
+                let result = probe(&client, &url).await;
+                (id, result)
+            });
+        }
+
+        while let Some(joined) = probes.join_next().await {
+            let Ok((id, result)) = joined else {
+                continue;
+            };
+            match result {
+                Ok(()) => self.registry.probe_succeeded(&id, SystemTime::now()),
+                Err(error) => {
+                    tracing::debug!("Health probe of service {} failed: {}", id, error);
+                    self.registry
+                        .probe_failed(&id, error, self.config.unhealthy_threshold);
+                }
+            }
+        }
+    }
+}
+
+/// Request a health endpoint, succeeding on any 2xx response
+async fn probe(client: &reqwest::Client, url: &str) -> Result<(), String> {
+    let response = client.get(url).send().await.map_err(|e| e.to_string())?;
+    let status = response.status();
+    if status.is_success() {
+        Ok(())
+    } else {
+        Err(format!("Health endpoint returned {}", status))
+    }
+}
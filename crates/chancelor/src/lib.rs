@@ -1,35 +1,145 @@
+pub mod health;
+pub mod registry;
+
 pub mod proto {
     tonic::include_proto!("degov.chancelor");
 }
 
+pub use health::{HealthConfig, HealthProber};
+pub use registry::{HealthStatus, ServiceEntry, ServiceRegistration, ServiceRegistry};
+
 use proto::frontdoor_server::{Frontdoor, FrontdoorServer};
-use proto::{GetServicesRequest, GetServicesResponse};
+use proto::{
+    DeregisterServiceRequest, DeregisterServiceResponse, GetServicesRequest, GetServicesResponse,
+    RegisterServiceRequest, RegisterServiceResponse, WatchServicesRequest,
+};
+use std::pin::Pin;
+use std::time::UNIX_EPOCH;
+use tokio_stream::{Stream, StreamExt};
 use tonic::transport::Server;
 use tonic::{Request, Response, Status};
 
-#[derive(Debug, Default)]
-pub struct FrontdoorImpl {}
+pub struct FrontdoorImpl {
+    registry: ServiceRegistry,
+}
+
+impl FrontdoorImpl {
+    pub fn new(registry: ServiceRegistry) -> Self {
+        Self { registry }
+    }
+}
 
 #[tonic::async_trait]
 impl Frontdoor for FrontdoorImpl {
+    type WatchServicesStream = Pin<Box<dyn Stream<Item = Result<GetServicesResponse, Status>> + Send>>;
+
     async fn get_services(
         &self,
         _request: Request<GetServicesRequest>,
     ) -> Result<Response<GetServicesResponse>, Status> {
-        Ok(Response::new(GetServicesResponse { services: vec![] }))
+        Ok(Response::new(services_response(self.registry.services())))
+    }
+
+    async fn watch_services(
+        &self,
+        _request: Request<WatchServicesRequest>,
+    ) -> Result<Response<Self::WatchServicesStream>, Status> {
+        let updates = tokio_stream::wrappers::WatchStream::new(self.registry.subscribe())
+            .map(|services| Ok(services_response(services.into_values().collect())));
+        Ok(Response::new(Box::pin(updates)))
+    }
+
+    async fn register_service(
+        &self,
+        request: Request<RegisterServiceRequest>,
+    ) -> Result<Response<RegisterServiceResponse>, Status> {
+        let request = request.into_inner();
+        if request.id.is_empty() {
+            return Err(Status::invalid_argument("Service id is required"));
+        }
+        if request.health_url.as_deref().is_some_and(|url| reqwest::Url::parse(url).is_err()) {
+            return Err(Status::invalid_argument("Invalid health_url"));
+        }
+
+        tracing::info!("Registered service {} ({})", request.id, request.name);
+        self.registry.register(ServiceRegistration {
+            id: request.id,
+            name: request.name,
+            endpoint: request.endpoint,
+            health_url: request.health_url,
+        });
+        Ok(Response::new(RegisterServiceResponse {}))
+    }
+
+    async fn deregister_service(
+        &self,
+        request: Request<DeregisterServiceRequest>,
+    ) -> Result<Response<DeregisterServiceResponse>, Status> {
+        let id = request.into_inner().id;
+        if !self.registry.deregister(&id) {
+            return Err(Status::not_found(format!("Service {} is not registered", id)));
+        }
+
+        tracing::info!("Deregistered service {}", id);
+        Ok(Response::new(DeregisterServiceResponse {}))
+    }
+}
+
+fn services_response(services: Vec<ServiceEntry>) -> GetServicesResponse {
+    GetServicesResponse {
+        services: services.into_iter().map(service_proto).collect(),
     }
 }
 
-pub struct Chancelor {}
+fn service_proto(entry: ServiceEntry) -> proto::Service {
+    let health = match entry.health {
+        HealthStatus::Unknown => proto::HealthStatus::Unknown,
+        HealthStatus::Healthy => proto::HealthStatus::Healthy,
+        HealthStatus::Unhealthy => proto::HealthStatus::Unhealthy,
+    };
+    proto::Service {
+        id: entry.registration.id,
+        name: entry.registration.name,
+        endpoint: entry.registration.endpoint,
+        health: health.into(),
+        last_seen_ms: entry
+            .last_seen
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_millis() as i64),
+        health_error: entry.last_error,
+    }
+}
+
+pub struct Chancelor {
+    registry: ServiceRegistry,
+    health: HealthConfig,
+}
 
 impl Chancelor {
     pub fn new() -> Self {
-        Self {}
+        Self {
+            registry: ServiceRegistry::new(),
+            health: HealthConfig::default(),
+        }
+    }
+
+    /// Set how registered services are probed
+    pub fn with_health_config(mut self, health: HealthConfig) -> Self {
+        self.health = health;
+        self
+    }
+
+    /// Get the service registry
+    pub fn registry(&self) -> &ServiceRegistry {
+        &self.registry
     }
 
     pub async fn run(self) -> anyhow::Result<()> {
         let addr = "[::1]:50051".parse()?;
-        let frontdoor = FrontdoorImpl::default();
+        let frontdoor = FrontdoorImpl::new(self.registry.clone());
+
+        let prober = HealthProber::new(self.registry, self.health)?;
+        tokio::spawn(prober.run());
 
         Server::builder()
             .add_service(FrontdoorServer::new(frontdoor))
@@ -39,3 +149,9 @@ impl Chancelor {
         Ok(())
     }
 }
+
+impl Default for Chancelor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,182 @@
+//! Registered services and their health
+
+use std::collections::BTreeMap;
+use std::time::SystemTime;
+use tokio::sync::watch;
+
+/// Health of a service as last observed by the prober
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HealthStatus {
+    /// Not probed yet, or the service has no health endpoint
+    #[default]
+    Unknown,
+    Healthy,
+    Unhealthy,
+}
+
+/// A service announced to chancelor
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServiceRegistration {
+    pub id: String,
+    pub name: String,
+    pub endpoint: String,
+    pub health_url: Option<String>,
+}
+
+/// A registered service with its observed health
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServiceEntry {
+    pub registration: ServiceRegistration,
+    pub health: HealthStatus,
+    /// Time of the last successful probe
+    pub last_seen: Option<SystemTime>,
+    /// Error of the last failed probe, cleared by a successful one
+    pub last_error: Option<String>,
+    /// Failed probes since the last successful one
+    pub(crate) failures: u32,
+}
+
+/// Registered services, shared by the RPC handlers and the health prober
+///
+/// Watchers are notified when a service is registered or removed and when
+/// its health status changes. A successful probe of a healthy service only
+/// refreshes `last_seen`, which keeps steady-state probing from waking every
+/// watcher on each round.
+#[derive(Clone)]
+pub struct ServiceRegistry {
+    services: watch::Sender<BTreeMap<String, ServiceEntry>>,
+}
+
+impl ServiceRegistry {
+    pub fn new() -> Self {
+        Self {
+            services: watch::Sender::new(BTreeMap::new()),
+        }
+    }
+
+    /// Register a service, replacing an earlier registration with the same ID
+    ///
+    /// Observed health is kept when the health endpoint is unchanged.
+    pub fn register(&self, registration: ServiceRegistration) {
+        self.services.send_modify(|services| {
+            let entry = match services.remove(&registration.id) {
+                Some(previous) if previous.registration.health_url == registration.health_url => ServiceEntry {
+                    registration,
+                    ..previous
+                },
+                _ => ServiceEntry {
+                    registration,
+                    health: HealthStatus::Unknown,
+                    last_seen: None,
+                    last_error: None,
+                    failures: 0,
+                },
+            };
+            services.insert(entry.registration.id.clone(), entry);
+        });
+    }
+
+    /// Remove a service, returning whether it was registered
+    pub fn deregister(&self, id: &str) -> bool {
+        self.services.send_if_modified(|services| services.remove(id).is_some())
+    }
+
+    /// All registered services, ordered by ID
+    pub fn services(&self) -> Vec<ServiceEntry> {
+        self.services.borrow().values().cloned().collect()
+    }
+
+    /// Subscribe to changes of the registered services
+    pub fn subscribe(&self) -> watch::Receiver<BTreeMap<String, ServiceEntry>> {
+        self.services.subscribe()
+    }
+
+    /// Record a successful probe
+    pub(crate) fn probe_succeeded(&self, id: &str, at: SystemTime) {
+        self.services.send_if_modified(|services| {
+            let Some(entry) = services.get_mut(id) else {
+                return false;
+            };
+            let changed = entry.health != HealthStatus::Healthy;
+            entry.health = HealthStatus::Healthy;
+            entry.last_seen = Some(at);
+            entry.last_error = None;
+            entry.failures = 0;
+            changed
+        });
+    }
+
+    /// Record a failed probe
+    ///
+    /// The service turns unhealthy once `threshold` probes in a row failed,
+    /// so a single dropped request does not flap the topology.
+    pub(crate) fn probe_failed(&self, id: &str, error: String, threshold: u32) {
+        self.services.send_if_modified(|services| {
+            let Some(entry) = services.get_mut(id) else {
+                return false;
+            };
+            entry.failures = entry.failures.saturating_add(1);
+            entry.last_error = Some(error);
+            if entry.failures >= threshold && entry.health != HealthStatus::Unhealthy {
+                entry.health = HealthStatus::Unhealthy;
+                return true;
+            }
+            false
+        });
+    }
+}
+
+impl Default for ServiceRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registration(id: &str, health_url: Option<&str>) -> ServiceRegistration {
+        ServiceRegistration {
+            id: id.to_string(),
+            name: "permits".to_string(),
+            endpoint: "http://permits:8080".to_string(),
+            health_url: health_url.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_health_transitions() {
+        let registry = ServiceRegistry::new();
+        let mut changes = registry.subscribe();
+        registry.register(registration("permits-1", Some("http://permits:8080/health")));
+        assert!(changes.has_changed().unwrap());
+        changes.mark_unchanged();
+
+        registry.probe_succeeded("permits-1", SystemTime::UNIX_EPOCH);
+        assert!(changes.has_changed().unwrap());
+        changes.mark_unchanged();
+
+        // Staying healthy only refreshes last_seen
+        let now = SystemTime::now();
+        registry.probe_succeeded("permits-1", now);
+        assert!(!changes.has_changed().unwrap());
+        assert_eq!(registry.services()[0].last_seen, Some(now));
+
+        registry.probe_failed("permits-1", "connection refused".to_string(), 2);
+        assert_eq!(registry.services()[0].health, HealthStatus::Healthy);
+        registry.probe_failed("permits-1", "connection refused".to_string(), 2);
+        assert_eq!(registry.services()[0].health, HealthStatus::Unhealthy);
+        assert!(changes.has_changed().unwrap());
+
+        // Re-registering with the same health endpoint keeps what was observed
+        registry.register(registration("permits-1", Some("http://permits:8080/health")));
+        assert_eq!(registry.services()[0].health, HealthStatus::Unhealthy);
+        registry.register(registration("permits-1", None));
+        assert_eq!(registry.services()[0].health, HealthStatus::Unknown);
+
+        assert!(registry.deregister("permits-1"));
+        assert!(!registry.deregister("permits-1"));
+        assert!(registry.services().is_empty());
+    }
+}
@@ -0,0 +1,406 @@
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::Utc;
+use clap::Args;
+use dgv_workflow::{
+    Action, Completion, Location, RuntimeType, State, StateMachine, TaskDefinition, TaskResult, Transition,
+    WorkerHealthStatus, WorkerId, WorkerInfo, WorkerStats, WorkflowDefinition, WorkflowEngine, WorkflowId,
+};
+use miette::{IntoDiagnostic, Result};
+use tokio::task::JoinSet;
+
+/// Time a simulated worker waits after finding the queue empty
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Percentiles of the scheduling latency in the report
+const PERCENTILES: &[f64] = &[50.0, 90.0, 99.0, 99.9];
+
+#[derive(Args)]
+pub struct BenchArgs {
+    /// Number of workflow instances to start
+    #[arg(long, short = 'n', default_value_t = 1000)]
+    instances: usize,
+
+    /// Instances started per second
+    #[arg(long, default_value_t = 100.0)]
+    rate: f64,
+
+    /// Number of simulated workers
+    #[arg(long, short = 'w', default_value_t = 4)]
+    workers: usize,
+
+    /// Time each simulated task takes, in milliseconds
+    #[arg(long, default_value_t = 0)]
+    task_ms: u64,
+
+    /// Stop waiting for tasks after this many seconds
+    #[arg(long, default_value_t = 300)]
+    timeout_secs: u64,
+
+    /// FoundationDB cluster file; the default cluster file when unset
+    #[arg(long, value_name = "FILE", env = "FDB_CLUSTER_FILE")]
+    cluster_file: Option<PathBuf>,
+}
+
+/// Handle the bench command
+///
+/// Runs an engine in-process against the cluster, without its RPC server,
+/// and drives it through the same storage calls the RPC handlers make. The
+/// instances, tasks and history written by a run are left in the database.
+pub async fn handle_bench_command(args: BenchArgs) -> Result<()> {
+    if args.instances == 0 || args.workers == 0 {
+        return Err(miette::miette!("At least one instance and one worker are required"));
+    }
+    if !(args.rate.is_finite() && args.rate > 0.0) {
+        return Err(miette::miette!("Rate must be a positive number, got {}", args.rate));
+    }
+
+    let network = crate::engine::boot();
+    let result = run(&args).await;
+    drop(network);
+    result
+}
+
+async fn run(args: &BenchArgs) -> Result<()> {
+    let engine = crate::engine::connect(args.cluster_file.as_deref()).await?;
+    let definition_id = engine
+        .register_workflow(synthetic_workflow())
+        .await
+        .into_diagnostic()?;
+
+    // Workers are registered before the first task is enqueued, so no task
+    // is rejected for want of a capable worker
+    let workers: Vec<WorkerId> = (0..args.workers)
+        .map(|i| WorkerId::from_string(format!("bench-{}-{}", std::process::id(), i)))
+        .collect();
+    for worker_id in &workers {
+        register_worker(&engine, worker_id).await?;
+    }
+
+    println!(
+        "Starting {} instance(s) at {}/s with {} simulated worker(s)...",
+        args.instances, args.rate, args.workers
+    );
+
+    let stats = Arc::new(Stats::default());
+    let started = Instant::now();
+    let deadline = started + Duration::from_secs(args.timeout_secs);
+
+    let mut simulated = JoinSet::new();
+    for worker_id in &workers {
+        simulated.spawn(simulate_worker(
+            engine.clone(),
+            worker_id.clone(),
+            stats.clone(),
+            Duration::from_millis(args.task_ms),
+            deadline,
+        ));
+    }
+
+    let start_duration = start_instances(&engine, definition_id, args, &stats).await;
+    while simulated.join_next().await.is_some() {}
+    let total_duration = started.elapsed();
+
+    for worker_id in &workers {
+        engine.scheduler().unregister_worker(worker_id);
+        if let Err(e) = engine.persistence().workers().unregister(worker_id).await {
+            eprintln!("Failed to unregister worker {}: {}", worker_id, e);
+        }
+    }
+
+    stats.report(args.instances, start_duration, total_duration);
+
+    if Instant::now() >= deadline {
+        return Err(miette::miette!(
+            "Timed out after {}s with {} task(s) unprocessed",
+            args.timeout_secs,
+            stats.unprocessed()
+        ));
+    }
+    Ok(())
+}
+
+/// Workflow with one task that completes as soon as the task does
+fn synthetic_workflow() -> WorkflowDefinition {
+    let task = TaskDefinition {
+        name: "bench".to_string(),
+        runtime_type: RuntimeType::JavaScript,
+        code: b"input".to_vec(),
+        timeout_ms: 30_000,
+        ..Default::default()
+    };
+    let state_machine = StateMachine::builder()
+        .initial_state("work")
+        .add_state(
+            State::new("work")
+                .on_enter(Action::execute_task(task))
+                .on_tasks_finished(Completion::new("done"))
+                .add_transition(Transition::new("done", "end")),
+        )
+        .add_state(State::new("end"))
+        .build()
+        .expect("synthetic workflow is valid");
+
+    WorkflowDefinition {
+        id: WorkflowId::new(),
+        name: "bench".to_string(),
+        description: Some("Synthetic workflow started by `degov bench`".to_string()),
+        state_machine,
+        created_at: Utc::now(),
+        subscriptions: Vec::new(),
+        timeout: None,
+        state_timeouts: Default::default(),
+        limits: Default::default(),
+        scheduling: Default::default(),
+        indexes: Vec::new(),
+        retention: None,
+    }
+}
+
+async fn register_worker(engine: &WorkflowEngine, worker_id: &WorkerId) -> Result<()> {
+    let worker = WorkerInfo {
+        id: worker_id.clone(),
+        capabilities: vec![RuntimeType::JavaScript],
+        hostname: "bench".to_string(),
+        location: Location::default(),
+        registered_at: Utc::now(),
+        last_heartbeat: Utc::now(),
+        status: WorkerHealthStatus::Healthy,
+        stats: WorkerStats::default(),
+        draining: false,
+    };
+    engine.scheduler().register_worker(worker.clone());
+    engine.persistence().workers().register(worker).await.into_diagnostic()
+}
+
+/// Start the instances at the target rate, returning how long it took
+///
+/// Starts run concurrently, so a slow start does not hold back the next.
+async fn start_instances(
+    engine: &Arc<WorkflowEngine>,
+    definition_id: WorkflowId,
+    args: &BenchArgs,
+    stats: &Arc<Stats>,
+) -> Duration {
+    let started = Instant::now();
+    let mut ticker = tokio::time::interval(Duration::from_secs_f64(1.0 / args.rate));
+    let mut starts = JoinSet::new();
+
+    for _ in 0..args.instances {
+        ticker.tick().await;
+        let engine = engine.clone();
+        let stats = stats.clone();
+        starts.spawn(async move {
+            match engine.start_workflow(&definition_id, serde_json::json!({})).await {
+                Ok(_) => {
+                    stats.start.succeeded();
+                    stats.started.fetch_add(1, Ordering::SeqCst);
+                }
+                Err(e) => stats.start.failed(e.is_conflict()),
+            }
+        });
+    }
+    while starts.join_next().await.is_some() {}
+
+    stats.starting.store(false, Ordering::SeqCst);
+    started.elapsed()
+}
+
+/// Poll for tasks and complete them like a worker would over RPC
+async fn simulate_worker(
+    engine: Arc<WorkflowEngine>,
+    worker_id: WorkerId,
+    stats: Arc<Stats>,
+    task_duration: Duration,
+    deadline: Instant,
+) {
+    let capabilities = [RuntimeType::JavaScript];
+    let location = Location::default();
+
+    while !stats.is_finished() && Instant::now() < deadline {
+        let tasks = engine.persistence().tasks();
+        let dequeued = tasks
+            .dequeue(&worker_id, &capabilities, &location, engine.scheduler(), engine.visibility_timeout())
+            .await;
+        let task = match dequeued {
+            Ok(Some(task)) => {
+                stats.dequeue.succeeded();
+                task
+            }
+            Ok(None) => {
+                stats.dequeue.succeeded();
+                tokio::time::sleep(POLL_INTERVAL).await;
+                continue;
+            }
+            Err(e) => {
+                stats.dequeue.failed(e.is_conflict());
+                continue;
+            }
+        };
+
+        if let Some(assigned_at) = task.started_at
+            && let Ok(latency) = (assigned_at - task.created_at).to_std()
+        {
+            stats.latencies.lock().unwrap().push(latency);
+        }
+
+        tokio::time::sleep(task_duration).await;
+        let result = TaskResult {
+            success: true,
+            output: b"{}".to_vec(),
+            error: None,
+            execution_time_ms: task_duration.as_millis() as u64,
+            kv_writes: Vec::new(),
+        };
+
+        match engine
+            .persistence()
+            .tasks()
+            .complete_fenced(&task.id, &worker_id, task.attempt, result)
+            .await
+        {
+            Ok(status) => {
+                stats.complete.succeeded();
+                match engine.handle_task_outcome(&task.id, status).await {
+                    Ok(()) => stats.outcome.succeeded(),
+                    Err(e) => stats.outcome.failed(e.is_conflict()),
+                }
+            }
+            Err(e) => stats.complete.failed(e.is_conflict()),
+        }
+        stats.processed.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// Outcomes of one kind of storage operation
+#[derive(Default)]
+struct Operation {
+    total: AtomicU64,
+    conflicts: AtomicU64,
+    errors: AtomicU64,
+}
+
+impl Operation {
+    fn succeeded(&self) {
+        self.total.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn failed(&self, conflict: bool) {
+        self.total.fetch_add(1, Ordering::Relaxed);
+        if conflict {
+            self.conflicts.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn print(&self, name: &str) {
+        let total = self.total.load(Ordering::Relaxed);
+        let conflicts = self.conflicts.load(Ordering::Relaxed);
+        let rate = if total == 0 {
+            0.0
+        } else {
+            conflicts as f64 / total as f64 * 100.0
+        };
+        println!(
+            "  {:<10} {:>10} {:>10} {:>9.2}% {:>8}",
+            name,
+            total,
+            conflicts,
+            rate,
+            self.errors.load(Ordering::Relaxed)
+        );
+    }
+}
+
+struct Stats {
+    start: Operation,
+    dequeue: Operation,
+    complete: Operation,
+    outcome: Operation,
+    /// Time from enqueueing each task to a worker taking it
+    latencies: Mutex<Vec<Duration>>,
+    /// Instances started successfully
+    started: AtomicUsize,
+    /// Tasks taken and completed, successfully or not
+    processed: AtomicUsize,
+    /// Whether instances are still being started
+    starting: AtomicBool,
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self {
+            start: Operation::default(),
+            dequeue: Operation::default(),
+            complete: Operation::default(),
+            outcome: Operation::default(),
+            latencies: Mutex::new(Vec::new()),
+            started: AtomicUsize::new(0),
+            processed: AtomicUsize::new(0),
+            starting: AtomicBool::new(true),
+        }
+    }
+}
+
+impl Stats {
+    /// Whether every started instance had its task processed
+    fn is_finished(&self) -> bool {
+        !self.starting.load(Ordering::SeqCst) && self.unprocessed() == 0
+    }
+
+    fn unprocessed(&self) -> usize {
+        self.started
+            .load(Ordering::SeqCst)
+            .saturating_sub(self.processed.load(Ordering::SeqCst))
+    }
+
+    fn report(&self, instances: usize, start_duration: Duration, total_duration: Duration) {
+        let started = self.started.load(Ordering::SeqCst);
+        let processed = self.processed.load(Ordering::SeqCst);
+        println!(
+            "\nStarted {}/{} instance(s) in {:.2?} ({:.1}/s)",
+            started,
+            instances,
+            start_duration,
+            started as f64 / start_duration.as_secs_f64()
+        );
+        println!(
+            "Processed {} task(s) in {:.2?} ({:.1}/s)",
+            processed,
+            total_duration,
+            processed as f64 / total_duration.as_secs_f64()
+        );
+
+        let mut latencies = self.latencies.lock().unwrap().clone();
+        latencies.sort();
+        println!("\nScheduling latency (enqueued to taken by a worker):");
+        if latencies.is_empty() {
+            println!("  no tasks were taken");
+        } else {
+            for p in PERCENTILES {
+                println!("  p{:<5} {:>10.2?}", p, percentile(&latencies, *p));
+            }
+            println!("  max    {:>10.2?}", latencies[latencies.len() - 1]);
+        }
+
+        println!("\nTransactions:");
+        println!(
+            "  {:<10} {:>10} {:>10} {:>10} {:>8}",
+            "operation", "total", "conflicts", "rate", "errors"
+        );
+        self.start.print("start");
+        self.dequeue.print("dequeue");
+        self.complete.print("complete");
+        self.outcome.print("advance");
+    }
+}
+
+/// Nearest-rank percentile of sorted, non-empty samples
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
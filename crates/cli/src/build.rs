@@ -1,11 +1,12 @@
 use std::path::{Path, PathBuf};
+use degov_crypto::StackKey;
 use dgv_agora_build::AppBuilder;
 use dgv_core::v1::service::{ServiceBuild, RustBuild};
 use miette::{IntoDiagnostic, Result};
 use std::borrow::Cow;
 
 /// Handle the build command
-pub async fn handle_build_command(path: PathBuf) -> Result<()> {
+pub async fn handle_build_command(path: PathBuf, signing_key: Option<PathBuf>) -> Result<()> {
     // Check if path exists
     if !path.exists() {
         return Err(miette::miette!("Path does not exist: {}", path.display()));
@@ -31,6 +32,12 @@ pub async fn handle_build_command(path: PathBuf) -> Result<()> {
 
     // Build all services concurrently
     let mut builder = AppBuilder::new();
+    if let Some(key_path) = signing_key {
+        let seed = std::fs::read_to_string(&key_path).into_diagnostic()?;
+        let key = StackKey::from_hex(&seed).into_diagnostic()?;
+        println!("Signing artifacts as {}", key.did());
+        builder = builder.with_signing_key(key);
+    }
     for (name, build) in service_builds {
         builder.add_service(name, build);
     }
@@ -49,6 +56,9 @@ pub async fn handle_build_command(path: PathBuf) -> Result<()> {
             if let Some(output_path) = &result.output_path {
                 println!("  Output: {}", output_path.display());
             }
+            if let Some(attestation_path) = &result.attestation_path {
+                println!("  Provenance: {}", attestation_path.display());
+            }
         } else {
             fail_count += 1;
             eprintln!("✗ Failed to build: {}", result.service_name);
@@ -0,0 +1,42 @@
+use std::path::Path;
+use std::sync::Arc;
+
+use dgv_workflow::WorkflowEngine;
+use miette::{IntoDiagnostic, Result};
+
+/// Start the FoundationDB client network, which runs until the returned
+/// guard is dropped
+pub fn boot() -> foundationdb::api::NetworkAutoStop {
+    // Booted once per command, before any database is opened
+    unsafe { foundationdb::boot() }
+}
+
+/// Create an engine on the cluster of `cluster_file`, or on the default
+/// cluster, without starting its RPC server
+pub async fn connect(cluster_file: Option<&Path>) -> Result<Arc<WorkflowEngine>> {
+    let db = match cluster_file {
+        Some(path) => foundationdb::Database::from_path(&path.to_string_lossy()),
+        None => foundationdb::Database::default(),
+    }
+    .into_diagnostic()?;
+    let bind_addr = "127.0.0.1:0".parse().into_diagnostic()?;
+    let engine = WorkflowEngine::new(db, bind_addr).await.into_diagnostic()?;
+    Ok(Arc::new(engine))
+}
+
+/// Register the workflow defined by a DGL file on the cluster of
+/// `cluster_file`, or on the default cluster
+pub async fn register(path: &Path, cluster_file: Option<&Path>) -> Result<()> {
+    let source = std::fs::read_to_string(path).into_diagnostic()?;
+
+    let network = boot();
+    let result = async {
+        let engine = connect(cluster_file).await?;
+        engine.register_from_dgl(&source).await.into_diagnostic()
+    }
+    .await;
+    drop(network);
+
+    println!("✓ Registered workflow {} from {}", result?, path.display());
+    Ok(())
+}
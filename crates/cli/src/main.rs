@@ -1,8 +1,12 @@
 use clap::{Parser, Subcommand, builder::styling};
 use clap_cargo::style;
+use miette::IntoDiagnostic;
 
+mod bench;
 mod dgl;
+mod engine;
 mod infrastructure;
+mod snapshot;
 mod validate;
 mod build;
 
@@ -31,6 +35,38 @@ enum Commands {
         /// Path to DGL service file or directory containing service files
         #[arg(value_name = "PATH")]
         path: std::path::PathBuf,
+
+        /// File holding the hex-encoded Ed25519 seed of the stack key; built
+        /// artifacts are signed and get SLSA provenance
+        #[arg(long, value_name = "FILE", env = "DEGOV_SIGNING_KEY")]
+        signing_key: Option<std::path::PathBuf>,
+    },
+
+    /// Load test the workflow engine: start synthetic workflow instances at
+    /// a target rate, run their tasks on simulated workers and report
+    /// scheduling latency and FoundationDB conflict rates
+    Bench(bench::BenchArgs),
+
+    /// Register the workflow defined by a DGL file with the engine
+    Register {
+        /// DGL file defining the workflow
+        #[arg(value_name = "FILE")]
+        path: std::path::PathBuf,
+
+        /// FoundationDB cluster file; the default cluster file when unset
+        #[arg(long, value_name = "FILE", env = "FDB_CLUSTER_FILE")]
+        cluster_file: Option<std::path::PathBuf>,
+    },
+
+    /// Copy workflow definitions and instances between environments
+    #[command(subcommand)]
+    Snapshot(snapshot::SnapshotCommands),
+
+    /// Print the WIT of the host API service components are built against
+    Wit {
+        /// File to write the WIT to instead of stdout
+        #[arg(long, short, value_name = "FILE")]
+        output: Option<std::path::PathBuf>,
     },
 }
 
@@ -40,9 +76,22 @@ async fn main() -> miette::Result<()> {
     let cli = Cli::parse();
 
     match cli.command {
-        Commands::Build { path } => {
-            build::handle_build_command(path).await?;
+        Commands::Build { path, signing_key } => {
+            build::handle_build_command(path, signing_key).await?;
+        }
+        Commands::Bench(args) => {
+            bench::handle_bench_command(args).await?;
+        }
+        Commands::Register { path, cluster_file } => {
+            engine::register(&path, cluster_file.as_deref()).await?;
+        }
+        Commands::Snapshot(command) => {
+            snapshot::handle_snapshot_command(command).await?;
         }
+        Commands::Wit { output } => match output {
+            Some(output) => std::fs::write(output, dgv_agora_process::host::WIT).into_diagnostic()?,
+            None => print!("{}", dgv_agora_process::host::WIT),
+        },
     }
 
     Ok(())
@@ -0,0 +1,119 @@
+use std::path::{Path, PathBuf};
+
+use clap::Subcommand;
+use dgv_workflow::{RestoreOptions, Snapshot, WorkflowId};
+use miette::{IntoDiagnostic, Result};
+
+#[derive(Subcommand)]
+pub enum SnapshotCommands {
+    /// Write all workflow definitions, and optionally instances, to a file
+    Export {
+        /// File to write the snapshot to
+        #[arg(value_name = "FILE")]
+        output: PathBuf,
+
+        /// Include instances and their pending timers
+        #[arg(long)]
+        instances: bool,
+
+        /// FoundationDB cluster file; the default cluster file when unset
+        #[arg(long, value_name = "FILE", env = "FDB_CLUSTER_FILE")]
+        cluster_file: Option<PathBuf>,
+    },
+
+    /// Restore a snapshot written by `snapshot export`
+    Import {
+        /// Snapshot file to restore
+        #[arg(value_name = "FILE")]
+        input: PathBuf,
+
+        /// Give every definition and instance a new ID instead of
+        /// overwriting the ones with the same ID
+        #[arg(long)]
+        fresh_ids: bool,
+
+        /// Restore a definition under another ID, as `FROM=TO`; may be
+        /// repeated
+        #[arg(long = "map-definition", value_name = "FROM=TO", value_parser = parse_mapping)]
+        definition_ids: Vec<(WorkflowId, WorkflowId)>,
+
+        /// FoundationDB cluster file; the default cluster file when unset
+        #[arg(long, value_name = "FILE", env = "FDB_CLUSTER_FILE")]
+        cluster_file: Option<PathBuf>,
+    },
+}
+
+/// Handle the snapshot commands
+pub async fn handle_snapshot_command(command: SnapshotCommands) -> Result<()> {
+    let network = crate::engine::boot();
+    let result = match command {
+        SnapshotCommands::Export {
+            output,
+            instances,
+            cluster_file,
+        } => export(&output, instances, cluster_file.as_deref()).await,
+        SnapshotCommands::Import {
+            input,
+            fresh_ids,
+            definition_ids,
+            cluster_file,
+        } => {
+            let mut options = RestoreOptions::new();
+            if fresh_ids {
+                options = options.with_fresh_ids();
+            }
+            for (from, to) in definition_ids {
+                options = options.map_definition(from, to);
+            }
+            import(&input, &options, cluster_file.as_deref()).await
+        }
+    };
+    drop(network);
+    result
+}
+
+async fn export(output: &Path, instances: bool, cluster_file: Option<&Path>) -> Result<()> {
+    let engine = crate::engine::connect(cluster_file).await?;
+    let snapshot = engine.snapshot(instances).await.into_diagnostic()?;
+
+    let file = std::fs::File::create(output).into_diagnostic()?;
+    serde_json::to_writer(std::io::BufWriter::new(file), &snapshot).into_diagnostic()?;
+
+    println!(
+        "✓ Wrote {} definition(s), {} instance(s) and {} timer(s) to {}",
+        snapshot.definitions.len(),
+        snapshot.instances.len(),
+        snapshot.timers.len(),
+        output.display()
+    );
+    Ok(())
+}
+
+async fn import(input: &Path, options: &RestoreOptions, cluster_file: Option<&Path>) -> Result<()> {
+    let file = std::fs::File::open(input).into_diagnostic()?;
+    let snapshot: Snapshot = serde_json::from_reader(std::io::BufReader::new(file)).into_diagnostic()?;
+    // Check before connecting, so an incompatible file fails fast
+    snapshot.check_version().into_diagnostic()?;
+
+    let engine = crate::engine::connect(cluster_file).await?;
+    let report = engine.restore(snapshot, options).await.into_diagnostic()?;
+
+    println!(
+        "✓ Restored {} definition(s), {} instance(s) and {} timer(s)",
+        report.definitions, report.instances, report.timers
+    );
+    Ok(())
+}
+
+/// Parse a `FROM=TO` pair of definition IDs
+fn parse_mapping(value: &str) -> std::result::Result<(WorkflowId, WorkflowId), String> {
+    let (from, to) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected FROM=TO, got '{}'", value))?;
+    let parse = |id: &str| {
+        uuid::Uuid::parse_str(id.trim())
+            .map(WorkflowId::from_uuid)
+            .map_err(|e| format!("invalid definition ID '{}': {}", id, e))
+    };
+    Ok((parse(from)?, parse(to)?))
+}
@@ -2,10 +2,13 @@
 //!
 //! NSIDs follow the AT Protocol Lexicon format: `{authority}/{entity}[#{fragment}]`
 //!
+//! NSIDs order by the bytes of the identifier, which is the key order of the
+//! Merkle Search Tree, so they can be used as tree keys directly.
+//!
 //! # Examples
 //!
 //! ```
-//! use degov_core::Nsid;
+//! use dgv_core::Nsid;
 //!
 //! // Parse a basic NSID
 //! let nsid: Nsid = "de.berlin/business".parse().unwrap();
@@ -21,6 +24,7 @@
 //! ```
 
 use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
 use std::fmt;
 use std::str::FromStr;
 use thiserror::Error;
@@ -70,7 +74,7 @@ impl Nsid {
     /// # Examples
     ///
     /// ```
-    /// use degov_core::Nsid;
+    /// use dgv_core::Nsid;
     ///
     /// let nsid = Nsid::new("de.berlin", "business", None).unwrap();
     /// assert_eq!(nsid.to_string(), "de.berlin/business");
@@ -127,6 +131,11 @@ impl Nsid {
         &self.full[..self.entity_start - 1]
     }
     
+    /// Get the dot separated segments of the authority (e.g., `de`, `berlin`)
+    pub fn authority_segments(&self) -> impl Iterator<Item = &str> {
+        self.authority().split('.')
+    }
+
     /// Get the entity part (e.g., `business-registration`)
     pub fn entity(&self) -> &str {
         match self.fragment_start {
@@ -153,6 +162,17 @@ impl Nsid {
         self.fragment_start.is_some()
     }
     
+    /// Check if the authority equals `namespace` or is nested below it
+    ///
+    /// `de.berlin.mitte/office` is within `de.berlin`, `de.berlinx/office` is not.
+    pub fn is_within(&self, namespace: &str) -> bool {
+        let authority = self.authority();
+        authority == namespace
+            || authority
+                .strip_prefix(namespace)
+                .is_some_and(|rest| rest.starts_with('.'))
+    }
+    
     /// Check if this is a federal (de.bund) NSID
     pub fn is_federal(&self) -> bool {
         self.authority().starts_with("de.bund")
@@ -309,6 +329,18 @@ impl AsRef<str> for Nsid {
     }
 }
 
+impl PartialOrd for Nsid {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Nsid {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.full.cmp(&other.full)
+    }
+}
+
 // Serde support
 impl Serialize for Nsid {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
@@ -332,6 +364,7 @@ impl<'de> Deserialize<'de> for Nsid {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::BTreeSet;
     
     #[test]
     fn test_parse_basic_nsid() {
@@ -351,6 +384,31 @@ mod tests {
         assert!(nsid.has_fragment());
     }
     
+    #[test]
+    fn test_authority_segments() {
+        let nsid: Nsid = "de.berlin.mitte/office".parse().unwrap();
+        assert_eq!(nsid.authority_segments().collect::<Vec<_>>(), ["de", "berlin", "mitte"]);
+    }
+    
+    #[test]
+    fn test_is_within() {
+        let nsid: Nsid = "de.berlin.mitte/office".parse().unwrap();
+        assert!(nsid.is_within("de.berlin.mitte"));
+        assert!(nsid.is_within("de.berlin"));
+        assert!(nsid.is_within("de"));
+        assert!(!nsid.is_within("de.berl"));
+        assert!(!nsid.is_within("de.bund"));
+    }
+    
+    #[test]
+    fn test_ordering_matches_key_order() {
+        let ids = ["de.berlin/business#workflow", "de.berlin/business", "de.bund/person", "de.berlin/agency"];
+        let set: BTreeSet<Nsid> = ids.iter().map(|s| s.parse().unwrap()).collect();
+        let mut keys = ids.to_vec();
+        keys.sort();
+        assert_eq!(set.iter().map(|n| n.as_str()).collect::<Vec<_>>(), keys);
+    }
+    
     #[test]
     fn test_federal_detection() {
         let nsid: Nsid = "de.bund/person".parse().unwrap();
@@ -0,0 +1,182 @@
+//! Signed in-toto attestations in DSSE envelopes
+//!
+//! Build provenance is an in-toto [`Statement`] about one or more artifacts,
+//! identified by their SHA-256 digest. The statement is signed with a
+//! [`StackKey`] and wrapped in a DSSE [`Envelope`], the format used by SLSA
+//! tooling, so the attestation can be checked without trusting the channel
+//! the artifact came through.
+
+use crate::did::{DidKey, StackKey};
+use crate::{CryptoError, CryptoResult, sha256_hex};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Payload type of an in-toto statement
+pub const IN_TOTO_PAYLOAD_TYPE: &str = "application/vnd.in-toto+json";
+
+/// Type URI of an in-toto v1 statement
+pub const STATEMENT_TYPE: &str = "https://in-toto.io/Statement/v1";
+
+/// An in-toto statement binding a predicate to artifact digests
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Statement {
+    #[serde(rename = "_type")]
+    pub statement_type: String,
+    pub subject: Vec<Subject>,
+    #[serde(rename = "predicateType")]
+    pub predicate_type: String,
+    pub predicate: serde_json::Value,
+}
+
+impl Statement {
+    /// Create a statement about `subject`
+    pub fn new(subject: Vec<Subject>, predicate_type: impl Into<String>, predicate: serde_json::Value) -> Self {
+        Self {
+            statement_type: STATEMENT_TYPE.to_string(),
+            subject,
+            predicate_type: predicate_type.into(),
+            predicate,
+        }
+    }
+
+    /// Whether `artifact` is one of the statement's subjects
+    pub fn covers(&self, artifact: &[u8]) -> bool {
+        let digest = sha256_hex(artifact);
+        self.subject
+            .iter()
+            .any(|subject| subject.digest.get("sha256") == Some(&digest))
+    }
+}
+
+/// An artifact named in a statement
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Subject {
+    pub name: String,
+    /// Digests by algorithm, hex encoded
+    pub digest: BTreeMap<String, String>,
+}
+
+impl Subject {
+    /// Describe `artifact` by its SHA-256 digest
+    pub fn sha256(name: impl Into<String>, artifact: &[u8]) -> Self {
+        Self {
+            name: name.into(),
+            digest: BTreeMap::from([("sha256".to_string(), sha256_hex(artifact))]),
+        }
+    }
+}
+
+/// A DSSE envelope
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Envelope {
+    #[serde(rename = "payloadType")]
+    pub payload_type: String,
+    /// Base64-encoded payload
+    pub payload: String,
+    pub signatures: Vec<EnvelopeSignature>,
+}
+
+/// One signature of an envelope
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EnvelopeSignature {
+    /// DID of the signing key
+    pub keyid: String,
+    /// Base64-encoded signature over the pre-authentication encoding
+    pub sig: String,
+}
+
+impl Envelope {
+    /// Sign a statement
+    pub fn sign_statement(statement: &Statement, key: &StackKey) -> CryptoResult<Self> {
+        let payload = serde_json::to_vec(statement).map_err(|e| CryptoError::Malformed(e.to_string()))?;
+        let signature = key.sign(&pae(IN_TOTO_PAYLOAD_TYPE, &payload));
+        Ok(Self {
+            payload_type: IN_TOTO_PAYLOAD_TYPE.to_string(),
+            payload: STANDARD.encode(payload),
+            signatures: vec![EnvelopeSignature {
+                keyid: key.did().to_string(),
+                sig: STANDARD.encode(signature),
+            }],
+        })
+    }
+
+    /// Return the payload if one of `trusted` signed it
+    pub fn verify(&self, trusted: &[DidKey]) -> CryptoResult<Vec<u8>> {
+        let payload = STANDARD
+            .decode(&self.payload)
+            .map_err(|e| CryptoError::Malformed(e.to_string()))?;
+        let message = pae(&self.payload_type, &payload);
+
+        let signed = self.signatures.iter().any(|signature| {
+            let Ok(did) = signature.keyid.parse::<DidKey>() else {
+                return false;
+            };
+            let Ok(sig) = STANDARD.decode(&signature.sig) else {
+                return false;
+            };
+            trusted.contains(&did) && did.verify(&message, &sig)
+        });
+        if !signed {
+            return Err(CryptoError::Untrusted);
+        }
+        Ok(payload)
+    }
+
+    /// Return the statement if one of `trusted` signed it
+    pub fn verify_statement(&self, trusted: &[DidKey]) -> CryptoResult<Statement> {
+        if self.payload_type != IN_TOTO_PAYLOAD_TYPE {
+            return Err(CryptoError::Malformed(format!("unexpected payload type {}", self.payload_type)));
+        }
+        let payload = self.verify(trusted)?;
+        serde_json::from_slice(&payload).map_err(|e| CryptoError::Malformed(e.to_string()))
+    }
+}
+
+/// Verify that a JSON-encoded envelope attests `artifact` and was signed by
+/// one of `trusted`
+pub fn verify_artifact(envelope: &str, artifact: &[u8], trusted: &[DidKey]) -> CryptoResult<Statement> {
+    let envelope: Envelope = serde_json::from_str(envelope).map_err(|e| CryptoError::Malformed(e.to_string()))?;
+    let statement = envelope.verify_statement(trusted)?;
+    if !statement.covers(artifact) {
+        return Err(CryptoError::DigestMismatch);
+    }
+    Ok(statement)
+}
+
+/// DSSE pre-authentication encoding
+fn pae(payload_type: &str, payload: &[u8]) -> Vec<u8> {
+    let mut message = format!("DSSEv1 {} {} {} ", payload_type.len(), payload_type, payload.len()).into_bytes();
+    message.extend_from_slice(payload);
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_artifact() {
+        let key = StackKey::from_seed([1; 32]);
+        let statement = Statement::new(
+            vec![Subject::sha256("task.wasm", b"\0asm")],
+            "https://slsa.dev/provenance/v1",
+            serde_json::json!({}),
+        );
+        let envelope = serde_json::to_string(&Envelope::sign_statement(&statement, &key).unwrap()).unwrap();
+
+        let verified = verify_artifact(&envelope, b"\0asm", &[key.did()]).unwrap();
+        assert_eq!(verified, statement);
+
+        assert!(matches!(
+            verify_artifact(&envelope, b"\0asm!", &[key.did()]),
+            Err(CryptoError::DigestMismatch)
+        ));
+        let other = StackKey::from_seed([2; 32]);
+        assert!(matches!(
+            verify_artifact(&envelope, b"\0asm", &[other.did()]),
+            Err(CryptoError::Untrusted)
+        ));
+    }
+}
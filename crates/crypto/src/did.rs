@@ -0,0 +1,112 @@
+//! Ed25519 keys identified by `did:key` DIDs
+
+use crate::{CryptoError, CryptoResult};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use std::fmt;
+use std::str::FromStr;
+
+/// Multicodec prefix of an Ed25519 public key
+const ED25519_MULTICODEC: [u8; 2] = [0xed, 0x01];
+
+/// Public half of a stack key, written as `did:key:z6Mk...`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DidKey(VerifyingKey);
+
+impl DidKey {
+    /// Check `signature` over `message`
+    pub fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        let Ok(signature) = Signature::from_slice(signature) else {
+            return false;
+        };
+        self.0.verify(message, &signature).is_ok()
+    }
+}
+
+impl fmt::Display for DidKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut bytes = ED25519_MULTICODEC.to_vec();
+        bytes.extend_from_slice(self.0.as_bytes());
+        write!(f, "did:key:z{}", bs58::encode(bytes).into_string())
+    }
+}
+
+impl FromStr for DidKey {
+    type Err = CryptoError;
+
+    fn from_str(s: &str) -> CryptoResult<Self> {
+        let encoded = s
+            .strip_prefix("did:key:z")
+            .ok_or_else(|| CryptoError::InvalidDid(format!("{} is not a base58 did:key", s)))?;
+        let bytes = bs58::decode(encoded)
+            .into_vec()
+            .map_err(|e| CryptoError::InvalidDid(e.to_string()))?;
+        let key = bytes
+            .strip_prefix(&ED25519_MULTICODEC)
+            .ok_or_else(|| CryptoError::InvalidDid(format!("{} is not an Ed25519 key", s)))?;
+        let key: [u8; 32] = key
+            .try_into()
+            .map_err(|_| CryptoError::InvalidDid(format!("{} has a truncated key", s)))?;
+        VerifyingKey::from_bytes(&key)
+            .map(DidKey)
+            .map_err(|e| CryptoError::InvalidDid(e.to_string()))
+    }
+}
+
+/// The signing key of a deployment stack
+///
+/// Artifacts built for the stack are signed with it, and the engine and
+/// workers trust code signed by its [`DidKey`].
+#[derive(Clone)]
+pub struct StackKey(SigningKey);
+
+impl StackKey {
+    /// Create a key from a 32-byte Ed25519 seed
+    pub fn from_seed(seed: [u8; 32]) -> Self {
+        Self(SigningKey::from_bytes(&seed))
+    }
+
+    /// Create a key from a hex-encoded seed, as stored in key files
+    pub fn from_hex(seed: &str) -> CryptoResult<Self> {
+        let bytes = hex::decode(seed.trim()).map_err(|e| CryptoError::InvalidKey(e.to_string()))?;
+        let seed: [u8; 32] = bytes
+            .try_into()
+            .map_err(|_| CryptoError::InvalidKey("seed must be 32 bytes".to_string()))?;
+        Ok(Self::from_seed(seed))
+    }
+
+    /// The DID identifying this key
+    pub fn did(&self) -> DidKey {
+        DidKey(self.0.verifying_key())
+    }
+
+    /// Sign `message`
+    pub fn sign(&self, message: &[u8]) -> Vec<u8> {
+        self.0.sign(message).to_bytes().to_vec()
+    }
+}
+
+impl fmt::Debug for StackKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("StackKey").field(&self.did().to_string()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_did_key_round_trip() {
+        let key = StackKey::from_seed([7; 32]);
+        let did = key.did().to_string();
+        assert!(did.starts_with("did:key:z6Mk"));
+        assert_eq!(did.parse::<DidKey>().unwrap(), key.did());
+
+        let signature = key.sign(b"artifact");
+        assert!(key.did().verify(b"artifact", &signature));
+        assert!(!key.did().verify(b"tampered", &signature));
+
+        assert!("did:web:example.org".parse::<DidKey>().is_err());
+        assert!(StackKey::from_hex("abcd").is_err());
+    }
+}
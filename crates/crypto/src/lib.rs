@@ -1 +1,32 @@
-// Cryptographic primitives, KMS/HSM integration
\ No newline at end of file
+// Cryptographic primitives, KMS/HSM integration
+
+pub mod attestation;
+pub mod did;
+
+pub use attestation::{Envelope, Statement, Subject, verify_artifact};
+pub use did::{DidKey, StackKey};
+
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+/// Errors from signing and verification
+#[derive(Debug, Error)]
+pub enum CryptoError {
+    #[error("Invalid key: {0}")]
+    InvalidKey(String),
+    #[error("Invalid DID: {0}")]
+    InvalidDid(String),
+    #[error("Malformed attestation: {0}")]
+    Malformed(String),
+    #[error("No signature from a trusted key")]
+    Untrusted,
+    #[error("Artifact digest does not match the attestation")]
+    DigestMismatch,
+}
+
+pub type CryptoResult<T> = Result<T, CryptoError>;
+
+/// Hex-encoded SHA-256 digest of `data`
+pub fn sha256_hex(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
@@ -1,111 +1,588 @@
+mod settings;
+
 use dashmap::DashMap;
-use dgv_dgl::v1::create_schema;
+use dgv_dgl::v1::global_symbols;
 use tower_lsp::jsonrpc::Result;
 use tower_lsp::lsp_types::*;
 use tower_lsp::{Client, LanguageServer, LspService, Server};
-use dgv_dgl::{Parser, Schema, SemanticInfo, CompletionEngine};
+use dgv_dgl::{DglDiagnostic, DiagnosticKind, Parser, SemanticInfo, WorkspaceIndex};
+use dgv_dgl::formatting::{format_document, format_range, BlockStructure};
+use dgv_dgl::workspace::{string_at, url_values};
 use miette::Diagnostic as _;
 use ropey::Rope;
+use serde::{Deserialize, Serialize};
+use settings::{SchemaContext, SchemaSelection, Settings};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::task::JoinHandle;
+
+/// Custom request returning diagrams of the workflow under the cursor
+pub const WORKFLOW_GRAPH_METHOD: &str = "degov/workflowGraph";
+
+/// Parameters of the `degov/workflowGraph` request
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkflowGraphParams {
+    pub text_document: TextDocumentIdentifier,
+    pub position: Position,
+}
+
+/// Diagram sources of a workflow definition, for the editor's preview panel
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkflowGraphResponse {
+    /// The document id
+    pub id: Option<String>,
+    /// Range of the workflow's `definition` node
+    pub range: Range,
+    /// Mermaid state diagram
+    pub mermaid: String,
+    /// Graphviz DOT digraph
+    pub dot: String,
+}
+
+/// Command validating a document right away, with the document URI as
+/// argument
+pub const VALIDATE_COMMAND: &str = "degov.validate";
+
+/// Command returning the `degov/workflowGraph` response for a document URI
+/// and position
+pub const SHOW_GRAPH_COMMAND: &str = "degov.showGraph";
 
+/// Command registering the workflow of a saved document with the engine,
+/// with the document URI as argument
+pub const REGISTER_COMMAND: &str = "degov.register";
+
+/// Environment variable naming the DeGov CLI run to register workflows
+///
+/// The CLI is never taken from client settings, which a workspace can set.
+pub const CLI_ENV: &str = "DEGOV_CLI";
+
+/// Extensions of the files indexed in workspace folders
+const DGL_EXTENSIONS: [&str; 2] = ["kdl", "dgl"];
+
+/// Directories never searched for DGL files
+const IGNORED_DIRS: [&str; 2] = ["target", "node_modules"];
+
+/// Pause in editing after which a changed document is validated
+const VALIDATION_DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Shared by the handlers and the background validation tasks, so cloning
+/// is cheap
+#[derive(Clone)]
 struct Backend {
     client: Client,
-    document_map: DashMap<String, DocumentData>,
-    schema: Schema,
-    completion_engine: CompletionEngine,
+    document_map: Arc<DashMap<String, DocumentData>>,
+    /// Settings last sent by the client
+    settings: Arc<RwLock<Settings>>,
+    /// Schemas the settings select
+    schemas: Arc<RwLock<SchemaSelection>>,
+    /// Workspace folders to index
+    roots: Arc<RwLock<Vec<PathBuf>>>,
+    /// Global symbols of every DGL file in the workspace
+    workspace: Arc<RwLock<WorkspaceIndex<Url>>>,
+    /// Text of the indexed files, to locate their symbols
+    workspace_files: Arc<DashMap<Url, Rope>>,
+    /// Pending validation of each open document
+    validations: Arc<DashMap<String, JoinHandle<()>>>,
+    /// Whether the client pulls diagnostics rather than having them pushed
+    pull_diagnostics: Arc<AtomicBool>,
+    /// Diagnostics last pulled for each document
+    pulled: Arc<DashMap<Url, PulledDiagnostics>>,
+    /// DeGov CLI run to register workflows, from the server's environment
+    cli: Arc<Path>,
+    /// Start of the server in milliseconds, so result ids handed out by an
+    /// earlier run are never mistaken for current ones
+    started_ms: u128,
+}
+
+/// Diagnostics handed to a client pulling them, numbered per document
+struct PulledDiagnostics {
+    /// Counts up each time the diagnostics of the document change
+    result: u64,
+    diagnostics: Vec<Diagnostic>,
+}
+
+/// Diagnostics and semantic information of a document, from a single parse
+struct Analysis {
+    /// The parsed document, `None` when the text is not valid KDL
+    document: Option<kdl::KdlDocument>,
+    /// Diagnostics of the parser and the schema
+    diagnostics: Vec<Diagnostic>,
+    /// Only computed when asked for, and only for valid KDL
+    semantic_info: Option<SemanticInfo>,
 }
 
 /// Data associated with a document
 struct DocumentData {
     /// The rope for efficient text manipulation
     rope: Rope,
-    /// Semantic information
+    /// Version of the text, as numbered by the client
+    version: i32,
+    /// Number of times the text was replaced, so a validation of an earlier
+    /// text is told apart even when the client kept the version, as on save
+    revision: u64,
+    /// Semantic information of the last validated version
     semantic_info: Option<SemanticInfo>,
 }
 
 impl Backend {
     fn new(client: Client) -> Self {
-        let schema = create_schema();
-        let completion_engine = CompletionEngine::new(schema.clone());
-        
         Self {
             client,
-            document_map: DashMap::new(),
-            schema,
-            completion_engine,
+            document_map: Arc::new(DashMap::new()),
+            settings: Arc::new(RwLock::new(Settings::default())),
+            schemas: Arc::new(RwLock::new(SchemaSelection::builtin())),
+            roots: Arc::new(RwLock::new(Vec::new())),
+            workspace: Arc::new(RwLock::new(WorkspaceIndex::new())),
+            workspace_files: Arc::new(DashMap::new()),
+            validations: Arc::new(DashMap::new()),
+            pull_diagnostics: Arc::new(AtomicBool::new(false)),
+            pulled: Arc::new(DashMap::new()),
+            cli: std::env::var_os(CLI_ENV)
+                .map_or_else(|| PathBuf::from("degov"), PathBuf::from)
+                .into(),
+            started_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_millis()),
         }
     }
 
-    async fn on_change(&self, uri: Url, text: &str) {
+    /// The schema applying to a document
+    fn schema_for(&self, uri: &Url) -> Arc<SchemaContext> {
+        let schemas = self.schemas.read().unwrap_or_else(|e| e.into_inner());
+        schemas.schema_for(uri.to_file_path().ok().as_deref())
+    }
+
+    /// Load the schemas the current settings select and revalidate open
+    /// documents against them
+    async fn apply_settings(&self) {
+        let settings = self.settings.read().unwrap_or_else(|e| e.into_inner()).clone();
+        let roots = self.roots.read().unwrap_or_else(|e| e.into_inner()).clone();
+        let (selection, problems) = tokio::task::spawn_blocking(move || SchemaSelection::load(&settings, &roots))
+            .await
+            .unwrap_or_else(|_| (SchemaSelection::builtin(), Vec::new()));
+        *self.schemas.write().unwrap_or_else(|e| e.into_inner()) = selection;
+
+        for problem in problems {
+            self.client.show_message(MessageType::ERROR, problem).await;
+        }
+        self.revalidate_open_documents().await;
+    }
+
+    /// Store the new text of a document and schedule its validation
+    ///
+    /// The semantic information of the previous version is kept until the
+    /// new one is validated.
+    fn on_change(&self, uri: Url, text: &str, version: i32) {
         let rope = Rope::from_str(text);
-        
-        // Parse and analyze the document
-        let semantic_info = if let Ok(doc) = text.parse::<kdl::KdlDocument>() {
-            Some(SemanticInfo::analyze(&doc, &self.schema, text))
-        } else {
-            None
+        let (revision, semantic_info) = match self.document_map.remove(uri.as_str()) {
+            Some((_, data)) => (data.revision + 1, data.semantic_info),
+            None => (0, None),
         };
-        
         self.document_map.insert(
             uri.to_string(),
             DocumentData {
                 rope,
+                version,
+                revision,
                 semantic_info,
             },
         );
+        self.schedule_validation(uri);
+    }
+
+    /// Validate a document once editing pauses, superseding its pending
+    /// validation
+    fn schedule_validation(&self, uri: Url) {
+        let key = uri.to_string();
+        let backend = self.clone();
+        let task = tokio::spawn(async move {
+            tokio::time::sleep(VALIDATION_DEBOUNCE).await;
+            backend.validate(uri.clone()).await;
+            // Leave the entry of a validation scheduled since then in place
+            let id = tokio::task::id();
+            backend.validations.remove_if(uri.as_str(), |_, task| task.id() == id);
+        });
+        if let Some(pending) = self.validations.insert(key, task) {
+            pending.abort();
+        }
+    }
+
+    /// Analyze and validate the current version of a document
+    ///
+    /// Results are dropped when the document changed in the meantime, so
+    /// diagnostics are only published for the latest version.
+    async fn validate(&self, uri: Url) {
+        let Some((text, version, revision)) = self
+            .document_map
+            .get(uri.as_str())
+            .map(|data| (data.rope.to_string(), data.version, data.revision))
+        else {
+            return;
+        };
+
+        let Some(analysis) = self.analyze(&uri, &text, true).await else {
+            return;
+        };
+        if let Some(document) = &analysis.document {
+            self.index_document(&uri, &text, document);
+        }
+        // Clients pulling diagnostics ask for them when they need them
+        let diagnostics = if self.pulls_diagnostics() {
+            None
+        } else {
+            let mut diagnostics = analysis.diagnostics;
+            diagnostics.extend(self.workspace_diagnostics(&uri, &Rope::from_str(&text)));
+            Some(diagnostics)
+        };
+
+        match self.document_map.get_mut(uri.as_str()) {
+            Some(mut data) if data.revision == revision => data.semantic_info = analysis.semantic_info,
+            _ => return,
+        }
+        if let Some(diagnostics) = diagnostics {
+            self.client
+                .publish_diagnostics(uri, diagnostics, Some(version))
+                .await;
+        }
+    }
+
+    fn pulls_diagnostics(&self) -> bool {
+        self.pull_diagnostics.load(Ordering::Relaxed)
+    }
+
+    /// Hand fresh diagnostics of a document to the client
+    ///
+    /// Clients pulling diagnostics are asked to pull again instead.
+    async fn report_diagnostics(&self, uri: Url, diagnostics: Vec<Diagnostic>, version: Option<i32>) {
+        if self.pulls_diagnostics() {
+            self.refresh_diagnostics().await;
+        } else {
+            self.client.publish_diagnostics(uri, diagnostics, version).await;
+        }
+    }
+
+    /// Ask a client pulling diagnostics to pull them again
+    async fn refresh_diagnostics(&self) {
+        if let Err(e) = self.client.workspace_diagnostic_refresh().await {
+            self.client
+                .log_message(MessageType::WARNING, format!("Cannot refresh diagnostics: {}", e))
+                .await;
+        }
+    }
+
+    /// A pull diagnostics report, leaving out the diagnostics when they
+    /// match the ones the client already has
+    fn document_report(
+        &self,
+        uri: &Url,
+        diagnostics: Vec<Diagnostic>,
+        previous_result_id: Option<&str>,
+    ) -> DocumentDiagnosticReport {
+        let result = {
+            let mut pulled = self.pulled.entry(uri.clone()).or_insert_with(|| PulledDiagnostics {
+                result: 0,
+                diagnostics: Vec::new(),
+            });
+            if pulled.diagnostics != diagnostics {
+                pulled.result += 1;
+                pulled.diagnostics = diagnostics.clone();
+            }
+            pulled.result
+        };
+
+        let result_id = format!("{}.{}", self.started_ms, result);
+        if previous_result_id == Some(result_id.as_str()) {
+            return DocumentDiagnosticReport::Unchanged(RelatedUnchangedDocumentDiagnosticReport {
+                related_documents: None,
+                unchanged_document_diagnostic_report: UnchangedDocumentDiagnosticReport { result_id },
+            });
+        }
+        DocumentDiagnosticReport::Full(RelatedFullDocumentDiagnosticReport {
+            related_documents: None,
+            full_document_diagnostic_report: FullDocumentDiagnosticReport {
+                result_id: Some(result_id),
+                items: diagnostics,
+            },
+        })
+    }
+
+    /// Text of an open or indexed document, with the version of open ones
+    fn document_text(&self, uri: &Url) -> Option<(String, Option<i32>)> {
+        if let Some(data) = self.document_map.get(uri.as_str()) {
+            return Some((data.rope.to_string(), Some(data.version)));
+        }
+        self.workspace_files.get(uri).map(|rope| (rope.to_string(), None))
+    }
+
+    /// Parse, validate and, with `semantics`, analyze a document off the
+    /// async workers, parsing it only once
+    async fn analyze(&self, uri: &Url, text: &str, semantics: bool) -> Option<Analysis> {
+        let context = self.schema_for(uri);
+        let (text, name) = (text.to_string(), uri.to_string());
+        tokio::task::spawn_blocking(move || {
+            let rope = Rope::from_str(&text);
+            let parser = Parser::new(text.clone(), name).with_schema(context.schema.clone());
+            let (document, diagnostics) = parser.check();
+            let semantic_info = document
+                .as_ref()
+                .filter(|_| semantics)
+                .map(|document| SemanticInfo::analyze(document, &context.schema, &text));
+            Analysis {
+                document,
+                diagnostics: diagnostics
+                    .iter()
+                    .map(|diag| to_lsp_diagnostic(diag, &rope))
+                    .collect(),
+                semantic_info,
+            }
+        })
+        .await
+        .ok()
     }
 
     async fn validate_document(&self, uri: &Url, text: &str) -> Vec<Diagnostic> {
-        let rope = Rope::from_str(text);
-        let parser = Parser::new(text.to_string(), uri.to_string())
-            .with_schema(self.schema.clone());
-        
-        match parser.parse() {
-            Ok(parsed) => {
-                // Successfully parsed and validated
+        let Some(analysis) = self.analyze(uri, text, false).await else {
+            return Vec::new();
+        };
+        // Runs for every workspace file on each pull, so stay out of the
+        // client's log
+        tracing::debug!("Validated DGL document {}: {} problem(s)", uri, analysis.diagnostics.len());
+
+        let mut diagnostics = analysis.diagnostics;
+        diagnostics.extend(self.workspace_diagnostics(uri, &Rope::from_str(text)));
+        diagnostics
+    }
+
+    /// Report symbols of a document that another workspace file defines too
+    fn workspace_diagnostics(&self, uri: &Url, rope: &Rope) -> Vec<Diagnostic> {
+        let workspace = self.workspace.read().unwrap_or_else(|e| e.into_inner());
+        workspace
+            .conflicts(uri)
+            .into_iter()
+            .map(|(symbol, other, definition)| {
+                let kind = DiagnosticKind::Duplicate {
+                    item_type: "definition".to_string(),
+                    name: symbol.name.clone(),
+                };
+                let span = symbol.definition_span;
+                let mut diagnostic = Diagnostic::new(
+                    Range::new(
+                        char_to_position(span.offset(), rope),
+                        char_to_position(span.offset() + span.len(), rope),
+                    ),
+                    Some(DiagnosticSeverity::ERROR),
+                    Some(NumberOrString::String(kind.code().to_string())),
+                    Some("degov-dgl".to_string()),
+                    kind.message(),
+                    None,
+                    None,
+                );
+                diagnostic.related_information = self
+                    .workspace_location(other, definition.definition_span)
+                    .map(|location| {
+                        vec![DiagnosticRelatedInformation {
+                            location,
+                            message: "also defined here".to_string(),
+                        }]
+                    });
+                diagnostic
+            })
+            .collect()
+    }
+
+    /// Index the global symbols of a file
+    ///
+    /// A file that stops parsing keeps its last symbols, so references stay
+    /// resolvable while it is being edited.
+    fn index_file(&self, uri: &Url, text: &str) {
+        let Ok(document) = text.parse::<kdl::KdlDocument>() else {
+            return;
+        };
+        self.index_document(uri, text, &document);
+    }
+
+    /// Index the global symbols of a file already parsed
+    fn index_document(&self, uri: &Url, text: &str, document: &kdl::KdlDocument) {
+        self.workspace_files.insert(uri.clone(), Rope::from_str(text));
+        self.workspace
+            .write()
+            .unwrap_or_else(|e| e.into_inner())
+            .update(uri.clone(), global_symbols(document));
+    }
+
+    /// Remove a file from the index
+    fn unindex_file(&self, uri: &Url) {
+        self.workspace_files.remove(uri);
+        self.pulled.remove(uri);
+        self.workspace.write().unwrap_or_else(|e| e.into_inner()).remove(uri);
+    }
+
+    /// Index a file as saved on disk, unless it is open in the editor
+    async fn index_file_from_disk(&self, uri: &Url) {
+        if self.document_map.contains_key(uri.as_str()) {
+            return;
+        }
+        let Ok(path) = uri.to_file_path() else {
+            return;
+        };
+        match tokio::fs::read_to_string(&path).await {
+            Ok(text) => self.index_file(uri, &text),
+            Err(_) => self.unindex_file(uri),
+        }
+    }
+
+    /// Index every DGL file in the workspace folders
+    async fn index_workspace(&self) {
+        let roots = self.roots.read().unwrap_or_else(|e| e.into_inner()).clone();
+        let files = tokio::task::spawn_blocking(move || {
+            let mut files = Vec::new();
+            for root in &roots {
+                collect_dgl_files(root, &mut files);
+            }
+            files
+        })
+        .await
+        .unwrap_or_default();
+
+        for path in &files {
+            if let Ok(uri) = Url::from_file_path(path) {
+                self.index_file_from_disk(&uri).await;
+            }
+        }
+
+        self.client
+            .log_message(MessageType::INFO, format!("Indexed {} DGL files", files.len()))
+            .await;
+        self.revalidate_open_documents().await;
+    }
+
+    /// Publish fresh diagnostics for open documents after the index changed
+    async fn revalidate_open_documents(&self) {
+        let open: Vec<String> = self.document_map.iter().map(|entry| entry.key().clone()).collect();
+
+        for uri in open {
+            if let Ok(uri) = Url::parse(&uri) {
+                self.schedule_validation(uri);
+            }
+        }
+        if self.pulls_diagnostics() {
+            self.refresh_diagnostics().await;
+        }
+    }
+
+    /// Location of a span in an indexed file
+    fn workspace_location(&self, uri: &Url, span: miette::SourceSpan) -> Option<Location> {
+        let rope = self.workspace_files.get(uri)?;
+        Some(Location::new(
+            uri.clone(),
+            Range::new(
+                char_to_position(span.offset(), &rope),
+                char_to_position(span.offset() + span.len(), &rope),
+            ),
+        ))
+    }
+
+    /// Definitions in the workspace of `target`, or else of the string value
+    /// at `offset`, e.g. a document id or a `<id>#<definition>` reference
+    ///
+    /// The definition the cursor is on is left out, so going to the
+    /// definition of a document's own `id` finds the other files using it.
+    fn workspace_definitions(&self, uri: &Url, text: &str, target: Option<&str>, offset: usize) -> Vec<Location> {
+        let Ok(document) = text.parse::<kdl::KdlDocument>() else {
+            return Vec::new();
+        };
+        let Some(name) = target.or_else(|| string_at(&document, offset)) else {
+            return Vec::new();
+        };
+
+        let workspace = self.workspace.read().unwrap_or_else(|e| e.into_inner());
+        let locate = |name: &str| -> Vec<Location> {
+            workspace
+                .lookup(name)
+                .filter(|(file, symbol)| {
+                    let span = symbol.definition_span;
+                    *file != uri || offset < span.offset() || offset > span.offset() + span.len()
+                })
+                .filter_map(|(file, symbol)| self.workspace_location(file, symbol.definition_span))
+                .collect()
+        };
+
+        // Plain definition names refer to the document's own definitions
+        let locations = locate(name);
+        match global_symbols(&document).first() {
+            Some(id) if locations.is_empty() => locate(&format!("{}#{}", id.name, name)),
+            _ => locations,
+        }
+    }
+
+    /// Render the workflow under the cursor
+    ///
+    /// Returns `None` when the document does not parse or has no workflow at
+    /// the position, so the preview keeps showing the last good diagram.
+    async fn workflow_graph(&self, params: WorkflowGraphParams) -> Result<Option<WorkflowGraphResponse>> {
+        let uri = params.text_document.uri;
+
+        let offset = match self.position_to_offset(&uri, params.position) {
+            Some(offset) => offset,
+            None => return Ok(None),
+        };
+        let doc_data = match self.document_map.get(&uri.to_string()) {
+            Some(data) => data,
+            None => return Ok(None),
+        };
+
+        let document = match doc_data.rope.to_string().parse::<kdl::KdlDocument>() {
+            Ok(document) => document,
+            Err(_) => return Ok(None),
+        };
+        let graph = match dgv_dgl::v1::workflow_at(&document, offset) {
+            Some(graph) => graph,
+            None => return Ok(None),
+        };
+
+        Ok(Some(WorkflowGraphResponse {
+            range: Range::new(
+                char_to_position(graph.span.offset(), &doc_data.rope),
+                char_to_position(graph.span.offset() + graph.span.len(), &doc_data.rope),
+            ),
+            mermaid: graph.to_mermaid(),
+            dot: graph.to_dot(),
+            id: graph.id,
+        }))
+    }
+
+    /// Register the workflow of a document, as saved, by running the CLI
+    async fn register_workflow(&self, uri: &Url) {
+        let Ok(path) = uri.to_file_path() else {
+            self.client
+                .show_message(MessageType::ERROR, format!("Cannot register {}: not a file", uri))
+                .await;
+            return;
+        };
+        let output = tokio::process::Command::new(&*self.cli)
+            .arg("register")
+            .arg(&path)
+            .output()
+            .await;
+        match output {
+            Ok(output) if output.status.success() => {
+                let message = String::from_utf8_lossy(&output.stdout).trim().to_string();
+                self.client.show_message(MessageType::INFO, message).await;
+            }
+            Ok(output) => {
+                let message = String::from_utf8_lossy(&output.stderr).trim().to_string();
                 self.client
-                    .log_message(MessageType::INFO, "✓ Valid DGL document")
+                    .show_message(MessageType::ERROR, format!("Registering {} failed: {}", path.display(), message))
                     .await;
-                
-                // Convert any warnings to diagnostics
-                parsed
-                    .diagnostics
-                    .iter()
-                    .map(|diag| {
-                        Diagnostic::new(
-                            Range::new(
-                                char_to_position(diag.span.offset(), &rope),
-                                char_to_position(diag.span.offset() + diag.span.len(), &rope),
-                            ),
-                            diag.severity().map(to_lsp_sev),
-                            diag.code().map(|c| NumberOrString::String(c.to_string())),
-                            Some("degov-dgl".to_string()),
-                            diag.to_string(),
-                            None,
-                            None,
-                        )
-                    })
-                    .collect()
             }
-            Err(dgl_err) => {
-                // Convert all diagnostics to LSP diagnostics
-                dgl_err
-                    .diagnostics
-                    .iter()
-                    .map(|diag| {
-                        Diagnostic::new(
-                            Range::new(
-                                char_to_position(diag.span.offset(), &rope),
-                                char_to_position(diag.span.offset() + diag.span.len(), &rope),
-                            ),
-                            diag.severity().map(to_lsp_sev),
-                            diag.code().map(|c| NumberOrString::String(c.to_string())),
-                            Some("degov-dgl".to_string()),
-                            diag.to_string(),
-                            None,
-                            None,
-                        )
-                    })
-                    .collect()
+            Err(e) => {
+                self.client
+                    .show_message(MessageType::ERROR, format!("Cannot run {}: {}", self.cli.display(), e))
+                    .await;
             }
         }
     }
@@ -125,6 +602,94 @@ impl Backend {
         let line_start = rope.line_to_char(line_idx);
         Some(line_start + column_idx)
     }
+
+    /// Compute edits after the user typed `{`, `}` or a newline
+    ///
+    /// `offset` is the character index just after the typed character.
+    fn on_type_edits(&self, rope: &Rope, offset: usize, ch: &str, unit: &str) -> Vec<TextEdit> {
+        let text = rope.to_string();
+        let blocks = BlockStructure::scan(&text);
+        let line_idx = rope.char_to_line(offset);
+
+        match ch {
+            "{" => {
+                let brace = offset - 1;
+                if blocks.block_opened_at(brace).is_none() {
+                    // Brace inside a string or comment
+                    return Vec::new();
+                }
+
+                // Only close the block if the typed brace is the one left unmatched
+                let mut without = rope.clone();
+                without.remove(brace..offset);
+                let without = BlockStructure::scan(&without.to_string());
+                if unclosed_count(&blocks) <= unclosed_count(&without) {
+                    return Vec::new();
+                }
+
+                let position = char_to_position(offset, rope);
+                vec![TextEdit::new(Range::new(position, position), "}".to_string())]
+            }
+            "}" => {
+                let brace = offset - 1;
+                if blocks.block_closed_at(brace).is_none() {
+                    return Vec::new();
+                }
+
+                let line_start = rope.line_to_char(line_idx);
+                if rope.slice(line_start..brace).chars().any(|c| !c.is_whitespace()) {
+                    return Vec::new();
+                }
+
+                let depth = blocks.depth_at(brace).saturating_sub(1);
+                reindent_line(rope, line_idx, depth, unit).into_iter().collect()
+            }
+            "\n" => {
+                let depth = blocks.depth_at(offset);
+                let line_start = rope.line_to_char(line_idx);
+                let (indent_len, first) = leading_whitespace(rope, line_idx);
+                let first_offset = line_start + indent_len;
+
+                // Cursor between `{` and `}`: indent the new line and move the
+                // closing brace onto its own line
+                if first == Some('}') && blocks.block_closed_at(first_offset).is_some() {
+                    let replacement = format!(
+                        "{}\n{}",
+                        unit.repeat(depth),
+                        unit.repeat(depth.saturating_sub(1))
+                    );
+                    return vec![TextEdit::new(
+                        Range::new(
+                            Position::new(line_idx as u32, 0),
+                            Position::new(line_idx as u32, indent_len as u32),
+                        ),
+                        replacement,
+                    )];
+                }
+
+                let mut edits: Vec<TextEdit> =
+                    reindent_line(rope, line_idx, depth, unit).into_iter().collect();
+
+                // Newline right after an unclosed `{`: add the closing brace
+                if let Some(open) = last_non_whitespace_before(rope, line_start) {
+                    let unclosed = blocks
+                        .block_opened_at(open)
+                        .is_some_and(|block| block.close.is_none());
+                    if unclosed {
+                        let line_end = line_start + line_len(rope, line_idx);
+                        let position = char_to_position(line_end, rope);
+                        edits.push(TextEdit::new(
+                            Range::new(position, position),
+                            format!("\n{}}}", unit.repeat(depth.saturating_sub(1))),
+                        ));
+                    }
+                }
+
+                edits
+            }
+            _ => Vec::new(),
+        }
+    }
 }
 
 // Helper trait to convert u32 to CompletionItemKind
@@ -179,6 +744,157 @@ fn char_to_position(char_idx: usize, rope: &Rope) -> Position {
     Position::new(line_idx as u32, column_idx as u32)
 }
 
+/// Collect the DGL files below `dir`, skipping hidden and build directories
+fn collect_dgl_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if path.is_dir() {
+            if !name.starts_with('.') && !IGNORED_DIRS.contains(&name.as_ref()) {
+                collect_dgl_files(&path, files);
+            }
+        } else if is_dgl_file(&path) {
+            files.push(path);
+        }
+    }
+}
+
+/// Whether a path has one of the DGL file extensions
+fn is_dgl_file(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| DGL_EXTENSIONS.contains(&ext))
+}
+
+/// Folding ranges of children blocks and multi-line strings
+///
+/// Blocks fold up to the line before their closing brace, which stays
+/// visible; strings fold entirely.
+fn folding_ranges(blocks: &BlockStructure, rope: &Rope) -> Vec<FoldingRange> {
+    let range = |start_line: usize, end_line: usize, kind: Option<FoldingRangeKind>| FoldingRange {
+        start_line: start_line as u32,
+        end_line: end_line as u32,
+        kind,
+        ..Default::default()
+    };
+
+    let block_ranges = blocks.blocks().iter().filter_map(|block| {
+        let start_line = rope.char_to_line(block.open);
+        let end_line = rope.char_to_line(block.close?).checked_sub(1)?;
+        (end_line > start_line).then(|| range(start_line, end_line, Some(FoldingRangeKind::Region)))
+    });
+    let string_ranges = blocks.multiline_strings().iter().filter_map(|string| {
+        let start_line = rope.char_to_line(string.start);
+        let end_line = rope.char_to_line(string.end.saturating_sub(1));
+        (end_line > start_line).then(|| range(start_line, end_line, None))
+    });
+
+    block_ranges.chain(string_ranges).collect()
+}
+
+/// Number of blocks without a closing brace
+fn unclosed_count(blocks: &BlockStructure) -> usize {
+    blocks.blocks().iter().filter(|b| b.close.is_none()).count()
+}
+
+/// Indentation unit from the client's formatting options
+fn indent_unit(options: &FormattingOptions) -> String {
+    if options.insert_spaces {
+        " ".repeat(options.tab_size as usize)
+    } else {
+        "\t".to_string()
+    }
+}
+
+/// Length of a line in characters, excluding the line break
+fn line_len(rope: &Rope, line_idx: usize) -> usize {
+    let line = rope.line(line_idx);
+    let mut len = line.len_chars();
+    while len > 0 && matches!(line.char(len - 1), '\n' | '\r') {
+        len -= 1;
+    }
+    len
+}
+
+/// Length of a line's leading whitespace and the first character after it
+fn leading_whitespace(rope: &Rope, line_idx: usize) -> (usize, Option<char>) {
+    let line = rope.line(line_idx);
+    let len = line_len(rope, line_idx);
+    let indent = line.chars().take(len).take_while(|c| *c == ' ' || *c == '\t').count();
+    let first = (indent < len).then(|| line.char(indent));
+    (indent, first)
+}
+
+/// Character index of the last non-whitespace character before `offset`
+fn last_non_whitespace_before(rope: &Rope, offset: usize) -> Option<usize> {
+    (0..offset).rev().find(|&idx| !rope.char(idx).is_whitespace())
+}
+
+/// Replace a line's leading whitespace so it sits at `depth`
+fn reindent_line(rope: &Rope, line_idx: usize, depth: usize, unit: &str) -> Option<TextEdit> {
+    let (indent_len, _) = leading_whitespace(rope, line_idx);
+    let line_start = rope.line_to_char(line_idx);
+    let indent = unit.repeat(depth);
+
+    if rope.slice(line_start..line_start + indent_len) == indent.as_str() {
+        return None;
+    }
+
+    Some(TextEdit::new(
+        Range::new(
+            Position::new(line_idx as u32, 0),
+            Position::new(line_idx as u32, indent_len as u32),
+        ),
+        indent,
+    ))
+}
+
+/// Quick fix carried in the `data` of a diagnostic until the client asks
+/// for code actions
+#[derive(Debug, Serialize, Deserialize)]
+struct QuickFix {
+    title: String,
+    edits: Vec<TextEdit>,
+}
+
+/// Convert a DGL diagnostic to an LSP diagnostic
+fn to_lsp_diagnostic(diag: &DglDiagnostic, rope: &Rope) -> Diagnostic {
+    let span_range = |offset: usize, len: usize| {
+        Range::new(char_to_position(offset, rope), char_to_position(offset + len, rope))
+    };
+
+    let mut diagnostic = Diagnostic::new(
+        span_range(diag.span.offset(), diag.span.len()),
+        diag.severity().map(to_lsp_sev),
+        diag.code().map(|c| NumberOrString::String(c.to_string())),
+        Some("degov-dgl".to_string()),
+        diag.to_string(),
+        None,
+        None,
+    );
+
+    let fixes: Vec<QuickFix> = diag
+        .fixes
+        .iter()
+        .map(|fix| QuickFix {
+            title: fix.title.clone(),
+            edits: fix
+                .edits
+                .iter()
+                .map(|(span, text)| TextEdit::new(span_range(span.offset(), span.len()), text.clone()))
+                .collect(),
+        })
+        .collect();
+    if !fixes.is_empty() {
+        diagnostic.data = serde_json::to_value(fixes).ok();
+    }
+    diagnostic
+}
+
 /// Convert miette severity to LSP diagnostic severity
 fn to_lsp_sev(sev: miette::Severity) -> DiagnosticSeverity {
     match sev {
@@ -190,7 +906,31 @@ fn to_lsp_sev(sev: miette::Severity) -> DiagnosticSeverity {
 
 #[tower_lsp::async_trait]
 impl LanguageServer for Backend {
-    async fn initialize(&self, _: InitializeParams) -> Result<InitializeResult> {
+    async fn initialize(&self, params: InitializeParams) -> Result<InitializeResult> {
+        #[allow(deprecated)]
+        let roots: Vec<PathBuf> = match (params.workspace_folders, params.root_uri) {
+            (Some(folders), _) => folders.iter().filter_map(|f| f.uri.to_file_path().ok()).collect(),
+            (None, Some(root)) => root.to_file_path().into_iter().collect(),
+            (None, None) => Vec::new(),
+        };
+        *self.roots.write().unwrap_or_else(|e| e.into_inner()) = roots;
+        let pull_diagnostics = params
+            .capabilities
+            .text_document
+            .as_ref()
+            .is_some_and(|text_document| text_document.diagnostic.is_some());
+        self.pull_diagnostics.store(pull_diagnostics, Ordering::Relaxed);
+        if let Some(options) = params.initialization_options {
+            match Settings::from_value(options) {
+                Ok(settings) => *self.settings.write().unwrap_or_else(|e| e.into_inner()) = settings,
+                Err(e) => {
+                    self.client
+                        .log_message(MessageType::WARNING, format!("Ignoring invalid initialization options: {}", e))
+                        .await;
+                }
+            }
+        }
+
         Ok(InitializeResult {
             capabilities: ServerCapabilities {
                 text_document_sync: Some(TextDocumentSyncCapability::Options(
@@ -210,9 +950,45 @@ impl LanguageServer for Backend {
                     resolve_provider: Some(false),
                     ..Default::default()
                 }),
+                signature_help_provider: Some(SignatureHelpOptions {
+                    trigger_characters: Some(vec![" ".to_string()]),
+                    retrigger_characters: None,
+                    work_done_progress_options: Default::default(),
+                }),
                 definition_provider: Some(OneOf::Left(true)),
                 references_provider: Some(OneOf::Left(true)),
                 document_symbol_provider: Some(OneOf::Left(true)),
+                code_action_provider: Some(CodeActionProviderCapability::Simple(true)),
+                folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
+                inlay_hint_provider: Some(OneOf::Left(true)),
+                diagnostic_provider: Some(DiagnosticServerCapabilities::Options(DiagnosticOptions {
+                    identifier: Some("degov-dgl".to_string()),
+                    // Duplicate definitions depend on the other files
+                    inter_file_dependencies: true,
+                    workspace_diagnostics: true,
+                    work_done_progress_options: Default::default(),
+                })),
+                code_lens_provider: Some(CodeLensOptions {
+                    resolve_provider: Some(false),
+                }),
+                execute_command_provider: Some(ExecuteCommandOptions {
+                    commands: vec![
+                        VALIDATE_COMMAND.to_string(),
+                        SHOW_GRAPH_COMMAND.to_string(),
+                        REGISTER_COMMAND.to_string(),
+                    ],
+                    work_done_progress_options: Default::default(),
+                }),
+                document_link_provider: Some(DocumentLinkOptions {
+                    resolve_provider: Some(false),
+                    work_done_progress_options: Default::default(),
+                }),
+                document_formatting_provider: Some(OneOf::Left(true)),
+                document_range_formatting_provider: Some(OneOf::Left(true)),
+                document_on_type_formatting_provider: Some(DocumentOnTypeFormattingOptions {
+                    first_trigger_character: "\n".to_string(),
+                    more_trigger_character: Some(vec!["{".to_string(), "}".to_string()]),
+                }),
                 ..Default::default()
             },
             server_info: Some(ServerInfo {
@@ -226,6 +1002,54 @@ impl LanguageServer for Backend {
         self.client
             .log_message(MessageType::INFO, "DeGov DGL Language Server initialized")
             .await;
+
+        // Keep the index current while files change outside the editor
+        let watchers = DidChangeWatchedFilesRegistrationOptions {
+            watchers: vec![FileSystemWatcher {
+                glob_pattern: GlobPattern::String("**/*.{kdl,dgl}".to_string()),
+                kind: None,
+            }],
+        };
+        let registration = Registration {
+            id: "dgl-file-watcher".to_string(),
+            method: "workspace/didChangeWatchedFiles".to_string(),
+            register_options: serde_json::to_value(watchers).ok(),
+        };
+        if let Err(e) = self.client.register_capability(vec![registration]).await {
+            self.client
+                .log_message(MessageType::WARNING, format!("Cannot watch DGL files: {}", e))
+                .await;
+        }
+
+        self.apply_settings().await;
+        self.index_workspace().await;
+    }
+
+    async fn did_change_configuration(&self, params: DidChangeConfigurationParams) {
+        match Settings::from_value(params.settings) {
+            Ok(settings) => {
+                *self.settings.write().unwrap_or_else(|e| e.into_inner()) = settings;
+                self.apply_settings().await;
+            }
+            Err(e) => {
+                self.client
+                    .log_message(MessageType::WARNING, format!("Ignoring invalid DGL settings: {}", e))
+                    .await;
+            }
+        }
+    }
+
+    async fn did_change_watched_files(&self, params: DidChangeWatchedFilesParams) {
+        for change in params.changes {
+            if change.typ == FileChangeType::DELETED {
+                if !self.document_map.contains_key(change.uri.as_str()) {
+                    self.unindex_file(&change.uri);
+                }
+            } else {
+                self.index_file_from_disk(&change.uri).await;
+            }
+        }
+        self.revalidate_open_documents().await;
     }
 
     async fn shutdown(&self) -> Result<()> {
@@ -237,6 +1061,11 @@ impl LanguageServer for Backend {
     
     async fn did_close(&self, params: DidCloseTextDocumentParams) {
         self.document_map.remove(&params.text_document.uri.to_string());
+        if let Some((_, pending)) = self.validations.remove(params.text_document.uri.as_str()) {
+            pending.abort();
+        }
+        // Unsaved edits are dropped, so index the file as on disk again
+        self.index_file_from_disk(&params.text_document.uri).await;
         self.client
             .log_message(MessageType::INFO, format!("Closed: {}", params.text_document.uri))
             .await;
@@ -247,28 +1076,14 @@ impl LanguageServer for Backend {
             .log_message(MessageType::INFO, format!("Opened: {}", params.text_document.uri))
             .await;
 
-        let uri = params.text_document.uri.clone();
-        let text = params.text_document.text.clone();
-        
-        self.on_change(uri.clone(), &text).await;
-        let diagnostics = self.validate_document(&uri, &text).await;
-
-        self.client
-            .publish_diagnostics(uri, diagnostics, None)
-            .await;
+        let document = params.text_document;
+        self.on_change(document.uri, &document.text, document.version);
     }
 
     async fn did_change(&self, params: DidChangeTextDocumentParams) {
         if let Some(change) = params.content_changes.first() {
-            let uri = params.text_document.uri.clone();
-            let text = change.text.clone();
-            
-            self.on_change(uri.clone(), &text).await;
-            let diagnostics = self.validate_document(&uri, &text).await;
-
-            self.client
-                .publish_diagnostics(uri, diagnostics, None)
-                .await;
+            let document = params.text_document;
+            self.on_change(document.uri, &change.text, document.version);
         }
     }
 
@@ -277,15 +1092,12 @@ impl LanguageServer for Backend {
             .log_message(MessageType::INFO, format!("Saved: {}", params.text_document.uri))
             .await;
 
+        // Saving keeps the client's version, the new revision supersedes
+        // validations of the text before
         if let Some(text) = params.text {
-            let uri = params.text_document.uri.clone();
-            
-            self.on_change(uri.clone(), &text).await;
-            let diagnostics = self.validate_document(&uri, &text).await;
-
-            self.client
-                .publish_diagnostics(uri, diagnostics, None)
-                .await;
+            let uri = params.text_document.uri;
+            let version = self.document_map.get(uri.as_str()).map_or(0, |data| data.version);
+            self.on_change(uri, &text, version);
         }
     }
 
@@ -351,7 +1163,7 @@ impl LanguageServer for Backend {
         };
         
         // Get completions
-        let completions = self.completion_engine.complete(&doc, offset, &text);
+        let completions = self.schema_for(&uri).completion_engine.complete(&doc, offset, &text);
         let lsp_completions: Vec<_> = completions.iter().map(|c| {
             CompletionItem {
                 label: c.label.clone(),
@@ -378,6 +1190,61 @@ impl LanguageServer for Backend {
         Ok(Some(CompletionResponse::Array(lsp_completions)))
     }
 
+    async fn signature_help(&self, params: SignatureHelpParams) -> Result<Option<SignatureHelp>> {
+        let uri = params.text_document_position_params.text_document.uri;
+        let position = params.text_document_position_params.position;
+
+        // Get the document
+        let doc_data = match self.document_map.get(&uri.to_string()) {
+            Some(data) => data,
+            None => return Ok(None),
+        };
+
+        // Get the text
+        let text = doc_data.rope.to_string();
+
+        // Parse the document
+        let doc = match text.parse::<kdl::KdlDocument>() {
+            Ok(doc) => doc,
+            Err(_) => return Ok(None),
+        };
+
+        // Convert position to offset
+        let offset = match self.position_to_offset(&uri, position) {
+            Some(offset) => offset,
+            None => return Ok(None),
+        };
+
+        let Some(help) = self.schema_for(&uri).completion_engine.signature_help(&doc, offset, &text) else {
+            return Ok(None);
+        };
+        let markdown = |value: &String| {
+            Documentation::MarkupContent(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value: value.clone(),
+            })
+        };
+        let parameters = help
+            .parameters
+            .iter()
+            .map(|parameter| ParameterInformation {
+                label: ParameterLabel::Simple(help.label[parameter.label.clone()].to_string()),
+                documentation: parameter.documentation.as_ref().map(markdown),
+            })
+            .collect();
+
+        Ok(Some(SignatureHelp {
+            signatures: vec![SignatureInformation {
+                label: help.label.clone(),
+                documentation: help.documentation.as_ref().map(markdown),
+                parameters: Some(parameters),
+                active_parameter: help.active_parameter.map(|idx| idx as u32),
+            }],
+            active_signature: Some(0),
+            active_parameter: help.active_parameter.map(|idx| idx as u32),
+        }))
+    }
+
     async fn goto_definition(&self, params: GotoDefinitionParams) -> Result<Option<GotoDefinitionResponse>> {
         let uri = params.text_document_position_params.text_document.uri;
         let position = params.text_document_position_params.position;
@@ -417,8 +1284,16 @@ impl LanguageServer for Backend {
                 ))));
             }
         }
-        
-        Ok(None)
+
+        // Resolve NSID and node references, or else any string value,
+        // against the definitions of the whole workspace
+        let target = semantic_info.find_reference_at(offset).map(|(_, target)| target);
+        let mut locations = self.workspace_definitions(&uri, &doc_data.rope.to_string(), target, offset);
+        match locations.len() {
+            0 => Ok(None),
+            1 => Ok(locations.pop().map(GotoDefinitionResponse::Scalar)),
+            _ => Ok(Some(GotoDefinitionResponse::Array(locations))),
+        }
     }
 
     async fn references(&self, params: ReferenceParams) -> Result<Option<Vec<Location>>> {
@@ -510,15 +1385,480 @@ impl LanguageServer for Backend {
         
         Ok(Some(DocumentSymbolResponse::Nested(symbols)))
     }
+
+    async fn on_type_formatting(&self, params: DocumentOnTypeFormattingParams) -> Result<Option<Vec<TextEdit>>> {
+        let uri = params.text_document_position.text_document.uri;
+        let position = params.text_document_position.position;
+
+        // Get the document data
+        let doc_data = match self.document_map.get(&uri.to_string()) {
+            Some(data) => data,
+            None => return Ok(None),
+        };
+        let rope = &doc_data.rope;
+
+        // Convert position to offset
+        let line_idx = position.line as usize;
+        if line_idx >= rope.len_lines() {
+            return Ok(None);
+        }
+        let offset = rope.line_to_char(line_idx) + position.character as usize;
+        if offset == 0 || offset > rope.len_chars() {
+            return Ok(None);
+        }
+
+        let unit = indent_unit(&params.options);
+        let edits = self.on_type_edits(rope, offset, &params.ch, &unit);
+
+        if edits.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(edits))
+    }
+
+    async fn code_action(&self, params: CodeActionParams) -> Result<Option<CodeActionResponse>> {
+        let uri = &params.text_document.uri;
+
+        // Fixes travel with the diagnostics the client sends back
+        let actions: Vec<CodeActionOrCommand> = params
+            .context
+            .diagnostics
+            .iter()
+            .flat_map(|diagnostic| {
+                let fixes: Vec<QuickFix> = diagnostic
+                    .data
+                    .clone()
+                    .and_then(|data| serde_json::from_value(data).ok())
+                    .unwrap_or_default();
+                fixes.into_iter().map(move |fix| {
+                    CodeActionOrCommand::CodeAction(CodeAction {
+                        title: fix.title,
+                        kind: Some(CodeActionKind::QUICKFIX),
+                        diagnostics: Some(vec![diagnostic.clone()]),
+                        edit: Some(WorkspaceEdit {
+                            changes: Some([(uri.clone(), fix.edits)].into_iter().collect()),
+                            ..Default::default()
+                        }),
+                        is_preferred: Some(true),
+                        ..Default::default()
+                    })
+                })
+            })
+            .collect();
+
+        if actions.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(actions))
+    }
+
+    async fn folding_range(&self, params: FoldingRangeParams) -> Result<Option<Vec<FoldingRange>>> {
+        let uri = params.text_document.uri;
+
+        // Get the document data
+        let doc_data = match self.document_map.get(&uri.to_string()) {
+            Some(data) => data,
+            None => return Ok(None),
+        };
+
+        // Scanned rather than parsed, so folding keeps working while editing
+        let blocks = BlockStructure::scan(&doc_data.rope.to_string());
+        Ok(Some(folding_ranges(&blocks, &doc_data.rope)))
+    }
+
+    async fn inlay_hint(&self, params: InlayHintParams) -> Result<Option<Vec<InlayHint>>> {
+        let uri = params.text_document.uri;
+
+        // Get the document data
+        let doc_data = match self.document_map.get(&uri.to_string()) {
+            Some(data) => data,
+            None => return Ok(None),
+        };
+
+        let semantic_info = match &doc_data.semantic_info {
+            Some(info) => info,
+            None => return Ok(None),
+        };
+
+        let range = params.range;
+        let hints = semantic_info
+            .inlay_hints
+            .iter()
+            .filter_map(|hint| {
+                let position = char_to_position(hint.offset, &doc_data.rope);
+                if position < range.start || position > range.end {
+                    return None;
+                }
+                let kind = match hint.kind {
+                    dgv_dgl::InlayHintKind::Type => InlayHintKind::TYPE,
+                    dgv_dgl::InlayHintKind::Default => InlayHintKind::PARAMETER,
+                };
+                Some(InlayHint {
+                    position,
+                    label: InlayHintLabel::String(hint.label.clone()),
+                    kind: Some(kind),
+                    text_edits: None,
+                    tooltip: None,
+                    padding_left: Some(kind == InlayHintKind::PARAMETER),
+                    padding_right: None,
+                    data: None,
+                })
+            })
+            .collect();
+
+        Ok(Some(hints))
+    }
+
+    async fn diagnostic(&self, params: DocumentDiagnosticParams) -> Result<DocumentDiagnosticReportResult> {
+        let uri = params.text_document.uri;
+        let diagnostics = match self.document_text(&uri) {
+            Some((text, _)) => self.validate_document(&uri, &text).await,
+            None => Vec::new(),
+        };
+        let report = self.document_report(&uri, diagnostics, params.previous_result_id.as_deref());
+        Ok(DocumentDiagnosticReportResult::Report(report))
+    }
+
+    async fn workspace_diagnostic(&self, params: WorkspaceDiagnosticParams) -> Result<WorkspaceDiagnosticReportResult> {
+        let previous: std::collections::HashMap<Url, String> = params
+            .previous_result_ids
+            .into_iter()
+            .map(|previous| (previous.uri, previous.value))
+            .collect();
+
+        // Open documents as edited, other indexed files as on disk
+        let mut uris: Vec<Url> = self
+            .document_map
+            .iter()
+            .filter_map(|entry| Url::parse(entry.key()).ok())
+            .collect();
+        uris.extend(
+            self.workspace_files
+                .iter()
+                .map(|entry| entry.key().clone())
+                .filter(|uri| !self.document_map.contains_key(uri.as_str())),
+        );
+
+        let mut items = Vec::new();
+        for uri in uris {
+            let Some((text, version)) = self.document_text(&uri) else {
+                continue;
+            };
+            let diagnostics = self.validate_document(&uri, &text).await;
+            let version = version.map(i64::from);
+            let item = match self.document_report(&uri, diagnostics, previous.get(&uri).map(String::as_str)) {
+                DocumentDiagnosticReport::Full(report) => {
+                    WorkspaceDocumentDiagnosticReport::Full(WorkspaceFullDocumentDiagnosticReport {
+                        uri,
+                        version,
+                        full_document_diagnostic_report: report.full_document_diagnostic_report,
+                    })
+                }
+                DocumentDiagnosticReport::Unchanged(report) => {
+                    WorkspaceDocumentDiagnosticReport::Unchanged(WorkspaceUnchangedDocumentDiagnosticReport {
+                        uri,
+                        version,
+                        unchanged_document_diagnostic_report: report.unchanged_document_diagnostic_report,
+                    })
+                }
+            };
+            items.push(item);
+        }
+
+        Ok(WorkspaceDiagnosticReportResult::Report(WorkspaceDiagnosticReport { items }))
+    }
+
+    async fn code_lens(&self, params: CodeLensParams) -> Result<Option<Vec<CodeLens>>> {
+        let uri = params.text_document.uri;
+
+        // Get the document data
+        let doc_data = match self.document_map.get(&uri.to_string()) {
+            Some(data) => data,
+            None => return Ok(None),
+        };
+
+        // Parse the document
+        let document = match doc_data.rope.to_string().parse::<kdl::KdlDocument>() {
+            Ok(document) => document,
+            Err(_) => return Ok(None),
+        };
+
+        let workflows = dgv_dgl::v1::workflows(&document);
+        let lens = |range: Range, title: &str, command: &str, arguments: Vec<serde_json::Value>| CodeLens {
+            range,
+            command: Some(Command::new(title.to_string(), command.to_string(), Some(arguments))),
+            data: None,
+        };
+
+        // Lenses sit above each definition; workflows can also be previewed
+        // and registered
+        let mut lenses = Vec::new();
+        for node in document.nodes().iter().filter(|node| node.name().value() == "definition") {
+            let span = node.span();
+            let start = char_to_position(span.offset(), &doc_data.rope);
+            let range = Range::new(start, start);
+            let uri_arg = serde_json::json!(uri);
+
+            lenses.push(lens(range, "Validate", VALIDATE_COMMAND, vec![uri_arg.clone()]));
+            if workflows.iter().any(|workflow| workflow.span == span) {
+                let graph_args = vec![uri_arg.clone(), serde_json::json!(start)];
+                lenses.push(lens(range, "Show graph", SHOW_GRAPH_COMMAND, graph_args));
+                lenses.push(lens(range, "Register with engine", REGISTER_COMMAND, vec![uri_arg]));
+            }
+        }
+
+        Ok(Some(lenses))
+    }
+
+    async fn execute_command(&self, params: ExecuteCommandParams) -> Result<Option<serde_json::Value>> {
+        let mut arguments = params.arguments.into_iter();
+        let Some(uri) = arguments.next().and_then(|arg| serde_json::from_value::<Url>(arg).ok()) else {
+            return Err(tower_lsp::jsonrpc::Error::invalid_params("expected a document URI"));
+        };
+
+        match params.command.as_str() {
+            VALIDATE_COMMAND => {
+                let Some((text, version)) = self.document_text(&uri) else {
+                    return Ok(None);
+                };
+                let diagnostics = self.validate_document(&uri, &text).await;
+                let message = match diagnostics.len() {
+                    0 => format!("{} is valid", uri),
+                    count => format!("{} has {} problem(s)", uri, count),
+                };
+                self.report_diagnostics(uri, diagnostics, version).await;
+                self.client.show_message(MessageType::INFO, message).await;
+                Ok(None)
+            }
+            SHOW_GRAPH_COMMAND => {
+                let Some(position) = arguments.next().and_then(|arg| serde_json::from_value(arg).ok()) else {
+                    return Err(tower_lsp::jsonrpc::Error::invalid_params("expected a position"));
+                };
+                let params = WorkflowGraphParams {
+                    text_document: TextDocumentIdentifier::new(uri),
+                    position,
+                };
+                let graph = self.workflow_graph(params).await?;
+                Ok(graph.and_then(|graph| serde_json::to_value(graph).ok()))
+            }
+            REGISTER_COMMAND => {
+                self.register_workflow(&uri).await;
+                Ok(None)
+            }
+            command => Err(tower_lsp::jsonrpc::Error::invalid_params(format!("unknown command {}", command))),
+        }
+    }
+
+    async fn document_link(&self, params: DocumentLinkParams) -> Result<Option<Vec<DocumentLink>>> {
+        let uri = params.text_document.uri;
+
+        // Get the document data
+        let doc_data = match self.document_map.get(&uri.to_string()) {
+            Some(data) => data,
+            None => return Ok(None),
+        };
+
+        // Parse the document
+        let text = doc_data.rope.to_string();
+        let doc = match text.parse::<kdl::KdlDocument>() {
+            Ok(doc) => doc,
+            Err(_) => return Ok(None),
+        };
+
+        let links = url_values(&doc, &text)
+            .into_iter()
+            .filter_map(|(span, url)| {
+                Some(DocumentLink {
+                    range: Range::new(
+                        char_to_position(span.offset(), &doc_data.rope),
+                        char_to_position(span.offset() + span.len(), &doc_data.rope),
+                    ),
+                    target: Some(Url::parse(url).ok()?),
+                    tooltip: None,
+                    data: None,
+                })
+            })
+            .collect();
+
+        Ok(Some(links))
+    }
+
+    async fn formatting(&self, params: DocumentFormattingParams) -> Result<Option<Vec<TextEdit>>> {
+        let uri = params.text_document.uri;
+
+        // Get the document data
+        let doc_data = match self.document_map.get(&uri.to_string()) {
+            Some(data) => data,
+            None => return Ok(None),
+        };
+        let rope = &doc_data.rope;
+        let text = rope.to_string();
+
+        // Documents that do not parse are left alone
+        let unit = indent_unit(&params.options);
+        let formatted = match format_document(&text, &self.schema_for(&uri).schema, &unit) {
+            Ok(formatted) => formatted,
+            Err(_) => return Ok(None),
+        };
+
+        if formatted == text {
+            return Ok(None);
+        }
+        let range = Range::new(Position::new(0, 0), char_to_position(rope.len_chars(), rope));
+        Ok(Some(vec![TextEdit::new(range, formatted)]))
+    }
+
+    async fn range_formatting(&self, params: DocumentRangeFormattingParams) -> Result<Option<Vec<TextEdit>>> {
+        let uri = params.text_document.uri;
+
+        // Convert the range to offsets
+        let (start, end) = match (
+            self.position_to_offset(&uri, params.range.start),
+            self.position_to_offset(&uri, params.range.end),
+        ) {
+            (Some(start), Some(end)) => (start, end),
+            _ => return Ok(None),
+        };
+
+        // Get the document data
+        let doc_data = match self.document_map.get(&uri.to_string()) {
+            Some(data) => data,
+            None => return Ok(None),
+        };
+        let rope = &doc_data.rope;
+        let text = rope.to_string();
+
+        // The top-level nodes in the range are formatted as a whole
+        let unit = indent_unit(&params.options);
+        let (replaced, formatted) = match format_range(&text, &self.schema_for(&uri).schema, &unit, start..end) {
+            Ok(Some(result)) => result,
+            Ok(None) | Err(_) => return Ok(None),
+        };
+
+        if rope.slice(replaced.clone()) == formatted.as_str() {
+            return Ok(None);
+        }
+        let range = Range::new(
+            char_to_position(replaced.start, rope),
+            char_to_position(replaced.end, rope),
+        );
+        Ok(Some(vec![TextEdit::new(range, formatted)]))
+    }
 }
 
 pub async fn start_server() {
     let stdin = tokio::io::stdin();
     let stdout = tokio::io::stdout();
 
-    let (service, socket) = LspService::new(|client| Backend::new(client));
+    let (service, socket) = LspService::build(|client| Backend::new(client))
+        .custom_method(WORKFLOW_GRAPH_METHOD, Backend::workflow_graph)
+        .finish();
     
     Server::new(stdin, stdout, socket)
         .serve(service)
         .await;
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pulling_backend() -> Backend {
+        let (service, _) = LspService::new(Backend::new);
+        let backend = service.inner().clone();
+        backend.pull_diagnostics.store(true, Ordering::Relaxed);
+        backend
+    }
+
+    async fn open(backend: &Backend, uri: &Url, text: &str, version: i32) {
+        backend
+            .did_open(DidOpenTextDocumentParams {
+                text_document: TextDocumentItem::new(uri.clone(), "dgl".to_string(), version, text.to_string()),
+            })
+            .await;
+    }
+
+    async fn pull(backend: &Backend, uri: &Url, previous_result_id: Option<String>) -> DocumentDiagnosticReport {
+        let params = DocumentDiagnosticParams {
+            text_document: TextDocumentIdentifier::new(uri.clone()),
+            identifier: None,
+            previous_result_id,
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        match backend.diagnostic(params).await.unwrap() {
+            DocumentDiagnosticReportResult::Report(report) => report,
+            DocumentDiagnosticReportResult::Partial(_) => panic!("unexpected partial report"),
+        }
+    }
+
+    fn full_result_id(report: DocumentDiagnosticReport) -> String {
+        match report {
+            DocumentDiagnosticReport::Full(report) => report.full_document_diagnostic_report.result_id.unwrap(),
+            DocumentDiagnosticReport::Unchanged(_) => panic!("expected a full report"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_document_diagnostic_result_ids() {
+        let backend = pulling_backend();
+        let uri = Url::parse("file:///workspace/a.dgl").unwrap();
+        open(&backend, &uri, "node {", 1).await;
+
+        let first = full_result_id(pull(&backend, &uri, None).await);
+        assert!(matches!(
+            pull(&backend, &uri, Some(first.clone())).await,
+            DocumentDiagnosticReport::Unchanged(_)
+        ));
+
+        open(&backend, &uri, "", 2).await;
+        let second = full_result_id(pull(&backend, &uri, Some(first.clone())).await);
+        assert_ne!(first, second);
+    }
+
+    #[tokio::test]
+    async fn test_workspace_diagnostic_result_ids() {
+        let backend = pulling_backend();
+        let uris = [
+            Url::parse("file:///workspace/a.dgl").unwrap(),
+            Url::parse("file:///workspace/b.dgl").unwrap(),
+        ];
+        for uri in &uris {
+            open(&backend, uri, "node {", 1).await;
+        }
+
+        let pull_workspace = |previous_result_ids: Vec<PreviousResultId>| {
+            let backend = backend.clone();
+            async move {
+                let params = WorkspaceDiagnosticParams {
+                    identifier: None,
+                    previous_result_ids,
+                    work_done_progress_params: Default::default(),
+                    partial_result_params: Default::default(),
+                };
+                match backend.workspace_diagnostic(params).await.unwrap() {
+                    WorkspaceDiagnosticReportResult::Report(report) => report.items,
+                    WorkspaceDiagnosticReportResult::Partial(_) => panic!("unexpected partial report"),
+                }
+            }
+        };
+
+        let previous: Vec<PreviousResultId> = pull_workspace(Vec::new())
+            .await
+            .into_iter()
+            .map(|item| match item {
+                WorkspaceDocumentDiagnosticReport::Full(report) => PreviousResultId {
+                    uri: report.uri,
+                    value: report.full_document_diagnostic_report.result_id.unwrap(),
+                },
+                WorkspaceDocumentDiagnosticReport::Unchanged(_) => panic!("expected a full report"),
+            })
+            .collect();
+        assert_eq!(previous.len(), uris.len());
+
+        let items = pull_workspace(previous).await;
+        assert_eq!(items.len(), uris.len());
+        assert!(items
+            .iter()
+            .all(|item| matches!(item, WorkspaceDocumentDiagnosticReport::Unchanged(_))));
+    }
+}
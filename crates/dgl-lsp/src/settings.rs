@@ -0,0 +1,185 @@
+//! Schema selection from the client's settings
+//!
+//! Documents are checked against the built-in DeGov schema (`v1`) unless the
+//! settings name a schema file, for all documents or for those matching a
+//! glob:
+//!
+//! ```json
+//! {
+//!   "dgl": {
+//!     "schema": "v1",
+//!     "schemas": [{ "pattern": "deployments/**/*.kdl", "schema": "schemas/deployments.kdl" }]
+//!   }
+//! }
+//! ```
+
+use dgv_dgl::v1::create_schema;
+use dgv_dgl::{load_schema, CompletionEngine, Schema};
+use globset::{Glob, GlobMatcher};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Name of the built-in schema
+const BUILTIN_SCHEMA: &str = "v1";
+
+/// Settings of the `dgl` section, also accepted as initialization options
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Settings {
+    /// Schema of the documents no mapping matches: `v1` or the path of a
+    /// schema file
+    pub schema: Option<String>,
+    /// Schemas of the documents matching a glob; the first match wins
+    pub schemas: Vec<SchemaMapping>,
+}
+
+/// Schema of the documents matching a glob
+#[derive(Debug, Clone, Deserialize)]
+pub struct SchemaMapping {
+    /// Glob matched against document paths relative to their workspace folder
+    pub pattern: String,
+    /// `v1` or the path of a schema file
+    pub schema: String,
+}
+
+impl Settings {
+    /// Read the settings sent by the client, with or without the enclosing
+    /// `dgl` section
+    pub fn from_value(value: serde_json::Value) -> serde_json::Result<Self> {
+        let value = match value {
+            serde_json::Value::Object(mut section) if section.contains_key("dgl") => {
+                section.remove("dgl").unwrap_or_default()
+            }
+            value => value,
+        };
+        if value.is_null() {
+            return Ok(Self::default());
+        }
+        serde_json::from_value(value)
+    }
+}
+
+/// A schema along with the completion engine built from it
+pub struct SchemaContext {
+    pub schema: Schema,
+    pub completion_engine: CompletionEngine,
+}
+
+impl SchemaContext {
+    fn new(schema: Schema) -> Self {
+        Self {
+            completion_engine: CompletionEngine::new(schema.clone()),
+            schema,
+        }
+    }
+}
+
+/// Schemas selected for the documents of the workspace
+pub struct SchemaSelection {
+    default: Arc<SchemaContext>,
+    mappings: Vec<(GlobMatcher, Arc<SchemaContext>)>,
+    roots: Vec<PathBuf>,
+}
+
+impl SchemaSelection {
+    /// The built-in schema for every document
+    pub fn builtin() -> Self {
+        Self {
+            default: Arc::new(SchemaContext::new(create_schema())),
+            mappings: Vec::new(),
+            roots: Vec::new(),
+        }
+    }
+
+    /// Load the schemas the settings name
+    ///
+    /// Relative schema paths are resolved against the first workspace
+    /// folder. Schemas that fail to load, and invalid globs, are replaced by
+    /// the built-in schema and reported in the returned messages.
+    pub fn load(settings: &Settings, roots: &[PathBuf]) -> (Self, Vec<String>) {
+        let builtin = Arc::new(SchemaContext::new(create_schema()));
+        let mut loader = SchemaLoader {
+            base: roots.first().cloned().unwrap_or_default(),
+            builtin: builtin.clone(),
+            loaded: HashMap::new(),
+            problems: Vec::new(),
+        };
+
+        let default = match &settings.schema {
+            Some(schema) => loader.load(schema),
+            None => builtin,
+        };
+        let mut mappings = Vec::new();
+        for mapping in &settings.schemas {
+            match Glob::new(&mapping.pattern) {
+                Ok(glob) => mappings.push((glob.compile_matcher(), loader.load(&mapping.schema))),
+                Err(e) => loader.problems.push(format!("Invalid schema pattern {}: {}", mapping.pattern, e)),
+            }
+        }
+
+        let selection = Self {
+            default,
+            mappings,
+            roots: roots.to_vec(),
+        };
+        (selection, loader.problems)
+    }
+
+    /// The schema applying to a document
+    pub fn schema_for(&self, path: Option<&Path>) -> Arc<SchemaContext> {
+        let Some(path) = path else {
+            return self.default.clone();
+        };
+        let relative = self
+            .roots
+            .iter()
+            .find_map(|root| path.strip_prefix(root).ok())
+            .unwrap_or(path);
+        self.mappings
+            .iter()
+            .find(|(matcher, _)| matcher.is_match(relative))
+            .map_or(&self.default, |(_, context)| context)
+            .clone()
+    }
+}
+
+/// Loads each schema file once
+struct SchemaLoader {
+    base: PathBuf,
+    builtin: Arc<SchemaContext>,
+    loaded: HashMap<String, Arc<SchemaContext>>,
+    problems: Vec<String>,
+}
+
+impl SchemaLoader {
+    fn load(&mut self, name: &str) -> Arc<SchemaContext> {
+        if name == BUILTIN_SCHEMA {
+            return self.builtin.clone();
+        }
+        if let Some(context) = self.loaded.get(name) {
+            return context.clone();
+        }
+
+        let path = self.base.join(name);
+        let schema = std::fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| {
+                load_schema(&text, path.display().to_string()).map_err(|e| {
+                    let first = e.diagnostics.first().map(|d| d.kind.message()).unwrap_or_default();
+                    format!("{}: {}", e, first)
+                })
+            });
+        let context = match schema {
+            Ok(schema) => Arc::new(SchemaContext::new(schema)),
+            Err(e) => {
+                self.problems
+                    .push(format!("Cannot load schema {}, using {}: {}", path.display(), BUILTIN_SCHEMA, e));
+                self.builtin.clone()
+            }
+        };
+        self.loaded.insert(name.to_string(), context.clone());
+        context
+    }
+}
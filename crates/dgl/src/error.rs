@@ -99,6 +99,29 @@ pub struct DglDiagnostic {
     
     /// Severity level
     pub severity: Severity,
+
+    /// Quick fixes resolving the diagnostic
+    pub fixes: Vec<Fix>,
+}
+
+/// A quick fix for a diagnostic
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fix {
+    /// Title shown to the user, e.g. "Replace with 'Workflow'"
+    pub title: String,
+
+    /// Spans to replace and their replacement text; empty spans insert
+    pub edits: Vec<(SourceSpan, String)>,
+}
+
+impl Fix {
+    /// Create a fix replacing a single span
+    pub fn replace(title: impl Into<String>, span: SourceSpan, text: impl Into<String>) -> Self {
+        Self {
+            title: title.into(),
+            edits: vec![(span, text.into())],
+        }
+    }
 }
 
 impl DglDiagnostic {
@@ -110,6 +133,7 @@ impl DglDiagnostic {
             span,
             related_spans: Vec::new(),
             severity: Severity::Error,
+            fixes: Vec::new(),
         }
     }
     
@@ -121,6 +145,7 @@ impl DglDiagnostic {
             span,
             related_spans: Vec::new(),
             severity: Severity::Warning,
+            fixes: Vec::new(),
         }
     }
     
@@ -129,6 +154,12 @@ impl DglDiagnostic {
         self.related_spans.push((span, label.into()));
         self
     }
+
+    /// Offer a quick fix
+    pub fn with_fix(mut self, fix: Fix) -> Self {
+        self.fixes.push(fix);
+        self
+    }
 }
 
 impl Display for DglDiagnostic {
@@ -282,6 +313,7 @@ pub fn from_kdl_error(err: kdl::KdlError, source_name: String) -> DglError {
             span: kdl_diag.span,
             related_spans: Vec::new(),
             severity: kdl_diag.severity,
+            fixes: Vec::new(),
         }
     }).collect();
     
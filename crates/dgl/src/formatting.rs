@@ -0,0 +1,304 @@
+//! Block structure for editor formatting
+//!
+//! Editors need to know how deeply a position is nested while the user is
+//! still typing, when the document usually does not parse. `BlockStructure`
+//! scans the KDL token stream (strings, raw strings and comments included),
+//! so braces inside string values or comments never affect indentation.
+//!
+//! Whole documents, which must parse, are pretty-printed by
+//! [`format_document`] and [`format_range`]: nodes are indented one unit per
+//! level, properties follow the order the schema declares them in and
+//! comments are kept.
+//!
+//! All offsets are character indices, matching the rope used by the LSP.
+
+use crate::schema::{NodeDef, Schema};
+use kdl::{FormatConfig, KdlDocument, KdlError, KdlNode};
+use std::ops::Range;
+
+/// A `{ ... }` children block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Block {
+    /// Character index of the opening brace
+    pub open: usize,
+    /// Character index of the matching closing brace, if any
+    pub close: Option<usize>,
+}
+
+/// Children blocks of a (possibly incomplete) KDL document
+#[derive(Debug, Clone, Default)]
+pub struct BlockStructure {
+    blocks: Vec<Block>,
+    multiline_strings: Vec<Range<usize>>,
+}
+
+impl BlockStructure {
+    /// Scan a document for children blocks
+    pub fn scan(text: &str) -> Self {
+        let chars: Vec<char> = text.chars().collect();
+        let mut blocks = Vec::new();
+        let mut multiline_strings = Vec::new();
+        let mut open_stack: Vec<usize> = Vec::new();
+        let mut i = 0;
+
+        while i < chars.len() {
+            match chars[i] {
+                '/' if chars.get(i + 1) == Some(&'/') => {
+                    while i < chars.len() && chars[i] != '\n' {
+                        i += 1;
+                    }
+                    continue;
+                }
+                '/' if chars.get(i + 1) == Some(&'*') => {
+                    i = skip_block_comment(&chars, i);
+                    continue;
+                }
+                '"' => {
+                    let end = skip_string(&chars, i);
+                    if chars[i..end].contains(&'\n') {
+                        multiline_strings.push(i..end);
+                    }
+                    i = end;
+                    continue;
+                }
+                '#' if starts_raw_string(&chars, i) => {
+                    let end = skip_raw_string(&chars, i);
+                    if chars[i..end].contains(&'\n') {
+                        multiline_strings.push(i..end);
+                    }
+                    i = end;
+                    continue;
+                }
+                '{' => {
+                    open_stack.push(blocks.len());
+                    blocks.push(Block { open: i, close: None });
+                }
+                '}' => {
+                    // Stray closing braces are left for the parser to report
+                    if let Some(idx) = open_stack.pop() {
+                        blocks[idx].close = Some(i);
+                    }
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+
+        Self { blocks, multiline_strings }
+    }
+
+    /// All blocks in order of their opening brace
+    pub fn blocks(&self) -> &[Block] {
+        &self.blocks
+    }
+
+    /// Strings spanning several lines, from their opening quote (or `#`) to
+    /// just past their closing one
+    pub fn multiline_strings(&self) -> &[Range<usize>] {
+        &self.multiline_strings
+    }
+
+    /// Nesting depth at a character index
+    ///
+    /// A position counts as inside a block when it lies after the opening
+    /// brace and at or before the closing brace, so the depth at a `}` is
+    /// that of the block's contents.
+    pub fn depth_at(&self, offset: usize) -> usize {
+        self.blocks
+            .iter()
+            .filter(|b| b.open < offset && b.close.is_none_or(|c| c >= offset))
+            .count()
+    }
+
+    /// The block opened by the brace at `offset`
+    pub fn block_opened_at(&self, offset: usize) -> Option<&Block> {
+        self.blocks.iter().find(|b| b.open == offset)
+    }
+
+    /// The block closed by the brace at `offset`
+    pub fn block_closed_at(&self, offset: usize) -> Option<&Block> {
+        self.blocks.iter().find(|b| b.close == Some(offset))
+    }
+}
+
+/// Pretty-print a document, indenting by `indent` per level
+///
+/// Arguments keep their order ahead of the properties. Properties, on the
+/// node line and as single-value child nodes, are put in schema order;
+/// unknown ones go last. Property child nodes only move among adjacent
+/// ones, so other children keep their place.
+pub fn format_document(text: &str, schema: &Schema, indent: &str) -> Result<String, KdlError> {
+    let mut doc: KdlDocument = text.parse()?;
+    order_document(&mut doc, schema);
+    doc.autoformat_config(&FormatConfig::builder().indent(indent).build());
+    Ok(doc.to_string())
+}
+
+/// Pretty-print the top-level nodes overlapping `range`
+///
+/// Returns the range of the whole lines holding those nodes with their
+/// formatted text, or `None` when the range touches no node.
+pub fn format_range(
+    text: &str,
+    schema: &Schema,
+    indent: &str,
+    range: Range<usize>,
+) -> Result<Option<(Range<usize>, String)>, KdlError> {
+    let doc: KdlDocument = text.parse()?;
+    let start = byte_index(text, range.start);
+    let end = byte_index(text, range.end);
+
+    let spans: Vec<Range<usize>> = doc
+        .nodes()
+        .iter()
+        .map(|node| node.span().offset()..node.span().offset() + node.span().len())
+        .filter(|span| span.start <= end && span.end >= start)
+        .collect();
+    let (Some(first), Some(last)) = (spans.first(), spans.last()) else {
+        return Ok(None);
+    };
+
+    // Node spans may include surrounding whitespace
+    let first_start = first.start + (text[first.start..].len() - text[first.start..].trim_start().len());
+    let last_end = text[..last.end].trim_end().len();
+    let line_start = text[..first_start].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = text[last_end..].find('\n').map_or(text.len(), |i| last_end + i);
+
+    let formatted = format_document(&text[line_start..line_end], schema, indent)?;
+    Ok(Some((
+        char_index(text, line_start)..char_index(text, line_end),
+        formatted.trim_end().to_string(),
+    )))
+}
+
+/// Put properties in schema order throughout a document
+fn order_document(doc: &mut KdlDocument, schema: &Schema) {
+    // As in the parser, a nameless root with properties holds the top-level
+    // nodes instead of describing each of them
+    let root_is_property_container = schema.root.name.as_ref().is_none_or(|n| n.is_empty())
+        && !schema.root.properties.is_empty();
+
+    if root_is_property_container {
+        order_nodes(doc.nodes_mut(), &schema.root);
+    } else {
+        for node in doc.nodes_mut() {
+            order_node(node, &schema.root);
+        }
+    }
+}
+
+/// Put the properties of a node and its children in schema order
+fn order_node(node: &mut KdlNode, def: &NodeDef) {
+    let def = def.apply_modifier(node);
+    node.entries_mut().sort_by_key(|entry| match entry.name() {
+        None => (false, 0),
+        Some(name) => (true, property_rank(&def, name.value())),
+    });
+
+    if let Some(children) = node.children_mut() {
+        order_nodes(children.nodes_mut(), &def);
+    }
+}
+
+/// Put runs of property nodes in schema order and descend into the others
+fn order_nodes(nodes: &mut [KdlNode], def: &NodeDef) {
+    for run in nodes.chunk_by_mut(|a, b| is_property_node(a, def) && is_property_node(b, def)) {
+        run.sort_by_key(|node| property_rank(def, node.name().value()));
+    }
+
+    for node in nodes.iter_mut().filter(|node| !is_property_node(node, def)) {
+        let child_def = def
+            .children
+            .iter()
+            .find(|child| child.name.as_deref() == Some(node.name().value()));
+        if let Some(child_def) = child_def {
+            order_node(node, child_def);
+        }
+    }
+}
+
+/// Whether a node is a property written as a child node (`key "value"`)
+fn is_property_node(node: &KdlNode, def: &NodeDef) -> bool {
+    def.properties.contains_key(node.name().value())
+        && node.entries().len() == 1
+        && node.entries()[0].name().is_none()
+}
+
+/// Position of a property in the schema, unknown ones last
+fn property_rank(def: &NodeDef, name: &str) -> usize {
+    def.properties.get_index_of(name).unwrap_or(usize::MAX)
+}
+
+/// Byte index of a character index, clamped to the end of the text
+fn byte_index(text: &str, char_idx: usize) -> usize {
+    text.char_indices().nth(char_idx).map_or(text.len(), |(i, _)| i)
+}
+
+/// Character index of a byte index
+fn char_index(text: &str, byte_idx: usize) -> usize {
+    text[..byte_idx].chars().count()
+}
+
+/// Skip a quoted string (single or triple quoted) starting at `start`
+fn skip_string(chars: &[char], start: usize) -> usize {
+    let multiline = chars.get(start + 1) == Some(&'"') && chars.get(start + 2) == Some(&'"');
+    let mut i = start + if multiline { 3 } else { 1 };
+
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 2,
+            '"' if !multiline => return i + 1,
+            '"' if chars.get(i + 1) == Some(&'"') && chars.get(i + 2) == Some(&'"') => return i + 3,
+            _ => i += 1,
+        }
+    }
+    chars.len()
+}
+
+/// Whether a raw string (`#"..."#`, `##"..."##`, ...) starts at `start`
+fn starts_raw_string(chars: &[char], start: usize) -> bool {
+    let mut i = start;
+    while chars.get(i) == Some(&'#') {
+        i += 1;
+    }
+    chars.get(i) == Some(&'"')
+}
+
+/// Skip a raw string starting at `start`
+fn skip_raw_string(chars: &[char], start: usize) -> usize {
+    let mut hashes = 0;
+    while chars.get(start + hashes) == Some(&'#') {
+        hashes += 1;
+    }
+
+    let mut i = start + hashes + 1;
+    while i < chars.len() {
+        if chars[i] == '"' && (1..=hashes).all(|n| chars.get(i + n) == Some(&'#')) {
+            return i + 1 + hashes;
+        }
+        i += 1;
+    }
+    chars.len()
+}
+
+/// Skip a (possibly nested) block comment starting at `start`
+fn skip_block_comment(chars: &[char], start: usize) -> usize {
+    let mut depth = 0;
+    let mut i = start;
+
+    while i < chars.len() {
+        if chars[i] == '/' && chars.get(i + 1) == Some(&'*') {
+            depth += 1;
+            i += 2;
+        } else if chars[i] == '*' && chars.get(i + 1) == Some(&'/') {
+            depth -= 1;
+            i += 2;
+            if depth == 0 {
+                return i;
+            }
+        } else {
+            i += 1;
+        }
+    }
+    chars.len()
+}
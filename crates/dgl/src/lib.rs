@@ -8,6 +8,11 @@
 //! - **IDE Support**: Semantic analysis, hover, completion, go-to-definition
 //! - **Graph Conversion**: Convert DGL to petgraph for analysis
 //! - **Error Reporting**: Rich diagnostics with miette integration
+//! - **Workspace Index**: Global symbols shared across the files of a project
+//! - **Schema Macros**: `dgl_schema!` / `dgl_node!` for concise schema definitions
+//! - **Schema Files**: Load schemas written in KDL with [`load_schema`]
+//! - **Editor Formatting**: Structure-aware block depth for on-type formatting
+//!   and schema-aware pretty-printing of whole documents
 //!
 //! # Example
 //!
@@ -34,18 +39,22 @@
 //! ```
 
 mod error;
+mod macros;
 mod span;
 mod parser;
 mod schema;
+mod schema_file;
 mod validation;
+pub mod formatting;
 pub mod semantic;
 pub mod syntax;
+pub mod workspace;
 
 // v1 schema implementation
 pub mod v1;
 
 // Re-export main types
-pub use error::{DglError, DglDiagnostic, DiagnosticKind, Result};
+pub use error::{DglError, DglDiagnostic, DiagnosticKind, Fix, Result};
 pub use span::Spanned;
 pub use schema::{
     Schema, NodeDef, ArgumentDef, PropertyDef, ValueType, KdlValue,
@@ -58,8 +67,11 @@ pub use validation::{
 };
 pub use semantic::{
     SemanticInfo, Symbol, SymbolKind, Reference, DocumentSymbol, 
-    HoverInfo, HoverContent, CompletionEngine,
+    HoverInfo, HoverContent, InlayHint, InlayHintKind, SignatureHelp, ParameterHelp,
+    CompletionEngine,
 };
+pub use workspace::WorkspaceIndex;
+pub use schema_file::load_schema;
 pub use parser::{Parser, ParsedDocument};
 
 /// Prelude module for convenient imports
@@ -71,4 +83,5 @@ pub mod prelude {
         Validator, AsyncValidator, ValidatorRegistry,
         Result, DglError,
     };
+    pub use crate::{dgl_node, dgl_schema};
 }
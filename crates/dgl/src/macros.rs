@@ -0,0 +1,215 @@
+//! Declarative Schema Macros
+//!
+//! `dgl_node!` and `dgl_schema!` build the same `NodeDef` and `Schema` values
+//! as the builder API, from a compact description. Unknown items, value types
+//! or modifiers are rejected at compile time.
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use dgv_dgl::{dgl_node, dgl_schema};
+//!
+//! let schema = dgl_schema!("my-dgl" {
+//!     root {
+//!         prop id: Custom("nsid", "nsid") [required, description("The ID of the document")];
+//!         child "definition" {
+//!             description = "Root definition node";
+//!             prop kind: Enum("kind") [required];
+//!             child_if (|_, node| NodeDef::get_node_property_value(node, "kind").as_deref() == Some("Workflow"))
+//!                 "workflow" {
+//!                     arg name: String;
+//!                     prop description: String [description("Human-readable description")];
+//!                 }
+//!         }
+//!     }
+//!     enum kind ["DataModel", "Workflow"] [description("The kind of the object")];
+//!     type_validator nsid = create_nsid_validator();
+//! });
+//! ```
+//!
+//! # Node items
+//!
+//! - `description = <expr>;`
+//! - `validator = <expr>;`
+//! - `allow_unknown_props;` / `allow_unknown_children;`
+//! - `arg <name>: <Type> [<modifiers>];`
+//! - `prop <name>: <Type> [<modifiers>];`
+//! - `prop_if (<predicate>) <name>: <Type> [<modifiers>];`
+//! - `child "<name>" { <items> }` / `child_def <expr>;`
+//! - `child_if (<predicate>) "<name>" { <items> }` / `child_if (<predicate>) <expr>;`
+//! - `completion <expr>;`
+//!
+//! Names are identifiers or string literals (for names such as `"node-name"`).
+//! Types are `String`, `Integer`, `Float`, `Boolean`, `Null`, `Any`,
+//! `Enum("name")`, `NodeRef("name")`, `Custom("name")` and
+//! `Custom("name", "validator")`. The modifier list is optional and accepts
+//! `required`, `optional`, `description(..)`, `default(..)`, `suggestions(..)`
+//! and, for enums, `value("value", "description")`.
+
+/// Build a `NodeDef` from a compact description
+#[macro_export]
+macro_rules! dgl_node {
+    ($name:literal { $($body:tt)* }) => {{
+        #[allow(unused_mut)]
+        let mut def = $crate::NodeDef::new($name);
+        $crate::__dgl_node_items!(def; $($body)*);
+        def
+    }};
+}
+
+/// Build a `Schema` from a compact description
+#[macro_export]
+macro_rules! dgl_schema {
+    ($name:literal { root { $($root:tt)* } $($rest:tt)* }) => {{
+        #[allow(unused_mut)]
+        let mut schema = $crate::Schema::new($name, $crate::dgl_node!("" { $($root)* }));
+        $crate::__dgl_schema_items!(schema; $($rest)*);
+        schema
+    }};
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __dgl_schema_items {
+    ($schema:ident;) => {};
+    ($schema:ident; enum $name:tt [$($value:literal),* $(,)?] $([$($mods:tt)*])? ; $($rest:tt)*) => {
+        $schema.define_enum(
+            $crate::__dgl_name!($name),
+            $crate::__dgl_modifiers!(
+                $crate::EnumDef::new(vec![$(::std::string::String::from($value)),*]);
+                $($($mods)*)?
+            ),
+        );
+        $crate::__dgl_schema_items!($schema; $($rest)*);
+    };
+    ($schema:ident; type_validator $name:tt = $def:expr; $($rest:tt)*) => {
+        $schema.register_type_validator($crate::__dgl_name!($name), $def);
+        $crate::__dgl_schema_items!($schema; $($rest)*);
+    };
+    ($schema:ident; validator $name:tt = $def:expr; $($rest:tt)*) => {
+        $schema.register_validator($crate::__dgl_name!($name), $def);
+        $crate::__dgl_schema_items!($schema; $($rest)*);
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __dgl_node_items {
+    ($def:ident;) => {};
+    ($def:ident; description = $desc:expr; $($rest:tt)*) => {
+        $def = $def.with_description($desc);
+        $crate::__dgl_node_items!($def; $($rest)*);
+    };
+    ($def:ident; validator = $validator:expr; $($rest:tt)*) => {
+        $def = $def.with_validator($validator);
+        $crate::__dgl_node_items!($def; $($rest)*);
+    };
+    ($def:ident; allow_unknown_props; $($rest:tt)*) => {
+        $def = $def.allow_unknown_props();
+        $crate::__dgl_node_items!($def; $($rest)*);
+    };
+    ($def:ident; allow_unknown_children; $($rest:tt)*) => {
+        $def = $def.allow_unknown_children();
+        $crate::__dgl_node_items!($def; $($rest)*);
+    };
+    ($def:ident; arg $name:tt : $ty:ident $(($($ty_args:tt)*))? $([$($mods:tt)*])? ; $($rest:tt)*) => {
+        $def = $def.with_argument($crate::__dgl_modifiers!(
+            $crate::ArgumentDef::new(
+                $crate::__dgl_name!($name),
+                $crate::__dgl_value_type!($ty $(($($ty_args)*))?),
+            );
+            $($($mods)*)?
+        ));
+        $crate::__dgl_node_items!($def; $($rest)*);
+    };
+    ($def:ident; prop $name:tt : $ty:ident $(($($ty_args:tt)*))? $([$($mods:tt)*])? ; $($rest:tt)*) => {
+        $def = $def.with_property(
+            $crate::__dgl_name!($name),
+            $crate::__dgl_modifiers!(
+                $crate::PropertyDef::new($crate::__dgl_value_type!($ty $(($($ty_args)*))?));
+                $($($mods)*)?
+            ),
+        );
+        $crate::__dgl_node_items!($def; $($rest)*);
+    };
+    ($def:ident; prop_if ($($pred:tt)*) $name:tt : $ty:ident $(($($ty_args:tt)*))? $([$($mods:tt)*])? ; $($rest:tt)*) => {
+        $def = $def.with_property_conditional(
+            $($pred)*,
+            $crate::__dgl_name!($name),
+            $crate::__dgl_modifiers!(
+                $crate::PropertyDef::new($crate::__dgl_value_type!($ty $(($($ty_args)*))?));
+                $($($mods)*)?
+            ),
+        );
+        $crate::__dgl_node_items!($def; $($rest)*);
+    };
+    ($def:ident; child $name:literal { $($body:tt)* } $($rest:tt)*) => {
+        $def = $def.with_child($crate::dgl_node!($name { $($body)* }));
+        $crate::__dgl_node_items!($def; $($rest)*);
+    };
+    ($def:ident; child_def $child:expr; $($rest:tt)*) => {
+        $def = $def.with_child($child);
+        $crate::__dgl_node_items!($def; $($rest)*);
+    };
+    ($def:ident; child_if ($($pred:tt)*) $name:literal { $($body:tt)* } $($rest:tt)*) => {
+        $def = $def.with_child_conditional($($pred)*, $crate::dgl_node!($name { $($body)* }));
+        $crate::__dgl_node_items!($def; $($rest)*);
+    };
+    ($def:ident; child_if ($($pred:tt)*) $child:expr; $($rest:tt)*) => {
+        $def = $def.with_child_conditional($($pred)*, $child);
+        $crate::__dgl_node_items!($def; $($rest)*);
+    };
+    ($def:ident; completion $item:expr; $($rest:tt)*) => {
+        $def = $def.with_completion($item);
+        $crate::__dgl_node_items!($def; $($rest)*);
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __dgl_value_type {
+    (String) => { $crate::ValueType::String };
+    (Integer) => { $crate::ValueType::Integer };
+    (Float) => { $crate::ValueType::Float };
+    (Boolean) => { $crate::ValueType::Boolean };
+    (Null) => { $crate::ValueType::Null };
+    (Any) => { $crate::ValueType::Any };
+    (Enum($name:expr)) => { $crate::ValueType::Enum(::std::string::String::from($name)) };
+    (NodeRef($name:expr)) => { $crate::ValueType::NodeRef(::std::string::String::from($name)) };
+    (Custom($name:expr)) => { $crate::ValueType::custom_unvalidated($name) };
+    (Custom($name:expr, $validator:expr)) => { $crate::ValueType::custom($name, $validator) };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __dgl_modifiers {
+    ($target:expr;) => { $target };
+    ($target:expr; required $(, $($rest:tt)*)?) => {
+        $crate::__dgl_modifiers!($target.required(); $($($rest)*)?)
+    };
+    ($target:expr; optional $(, $($rest:tt)*)?) => {
+        $crate::__dgl_modifiers!($target.optional(); $($($rest)*)?)
+    };
+    ($target:expr; description($desc:expr) $(, $($rest:tt)*)?) => {
+        $crate::__dgl_modifiers!($target.with_description($desc); $($($rest)*)?)
+    };
+    ($target:expr; default($value:expr) $(, $($rest:tt)*)?) => {
+        $crate::__dgl_modifiers!($target.with_default($value); $($($rest)*)?)
+    };
+    ($target:expr; suggestions($($value:expr),* $(,)?) $(, $($rest:tt)*)?) => {
+        $crate::__dgl_modifiers!(
+            $target.with_suggestions(vec![$(::std::string::String::from($value)),*]);
+            $($($rest)*)?
+        )
+    };
+    ($target:expr; value($value:expr, $desc:expr) $(, $($rest:tt)*)?) => {
+        $crate::__dgl_modifiers!($target.with_value_desc($value, $desc); $($($rest)*)?)
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __dgl_name {
+    ($name:ident) => { stringify!($name) };
+    ($name:literal) => { $name };
+}
@@ -2,10 +2,12 @@
 //!
 //! Provides the main parsing interface using the schema framework
 
-use crate::error::{DglDiagnostic, DglError, DiagnosticKind};
-use crate::schema::{Schema, NodeDef, ValueType};
+use crate::error::{DglDiagnostic, DglError, DiagnosticKind, Fix};
+use crate::schema::{Schema, NodeDef, PropertyDef, ValueType};
 use crate::semantic::SemanticInfo;
-use miette::NamedSource;
+use crate::span::head_end;
+use kdl::{KdlEntry, KdlNode, KdlValue};
+use miette::{NamedSource, SourceSpan};
 use std::sync::Arc;
 
 /// The main parser for schema-validated KDL documents
@@ -79,6 +81,33 @@ impl Parser {
         })
     }
 
+    /// Parse and validate the document, keeping the parsed document along
+    /// with all diagnostics whatever their severity
+    ///
+    /// Editors use this to keep working with documents that have errors.
+    /// The document is `None` when the source is not valid KDL.
+    pub fn check(&self) -> (Option<kdl::KdlDocument>, Vec<DglDiagnostic>) {
+        let doc = match self.source.parse::<kdl::KdlDocument>() {
+            Ok(doc) => doc,
+            Err(err) => {
+                let error = crate::error::from_kdl_error(err, self.source_name.clone());
+                return (None, error.diagnostics);
+            }
+        };
+
+        let diagnostics = match &self.schema {
+            Some(schema) => {
+                let named_source = Arc::new(NamedSource::new(
+                    self.source_name.clone(),
+                    self.source.clone(),
+                ));
+                self.validate_document(&doc, schema, &named_source)
+            }
+            None => Vec::new(),
+        };
+        (Some(doc), diagnostics)
+    }
+
     fn validate_document(
         &self,
         doc: &kdl::KdlDocument,
@@ -104,14 +133,12 @@ impl Parser {
                     if let Some(entry) = node.entries().first() {
                         if entry.name().is_none() && node.entries().len() == 1 {
                             validated_properties.insert(node_name.to_string());
-                            let value = entry.value();
                             diagnostics.extend(self.validate_property_value(
-                                value,
+                                entry,
                                 prop_def,
                                 node_name,
                                 schema,
                                 source,
-                                entry.span(),
                             ));
                             continue;
                         }
@@ -151,13 +178,21 @@ impl Parser {
             // Check for missing required properties
             for (prop_name, prop_def) in &schema.root.properties {
                 if prop_def.required && !validated_properties.contains(prop_name) {
-                    diagnostics.push(DglDiagnostic::error(
+                    let mut diagnostic = DglDiagnostic::error(
                         source.clone(),
                         DiagnosticKind::MissingProperty {
                             property: prop_name.clone(),
                         },
                         miette::SourceSpan::new(0.into(), 0),
-                    ));
+                    );
+                    if let Some(value) = fill_value(prop_def, schema) {
+                        diagnostic = diagnostic.with_fix(Fix::replace(
+                            format!("Add '{}'", prop_name),
+                            SourceSpan::new(0.into(), 0),
+                            format!("{} {}\n", prop_name, value),
+                        ));
+                    }
+                    diagnostics.push(diagnostic);
                 }
             }
         } else {
@@ -300,13 +335,17 @@ impl Parser {
             if prop_def.required {
                 let value = self.get_property_value(node, prop_name);
                 if value.is_none() {
-                    diagnostics.push(DglDiagnostic::error(
+                    let mut diagnostic = DglDiagnostic::error(
                         source.clone(),
                         DiagnosticKind::MissingProperty {
                             property: prop_name.clone(),
                         },
                         node.span(),
-                    ));
+                    );
+                    if let Some(value) = fill_value(prop_def, schema) {
+                        diagnostic = diagnostic.with_fix(insert_property_fix(node, prop_name, &value));
+                    }
+                    diagnostics.push(diagnostic);
                 }
             }
         }
@@ -320,26 +359,26 @@ impl Parser {
                 
                 // Check if property is defined in schema
                 if let Some(prop_def) = node_def.properties.get(prop_name_str) {
-                    let value = entry.value();
-                    
                     diagnostics.extend(self.validate_property_value(
-                        value,
+                        entry,
                         prop_def,
                         prop_name_str,
                         schema,
                         source,
-                        entry_span,
                     ));
                 } else if !node_def.allow_unknown_properties {
                     // Unknown property in strict mode
-                    diagnostics.push(DglDiagnostic::error(
-                        source.clone(),
-                        DiagnosticKind::UnknownProperty {
-                            property: prop_name_str.to_string(),
-                            suggestion: None,
-                        },
-                        entry_span,
-                    ));
+                    diagnostics.push(
+                        DglDiagnostic::error(
+                            source.clone(),
+                            DiagnosticKind::UnknownProperty {
+                                property: prop_name_str.to_string(),
+                                suggestion: None,
+                            },
+                            entry_span,
+                        )
+                        .with_fix(remove_entry_fix(source.inner(), entry, prop_name_str)),
+                    );
                 }
             }
         }
@@ -361,16 +400,12 @@ impl Parser {
                     if let Some(entry) = child.entries().first() {
                         // Only treat it as property if it's an argument (not named)
                         if entry.name().is_none() {
-                            let value = entry.value();
-                            let entry_span = entry.span();
-                            
                             diagnostics.extend(self.validate_property_value(
-                                value,
+                                entry,
                                 prop_def,
                                 child_name,
                                 schema,
                                 source,
-                                entry_span,
                             ));
                         }
                     }
@@ -384,14 +419,15 @@ impl Parser {
     /// Validate a single property value (extracted to avoid duplication)
     fn validate_property_value(
         &self,
-        value: &kdl::KdlValue,
-        prop_def: &crate::PropertyDef,
+        entry: &KdlEntry,
+        prop_def: &PropertyDef,
         _prop_name: &str,
         schema: &Schema,
         source: &Arc<NamedSource<String>>,
-        span: miette::SourceSpan,
     ) -> Vec<DglDiagnostic> {
         let mut diagnostics = Vec::new();
+        let value = entry.value();
+        let span = entry.span();
 
         // Type validation
         if !prop_def.ty.matches(value) {
@@ -410,7 +446,7 @@ impl Parser {
             if let Some(enum_def) = schema.get_enum(enum_name) {
                 if let Some(string_value) = value.as_string() {
                     if !enum_def.is_valid(string_value) {
-                        diagnostics.push(DglDiagnostic::error(
+                        let mut diagnostic = DglDiagnostic::error(
                             source.clone(),
                             DiagnosticKind::InvalidValue {
                                 message: format!(
@@ -423,7 +459,11 @@ impl Parser {
                                 )),
                             },
                             span,
-                        ));
+                        );
+                        if let Some(closest) = closest_value(string_value, &enum_def.values) {
+                            diagnostic = diagnostic.with_fix(replace_value_fix(source.inner(), entry, closest));
+                        }
+                        diagnostics.push(diagnostic);
                     }
                 }
             }
@@ -545,6 +585,82 @@ impl Parser {
     }
 }
 
+/// Value a fix inserts for a missing property: its default, or else a
+/// placeholder of its type
+fn fill_value(prop_def: &PropertyDef, schema: &Schema) -> Option<KdlValue> {
+    if let Some(default) = &prop_def.default {
+        return Some(default.clone().into());
+    }
+    match &prop_def.ty {
+        ValueType::String => Some(KdlValue::String(String::new())),
+        ValueType::Integer => Some(KdlValue::Integer(0)),
+        ValueType::Float => Some(KdlValue::Float(0.0)),
+        ValueType::Boolean => Some(KdlValue::Bool(false)),
+        ValueType::Enum(name) => schema
+            .get_enum(name)?
+            .values
+            .first()
+            .map(|value| KdlValue::String(value.clone())),
+        _ => None,
+    }
+}
+
+/// Fix adding a property after the last entry of a node
+fn insert_property_fix(node: &KdlNode, name: &str, value: &KdlValue) -> Fix {
+    Fix::replace(
+        format!("Add '{}'", name),
+        SourceSpan::new(head_end(node).into(), 0),
+        format!(" {}={}", name, value),
+    )
+}
+
+/// Fix removing a property along with the whitespace before it
+fn remove_entry_fix(text: &str, entry: &KdlEntry, name: &str) -> Fix {
+    let end = entry.span().offset() + entry.span().len();
+    let start = text[..entry.span().offset()].trim_end_matches([' ', '\t']).len();
+    Fix::replace(format!("Remove '{}'", name), SourceSpan::new(start.into(), end - start), "")
+}
+
+/// Fix replacing the value of an entry, keeping the property name as written
+fn replace_value_fix(text: &str, entry: &KdlEntry, value: &str) -> Fix {
+    let span = entry.span();
+    let replacement = match entry.name() {
+        Some(name) => {
+            let name_span = name.span();
+            let name_text = &text[name_span.offset()..name_span.offset() + name_span.len()];
+            format!("{}={}", name_text, KdlValue::String(value.to_string()))
+        }
+        None => KdlValue::String(value.to_string()).to_string(),
+    };
+    Fix::replace(format!("Replace with '{}'", value), span, replacement)
+}
+
+/// The candidate closest to `value` by edit distance, ignoring case
+fn closest_value<'a>(value: &str, candidates: &'a [String]) -> Option<&'a str> {
+    let value = value.to_lowercase();
+    candidates
+        .iter()
+        .min_by_key(|candidate| edit_distance(&value, &candidate.to_lowercase()))
+        .map(String::as_str)
+}
+
+/// Levenshtein distance between two strings
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
 /// Get a human-readable name for a KDL value type
 #[allow(dead_code)]
 fn value_type_name(value: &kdl::KdlValue) -> String {
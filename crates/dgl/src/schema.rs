@@ -3,6 +3,7 @@
 //! This module provides a framework for defining KDL-based language schemas.
 //! It's completely generic and can be used to build any KDL-based DGL.
 
+use indexmap::IndexMap;
 use std::collections::HashMap;
 use std::sync::Arc;
 use miette::SourceSpan;
@@ -82,8 +83,8 @@ pub struct NodeDef {
     /// Expected arguments (positional)
     pub arguments: Vec<ArgumentDef>,
     
-    /// Expected properties (key-value)
-    pub properties: HashMap<String, PropertyDef>,
+    /// Expected properties (key-value), in the order formatting puts them
+    pub properties: IndexMap<String, PropertyDef>,
     
     /// Expected child nodes
     pub children: Vec<NodeDef>,
@@ -130,7 +131,7 @@ impl NodeDef {
             name: Some(name.into()),
             description: None,
             arguments: Vec::new(),
-            properties: HashMap::new(),
+            properties: IndexMap::new(),
             children: Vec::new(),
             allow_unknown_properties: false,
             allow_unknown_children: false,
@@ -582,6 +583,17 @@ impl ValueType {
         }
     }
     
+    /// Whether values of this type name another node or document
+    ///
+    /// Besides node references, NSIDs (the custom `nsid` type) name documents.
+    pub fn is_reference(&self) -> bool {
+        match self {
+            ValueType::NodeRef(_) => true,
+            ValueType::Custom { name, .. } => name == "nsid",
+            _ => false,
+        }
+    }
+
     /// Get a human-readable name for this type
     pub fn name(&self) -> String {
         match self {
@@ -621,6 +633,18 @@ impl TryFrom<kdl::KdlValue> for KdlValue {
     }
 }
 
+impl From<KdlValue> for kdl::KdlValue {
+    fn from(value: KdlValue) -> Self {
+        match value {
+            KdlValue::String(s) => kdl::KdlValue::String(s),
+            KdlValue::Integer(i) => kdl::KdlValue::Integer(i),
+            KdlValue::Float(f) => kdl::KdlValue::Float(f),
+            KdlValue::Boolean(b) => kdl::KdlValue::Bool(b),
+            KdlValue::Null => kdl::KdlValue::Null,
+        }
+    }
+}
+
 /// Definition of an enum type
 #[derive(Debug, Clone)]
 pub struct EnumDef {
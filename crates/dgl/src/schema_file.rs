@@ -0,0 +1,230 @@
+//! Schemas written as KDL files
+//!
+//! Lets projects describe their own DGL dialect without writing Rust. The
+//! file declares enums and the node tree; value types use the names shown
+//! in hovers (`string`, `enum<environment>`, `ref<model>`, ...), any other
+//! name is a custom type checked by the type validator of that name.
+//!
+//! ```kdl
+//! schema "deployments"
+//!
+//! enum "environment" "development" "production" description="Deployment stage"
+//!
+//! root {
+//!     property "id" type="nsid" required=#true description="Document id"
+//!     node "service" description="A deployed service" {
+//!         argument "name" type="string"
+//!         property "environment" type="enum<environment>" default="development"
+//!         node "labels" unknown-properties=#true
+//!     }
+//! }
+//! ```
+//!
+//! `root` takes the root node name as optional argument; without one,
+//! top-level nodes named like root properties are read as properties.
+
+use crate::error::{from_kdl_error, DglDiagnostic, DglError, DiagnosticKind, Result};
+use crate::schema::{ArgumentDef, EnumDef, KdlValue, NodeDef, PropertyDef, Schema, ValueType};
+use crate::validation::create_nsid_validator;
+use kdl::{KdlDocument, KdlNode};
+use miette::SourceSpan;
+
+/// Load a schema from the text of a schema file
+pub fn load_schema(text: &str, source_name: impl Into<String>) -> Result<Schema> {
+    let source_name = source_name.into();
+    let doc: KdlDocument = text.parse().map_err(|e| from_kdl_error(e, source_name.clone()))?;
+
+    let mut loader = Loader {
+        errors: DglError::new(text.to_string(), source_name),
+    };
+    let mut name = String::new();
+    let mut enums = Vec::new();
+    let mut root = None;
+    for node in doc.nodes() {
+        match node.name().value() {
+            "schema" => name = loader.name(node).unwrap_or_default(),
+            "enum" => enums.extend(loader.enum_def(node)),
+            "root" if root.is_some() => loader.error(
+                DiagnosticKind::Duplicate {
+                    item_type: "node".to_string(),
+                    name: "root".to_string(),
+                },
+                node.name().span(),
+            ),
+            "root" => {
+                let root_name = node
+                    .entries()
+                    .iter()
+                    .find(|e| e.name().is_none())
+                    .and_then(|e| e.value().as_string())
+                    .unwrap_or_default();
+                root = Some(loader.node_def(node, root_name));
+            }
+            other => loader.unknown_node(node, other, "schema, enum or root"),
+        }
+    }
+
+    let Some(root) = root else {
+        loader.error(
+            DiagnosticKind::MissingNode {
+                node_name: "root".to_string(),
+            },
+            SourceSpan::from((0, 0)),
+        );
+        return Err(loader.errors);
+    };
+    if loader.errors.has_errors() {
+        return Err(loader.errors);
+    }
+
+    let mut schema = Schema::new(name, root);
+    for (name, def) in enums {
+        schema.define_enum(name, def);
+    }
+    schema.register_type_validator("nsid", create_nsid_validator());
+    Ok(schema)
+}
+
+/// Collects the problems found while loading
+struct Loader {
+    errors: DglError,
+}
+
+impl Loader {
+    fn node_def(&mut self, node: &KdlNode, name: &str) -> NodeDef {
+        let mut def = NodeDef::new(name);
+        def.description = string_property(node, "description");
+        def.allow_unknown_properties = flag(node, "unknown-properties");
+        def.allow_unknown_children = flag(node, "unknown-children");
+
+        for child in node.children().map(|c| c.nodes()).unwrap_or_default() {
+            match child.name().value() {
+                "argument" => {
+                    if let Some(name) = self.name(child) {
+                        let mut argument = ArgumentDef::new(name, self.value_type(child));
+                        argument.default = default_value(child);
+                        // Arguments are required unless they have a default
+                        argument.required = child
+                            .get("required")
+                            .and_then(|v| v.as_bool())
+                            .unwrap_or(argument.default.is_none());
+                        argument.description = string_property(child, "description");
+                        def.arguments.push(argument);
+                    }
+                }
+                "property" => {
+                    if let Some(name) = self.name(child) {
+                        let mut property = PropertyDef::new(self.value_type(child));
+                        property.required = flag(child, "required");
+                        property.default = default_value(child);
+                        property.description = string_property(child, "description");
+                        def.properties.insert(name, property);
+                    }
+                }
+                "node" => {
+                    if let Some(name) = self.name(child) {
+                        let child_def = self.node_def(child, &name);
+                        def.children.push(child_def);
+                    }
+                }
+                other => self.unknown_node(child, other, "argument, property or node"),
+            }
+        }
+
+        def
+    }
+
+    fn enum_def(&mut self, node: &KdlNode) -> Option<(String, EnumDef)> {
+        let name = self.name(node)?;
+        let values = node
+            .entries()
+            .iter()
+            .filter(|e| e.name().is_none())
+            .skip(1)
+            .filter_map(|e| e.value().as_string().map(str::to_string))
+            .collect();
+        let mut def = EnumDef::new(values);
+        def.description = string_property(node, "description");
+        Some((name, def))
+    }
+
+    /// The name given as first argument
+    fn name(&mut self, node: &KdlNode) -> Option<String> {
+        let name = node
+            .entries()
+            .iter()
+            .find(|e| e.name().is_none())
+            .and_then(|e| e.value().as_string());
+        if name.is_none() {
+            self.error(
+                DiagnosticKind::MissingProperty {
+                    property: "name".to_string(),
+                },
+                node.span(),
+            );
+        }
+        name.map(str::to_string)
+    }
+
+    /// The type given by the `type` property, `any` when left out
+    fn value_type(&mut self, node: &KdlNode) -> ValueType {
+        let Some(entry) = node.entry("type") else {
+            return ValueType::Any;
+        };
+        let Some(name) = entry.value().as_string() else {
+            self.error(
+                DiagnosticKind::TypeMismatch {
+                    expected: "string".to_string(),
+                    got: entry.value().to_string(),
+                },
+                entry.span(),
+            );
+            return ValueType::Any;
+        };
+
+        match name {
+            "string" => ValueType::String,
+            "integer" => ValueType::Integer,
+            "float" => ValueType::Float,
+            "boolean" => ValueType::Boolean,
+            "null" => ValueType::Null,
+            "any" => ValueType::Any,
+            _ => {
+                if let Some(enum_name) = name.strip_prefix("enum<").and_then(|n| n.strip_suffix('>')) {
+                    ValueType::Enum(enum_name.to_string())
+                } else if let Some(node_name) = name.strip_prefix("ref<").and_then(|n| n.strip_suffix('>')) {
+                    ValueType::NodeRef(node_name.to_string())
+                } else {
+                    ValueType::custom(name, name)
+                }
+            }
+        }
+    }
+
+    fn unknown_node(&mut self, node: &KdlNode, name: &str, expected: &str) {
+        self.error(
+            DiagnosticKind::UnknownNode {
+                node_name: name.to_string(),
+                suggestion: Some(format!("Expected {}", expected)),
+            },
+            node.name().span(),
+        );
+    }
+
+    fn error(&mut self, kind: DiagnosticKind, span: SourceSpan) {
+        let diagnostic = DglDiagnostic::error(self.errors.source.clone(), kind, span);
+        self.errors.add_diagnostic(diagnostic);
+    }
+}
+
+fn string_property(node: &KdlNode, name: &str) -> Option<String> {
+    node.get(name).and_then(|v| v.as_string()).map(str::to_string)
+}
+
+fn flag(node: &KdlNode, name: &str) -> bool {
+    node.get(name).and_then(|v| v.as_bool()).unwrap_or(false)
+}
+
+fn default_value(node: &KdlNode) -> Option<KdlValue> {
+    node.get("default").cloned().and_then(|v| KdlValue::try_from(v).ok())
+}
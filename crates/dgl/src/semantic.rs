@@ -3,8 +3,10 @@
 //! Provides hover information, go-to-definition, find references, etc.
 
 use crate::schema::{CompletionItem, CompletionKind, NodeDef, Schema};
+use crate::span::head_end;
 use miette::SourceSpan;
 use std::collections::HashMap;
+use std::ops::Range;
 
 /// Semantic information about a document
 #[derive(Debug, Clone)]
@@ -20,6 +22,9 @@ pub struct SemanticInfo {
     
     /// Hover information at various positions
     pub hover_info: Vec<HoverInfo>,
+    
+    /// Inline hints for argument types and omitted defaults
+    pub inlay_hints: Vec<InlayHint>,
 }
 
 impl SemanticInfo {
@@ -191,6 +196,54 @@ pub enum HoverContent {
     },
 }
 
+/// An inline annotation shown after a position in the document
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InlayHint {
+    /// Offset the hint is shown at
+    pub offset: usize,
+    
+    /// Text of the hint
+    pub label: String,
+    
+    /// What the hint describes
+    pub kind: InlayHintKind,
+}
+
+/// Kind of inlay hint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InlayHintKind {
+    /// Expected type of an argument
+    Type,
+    /// Default value of an omitted property
+    Default,
+}
+
+/// Positional arguments of the node whose entries are being typed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignatureHelp {
+    /// Node name followed by its arguments, optional ones in brackets
+    pub label: String,
+    
+    /// Description of the node
+    pub documentation: Option<String>,
+    
+    /// Arguments in order
+    pub parameters: Vec<ParameterHelp>,
+    
+    /// Index of the argument at the cursor, if the node takes that many
+    pub active_parameter: Option<usize>,
+}
+
+/// A positional argument in a signature
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParameterHelp {
+    /// Range of the argument within the signature label
+    pub label: Range<usize>,
+    
+    /// Description of the argument
+    pub documentation: Option<String>,
+}
+
 /// Semantic analyzer
 struct SemanticAnalyzer<'a> {
     schema: &'a Schema,
@@ -199,6 +252,7 @@ struct SemanticAnalyzer<'a> {
     references: Vec<Reference>,
     document_symbols: Vec<DocumentSymbol>,
     hover_info: Vec<HoverInfo>,
+    inlay_hints: Vec<InlayHint>,
 }
 
 impl<'a> SemanticAnalyzer<'a> {
@@ -210,6 +264,7 @@ impl<'a> SemanticAnalyzer<'a> {
             references: Vec::new(),
             document_symbols: Vec::new(),
             hover_info: Vec::new(),
+            inlay_hints: Vec::new(),
         }
     }
     
@@ -238,6 +293,7 @@ impl<'a> SemanticAnalyzer<'a> {
                 if entry.name().is_none() && node.entries().len() == 1 {
                     // This is a root-level property
                     self.add_property_hover(node, prop_def);
+                    self.add_reference(entry, prop_def);
                     self.add_document_symbol_for_property(node, prop_def);
                     return;
                 }
@@ -286,15 +342,24 @@ impl<'a> SemanticAnalyzer<'a> {
             children: Vec::new(),
         };
         
-        // Analyze properties
+        // Analyze arguments and properties
+        let mut arguments = effective_node_def.arguments.iter();
         for entry in node.entries() {
             if let Some(prop_name) = entry.name() {
                 let prop_name_str = prop_name.value();
                 if let Some(prop_def) = effective_node_def.properties.get(prop_name_str) {
                     self.add_property_hover_from_entry(entry, prop_def);
+                    self.add_reference(entry, prop_def);
                 }
+            } else if let Some(arg_def) = arguments.next() {
+                self.inlay_hints.push(InlayHint {
+                    offset: entry.span().offset() + entry.span().len(),
+                    label: format!(": {}", arg_def.ty.name()),
+                    kind: InlayHintKind::Type,
+                });
             }
         }
+        self.add_default_hints(node, &effective_node_def);
         
         // Analyze children
         if let Some(children) = node.children() {
@@ -306,6 +371,7 @@ impl<'a> SemanticAnalyzer<'a> {
                     if let Some(entry) = child.entries().first() {
                         if entry.name().is_none() && child.entries().len() == 1 {
                             self.add_property_hover(child, prop_def);
+                            self.add_reference(entry, prop_def);
                             continue;
                         }
                     }
@@ -354,6 +420,40 @@ impl<'a> SemanticAnalyzer<'a> {
         });
     }
     
+    /// Record a value naming another node or document as a reference
+    fn add_reference(&mut self, entry: &kdl::KdlEntry, prop_def: &crate::PropertyDef) {
+        if !prop_def.ty.is_reference() {
+            return;
+        }
+        if let Some(target) = entry.value().as_string() {
+            self.references.push(Reference {
+                target: target.to_string(),
+                span: entry.span(),
+            });
+        }
+    }
+
+    /// Show the defaults of optional properties the node leaves out
+    fn add_default_hints(&mut self, node: &kdl::KdlNode, node_def: &NodeDef) {
+        let offset = head_end(node);
+        for (name, prop_def) in &node_def.properties {
+            let Some(default) = prop_def.default.as_ref().filter(|_| !prop_def.required) else {
+                continue;
+            };
+            let given = node.entry(name.as_str()).is_some()
+                || node
+                    .children()
+                    .is_some_and(|children| children.get(name).is_some());
+            if !given {
+                self.inlay_hints.push(InlayHint {
+                    offset,
+                    label: format!("{}={}", name, kdl::KdlValue::from(default.clone())),
+                    kind: InlayHintKind::Default,
+                });
+            }
+        }
+    }
+    
     fn add_document_symbol_for_property(&mut self, node: &kdl::KdlNode, _prop_def: &crate::PropertyDef) {
         self.document_symbols.push(DocumentSymbol {
             name: node.name().value().to_string(),
@@ -371,6 +471,7 @@ impl<'a> SemanticAnalyzer<'a> {
             references: self.references,
             document_symbols: self.document_symbols,
             hover_info: self.hover_info,
+            inlay_hints: self.inlay_hints,
         }
     }
 }
@@ -413,6 +514,51 @@ impl CompletionEngine {
         }
     }
     
+    /// Get the signature of the node whose entries contain the offset
+    ///
+    /// The offset must lie after the node name, on the node line before its
+    /// children block.
+    pub fn signature_help(
+        &self,
+        doc: &kdl::KdlDocument,
+        offset: usize,
+        source: &str,
+    ) -> Option<SignatureHelp> {
+        let root_is_property_container = self.schema.root.name.as_ref().map_or(true, |n| n.is_empty())
+            && !self.schema.root.properties.is_empty();
+        
+        let mut nodes = doc.nodes();
+        let mut parent: Option<NodeDef> = None;
+        loop {
+            let node = nodes.iter().find(|node| {
+                let span = node.span();
+                offset > span.offset() && offset <= span.offset() + span.len()
+            })?;
+            let node_name = node.name().value();
+            let node_def = match &parent {
+                Some(parent) => parent.children.iter().find(|def| def.name.as_deref() == Some(node_name))?,
+                None if root_is_property_container => {
+                    self.schema.root.children.iter().find(|def| def.name.as_deref() == Some(node_name))?
+                }
+                None => &self.schema.root,
+            };
+            let effective_node_def = node_def.apply_modifier(node);
+            
+            let name_end = node.name().span().offset() + node.name().span().len();
+            let entries_end = head_end(node);
+            let line_end = entries_end
+                + source
+                    .get(entries_end..)
+                    .map_or(0, |rest| rest.len() - rest.trim_start_matches([' ', '\t']).len());
+            if offset > name_end && offset <= line_end {
+                return Some(signature(node, &effective_node_def, offset));
+            }
+            
+            nodes = node.children()?.nodes();
+            parent = Some(effective_node_def);
+        }
+    }
+    
     fn get_root_completions(&self) -> Vec<CompletionItem> {
         let mut completions = Vec::new();
         
@@ -661,6 +807,38 @@ impl CompletionEngine {
     }
 }
 
+/// Signature of a node's arguments, marking the one at the offset
+fn signature(node: &kdl::KdlNode, node_def: &NodeDef, offset: usize) -> SignatureHelp {
+    let mut label = node.name().value().to_string();
+    let mut parameters = Vec::new();
+    for arg_def in &node_def.arguments {
+        label.push(' ');
+        let argument = format!("{}: {}", arg_def.name, arg_def.ty.name());
+        let argument = if arg_def.required { argument } else { format!("[{}]", argument) };
+        parameters.push(ParameterHelp {
+            label: label.len()..label.len() + argument.len(),
+            documentation: arg_def.description.clone(),
+        });
+        label.push_str(&argument);
+    }
+    
+    // Arguments ending before the offset are complete; one ending at it is
+    // still being typed
+    let active = node
+        .entries()
+        .iter()
+        .filter(|entry| entry.name().is_none())
+        .filter(|entry| entry.span().offset() + entry.span().len() < offset)
+        .count();
+    
+    SignatureHelp {
+        label,
+        documentation: node_def.description.clone(),
+        active_parameter: (active < parameters.len()).then_some(active),
+        parameters,
+    }
+}
+
 /// Context for completions
 #[derive(Debug, Clone)]
 #[allow(dead_code)]
@@ -77,3 +77,12 @@ impl<T: std::fmt::Display> std::fmt::Display for Spanned<T> {
         write!(f, "{}", self.value)
     }
 }
+
+/// Offset just past a node's name and its last entry, before any children
+pub(crate) fn head_end(node: &kdl::KdlNode) -> usize {
+    let name_span = node.name().span();
+    node.entries()
+        .iter()
+        .map(|entry| entry.span().offset() + entry.span().len())
+        .fold(name_span.offset() + name_span.len(), usize::max)
+}
@@ -9,8 +9,13 @@ use crate::validation::create_nsid_validator;
 use crate::prelude::*;
 
 mod model;
+mod symbols;
 mod workflow;
 
+pub use model::{searchable_fields, SearchableField, SearchableType};
+pub use symbols::global_symbols;
+pub use workflow::{workflow_at, workflows, WorkflowGraph, WorkflowState, WorkflowTask, WorkflowTransition};
+
 /// Create the complete DeGov DGL v1 schema
 pub fn create_schema() -> Schema {
     let kind_enum = EnumDef::new(vec!["DataModel".to_string(), "Workflow".to_string()])
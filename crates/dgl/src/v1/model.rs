@@ -13,6 +13,11 @@ fn create_string_type_node_def() -> NodeDef {
         .with_argument(ArgumentDef::new("id", ValueType::String))
         .with_property("name", PropertyDef::new(ValueType::String))
         .with_property("description", PropertyDef::new(ValueType::String))
+        .with_property(
+            "searchable",
+            PropertyDef::new(ValueType::Boolean)
+                .with_description("Index the field for workflow instance search"),
+        )
 }
 
 fn create_integer_type_node_def() -> NodeDef {
@@ -21,4 +26,81 @@ fn create_integer_type_node_def() -> NodeDef {
         .with_argument(ArgumentDef::new("id", ValueType::String))
         .with_property("name", PropertyDef::new(ValueType::String))
         .with_property("description", PropertyDef::new(ValueType::String))
+        .with_property(
+            "searchable",
+            PropertyDef::new(ValueType::Boolean)
+                .with_description("Index the field for workflow instance search"),
+        )
+}
+
+/// Type of a model field declared `searchable`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchableType {
+    String,
+    Integer,
+}
+
+/// A model field declared `searchable`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchableField {
+    /// The field id, as used in instance contexts
+    pub id: String,
+    pub ty: SearchableType,
+}
+
+/// Collect the model fields marked `searchable #true` in a parsed document
+pub fn searchable_fields(document: &kdl::KdlDocument) -> Vec<SearchableField> {
+    let models = document
+        .nodes()
+        .iter()
+        .filter(|node| node.name().value() == "definition")
+        .filter_map(|node| node.children())
+        .flat_map(|children| children.nodes())
+        .filter(|node| node.name().value() == "model")
+        .filter_map(|node| node.children());
+
+    models
+        .flat_map(|children| children.nodes())
+        .filter(|field| is_searchable(field))
+        .filter_map(|field| {
+            let ty = match field.name().value() {
+                "string" => SearchableType::String,
+                "integer" => SearchableType::Integer,
+                _ => return None,
+            };
+            let id = field
+                .entries()
+                .iter()
+                .find(|e| e.name().is_none())
+                .and_then(|e| e.value().as_string())?;
+            Some(SearchableField {
+                id: id.to_string(),
+                ty,
+            })
+        })
+        .collect()
+}
+
+/// Whether a field sets `searchable #true`, either as a property or as a
+/// child node
+fn is_searchable(field: &kdl::KdlNode) -> bool {
+    let direct = field
+        .entries()
+        .iter()
+        .find(|e| e.name().map(|n| n.value()) == Some("searchable"))
+        .and_then(|e| e.value().as_bool());
+
+    direct
+        .or_else(|| {
+            field
+                .children()?
+                .nodes()
+                .iter()
+                .find(|child| child.name().value() == "searchable")?
+                .entries()
+                .first()?
+                .value()
+                .as_bool()
+        })
+        .unwrap_or(false)
 }
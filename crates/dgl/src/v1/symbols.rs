@@ -0,0 +1,53 @@
+//! Global symbols of DGL v1 documents
+
+use crate::semantic::{Symbol, SymbolKind};
+
+/// Symbols a document makes known to the rest of the workspace
+///
+/// The document `id` is a symbol of its own, and every named definition is
+/// known as `<id>#<name>`. Documents without an id define nothing.
+pub fn global_symbols(document: &kdl::KdlDocument) -> Vec<Symbol> {
+    let argument = |node: &kdl::KdlNode| {
+        node.entries()
+            .first()
+            .filter(|entry| entry.name().is_none())
+            .and_then(|entry| entry.value().as_string())
+            .map(str::to_string)
+    };
+
+    let Some((id_node, id)) = document
+        .nodes()
+        .iter()
+        .find(|node| node.name().value() == "id")
+        .and_then(|node| Some((node, argument(node)?)))
+    else {
+        return Vec::new();
+    };
+
+    let mut symbols = vec![Symbol {
+        name: id.clone(),
+        kind: SymbolKind::Definition,
+        definition_span: id_node.span(),
+        documentation: None,
+        type_info: Some("document".to_string()),
+    }];
+
+    for definition in document.nodes().iter().filter(|node| node.name().value() == "definition") {
+        let Some(name) = argument(definition) else {
+            continue;
+        };
+        let kind = definition
+            .children()
+            .and_then(|children| children.nodes().iter().find(|node| node.name().value() == "kind"))
+            .and_then(argument);
+        symbols.push(Symbol {
+            name: format!("{}#{}", id, name),
+            kind: SymbolKind::Definition,
+            definition_span: definition.span(),
+            documentation: None,
+            type_info: kind,
+        });
+    }
+
+    symbols
+}
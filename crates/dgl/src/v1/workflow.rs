@@ -18,7 +18,34 @@ fn create_state_node_def() -> NodeDef {
         .with_description("State available in the workflow")
         .with_argument(ArgumentDef::new("name", ValueType::String))
         .with_property("description", PropertyDef::new(ValueType::String))
-        .with_property("type", PropertyDef::new(ValueType::String))
+        .with_property(
+            "type",
+            PropertyDef::new(ValueType::String)
+                .with_description("\"initial\" for the state workflows start in, the first state by default"),
+        )
+        .with_child(create_task_node_def())
+}
+
+fn create_task_node_def() -> NodeDef {
+    NodeDef::new("task")
+        .with_description("Task executed when the state is entered")
+        .with_argument(ArgumentDef::new("name", ValueType::String))
+        .with_property(
+            "runtime",
+            PropertyDef::new(ValueType::String)
+                .with_description("Runtime executing the task, \"javascript\" by default"),
+        )
+        .with_property("code", PropertyDef::new(ValueType::String).with_description("Source code of the task"))
+        .with_property("timeout-ms", PropertyDef::new(ValueType::Integer))
+        .with_property(
+            "result",
+            PropertyDef::new(ValueType::String)
+                .with_description("Where the output is stored in the workflow context, e.g. \"$.steps.verify\""),
+        )
+        .with_property(
+            "on-complete",
+            PropertyDef::new(ValueType::String).with_description("Event fired when the task completes"),
+        )
 }
 
 fn create_transitions_node_def() -> NodeDef {
@@ -34,4 +61,271 @@ fn create_transition_node_def() -> NodeDef {
         .with_property("description", PropertyDef::new(ValueType::String))
         .with_property("from", PropertyDef::new(ValueType::String))
         .with_property("to", PropertyDef::new(ValueType::String))
+        .with_property(
+            "guard",
+            PropertyDef::new(ValueType::String)
+                .with_description("Condition on the workflow context, e.g. \"amount > 1000 && applicant.verified\""),
+        )
+}
+
+/// A state declared in a workflow definition
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorkflowState {
+    pub name: String,
+    pub description: Option<String>,
+    /// The `type` of the state, such as `initial`
+    pub kind: Option<String>,
+    /// Tasks executed when the state is entered
+    pub tasks: Vec<WorkflowTask>,
+}
+
+/// A task declared in a workflow state
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorkflowTask {
+    pub name: String,
+    pub runtime: Option<String>,
+    pub code: Option<String>,
+    pub timeout_ms: Option<u64>,
+    /// Where the output is stored in the workflow context
+    pub result: Option<String>,
+    /// The event fired when the task completes
+    pub on_complete: Option<String>,
+}
+
+/// A transition declared in a workflow definition
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorkflowTransition {
+    /// The event that triggers the transition
+    pub name: String,
+    pub from: String,
+    pub to: String,
+    pub guard: Option<String>,
+}
+
+/// States and transitions of a workflow definition, for rendering diagrams
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorkflowGraph {
+    /// The document id
+    pub id: Option<String>,
+    /// Span of the `definition` node declaring the workflow
+    pub span: miette::SourceSpan,
+    pub states: Vec<WorkflowState>,
+    pub transitions: Vec<WorkflowTransition>,
+}
+
+impl WorkflowGraph {
+    /// The state workflows start in: the one of type `initial`, or else the
+    /// first declared state
+    pub fn initial_state(&self) -> Option<&str> {
+        self.states
+            .iter()
+            .find(|state| state.kind.as_deref() == Some("initial"))
+            .or_else(|| self.states.first())
+            .map(|state| state.name.as_str())
+    }
+
+    /// Render the workflow as a Mermaid state diagram
+    ///
+    /// States get generated ids so that any state name can be used.
+    pub fn to_mermaid(&self) -> String {
+        let names = self.state_names();
+        let id = |name: &str| format!("s{}", names.iter().position(|n| *n == name).unwrap_or(0));
+
+        let mut out = String::from("stateDiagram-v2\n");
+        for name in &names {
+            out.push_str(&format!("    state \"{}\" as {}\n", mermaid_escape(name), id(name)));
+        }
+        for state in &self.states {
+            if let Some(description) = &state.description {
+                out.push_str(&format!("    {} : {}\n", id(&state.name), mermaid_escape(description)));
+            }
+        }
+        for transition in &self.transitions {
+            let mut label = transition.name.clone();
+            if let Some(guard) = &transition.guard {
+                label.push_str(&format!(" [{}]", guard));
+            }
+            out.push_str(&format!(
+                "    {} --> {} : {}\n",
+                id(&transition.from),
+                id(&transition.to),
+                mermaid_escape(&label)
+            ));
+        }
+        out
+    }
+
+    /// Render the workflow as a Graphviz DOT digraph
+    pub fn to_dot(&self) -> String {
+        let title = self.id.as_deref().unwrap_or("workflow");
+        let mut out = format!("digraph \"{}\" {{\n", dot_escape(title));
+        out.push_str("    rankdir=LR;\n");
+        out.push_str("    node [shape=box, style=rounded];\n");
+        for name in self.state_names() {
+            let description = self
+                .states
+                .iter()
+                .find(|state| state.name == name)
+                .and_then(|state| state.description.as_deref());
+            match description {
+                Some(description) => out.push_str(&format!(
+                    "    \"{}\" [tooltip=\"{}\"];\n",
+                    dot_escape(name),
+                    dot_escape(description)
+                )),
+                None => out.push_str(&format!("    \"{}\";\n", dot_escape(name))),
+            }
+        }
+        for transition in &self.transitions {
+            let mut label = dot_escape(&transition.name);
+            if let Some(guard) = &transition.guard {
+                label.push_str(&format!("\\n[{}]", dot_escape(guard)));
+            }
+            out.push_str(&format!(
+                "    \"{}\" -> \"{}\" [label=\"{}\"];\n",
+                dot_escape(&transition.from),
+                dot_escape(&transition.to),
+                label
+            ));
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    /// Declared states followed by states only referenced by transitions
+    fn state_names(&self) -> Vec<&str> {
+        let mut names: Vec<&str> = self.states.iter().map(|state| state.name.as_str()).collect();
+        for transition in &self.transitions {
+            for name in [transition.from.as_str(), transition.to.as_str()] {
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        }
+        names
+    }
+}
+
+/// Find the workflow definition at a byte offset in a parsed document
+///
+/// When the offset lies outside of every workflow, the document's only
+/// workflow is returned, if it has exactly one.
+pub fn workflow_at(document: &kdl::KdlDocument, offset: usize) -> Option<WorkflowGraph> {
+    let mut workflows = workflows(document);
+    let contains = |workflow: &WorkflowGraph| {
+        let span = workflow.span;
+        span.offset() <= offset && offset <= span.offset() + span.len()
+    };
+    match workflows.iter().position(contains) {
+        Some(found) => Some(workflows.swap_remove(found)),
+        None if workflows.len() == 1 => workflows.pop(),
+        None => None,
+    }
+}
+
+/// All workflow definitions of a parsed document
+pub fn workflows(document: &kdl::KdlDocument) -> Vec<WorkflowGraph> {
+    let id = document
+        .nodes()
+        .iter()
+        .find(|node| node.name().value() == "id")
+        .and_then(|node| node.entries().first())
+        .and_then(|entry| entry.value().as_string())
+        .map(str::to_string);
+
+    document
+        .nodes()
+        .iter()
+        .filter(|node| node.name().value() == "definition")
+        .filter_map(|definition| {
+            let workflow = definition
+                .children()?
+                .nodes()
+                .iter()
+                .find(|node| node.name().value() == "workflow")?;
+            Some(workflow_graph(id.clone(), definition, workflow))
+        })
+        .collect()
+}
+
+fn workflow_graph(id: Option<String>, definition: &kdl::KdlNode, workflow: &kdl::KdlNode) -> WorkflowGraph {
+    let states = section(workflow, "states")
+        .filter(|node| node.name().value() == "state")
+        .filter_map(|node| {
+            Some(WorkflowState {
+                name: argument(node)?,
+                description: NodeDef::get_node_property_value(node, "description"),
+                kind: NodeDef::get_node_property_value(node, "type"),
+                tasks: node
+                    .children()
+                    .into_iter()
+                    .flat_map(|children| children.nodes())
+                    .filter(|node| node.name().value() == "task")
+                    .filter_map(workflow_task)
+                    .collect(),
+            })
+        })
+        .collect();
+    let transitions = section(workflow, "transitions")
+        .filter(|node| node.name().value() == "transition")
+        .filter_map(|node| {
+            Some(WorkflowTransition {
+                name: argument(node)?,
+                from: NodeDef::get_node_property_value(node, "from")?,
+                to: NodeDef::get_node_property_value(node, "to")?,
+                guard: NodeDef::get_node_property_value(node, "guard"),
+            })
+        })
+        .collect();
+
+    WorkflowGraph {
+        id,
+        span: definition.span(),
+        states,
+        transitions,
+    }
+}
+
+fn workflow_task(node: &kdl::KdlNode) -> Option<WorkflowTask> {
+    Some(WorkflowTask {
+        name: argument(node)?,
+        runtime: NodeDef::get_node_property_value(node, "runtime"),
+        code: NodeDef::get_node_property_value(node, "code"),
+        timeout_ms: node
+            .entries()
+            .iter()
+            .find(|e| e.name().map(|n| n.value()) == Some("timeout-ms"))
+            .and_then(|e| e.value().as_integer())
+            .and_then(|timeout| u64::try_from(timeout).ok()),
+        result: NodeDef::get_node_property_value(node, "result"),
+        on_complete: NodeDef::get_node_property_value(node, "on-complete"),
+    })
+}
+
+/// The nodes inside the `name` blocks of a workflow
+fn section<'a>(workflow: &'a kdl::KdlNode, name: &'a str) -> impl Iterator<Item = &'a kdl::KdlNode> + 'a {
+    workflow
+        .children()
+        .into_iter()
+        .flat_map(|children| children.nodes())
+        .filter(move |node| node.name().value() == name)
+        .filter_map(|node| node.children())
+        .flat_map(|children| children.nodes())
+}
+
+/// The first argument of a node, if it is a string
+fn argument(node: &kdl::KdlNode) -> Option<String> {
+    node.entries()
+        .iter()
+        .find(|e| e.name().is_none())
+        .and_then(|e| e.value().as_string())
+        .map(str::to_string)
+}
+
+fn mermaid_escape(text: &str) -> String {
+    text.replace('"', "#quot;").replace(['\n', '\r'], " ")
+}
+
+fn dot_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"").replace(['\n', '\r'], " ")
 }
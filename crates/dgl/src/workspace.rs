@@ -0,0 +1,126 @@
+//! Symbols shared across the documents of a workspace
+//!
+//! Editors validate one document at a time, but definitions in one file are
+//! referenced from others. A `WorkspaceIndex` keeps the global symbols of
+//! every indexed file, keyed by whatever identifies files to the caller (a
+//! path or URI), so lookups and duplicate checks see the whole workspace.
+
+use crate::semantic::Symbol;
+use miette::SourceSpan;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// Global symbols of all files in a workspace
+#[derive(Debug, Clone)]
+pub struct WorkspaceIndex<K> {
+    files: HashMap<K, Vec<Symbol>>,
+}
+
+impl<K> Default for WorkspaceIndex<K> {
+    fn default() -> Self {
+        Self { files: HashMap::new() }
+    }
+}
+
+impl<K: Eq + Hash> WorkspaceIndex<K> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replace the symbols of a file
+    pub fn update(&mut self, file: K, symbols: Vec<Symbol>) {
+        self.files.insert(file, symbols);
+    }
+
+    /// Forget a file
+    pub fn remove(&mut self, file: &K) {
+        self.files.remove(file);
+    }
+
+    /// Whether a file is indexed
+    pub fn contains(&self, file: &K) -> bool {
+        self.files.contains_key(file)
+    }
+
+    /// Number of indexed files
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// All definitions of a symbol, with the files defining them
+    pub fn lookup<'a>(&'a self, name: &'a str) -> impl Iterator<Item = (&'a K, &'a Symbol)> + 'a {
+        self.files
+            .iter()
+            .flat_map(move |(file, symbols)| symbols.iter().filter(move |s| s.name == name).map(move |s| (file, s)))
+    }
+
+    /// Symbols of `file` also defined by another file, with that file and
+    /// its definition
+    pub fn conflicts<'a>(&'a self, file: &'a K) -> Vec<(&'a Symbol, &'a K, &'a Symbol)> {
+        let Some(symbols) = self.files.get(file) else {
+            return Vec::new();
+        };
+        symbols
+            .iter()
+            .flat_map(|symbol| {
+                self.lookup(&symbol.name)
+                    .filter(move |(other, _)| *other != file)
+                    .map(move |(other, definition)| (symbol, other, definition))
+            })
+            .collect()
+    }
+}
+
+/// The string value of the entry at `offset`, e.g. to look up the symbol a
+/// value refers to
+pub fn string_at(document: &kdl::KdlDocument, offset: usize) -> Option<&str> {
+    document.nodes().iter().find_map(|node| {
+        let span = node.span();
+        if offset < span.offset() || offset > span.offset() + span.len() {
+            return None;
+        }
+        node.entries()
+            .iter()
+            .find(|entry| {
+                let span = entry.span();
+                span.offset() <= offset && offset <= span.offset() + span.len()
+            })
+            .and_then(|entry| entry.value().as_string())
+            .or_else(|| string_at(node.children()?, offset))
+    })
+}
+
+/// String values holding `http` or `https` URLs, with the span of the value
+///
+/// Spans of properties leave out the name, so only the value is linked.
+pub fn url_values<'a>(document: &'a kdl::KdlDocument, source: &str) -> Vec<(SourceSpan, &'a str)> {
+    let mut urls = Vec::new();
+    for node in document.nodes() {
+        for entry in node.entries() {
+            let Some(value) = entry.value().as_string() else {
+                continue;
+            };
+            if !value.starts_with("https://") && !value.starts_with("http://") {
+                continue;
+            }
+            let span = entry.span();
+            let end = span.offset() + span.len();
+            let value_from = entry
+                .name()
+                .map_or(span.offset(), |name| name.span().offset() + name.span().len());
+            let Some(rest) = source.get(value_from..end) else {
+                continue;
+            };
+            let start = end - rest.trim_start_matches(|c: char| c == '=' || c.is_whitespace()).len();
+            urls.push((SourceSpan::new(start.into(), end - start), value));
+        }
+        if let Some(children) = node.children() {
+            urls.extend(url_values(children, source));
+        }
+    }
+    urls
+}
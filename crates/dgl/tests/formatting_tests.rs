@@ -0,0 +1,111 @@
+//! Tests for block structure and document formatting
+
+use dgv_dgl::formatting::{format_document, format_range, BlockStructure};
+use dgv_dgl::prelude::*;
+
+fn offset_of(text: &str, pattern: &str) -> usize {
+    let byte = text.find(pattern).unwrap();
+    text[..byte].chars().count()
+}
+
+#[test]
+fn test_nested_block_depth() {
+    let text = "a {\n    b {\n        c 1\n    }\n}\n";
+    let blocks = BlockStructure::scan(text);
+
+    assert_eq!(blocks.blocks().len(), 2);
+    assert_eq!(blocks.depth_at(0), 0);
+    assert_eq!(blocks.depth_at(offset_of(text, "b {")), 1);
+    assert_eq!(blocks.depth_at(offset_of(text, "c 1")), 2);
+    assert_eq!(blocks.depth_at(text.chars().count()), 0);
+}
+
+#[test]
+fn test_closing_brace_has_content_depth() {
+    let text = "a {\n    b 1\n}";
+    let blocks = BlockStructure::scan(text);
+    let close = offset_of(text, "}");
+
+    assert_eq!(blocks.depth_at(close), 1);
+    assert_eq!(blocks.block_closed_at(close).unwrap().open, offset_of(text, "{"));
+}
+
+#[test]
+fn test_unclosed_block() {
+    let text = "a {\n    b {\n";
+    let blocks = BlockStructure::scan(text);
+
+    assert!(blocks.blocks().iter().all(|b| b.close.is_none()));
+    assert_eq!(blocks.depth_at(text.chars().count()), 2);
+    assert!(blocks.block_opened_at(offset_of(text, "{")).is_some());
+}
+
+#[test]
+fn test_braces_in_strings_and_comments_ignored() {
+    let text = r####"a "{" #"}"# """
+{
+""" {
+    // }
+    /* { /* } */ } */
+    b 1
+}"####;
+    let blocks = BlockStructure::scan(text);
+
+    assert_eq!(blocks.blocks().len(), 1);
+    assert!(blocks.blocks()[0].close.is_some());
+    assert_eq!(blocks.depth_at(offset_of(text, "b 1")), 1);
+}
+
+#[test]
+fn test_multiline_strings() {
+    let text = "a \"one line\"\nb \"\"\"\n    two\n    lines\n    \"\"\"\nc #\"raw\nstring\"#\n";
+    let blocks = BlockStructure::scan(text);
+    let strings = blocks.multiline_strings();
+
+    assert_eq!(strings.len(), 2);
+    assert_eq!(strings[0].start, offset_of(text, "\"\"\""));
+    assert_eq!(strings[1], offset_of(text, "#\"raw")..text.len() - 1);
+}
+
+#[test]
+fn test_stray_closing_brace() {
+    let blocks = BlockStructure::scan("} a { }");
+
+    assert_eq!(blocks.blocks().len(), 1);
+    assert_eq!(blocks.depth_at(0), 0);
+}
+
+fn task_schema() -> Schema {
+    let step = NodeDef::new("step")
+        .with_property("name", PropertyDef::new(ValueType::String))
+        .with_property("runtime", PropertyDef::new(ValueType::String));
+    let task = NodeDef::new("task")
+        .with_property("name", PropertyDef::new(ValueType::String))
+        .with_property("runtime", PropertyDef::new(ValueType::String))
+        .with_property("timeout", PropertyDef::new(ValueType::Integer))
+        .with_child(step);
+    Schema::new("test", task)
+}
+
+#[test]
+fn test_format_orders_properties_and_keeps_comments() {
+    let text = "task runtime=\"js\"   name=\"a\" \"arg\" {\n// the slow part\n      timeout 30\n  runtime \"wasm\"\n step runtime=\"js\" name=\"b\"\n}\n";
+    let formatted = format_document(text, &task_schema(), "    ").unwrap();
+
+    assert!(formatted.starts_with("task \"arg\" name=\"a\" runtime=\"js\" {\n"));
+    assert!(formatted.contains("// the slow part"));
+    assert!(formatted.find("    runtime \"wasm\"").unwrap() < formatted.find("    timeout 30").unwrap());
+    assert!(formatted.contains("\n    step name=\"b\" runtime=\"js\"\n"));
+    assert_eq!(format_document(&formatted, &task_schema(), "    ").unwrap(), formatted);
+}
+
+#[test]
+fn test_format_range_covers_whole_top_level_nodes() {
+    let text = "task name=\"a\"\ntask runtime=\"js\" name=\"b\" {\n  step name=\"c\"\n}\n";
+    let range = offset_of(text, "step")..offset_of(text, "step") + 4;
+    let (replaced, formatted) = format_range(text, &task_schema(), "    ", range).unwrap().unwrap();
+
+    assert_eq!(replaced, offset_of(text, "task runtime")..text.chars().count() - 1);
+    assert_eq!(formatted, "task name=\"b\" runtime=\"js\" {\n    step name=\"c\"\n}");
+    assert!(format_document("task {", &task_schema(), "    ").is_err());
+}
@@ -131,6 +131,29 @@ fn test_required_property_missing() {
     assert!(result.is_err());
 }
 
+#[test]
+fn test_check_keeps_document_with_errors() {
+    let source = r#"person age="30""#;
+    
+    let root = NodeDef::new("person")
+        .with_property("name", PropertyDef::new(ValueType::String).required());
+    let schema = Schema::new("test-schema", root);
+    
+    let parser = Parser::new(source.to_string(), "check.dgl".to_string())
+        .with_schema(schema.clone());
+    let (document, diagnostics) = parser.check();
+    
+    // Schema errors are reported but the document is kept
+    assert_eq!(document.unwrap().nodes().len(), 1);
+    assert!(diagnostics.iter().any(|d| d.severity == miette::Severity::Error));
+    
+    let parser = Parser::new("person {".to_string(), "check.dgl".to_string())
+        .with_schema(schema);
+    let (document, diagnostics) = parser.check();
+    assert!(document.is_none());
+    assert!(!diagnostics.is_empty());
+}
+
 #[test]
 fn test_optional_property_missing() {
     let source = r#"person name="John""#;
@@ -713,6 +736,56 @@ config {
     assert!(result.is_ok());
 }
 
+// ============================================================================
+// QUICK FIX TESTS
+// ============================================================================
+
+fn status_schema() -> Schema {
+    let mut schema = Schema::new("test-schema", NodeDef::new("person"));
+    schema.define_enum("Status", EnumDef::new(vec![
+        "active".to_string(),
+        "inactive".to_string(),
+        "pending".to_string(),
+    ]));
+    schema.root = schema.root.clone()
+        .with_property("name", PropertyDef::new(ValueType::String).required())
+        .with_property("status", PropertyDef::new(ValueType::Enum("Status".to_string())));
+    schema
+}
+
+/// Apply the only fix of the only diagnostic with `code`
+fn apply_fix(source: &str, code: &str) -> String {
+    let parser = Parser::new(source.to_string(), "fix.dgl".to_string())
+        .with_schema(status_schema());
+    let err = parser.parse().unwrap_err();
+    let diagnostic = err.diagnostics.iter().find(|d| d.kind.code() == code).unwrap();
+    assert_eq!(diagnostic.fixes.len(), 1);
+
+    let mut fixed = source.to_string();
+    for (span, text) in diagnostic.fixes[0].edits.iter().rev() {
+        fixed.replace_range(span.offset()..span.offset() + span.len(), text);
+    }
+    fixed
+}
+
+#[test]
+fn test_fix_missing_property() {
+    let fixed = apply_fix(r#"person status="active""#, "dgl::missing_property");
+    assert_eq!(fixed, r#"person status="active" name="""#);
+}
+
+#[test]
+fn test_fix_invalid_enum_value() {
+    let fixed = apply_fix(r#"person name="Jo" status="inactve""#, "dgl::invalid_value");
+    assert_eq!(fixed, r#"person name="Jo" status="inactive""#);
+}
+
+#[test]
+fn test_fix_unknown_property() {
+    let fixed = apply_fix(r#"person name="Jo" colour="red" status="active""#, "dgl::unknown_property");
+    assert_eq!(fixed, r#"person name="Jo" status="active""#);
+}
+
 // ============================================================================
 // ERROR HANDLING TESTS
 // ============================================================================
@@ -0,0 +1,64 @@
+//! Tests for loading schemas from KDL files
+
+use dgv_dgl::prelude::*;
+use dgv_dgl::{load_schema, KdlValue};
+
+const SCHEMA: &str = r#"
+schema "deployments"
+
+enum "environment" "development" "production"
+
+root {
+    property "id" type="nsid" required=#true
+    node "service" description="A deployed service" {
+        argument "name" type="string"
+        argument "port" type="integer" default=8080
+        property "environment" type="enum<environment>" default="development"
+        property "uses" type="ref<service>"
+    }
+}
+"#;
+
+#[test]
+fn test_load_schema() {
+    let schema = load_schema(SCHEMA, "deployments.kdl").unwrap();
+    assert_eq!(schema.name, "deployments");
+    assert_eq!(schema.get_enum("environment").unwrap().values, ["development", "production"]);
+
+    let id = &schema.root.properties["id"];
+    assert!(id.required);
+    assert_eq!(id.ty.name(), "nsid");
+
+    let service = &schema.root.children[0];
+    assert_eq!(service.description.as_deref(), Some("A deployed service"));
+    assert!(service.arguments[0].required);
+    assert!(!service.arguments[1].required);
+    assert_eq!(service.properties["environment"].ty.name(), "enum<environment>");
+    assert_eq!(
+        service.properties["environment"].default,
+        Some(KdlValue::String("development".to_string()))
+    );
+    assert_eq!(service.properties["uses"].ty.name(), "ref<service>");
+}
+
+#[test]
+fn test_loaded_schema_validates_documents() {
+    let schema = load_schema(SCHEMA, "deployments.kdl").unwrap();
+
+    let source = "id \"de.example/deployments\"\nservice \"api\" environment=\"production\"\n";
+    let parser = Parser::new(source.to_string(), "ok.dgl".to_string()).with_schema(schema.clone());
+    assert!(parser.parse().is_ok());
+
+    let source = "id \"not an nsid\"\nservice \"api\" environment=\"staging\"\n";
+    let parser = Parser::new(source.to_string(), "bad.dgl".to_string()).with_schema(schema);
+    assert_eq!(parser.parse().unwrap_err().error_count(), 2);
+}
+
+#[test]
+fn test_load_schema_errors() {
+    let err = load_schema("root {\n    field \"id\"\n    property\n}\n", "bad.kdl").unwrap_err();
+    let codes: Vec<_> = err.diagnostics.iter().map(|d| d.kind.code()).collect();
+    assert_eq!(codes, ["dgl::unknown_node", "dgl::missing_property"]);
+
+    assert!(load_schema("schema \"empty\"", "empty.kdl").is_err());
+}
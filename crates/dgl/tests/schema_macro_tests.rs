@@ -0,0 +1,131 @@
+//! Tests for the declarative schema macros
+
+use dgv_dgl::prelude::*;
+use dgv_dgl::KdlValue;
+
+fn workflow_schema() -> Schema {
+    dgl_schema!("macro-test" {
+        root {
+            prop id: String [required, description("The ID of the document")];
+            child "definition" {
+                description = "Definition containing a kind property";
+                prop kind: Enum("kind") [required];
+                child_if (|_, node| NodeDef::get_node_property_value(node, "kind") == Some("Workflow".to_string()))
+                    "workflow" {
+                        description = "Workflow type definition";
+                        child "states" {
+                            child "state" {
+                                arg name: String;
+                                prop description: String;
+                                prop "type": String [suggestions("task", "human")];
+                            }
+                        }
+                    }
+            }
+        }
+        enum kind ["DataModel", "Workflow"] [description("The kind of the object"), value("Workflow", "A workflow")];
+    })
+}
+
+#[test]
+fn test_macro_builds_equivalent_node_def() {
+    let from_macro = dgl_node!("state" {
+        description = "State available in the workflow";
+        arg name: String;
+        prop description: String [description("Human-readable description")];
+        prop priority: Integer [default(KdlValue::Integer(0))];
+    });
+
+    let from_builder = NodeDef::new("state")
+        .with_description("State available in the workflow")
+        .with_argument(ArgumentDef::new("name", ValueType::String))
+        .with_property(
+            "description",
+            PropertyDef::new(ValueType::String).with_description("Human-readable description"),
+        )
+        .with_property(
+            "priority",
+            PropertyDef::new(ValueType::Integer).with_default(KdlValue::Integer(0)),
+        );
+
+    assert_eq!(from_macro.name, from_builder.name);
+    assert_eq!(from_macro.description, from_builder.description);
+    assert_eq!(from_macro.arguments.len(), from_builder.arguments.len());
+    assert_eq!(from_macro.arguments[0].name, "name");
+    assert_eq!(from_macro.arguments[0].ty, ValueType::String);
+    assert!(from_macro.arguments[0].required);
+
+    for (name, prop) in &from_builder.properties {
+        let other = from_macro.properties.get(name).expect("property missing");
+        assert_eq!(other.ty, prop.ty);
+        assert_eq!(other.required, prop.required);
+        assert_eq!(other.default, prop.default);
+        assert_eq!(other.description, prop.description);
+    }
+}
+
+#[test]
+fn test_macro_value_types() {
+    let def = dgl_node!("types" {
+        prop a: Boolean;
+        prop b: Float;
+        prop c: NodeRef("model");
+        prop d: Custom("nsid", "nsid");
+        prop e: Custom("free");
+        prop "node-name": Any [optional];
+    });
+
+    assert_eq!(def.properties["a"].ty, ValueType::Boolean);
+    assert_eq!(def.properties["b"].ty, ValueType::Float);
+    assert_eq!(def.properties["c"].ty, ValueType::NodeRef("model".to_string()));
+    assert_eq!(def.properties["d"].ty, ValueType::custom("nsid", "nsid"));
+    assert_eq!(def.properties["e"].ty, ValueType::custom_unvalidated("free"));
+    assert_eq!(def.properties["node-name"].ty, ValueType::Any);
+}
+
+#[test]
+fn test_macro_schema_enums() {
+    let schema = workflow_schema();
+
+    assert_eq!(schema.name, "macro-test");
+    let kind = schema.get_enum("kind").expect("enum should be defined");
+    assert!(kind.is_valid("Workflow"));
+    assert!(!kind.is_valid("Service"));
+    assert_eq!(kind.description.as_deref(), Some("The kind of the object"));
+    assert_eq!(kind.value_descriptions.get("Workflow").map(String::as_str), Some("A workflow"));
+}
+
+#[test]
+fn test_macro_schema_validates_documents() {
+    let source = r#"
+id "de.berlin/issue-card"
+
+definition {
+    kind "Workflow"
+    workflow {
+        states {
+            state "draft" type="task"
+        }
+    }
+}
+    "#;
+
+    let parser = Parser::new(source.to_string(), "macro.dgl".to_string()).with_schema(workflow_schema());
+    assert!(parser.parse().is_ok());
+}
+
+#[test]
+fn test_macro_schema_conditional_child() {
+    let source = r#"
+id "de.berlin/person"
+
+definition {
+    kind "DataModel"
+    workflow {
+    }
+}
+    "#;
+
+    let parser = Parser::new(source.to_string(), "macro.dgl".to_string()).with_schema(workflow_schema());
+    assert!(parser.parse().is_err(), "workflow child is only allowed for kind=Workflow");
+}
@@ -189,8 +189,8 @@ fn test_schema_modifier_removing_properties() {
 
             // In production mode, remove debug-related properties
             if mode_value == Some("production") {
-                modified_def.properties.remove("debug");
-                modified_def.properties.remove("log_level");
+                modified_def.properties.shift_remove("debug");
+                modified_def.properties.shift_remove("log_level");
             }
 
             modified_def
@@ -2,6 +2,7 @@
 
 use dgv_dgl::prelude::*;
 use dgv_dgl::semantic::{CompletionEngine, SemanticInfo};
+use dgv_dgl::{InlayHintKind, KdlValue};
 
 #[test]
 fn test_semantic_info_basic() {
@@ -177,3 +178,94 @@ root root_prop="value" {
     assert!(!semantic_info.hover_info.is_empty());
 }
 
+
+#[test]
+fn test_reference_values_recorded() {
+    let root = NodeDef::new("")
+        .with_property(
+            "id",
+            PropertyDef::new(ValueType::Custom {
+                name: "nsid".to_string(),
+                validator: None,
+            }),
+        )
+        .with_property("title", PropertyDef::new(ValueType::String))
+        .with_child(
+            NodeDef::new("uses").with_property("model", PropertyDef::new(ValueType::NodeRef("model".to_string()))),
+        );
+    let schema = Schema::new("test", root);
+
+    let source = r#"
+id "de.berlin/business"
+title "Business"
+uses model="de.berlin/person#person"
+    "#;
+    let doc: kdl::KdlDocument = source.parse().unwrap();
+    let semantic_info = SemanticInfo::analyze(&doc, &schema, source);
+
+    let targets: Vec<_> = semantic_info.references.iter().map(|r| r.target.as_str()).collect();
+    assert_eq!(targets, ["de.berlin/business", "de.berlin/person#person"]);
+
+    let offset = source.find("de.berlin/person").unwrap();
+    assert_eq!(semantic_info.find_reference_at(offset).map(|(_, t)| t), Some("de.berlin/person#person"));
+}
+
+#[test]
+fn test_inlay_hints() {
+    let root = NodeDef::new("step")
+        .with_argument(ArgumentDef::new("name", ValueType::String))
+        .with_property("retries", PropertyDef::new(ValueType::Integer).with_default(KdlValue::Integer(3)))
+        .with_property("timeout", PropertyDef::new(ValueType::Integer).with_default(KdlValue::Integer(30)))
+        .with_property("queue", PropertyDef::new(ValueType::String));
+    let schema = Schema::new("test", root);
+
+    let source = "step \"fetch\" timeout=10\n";
+    let doc: kdl::KdlDocument = source.parse().unwrap();
+    let semantic_info = SemanticInfo::analyze(&doc, &schema, source);
+
+    let hints: Vec<_> = semantic_info
+        .inlay_hints
+        .iter()
+        .map(|hint| (hint.offset, hint.label.as_str(), hint.kind))
+        .collect();
+    let head_end = source.find('\n').unwrap();
+    assert_eq!(
+        hints,
+        [
+            (source.find(" timeout").unwrap(), ": string", InlayHintKind::Type),
+            (head_end, "retries=3", InlayHintKind::Default),
+        ]
+    );
+}
+
+#[test]
+fn test_signature_help() {
+    let step = NodeDef::new("step")
+        .with_description("A workflow step")
+        .with_argument(ArgumentDef::new("name", ValueType::String).with_description("Step name"))
+        .with_argument(ArgumentDef::new("title", ValueType::String).optional());
+    let root = NodeDef::new("")
+        .with_property("id", PropertyDef::new(ValueType::String))
+        .with_child(NodeDef::new("workflow").with_child(step));
+    let engine = CompletionEngine::new(Schema::new("test", root));
+
+    let source = "id \"x\"\nworkflow {\n    step \"fetch\" \n}\n";
+    let doc: kdl::KdlDocument = source.parse().unwrap();
+    let fetch_end = source.find("\" \n").unwrap() + 1;
+
+    let help = engine.signature_help(&doc, fetch_end, source).unwrap();
+    assert_eq!(help.label, "step name: string [title: string]");
+    assert_eq!(&help.label[help.parameters[1].label.clone()], "[title: string]");
+    assert_eq!(help.parameters[0].documentation.as_deref(), Some("Step name"));
+    assert_eq!(help.documentation.as_deref(), Some("A workflow step"));
+    assert_eq!(help.active_parameter, Some(0));
+
+    let help = engine.signature_help(&doc, fetch_end + 1, source).unwrap();
+    assert_eq!(help.active_parameter, Some(1));
+
+    assert!(engine.signature_help(&doc, source.find("step").unwrap() + 2, source).is_none());
+    assert_eq!(
+        engine.signature_help(&doc, source.find(" {").unwrap() + 1, source).map(|help| help.label),
+        Some("workflow".to_string())
+    );
+}
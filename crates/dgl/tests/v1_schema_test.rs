@@ -27,3 +27,117 @@ definition {
     
     assert!(result.is_ok());
 }
+
+#[test]
+fn test_v1_searchable_fields() {
+    let source = r#"
+id "de.berlin/business-registration"
+
+definition {
+    kind "DataModel"
+    model {
+        string "applicant_name" searchable=#true
+        string "notes"
+        integer "employees" searchable=#true
+    }
+}
+    "#;
+
+    let parser = Parser::new(source.to_string(), "v1-schema-test.dgl".to_string());
+    let parser = parser.with_schema(v1::create_schema());
+
+    let parsed = parser.parse().expect("document should parse");
+    let fields = v1::searchable_fields(&parsed.document);
+
+    assert_eq!(
+        fields,
+        vec![
+            v1::SearchableField {
+                id: "applicant_name".to_string(),
+                ty: v1::SearchableType::String,
+            },
+            v1::SearchableField {
+                id: "employees".to_string(),
+                ty: v1::SearchableType::Integer,
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_v1_workflow_graph() {
+    let source = r#"
+id "de.berlin/issue-card"
+
+definition {
+    kind "Workflow"
+    workflow {
+        states {
+            state "draft" description="Application being filled in"
+            state "review"
+            state "issued"
+        }
+        transitions {
+            transition "submit" from="draft" to="review"
+            transition "approve" from="review" to="issued" guard="applicant.verified"
+        }
+    }
+}
+    "#;
+
+    let parser = Parser::new(source.to_string(), "v1-schema-test.dgl".to_string());
+    let parser = parser.with_schema(v1::create_schema());
+
+    let parsed = parser.parse().expect("document should parse");
+    let offset = source.find("approve").unwrap();
+    let graph = v1::workflow_at(&parsed.document, offset).expect("cursor is in a workflow");
+
+    assert_eq!(graph.id.as_deref(), Some("de.berlin/issue-card"));
+    assert_eq!(graph.states.len(), 3);
+    assert_eq!(graph.transitions[1].guard.as_deref(), Some("applicant.verified"));
+    assert!(graph.to_mermaid().contains("s1 --> s2 : approve [applicant.verified]"));
+    assert!(graph.to_dot().contains("\"draft\" -> \"review\" [label=\"submit\"];"));
+}
+
+#[test]
+fn test_v1_workflow_tasks() {
+    let source = r#"
+id "de.berlin/issue-card"
+
+definition {
+    kind "Workflow"
+    workflow {
+        states {
+            state "draft"
+            state "review" type="initial" {
+                task "verify" code="export default (ctx) => ctx" timeout-ms=5000 result="$.steps.verify" on-complete="verified"
+            }
+            state "issued"
+        }
+        transitions {
+            transition "verified" from="review" to="issued"
+        }
+    }
+}
+    "#;
+
+    let parser = Parser::new(source.to_string(), "v1-schema-test.dgl".to_string());
+    let parser = parser.with_schema(v1::create_schema());
+
+    let parsed = parser.parse().expect("document should parse");
+    let workflows = v1::workflows(&parsed.document);
+
+    assert_eq!(workflows.len(), 1);
+    assert_eq!(workflows[0].initial_state(), Some("review"));
+    assert_eq!(
+        workflows[0].states[1].tasks,
+        vec![v1::WorkflowTask {
+            name: "verify".to_string(),
+            runtime: None,
+            code: Some("export default (ctx) => ctx".to_string()),
+            timeout_ms: Some(5000),
+            result: Some("$.steps.verify".to_string()),
+            on_complete: Some("verified".to_string()),
+        }]
+    );
+}
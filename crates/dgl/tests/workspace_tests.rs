@@ -0,0 +1,79 @@
+//! Tests for the workspace symbol index
+
+use dgv_dgl::workspace::{string_at, url_values};
+use dgv_dgl::{v1, WorkspaceIndex};
+
+fn parse(source: &str) -> kdl::KdlDocument {
+    source.parse().unwrap()
+}
+
+#[test]
+fn test_global_symbols() {
+    let document = parse(
+        r#"
+id "de.berlin/business"
+
+definition {
+    kind "DataModel"
+}
+
+definition "register-business" {
+    kind "Workflow"
+}
+"#,
+    );
+    let symbols = v1::global_symbols(&document);
+    let names: Vec<_> = symbols.iter().map(|s| s.name.as_str()).collect();
+
+    assert_eq!(names, ["de.berlin/business", "de.berlin/business#register-business"]);
+    assert_eq!(symbols[1].type_info.as_deref(), Some("Workflow"));
+    assert!(v1::global_symbols(&parse("definition \"orphan\"")).is_empty());
+}
+
+#[test]
+fn test_index_lookup_and_conflicts() {
+    let mut index = WorkspaceIndex::new();
+    index.update("a.dgl", v1::global_symbols(&parse("id \"app.degov/portal\"\ndefinition \"rpc\"")));
+    index.update("b.dgl", v1::global_symbols(&parse("id \"app.degov/portal\"")));
+    index.update("c.dgl", v1::global_symbols(&parse("id \"app.degov/other\"")));
+
+    let found: Vec<_> = index.lookup("app.degov/portal#rpc").map(|(file, _)| *file).collect();
+    assert_eq!(found, ["a.dgl"]);
+
+    let conflicts = index.conflicts(&"b.dgl");
+    assert_eq!(conflicts.len(), 1);
+    assert_eq!(*conflicts[0].1, "a.dgl");
+    assert!(index.conflicts(&"c.dgl").is_empty());
+
+    index.remove(&"a.dgl");
+    assert!(index.conflicts(&"b.dgl").is_empty());
+    assert_eq!(index.len(), 2);
+}
+
+#[test]
+fn test_string_at() {
+    let source = "service {\n    reference \"portal-rpc\"\n}\n";
+    let document = parse(source);
+
+    assert_eq!(string_at(&document, source.find("portal").unwrap()), Some("portal-rpc"));
+    assert_eq!(string_at(&document, 0), None);
+}
+
+#[test]
+fn test_url_values() {
+    let source = "service \"https://example.org/a\" docs = \"http://example.org/b\" title=\"ftp://x\" {\n    homepage \"https://example.org/c\"\n}\n";
+    let document = parse(source);
+
+    let urls: Vec<_> = url_values(&document, source)
+        .into_iter()
+        .map(|(span, url)| (&source[span.offset()..span.offset() + span.len()], url))
+        .collect();
+    assert_eq!(
+        urls,
+        [
+            ("\"https://example.org/a\"", "https://example.org/a"),
+            ("\"http://example.org/b\"", "http://example.org/b"),
+            ("\"https://example.org/c\"", "https://example.org/c"),
+        ]
+    );
+}
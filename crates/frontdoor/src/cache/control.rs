@@ -0,0 +1,67 @@
+//! `Cache-Control` header parsing
+
+use axum::http::{HeaderMap, header};
+use std::time::Duration;
+
+/// The `Cache-Control` directives the response cache acts on
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CacheControl {
+    pub no_store: bool,
+    pub no_cache: bool,
+    pub private: bool,
+    pub max_age: Option<Duration>,
+    pub s_maxage: Option<Duration>,
+}
+
+impl CacheControl {
+    /// Parse all `Cache-Control` headers, ignoring unknown directives
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let mut control = Self::default();
+        let directives = headers
+            .get_all(header::CACHE_CONTROL)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','));
+
+        for directive in directives {
+            let (name, value) = match directive.split_once('=') {
+                Some((name, value)) => (name.trim(), Some(value.trim().trim_matches('"'))),
+                None => (directive.trim(), None),
+            };
+            let seconds = || value.and_then(|v| v.parse().ok()).map(Duration::from_secs);
+
+            match name.to_ascii_lowercase().as_str() {
+                "no-store" => control.no_store = true,
+                "no-cache" => control.no_cache = true,
+                "private" => control.private = true,
+                "max-age" => control.max_age = seconds(),
+                "s-maxage" => control.s_maxage = seconds(),
+                _ => {}
+            }
+        }
+        control
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    #[test]
+    fn test_parse() {
+        let mut headers = HeaderMap::new();
+        headers.append(header::CACHE_CONTROL, HeaderValue::from_static("public, max-age=60"));
+        headers.append(header::CACHE_CONTROL, HeaderValue::from_static("S-MAXAGE=\"300\""));
+
+        let control = CacheControl::from_headers(&headers);
+        assert_eq!(control.max_age, Some(Duration::from_secs(60)));
+        assert_eq!(control.s_maxage, Some(Duration::from_secs(300)));
+        assert!(!control.no_store);
+
+        headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store,private"));
+        let control = CacheControl::from_headers(&headers);
+        assert!(control.no_store && control.private);
+        assert_eq!(control.max_age, None);
+    }
+}
@@ -0,0 +1,78 @@
+//! FoundationDB cache backend shared between frontdoor instances
+
+use super::{CacheBackend, CachedResponse};
+use crate::error::{FrontdoorError, Result};
+use foundationdb::Database;
+use std::sync::Arc;
+
+/// Largest key FoundationDB accepts
+const MAX_KEY_BYTES: usize = 10_000;
+
+/// Largest value FoundationDB accepts
+const MAX_VALUE_BYTES: usize = 100_000;
+
+/// Cache entries stored in FoundationDB
+///
+/// Expired entries are ignored on read and overwritten by the next store;
+/// keys outside the cache's use are never touched.
+pub struct FdbBackend {
+    db: Arc<Database>,
+    prefix: Vec<u8>,
+}
+
+impl FdbBackend {
+    /// Create a backend storing entries under `prefix`
+    pub fn new(db: Arc<Database>, prefix: impl Into<Vec<u8>>) -> Self {
+        Self {
+            db,
+            prefix: prefix.into(),
+        }
+    }
+
+    fn build_key(&self, key: &str) -> Vec<u8> {
+        let mut full = Vec::with_capacity(self.prefix.len() + key.len());
+        full.extend_from_slice(&self.prefix);
+        full.extend_from_slice(key.as_bytes());
+        full
+    }
+}
+
+#[async_trait::async_trait]
+impl CacheBackend for FdbBackend {
+    async fn get(&self, key: &str) -> Result<Option<CachedResponse>> {
+        let key = self.build_key(key);
+        if key.len() > MAX_KEY_BYTES {
+            return Ok(None);
+        }
+
+        let tx = self.db.create_trx().map_err(cache_error)?;
+        let value = tx.get(&key, true).await.map_err(cache_error)?;
+        tx.cancel();
+
+        value
+            .map(|v| serde_json::from_slice(&v).map_err(cache_error))
+            .transpose()
+    }
+
+    async fn put(&self, key: &str, response: &CachedResponse) -> Result<()> {
+        let key = self.build_key(key);
+        let value = serde_json::to_vec(response).map_err(cache_error)?;
+        if key.len() > MAX_KEY_BYTES || value.len() > MAX_VALUE_BYTES {
+            return Ok(());
+        }
+
+        let tx = self.db.create_trx().map_err(cache_error)?;
+
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))
+            .map_err(cache_error)?;
+
+        tx.set(&key, &value);
+        tx.commit().await.map_err(cache_error)?;
+        Ok(())
+    }
+}
+
+fn cache_error(e: impl std::fmt::Display) -> FrontdoorError {
+    FrontdoorError::Cache(e.to_string())
+}
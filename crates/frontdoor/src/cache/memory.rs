@@ -0,0 +1,88 @@
+//! Bounded in-memory cache storage
+
+use super::CachedResponse;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// In-memory entries, bounded by count
+///
+/// When full, expired entries are dropped first, then the entry closest to
+/// expiry.
+pub struct MemoryStore {
+    entries: Mutex<HashMap<String, CachedResponse>>,
+    max_entries: usize,
+}
+
+impl MemoryStore {
+    pub fn new(max_entries: usize) -> Self {
+        Self {
+            entries: Mutex::new(HashMap::new()),
+            max_entries,
+        }
+    }
+
+    /// Get a fresh entry
+    pub fn get(&self, key: &str, now_ms: u64) -> Option<CachedResponse> {
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.get(key).filter(|entry| entry.is_fresh(now_ms)).cloned()
+    }
+
+    /// Store an entry, evicting others if the store is full
+    pub fn put(&self, key: String, entry: CachedResponse, now_ms: u64) {
+        if self.max_entries == 0 {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        if !entries.contains_key(&key) && entries.len() >= self.max_entries {
+            entries.retain(|_, entry| entry.is_fresh(now_ms));
+        }
+        if !entries.contains_key(&key) && entries.len() >= self.max_entries {
+            let soonest = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.expires_at_ms)
+                .map(|(key, _)| key.clone());
+            if let Some(soonest) = soonest {
+                entries.remove(&soonest);
+            }
+        }
+        entries.insert(key, entry);
+    }
+
+    /// Number of stored entries, including expired ones not yet evicted
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(expires_at_ms: u64) -> CachedResponse {
+        CachedResponse {
+            status: 200,
+            headers: Vec::new(),
+            body: b"{}".to_vec(),
+            stored_at_ms: 0,
+            expires_at_ms,
+        }
+    }
+
+    #[test]
+    fn test_eviction() {
+        let store = MemoryStore::new(2);
+        store.put("a".to_string(), entry(100), 0);
+        store.put("b".to_string(), entry(50), 0);
+        store.put("c".to_string(), entry(200), 0);
+
+        assert_eq!(store.len(), 2);
+        assert!(store.get("b", 0).is_none());
+        assert!(store.get("a", 0).is_some());
+        assert!(store.get("a", 100).is_none());
+    }
+}
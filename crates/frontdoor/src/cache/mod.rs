@@ -0,0 +1,504 @@
+//! Opt-in response cache for idempotent upstream calls
+//!
+//! The cache is an axum middleware applied to individual routes, typically
+//! the GET-mapped Connect endpoints of public registry lookups:
+//!
+//! ```ignore
+//! let cache = ResponseCache::builder()
+//!     .with_default_ttl(Duration::from_secs(30))
+//!     .build();
+//!
+//! let router = Router::new().route(
+//!     "/registry.v1.RegistryService/Lookup",
+//!     get(lookup).route_layer(middleware::from_fn_with_state(cache, cache_responses)),
+//! );
+//! ```
+//!
+//! Only `GET` responses with status 200 are stored. Upstream `Cache-Control`
+//! decides whether and for how long (`s-maxage`, then `max-age`, then the
+//! configured default); `no-store`, `private` and responses setting cookies
+//! are never stored. Clients revalidate with `If-None-Match` against the
+//! upstream `ETag`. Concurrent misses for the same key are coalesced so only
+//! one request reaches the upstream.
+//!
+//! Entries live in memory and, optionally, in a shared [`CacheBackend`]
+//! such as [`FdbBackend`] so that several frontdoor instances share one cache.
+
+mod control;
+#[cfg(feature = "fdb")]
+mod fdb;
+mod memory;
+
+pub use control::CacheControl;
+#[cfg(feature = "fdb")]
+pub use fdb::FdbBackend;
+pub use memory::MemoryStore;
+
+use crate::error::Result;
+use axum::body::{Body, HttpBody};
+use axum::extract::{Request, State};
+use axum::http::{HeaderName, HeaderValue, Method, StatusCode, header};
+use axum::middleware::Next;
+use axum::response::Response;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+/// Headers that describe the connection rather than the response
+const HOP_BY_HOP_HEADERS: [HeaderName; 3] = [
+    header::CONNECTION,
+    header::TRANSFER_ENCODING,
+    header::UPGRADE,
+];
+
+/// A stored upstream response
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CachedResponse {
+    pub status: u16,
+    pub headers: Vec<(String, Vec<u8>)>,
+    pub body: Vec<u8>,
+    /// Milliseconds since the Unix epoch
+    pub stored_at_ms: u64,
+    /// Milliseconds since the Unix epoch
+    pub expires_at_ms: u64,
+}
+
+impl CachedResponse {
+    /// Whether the entry is still fresh at `now_ms`
+    pub fn is_fresh(&self, now_ms: u64) -> bool {
+        now_ms < self.expires_at_ms
+    }
+
+    /// The upstream `ETag`, if any
+    pub fn etag(&self) -> Option<&[u8]> {
+        self.headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(header::ETAG.as_str()))
+            .map(|(_, value)| value.as_slice())
+    }
+
+    /// Build the response for a client, honoring `If-None-Match`
+    fn to_response(&self, if_none_match: Option<&HeaderValue>, now_ms: u64) -> Response {
+        let not_modified = match (if_none_match, self.etag()) {
+            (Some(candidates), Some(etag)) => etag_matches(candidates.as_bytes(), etag),
+            _ => false,
+        };
+
+        let (status, body) = if not_modified {
+            (StatusCode::NOT_MODIFIED, Body::empty())
+        } else {
+            let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::OK);
+            (status, Body::from(self.body.clone()))
+        };
+
+        let mut response = Response::new(body);
+        *response.status_mut() = status;
+        let headers = response.headers_mut();
+        for (name, value) in &self.headers {
+            let (Ok(name), Ok(value)) = (
+                HeaderName::from_bytes(name.as_bytes()),
+                HeaderValue::from_bytes(value),
+            ) else {
+                continue;
+            };
+            if not_modified && name == header::CONTENT_LENGTH {
+                continue;
+            }
+            headers.append(name, value);
+        }
+
+        let age = now_ms.saturating_sub(self.stored_at_ms) / 1000;
+        headers.insert(header::AGE, HeaderValue::from(age));
+        response
+    }
+}
+
+/// Shared storage behind the in-memory cache
+#[async_trait::async_trait]
+pub trait CacheBackend: Send + Sync {
+    /// Get an entry; expired entries may be returned and are ignored
+    async fn get(&self, key: &str) -> Result<Option<CachedResponse>>;
+
+    /// Store an entry until its expiry
+    async fn put(&self, key: &str, response: &CachedResponse) -> Result<()>;
+}
+
+/// Cache settings
+#[derive(Debug, Clone)]
+pub struct CacheConfig {
+    /// Lifetime of responses without `max-age`; `None` stores only responses
+    /// that carry an explicit lifetime
+    pub default_ttl: Option<Duration>,
+    /// Upper bound on any lifetime, including upstream `max-age`
+    pub max_ttl: Duration,
+    /// Maximum number of entries held in memory
+    pub max_entries: usize,
+    /// Responses with larger or unknown-length bodies pass through uncached
+    pub max_body_bytes: usize,
+    /// Request headers that select between different cached responses
+    pub vary: Vec<HeaderName>,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            default_ttl: None,
+            max_ttl: Duration::from_secs(3600),
+            max_entries: 1024,
+            max_body_bytes: 1024 * 1024,
+            vary: vec![header::ACCEPT, header::ACCEPT_ENCODING],
+        }
+    }
+}
+
+pub struct ResponseCacheBuilder {
+    config: CacheConfig,
+    backend: Option<Arc<dyn CacheBackend>>,
+}
+
+impl ResponseCacheBuilder {
+    pub fn new() -> Self {
+        Self {
+            config: CacheConfig::default(),
+            backend: None,
+        }
+    }
+
+    pub fn with_default_ttl(mut self, ttl: Duration) -> Self {
+        self.config.default_ttl = Some(ttl);
+        self
+    }
+
+    pub fn with_max_ttl(mut self, ttl: Duration) -> Self {
+        self.config.max_ttl = ttl;
+        self
+    }
+
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.config.max_entries = max_entries;
+        self
+    }
+
+    pub fn with_max_body_bytes(mut self, max_body_bytes: usize) -> Self {
+        self.config.max_body_bytes = max_body_bytes;
+        self
+    }
+
+    pub fn with_vary(mut self, headers: Vec<HeaderName>) -> Self {
+        self.config.vary = headers;
+        self
+    }
+
+    pub fn with_backend(mut self, backend: Arc<dyn CacheBackend>) -> Self {
+        self.backend = Some(backend);
+        self
+    }
+
+    pub fn build(self) -> ResponseCache {
+        ResponseCache {
+            inner: Arc::new(Inner {
+                memory: MemoryStore::new(self.config.max_entries),
+                config: self.config,
+                backend: self.backend,
+                inflight: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+}
+
+impl Default for ResponseCacheBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Response cache shared by the routes it is applied to
+#[derive(Clone)]
+pub struct ResponseCache {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    config: CacheConfig,
+    memory: MemoryStore,
+    backend: Option<Arc<dyn CacheBackend>>,
+    /// Locks held while a miss is fetched from the upstream
+    inflight: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+}
+
+impl ResponseCache {
+    pub fn builder() -> ResponseCacheBuilder {
+        ResponseCacheBuilder::new()
+    }
+
+    /// Build the cache key of a request
+    fn key(&self, request: &Request) -> String {
+        let mut key = request
+            .uri()
+            .path_and_query()
+            .map_or("/", |pq| pq.as_str())
+            .to_string();
+        for name in &self.inner.config.vary {
+            key.push('\n');
+            key.push_str(name.as_str());
+            key.push(':');
+            for value in request.headers().get_all(name) {
+                key.push_str(&String::from_utf8_lossy(value.as_bytes()));
+            }
+        }
+        key
+    }
+
+    /// Find a fresh entry, in memory first and then in the backend
+    async fn lookup(&self, key: &str, now_ms: u64) -> Option<CachedResponse> {
+        if let Some(entry) = self.inner.memory.get(key, now_ms) {
+            return Some(entry);
+        }
+
+        let backend = self.inner.backend.as_ref()?;
+        match backend.get(key).await {
+            Ok(Some(entry)) if entry.is_fresh(now_ms) => {
+                self.inner.memory.put(key.to_string(), entry.clone(), now_ms);
+                Some(entry)
+            }
+            Ok(_) => None,
+            Err(e) => {
+                warn!("Response cache backend lookup failed: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Store a response if it is cacheable, returning it to the caller
+    async fn store(&self, key: &str, response: Response, now_ms: u64) -> Response {
+        let Some(ttl) = self.cacheable_ttl(&response) else {
+            return response;
+        };
+
+        let (parts, body) = response.into_parts();
+        let body = match axum::body::to_bytes(body, self.inner.config.max_body_bytes).await {
+            Ok(body) => body,
+            Err(e) => {
+                // The size hint promised a small body, so this is an upstream
+                // failure mid-response
+                warn!("Failed to buffer upstream response: {}", e);
+                let mut response = Response::new(Body::empty());
+                *response.status_mut() = StatusCode::BAD_GATEWAY;
+                return response;
+            }
+        };
+
+        let entry = CachedResponse {
+            status: parts.status.as_u16(),
+            headers: parts
+                .headers
+                .iter()
+                .filter(|(name, _)| !HOP_BY_HOP_HEADERS.contains(name))
+                .map(|(name, value)| (name.as_str().to_string(), value.as_bytes().to_vec()))
+                .collect(),
+            body: body.to_vec(),
+            stored_at_ms: now_ms,
+            expires_at_ms: now_ms.saturating_add(ttl.as_millis() as u64),
+        };
+
+        if let Some(backend) = &self.inner.backend
+            && let Err(e) = backend.put(key, &entry).await
+        {
+            warn!("Response cache backend store failed: {}", e);
+        }
+        self.inner.memory.put(key.to_string(), entry, now_ms);
+
+        Response::from_parts(parts, Body::from(body))
+    }
+
+    /// Lifetime of a response, `None` if it must not be stored
+    fn cacheable_ttl(&self, response: &Response) -> Option<Duration> {
+        if response.status() != StatusCode::OK || response.headers().contains_key(header::SET_COOKIE) {
+            return None;
+        }
+
+        let within_limit = response
+            .body()
+            .size_hint()
+            .exact()
+            .is_some_and(|len| len <= self.inner.config.max_body_bytes as u64);
+        if !within_limit {
+            return None;
+        }
+
+        let control = CacheControl::from_headers(response.headers());
+        if control.no_store || control.private || control.no_cache {
+            return None;
+        }
+
+        let ttl = control
+            .s_maxage
+            .or(control.max_age)
+            .or(self.inner.config.default_ttl)?;
+        Some(ttl.min(self.inner.config.max_ttl)).filter(|ttl| !ttl.is_zero())
+    }
+
+    /// Get the lock serializing upstream fetches of a key
+    fn inflight_lock(&self, key: &str) -> Arc<tokio::sync::Mutex<()>> {
+        let mut inflight = self.inner.inflight.lock().unwrap_or_else(|e| e.into_inner());
+        inflight.entry(key.to_string()).or_default().clone()
+    }
+
+    /// Drop the lock of a key once nobody waits on it anymore
+    fn release_inflight(&self, key: &str, lock: Arc<tokio::sync::Mutex<()>>) {
+        let mut inflight = self.inner.inflight.lock().unwrap_or_else(|e| e.into_inner());
+        // One reference is the map's, one is ours
+        if Arc::strong_count(&lock) <= 2 {
+            inflight.remove(key);
+        }
+    }
+}
+
+/// Middleware serving cached responses for the routes it is applied to
+pub async fn cache_responses(
+    State(cache): State<ResponseCache>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    if request.method() != Method::GET {
+        return next.run(request).await;
+    }
+
+    let control = CacheControl::from_headers(request.headers());
+    if control.no_store {
+        return next.run(request).await;
+    }
+
+    let key = cache.key(&request);
+    let if_none_match = request.headers().get(header::IF_NONE_MATCH).cloned();
+
+    // `no-cache` skips the lookup but still refreshes the entry
+    if !control.no_cache
+        && let Some(entry) = cache.lookup(&key, now_ms()).await
+    {
+        return entry.to_response(if_none_match.as_ref(), now_ms());
+    }
+
+    // Stampede protection: one request per key goes upstream, the others
+    // wait for it and are then served from the cache
+    let lock = cache.inflight_lock(&key);
+    let guard = lock.lock().await;
+
+    if !control.no_cache
+        && let Some(entry) = cache.lookup(&key, now_ms()).await
+    {
+        drop(guard);
+        cache.release_inflight(&key, lock);
+        return entry.to_response(if_none_match.as_ref(), now_ms());
+    }
+
+    // Fetch the full response so it can be stored; the conditional part of
+    // the request is answered from the stored entry
+    request.headers_mut().remove(header::IF_NONE_MATCH);
+    let response = next.run(request).await;
+    let now = now_ms();
+    let response = cache.store(&key, response, now).await;
+
+    drop(guard);
+    cache.release_inflight(&key, lock);
+
+    match cache.inner.memory.get(&key, now) {
+        Some(entry) if if_none_match.is_some() && response.status() == StatusCode::OK => {
+            entry.to_response(if_none_match.as_ref(), now)
+        }
+        _ => response,
+    }
+}
+
+/// Check an `If-None-Match` header against an entity tag, using the weak
+/// comparison required for conditional GETs
+fn etag_matches(candidates: &[u8], etag: &[u8]) -> bool {
+    let strip_weak = |tag: &[u8]| -> Vec<u8> { tag.strip_prefix(b"W/").unwrap_or(tag).to_vec() };
+    let etag = strip_weak(etag);
+
+    candidates
+        .split(|b| *b == b',')
+        .map(|candidate| candidate.trim_ascii())
+        .any(|candidate| candidate == b"*" || strip_weak(candidate) == etag)
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::get;
+    use axum::{Router, middleware};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tower::ServiceExt;
+
+    fn router(cache: ResponseCache, calls: Arc<AtomicUsize>) -> Router {
+        let lookup = move || {
+            let calls = calls.clone();
+            async move {
+                calls.fetch_add(1, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                (
+                    [(header::CACHE_CONTROL, "max-age=60"), (header::ETAG, "\"v1\"")],
+                    "{\"name\":\"Berlin\"}",
+                )
+            }
+        };
+        Router::new().route(
+            "/lookup",
+            get(lookup).route_layer(middleware::from_fn_with_state(cache, cache_responses)),
+        )
+    }
+
+    fn lookup_request() -> Request {
+        Request::get("/lookup?connect=v1&encoding=json").body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_cache_responses() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let app = router(ResponseCache::builder().build(), calls.clone());
+
+        // Concurrent misses reach the upstream once
+        let responses = futures::future::join_all(
+            (0..8).map(|_| app.clone().oneshot(lookup_request())),
+        )
+        .await;
+        for response in responses {
+            let response = response.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            assert_eq!(&body[..], b"{\"name\":\"Berlin\"}");
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        let mut request = lookup_request();
+        request
+            .headers_mut()
+            .insert(header::IF_NONE_MATCH, HeaderValue::from_static("W/\"v1\""));
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        let mut request = lookup_request();
+        request
+            .headers_mut()
+            .insert(header::CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+        app.oneshot(request).await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_etag_matches() {
+        assert!(etag_matches(br#""abc""#, br#""abc""#));
+        assert!(etag_matches(br#"W/"abc""#, br#""abc""#));
+        assert!(etag_matches(br#""x", "abc""#, br#"W/"abc""#));
+        assert!(etag_matches(b"*", br#""abc""#));
+        assert!(!etag_matches(br#""abd""#, br#""abc""#));
+    }
+}
@@ -4,6 +4,9 @@ use thiserror::Error;
 pub enum FrontdoorError {
     #[error("Missing listen address")]
     MissingListenAddress,
+
+    #[error("Response cache error: {0}")]
+    Cache(String),
 }
 
 pub type Result<T> = std::result::Result<T, FrontdoorError>;
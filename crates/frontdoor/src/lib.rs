@@ -9,6 +9,7 @@ pub use tokio_util::sync::CancellationToken;
 use tower_http::{cors::CorsLayer, trace::TraceLayer};
 use tracing::{error, info};
 
+pub mod cache;
 mod error;
 
 use crate::error::{FrontdoorError, Result};
@@ -20,4 +20,10 @@ pub enum MstError {
     NodeNotFound,
     #[error("Conflict: {0}")]
     Conflict(String),
+    #[error("Replay mismatch: expected root {expected}, got {actual}")]
+    ReplayMismatch { expected: String, actual: String },
+    #[error("Audit error: {0}")]
+    Audit(String),
+    #[error("Precondition failed for key '{key}': expected {expected:?}, found {actual:?}")]
+    PreconditionFailed { key: String, expected: Option<String>, actual: Option<String> },
 }
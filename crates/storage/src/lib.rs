@@ -4,8 +4,11 @@ mod error;
 mod mst;
 
 pub use error::MstError;
+pub use mst::audit::{current_actor, with_actor, AuditOperation, AuditRecord, Auditor, TreeAuditor};
+pub use mst::conditional::ValueHash;
 pub use mst::iterator::{MstIterator, MstIteratorTyped};
 pub use mst::node::{Node, NodeHash, B};
+pub use mst::replay::{Operation, OperationLog, RootHash};
 pub use mst::sync::{ConflictResolver, NodeFetcher, PreferLocalResolver, PreferRemoteResolver};
 pub use mst::tree::MerkleSearchTree;
 pub use mst::types::{MerkleProof, ProofNode, ReconcileResult, TreeDiff, TreeStats};
@@ -0,0 +1,287 @@
+//! Access audit hooks
+//!
+//! A tree with a registered [`Auditor`] records every key it reads, writes or
+//! deletes on behalf of a caller, together with the actor set for the current
+//! task by [`with_actor`]. Records are buffered and handed to the auditor in
+//! batches, once enough have accumulated or the oldest has waited too long.
+//! Buffered records are lost if the process stops before they are flushed,
+//! so callers should call [`MerkleSearchTree::flush_audit`] on shutdown.
+//!
+//! Internal traversals (diff, stats, proofs, reconciliation) are not
+//! audited, only the operations that hand values to or take values from a
+//! caller.
+
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::error::MstError;
+use super::tree::MerkleSearchTree;
+
+/// Number of records buffered before they are handed to the auditor
+const DEFAULT_BATCH_SIZE: usize = 100;
+
+/// Longest time a record is buffered before the next operation flushes it
+const DEFAULT_MAX_DELAY: Duration = Duration::from_secs(5);
+
+tokio::task_local! {
+	static ACTOR: String;
+}
+
+/// Run `future` with `actor` recorded as the actor of every audited access
+pub async fn with_actor<F: Future>(actor: impl Into<String>, future: F) -> F::Output {
+	ACTOR.scope(actor.into(), future).await
+}
+
+/// The actor of the current task, if one was set with [`with_actor`]
+pub fn current_actor() -> Option<String> {
+	ACTOR.try_with(|actor| actor.clone()).ok()
+}
+
+/// Kind of access to a key
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditOperation {
+	Read,
+	Write,
+	Delete,
+}
+
+/// A single audited access
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditRecord {
+	pub key: String,
+	/// Actor of the task that accessed the key, `None` when unset
+	pub actor: Option<String>,
+	pub operation: AuditOperation,
+	/// Milliseconds since the Unix epoch
+	pub timestamp_ms: u64,
+}
+
+/// Receiver of batches of audit records
+#[async_trait::async_trait]
+pub trait Auditor: Send + Sync {
+	/// Persist a batch of records
+	///
+	/// An error fails the operation that triggered the flush.
+	async fn record(&self, records: Vec<AuditRecord>) -> Result<(), MstError>;
+}
+
+/// Auditor that appends records to a separate audit tree
+///
+/// Records are keyed by timestamp, so the audit tree lists them in the order
+/// they were recorded and a time window can be read with `get_range`.
+pub struct TreeAuditor {
+	tree: tokio::sync::Mutex<MerkleSearchTree>,
+}
+
+impl TreeAuditor {
+	/// Name of the audit tree in the database
+	pub const TREE_NAME: &'static str = "audit";
+
+	/// Open the audit tree of a database
+	pub async fn open(db: foundationdb::Database) -> Result<Self, MstError> {
+		let tree = MerkleSearchTree::open_named(db, Self::TREE_NAME).await?;
+		Ok(Self::new(tree))
+	}
+
+	/// Record into `tree`
+	pub fn new(tree: MerkleSearchTree) -> Self {
+		Self { tree: tokio::sync::Mutex::new(tree) }
+	}
+
+	/// Key of a record, ordered by time and unique across writers
+	pub fn record_key(record: &AuditRecord) -> String {
+		format!("{:020}/{:016x}", record.timestamp_ms, rand::random::<u64>())
+	}
+}
+
+#[async_trait::async_trait]
+impl Auditor for TreeAuditor {
+	async fn record(&self, records: Vec<AuditRecord>) -> Result<(), MstError> {
+		let entries = records
+			.iter()
+			.map(|record| Ok::<_, MstError>((Self::record_key(record), MerkleSearchTree::encode_value(record)?)))
+			.collect::<Result<Vec<_>, MstError>>()?;
+		self.tree.lock().await.put_batch(entries).await
+	}
+}
+
+/// Auditor registered on a tree, with the records not yet handed to it
+///
+/// Clones of a tree share the hook and its buffer.
+#[derive(Clone)]
+pub(crate) struct AuditHook {
+	auditor: Arc<dyn Auditor>,
+	pending: Arc<tokio::sync::Mutex<Pending>>,
+	batch_size: usize,
+	max_delay: Duration,
+}
+
+#[derive(Default)]
+struct Pending {
+	records: Vec<AuditRecord>,
+	oldest: Option<Instant>,
+}
+
+impl MerkleSearchTree {
+	/// Record accesses to this tree with `auditor`
+	pub fn with_auditor(mut self, auditor: Arc<dyn Auditor>) -> Self {
+		self.audit = Some(AuditHook {
+			auditor,
+			pending: Arc::new(tokio::sync::Mutex::new(Pending::default())),
+			batch_size: DEFAULT_BATCH_SIZE,
+			max_delay: DEFAULT_MAX_DELAY,
+		});
+		self
+	}
+
+	/// Hand records to the auditor once `batch_size` have accumulated or the
+	/// oldest has waited `max_delay`
+	///
+	/// Has no effect without an auditor.
+	pub fn with_audit_batching(mut self, batch_size: usize, max_delay: Duration) -> Self {
+		if let Some(hook) = &mut self.audit {
+			hook.batch_size = batch_size.max(1);
+			hook.max_delay = max_delay;
+		}
+		self
+	}
+
+	/// Hand all buffered audit records to the auditor
+	pub async fn flush_audit(&self) -> Result<(), MstError> {
+		let Some(hook) = &self.audit else { return Ok(()) };
+		let records = {
+			let mut pending = hook.pending.lock().await;
+			pending.oldest = None;
+			std::mem::take(&mut pending.records)
+		};
+		if records.is_empty() {
+			return Ok(());
+		}
+		hook.auditor.record(records).await
+	}
+
+	/// Record an access to `keys` by the current actor
+	pub(crate) async fn audit<I>(&self, operation: AuditOperation, keys: I) -> Result<(), MstError>
+	where
+		I: IntoIterator,
+		I::Item: Into<String>,
+	{
+		let Some(hook) = &self.audit else { return Ok(()) };
+		let actor = current_actor();
+		let timestamp_ms = SystemTime::now()
+			.duration_since(UNIX_EPOCH)
+			.map(|d| d.as_millis() as u64)
+			.unwrap_or_default();
+
+		let due = {
+			let mut pending = hook.pending.lock().await;
+			for key in keys {
+				pending.records.push(AuditRecord {
+					key: key.into(),
+					actor: actor.clone(),
+					operation,
+					timestamp_ms,
+				});
+			}
+			if !pending.records.is_empty() && pending.oldest.is_none() {
+				pending.oldest = Some(Instant::now());
+			}
+			pending.records.len() >= hook.batch_size
+				|| pending.oldest.is_some_and(|oldest| oldest.elapsed() >= hook.max_delay)
+		};
+
+		if due {
+			self.flush_audit().await?;
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mst::{test_db, test_tree_name};
+
+	/// Auditor keeping the batches it was handed
+	#[derive(Default)]
+	struct Recorder {
+		batches: std::sync::Mutex<Vec<Vec<AuditRecord>>>,
+	}
+
+	#[async_trait::async_trait]
+	impl Auditor for Recorder {
+		async fn record(&self, records: Vec<AuditRecord>) -> Result<(), MstError> {
+			self.batches.lock().unwrap().push(records);
+			Ok(())
+		}
+	}
+
+	fn accesses(batch: &[AuditRecord]) -> Vec<(&str, Option<&str>, AuditOperation)> {
+		batch
+			.iter()
+			.map(|record| (record.key.as_str(), record.actor.as_deref(), record.operation))
+			.collect()
+	}
+
+	#[tokio::test]
+	#[ignore = "needs a FoundationDB cluster"]
+	async fn test_accesses_are_audited_in_batches() {
+		let recorder = Arc::new(Recorder::default());
+		let mut tree = MerkleSearchTree::open_named(test_db(), &test_tree_name())
+			.await
+			.unwrap()
+			.with_auditor(recorder.clone())
+			.with_audit_batching(3, Duration::from_secs(3600));
+
+		with_actor("clerk-1", async {
+			tree.put("citizen/1".to_string(), b"record".to_vec()).await.unwrap();
+			tree.get("citizen/1").await.unwrap();
+			assert!(recorder.batches.lock().unwrap().is_empty());
+			tree.delete("citizen/1").await.unwrap();
+		})
+		.await;
+		tree.get("citizen/2").await.unwrap();
+
+		{
+			let batches = recorder.batches.lock().unwrap();
+			assert_eq!(batches.len(), 1);
+			assert_eq!(
+				accesses(&batches[0]),
+				vec![
+					("citizen/1", Some("clerk-1"), AuditOperation::Write),
+					("citizen/1", Some("clerk-1"), AuditOperation::Read),
+					("citizen/1", Some("clerk-1"), AuditOperation::Delete),
+				]
+			);
+		}
+
+		tree.flush_audit().await.unwrap();
+		let batches = recorder.batches.lock().unwrap();
+		assert_eq!(accesses(&batches[1]), vec![("citizen/2", None, AuditOperation::Read)]);
+	}
+
+	#[tokio::test]
+	#[ignore = "needs a FoundationDB cluster"]
+	async fn test_tree_auditor_appends_records() {
+		let name = test_tree_name();
+		let auditor = TreeAuditor::new(MerkleSearchTree::open_named(test_db(), &name).await.unwrap());
+		let records = vec![
+			AuditRecord { key: "citizen/1".to_string(), actor: Some("clerk-1".to_string()), operation: AuditOperation::Read, timestamp_ms: 1 },
+			AuditRecord { key: "citizen/1".to_string(), actor: None, operation: AuditOperation::Write, timestamp_ms: 2 },
+		];
+		auditor.record(records.clone()).await.unwrap();
+
+		let audit_tree = MerkleSearchTree::open_named(test_db(), &name).await.unwrap();
+		let stored = audit_tree
+			.get_range_typed::<AuditRecord>("0", "9")
+			.await
+			.unwrap()
+			.into_iter()
+			.map(|(_, record)| record)
+			.collect::<Vec<_>>();
+		assert_eq!(stored, records);
+	}
+}
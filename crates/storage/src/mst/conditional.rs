@@ -0,0 +1,132 @@
+//! Conditional writes for optimistic concurrency
+//!
+//! Multiple services can run read-modify-write cycles against the same tree
+//! without a global lock: read a value together with its hash, compute the
+//! update, and write it back with `upsert_if`. The write only lands if the
+//! stored value still has the hash that was read; otherwise the caller gets
+//! `MstError::PreconditionFailed` and can re-read and retry.
+
+use crate::error::MstError;
+use super::audit::AuditOperation;
+use super::node::{hash_data, NodeHash};
+use super::tree::MerkleSearchTree;
+
+/// Number of times a conditional write is retried on FDB commit conflicts
+const MAX_COMMIT_ATTEMPTS: usize = 5;
+
+/// Hash identifying a stored value, used as its version
+pub type ValueHash = NodeHash;
+
+impl MerkleSearchTree {
+	/// Compute the version hash of a raw value
+	pub fn value_hash(value: &[u8]) -> ValueHash {
+		hash_data(value)
+	}
+
+	/// Get a value together with its version hash
+	pub async fn get_versioned(&self, key: &str) -> Result<Option<(Vec<u8>, ValueHash)>, MstError> {
+		Ok(self.get(key).await?.map(|value| {
+			let hash = Self::value_hash(&value);
+			(value, hash)
+		}))
+	}
+
+	/// Insert or update a key only if its current value matches `expected`
+	///
+	/// `expected` is the version hash of the value the caller last read, or
+	/// `None` if the key must not exist yet. Returns the version hash of the
+	/// newly written value. The check and the write happen in one FDB
+	/// transaction, so a concurrent writer either fails the precondition or
+	/// causes a commit conflict that is retried against the new state.
+	pub async fn upsert_if(&mut self, key: String, expected: Option<ValueHash>, value: Vec<u8>) -> Result<ValueHash, MstError> {
+		let new_hash = Self::value_hash(&value);
+
+		for attempt in 1..=MAX_COMMIT_ATTEMPTS {
+			let tx = self.db.create_trx()?;
+			// Reading the root through the transaction adds it to the read
+			// conflict set, so any concurrent write to the tree aborts this commit
+			let current_root = self.fdb_get_root_with_tx(&tx).await?;
+			let actual = self.get_at(current_root, &key).await?.map(|v| Self::value_hash(&v));
+
+			if actual != expected {
+				tx.cancel();
+				return Err(MstError::PreconditionFailed {
+					key,
+					expected: expected.map(hex::encode),
+					actual: actual.map(hex::encode),
+				});
+			}
+
+			let key_layer = Self::compute_layer(&key);
+			let (new_layer, new_root) = self.insert_rec(&tx, current_root, key.clone(), value.clone(), key_layer).await?;
+			self.fdb_set_root(&tx, new_layer, new_root).await?;
+
+			match tx.commit().await {
+				Ok(_) => {
+					self.root = Some((new_layer, new_root));
+					self.audit(AuditOperation::Write, [key]).await?;
+					return Ok(new_hash);
+				}
+				Err(e) if e.is_retryable() && attempt < MAX_COMMIT_ATTEMPTS => continue,
+				Err(e) => return Err(e.into()),
+			}
+		}
+
+		Err(MstError::Conflict(format!("conditional write to '{}' kept conflicting", key)))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::mst::{test_db, test_tree_name};
+
+	fn assert_precondition_failed(result: Result<ValueHash, MstError>) {
+		assert!(matches!(result, Err(MstError::PreconditionFailed { .. })), "{:?}", result);
+	}
+
+	#[tokio::test]
+	#[ignore = "needs a FoundationDB cluster"]
+	async fn test_upsert_if_checks_the_stored_version() {
+		let mut tree = MerkleSearchTree::open_named(test_db(), &test_tree_name()).await.unwrap();
+
+		let first = tree.upsert_if("record".to_string(), None, b"v1".to_vec()).await.unwrap();
+		assert_eq!(first, MerkleSearchTree::value_hash(b"v1"));
+		// The key exists now
+		assert_precondition_failed(tree.upsert_if("record".to_string(), None, b"v2".to_vec()).await);
+
+		let second = tree.upsert_if("record".to_string(), Some(first), b"v2".to_vec()).await.unwrap();
+		// A write based on the first version is stale
+		assert_precondition_failed(tree.upsert_if("record".to_string(), Some(first), b"v3".to_vec()).await);
+		assert_eq!(tree.get_versioned("record").await.unwrap(), Some((b"v2".to_vec(), second)));
+	}
+
+	#[tokio::test]
+	#[ignore = "needs a FoundationDB cluster"]
+	async fn test_concurrent_writers_do_not_lose_updates() {
+		let name = test_tree_name();
+		let mut a = MerkleSearchTree::open_named(test_db(), &name).await.unwrap();
+		let mut b = MerkleSearchTree::open_named(test_db(), &name).await.unwrap();
+		let base = a.upsert_if("counter".to_string(), None, b"0".to_vec()).await.unwrap();
+
+		// Both read the same version; the second writer has to re-read
+		let (_, read_by_a) = a.get_versioned("counter").await.unwrap().unwrap();
+		let (_, read_by_b) = b.get_versioned("counter").await.unwrap().unwrap();
+		assert_eq!(read_by_a, base);
+		assert_eq!(read_by_b, base);
+
+		let written = b.upsert_if("counter".to_string(), Some(read_by_b), b"1".to_vec()).await.unwrap();
+		match a.upsert_if("counter".to_string(), Some(read_by_a), b"1".to_vec()).await {
+			Err(MstError::PreconditionFailed { key, expected, actual }) => {
+				assert_eq!(key, "counter");
+				assert_eq!(expected, Some(hex::encode(base)));
+				assert_eq!(actual, Some(hex::encode(written)));
+			}
+			other => panic!("expected a failed precondition, got {:?}", other),
+		}
+
+		let (value, version) = a.get_versioned("counter").await.unwrap().unwrap();
+		a.upsert_if("counter".to_string(), Some(version), [value, b"+1".to_vec()].concat()).await.unwrap();
+		assert_eq!(b.get("counter").await.unwrap(), Some(b"1+1".to_vec()));
+	}
+}
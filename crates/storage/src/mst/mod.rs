@@ -1,7 +1,28 @@
+pub mod audit;
+pub mod conditional;
 pub mod iterator;
 pub mod node;
 pub mod operations;
 pub mod proof;
+pub mod replay;
 pub mod sync;
 pub mod tree;
 pub mod types;
+
+/// Connect to the FoundationDB cluster for a test, starting the network on
+/// first use
+///
+/// The network can only be started once per process, so it is left running
+/// until the test binary exits.
+#[cfg(test)]
+pub(crate) fn test_db() -> foundationdb::Database {
+	static NETWORK: std::sync::Once = std::sync::Once::new();
+	NETWORK.call_once(|| std::mem::forget(unsafe { foundationdb::boot() }));
+	foundationdb::Database::default().unwrap()
+}
+
+/// Name of a tree no other test run uses
+#[cfg(test)]
+pub(crate) fn test_tree_name() -> String {
+	format!("test/{:016x}", rand::random::<u64>())
+}
@@ -3,6 +3,7 @@
 use foundationdb::Transaction;
 
 use crate::error::MstError;
+use super::audit::AuditOperation;
 use super::iterator::{MstIterator, MstIteratorTyped};
 use super::node::{from_bytebuf, to_bytebuf, Node, NodeHash, B};
 use super::tree::MerkleSearchTree;
@@ -21,11 +22,11 @@ impl MerkleSearchTree {
 		let tx = self.db.create_trx()?;
 		let current_root = self.fdb_get_root_with_tx(&tx).await?;
 		let key_layer = Self::compute_layer(&key);
-		let (new_layer, new_root) = self.insert_rec(&tx, current_root, key, value, key_layer).await?;
+		let (new_layer, new_root) = self.insert_rec(&tx, current_root, key.clone(), value, key_layer).await?;
 		self.fdb_set_root(&tx, new_layer, new_root).await?;
 		tx.commit().await?;
 		self.root = Some((new_layer, new_root));
-		Ok(())
+		self.audit(AuditOperation::Write, [key]).await
 	}
 
 	#[async_recursion::async_recursion]
@@ -153,8 +154,16 @@ impl MerkleSearchTree {
 	///
 	/// Returns the raw DAG-CBOR encoded bytes, or None if key doesn't exist.
 	pub async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, MstError> {
+		let root = self.fdb_get_root().await?;
+		let value = self.get_at(root, key).await?;
+		self.audit(AuditOperation::Read, [key]).await?;
+		Ok(value)
+	}
+
+	/// Look up a key in the tree rooted at `root`
+	pub(crate) async fn get_at(&self, root: Option<(u32, NodeHash)>, key: &str) -> Result<Option<Vec<u8>>, MstError> {
 		// Naive traversal: if root is a leaf and matches, return. If inner, linear scan of separators.
-		let Some((mut layer, root_hash)) = self.fdb_get_root().await? else { return Ok(None) };
+		let Some((mut layer, root_hash)) = root else { return Ok(None) };
 		let mut node = match self.fdb_get_node(layer, root_hash).await? { Some(n) => n, None => return Ok(None) };
 		loop {
 			match node {
@@ -211,6 +220,7 @@ impl MerkleSearchTree {
 			}
 		}
 		results.sort_by(|a, b| a.0.cmp(&b.0));
+		self.audit(AuditOperation::Read, results.iter().map(|(key, _)| key.as_str())).await?;
 		Ok(results)
 	}
 
@@ -225,12 +235,12 @@ impl MerkleSearchTree {
 			} else {
 				// Tree became empty
 				// Clear root key
-				tx.clear(&Self::key_root());
+				tx.clear(self.key_root());
 				self.root = None;
 			}
 			tx.commit().await?;
 		}
-		Ok(())
+		self.audit(AuditOperation::Delete, [key]).await
 	}
 
 	#[async_recursion::async_recursion]
@@ -438,7 +448,7 @@ impl MerkleSearchTree {
 			}
 		}
 
-		Ok(())
+		self.audit(AuditOperation::Write, entries.iter().map(|(key, _)| key.as_str())).await
 	}
 
 	/// Batch delete multiple keys
@@ -467,14 +477,14 @@ impl MerkleSearchTree {
 				self.fdb_set_root(&tx, layer, hash).await?;
 				self.root = Some((layer, hash));
 			} else {
-				tx.clear(&Self::key_root());
+				tx.clear(self.key_root());
 				self.root = None;
 			}
 
 			tx.commit().await?;
 		}
 		
-		Ok(())
+		self.audit(AuditOperation::Delete, keys).await
 	}
 
 	/// Iterate over all key-value pairs in order
@@ -489,6 +499,7 @@ impl MerkleSearchTree {
 		}
 
 		entries.sort_by(|a, b| a.0.cmp(&b.0));
+		self.audit(AuditOperation::Read, entries.iter().map(|(key, _)| key.as_str())).await?;
 
 		Ok(MstIterator {
 			entries,
@@ -555,6 +566,7 @@ impl MerkleSearchTree {
 		}
 
 		entries.sort_by(|a, b| a.0.cmp(&b.0));
+		self.audit(AuditOperation::Read, entries.iter().map(|(key, _)| key.as_str())).await?;
 
 		Ok(MstIteratorTyped {
 			entries,
@@ -0,0 +1,160 @@
+//! Deterministic replay of operation logs
+//!
+//! A seeded operation log is replayed against an empty tree to produce a final
+//! root hash. Because the tree is content-addressed, the same log must always
+//! yield the same root; comparing it against a recorded hash catches structural
+//! incompatibilities between versions and non-determinism regressions.
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::MstError;
+use super::node::NodeHash;
+use super::tree::MerkleSearchTree;
+
+/// Root hash produced by replaying a log (`None` for an empty tree)
+pub type RootHash = Option<NodeHash>;
+
+/// A single recorded tree mutation
+///
+/// Values are opaque bytes, stored as given; generated logs use the
+/// big-endian bytes of a random `u64`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Operation {
+	Put { key: String, value: Vec<u8> },
+	Delete { key: String },
+}
+
+/// An ordered log of tree mutations, optionally derived from a seed
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OperationLog {
+	pub seed: Option<u64>,
+	pub operations: Vec<Operation>,
+}
+
+impl OperationLog {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Generate a pseudo-random log from a seed
+	///
+	/// Keys are drawn from `key_space` distinct keys so that later operations
+	/// overwrite and delete earlier ones. The generator is a fixed SplitMix64,
+	/// so a given `(seed, count, key_space)` produces the same log on every
+	/// platform and crate version.
+	pub fn generate(seed: u64, count: usize, key_space: usize) -> Self {
+		let mut rng = SplitMix64(seed);
+		let key_space = key_space.max(1) as u64;
+		let mut operations = Vec::with_capacity(count);
+
+		for _ in 0..count {
+			let key = format!("key/{:08}", rng.next() % key_space);
+			// Roughly one in four operations is a delete
+			if rng.next() % 4 == 0 {
+				operations.push(Operation::Delete { key });
+			} else {
+				let value = rng.next().to_be_bytes().to_vec();
+				operations.push(Operation::Put { key, value });
+			}
+		}
+
+		Self { seed: Some(seed), operations }
+	}
+
+	/// Append a put operation
+	pub fn put(mut self, key: impl Into<String>, value: Vec<u8>) -> Self {
+		self.operations.push(Operation::Put { key: key.into(), value });
+		self
+	}
+
+	/// Append a delete operation
+	pub fn delete(mut self, key: impl Into<String>) -> Self {
+		self.operations.push(Operation::Delete { key: key.into() });
+		self
+	}
+
+	pub fn len(&self) -> usize {
+		self.operations.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.operations.is_empty()
+	}
+}
+
+impl MerkleSearchTree {
+	/// Replay an operation log and return the resulting root hash
+	///
+	/// The tree must be empty; replaying on top of existing data would make the
+	/// result depend on state outside the log.
+	pub async fn replay(&mut self, log: &OperationLog) -> Result<RootHash, MstError> {
+		if self.fdb_get_root().await?.is_some() {
+			return Err(MstError::Conflict("replay requires an empty tree".to_string()));
+		}
+
+		for op in &log.operations {
+			match op {
+				Operation::Put { key, value } => self.put(key.clone(), value.clone()).await?,
+				Operation::Delete { key } => self.delete(key).await?,
+			}
+		}
+
+		Ok(self.root_hash())
+	}
+
+	/// Replay an operation log and check the result against a recorded root hash
+	pub async fn replay_and_verify(&mut self, log: &OperationLog, expected: RootHash) -> Result<(), MstError> {
+		let actual = self.replay(log).await?;
+		if actual != expected {
+			return Err(MstError::ReplayMismatch {
+				expected: expected.map(hex::encode).unwrap_or_else(|| "<empty>".to_string()),
+				actual: actual.map(hex::encode).unwrap_or_else(|| "<empty>".to_string()),
+			});
+		}
+		Ok(())
+	}
+}
+
+/// Minimal SplitMix64 generator with a stable output sequence
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+	fn next(&mut self) -> u64 {
+		self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+		let mut z = self.0;
+		z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+		z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+		z ^ (z >> 31)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn test_generate_is_stable() {
+		let log = OperationLog::generate(42, 3, 16);
+		assert_eq!(log, OperationLog::generate(42, 3, 16));
+		assert_eq!(log.seed, Some(42));
+		assert_eq!(
+			log.operations,
+			vec![
+				Operation::Put { key: "key/00000005".to_string(), value: vec![71, 82, 103, 87, 19, 15, 159, 82] },
+				Operation::Put { key: "key/00000004".to_string(), value: vec![222, 68, 49, 250, 60, 128, 219, 6] },
+				Operation::Delete { key: "key/00000013".to_string() },
+			]
+		);
+	}
+
+	#[test]
+	fn test_log_round_trips() {
+		let log = OperationLog::generate(7, 64, 8).put("key/extra", vec![0, 1, 2]).delete("key/extra");
+
+		let json = serde_json::to_vec(&log).unwrap();
+		assert_eq!(serde_json::from_slice::<OperationLog>(&json).unwrap(), log);
+
+		let cbor = serde_ipld_dagcbor::to_vec(&log).unwrap();
+		assert_eq!(serde_ipld_dagcbor::from_slice::<OperationLog>(&cbor).unwrap(), log);
+	}
+}
@@ -10,6 +10,7 @@ use std::collections::HashMap;
 use std::sync::Arc;
 
 use crate::error::MstError;
+use super::audit::AuditHook;
 use super::node::{hash_data, Node, NodeHash};
 
 /// In-memory cache for nodes to reduce FDB reads
@@ -23,11 +24,16 @@ type NodeCache = Arc<tokio::sync::RwLock<HashMap<(u32, NodeHash), Node>>>;
 /// - Nodes are stored by (layer, hash) in FDB
 /// - Tree structure enables efficient sync via hash comparison
 /// - Values are stored as raw bytes (DAG-CBOR encoded)
+///
+/// Several trees can share a database: nodes are content-addressed, so only
+/// the root is kept per tree name.
 #[derive(Clone)]
 pub struct MerkleSearchTree {
     pub(crate) db: Arc<Database>,
 	pub(crate) root: Option<(u32, NodeHash)>,
     pub(crate) cache: NodeCache,
+	pub(crate) root_key: Vec<u8>,
+	pub(crate) audit: Option<AuditHook>,
 }
 
 impl MerkleSearchTree {
@@ -36,11 +42,22 @@ impl MerkleSearchTree {
 	}
 
 	pub async fn open(db: Database) -> Result<Self, MstError> {
+		Self::open_with_root(db, b"mstr".to_vec()).await
+	}
+
+	/// Open the tree called `name`, kept apart from the default tree
+	pub async fn open_named(db: Database, name: &str) -> Result<Self, MstError> {
+		let mut root_key = b"mstr/".to_vec();
+		root_key.extend_from_slice(name.as_bytes());
+		Self::open_with_root(db, root_key).await
+	}
+
+	async fn open_with_root(db: Database, root_key: Vec<u8>) -> Result<Self, MstError> {
 		let db = Arc::new(db);
 		let cache = Arc::new(tokio::sync::RwLock::new(HashMap::new()));
-		let tmp = Self { db: db.clone(), root: None, cache: cache.clone() };
-		let root = tmp.fdb_get_root().await?;
-		Ok(Self { db, root, cache })
+		let mut tree = Self { db, root: None, cache, root_key, audit: None };
+		tree.root = tree.fdb_get_root().await?;
+		Ok(tree)
 	}
 
 	/// Get the root hash of the tree
@@ -56,8 +73,8 @@ impl MerkleSearchTree {
 
 	// ========== FDB operations ==========
 
-	pub(crate) fn key_root() -> Vec<u8> {
-		b"mstr".to_vec()
+	pub(crate) fn key_root(&self) -> &[u8] {
+		&self.root_key
 	}
 
 	pub(crate) fn key_node(layer: u32, hash: NodeHash) -> Vec<u8> {
@@ -77,7 +94,7 @@ impl MerkleSearchTree {
 	}
 
 	pub(crate) async fn fdb_get_root_with_tx(&self, tx: &Transaction) -> Result<Option<(u32, NodeHash)>, MstError> {
-		if let Some(bytes) = tx.get(&Self::key_root(), false).await? {
+		if let Some(bytes) = tx.get(self.key_root(), false).await? {
 			let data = bytes.as_ref();
 			if data.len() != 4 + 32 { return Ok(None); }
 			let mut layer_bytes = [0u8; 4];
@@ -95,7 +112,7 @@ impl MerkleSearchTree {
 		let mut v = Vec::with_capacity(4 + 32);
 		v.extend_from_slice(&layer.to_be_bytes());
 		v.extend_from_slice(&hash);
-		tx.set(&Self::key_root(), &v);
+		tx.set(self.key_root(), &v);
 		Ok(())
 	}
 
@@ -154,7 +154,7 @@ async fn register_workflow(engine: Arc<WorkflowEngine>) -> Result<WorkflowId, Bo
                     "#
                     .to_vec(),
                     timeout_ms: 5000,
-                    retry_policy: None,
+                    ..Default::default()
                 }))
                 .add_transition(Transition::new("next", "processing")),
         )
@@ -178,7 +178,7 @@ async fn register_workflow(engine: Arc<WorkflowEngine>) -> Result<WorkflowId, Bo
                     "#
                     .to_vec(),
                     timeout_ms: 5000,
-                    retry_policy: None,
+                    ..Default::default()
                 }))
                 .add_transition(Transition::new("done", "end")),
         )
@@ -194,6 +194,13 @@ async fn register_workflow(engine: Arc<WorkflowEngine>) -> Result<WorkflowId, Bo
         description: Some("A demonstration workflow with greeting and data processing".to_string()),
         state_machine,
         created_at: chrono::Utc::now(),
+        subscriptions: Vec::new(),
+        timeout: None,
+        state_timeouts: Default::default(),
+        limits: Default::default(),
+        scheduling: Default::default(),
+        indexes: Vec::new(),
+        retention: None,
     };
 
     let workflow_id = engine.register_workflow(workflow_def).await?;
@@ -0,0 +1,240 @@
+//! Access control for operations on workflow instances
+//!
+//! The engine does not authenticate callers itself. Whoever fronts it (the
+//! frontdoor, an admin tool) resolves the caller into a [`Principal`], and an
+//! [`AccessPolicy`] decides which [`InstanceAction`]s that principal may
+//! perform on a given instance.
+//!
+//! RPC methods can be guarded as well: an [`RpcAuthorizer`] registered for a
+//! method sees the headers and request message of every call to it, e.g. to
+//! restrict `RegisterWorker` and `CompleteTask` to authenticated workers.
+//! Calls naming a tenant are served for it only once a [`TenantAuthorizer`]
+//! accepted the tenant for the caller.
+
+use crate::types::{WorkflowId, WorkflowInstance};
+use async_trait::async_trait;
+use axum::http::HeaderMap;
+use degov_crypto::DidKey;
+use std::collections::{HashMap, HashSet};
+
+/// An authenticated caller
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Principal {
+    pub id: String,
+    pub roles: HashSet<String>,
+}
+
+impl Principal {
+    pub fn new(id: impl Into<String>) -> Self {
+        Self {
+            id: id.into(),
+            roles: HashSet::new(),
+        }
+    }
+
+    /// Add a role
+    pub fn with_role(mut self, role: impl Into<String>) -> Self {
+        self.roles.insert(role.into());
+        self
+    }
+
+    /// Check whether the principal holds a role
+    pub fn has_role(&self, role: &str) -> bool {
+        self.roles.contains(role)
+    }
+}
+
+/// Operation on a workflow instance subject to access control
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum InstanceAction {
+    /// Read comments and attachments
+    ReadAnnotations,
+    /// Add a comment
+    Comment,
+    /// Attach a file reference
+    Attach,
+}
+
+/// Decides whether a principal may perform an action on an instance
+pub trait AccessPolicy: Send + Sync {
+    fn is_allowed(&self, principal: &Principal, action: InstanceAction, instance: &WorkflowInstance) -> bool;
+}
+
+/// Policy allowing every action, the engine default
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AllowAll;
+
+impl AccessPolicy for AllowAll {
+    fn is_allowed(&self, _principal: &Principal, _action: InstanceAction, _instance: &WorkflowInstance) -> bool {
+        true
+    }
+}
+
+/// Policy granting actions to roles
+///
+/// Grants apply to all instances or to the instances of one workflow
+/// definition. Actions without any grant are denied.
+#[derive(Debug, Clone, Default)]
+pub struct RolePolicy {
+    grants: HashMap<(Option<WorkflowId>, InstanceAction), HashSet<String>>,
+}
+
+impl RolePolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allow a role to perform an action on all instances
+    pub fn allow(mut self, role: impl Into<String>, action: InstanceAction) -> Self {
+        self.grants.entry((None, action)).or_default().insert(role.into());
+        self
+    }
+
+    /// Allow a role to perform an action on the instances of one definition
+    pub fn allow_for(mut self, definition_id: WorkflowId, role: impl Into<String>, action: InstanceAction) -> Self {
+        self.grants
+            .entry((Some(definition_id), action))
+            .or_default()
+            .insert(role.into());
+        self
+    }
+}
+
+impl AccessPolicy for RolePolicy {
+    fn is_allowed(&self, principal: &Principal, action: InstanceAction, instance: &WorkflowInstance) -> bool {
+        [None, Some(instance.definition_id)]
+            .into_iter()
+            .filter_map(|scope| self.grants.get(&(scope, action)))
+            .flatten()
+            .any(|role| principal.has_role(role))
+    }
+}
+
+/// Encoding of an RPC request message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RpcCodec {
+    Proto,
+    Json,
+}
+
+/// An RPC call awaiting authorization
+#[derive(Debug, Clone, Copy)]
+pub struct RpcCall<'a> {
+    /// Method name, e.g. `"RegisterWorker"`
+    pub method: &'a str,
+    pub headers: &'a HeaderMap,
+    /// Request message, encoded as `codec` says; messages can be decoded
+    /// with types generated from `proto/workflow.proto`
+    pub message: &'a [u8],
+    pub codec: RpcCodec,
+}
+
+/// Check deciding whether an RPC call may proceed
+///
+/// Calls that are refused fail with `permission_denied` (gRPC status 7),
+/// whichever protocol they were made in.
+#[async_trait]
+pub trait RpcAuthorizer: Send + Sync {
+    /// Allow the call, or refuse it with the reason returned to the caller
+    async fn authorize(&self, call: &RpcCall<'_>) -> Result<(), String>;
+}
+
+/// Check deciding whether a call may act for the tenant it names
+///
+/// The tenant is named by the caller, so an authorizer binds it to what the
+/// fronting proxy authenticated, e.g. a principal header it sets. Calls
+/// naming a tenant are refused with `permission_denied` while the engine has
+/// no authorizer.
+#[async_trait]
+pub trait TenantAuthorizer: Send + Sync {
+    /// Allow the call with `headers` to act for `tenant`, or refuse it with
+    /// the reason returned to the caller
+    async fn authorize(&self, tenant: &DidKey, headers: &HeaderMap) -> Result<(), String>;
+}
+
+/// Authorizer admitting a fixed set of tenants to every caller
+///
+/// Only suitable when whoever fronts the engine already restricts the
+/// tenant header to the caller's own tenant.
+#[derive(Debug, Clone, Default)]
+pub struct TenantAllowlist {
+    tenants: Vec<DidKey>,
+}
+
+impl TenantAllowlist {
+    pub fn new(tenants: impl IntoIterator<Item = DidKey>) -> Self {
+        Self {
+            tenants: tenants.into_iter().collect(),
+        }
+    }
+}
+
+#[async_trait]
+impl TenantAuthorizer for TenantAllowlist {
+    async fn authorize(&self, tenant: &DidKey, _headers: &HeaderMap) -> Result<(), String> {
+        if self.tenants.contains(tenant) {
+            Ok(())
+        } else {
+            Err(format!("unknown tenant {}", tenant))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::WorkflowStatus;
+    use chrono::Utc;
+
+    fn instance(definition_id: WorkflowId) -> WorkflowInstance {
+        WorkflowInstance {
+            id: WorkflowId::new(),
+            definition_id,
+            current_state: "review".to_string(),
+            context: serde_json::json!({}),
+            status: WorkflowStatus::Running,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            completed_at: None,
+            parent: None,
+            awaiting_children: Vec::new(),
+            compensation: None,
+            branches: Vec::new(),
+            awaited_tasks: Vec::new(),
+            idempotency_key: None,
+            state_entry: 0,
+            paused_at: None,
+            held_tasks: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_role_policy() {
+        let permits = WorkflowId::new();
+        let policy = RolePolicy::new()
+            .allow("clerk", InstanceAction::ReadAnnotations)
+            .allow("clerk", InstanceAction::Comment)
+            .allow_for(permits, "building-authority", InstanceAction::Attach);
+
+        let clerk = Principal::new("clerk-1").with_role("clerk");
+        let authority = Principal::new("ba-1").with_role("clerk").with_role("building-authority");
+        let permit = instance(permits);
+        let other = instance(WorkflowId::new());
+
+        assert!(policy.is_allowed(&clerk, InstanceAction::Comment, &permit));
+        assert!(!policy.is_allowed(&clerk, InstanceAction::Attach, &permit));
+        assert!(policy.is_allowed(&authority, InstanceAction::Attach, &permit));
+        assert!(!policy.is_allowed(&authority, InstanceAction::Attach, &other));
+        assert!(!policy.is_allowed(&Principal::new("anonymous"), InstanceAction::ReadAnnotations, &other));
+    }
+
+    #[tokio::test]
+    async fn test_tenant_allowlist() {
+        let known = degov_crypto::StackKey::from_seed([1; 32]).did();
+        let unknown = degov_crypto::StackKey::from_seed([2; 32]).did();
+        let allowlist = TenantAllowlist::new([known]);
+
+        assert_eq!(allowlist.authorize(&known, &HeaderMap::new()).await, Ok(()));
+        assert!(allowlist.authorize(&unknown, &HeaderMap::new()).await.is_err());
+    }
+}
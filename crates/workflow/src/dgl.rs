@@ -0,0 +1,187 @@
+//! Workflow definitions written in DGL
+//!
+//! A DGL document of kind `Workflow` declares states, the tasks they run on
+//! entry and the transitions between them:
+//!
+//! ```text
+//! id "de.berlin/issue-card"
+//!
+//! definition {
+//!     kind "Workflow"
+//!     workflow {
+//!         states {
+//!             state "review" {
+//!                 task "verify" code="..." result="$.steps.verify" on-complete="verified"
+//!             }
+//!             state "issued"
+//!         }
+//!         transitions {
+//!             transition "verified" from="review" to="issued" guard="steps.verify.ok"
+//!         }
+//!     }
+//! }
+//! ```
+//!
+//! Transitions are named after the event that triggers them. Workflows start
+//! in the state of type `initial`, or else in the first declared state.
+
+use crate::error::{WorkflowError, WorkflowResult};
+use crate::state_machine::{Action, Guard, State, StateMachine, Transition};
+use crate::types::{RuntimeType, TaskDefinition, WorkflowDefinition, WorkflowId};
+use chrono::Utc;
+use dgv_dgl::Parser;
+use dgv_dgl::v1::{WorkflowGraph, WorkflowTask};
+use std::collections::HashMap;
+
+/// Timeout of tasks that do not set `timeout-ms`
+const DEFAULT_TASK_TIMEOUT_MS: u64 = 30_000;
+
+/// Parse a DGL document holding a single workflow into a definition
+pub fn parse(source: &str) -> WorkflowResult<WorkflowDefinition> {
+    let parsed = Parser::new(source.to_string(), "workflow.dgl".to_string())
+        .with_schema(dgv_dgl::v1::create_schema())
+        .parse()
+        .map_err(|e| {
+            let diagnostics: Vec<String> = e.diagnostics.iter().map(ToString::to_string).collect();
+            WorkflowError::InvalidDefinition(format!("{}: {}", e, diagnostics.join("; ")))
+        })?;
+
+    let mut workflows = dgv_dgl::v1::workflows(&parsed.document);
+    match (workflows.pop(), workflows.is_empty()) {
+        (Some(workflow), true) => from_workflow(&workflow),
+        (None, _) => Err(WorkflowError::InvalidDefinition("Document defines no workflow".to_string())),
+        (Some(_), false) => Err(WorkflowError::InvalidDefinition(
+            "Document defines more than one workflow".to_string(),
+        )),
+    }
+}
+
+/// Convert a parsed DGL workflow into a definition
+///
+/// The definition is named after the document id and gets a new id.
+pub fn from_workflow(workflow: &WorkflowGraph) -> WorkflowResult<WorkflowDefinition> {
+    let initial_state = workflow
+        .initial_state()
+        .ok_or_else(|| WorkflowError::InvalidDefinition("Workflow declares no states".to_string()))?;
+
+    let mut states: HashMap<&str, State> = HashMap::new();
+    for declared in &workflow.states {
+        let mut state = State::new(declared.name.as_str());
+        for task in &declared.tasks {
+            state = state.on_enter(Action::ExecuteTask(task_definition(task)));
+        }
+        if states.insert(declared.name.as_str(), state).is_some() {
+            return Err(WorkflowError::InvalidDefinition(format!(
+                "State '{}' is declared more than once",
+                declared.name
+            )));
+        }
+    }
+
+    for declared in &workflow.transitions {
+        let state = states.remove(declared.from.as_str()).ok_or_else(|| {
+            WorkflowError::InvalidDefinition(format!(
+                "Transition '{}' starts in undeclared state '{}'",
+                declared.name, declared.from
+            ))
+        })?;
+        let mut transition = Transition::new(declared.name.as_str(), declared.to.as_str());
+        if let Some(guard) = &declared.guard {
+            transition = transition.with_guard(Guard::expr(guard)?);
+        }
+        states.insert(declared.from.as_str(), state.add_transition(transition));
+    }
+
+    let state_machine = states
+        .into_values()
+        .fold(StateMachine::builder().initial_state(initial_state), |builder, state| {
+            builder.add_state(state)
+        })
+        .build()?;
+
+    Ok(WorkflowDefinition {
+        id: WorkflowId::new(),
+        name: workflow.id.clone().unwrap_or_else(|| "workflow".to_string()),
+        description: None,
+        state_machine,
+        created_at: Utc::now(),
+        subscriptions: Vec::new(),
+        timeout: None,
+        state_timeouts: HashMap::new(),
+        limits: Default::default(),
+        scheduling: Default::default(),
+        indexes: Vec::new(),
+        retention: None,
+    })
+}
+
+fn task_definition(task: &WorkflowTask) -> TaskDefinition {
+    TaskDefinition {
+        name: task.name.clone(),
+        runtime_type: RuntimeType::from_name(task.runtime.as_deref().unwrap_or("javascript")),
+        code: task.code.clone().unwrap_or_default().into_bytes(),
+        timeout_ms: task.timeout_ms.unwrap_or(DEFAULT_TASK_TIMEOUT_MS),
+        result_path: task.result.clone(),
+        on_complete: task.on_complete.clone(),
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ISSUE_CARD: &str = r#"
+id "de.berlin/issue-card"
+
+definition {
+    kind "Workflow"
+    workflow {
+        states {
+            state "draft"
+            state "review" {
+                task "verify" code="export default (ctx) => ({ ok: true })" result="$.steps.verify" on-complete="verified"
+            }
+            state "issued"
+            state "rejected"
+        }
+        transitions {
+            transition "submit" from="draft" to="review"
+            transition "verified" from="review" to="issued" guard="steps.verify.ok"
+            transition "verified" from="review" to="rejected"
+        }
+    }
+}
+"#;
+
+    #[test]
+    fn test_parse() {
+        let definition = parse(ISSUE_CARD).unwrap();
+        assert_eq!(definition.name, "de.berlin/issue-card");
+
+        let sm = &definition.state_machine;
+        assert_eq!(sm.initial_state(), "draft");
+        assert!(sm.is_final("issued"));
+
+        let review = sm.get_state("review").unwrap();
+        assert_eq!(review.transitions().len(), 2);
+        assert!(review.transitions()[0].guard().is_some());
+        match &review.on_enter_actions()[0] {
+            Action::ExecuteTask(task) => {
+                assert_eq!(task.runtime_type, RuntimeType::JavaScript);
+                assert_eq!(task.result_path.as_deref(), Some("$.steps.verify"));
+                assert_eq!(task.on_complete.as_deref(), Some("verified"));
+            }
+            action => panic!("unexpected action {:?}", action),
+        }
+    }
+
+    #[test]
+    fn test_parse_rejects_undeclared_states() {
+        let source = ISSUE_CARD.replace(r#"from="draft" to="review""#, r#"from="draft" to="archived""#);
+        assert!(parse(&source).is_err());
+
+        let source = ISSUE_CARD.replace(r#"from="draft""#, r#"from="archived""#);
+        assert!(parse(&source).is_err());
+    }
+}
@@ -0,0 +1,118 @@
+//! Per-method authorization of RPC calls
+//!
+//! Connect calls are checked by [`authorize_rpc`] once the size limit has
+//! buffered their body; gRPC calls reach it translated into Connect calls.
+//! The WebSocket transport and the local transport of embedded workers
+//! decode requests themselves and check them with [`check`] before
+//! dispatching.
+
+use super::server::connect_error;
+use super::WorkflowEngine;
+use crate::access::{RpcCall, RpcCodec};
+use axum::body::Body;
+use axum::extract::{Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::Next;
+use axum::response::Response;
+use std::sync::Arc;
+
+const SERVICE_PATH: &str = "/workflow.WorkflowService/";
+
+const ENVELOPE_HEADER_LEN: usize = 5;
+
+/// Run the authorizer registered for the called method, if any
+pub(super) async fn check(engine: &WorkflowEngine, call: &RpcCall<'_>) -> Result<(), String> {
+    match engine.rpc_authorizers.get(call.method) {
+        Some(authorizer) => authorizer.authorize(call).await,
+        None => Ok(()),
+    }
+}
+
+/// Refuse Connect calls their method's authorizer denies with
+/// `permission_denied`
+///
+/// Requests whose message cannot be extracted, e.g. Connect GET requests,
+/// are refused as well when their method is guarded.
+pub(super) async fn authorize_rpc(
+    State(engine): State<Arc<WorkflowEngine>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(method) = request.uri().path().strip_prefix(SERVICE_PATH).map(str::to_string) else {
+        return next.run(request).await;
+    };
+    if !engine.rpc_authorizers.contains_key(&method) {
+        return next.run(request).await;
+    }
+
+    // The size limit already buffered the body
+    let (parts, body) = request.into_parts();
+    let Ok(body) = axum::body::to_bytes(body, usize::MAX).await else {
+        return connect_error(StatusCode::BAD_REQUEST, "invalid_argument", "unreadable request body");
+    };
+    let content_type = parts
+        .headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+
+    let result = match connect_message(content_type, &body) {
+        Some((message, codec)) => {
+            let call = RpcCall {
+                method: &method,
+                headers: &parts.headers,
+                message,
+                codec,
+            };
+            check(&engine, &call).await
+        }
+        None => Err(format!("cannot authorize {} requests of type {:?}", method, content_type)),
+    };
+    if let Err(reason) = result {
+        return connect_error(StatusCode::FORBIDDEN, "permission_denied", reason);
+    }
+
+    next.run(Request::from_parts(parts, Body::from(body))).await
+}
+
+/// Extract the request message and its codec from a Connect request body
+///
+/// Streaming requests carry their message in an envelope.
+fn connect_message<'a>(content_type: &str, body: &'a [u8]) -> Option<(&'a [u8], RpcCodec)> {
+    let media_type = content_type.split(';').next().unwrap_or_default().trim();
+    match media_type {
+        "application/proto" => Some((body, RpcCodec::Proto)),
+        "application/json" => Some((body, RpcCodec::Json)),
+        "application/connect+proto" => open_envelope(body).map(|message| (message, RpcCodec::Proto)),
+        "application/connect+json" => open_envelope(body).map(|message| (message, RpcCodec::Json)),
+        _ => None,
+    }
+}
+
+/// Extract the message of an uncompressed envelope
+fn open_envelope(body: &[u8]) -> Option<&[u8]> {
+    let (header, message) = body.split_at_checked(ENVELOPE_HEADER_LEN)?;
+    let len = u32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize;
+    (header[0] == 0 && message.len() == len).then_some(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_connect_message() {
+        assert_eq!(connect_message("application/proto", b"msg"), Some((&b"msg"[..], RpcCodec::Proto)));
+        assert_eq!(
+            connect_message("application/json; charset=utf-8", b"{}"),
+            Some((&b"{}"[..], RpcCodec::Json))
+        );
+        assert_eq!(
+            connect_message("application/connect+proto", &[0, 0, 0, 0, 3, b'm', b's', b'g']),
+            Some((&b"msg"[..], RpcCodec::Proto))
+        );
+        assert_eq!(connect_message("application/connect+proto", &[1, 0, 0, 0, 0]), None);
+        assert_eq!(connect_message("application/connect+json", &[0, 0, 0]), None);
+        assert_eq!(connect_message("text/plain", b"msg"), None);
+    }
+}
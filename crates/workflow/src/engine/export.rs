@@ -0,0 +1,184 @@
+//! HTTP endpoint exporting workflow instance lists as CSV or XLSX
+//!
+//! `GET /export/instances?format=csv&status=Running&fields=applicant.name,fee:float`
+//!
+//! CSV is streamed page by page, so exports of any size run in constant
+//! memory. XLSX workbooks have to be assembled in full and are limited to a
+//! single worksheet.
+
+use super::WorkflowEngine;
+use crate::error::{EngineError, ExportError, ExportResult, Result};
+use crate::export::{write_xlsx, ColumnMapping, CsvEncoder, ExportFormat, XLSX_MAX_ROWS};
+use crate::types::{InstanceCursor, InstanceFilter, WorkflowId, WorkflowStatus};
+use axum::body::{Body, Bytes};
+use axum::extract::{Query, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use futures::TryStreamExt;
+use serde::Deserialize;
+use std::sync::Arc;
+
+/// Number of instances scanned per storage read
+const EXPORT_PAGE_SIZE: usize = 500;
+
+/// Query parameters of an export request
+#[derive(Debug, Deserialize)]
+pub(super) struct ExportQuery {
+    /// `csv` (default) or `xlsx`
+    format: Option<String>,
+    definition_id: Option<String>,
+    status: Option<WorkflowStatus>,
+    /// Comma separated context fields, each `path` or `path:type`
+    fields: Option<String>,
+}
+
+impl ExportQuery {
+    fn format(&self) -> ExportResult<ExportFormat> {
+        self.format.as_deref().map_or(Ok(ExportFormat::Csv), ExportFormat::parse)
+    }
+
+    fn filter(&self) -> ExportResult<InstanceFilter> {
+        let definition_id = self
+            .definition_id
+            .as_deref()
+            .map(|id| {
+                uuid::Uuid::parse_str(id)
+                    .map(WorkflowId::from_uuid)
+                    .map_err(|e| ExportError::InvalidFilter(format!("definition_id: {}", e)))
+            })
+            .transpose()?;
+
+        Ok(InstanceFilter {
+            definition_id,
+            status: self.status,
+            ..Default::default()
+        })
+    }
+
+    fn mapping(&self) -> ExportResult<ColumnMapping> {
+        match &self.fields {
+            Some(fields) => ColumnMapping::from_selections(fields.split(',')),
+            None => Ok(ColumnMapping::new()),
+        }
+    }
+}
+
+pub(super) async fn export_instances_handler(
+    State(engine): State<Arc<WorkflowEngine>>,
+    Query(query): Query<ExportQuery>,
+) -> Response {
+    let request = query
+        .format()
+        .and_then(|format| Ok((format, query.filter()?, query.mapping()?)));
+
+    let result = match request {
+        Ok((ExportFormat::Csv, filter, mapping)) => export_csv(engine, filter, mapping),
+        Ok((ExportFormat::Xlsx, filter, mapping)) => export_xlsx(engine, filter, mapping).await,
+        Err(e) => return (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
+    };
+
+    match result {
+        Ok(response) => response,
+        Err(e) => {
+            tracing::error!("Instance export failed: {}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+        }
+    }
+}
+
+/// State of a streaming CSV export
+struct CsvExport {
+    engine: Arc<WorkflowEngine>,
+    filter: InstanceFilter,
+    mapping: ColumnMapping,
+    encoder: CsvEncoder,
+    cursor: Option<InstanceCursor>,
+    done: bool,
+}
+
+fn export_csv(
+    engine: Arc<WorkflowEngine>,
+    filter: InstanceFilter,
+    mapping: ColumnMapping,
+) -> Result<Response> {
+    let mut encoder = CsvEncoder::new();
+    encoder.write_headers(&mapping.headers())?;
+
+    let state = CsvExport {
+        engine,
+        filter,
+        mapping,
+        encoder,
+        cursor: None,
+        done: false,
+    };
+
+    let stream = futures::stream::try_unfold(state, |mut state| async move {
+        if state.done {
+            return Ok(None);
+        }
+
+        let page = state
+            .engine
+            .persistence()
+            .workflows()
+            .list(&state.filter, state.cursor.as_ref(), EXPORT_PAGE_SIZE)
+            .await?;
+        for instance in &page.instances {
+            state.encoder.write_row(&state.mapping.row(instance))?;
+        }
+        state.cursor = page.next;
+        state.done = state.cursor.is_none();
+
+        let chunk = Bytes::from(state.encoder.take()?);
+        Ok::<_, ExportError>(Some((chunk, state)))
+    })
+    .inspect_err(|e| tracing::error!("Instance export aborted: {}", e));
+
+    Ok(file_response(ExportFormat::Csv, Body::from_stream(stream)))
+}
+
+async fn export_xlsx(
+    engine: Arc<WorkflowEngine>,
+    filter: InstanceFilter,
+    mapping: ColumnMapping,
+) -> Result<Response> {
+    let mut rows = Vec::new();
+    let mut cursor = None;
+    loop {
+        let page = engine
+            .persistence()
+            .workflows()
+            .list(&filter, cursor.as_ref(), EXPORT_PAGE_SIZE)
+            .await?;
+        rows.extend(page.instances.iter().map(|instance| mapping.row(instance)));
+        if rows.len() > XLSX_MAX_ROWS {
+            return Err(ExportError::TooManyRows(rows.len()).into());
+        }
+
+        cursor = page.next;
+        if cursor.is_none() {
+            break;
+        }
+    }
+
+    // Building the workbook is CPU bound, keep it off the async workers
+    let headers = mapping.headers();
+    let workbook = tokio::task::spawn_blocking(move || write_xlsx(&headers, &rows))
+        .await
+        .map_err(|e| EngineError::Internal(format!("Export task failed: {}", e)))??;
+
+    Ok(file_response(ExportFormat::Xlsx, Body::from(workbook)))
+}
+
+fn file_response(format: ExportFormat, body: Body) -> Response {
+    let disposition = format!("attachment; filename=\"instances.{}\"", format.extension());
+    (
+        [
+            (header::CONTENT_TYPE, format.content_type().to_string()),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        body,
+    )
+        .into_response()
+}
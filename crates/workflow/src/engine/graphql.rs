@@ -0,0 +1,549 @@
+//! Read-only GraphQL gateway over workflow and registry data
+//!
+//! `POST /graphql` answers queries over workflow instances, their history,
+//! workflow definitions and the records of a Merkle search tree registry:
+//!
+//! ```graphql
+//! {
+//!   instances(status: "Running", first: 20) {
+//!     nodes { id currentState context definition { name } }
+//!     next
+//!   }
+//!   records(prefix: "permits/") { key value }
+//! }
+//! ```
+//!
+//! Every field is checked against a [`FieldPolicy`] before it is resolved.
+//! The principal is taken from the `x-principal-id` and `x-principal-roles`
+//! (comma separated) headers, set by the trusted gateway in front of the
+//! engine just like the principal of RPC requests.
+
+use super::WorkflowEngine;
+use crate::access::{AllowAll, Principal};
+use crate::error::{EngineError, WorkflowError};
+use crate::types::{
+    HistoryEvent, InstanceCursor, InstanceFilter, WorkflowDefinition, WorkflowId, WorkflowInstance, WorkflowStatus,
+};
+use async_graphql::extensions::{Extension, ExtensionContext, ExtensionFactory, NextResolve, ResolveInfo};
+use async_graphql::{
+    Context, EmptyMutation, EmptySubscription, ID, Json, Object, Schema, ServerError, ServerResult, Value,
+};
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::response::{IntoResponse, Response};
+use chrono::{DateTime, Utc};
+use dgv_storage::MerkleSearchTree;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+/// Page size when a query does not ask for one
+const DEFAULT_PAGE_SIZE: usize = 50;
+
+/// Upper bound on the page size of a query
+const MAX_PAGE_SIZE: usize = 500;
+
+/// Maximum nesting depth of a query
+const MAX_QUERY_DEPTH: usize = 8;
+
+/// Maximum complexity of a query, roughly the number of fields resolved
+const MAX_QUERY_COMPLEXITY: usize = 2000;
+
+/// Header naming the principal of a request
+const PRINCIPAL_ID_HEADER: &str = "x-principal-id";
+
+/// Header listing the roles of the principal, comma separated
+const PRINCIPAL_ROLES_HEADER: &str = "x-principal-roles";
+
+/// Decides which fields of the GraphQL schema a principal may read
+pub trait FieldPolicy: Send + Sync {
+    /// `type_name` and `field` as named in the schema, e.g.
+    /// `WorkflowInstance` and `context`
+    fn can_read(&self, principal: &Principal, type_name: &str, field: &str) -> bool;
+}
+
+impl FieldPolicy for AllowAll {
+    fn can_read(&self, _principal: &Principal, _type_name: &str, _field: &str) -> bool {
+        true
+    }
+}
+
+/// Policy restricting types and fields to roles
+///
+/// Types and fields without a grant are readable by everyone. Once a type
+/// or field has a grant, only principals holding one of its granted roles
+/// may read it; a field of a restricted type needs both.
+#[derive(Debug, Clone, Default)]
+pub struct FieldRolePolicy {
+    grants: HashMap<(String, Option<String>), HashSet<String>>,
+}
+
+impl FieldRolePolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allow a role to read all fields of a type
+    pub fn allow_type(mut self, role: impl Into<String>, type_name: impl Into<String>) -> Self {
+        self.grants
+            .entry((type_name.into(), None))
+            .or_default()
+            .insert(role.into());
+        self
+    }
+
+    /// Allow a role to read one field of a type
+    pub fn allow_field(
+        mut self,
+        role: impl Into<String>,
+        type_name: impl Into<String>,
+        field: impl Into<String>,
+    ) -> Self {
+        self.grants
+            .entry((type_name.into(), Some(field.into())))
+            .or_default()
+            .insert(role.into());
+        self
+    }
+
+    fn permits(&self, principal: &Principal, key: &(String, Option<String>)) -> bool {
+        self.grants
+            .get(key)
+            .is_none_or(|roles| roles.iter().any(|role| principal.has_role(role)))
+    }
+}
+
+impl FieldPolicy for FieldRolePolicy {
+    fn can_read(&self, principal: &Principal, type_name: &str, field: &str) -> bool {
+        self.permits(principal, &(type_name.to_string(), None))
+            && self.permits(principal, &(type_name.to_string(), Some(field.to_string())))
+    }
+}
+
+/// Configuration of the GraphQL endpoint
+pub struct GraphQlGateway {
+    policy: Arc<dyn FieldPolicy>,
+    records: Option<Arc<MerkleSearchTree>>,
+    schema: Schema<Query, EmptyMutation, EmptySubscription>,
+}
+
+impl GraphQlGateway {
+    /// Create a gateway over workflow data, readable by everyone
+    pub fn new() -> Self {
+        Self::build(Arc::new(AllowAll), None)
+    }
+
+    /// Set the policy deciding who may read which fields
+    pub fn with_field_policy(self, policy: Arc<dyn FieldPolicy>) -> Self {
+        Self::build(policy, self.records)
+    }
+
+    /// Serve the records of a registry tree
+    pub fn with_records(self, tree: MerkleSearchTree) -> Self {
+        Self::build(self.policy, Some(Arc::new(tree)))
+    }
+
+    /// Get the schema in SDL
+    pub fn sdl(&self) -> String {
+        self.schema.sdl()
+    }
+
+    fn build(policy: Arc<dyn FieldPolicy>, records: Option<Arc<MerkleSearchTree>>) -> Self {
+        let mut schema = Schema::build(Query, EmptyMutation, EmptySubscription)
+            .extension(FieldGuard)
+            .limit_depth(MAX_QUERY_DEPTH)
+            .limit_complexity(MAX_QUERY_COMPLEXITY)
+            .data(policy.clone());
+        if let Some(records) = &records {
+            schema = schema.data(records.clone());
+        }
+        Self {
+            policy,
+            records,
+            schema: schema.finish(),
+        }
+    }
+}
+
+impl Default for GraphQlGateway {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub(super) async fn graphql_handler(
+    State(engine): State<Arc<WorkflowEngine>>,
+    headers: HeaderMap,
+    axum::Json(request): axum::Json<async_graphql::Request>,
+) -> Response {
+    let Some(gateway) = engine.graphql() else {
+        return axum::http::StatusCode::NOT_FOUND.into_response();
+    };
+    let request = request.data(engine.clone()).data(principal(&headers));
+    axum::Json(gateway.schema.execute(request).await).into_response()
+}
+
+/// Read the principal of a request; without headers it has no id and no
+/// roles
+fn principal(headers: &HeaderMap) -> Principal {
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok()).unwrap_or_default();
+    header(PRINCIPAL_ROLES_HEADER)
+        .split(',')
+        .map(str::trim)
+        .filter(|role| !role.is_empty())
+        .fold(Principal::new(header(PRINCIPAL_ID_HEADER)), Principal::with_role)
+}
+
+/// Extension checking every field against the field policy
+struct FieldGuard;
+
+impl ExtensionFactory for FieldGuard {
+    fn create(&self) -> Arc<dyn Extension> {
+        Arc::new(FieldGuard)
+    }
+}
+
+#[async_trait::async_trait]
+impl Extension for FieldGuard {
+    async fn resolve(
+        &self,
+        ctx: &ExtensionContext<'_>,
+        info: ResolveInfo<'_>,
+        next: NextResolve<'_>,
+    ) -> ServerResult<Option<Value>> {
+        // Introspection is not data
+        if info.parent_type.starts_with("__") || info.name.starts_with("__") {
+            return next.run(ctx, info).await;
+        }
+
+        let policy = ctx.data_unchecked::<Arc<dyn FieldPolicy>>();
+        let principal = ctx.data_unchecked::<Principal>();
+        if !policy.can_read(principal, info.parent_type, info.name) {
+            return Err(ServerError::new(
+                format!("Not allowed to read {}.{}", info.parent_type, info.name),
+                None,
+            ));
+        }
+        next.run(ctx, info).await
+    }
+}
+
+fn page_size(first: Option<usize>) -> usize {
+    first.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE)
+}
+
+fn parse_id(id: &str) -> Result<WorkflowId, EngineError> {
+    uuid::Uuid::parse_str(id)
+        .map(WorkflowId::from_uuid)
+        .map_err(|e| WorkflowError::NotFound(format!("{}: {}", id, e)).into())
+}
+
+fn engine<'a>(ctx: &Context<'a>) -> &'a Arc<WorkflowEngine> {
+    ctx.data_unchecked::<Arc<WorkflowEngine>>()
+}
+
+/// Root of all queries
+struct Query;
+
+#[Object]
+impl Query {
+    /// Get a workflow instance
+    async fn instance(&self, ctx: &Context<'_>, id: ID) -> async_graphql::Result<Option<Instance>> {
+        let instance = engine(ctx).persistence().workflows().get_instance(&parse_id(&id)?).await?;
+        Ok(instance.map(Instance))
+    }
+
+    /// Page through workflow instances, oldest first
+    async fn instances(
+        &self,
+        ctx: &Context<'_>,
+        definition_id: Option<ID>,
+        status: Option<String>,
+        created_after: Option<DateTime<Utc>>,
+        created_before: Option<DateTime<Utc>>,
+        after: Option<String>,
+        first: Option<usize>,
+    ) -> async_graphql::Result<InstancePage> {
+        let invalid = |message: String| EngineError::Workflow(WorkflowError::InvalidFilter(message));
+        let status = status
+            .map(|status| {
+                serde_json::from_value::<WorkflowStatus>(serde_json::Value::String(status.clone()))
+                    .map_err(|_| invalid(format!("Unknown status: {}", status)))
+            })
+            .transpose()?;
+        let cursor = after
+            .map(|cursor| cursor.parse::<InstanceCursor>().map_err(invalid))
+            .transpose()?;
+        let filter = InstanceFilter {
+            definition_id: definition_id.as_deref().map(|id| parse_id(id)).transpose()?,
+            status,
+            created_after,
+            created_before,
+        };
+
+        let page = engine(ctx)
+            .list_instances(&filter, cursor.as_ref(), page_size(first))
+            .await?;
+        Ok(InstancePage {
+            nodes: page.instances.into_iter().map(Instance).collect(),
+            next: page.next.map(|cursor| cursor.to_string()),
+        })
+    }
+
+    /// Get a workflow definition
+    async fn definition(&self, ctx: &Context<'_>, id: ID) -> async_graphql::Result<Option<Definition>> {
+        let definition = engine(ctx).persistence().workflows().get_definition(&parse_id(&id)?).await?;
+        Ok(definition.map(Definition))
+    }
+
+    /// Page through workflow definitions in ID order
+    async fn definitions(
+        &self,
+        ctx: &Context<'_>,
+        after: Option<ID>,
+        first: Option<usize>,
+    ) -> async_graphql::Result<Vec<Definition>> {
+        let after = after.as_deref().map(|id| parse_id(id)).transpose()?;
+        let definitions = engine(ctx)
+            .persistence()
+            .workflows()
+            .list_definitions(after.as_ref(), page_size(first))
+            .await?;
+        Ok(definitions.into_iter().map(Definition).collect())
+    }
+
+    /// Get a registry record
+    async fn record(&self, ctx: &Context<'_>, key: String) -> async_graphql::Result<Option<Record>> {
+        let value = registry(ctx)?.get(&key).await?;
+        Ok(value.map(|value| Record { key, value }))
+    }
+
+    /// Page through the registry records under `prefix` in key order
+    async fn records(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default)] prefix: String,
+        after: Option<String>,
+        first: Option<usize>,
+    ) -> async_graphql::Result<Vec<Record>> {
+        // Keys after `after` start with it followed by a NUL at the earliest
+        let start = match after {
+            Some(after) if after >= prefix => format!("{}\0", after),
+            _ => prefix.clone(),
+        };
+        let end = format!("{}{}", prefix, char::MAX);
+
+        let entries = registry(ctx)?.get_range(&start, &end).await?;
+        Ok(entries
+            .into_iter()
+            .take(page_size(first))
+            .map(|(key, value)| Record { key, value })
+            .collect())
+    }
+}
+
+fn registry<'a>(ctx: &Context<'a>) -> async_graphql::Result<&'a Arc<MerkleSearchTree>> {
+    ctx.data::<Arc<MerkleSearchTree>>()
+        .map_err(|_| "No registry is configured".into())
+}
+
+/// A page of workflow instances
+struct InstancePage {
+    nodes: Vec<Instance>,
+    next: Option<String>,
+}
+
+#[Object]
+impl InstancePage {
+    async fn nodes(&self) -> &[Instance] {
+        &self.nodes
+    }
+
+    /// Cursor to pass as `after` for the next page, unset on the last page
+    async fn next(&self) -> Option<&str> {
+        self.next.as_deref()
+    }
+}
+
+struct Instance(WorkflowInstance);
+
+#[Object(name = "WorkflowInstance")]
+impl Instance {
+    async fn id(&self) -> ID {
+        ID(self.0.id.to_string())
+    }
+
+    async fn definition_id(&self) -> ID {
+        ID(self.0.definition_id.to_string())
+    }
+
+    async fn definition(&self, ctx: &Context<'_>) -> async_graphql::Result<Option<Definition>> {
+        let definition = engine(ctx)
+            .persistence()
+            .workflows()
+            .get_definition(&self.0.definition_id)
+            .await?;
+        Ok(definition.map(Definition))
+    }
+
+    async fn current_state(&self) -> &str {
+        &self.0.current_state
+    }
+
+    async fn status(&self) -> &str {
+        self.0.status.as_str()
+    }
+
+    async fn context(&self) -> Json<&serde_json::Value> {
+        Json(&self.0.context)
+    }
+
+    async fn created_at(&self) -> DateTime<Utc> {
+        self.0.created_at
+    }
+
+    async fn updated_at(&self) -> DateTime<Utc> {
+        self.0.updated_at
+    }
+
+    async fn completed_at(&self) -> Option<DateTime<Utc>> {
+        self.0.completed_at
+    }
+
+    async fn parent_id(&self) -> Option<ID> {
+        self.0.parent.as_ref().map(|parent| ID(parent.workflow_id.to_string()))
+    }
+
+    /// History entries, oldest first
+    async fn history(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<HistoryEntry>> {
+        let history = engine(ctx).get_history(&self.0.id).await?;
+        Ok(history.into_iter().map(HistoryEntry).collect())
+    }
+}
+
+struct HistoryEntry(HistoryEvent);
+
+#[Object]
+impl HistoryEntry {
+    async fn sequence(&self) -> u64 {
+        self.0.sequence
+    }
+
+    async fn recorded_at(&self) -> DateTime<Utc> {
+        self.0.recorded_at
+    }
+
+    async fn kind(&self) -> &str {
+        self.0.kind.as_str()
+    }
+
+    /// Fields of the entry besides its kind
+    async fn details(&self) -> Json<serde_json::Value> {
+        let mut details = serde_json::to_value(&self.0.kind).unwrap_or_default();
+        if let serde_json::Value::Object(fields) = &mut details {
+            fields.remove("type");
+        }
+        Json(details)
+    }
+}
+
+struct Definition(WorkflowDefinition);
+
+#[Object(name = "WorkflowDefinition")]
+impl Definition {
+    async fn id(&self) -> ID {
+        ID(self.0.id.to_string())
+    }
+
+    async fn name(&self) -> &str {
+        &self.0.name
+    }
+
+    async fn description(&self) -> Option<&str> {
+        self.0.description.as_deref()
+    }
+
+    async fn created_at(&self) -> DateTime<Utc> {
+        self.0.created_at
+    }
+
+    async fn initial_state(&self) -> &str {
+        self.0.state_machine.initial_state()
+    }
+
+    /// The full state machine as JSON
+    async fn state_machine(&self) -> async_graphql::Result<Json<serde_json::Value>> {
+        Ok(Json(serde_json::to_value(&self.0.state_machine)?))
+    }
+}
+
+struct Record {
+    key: String,
+    value: Vec<u8>,
+}
+
+#[Object(name = "RegistryRecord")]
+impl Record {
+    async fn key(&self) -> &str {
+        &self.key
+    }
+
+    async fn value(&self) -> async_graphql::Result<Json<serde_json::Value>> {
+        Ok(Json(MerkleSearchTree::decode_value(&self.value)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_field_role_policy() {
+        let policy = FieldRolePolicy::new()
+            .allow_field("case-worker", "WorkflowInstance", "context")
+            .allow_type("registrar", "RegistryRecord");
+
+        let clerk = Principal::new("clerk-1");
+        let case_worker = Principal::new("cw-1").with_role("case-worker");
+        let registrar = Principal::new("reg-1").with_role("registrar");
+
+        assert!(policy.can_read(&clerk, "WorkflowInstance", "status"));
+        assert!(!policy.can_read(&clerk, "WorkflowInstance", "context"));
+        assert!(policy.can_read(&case_worker, "WorkflowInstance", "context"));
+        assert!(!policy.can_read(&case_worker, "RegistryRecord", "key"));
+        assert!(policy.can_read(&registrar, "RegistryRecord", "value"));
+    }
+
+    #[test]
+    fn test_principal_from_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert(PRINCIPAL_ID_HEADER, "cw-1".parse().unwrap());
+        headers.insert(PRINCIPAL_ROLES_HEADER, "case-worker, clerk,".parse().unwrap());
+
+        let principal = principal(&headers);
+        assert_eq!(principal.id, "cw-1");
+        assert!(principal.has_role("case-worker"));
+        assert!(principal.has_role("clerk"));
+        assert_eq!(principal.roles.len(), 2);
+        assert!(super::principal(&HeaderMap::new()).roles.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_field_guard() {
+        let policy = FieldRolePolicy::new().allow_field("admin", "Query", "definitions");
+        let gateway = GraphQlGateway::new().with_field_policy(Arc::new(policy));
+
+        let response = gateway
+            .schema
+            .execute(async_graphql::Request::new("{ definitions { id } }").data(Principal::new("clerk-1")))
+            .await;
+        assert_eq!(response.errors.len(), 1);
+        assert_eq!(response.errors[0].message, "Not allowed to read Query.definitions");
+
+        // Introspection is open
+        let response = gateway
+            .schema
+            .execute(async_graphql::Request::new("{ __schema { queryType { name } } }").data(Principal::new("clerk-1")))
+            .await;
+        assert!(response.errors.is_empty());
+    }
+}
@@ -0,0 +1,744 @@
+//! Classic gRPC compatibility for the workflow service
+//!
+//! Some clients only speak gRPC over HTTP/2 with binary protobuf. Requests
+//! carrying an `application/grpc` content type are translated into Connect
+//! requests and passed down the same middleware stack as Connect calls, so
+//! deadlines, metrics, authorization and idempotent replay apply to both
+//! protocols alike. The Connect response is translated back into gRPC
+//! framing and status.
+//!
+//! Server-streaming methods are translated the same way: each message of the
+//! Connect stream is relayed as a gRPC message, and the status of its
+//! end-of-stream envelope becomes the gRPC status.
+//!
+//! gRPC-Web requests (`application/grpc-web` and the base64 encoded
+//! `application/grpc-web-text`) are served the same way. Their status travels
+//! in a trailer frame at the end of the body instead of HTTP trailers, so
+//! browsers and proxies like envoy can call the service over HTTP/1.1.
+
+use super::instrument::http_code;
+use super::server::END_STREAM_FLAG;
+use axum::body::{Body, BodyDataStream, Bytes, HttpBody};
+use axum::extract::{Request, State};
+use axum::http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode};
+use axum::middleware::Next;
+use axum::response::Response;
+use base64::Engine as _;
+use futures::{Stream, StreamExt};
+use http_body::Frame;
+use std::convert::Infallible;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+const SERVICE_PATH: &str = "/workflow.WorkflowService/";
+const GRPC_CONTENT_TYPE: &str = "application/grpc";
+const GRPC_WEB_CONTENT_TYPE: &str = "application/grpc-web+proto";
+const GRPC_WEB_TEXT_CONTENT_TYPE: &str = "application/grpc-web-text+proto";
+
+/// Content type of the Connect unary request a gRPC call is translated into
+const CONNECT_UNARY_CONTENT_TYPE: &str = "application/proto";
+
+/// Content type of the Connect streaming request a gRPC call is translated into
+const CONNECT_STREAMING_CONTENT_TYPE: &str = "application/connect+proto";
+
+/// Header carrying the client's deadline for a gRPC call
+const GRPC_TIMEOUT_HEADER: &str = "grpc-timeout";
+
+/// Header carrying the deadline of the translated Connect call
+const CONNECT_TIMEOUT_HEADER: &str = "connect-timeout-ms";
+
+/// Largest deadline a Connect call can carry
+const MAX_CONNECT_TIMEOUT_MS: u128 = 9_999_999_999;
+
+/// Methods answering with a stream of messages
+const STREAMING_METHODS: &[&str] = &["StreamTasks", "WatchWorkflow"];
+
+/// Frame flag marking the gRPC-Web trailer frame
+const TRAILER_FLAG: u8 = 0x80;
+
+/// Length of the gRPC message prefix (compression flag + 4 byte length)
+const FRAME_HEADER_LEN: usize = 5;
+
+/// gRPC status codes used by the shim
+mod code {
+    pub const OK: u32 = 0;
+    pub const UNKNOWN: u32 = 2;
+    pub const INVALID_ARGUMENT: u32 = 3;
+    pub const RESOURCE_EXHAUSTED: u32 = 8;
+    pub const UNIMPLEMENTED: u32 = 12;
+}
+
+/// A non-OK gRPC status
+#[derive(Debug)]
+struct GrpcStatus {
+    code: u32,
+    message: String,
+}
+
+impl GrpcStatus {
+    fn new(code: u32, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    /// Status of a Connect error, e.g. `{"code":"not_found","message":"..."}`
+    ///
+    /// Bodies without a Connect code fall back to the code implied by the
+    /// HTTP status.
+    fn from_connect_error(status: StatusCode, body: &[u8]) -> Self {
+        let error = serde_json::from_slice::<serde_json::Value>(body).ok();
+        Self::from_connect_json(error.as_ref(), http_code(status))
+    }
+
+    /// Status of the end-of-stream envelope closing a Connect stream, e.g.
+    /// `{"error":{"code":"deadline_exceeded","message":"..."}}`
+    fn from_end_stream(trailer: &[u8]) -> Self {
+        match serde_json::from_slice::<serde_json::Value>(trailer) {
+            Ok(trailer) => match trailer.get("error") {
+                Some(error) => Self::from_connect_json(Some(error), "unknown"),
+                None => Self::new(code::OK, ""),
+            },
+            Err(e) => Self::new(code::UNKNOWN, format!("Invalid end of stream: {}", e)),
+        }
+    }
+
+    fn from_connect_json(error: Option<&serde_json::Value>, fallback_code: &str) -> Self {
+        let connect_code = error
+            .and_then(|error| error.get("code")?.as_str())
+            .unwrap_or(fallback_code);
+        let message = error
+            .and_then(|error| error.get("message")?.as_str())
+            .unwrap_or_default();
+        Self::new(grpc_code(connect_code), message)
+    }
+}
+
+/// gRPC status code of a Connect error code; both protocols share the
+/// canonical code set
+fn grpc_code(connect_code: &str) -> u32 {
+    match connect_code {
+        "canceled" => 1,
+        "unknown" => 2,
+        "invalid_argument" => 3,
+        "deadline_exceeded" => 4,
+        "not_found" => 5,
+        "already_exists" => 6,
+        "permission_denied" => 7,
+        "resource_exhausted" => 8,
+        "failed_precondition" => 9,
+        "aborted" => 10,
+        "out_of_range" => 11,
+        "unimplemented" => 12,
+        "internal" => 13,
+        "unavailable" => 14,
+        "data_loss" => 15,
+        "unauthenticated" => 16,
+        _ => code::UNKNOWN,
+    }
+}
+
+/// gRPC flavour a request was made in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Protocol {
+    Grpc,
+    GrpcWeb,
+    /// gRPC-Web with base64 encoded bodies
+    GrpcWebText,
+}
+
+impl Protocol {
+    /// Select the protocol from a request content type
+    fn from_content_type(content_type: &str) -> Option<Self> {
+        let media_type = content_type.split(';').next().unwrap_or_default().trim();
+        match media_type {
+            "application/grpc" | "application/grpc+proto" => Some(Self::Grpc),
+            "application/grpc-web" | "application/grpc-web+proto" => Some(Self::GrpcWeb),
+            "application/grpc-web-text" | "application/grpc-web-text+proto" => Some(Self::GrpcWebText),
+            _ => None,
+        }
+    }
+}
+
+/// Serve gRPC and gRPC-Web requests as Connect calls, passing everything
+/// else through unchanged
+pub(super) async fn grpc_compat(
+    State(max_message_bytes): State<usize>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let protocol = request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(Protocol::from_content_type);
+
+    let Some(protocol) = protocol else {
+        return next.run(request).await;
+    };
+
+    let Some(method) = request.uri().path().strip_prefix(SERVICE_PATH) else {
+        return status_response(
+            protocol,
+            GrpcStatus::new(code::UNIMPLEMENTED, format!("Unknown service path: {}", request.uri().path())),
+        );
+    };
+    let streaming = STREAMING_METHODS.contains(&method);
+
+    // Base64 takes four bytes for every three
+    let max_body_bytes = match protocol {
+        Protocol::GrpcWebText => (max_message_bytes + FRAME_HEADER_LEN).div_ceil(3) * 4,
+        _ => max_message_bytes + FRAME_HEADER_LEN,
+    };
+    let (mut parts, body) = request.into_parts();
+    let body = match axum::body::to_bytes(body, max_body_bytes).await {
+        Ok(body) => body,
+        Err(_) => {
+            return status_response(
+                protocol,
+                GrpcStatus::new(
+                    code::RESOURCE_EXHAUSTED,
+                    format!("request message exceeds the {} byte limit", max_message_bytes),
+                ),
+            )
+        }
+    };
+    let body = match decode_body(protocol, body).and_then(|body| decode_frame(&body).map(|_| body)) {
+        Ok(body) => body,
+        Err(status) => return status_response(protocol, status),
+    };
+
+    // A Connect streaming request wraps its message in an envelope laid out
+    // like the gRPC frame; a unary one sends the bare message
+    let (body, content_type) = match streaming {
+        true => (body, CONNECT_STREAMING_CONTENT_TYPE),
+        false => (body.slice(FRAME_HEADER_LEN..), CONNECT_UNARY_CONTENT_TYPE),
+    };
+    if let Err(status) = connect_headers(&mut parts.headers, content_type, body.len()) {
+        return status_response(protocol, status);
+    }
+
+    let response = next.run(Request::from_parts(parts, Body::from(body))).await;
+    match streaming {
+        true => grpc_stream_from_connect(protocol, response).await,
+        false => grpc_from_connect(protocol, response).await,
+    }
+}
+
+/// Rewrite gRPC request headers into those of a Connect call
+fn connect_headers(headers: &mut HeaderMap, content_type: &'static str, len: usize) -> Result<(), GrpcStatus> {
+    headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+    headers.insert(header::CONTENT_LENGTH, HeaderValue::from(len));
+    headers.insert(HeaderName::from_static("connect-protocol-version"), HeaderValue::from_static("1"));
+
+    if let Some(value) = headers.remove(GRPC_TIMEOUT_HEADER) {
+        let Some(timeout) = value.to_str().ok().and_then(parse_grpc_timeout) else {
+            return Err(GrpcStatus::new(
+                code::INVALID_ARGUMENT,
+                format!("invalid {} header", GRPC_TIMEOUT_HEADER),
+            ));
+        };
+        // Connect deadlines are whole, positive milliseconds
+        let ms = timeout.as_nanos().div_ceil(1_000_000).clamp(1, MAX_CONNECT_TIMEOUT_MS);
+        headers.insert(CONNECT_TIMEOUT_HEADER, HeaderValue::from(ms as u64));
+    }
+    Ok(())
+}
+
+/// Parse a `grpc-timeout` value: at most 8 digits followed by a unit of
+/// `H`ours, `M`inutes, `S`econds, `m`illi-, `u` micro- or `n`anoseconds
+fn parse_grpc_timeout(value: &str) -> Option<Duration> {
+    let (digits, unit) = value.split_at_checked(value.len().checked_sub(1)?)?;
+    if digits.is_empty() || digits.len() > 8 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let amount: u64 = digits.parse().ok()?;
+    match unit {
+        "H" => Some(Duration::from_secs(amount * 60 * 60)),
+        "M" => Some(Duration::from_secs(amount * 60)),
+        "S" => Some(Duration::from_secs(amount)),
+        "m" => Some(Duration::from_millis(amount)),
+        "u" => Some(Duration::from_micros(amount)),
+        "n" => Some(Duration::from_nanos(amount)),
+        _ => None,
+    }
+}
+
+/// Translate the Connect response to a unary call into a gRPC response
+///
+/// Response headers other than the content type, e.g. the marker of a
+/// replayed response, are passed on.
+async fn grpc_from_connect(protocol: Protocol, response: Response) -> Response {
+    let (parts, body) = response.into_parts();
+    // The size limit already bounds unary responses
+    let body = match axum::body::to_bytes(body, usize::MAX).await {
+        Ok(body) => body,
+        Err(e) => return status_response(protocol, GrpcStatus::new(code::UNKNOWN, e.to_string())),
+    };
+
+    let mut response = match parts.status {
+        StatusCode::OK => message_response(protocol, encode_frame(&body)),
+        status => status_response(protocol, GrpcStatus::from_connect_error(status, &body)),
+    };
+    for (name, value) in &parts.headers {
+        if name != header::CONTENT_TYPE && name != header::CONTENT_LENGTH {
+            response.headers_mut().append(name, value.clone());
+        }
+    }
+    response
+}
+
+/// Translate the Connect response to a server-streaming call into a gRPC
+/// response, relaying each message as it arrives
+///
+/// Errors raised before the stream started are answered trailers-only.
+async fn grpc_stream_from_connect(protocol: Protocol, response: Response) -> Response {
+    if response.status() != StatusCode::OK {
+        return grpc_from_connect(protocol, response).await;
+    }
+
+    let envelopes = EnvelopeReader {
+        data: response.into_body().into_data_stream(),
+        buffer: Vec::new(),
+    };
+    let frames = futures::stream::unfold(Some(envelopes), move |envelopes| async move {
+        let mut envelopes = envelopes?;
+        let status = match envelopes.next().await {
+            Ok(Some((flags, message))) if flags & END_STREAM_FLAG == 0 => {
+                let frame = Frame::data(encode_text(protocol, encode_frame(&message)));
+                return Some((frame, Some(envelopes)));
+            }
+            Ok(Some((_, trailer))) => GrpcStatus::from_end_stream(&trailer),
+            Ok(None) => GrpcStatus::new(code::UNKNOWN, "stream ended without a status"),
+            Err(e) => GrpcStatus::new(code::UNKNOWN, e.to_string()),
+        };
+        let frame = match protocol {
+            Protocol::Grpc => Frame::trailers(status_headers(&status)),
+            _ => Frame::data(encode_text(protocol, Bytes::from(encode_trailer_frame(&status)))),
+        };
+        Some((frame, None))
+    });
+
+    grpc_response(protocol, Body::new(StreamedBody { frames: Box::pin(frames) }))
+}
+
+/// Splits a Connect streaming response body into its envelopes
+struct EnvelopeReader {
+    data: BodyDataStream,
+    buffer: Vec<u8>,
+}
+
+impl EnvelopeReader {
+    /// Read the flags and message of the next envelope, `None` once the
+    /// body ended
+    async fn next(&mut self) -> Result<Option<(u8, Vec<u8>)>, axum::Error> {
+        loop {
+            if let Some(header) = self.buffer.get(..FRAME_HEADER_LEN) {
+                let len = u32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize;
+                if self.buffer.len() >= FRAME_HEADER_LEN + len {
+                    let flags = self.buffer[0];
+                    let message = self.buffer[FRAME_HEADER_LEN..FRAME_HEADER_LEN + len].to_vec();
+                    self.buffer.drain(..FRAME_HEADER_LEN + len);
+                    return Ok(Some((flags, message)));
+                }
+            }
+            match self.data.next().await {
+                Some(chunk) => self.buffer.extend_from_slice(&chunk?),
+                None => return Ok(None),
+            }
+        }
+    }
+}
+
+/// Undo the base64 encoding of gRPC-Web text request bodies
+fn decode_body(protocol: Protocol, body: Bytes) -> Result<Bytes, GrpcStatus> {
+    if protocol != Protocol::GrpcWebText {
+        return Ok(body);
+    }
+    base64::engine::general_purpose::STANDARD
+        .decode(&body)
+        .map(Bytes::from)
+        .map_err(|e| GrpcStatus::new(code::INVALID_ARGUMENT, format!("Invalid base64 body: {}", e)))
+}
+
+/// Extract the single message of a unary gRPC request body
+fn decode_frame(body: &[u8]) -> Result<&[u8], GrpcStatus> {
+    if body.len() < FRAME_HEADER_LEN {
+        return Err(GrpcStatus::new(code::INVALID_ARGUMENT, "Truncated message frame"));
+    }
+    if body[0] != 0 {
+        return Err(GrpcStatus::new(code::UNIMPLEMENTED, "Message compression is not supported"));
+    }
+
+    let len = u32::from_be_bytes([body[1], body[2], body[3], body[4]]) as usize;
+    let message = &body[FRAME_HEADER_LEN..];
+    if message.len() != len {
+        return Err(GrpcStatus::new(
+            code::INVALID_ARGUMENT,
+            "Unary request must contain exactly one message",
+        ));
+    }
+
+    Ok(message)
+}
+
+/// Prefix a message with the uncompressed gRPC frame header
+fn encode_frame(message: &[u8]) -> Bytes {
+    let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + message.len());
+    frame.push(0);
+    frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    frame.extend_from_slice(message);
+    Bytes::from(frame)
+}
+
+/// Build a response carrying one message followed by an OK status trailer
+fn message_response(protocol: Protocol, frame: Bytes) -> Response {
+    if protocol != Protocol::Grpc {
+        let mut body = frame.to_vec();
+        body.extend_from_slice(&encode_trailer_frame(&GrpcStatus::new(code::OK, "")));
+        return grpc_web_response(protocol, body);
+    }
+
+    grpc_response(
+        protocol,
+        Body::new(GrpcBody {
+            data: Some(frame),
+            trailers: Some(status_headers(&GrpcStatus::new(code::OK, ""))),
+        }),
+    )
+}
+
+/// Build a trailers-only response for a failed call
+fn status_response(protocol: Protocol, status: GrpcStatus) -> Response {
+    if protocol != Protocol::Grpc {
+        return grpc_web_response(protocol, encode_trailer_frame(&status));
+    }
+
+    let mut response = grpc_response(protocol, Body::empty());
+    response.headers_mut().extend(status_headers(&status));
+    response
+}
+
+/// The `grpc-status` and `grpc-message` headers reporting `status`
+fn status_headers(status: &GrpcStatus) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert("grpc-status", HeaderValue::from(status.code));
+    if !status.message.is_empty() {
+        if let Ok(message) = HeaderValue::from_str(&percent_encode(&status.message)) {
+            headers.insert("grpc-message", message);
+        }
+    }
+    headers
+}
+
+fn grpc_response(protocol: Protocol, body: Body) -> Response {
+    let content_type = match protocol {
+        Protocol::Grpc => GRPC_CONTENT_TYPE,
+        Protocol::GrpcWeb => GRPC_WEB_CONTENT_TYPE,
+        Protocol::GrpcWebText => GRPC_WEB_TEXT_CONTENT_TYPE,
+    };
+
+    let mut response = Response::new(body);
+    *response.status_mut() = StatusCode::OK;
+    response
+        .headers_mut()
+        .insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+    response
+}
+
+/// Build a gRPC-Web response from its frames
+fn grpc_web_response(protocol: Protocol, body: Vec<u8>) -> Response {
+    grpc_response(protocol, Body::from(encode_text(protocol, Bytes::from(body))))
+}
+
+/// Base64 encode gRPC-Web frames for text clients
+fn encode_text(protocol: Protocol, frames: Bytes) -> Bytes {
+    match protocol {
+        Protocol::GrpcWebText => base64::engine::general_purpose::STANDARD.encode(frames).into(),
+        _ => frames,
+    }
+}
+
+/// Encode a status as the gRPC-Web trailer frame ending a response
+fn encode_trailer_frame(status: &GrpcStatus) -> Vec<u8> {
+    let mut trailers = format!("grpc-status:{}\r\n", status.code);
+    if !status.message.is_empty() {
+        trailers.push_str(&format!("grpc-message:{}\r\n", percent_encode(&status.message)));
+    }
+
+    let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + trailers.len());
+    frame.push(TRAILER_FLAG);
+    frame.extend_from_slice(&(trailers.len() as u32).to_be_bytes());
+    frame.extend_from_slice(trailers.as_bytes());
+    frame
+}
+
+/// Percent-encode a `grpc-message` value as required by the gRPC spec
+fn percent_encode(message: &str) -> String {
+    let mut encoded = String::with_capacity(message.len());
+    for byte in message.bytes() {
+        if (0x20..=0x7e).contains(&byte) && byte != b'%' {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+/// Response body with a single data frame and trailers
+struct GrpcBody {
+    data: Option<Bytes>,
+    trailers: Option<HeaderMap>,
+}
+
+impl HttpBody for GrpcBody {
+    type Data = Bytes;
+    type Error = Infallible;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.get_mut();
+        if let Some(data) = this.data.take() {
+            return Poll::Ready(Some(Ok(Frame::data(data))));
+        }
+        if let Some(trailers) = this.trailers.take() {
+            return Poll::Ready(Some(Ok(Frame::trailers(trailers))));
+        }
+        Poll::Ready(None)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.data.is_none() && self.trailers.is_none()
+    }
+}
+
+/// Response body relaying the frames of a translated stream
+struct StreamedBody {
+    frames: Pin<Box<dyn Stream<Item = Frame<Bytes>> + Send>>,
+}
+
+impl HttpBody for StreamedBody {
+    type Data = Bytes;
+    type Error = Infallible;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        self.get_mut().frames.poll_next_unpin(cx).map(|frame| frame.map(Ok))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::instrument::record_rpc;
+    use crate::engine::server::enforce_deadline;
+    use axum::middleware;
+    use axum::routing::post;
+    use axum::Router;
+    use std::sync::Mutex;
+    use tower::ServiceExt;
+
+    /// Records the labels of every counter increment
+    #[derive(Default)]
+    struct CounterRecorder(Mutex<Vec<Vec<(String, String)>>>);
+
+    impl metrics::Recorder for CounterRecorder {
+        fn describe_counter(&self, _: metrics::KeyName, _: Option<metrics::Unit>, _: metrics::SharedString) {}
+        fn describe_gauge(&self, _: metrics::KeyName, _: Option<metrics::Unit>, _: metrics::SharedString) {}
+        fn describe_histogram(&self, _: metrics::KeyName, _: Option<metrics::Unit>, _: metrics::SharedString) {}
+
+        fn register_counter(&self, key: &metrics::Key, _: &metrics::Metadata<'_>) -> metrics::Counter {
+            let labels = key.labels().map(|l| (l.key().to_string(), l.value().to_string())).collect();
+            self.0.lock().unwrap().push(labels);
+            metrics::Counter::noop()
+        }
+
+        fn register_gauge(&self, _: &metrics::Key, _: &metrics::Metadata<'_>) -> metrics::Gauge {
+            metrics::Gauge::noop()
+        }
+
+        fn register_histogram(&self, _: &metrics::Key, _: &metrics::Metadata<'_>) -> metrics::Histogram {
+            metrics::Histogram::noop()
+        }
+    }
+
+    /// A service with an `Echo` method, a `Slow` one outliving any test
+    /// deadline and a `StreamTasks` stream echoing its request before
+    /// failing, behind the engine's deadline and metrics layers
+    fn app() -> Router {
+        Router::new()
+            .route(
+                "/workflow.WorkflowService/Echo",
+                post(|body: Bytes| async move { ([(header::CONTENT_TYPE, CONNECT_UNARY_CONTENT_TYPE)], body) }),
+            )
+            .route(
+                "/workflow.WorkflowService/StreamTasks",
+                post(|headers: HeaderMap, body: Bytes| async move {
+                    assert_eq!(headers[header::CONTENT_TYPE], CONNECT_STREAMING_CONTENT_TYPE);
+                    let trailer = br#"{"error":{"code":"not_found","message":"gone"}}"#;
+                    let mut envelopes = body.to_vec();
+                    envelopes.push(END_STREAM_FLAG);
+                    envelopes.extend_from_slice(&(trailer.len() as u32).to_be_bytes());
+                    envelopes.extend_from_slice(trailer);
+                    ([(header::CONTENT_TYPE, CONNECT_STREAMING_CONTENT_TYPE)], envelopes)
+                }),
+            )
+            .route(
+                "/workflow.WorkflowService/Slow",
+                post(|| async {
+                    tokio::time::sleep(Duration::from_secs(60)).await;
+                    StatusCode::OK
+                }),
+            )
+            .layer(middleware::from_fn(enforce_deadline))
+            .layer(middleware::from_fn(|request: Request, next: Next| async move {
+                let method = request.uri().path().trim_start_matches(SERVICE_PATH).to_string();
+                record_rpc(method, request, next).await
+            }))
+            .layer(middleware::from_fn_with_state(1024usize, grpc_compat))
+    }
+
+    /// Call `method` over `protocol` with `message`, recording metrics
+    fn call(protocol: &str, method: &str, timeout: Option<&str>, message: &[u8]) -> (Response, CounterRecorder) {
+        let mut request = Request::post(format!("{}{}", SERVICE_PATH, method)).header(header::CONTENT_TYPE, protocol);
+        if let Some(timeout) = timeout {
+            request = request.header(GRPC_TIMEOUT_HEADER, timeout);
+        }
+        let request = request.body(Body::from(encode_frame(message))).unwrap();
+
+        let recorder = CounterRecorder::default();
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+        let response = metrics::with_local_recorder(&recorder, || runtime.block_on(app().oneshot(request))).unwrap();
+        (response, recorder)
+    }
+
+    fn labels(method: &str, code: &str) -> Vec<(String, String)> {
+        vec![("method".to_string(), method.to_string()), ("code".to_string(), code.to_string())]
+    }
+
+    #[test]
+    fn test_grpc_call_is_recorded() {
+        let (response, recorder) = call(GRPC_WEB_CONTENT_TYPE, "Echo", None, b"ping");
+        assert_eq!(recorder.0.into_inner().unwrap(), vec![labels("Echo", "ok")]);
+
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let body = runtime.block_on(axum::body::to_bytes(response.into_body(), usize::MAX)).unwrap();
+        let mut expected = encode_frame(b"ping").to_vec();
+        expected.extend_from_slice(&encode_trailer_frame(&GrpcStatus::new(code::OK, "")));
+        assert_eq!(body, expected);
+    }
+
+    #[test]
+    fn test_grpc_timeout_is_enforced() {
+        let (response, recorder) = call(GRPC_CONTENT_TYPE, "Slow", Some("20m"), b"");
+        assert_eq!(response.headers()["grpc-status"], "4");
+        assert_eq!(recorder.0.into_inner().unwrap(), vec![labels("Slow", "deadline_exceeded")]);
+
+        let (response, recorder) = call(GRPC_CONTENT_TYPE, "Slow", Some("soon"), b"");
+        assert_eq!(response.headers()["grpc-status"], "3");
+        assert!(recorder.0.into_inner().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_grpc_web_stream() {
+        let (response, _) = call(GRPC_WEB_CONTENT_TYPE, "StreamTasks", None, b"ping");
+
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let body = runtime.block_on(axum::body::to_bytes(response.into_body(), usize::MAX)).unwrap();
+        let mut expected = encode_frame(b"ping").to_vec();
+        expected.extend_from_slice(&encode_trailer_frame(&GrpcStatus::new(5, "gone")));
+        assert_eq!(body, expected);
+    }
+
+    #[test]
+    fn test_grpc_stream_ends_with_trailers() {
+        let (response, _) = call(GRPC_CONTENT_TYPE, "StreamTasks", None, b"ping");
+        let mut body = response.into_body();
+
+        let runtime = tokio::runtime::Builder::new_current_thread().build().unwrap();
+        let mut frames = Vec::new();
+        while let Some(frame) = runtime.block_on(std::future::poll_fn(|cx| Pin::new(&mut body).poll_frame(cx))) {
+            frames.push(frame.unwrap());
+        }
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].data_ref().unwrap(), &encode_frame(b"ping"));
+        let trailers = frames[1].trailers_ref().unwrap();
+        assert_eq!(trailers["grpc-status"], "5");
+        assert_eq!(trailers["grpc-message"], "gone");
+    }
+
+    #[test]
+    fn test_parse_grpc_timeout() {
+        assert_eq!(parse_grpc_timeout("20m"), Some(Duration::from_millis(20)));
+        assert_eq!(parse_grpc_timeout("2H"), Some(Duration::from_secs(2 * 60 * 60)));
+        assert_eq!(parse_grpc_timeout("1500n"), Some(Duration::from_nanos(1500)));
+        assert_eq!(parse_grpc_timeout("123456789S"), None);
+        assert_eq!(parse_grpc_timeout("m"), None);
+        assert_eq!(parse_grpc_timeout("10x"), None);
+    }
+
+    #[test]
+    fn test_status_from_connect_error() {
+        let status = GrpcStatus::from_connect_error(
+            StatusCode::FORBIDDEN,
+            br#"{"code":"permission_denied","message":"not yours"}"#,
+        );
+        assert_eq!(status.code, 7);
+        assert_eq!(status.message, "not yours");
+
+        let status = GrpcStatus::from_connect_error(StatusCode::NOT_FOUND, b"");
+        assert_eq!(status.code, code::UNIMPLEMENTED);
+    }
+
+    #[test]
+    fn test_frame_roundtrip() {
+        let frame = encode_frame(b"hello");
+        assert_eq!(&frame[..FRAME_HEADER_LEN], &[0, 0, 0, 0, 5]);
+        assert_eq!(decode_frame(&frame).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn test_compressed_frame_rejected() {
+        let status = decode_frame(&[1, 0, 0, 0, 0]).unwrap_err();
+        assert_eq!(status.code, code::UNIMPLEMENTED);
+    }
+
+    #[test]
+    fn test_length_mismatch_rejected() {
+        let status = decode_frame(&[0, 0, 0, 0, 9, 1, 2]).unwrap_err();
+        assert_eq!(status.code, code::INVALID_ARGUMENT);
+    }
+
+    #[test]
+    fn test_grpc_content_types() {
+        assert_eq!(Protocol::from_content_type("application/grpc"), Some(Protocol::Grpc));
+        assert_eq!(Protocol::from_content_type("application/grpc+proto"), Some(Protocol::Grpc));
+        assert_eq!(Protocol::from_content_type("application/grpc-web"), Some(Protocol::GrpcWeb));
+        assert_eq!(
+            Protocol::from_content_type("application/grpc-web-text; charset=utf-8"),
+            Some(Protocol::GrpcWebText)
+        );
+        assert_eq!(Protocol::from_content_type("application/proto"), None);
+    }
+
+    #[test]
+    fn test_trailer_frame() {
+        let frame = encode_trailer_frame(&GrpcStatus::new(code::INVALID_ARGUMENT, "bad input"));
+        let trailers = b"grpc-status:3\r\ngrpc-message:bad input\r\n";
+        assert_eq!(frame[0], TRAILER_FLAG);
+        assert_eq!(&frame[1..FRAME_HEADER_LEN], &(trailers.len() as u32).to_be_bytes());
+        assert_eq!(&frame[FRAME_HEADER_LEN..], trailers);
+    }
+
+    #[test]
+    fn test_percent_encode() {
+        assert_eq!(percent_encode("100% done\n"), "100%25 done%0A");
+    }
+}
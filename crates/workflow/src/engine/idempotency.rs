@@ -0,0 +1,248 @@
+//! Replay of stored responses to retried unary calls
+
+use super::server::connect_error;
+use super::tenant::TENANT_HEADER;
+use super::WorkflowEngine;
+use crate::idempotency::{IdempotencyStore, PendingCall, Reservation, StoredResponse};
+use axum::body::Body;
+use axum::extract::{Request, State};
+use axum::http::{header, HeaderValue, Method, StatusCode};
+use axum::middleware::Next;
+use axum::response::Response;
+use chrono::Utc;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Header carrying the client's idempotency key
+const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+/// Header marking a replayed response
+const REPLAYED_HEADER: &str = "idempotent-replayed";
+
+/// Header naming the authenticated caller, set by whoever fronts the engine
+const CALLER_HEADER: &str = "x-principal-id";
+
+/// Longest idempotency key accepted
+const MAX_KEY_LEN: usize = 255;
+
+/// How long a key stays reserved for a call that never finishes, e.g.
+/// because the engine stopped
+const PENDING_LEASE: Duration = Duration::from_secs(60);
+
+/// How long a retry waits for the call holding its key to respond
+const MAX_PENDING_WAIT: Duration = Duration::from_secs(5);
+
+/// Interval at which a waiting retry checks for the response
+const PENDING_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Replay the stored response to a unary call carrying a known
+/// `Idempotency-Key`, storing the response otherwise
+///
+/// Keys are scoped to the caller, its tenant and the RPC method. A key
+/// sent with a different request than the one it was first used for is
+/// rejected.
+///
+/// The key is reserved before the call runs. A retry arriving while it
+/// runs waits for its response, and is answered with `aborted` if none
+/// comes in time. Responses to server errors are not stored, so such calls
+/// can be retried. A response too large to store is answered, then and on
+/// retries, with `resource_exhausted`, as the call already ran. When the
+/// store is unavailable calls are refused with `unavailable` rather than
+/// risk running twice.
+pub(super) async fn replay_idempotent(
+    State(engine): State<Arc<WorkflowEngine>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some((store, ttl)) = engine.idempotency.clone() else {
+        return next.run(request).await;
+    };
+    let max_message_bytes = engine.message_limits().max_message_bytes();
+    replay_with(store, ttl, max_message_bytes, request, next).await
+}
+
+/// [`replay_idempotent`] with the engine's store, response lifetime and
+/// message limit
+async fn replay_with(
+    store: Arc<dyn IdempotencyStore>,
+    ttl: chrono::Duration,
+    max_message_bytes: usize,
+    request: Request,
+    next: Next,
+) -> Response {
+    let key = request
+        .headers()
+        .get(IDEMPOTENCY_KEY_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+    let streaming = request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/connect+"));
+    let Some(key) = key.filter(|_| request.method() == Method::POST && !streaming) else {
+        return next.run(request).await;
+    };
+    if key.is_empty() || key.len() > MAX_KEY_LEN {
+        return connect_error(
+            StatusCode::BAD_REQUEST,
+            "invalid_argument",
+            format!("Idempotency-Key must be 1 to {} characters", MAX_KEY_LEN),
+        );
+    }
+
+    // The size limit already buffered the body
+    let (parts, body) = request.into_parts();
+    let Ok(body) = axum::body::to_bytes(body, usize::MAX).await else {
+        return connect_error(StatusCode::BAD_REQUEST, "invalid_argument", "unreadable request body");
+    };
+    // Callers are told apart by a digest of their tenant and name, which
+    // may contain the separator; header values cannot contain newlines
+    let header = |name: &str| parts.headers.get(name).map(|v| v.as_bytes()).unwrap_or_default();
+    let mut caller = header(TENANT_HEADER).to_vec();
+    caller.push(b'\n');
+    caller.extend_from_slice(header(CALLER_HEADER));
+    let store_key = format!("{}:{}:{}", degov_crypto::sha256_hex(&caller), parts.uri.path(), key);
+    let mut digest_input = parts.uri.path().as_bytes().to_vec();
+    digest_input.extend_from_slice(&body);
+    let request_digest = degov_crypto::sha256_hex(&digest_input);
+
+    let lease = chrono::Duration::from_std(PENDING_LEASE).unwrap_or(chrono::Duration::MAX);
+    let call = PendingCall {
+        request_digest: request_digest.clone(),
+        expires_at: Utc::now() + lease,
+    };
+    let wait_until = tokio::time::Instant::now() + MAX_PENDING_WAIT;
+    loop {
+        match store.reserve(&store_key, call.clone()).await {
+            Ok(Reservation::Reserved) => break,
+            Ok(Reservation::Stored(stored)) if stored.request_digest == request_digest => return replay(stored),
+            Ok(Reservation::Pending(pending)) if pending.request_digest == request_digest => {
+                if tokio::time::Instant::now() >= wait_until {
+                    return connect_error(
+                        StatusCode::CONFLICT,
+                        "aborted",
+                        "a request with this Idempotency-Key is still in progress",
+                    );
+                }
+                tokio::time::sleep(PENDING_POLL_INTERVAL).await;
+            }
+            Ok(_) => {
+                return connect_error(
+                    StatusCode::BAD_REQUEST,
+                    "invalid_argument",
+                    "Idempotency-Key was already used for a different request",
+                );
+            }
+            Err(e) => {
+                tracing::warn!("Idempotency store unavailable: {}", e);
+                return connect_error(StatusCode::SERVICE_UNAVAILABLE, "unavailable", "idempotency store unavailable");
+            }
+        }
+    }
+
+    let response = next.run(Request::from_parts(parts, Body::from(body))).await;
+    if response.status().is_server_error() {
+        release(store.as_ref(), &store_key).await;
+        return response;
+    }
+
+    let (parts, body) = response.into_parts();
+    let (parts, body) = match axum::body::to_bytes(body, max_message_bytes).await {
+        Ok(body) => (parts, body),
+        // The call ran, so the key stays taken and retries get the same error
+        Err(_) => {
+            let error = connect_error(
+                StatusCode::TOO_MANY_REQUESTS,
+                "resource_exhausted",
+                format!("response message exceeds the {} byte limit", max_message_bytes),
+            );
+            let (parts, body) = error.into_parts();
+            (parts, axum::body::to_bytes(body, usize::MAX).await.unwrap_or_default())
+        }
+    };
+
+    let stored = StoredResponse {
+        request_digest,
+        status: parts.status.as_u16(),
+        content_type: parts
+            .headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(str::to_string),
+        body: body.to_vec(),
+        expires_at: Utc::now() + ttl,
+    };
+    if let Err(e) = store.put(&store_key, stored).await {
+        tracing::warn!("Failed to store response for idempotency key {}: {}", key, e);
+    }
+
+    Response::from_parts(parts, Body::from(body))
+}
+
+/// Free a reserved key after a call whose response is not stored
+async fn release(store: &dyn IdempotencyStore, key: &str) {
+    if let Err(e) = store.release(key).await {
+        tracing::warn!("Failed to release idempotency key {}: {}", key, e);
+    }
+}
+
+/// Build the response replaying a stored one
+fn replay(stored: StoredResponse) -> Response {
+    let mut response = Response::new(Body::from(stored.body));
+    *response.status_mut() = StatusCode::from_u16(stored.status).unwrap_or(StatusCode::OK);
+    let headers = response.headers_mut();
+    if let Some(content_type) = stored.content_type.and_then(|v| HeaderValue::from_str(&v).ok()) {
+        headers.insert(header::CONTENT_TYPE, content_type);
+    }
+    headers.insert(REPLAYED_HEADER, HeaderValue::from_static("true"));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::idempotency::MemoryIdempotencyStore;
+    use axum::middleware;
+    use axum::routing::post;
+    use axum::Router;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn test_oversized_response_keeps_key() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let store: Arc<dyn IdempotencyStore> = Arc::new(MemoryIdempotencyStore::new());
+        let counted = calls.clone();
+        let app = Router::new()
+            .route(
+                "/workflow.WorkflowService/Big",
+                post(move || {
+                    counted.fetch_add(1, Ordering::SeqCst);
+                    async { vec![0u8; 64] }
+                }),
+            )
+            .layer(middleware::from_fn(move |request: Request, next: Next| {
+                replay_with(store.clone(), chrono::Duration::minutes(1), 16, request, next)
+            }));
+
+        let call = || {
+            axum::http::Request::post("/workflow.WorkflowService/Big")
+                .header(header::CONTENT_TYPE, "application/proto")
+                .header(IDEMPOTENCY_KEY_HEADER, "retry-me")
+                .body(Body::from("request"))
+                .unwrap()
+        };
+
+        let first = app.clone().oneshot(call()).await.unwrap();
+        assert_eq!(first.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(first.headers().get(REPLAYED_HEADER).is_none());
+
+        let retry = app.oneshot(call()).await.unwrap();
+        assert_eq!(retry.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(retry.headers()[REPLAYED_HEADER], "true");
+        let body = axum::body::to_bytes(retry.into_body(), usize::MAX).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("resource_exhausted"));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+}
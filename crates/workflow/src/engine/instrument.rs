@@ -0,0 +1,171 @@
+//! Metrics and tracing of Connect RPC calls
+//!
+//! Every call to the workflow service is counted and timed per method and
+//! Connect error code, and handled inside an `rpc` span naming its method.
+//! gRPC calls reach this layer translated into Connect calls and are
+//! recorded the same way; calls of workers embedded in the engine are
+//! recorded by [`instrument_local`].
+//! Methods can opt out with [`super::WorkflowEngine::without_rpc_instrumentation`],
+//! e.g. to keep frequent heartbeats out of traces.
+
+use super::WorkflowEngine;
+use crate::error::{RpcError, RpcResult};
+use axum::body::{Body, HttpBody};
+use axum::extract::{Request, State};
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::Response;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::Instrument;
+
+/// Counter of RPC calls, by method and Connect code
+const REQUESTS_METRIC: &str = "workflow_rpc_requests_total";
+
+/// Histogram of RPC handling time, by method
+const DURATION_METRIC: &str = "workflow_rpc_duration_seconds";
+
+const SERVICE_PATH: &str = "/workflow.WorkflowService/";
+
+/// Largest error body read to find its Connect code
+const MAX_ERROR_BODY_BYTES: usize = 64 * 1024;
+
+/// Record metrics and open a span for workflow service calls
+pub(super) async fn instrument_rpc(
+    State(engine): State<Arc<WorkflowEngine>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(method) = request.uri().path().strip_prefix(SERVICE_PATH).map(str::to_string) else {
+        return next.run(request).await;
+    };
+    if !engine.instruments_rpc(&method) {
+        return next.run(request).await;
+    }
+    record_rpc(method, request, next).await
+}
+
+/// Run a call to `method` inside its span and record its metrics
+pub(super) async fn record_rpc(method: String, request: Request, next: Next) -> Response {
+    let start = Instant::now();
+    let response = next.run(request).instrument(rpc_span(&method)).await;
+    let (code, response) = connect_code(response).await;
+
+    record_metrics(method, code, start);
+    response
+}
+
+/// Run a call of a worker embedded in the engine inside its span and
+/// record its metrics, like calls arriving over HTTP
+pub(super) async fn instrument_local<T>(
+    engine: &WorkflowEngine,
+    method: &str,
+    call: impl Future<Output = RpcResult<T>>,
+) -> RpcResult<T> {
+    if !engine.instruments_rpc(method) {
+        return call.await;
+    }
+
+    let start = Instant::now();
+    let result = call.instrument(rpc_span(method)).await;
+    let code = match &result {
+        Ok(_) => "ok",
+        Err(e) => local_code(e),
+    };
+
+    record_metrics(method.to_string(), code.to_string(), start);
+    result
+}
+
+fn rpc_span(method: &str) -> tracing::Span {
+    tracing::info_span!("rpc", rpc.service = "workflow.WorkflowService", rpc.method = %method)
+}
+
+fn record_metrics(method: String, code: String, start: Instant) {
+    metrics::counter!(REQUESTS_METRIC, "method" => method.clone(), "code" => code).increment(1);
+    metrics::histogram!(DURATION_METRIC, "method" => method).record(start.elapsed().as_secs_f64());
+}
+
+/// Connect code of a failed local call
+fn local_code(error: &RpcError) -> &'static str {
+    match error {
+        RpcError::Timeout => "deadline_exceeded",
+        RpcError::ResourceExhausted(_) => "resource_exhausted",
+        RpcError::PermissionDenied(_) => "permission_denied",
+        RpcError::Retried { source, .. } => local_code(source),
+        _ => "internal",
+    }
+}
+
+/// Find the Connect code of a response, reading it from the error body
+///
+/// Streaming responses report errors in their trailer and count as `ok`
+/// once they started.
+async fn connect_code(response: Response) -> (String, Response) {
+    if response.status() == StatusCode::OK {
+        return ("ok".to_string(), response);
+    }
+    let fallback = http_code(response.status()).to_string();
+    if response.body().size_hint().upper().is_none_or(|len| len > MAX_ERROR_BODY_BYTES as u64) {
+        return (fallback, response);
+    }
+
+    let (parts, body) = response.into_parts();
+    let Ok(bytes) = axum::body::to_bytes(body, MAX_ERROR_BODY_BYTES).await else {
+        return (fallback, Response::from_parts(parts, Body::empty()));
+    };
+    let code = serde_json::from_slice::<serde_json::Value>(&bytes)
+        .ok()
+        .and_then(|error| error.get("code")?.as_str().map(str::to_string))
+        .unwrap_or(fallback);
+    (code, Response::from_parts(parts, Body::from(bytes)))
+}
+
+/// Connect code implied by an HTTP status, following the Connect protocol
+pub(super) fn http_code(status: StatusCode) -> &'static str {
+    match status.as_u16() {
+        400 => "internal",
+        401 => "unauthenticated",
+        403 => "permission_denied",
+        404 => "unimplemented",
+        429 | 502 | 503 | 504 => "unavailable",
+        _ => "unknown",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_connect_code_from_error_body() {
+        let response = Response::builder()
+            .status(StatusCode::TOO_MANY_REQUESTS)
+            .body(Body::from(r#"{"code":"resource_exhausted","message":"too big"}"#))
+            .unwrap();
+        let (code, response) = connect_code(response).await;
+        assert_eq!(code, "resource_exhausted");
+
+        // The body is passed on untouched
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(body.starts_with(b"{\"code\""));
+    }
+
+    #[test]
+    fn test_local_code() {
+        assert_eq!(local_code(&RpcError::Timeout), "deadline_exceeded");
+        let retried = RpcError::Retried {
+            attempts: 3,
+            source: Box::new(RpcError::ResourceExhausted("too big".to_string())),
+        };
+        assert_eq!(local_code(&retried), "resource_exhausted");
+        assert_eq!(local_code(&RpcError::Protocol("bad".to_string())), "internal");
+    }
+
+    #[tokio::test]
+    async fn test_connect_code_falls_back_to_status() {
+        let response = Response::builder().status(StatusCode::NOT_FOUND).body(Body::from("not json")).unwrap();
+        assert_eq!(connect_code(response).await.0, "unimplemented");
+    }
+}
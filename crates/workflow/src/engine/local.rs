@@ -0,0 +1,115 @@
+//! In-process transport for workers embedded in the engine
+//!
+//! The worker and the server each include the generated protobuf code, so
+//! requests and responses are re-encoded between the two copies; no socket
+//! is involved. Calls pass the engine's RPC authorizers and are recorded in
+//! the RPC metrics like remote ones.
+
+use super::server::{
+    complete_task_handler, deregister_worker_handler, fetch_task_code_handler, heartbeat_handler, kv_get_handler,
+    kv_range_handler, poll_tasks_handler, register_worker_handler,
+};
+use super::authorize;
+use super::instrument::instrument_local;
+use super::WorkflowEngine;
+use crate::access::{RpcCall, RpcCodec};
+use crate::error::{RpcError, RpcResult};
+use crate::worker::proto::*;
+use crate::worker::EngineClient;
+use async_trait::async_trait;
+use axum::extract::State;
+use axum::http::HeaderMap;
+use prost::Message;
+use std::future::Future;
+use std::sync::Arc;
+
+/// Routes worker calls straight to the engine's RPC handlers
+pub(crate) struct LocalTransport {
+    engine: Arc<WorkflowEngine>,
+    /// Headers shown to RPC authorizers with every call
+    headers: HeaderMap,
+}
+
+impl LocalTransport {
+    pub(crate) fn new(engine: Arc<WorkflowEngine>, headers: HeaderMap) -> Self {
+        Self { engine, headers }
+    }
+
+    fn state(&self) -> State<Arc<WorkflowEngine>> {
+        State(self.engine.clone())
+    }
+
+    /// Pass a worker request to the handler of `method` and hand its
+    /// response back
+    async fn relay<Req, HandlerReq, HandlerRes, Res, F, Fut>(
+        &self,
+        method: &str,
+        request: Req,
+        handler: F,
+    ) -> RpcResult<Res>
+    where
+        Req: Message,
+        HandlerReq: Message + Default,
+        HandlerRes: Message,
+        Res: Message + Default,
+        F: FnOnce(HandlerReq) -> Fut,
+        Fut: Future<Output = HandlerRes>,
+    {
+        instrument_local(&self.engine, method, async {
+            let message = request.encode_to_vec();
+            let call = RpcCall {
+                method,
+                headers: &self.headers,
+                message: &message,
+                codec: RpcCodec::Proto,
+            };
+            authorize::check(&self.engine, &call).await.map_err(RpcError::PermissionDenied)?;
+
+            let request = HandlerReq::decode(message.as_slice())
+                .map_err(|e| RpcError::Protocol(format!("Invalid request: {}", e)))?;
+            let response = handler(request).await;
+            Res::decode(response.encode_to_vec().as_slice())
+                .map_err(|e| RpcError::Protocol(format!("Invalid response: {}", e)))
+        })
+        .await
+    }
+}
+
+#[async_trait]
+impl EngineClient for LocalTransport {
+    async fn register_worker(&self, request: RegisterWorkerRequest) -> RpcResult<RegisterWorkerResponse> {
+        self.relay("RegisterWorker", request, |req| register_worker_handler(self.state(), req)).await
+    }
+
+    async fn poll_tasks(&self, request: PollTasksRequest) -> RpcResult<PollTasksResponse> {
+        self.relay("PollTasks", request, |req| poll_tasks_handler(self.state(), req)).await
+    }
+
+    async fn fetch_task_code(&self, request: FetchTaskCodeRequest) -> RpcResult<FetchTaskCodeResponse> {
+        self.relay("FetchTaskCode", request, |req| fetch_task_code_handler(self.state(), req)).await
+    }
+
+    async fn complete_task(&self, request: CompleteTaskRequest) -> RpcResult<CompleteTaskResponse> {
+        self.relay("CompleteTask", request, |req| complete_task_handler(self.state(), req)).await
+    }
+
+    async fn heartbeat(&self, request: HeartbeatRequest) -> RpcResult<HeartbeatResponse> {
+        self.relay("Heartbeat", request, |req| heartbeat_handler(self.state(), req)).await
+    }
+
+    async fn deregister_worker(&self, request: DeregisterWorkerRequest) -> RpcResult<DeregisterWorkerResponse> {
+        self.relay("DeregisterWorker", request, |req| deregister_worker_handler(self.state(), req)).await
+    }
+
+    async fn kv_get(&self, request: KvGetRequest) -> RpcResult<KvGetResponse> {
+        self.relay("KvGet", request, |req| kv_get_handler(self.state(), req)).await
+    }
+
+    async fn kv_range(&self, request: KvRangeRequest) -> RpcResult<KvRangeResponse> {
+        self.relay("KvRange", request, |req| kv_range_handler(self.state(), req)).await
+    }
+
+    fn supports_streaming(&self) -> bool {
+        false
+    }
+}
@@ -1,25 +1,124 @@
 //! Workflow engine implementation
 
+mod authorize;
+mod export;
+#[cfg(feature = "graphql")]
+mod graphql;
+mod grpc;
+mod idempotency;
+mod instrument;
+mod local;
 mod registry;
 mod scheduler;
 mod server;
+mod tenant;
+mod tls;
+#[cfg(feature = "websocket")]
+mod websocket;
 
+#[cfg(feature = "graphql")]
+pub use graphql::{FieldPolicy, FieldRolePolicy, GraphQlGateway};
 pub use registry::WorkflowRegistry;
 pub use scheduler::TaskScheduler;
 pub use server::run_server;
+pub use tls::ServerTls;
 
-use crate::error::{EngineError, Result};
+pub(crate) use local::LocalTransport;
+
+use crate::access::{AccessPolicy, AllowAll, InstanceAction, Principal, RpcAuthorizer, TenantAuthorizer};
+use crate::idempotency::IdempotencyStore;
+use crate::error::{EngineError, Result, SearchError, WorkflowError};
 use crate::persistence::PersistenceLayer;
-use crate::state_machine::Context;
+use crate::search::{Indexer, SearchIndex, SearchQuery, SearchResults, SearchSchema};
+use crate::snapshot::{RestoreOptions, RestoreReport, Snapshot};
+use crate::telemetry;
+use crate::state_machine::{Action, Context, Fork, HttpRequest, is_valid_result_path, set_path};
+use crate::transfer::MessageLimits;
 use crate::types::{
-    TaskDefinition, TaskExecution, TaskId, TaskStatus, WorkflowDefinition, WorkflowId,
-    WorkflowInstance, WorkflowStatus,
+    Annotation, AnnotationContent, AnnotationId, ArchivedInstance, AwaitedTasks, Backpressure, BranchState, Compensation, CompensationStep,
+    Deadline, EventReaction, EventSubscription, HistoryEvent, HistoryEventKind, HttpCall, HumanTask, HumanTaskId,
+    HumanTaskStatus, InstanceCursor, InstanceFilter, InstancePage, ParentLink, PublishedEvent,
+    RetentionAction, ScheduledTimer, TaskDefinition, TaskExecution, TaskId, TaskStatus, WorkerHealthStatus, WorkerId, WorkerInfo,
+    WorkflowDefinition, WorkflowId, WorkflowInstance, WorkflowStatus, is_valid_topic,
 };
-use chrono::Utc;
+use chrono::{DateTime, Utc};
+use degov_crypto::DidKey;
 use foundationdb::Database;
 use parking_lot::RwLock;
+use std::collections::{HashMap, HashSet};
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
+
+/// How often the engine checks for expired timers
+const TIMER_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Maximum number of timers fired per poll
+const TIMER_BATCH_SIZE: usize = 100;
+
+/// How long a claimed timer waits before it fires again, in case the
+/// firing engine never finished it
+const TIMER_LEASE: Duration = Duration::from_secs(30);
+
+/// How often the engine delivers events published on the event bus
+const EVENT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Maximum number of events delivered per subscription and poll
+const EVENT_BATCH_SIZE: usize = 100;
+
+/// Number of instances scanned per storage read when signalling
+const SIGNAL_BATCH_SIZE: usize = 500;
+
+/// How often the engine sends due HTTP requests
+const HTTP_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Maximum number of HTTP requests sent per poll
+const HTTP_BATCH_SIZE: usize = 100;
+
+/// How often the engine starts queued instances whose definition has a
+/// free running slot
+const ADMISSION_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Default interval at which workers are expected to send heartbeats
+const DEFAULT_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Default number of heartbeats in a row a worker may miss before it is
+/// considered dead
+const DEFAULT_MAX_MISSED_HEARTBEATS: u32 = 3;
+
+/// Default number of tenants an engine serves at once
+const DEFAULT_MAX_TENANTS: usize = 1000;
+
+/// How often the engine looks for lost workers and orphaned tasks
+const RECOVERY_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Number of tasks or instances scanned per storage read during recovery
+const RECOVERY_BATCH_SIZE: usize = 500;
+
+/// How often the engine archives instances past their retention period
+const ARCHIVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Number of instances scanned per storage read when archiving
+const ARCHIVE_BATCH_SIZE: usize = 100;
+
+/// Number of history events read per storage read
+const HISTORY_PAGE_SIZE: usize = 500;
+
+/// Number of definitions, instances or timers read per storage read when
+/// taking a snapshot
+const SNAPSHOT_PAGE_SIZE: usize = 500;
+
+/// Maximum length of an idempotency key in bytes
+const MAX_IDEMPOTENCY_KEY_BYTES: usize = 256;
+
+/// Maximum length of a comment body in bytes
+const MAX_COMMENT_BYTES: usize = 16 * 1024;
+
+/// Event fired on a parent once all awaited child workflows have completed
+pub const CHILD_COMPLETED_EVENT: &str = "child_completed";
+
+/// Name of the timers of deadlines that fail the instance
+const TIMED_OUT_EVENT: &str = "timed_out";
 
 /// Main workflow engine
 pub struct WorkflowEngine {
@@ -27,6 +126,27 @@ pub struct WorkflowEngine {
     registry: Arc<RwLock<WorkflowRegistry>>,
     scheduler: Arc<TaskScheduler>,
     bind_addr: SocketAddr,
+    limits: MessageLimits,
+    search: Option<(Arc<dyn SearchIndex>, Arc<SearchSchema>)>,
+    access: Arc<dyn AccessPolicy>,
+    heartbeat_interval: Duration,
+    max_missed_heartbeats: u32,
+    trusted_signers: Vec<DidKey>,
+    http: reqwest::Client,
+    /// RPC methods left out of metrics and traces
+    uninstrumented_rpc: HashSet<String>,
+    /// Store replaying responses to retried calls, and how long they are kept
+    idempotency: Option<(Arc<dyn IdempotencyStore>, chrono::Duration)>,
+    /// Checks guarding RPC methods, by method name
+    rpc_authorizers: HashMap<String, Arc<dyn RpcAuthorizer>>,
+    /// Check admitting calls to the tenants they name
+    tenant_authorizer: Option<Arc<dyn TenantAuthorizer>>,
+    /// Most tenants served at once
+    max_tenants: usize,
+    /// TLS the RPC server is served with, plaintext HTTP when unset
+    tls: Option<Arc<ServerTls>>,
+    #[cfg(feature = "graphql")]
+    graphql: Option<Arc<GraphQlGateway>>,
 }
 
 impl WorkflowEngine {
@@ -42,14 +162,192 @@ impl WorkflowEngine {
             .await
             .map_err(|e| EngineError::Internal(format!("Database health check failed: {}", e)))?;
 
+        // Instances saved before the secondary indexes existed are not listed
+        // until indexed
+        persistence.workflows().ensure_indexes().await?;
+
         Ok(Self {
             persistence,
             registry,
             scheduler,
             bind_addr,
+            limits: MessageLimits::default(),
+            search: None,
+            access: Arc::new(AllowAll),
+            heartbeat_interval: DEFAULT_HEARTBEAT_INTERVAL,
+            max_missed_heartbeats: DEFAULT_MAX_MISSED_HEARTBEATS,
+            trusted_signers: Vec::new(),
+            http: reqwest::Client::new(),
+            uninstrumented_rpc: HashSet::new(),
+            idempotency: None,
+            rpc_authorizers: HashMap::new(),
+            tenant_authorizer: None,
+            max_tenants: DEFAULT_MAX_TENANTS,
+            tls: None,
+            #[cfg(feature = "graphql")]
+            graphql: None,
+        })
+    }
+
+    /// Set the RPC message size limits
+    pub fn with_message_limits(mut self, limits: MessageLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Enable instance search over the fields declared in `schema`
+    ///
+    /// The index is kept up to date by an indexer tailing the change feed
+    /// while the engine runs.
+    pub fn with_search(mut self, index: Arc<dyn SearchIndex>, schema: SearchSchema) -> Self {
+        self.search = Some((index, Arc::new(schema)));
+        self
+    }
+
+    /// Set the policy deciding who may read and annotate instances
+    pub fn with_access_policy(mut self, policy: Arc<dyn AccessPolicy>) -> Self {
+        self.access = policy;
+        self
+    }
+
+    /// Set the interval at which workers are expected to send heartbeats
+    ///
+    /// A worker is marked degraded once an interval passes without one.
+    pub fn with_heartbeat_interval(mut self, interval: Duration) -> Self {
+        self.heartbeat_interval = interval;
+        self
+    }
+
+    /// Set how many heartbeats in a row a worker may miss before it is marked
+    /// dead and its tasks are reassigned
+    ///
+    /// The time this takes is also the visibility timeout of the lease a
+    /// worker holds on a polled task, extended with every heartbeat reporting
+    /// the task.
+    pub fn with_max_missed_heartbeats(mut self, missed: u32) -> Self {
+        self.max_missed_heartbeats = missed.max(1);
+        self
+    }
+
+    /// Get the RPC message size limits
+    pub fn message_limits(&self) -> &MessageLimits {
+        &self.limits
+    }
+
+    /// Leave calls to the RPC `method`, e.g. `"Heartbeat"`, out of the
+    /// per-method metrics and `rpc` spans recorded for every call
+    pub fn without_rpc_instrumentation(mut self, method: impl Into<String>) -> Self {
+        self.uninstrumented_rpc.insert(method.into());
+        self
+    }
+
+    /// Replay responses to unary calls retried with the same
+    /// `Idempotency-Key` header for `ttl`
+    ///
+    /// `persistence().idempotency_keys()` stores responses in FoundationDB,
+    /// shared by all engines; expired ones are purged by the archiver.
+    pub fn with_idempotency(mut self, store: Arc<dyn IdempotencyStore>, ttl: Duration) -> Self {
+        let ttl = chrono::Duration::from_std(ttl).unwrap_or(chrono::Duration::MAX);
+        self.idempotency = Some((store, ttl));
+        self
+    }
+
+    /// Require calls to the RPC `method`, e.g. `"CompleteTask"`, to pass
+    /// `authorizer`, replacing an earlier one for the method
+    ///
+    /// The check applies to Connect, gRPC, gRPC-Web and WebSocket calls
+    /// alike, and to the calls of embedded workers. Methods without an
+    /// authorizer are open to every caller.
+    pub fn with_rpc_authorizer(mut self, method: impl Into<String>, authorizer: Arc<dyn RpcAuthorizer>) -> Self {
+        self.rpc_authorizers.insert(method.into(), authorizer);
+        self
+    }
+
+    /// Serve calls naming a tenant in the `x-tenant-did` header once
+    /// `authorizer` admits the caller to that tenant
+    ///
+    /// Calls naming a tenant are refused while no authorizer is set.
+    pub fn with_tenant_authorizer(mut self, authorizer: Arc<dyn TenantAuthorizer>) -> Self {
+        self.tenant_authorizer = Some(authorizer);
+        self
+    }
+
+    /// Serve RPC calls over TLS
+    ///
+    /// With client roots set, callers must present a certificate issued by
+    /// one of them, so workers and engine authenticate each other.
+    pub fn with_tls(mut self, tls: ServerTls) -> Self {
+        self.tls = Some(Arc::new(tls));
+        self
+    }
+
+    /// Set how many tenants are served at once
+    ///
+    /// Calls naming a tenant not open yet are refused with
+    /// `resource_exhausted` once that many are.
+    pub fn with_max_tenants(mut self, max: usize) -> Self {
+        self.max_tenants = max;
+        self
+    }
+
+    /// Whether calls to the RPC `method` are recorded
+    fn instruments_rpc(&self, method: &str) -> bool {
+        !self.uninstrumented_rpc.contains(method)
+    }
+
+    /// Only accept workflow definitions whose task code is attested by one
+    /// of `signers`
+    pub fn with_trusted_signers(mut self, signers: Vec<DidKey>) -> Self {
+        self.trusted_signers = signers;
+        self
+    }
+
+    /// Serve read-only GraphQL queries at `/graphql`
+    #[cfg(feature = "graphql")]
+    pub fn with_graphql(mut self, gateway: GraphQlGateway) -> Self {
+        self.graphql = Some(Arc::new(gateway));
+        self
+    }
+
+    /// Open an engine working on the key space of the tenant identified by
+    /// `did`
+    ///
+    /// It shares this engine's configuration and registered definitions,
+    /// but keeps its instances, tasks, timers and workers apart. Search is
+    /// not enabled for tenants, as the index is shared.
+    pub async fn for_tenant(&self, did: &DidKey) -> Result<WorkflowEngine> {
+        let persistence = Arc::new(self.persistence.for_tenant(did).await?);
+        persistence.workflows().ensure_indexes().await?;
+
+        Ok(Self {
+            scheduler: Arc::new(TaskScheduler::new(persistence.clone())),
+            persistence,
+            registry: self.registry.clone(),
+            bind_addr: self.bind_addr,
+            limits: self.limits,
+            search: None,
+            access: self.access.clone(),
+            heartbeat_interval: self.heartbeat_interval,
+            max_missed_heartbeats: self.max_missed_heartbeats,
+            trusted_signers: self.trusted_signers.clone(),
+            http: self.http.clone(),
+            uninstrumented_rpc: self.uninstrumented_rpc.clone(),
+            idempotency: self.idempotency.clone(),
+            rpc_authorizers: self.rpc_authorizers.clone(),
+            tenant_authorizer: self.tenant_authorizer.clone(),
+            max_tenants: self.max_tenants,
+            tls: self.tls.clone(),
+            #[cfg(feature = "graphql")]
+            graphql: self.graphql.clone(),
         })
     }
 
+    /// Get the GraphQL gateway, if enabled
+    #[cfg(feature = "graphql")]
+    fn graphql(&self) -> Option<&Arc<GraphQlGateway>> {
+        self.graphql.as_ref()
+    }
+
     /// Register a workflow definition
     pub async fn register_workflow(&self, definition: WorkflowDefinition) -> Result<WorkflowId> {
         // Validate the state machine
@@ -58,6 +356,114 @@ impl WorkflowEngine {
             .validate()
             .map_err(EngineError::Workflow)?;
 
+        // Subscriptions must name a valid topic and signal an event the
+        // workflow handles
+        for subscription in &definition.subscriptions {
+            if !is_valid_topic(&subscription.topic) {
+                return Err(WorkflowError::InvalidTopic(subscription.topic.clone()).into());
+            }
+            if let EventReaction::Signal { event, .. } = &subscription.reaction
+                && !definition.state_machine.handles_event(event)
+            {
+                return Err(WorkflowError::InvalidDefinition(format!(
+                    "Subscription to '{}' signals event '{}' without a matching transition",
+                    subscription.topic, event
+                ))
+                .into());
+            }
+        }
+
+        // Deadlines must belong to a state and fire an event the workflow
+        // handles
+        let deadlines = definition.timeout.iter().map(|d| (None, d));
+        let state_deadlines = definition.state_timeouts.iter().map(|(s, d)| (Some(s), d));
+        for (state, deadline) in deadlines.chain(state_deadlines) {
+            if let Some(state) = state
+                && definition.state_machine.get_state(state).is_none()
+            {
+                return Err(WorkflowError::InvalidDefinition(format!(
+                    "Timeout set for unknown state '{}'",
+                    state
+                ))
+                .into());
+            }
+            if let Some(state) = state
+                && definition.state_machine.get_state(state).is_some_and(|s| s.is_composite())
+            {
+                return Err(WorkflowError::InvalidDefinition(format!(
+                    "Timeout set for composite state '{}'",
+                    state
+                ))
+                .into());
+            }
+            if let Some(event) = &deadline.on_timeout
+                && !definition.state_machine.handles_event(event)
+            {
+                return Err(WorkflowError::InvalidDefinition(format!(
+                    "Timeout fires event '{}' without a matching transition",
+                    event
+                ))
+                .into());
+            }
+            if duration_ms(deadline.after_ms).is_none() {
+                return Err(WorkflowError::InvalidDefinition(format!(
+                    "Timeout of {}ms is out of range",
+                    deadline.after_ms
+                ))
+                .into());
+            }
+        }
+
+        // A zero limit would never let an instance run or a task dispatch
+        if definition.limits.max_running_instances == Some(0) || definition.limits.max_tasks_per_second == Some(0) {
+            return Err(WorkflowError::InvalidDefinition("Limits must be at least 1".to_string()).into());
+        }
+
+        // A zero retention period would archive instances as they finish
+        if let Some(retention) = &definition.retention {
+            if retention.keep_for_ms == 0 {
+                return Err(WorkflowError::InvalidDefinition("Retention period must be at least 1ms".to_string()).into());
+            }
+            if duration_ms(retention.keep_for_ms).is_none() {
+                return Err(WorkflowError::InvalidDefinition(format!(
+                    "Retention period of {}ms is out of range",
+                    retention.keep_for_ms
+                ))
+                .into());
+            }
+        }
+
+        // A zero weight would never serve the queue
+        if definition.scheduling.weight == 0 {
+            return Err(WorkflowError::InvalidDefinition("Queue weight must be at least 1".to_string()).into());
+        }
+
+        // Queue names are ended by a NUL byte in queue keys
+        if let Some(queue) = &definition.scheduling.queue
+            && (queue.is_empty() || queue.contains('\0'))
+        {
+            return Err(WorkflowError::InvalidDefinition(format!("Invalid queue name '{}'", queue)).into());
+        }
+
+        // Index paths are ended by a NUL byte in index keys
+        for path in &definition.indexes {
+            if !is_valid_result_path(path) || path.contains('\0') {
+                return Err(WorkflowError::InvalidDefinition(format!("Invalid index path '{}'", path)).into());
+            }
+        }
+
+        // Deployed code must be signed by the stack when signers are configured
+        if !self.trusted_signers.is_empty() {
+            for task in definition.state_machine.tasks() {
+                task.verify_code(&self.trusted_signers).map_err(|e| {
+                    EngineError::Workflow(WorkflowError::UntrustedCode {
+                        task: task.name.clone(),
+                        reason: e.to_string(),
+                    })
+                })?;
+            }
+        }
+
         // Save to persistence
         self.persistence
             .workflows()
@@ -65,19 +471,112 @@ impl WorkflowEngine {
             .await
             .map_err(EngineError::Persistence)?;
 
-        // Add to registry
+        // Add to registry; definitions sharing a queue set its weight in turn
         let id = definition.id;
+        self.scheduler.set_queue_weight(&definition.fair_queue(), definition.scheduling.weight);
         self.registry.write().register(definition);
 
         tracing::info!("Registered workflow: {}", id);
         Ok(id)
     }
 
+    /// Register the workflow defined by a DGL document
+    ///
+    /// See [`crate::dgl`] for the states, tasks and transitions a document
+    /// can declare.
+    pub async fn register_from_dgl(&self, source: &str) -> Result<WorkflowId> {
+        let definition = crate::dgl::parse(source).map_err(EngineError::Workflow)?;
+        self.register_workflow(definition).await
+    }
+
     /// Start a workflow instance
     pub async fn start_workflow(
         &self,
         definition_id: &WorkflowId,
         input: serde_json::Value,
+    ) -> Result<WorkflowInstance> {
+        self.create_instance(WorkflowId::new(), definition_id, input, None, None).await
+    }
+
+    /// Start a workflow instance at most once per idempotency key
+    ///
+    /// When an instance of the definition was already started with `key`, it
+    /// is returned as it is now and `input` is ignored, so clients can retry
+    /// a start without creating duplicates. Keys are released when their
+    /// instance is deleted.
+    pub async fn start_workflow_with_key(
+        &self,
+        definition_id: &WorkflowId,
+        input: serde_json::Value,
+        key: impl Into<String>,
+    ) -> Result<WorkflowInstance> {
+        let key = key.into();
+        if key.is_empty() || key.len() > MAX_IDEMPOTENCY_KEY_BYTES {
+            return Err(WorkflowError::InvalidIdempotencyKey(format!(
+                "must be between 1 and {} bytes long",
+                MAX_IDEMPOTENCY_KEY_BYTES
+            ))
+            .into());
+        }
+        self.create_instance(WorkflowId::new(), definition_id, input, None, Some(key)).await
+    }
+
+    /// Start a child workflow on behalf of a parent instance
+    pub async fn start_child_workflow(
+        &self,
+        parent_id: &WorkflowId,
+        definition_id: &WorkflowId,
+        input: serde_json::Value,
+        await_completion: bool,
+        result_key: Option<String>,
+    ) -> Result<WorkflowInstance> {
+        let parent = ParentLink {
+            workflow_id: *parent_id,
+            result_key,
+            await_completion,
+        };
+
+        // Block the parent before the child's initial actions run, as they
+        // may complete the child right away; a queued child is awaited as well
+        let child_id = WorkflowId::new();
+        if await_completion {
+            self.persistence
+                .workflows()
+                .add_awaited_child(parent_id, &child_id)
+                .await
+                .map_err(EngineError::Persistence)?;
+        }
+
+        let child = match self.create_instance(child_id, definition_id, input, Some(parent), None).await {
+            Ok(child) => child,
+            Err(e) => {
+                if await_completion {
+                    self.persistence
+                        .workflows()
+                        .resolve_child(parent_id, &child_id, None, serde_json::Value::Null)
+                        .await?;
+                }
+                return Err(e);
+            }
+        };
+
+        tracing::info!("Workflow {} started child workflow {}", parent_id, child.id);
+        Ok(child)
+    }
+
+    /// Create, persist and enter the initial state of a workflow instance
+    /// with the given id
+    ///
+    /// Returns the existing instance instead when another one already holds
+    /// `idempotency_key`.
+    #[tracing::instrument(name = "workflow.create", skip_all, fields(definition_id = %definition_id))]
+    async fn create_instance(
+        &self,
+        id: WorkflowId,
+        definition_id: &WorkflowId,
+        input: serde_json::Value,
+        parent: Option<ParentLink>,
+        idempotency_key: Option<String>,
     ) -> Result<WorkflowInstance> {
         // Get workflow definition
         let definition = self
@@ -88,8 +587,8 @@ impl WorkflowEngine {
             .clone();
 
         // Create workflow instance
-        let instance = WorkflowInstance {
-            id: WorkflowId::new(),
+        let mut instance = WorkflowInstance {
+            id,
             definition_id: *definition_id,
             current_state: definition.state_machine.initial_state().to_string(),
             context: input,
@@ -97,27 +596,139 @@ impl WorkflowEngine {
             created_at: Utc::now(),
             updated_at: Utc::now(),
             completed_at: None,
+            parent,
+            awaiting_children: Vec::new(),
+            compensation: None,
+            branches: Vec::new(),
+            awaited_tasks: Vec::new(),
+            idempotency_key,
+            state_entry: 0,
+            paused_at: None,
+            held_tasks: Vec::new(),
         };
 
-        // Save instance
-        self.persistence
-            .workflows()
-            .save_instance(&instance)
-            .await
-            .map_err(EngineError::Persistence)?;
+        // Save instance, queued when the definition runs at its limit
+        let max_running = definition.limits.max_running_instances;
+        if let Some(existing) = self.persistence.workflows().insert_instance(&mut instance, max_running).await? {
+            tracing::info!(
+                "Workflow instance {} already started with idempotency key",
+                existing.id
+            );
+            return Ok(existing);
+        }
+        if instance.status == WorkflowStatus::Pending {
+            tracing::info!("Queued workflow instance {} until a running slot frees up", instance.id);
+            return Ok(instance);
+        }
 
-        // Execute initial state actions
-        self.execute_state_actions(&instance, &definition).await?;
+        self.enter_initial_state(&instance, &definition).await?;
 
         tracing::info!("Started workflow instance: {}", instance.id);
         Ok(instance)
     }
 
+    /// Execute the initial state actions of a new or admitted instance and
+    /// start its timers
+    async fn enter_initial_state(&self, instance: &WorkflowInstance, definition: &WorkflowDefinition) -> Result<()> {
+        // Composite states around the initial state are entered first
+        let mut entered: Vec<&str> = definition
+            .state_machine
+            .ancestry(&instance.current_state)
+            .map(|state| state.name())
+            .collect();
+        entered.reverse();
+        for state in entered {
+            self.execute_state_actions(&instance.id, state, definition, None)
+                .await?;
+        }
+        let (state, entry) = (&instance.current_state, instance.state_entry);
+        self.schedule_state_timers(&instance.id, definition, state, None, entry)
+            .await?;
+        if let Some(deadline) = &definition.timeout {
+            self.schedule_deadline(&instance.id, state, None, entry, deadline, true)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Start queued instances of definitions with a running instance limit
+    /// while they have free slots
+    pub async fn admit_queued_instances(&self) -> Result<usize> {
+        let limited: Vec<(WorkflowDefinition, u32)> = {
+            let registry = self.registry.read();
+            registry
+                .list()
+                .iter()
+                .filter_map(|id| registry.get(id))
+                .filter_map(|d| d.limits.max_running_instances.map(|max| (d.clone(), max)))
+                .collect()
+        };
+
+        let mut admitted = 0;
+        for (definition, max_running) in limited {
+            while let Some(instance) = self
+                .persistence
+                .workflows()
+                .admit_next(&definition.id, max_running)
+                .await?
+            {
+                self.enter_initial_state(&instance, &definition).await?;
+                tracing::info!("Admitted queued workflow instance: {}", instance.id);
+                admitted += 1;
+            }
+        }
+        Ok(admitted)
+    }
+
+    /// Report how loaded a definition with a running instance limit is,
+    /// `None` when it has no limit
+    pub async fn backpressure(&self, definition_id: &WorkflowId) -> Result<Option<Backpressure>> {
+        let max_running = self
+            .registry
+            .read()
+            .get(definition_id)
+            .and_then(|d| d.limits.max_running_instances);
+        let Some(max_running) = max_running else {
+            return Ok(None);
+        };
+        let backpressure = self
+            .persistence
+            .workflows()
+            .backpressure(definition_id, max_running)
+            .await?;
+        Ok(Some(backpressure))
+    }
+
+    /// Admit queued instances until the engine shuts down
+    async fn run_admissions(self: Arc<Self>) {
+        let mut interval = tokio::time::interval(ADMISSION_POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(e) = self.admit_queued_instances().await {
+                tracing::error!("Instance admission failed: {}", e);
+            }
+        }
+    }
+
     /// Transition a workflow to a new state
     pub async fn transition_workflow(
         &self,
         workflow_id: &WorkflowId,
         event: &str,
+    ) -> Result<String> {
+        self.transition_with_data(workflow_id, event, None).await
+    }
+
+    /// Transition a workflow, first setting `data` in its context so guards
+    /// see it
+    ///
+    /// The key of `data` may be a path such as `$.steps.verify`.
+    #[tracing::instrument(name = "workflow.transition", skip_all, fields(workflow_id = %workflow_id, event = %event))]
+    async fn transition_with_data(
+        &self,
+        workflow_id: &WorkflowId,
+        event: &str,
+        data: Option<(&str, serde_json::Value)>,
     ) -> Result<String> {
         // Get workflow instance
         let instance = self
@@ -128,6 +739,30 @@ impl WorkflowEngine {
             .map_err(EngineError::Persistence)?
             .ok_or_else(|| EngineError::Workflow(crate::error::WorkflowError::NotFound(workflow_id.to_string())))?;
 
+        // A failed workflow rolling back accepts no further events
+        if instance.status == WorkflowStatus::Compensating {
+            return Err(EngineError::Workflow(crate::error::WorkflowError::InvalidState(format!(
+                "workflow {} is compensating",
+                workflow_id
+            ))));
+        }
+
+        // Nor does a cancelled or paused one
+        if matches!(instance.status, WorkflowStatus::Cancelled | WorkflowStatus::Paused) {
+            return Err(EngineError::Workflow(crate::error::WorkflowError::InvalidState(format!(
+                "workflow {} is {}",
+                workflow_id,
+                instance.status.as_str().to_lowercase()
+            ))));
+        }
+
+        // States blocked on child workflows accept no events until they finish
+        if !instance.awaiting_children.is_empty() {
+            return Err(EngineError::Workflow(
+                crate::error::WorkflowError::AwaitingChildren(workflow_id.to_string()),
+            ));
+        }
+
         // Get workflow definition
         let definition = self
             .persistence
@@ -143,113 +778,2758 @@ impl WorkflowEngine {
             instance.current_state.clone(),
             instance.context.clone(),
         );
+        if let Some((key, value)) = data {
+            set_path(ctx.data_mut(), key, value);
+        }
 
         // Perform transition
-        let new_state = definition
+        let change = definition
             .state_machine
-            .transition(&mut ctx, event)
+            .transition_states(&mut ctx, event)
             .await
             .map_err(EngineError::Workflow)?;
+        let new_state = change.state;
+
+        let status = definition.state_machine.status_in(&new_state);
+        let is_final = status == WorkflowStatus::Completed;
 
         // Update workflow instance
-        self.persistence
+        let state_entry = self
+            .persistence
             .workflows()
-            .update_state(workflow_id, &new_state, WorkflowStatus::Running)
+            .transition(workflow_id, event, &new_state, status, ctx.data().clone())
             .await
             .map_err(EngineError::Persistence)?;
 
-        self.persistence
+        // Human tasks of the state just left can no longer be completed
+        self.persistence.human_tasks().cancel_active(workflow_id, None).await?;
+
+        for state in &change.entered {
+            self.execute_state_actions(workflow_id, state, &definition, None)
+                .await?;
+        }
+        self.schedule_state_timers(workflow_id, &definition, &new_state, None, state_entry)
+            .await?;
+
+        tracing::info!("Workflow {} transitioned to state: {}", workflow_id, new_state);
+
+        if is_final && let Some(parent) = &instance.parent {
+            self.notify_parent(workflow_id, parent, ctx.data().clone())
+                .await?;
+        }
+
+        Ok(new_state)
+    }
+
+    /// Hand a completed child's result to its parent and resume the parent
+    /// once no awaited children remain
+    async fn notify_parent(
+        &self,
+        child_id: &WorkflowId,
+        parent: &ParentLink,
+        result: serde_json::Value,
+    ) -> Result<()> {
+        let parent_instance = self
+            .persistence
             .workflows()
-            .update_context(workflow_id, ctx.data().clone())
+            .resolve_child(&parent.workflow_id, child_id, parent.result_key.as_deref(), result)
             .await
             .map_err(EngineError::Persistence)?;
 
-        tracing::info!("Workflow {} transitioned to state: {}", workflow_id, new_state);
-        Ok(new_state)
+        if !parent.await_completion || !parent_instance.awaiting_children.is_empty() {
+            return Ok(());
+        }
+
+        // Boxed because resuming the parent may in turn complete it and
+        // notify its own parent
+        let resumed = Box::pin(self.transition_workflow(&parent.workflow_id, CHILD_COMPLETED_EVENT)).await;
+        if let Err(e) = resumed {
+            tracing::warn!(
+                "Parent workflow {} did not handle '{}': {}",
+                parent.workflow_id,
+                CHILD_COMPLETED_EVENT,
+                e
+            );
+        }
+
+        Ok(())
     }
 
-    /// Execute state actions (enqueue tasks)
+    /// Execute state actions (enqueue tasks, start child workflows, publish
+    /// events, schedule HTTP requests, create human tasks, start fork
+    /// branches)
+    ///
+    /// `branch` names the fork branch that entered the state, if any.
     async fn execute_state_actions(
         &self,
-        instance: &WorkflowInstance,
+        workflow_id: &WorkflowId,
+        state_name: &str,
         definition: &WorkflowDefinition,
+        branch: Option<&str>,
     ) -> Result<()> {
         let state = definition
             .state_machine
-            .get_state(&instance.current_state)
+            .get_state(state_name)
             .ok_or_else(|| {
                 EngineError::Workflow(crate::error::WorkflowError::InvalidState(
-                    instance.current_state.clone(),
+                    state_name.to_string(),
                 ))
             })?;
 
-        // Enqueue tasks from on_enter actions
+        // A completion policy waits for the tasks enqueued below. They are
+        // recorded before being enqueued, so none finishes unnoticed.
+        let mut awaited = Vec::new();
+        if state.completion().is_some() {
+            awaited = state
+                .on_enter_actions()
+                .iter()
+                .filter(|action| matches!(action, Action::ExecuteTask(_)))
+                .map(|_| TaskId::new())
+                .collect();
+            let entry = AwaitedTasks {
+                branch: branch.map(str::to_string),
+                state: state_name.to_string(),
+                pending: awaited.clone(),
+            };
+            self.persistence.workflows().await_tasks(workflow_id, entry).await?;
+        }
+        let mut awaited = awaited.into_iter();
+        let mut rejected = None;
+        let mut cached = Vec::new();
+
+        // Enqueue tasks and start children from on_enter actions
         for action in state.on_enter_actions() {
-            if let crate::state_machine::Action::ExecuteTask(task_def) = action {
-                self.enqueue_task(instance.id, task_def.clone()).await?;
+            match action {
+                Action::ExecuteTask(task_def) => {
+                    let (task_id, enqueued) = match awaited.next() {
+                        Some(task_id) => {
+                            let enqueued = self.enqueue_task_with_id(task_id, *workflow_id, task_def.clone()).await?;
+                            (task_id, enqueued)
+                        }
+                        None => self.enqueue_task(*workflow_id, task_def.clone()).await?,
+                    };
+                    match enqueued {
+                        Enqueued::Queued => {}
+                        Enqueued::Rejected => {
+                            rejected.get_or_insert((task_id, task_def.name.clone()));
+                        }
+                        Enqueued::Cached => cached.push(task_id),
+                    }
+                }
+                Action::StartChildWorkflow {
+                    definition_id,
+                    input,
+                    await_completion,
+                    result_key,
+                } => {
+                    // Boxed because the child's initial state may start
+                    // children of its own
+                    Box::pin(self.start_child_workflow(
+                        workflow_id,
+                        definition_id,
+                        input.clone(),
+                        *await_completion,
+                        result_key.clone(),
+                    ))
+                    .await?;
+                }
+                Action::PublishEvent { topic, payload_key } => {
+                    self.publish_from(workflow_id, topic, payload_key.as_deref()).await?;
+                }
+                Action::HttpRequest(request) => {
+                    let instance = self
+                        .persistence
+                        .workflows()
+                        .get_instance(workflow_id)
+                        .await?
+                        .ok_or_else(|| WorkflowError::NotFound(workflow_id.to_string()))?;
+                    self.schedule_http_call(workflow_id, state_name, branch, request, &instance.context)
+                        .await?;
+                }
+                _ => {}
             }
         }
 
-        Ok(())
-    }
+        if let Some(spec) = state.human_task_spec() {
+            let task = HumanTask {
+                id: HumanTaskId::new(),
+                workflow_id: *workflow_id,
+                state: state_name.to_string(),
+                branch: branch.map(str::to_string),
+                title: spec.title().to_string(),
+                assignee: spec.assignee().map(str::to_string),
+                role: spec.role().map(str::to_string),
+                event: spec.event().to_string(),
+                result_key: spec.form_key().map(str::to_string),
+                status: HumanTaskStatus::Open,
+                claimed_by: None,
+                form: None,
+                created_at: Utc::now(),
+                claimed_at: None,
+                completed_at: None,
+            };
+            self.persistence.human_tasks().create(&task).await?;
+            tracing::info!("Workflow {} created human task {}: {}", workflow_id, task.id, task.title);
+        }
 
-    /// Enqueue a task for execution
-    async fn enqueue_task(&self, workflow_id: WorkflowId, definition: TaskDefinition) -> Result<TaskId> {
-        let task = TaskExecution {
-            id: TaskId::new(),
-            workflow_id,
-            definition,
-            input: Vec::new(), // TODO: Get from context
-            status: TaskStatus::Pending,
-            assigned_worker: None,
-            attempt: 0,
-            created_at: Utc::now(),
-            started_at: None,
-            completed_at: None,
-            result: None,
-        };
+        if let Some(fork) = state.fork_spec() {
+            // Boxed because the branches' initial states run this again
+            Box::pin(self.fork(workflow_id, fork, definition)).await?;
+        }
 
-        let task_id = task.id;
-        self.persistence
-            .tasks()
-            .enqueue(task)
-            .await
-            .map_err(EngineError::Persistence)?;
+        // A rejected task never runs, so it settles the state as failed
+        if let Some((task_id, name)) = rejected {
+            // Boxed because the failed event enters a state running this again
+            if !Box::pin(self.settle_task(workflow_id, &task_id, false)).await? {
+                self.fail_workflow(workflow_id, format!("Task '{}' was rejected", name))
+                    .await?;
+            }
+        }
 
-        tracing::info!("Enqueued task: {}", task_id);
-        Ok(task_id)
-    }
+        // Tasks completed from the cache move the workflow on like any other
+        // completion, once the state's actions ran
+        for task_id in cached {
+            // Boxed because the completion event enters a state running this again
+            Box::pin(self.handle_task_outcome(&task_id, TaskStatus::Completed)).await?;
+        }
 
-    /// Get the scheduler
-    pub fn scheduler(&self) -> &TaskScheduler {
-        &self.scheduler
+        Ok(())
     }
 
-    /// Get the persistence layer
-    pub fn persistence(&self) -> &PersistenceLayer {
-        &self.persistence
-    }
+    /// Start the branches of a forked state
+    async fn fork(&self, workflow_id: &WorkflowId, fork: &Fork, definition: &WorkflowDefinition) -> Result<()> {
+        let branches = fork
+            .branches()
+            .iter()
+            .map(|branch| BranchState {
+                name: branch.name().to_string(),
+                state: branch.initial_state().to_string(),
+                joined: false,
+                state_entry: 0,
+            })
+            .collect();
+        self.persistence.workflows().fork(workflow_id, branches).await?;
 
-    /// Run the engine (start RPC server)
-    pub async fn run(self: Arc<Self>) -> Result<()> {
-        let bind_addr = self.bind_addr;
-        tracing::info!("Starting workflow engine on {}", bind_addr);
-        
-        // Start the RPC server
-        server::run_server(self, bind_addr).await
-    }
+        for branch in fork.branches() {
+            self.execute_state_actions(workflow_id, branch.initial_state(), definition, Some(branch.name()))
+                .await?;
+            self.schedule_state_timers(
+                workflow_id,
+                definition,
+                branch.initial_state(),
+                Some(branch.name()),
+                0,
+            )
+            .await?;
+        }
 
-    /// Recover from crashes (reschedule orphaned tasks)
-    pub async fn recover(&self) -> Result<()> {
-        tracing::info!("Starting recovery process");
-        
-        // TODO: Implement recovery logic
-        // 1. Find tasks with status Assigned but worker is dead
-        // 2. Reschedule them
-        // 3. Find workflows in Running state and verify consistency
-        
-        tracing::info!("Recovery complete");
+        tracing::info!(
+            "Workflow {} forked into {} branches joining in '{}'",
+            workflow_id,
+            fork.branches().len(),
+            fork.join_state()
+        );
         Ok(())
     }
-}
 
+    /// Transition one branch of a forked workflow
+    ///
+    /// Once a branch enters the join state it ends. When enough branches have
+    /// joined, the workflow continues in the join state.
+    pub async fn transition_branch(&self, workflow_id: &WorkflowId, branch: &str, event: &str) -> Result<String> {
+        self.transition_branch_with_data(workflow_id, branch, event, None)
+            .await
+    }
+
+    #[tracing::instrument(
+        name = "workflow.transition",
+        skip_all,
+        fields(workflow_id = %workflow_id, branch = %branch, event = %event)
+    )]
+    async fn transition_branch_with_data(
+        &self,
+        workflow_id: &WorkflowId,
+        branch: &str,
+        event: &str,
+        data: Option<(&str, serde_json::Value)>,
+    ) -> Result<String> {
+        let instance = self
+            .persistence
+            .workflows()
+            .get_instance(workflow_id)
+            .await?
+            .ok_or_else(|| WorkflowError::NotFound(workflow_id.to_string()))?;
+        let from = instance
+            .branches
+            .iter()
+            .find(|b| b.name == branch && !b.joined)
+            .map(|b| b.state.clone())
+            .filter(|_| instance.status == WorkflowStatus::Running)
+            .ok_or_else(|| {
+                WorkflowError::InvalidState(format!("workflow {} has no running branch '{}'", workflow_id, branch))
+            })?;
+
+        let definition = self
+            .persistence
+            .workflows()
+            .get_definition(&instance.definition_id)
+            .await?
+            .ok_or_else(|| WorkflowError::NotFound(instance.definition_id.to_string()))?;
+        let fork = definition
+            .state_machine
+            .get_state(&instance.current_state)
+            .and_then(|state| state.fork_spec())
+            .ok_or_else(|| WorkflowError::InvalidState(instance.current_state.clone()))?;
+
+        let mut ctx = Context::with_data(*workflow_id, from, instance.context.clone());
+        if let Some((key, value)) = data {
+            set_path(ctx.data_mut(), key, value);
+        }
+        let change = definition
+            .state_machine
+            .transition_branch_states(&mut ctx, event, fork.join_state())
+            .await?;
+        let new_state = change.state;
+        let joined = new_state == fork.join_state();
+
+        // Branches do not fork again
+        if !joined
+            && definition
+                .state_machine
+                .get_state(&new_state)
+                .is_some_and(|state| state.fork_spec().is_some())
+        {
+            return Err(WorkflowError::InvalidState(format!(
+                "branch '{}' cannot enter forked state '{}'",
+                branch, new_state
+            ))
+            .into());
+        }
+
+        let updated = self
+            .persistence
+            .workflows()
+            .transition_branch(workflow_id, branch, event, &new_state, joined, ctx.data().clone())
+            .await?;
+        self.persistence
+            .human_tasks()
+            .cancel_active(workflow_id, Some(branch))
+            .await?;
+        tracing::info!("Workflow {} branch '{}' transitioned to state: {}", workflow_id, branch, new_state);
+
+        if !joined {
+            for state in &change.entered {
+                self.execute_state_actions(workflow_id, state, &definition, Some(branch))
+                    .await?;
+            }
+            let state_entry = updated
+                .branches
+                .iter()
+                .find(|b| b.name == branch)
+                .map_or(0, |b| b.state_entry);
+            self.schedule_state_timers(workflow_id, &definition, &new_state, Some(branch), state_entry)
+                .await?;
+            return Ok(new_state);
+        }
+
+        let arrived = updated.branches.iter().filter(|b| b.joined).count();
+        if arrived >= fork.required() {
+            self.join(&updated, fork, &definition).await?;
+        }
+        Ok(new_state)
+    }
+
+    /// Continue a forked workflow in its join state
+    async fn join(&self, instance: &WorkflowInstance, fork: &Fork, definition: &WorkflowDefinition) -> Result<()> {
+        let workflow_id = &instance.id;
+        let join_state = definition
+            .state_machine
+            .get_state(fork.join_state())
+            .ok_or_else(|| WorkflowError::InvalidState(fork.join_state().to_string()))?;
+
+        let mut ctx = Context::with_data(*workflow_id, fork.join_state().to_string(), instance.context.clone());
+        for action in join_state.on_enter_actions() {
+            action.execute(&mut ctx).await?;
+        }
+        let status = definition.state_machine.status_in(fork.join_state());
+
+        let Some(joined) = self
+            .persistence
+            .workflows()
+            .join(workflow_id, fork.join_state(), status, ctx.data().clone())
+            .await?
+        else {
+            return Ok(());
+        };
+
+        // Branches still running were dropped
+        self.persistence.human_tasks().cancel_active(workflow_id, None).await?;
+        tracing::info!("Workflow {} joined in state: {}", workflow_id, fork.join_state());
+
+        self.execute_state_actions(workflow_id, fork.join_state(), definition, None)
+            .await?;
+        self.schedule_state_timers(workflow_id, definition, fork.join_state(), None, joined.state_entry)
+            .await?;
+
+        if status == WorkflowStatus::Completed
+            && let Some(parent) = &joined.parent
+        {
+            self.notify_parent(workflow_id, parent, joined.context.clone())
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Schedule the timers declared by a state that was just entered
+    ///
+    /// `state_entry` tells this entry of the state apart from earlier ones,
+    /// whose timers must no longer fire.
+    async fn schedule_state_timers(
+        &self,
+        workflow_id: &WorkflowId,
+        definition: &WorkflowDefinition,
+        state_name: &str,
+        branch: Option<&str>,
+        state_entry: u64,
+    ) -> Result<()> {
+        let Some(state) = definition.state_machine.get_state(state_name) else {
+            return Ok(());
+        };
+        if let Some(deadline) = definition.state_timeouts.get(state_name) {
+            self.schedule_deadline(workflow_id, state_name, branch, state_entry, deadline, false)
+                .await?;
+        }
+
+        let now = Utc::now();
+        for timer in state.timers() {
+            let after = chrono::Duration::from_std(timer.after())
+                .map_err(|e| EngineError::Internal(format!("Invalid timer duration: {}", e)))?;
+
+            let scheduled = ScheduledTimer {
+                workflow_id: *workflow_id,
+                state: state_name.to_string(),
+                branch: branch.map(str::to_string),
+                event: timer.event().to_string(),
+                fire_at: now + after,
+                state_entry,
+                instance_wide: false,
+                fails: false,
+            };
+
+            self.persistence
+                .timers()
+                .schedule(&scheduled)
+                .await
+                .map_err(EngineError::Persistence)?;
+
+            tracing::debug!(
+                "Scheduled timer '{}' for workflow {} at {}",
+                scheduled.event,
+                workflow_id,
+                scheduled.fire_at
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Schedule the timer enforcing a deadline of a state or, when
+    /// `instance_wide`, of the whole instance
+    async fn schedule_deadline(
+        &self,
+        workflow_id: &WorkflowId,
+        state_name: &str,
+        branch: Option<&str>,
+        state_entry: u64,
+        deadline: &Deadline,
+        instance_wide: bool,
+    ) -> Result<()> {
+        let fire_at = duration_ms(deadline.after_ms)
+            .and_then(|after| Utc::now().checked_add_signed(after))
+            .ok_or_else(|| {
+                WorkflowError::InvalidDefinition(format!("Timeout of {}ms is out of range", deadline.after_ms))
+            })?;
+        let scheduled = ScheduledTimer {
+            workflow_id: *workflow_id,
+            state: state_name.to_string(),
+            branch: branch.map(str::to_string),
+            event: deadline.on_timeout.clone().unwrap_or_else(|| TIMED_OUT_EVENT.to_string()),
+            fire_at,
+            state_entry,
+            instance_wide,
+            fails: deadline.on_timeout.is_none(),
+        };
+        self.persistence.timers().schedule(&scheduled).await?;
+
+        tracing::debug!(
+            "Scheduled deadline of workflow {} at {}",
+            workflow_id,
+            scheduled.fire_at
+        );
+        Ok(())
+    }
+
+    /// Fire all timers whose deadline has passed
+    ///
+    /// Timers whose workflow has since left the state they were scheduled in,
+    /// or left and entered it again, are discarded without firing;
+    /// instance-wide deadlines only need the workflow to still be running.
+    /// Deadlines without a timeout event fail the workflow instead. A timer
+    /// is removed once fired; when firing fails it fires again after
+    /// [`TIMER_LEASE`].
+    pub async fn fire_due_timers(&self) -> Result<usize> {
+        let due = self
+            .persistence
+            .timers()
+            .due(Utc::now(), TIMER_BATCH_SIZE)
+            .await
+            .map_err(EngineError::Persistence)?;
+
+        let mut fired = 0;
+        for timer in due {
+            let instance = self
+                .persistence
+                .workflows()
+                .get_instance(&timer.workflow_id)
+                .await
+                .map_err(EngineError::Persistence)?;
+
+            // Timers of paused instances wait for the resume instead, with
+            // the time they had left when the instance was paused
+            if let Some(paused) = instance.as_ref().filter(|i| i.status == WorkflowStatus::Paused) {
+                self.persistence
+                    .timers()
+                    .suspend(&timer, paused.paused_at.unwrap_or(paused.updated_at))
+                    .await
+                    .map_err(EngineError::Persistence)?;
+                continue;
+            }
+
+            // The timer stays scheduled until it has been applied, so it
+            // fires again should this engine fail before
+            let lease = chrono::Duration::from_std(TIMER_LEASE).unwrap_or(chrono::Duration::MAX);
+            let Some(leased) = self
+                .persistence
+                .timers()
+                .claim(&timer, Utc::now() + lease)
+                .await
+                .map_err(EngineError::Persistence)?
+            else {
+                continue;
+            };
+
+            // A timer of an earlier entry of the state, e.g. before a
+            // self-transition, or one that already fired is stale
+            if !instance.is_some_and(|i| timer.applies_to(&i)) {
+                tracing::debug!(
+                    "Discarding stale timer '{}' for workflow {}",
+                    timer.event,
+                    timer.workflow_id
+                );
+                self.persistence.timers().remove(&leased).await?;
+                continue;
+            }
+
+            let transitioned = if timer.fails {
+                let reason = if timer.instance_wide {
+                    "Workflow timed out".to_string()
+                } else {
+                    format!("State '{}' timed out", timer.state)
+                };
+                self.fail_workflow(&timer.workflow_id, reason).await
+            } else {
+                match &timer.branch {
+                    Some(branch) => self.transition_branch(&timer.workflow_id, branch, &timer.event).await.map(drop),
+                    None => self.transition_workflow(&timer.workflow_id, &timer.event).await.map(drop),
+                }
+            };
+            match transitioned {
+                Ok(_) => {
+                    self.persistence.timers().remove(&leased).await?;
+                    fired += 1;
+                }
+                // The workflow rejected the event; firing again would not help
+                Err(EngineError::Workflow(e)) => {
+                    tracing::error!(
+                        "Workflow {} rejected timer '{}': {}",
+                        timer.workflow_id,
+                        timer.event,
+                        e
+                    );
+                    self.persistence.timers().remove(&leased).await?;
+                }
+                Err(e) => tracing::error!(
+                    "Failed to fire timer '{}' for workflow {}, retrying after {:?}: {}",
+                    timer.event,
+                    timer.workflow_id,
+                    TIMER_LEASE,
+                    e
+                ),
+            }
+        }
+
+        Ok(fired)
+    }
+
+    /// Poll for expired timers until the engine shuts down
+    async fn run_timers(self: Arc<Self>) {
+        let mut interval = tokio::time::interval(TIMER_POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(e) = self.fire_due_timers().await {
+                tracing::error!("Timer poll failed: {}", e);
+            }
+        }
+    }
+
+    /// Publish an event on the event bus
+    ///
+    /// Subscribed workflows receive it with the next delivery round.
+    pub async fn publish_event(&self, topic: &str, payload: serde_json::Value) -> Result<()> {
+        if !is_valid_topic(topic) {
+            return Err(WorkflowError::InvalidTopic(topic.to_string()).into());
+        }
+        self.persistence.event_bus().publish(topic, payload, None).await?;
+        tracing::debug!("Published event on '{}'", topic);
+        Ok(())
+    }
+
+    /// Publish an event with a payload taken from an instance's context
+    async fn publish_from(&self, workflow_id: &WorkflowId, topic: &str, payload_key: Option<&str>) -> Result<()> {
+        let instance = self
+            .persistence
+            .workflows()
+            .get_instance(workflow_id)
+            .await?
+            .ok_or_else(|| WorkflowError::NotFound(workflow_id.to_string()))?;
+        let payload = match payload_key {
+            Some(key) => instance.context.get(key).cloned().unwrap_or(serde_json::Value::Null),
+            None => instance.context,
+        };
+
+        self.persistence
+            .event_bus()
+            .publish(topic, payload, Some(workflow_id))
+            .await?;
+        tracing::info!("Workflow {} published event on '{}'", workflow_id, topic);
+        Ok(())
+    }
+
+    /// Deliver events published since the last round to their subscribers
+    ///
+    /// Every subscription consumes its topic with its own cursor. A definition
+    /// only receives events published after it was registered. Events are
+    /// claimed before delivery, so an event is delivered at most once even
+    /// with several engines.
+    pub async fn dispatch_events(&self) -> Result<usize> {
+        let subscriptions = self.registry.read().subscriptions();
+
+        let mut delivered = 0;
+        for (definition_id, registered_at, subscription) in subscriptions {
+            let subscriber = format!("{}:{}", definition_id, subscription.topic);
+            let mut cursor = self.persistence.event_bus().cursor(&subscriber).await?;
+            let is_new = cursor.is_none();
+            let events = self
+                .persistence
+                .event_bus()
+                .read(&subscription.topic, cursor.as_deref(), EVENT_BATCH_SIZE)
+                .await?;
+
+            for event in events {
+                let claimed = self
+                    .persistence
+                    .event_bus()
+                    .claim(&subscriber, cursor.as_deref(), &event.cursor)
+                    .await?;
+                if !claimed {
+                    // Another engine is consuming this subscription
+                    break;
+                }
+                cursor = Some(event.cursor.clone());
+                if is_new && event.published_at < registered_at {
+                    continue;
+                }
+
+                match self.deliver_event(&definition_id, &subscription, &event).await {
+                    Ok(()) => delivered += 1,
+                    Err(e) => tracing::error!(
+                        "Failed to deliver event on '{}' to workflow {}: {}",
+                        subscription.topic,
+                        definition_id,
+                        e
+                    ),
+                }
+            }
+        }
+
+        Ok(delivered)
+    }
+
+    /// Start or signal instances of a subscribed definition
+    async fn deliver_event(
+        &self,
+        definition_id: &WorkflowId,
+        subscription: &EventSubscription,
+        event: &PublishedEvent,
+    ) -> Result<()> {
+        let (event_name, data_key) = match &subscription.reaction {
+            EventReaction::Start => {
+                let instance = self
+                    .create_instance(WorkflowId::new(), definition_id, event.payload.clone(), None, None)
+                    .await?;
+                tracing::info!("Event on '{}' started workflow {}", event.topic, instance.id);
+                return Ok(());
+            }
+            EventReaction::Signal { event: signal, data_key, .. } => (signal.as_str(), data_key.as_deref()),
+        };
+
+        let definition = self
+            .registry
+            .read()
+            .get(definition_id)
+            .cloned()
+            .ok_or_else(|| WorkflowError::NotFound(definition_id.to_string()))?;
+        let handles = |state: &str| definition.state_machine.accepts(state, event_name);
+
+        let filter = InstanceFilter {
+            definition_id: Some(*definition_id),
+            status: Some(WorkflowStatus::Running),
+            ..Default::default()
+        };
+        let mut after = None;
+        loop {
+            let page = self
+                .persistence
+                .workflows()
+                .list(&filter, after.as_ref(), SIGNAL_BATCH_SIZE)
+                .await?;
+
+            for instance in &page.instances {
+                if event.source == Some(instance.id)
+                    || !subscription.reaction.correlates(&instance.context, &event.payload)
+                {
+                    continue;
+                }
+                let data = || data_key.map(|key| (key, event.payload.clone()));
+
+                // Signals go to the instance or to those of its running
+                // branches waiting for the event
+                let running: Vec<&BranchState> = instance.branches.iter().filter(|b| !b.joined).collect();
+                let signalled = if running.is_empty() {
+                    if !handles(&instance.current_state) {
+                        continue;
+                    }
+                    self.transition_with_data(&instance.id, event_name, data()).await.map(|_| ())
+                } else {
+                    let mut signalled = Ok(());
+                    for branch in running.into_iter().filter(|b| handles(&b.state)) {
+                        signalled = self
+                            .transition_branch_with_data(&instance.id, &branch.name, event_name, data())
+                            .await
+                            .map(|_| ());
+                        if signalled.is_err() {
+                            break;
+                        }
+                    }
+                    signalled
+                };
+                if let Err(e) = signalled {
+                    tracing::warn!(
+                        "Workflow {} did not take '{}' signalled by '{}': {}",
+                        instance.id,
+                        event_name,
+                        event.topic,
+                        e
+                    );
+                }
+            }
+
+            after = page.next;
+            if after.is_none() {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Deliver published events until the engine shuts down
+    async fn run_event_bus(self: Arc<Self>) {
+        let mut interval = tokio::time::interval(EVENT_POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(e) = self.dispatch_events().await {
+                tracing::error!("Event delivery failed: {}", e);
+            }
+        }
+    }
+
+    /// Persist an HTTP request with its templates filled in from `context`
+    async fn schedule_http_call(
+        &self,
+        workflow_id: &WorkflowId,
+        state_name: &str,
+        branch: Option<&str>,
+        request: &HttpRequest,
+        context: &serde_json::Value,
+    ) -> Result<()> {
+        let call = HttpCall {
+            id: uuid::Uuid::new_v4(),
+            workflow_id: *workflow_id,
+            state: state_name.to_string(),
+            branch: branch.map(str::to_string),
+            request: request.render(context)?,
+            attempt: 0,
+            due_at: Utc::now(),
+        };
+        self.persistence.http_calls().schedule(&call).await?;
+        tracing::debug!(
+            "Scheduled {} {} for workflow {}",
+            call.request.method,
+            call.request.url,
+            workflow_id
+        );
+        Ok(())
+    }
+
+    /// Send all HTTP requests that are due
+    ///
+    /// Requests are claimed before they are sent, so an attempt is made at
+    /// most once even with several engines. Failed attempts are scheduled
+    /// again while the retry policy allows; the final outcome is handed to
+    /// the workflow.
+    pub async fn send_due_http_calls(&self) -> Result<usize> {
+        let due = self
+            .persistence
+            .http_calls()
+            .due(Utc::now(), HTTP_BATCH_SIZE)
+            .await?;
+
+        let mut sent = 0;
+        for call in due {
+            if !self.persistence.http_calls().claim(&call).await? {
+                continue;
+            }
+            sent += 1;
+
+            let outcome = self.send_http_request(&call.request).await;
+            if let Err(error) = &outcome
+                && let Some(policy) = &call.request.retry_policy
+                && policy.should_retry(call.attempt)
+            {
+                let delay = chrono::Duration::from_std(policy.delay_with_jitter(call.attempt, rand::random::<f64>()))
+                    .map_err(|e| EngineError::Internal(format!("Invalid retry delay: {}", e)))?;
+                tracing::warn!(
+                    "{} {} for workflow {} failed, retrying: {}",
+                    call.request.method,
+                    call.request.url,
+                    call.workflow_id,
+                    error["error"].as_str().unwrap_or_default()
+                );
+                let retry = HttpCall {
+                    attempt: call.attempt + 1,
+                    due_at: Utc::now() + delay,
+                    ..call
+                };
+                self.persistence.http_calls().schedule(&retry).await?;
+                continue;
+            }
+
+            if let Err(e) = self.finish_http_call(&call, outcome).await {
+                tracing::error!(
+                    "Failed to hand the response of {} {} to workflow {}: {}",
+                    call.request.method,
+                    call.request.url,
+                    call.workflow_id,
+                    e
+                );
+            }
+        }
+
+        Ok(sent)
+    }
+
+    /// Make a request, describing the response or the failure as JSON
+    ///
+    /// Responses without a success status are failures.
+    async fn send_http_request(
+        &self,
+        request: &HttpRequest,
+    ) -> std::result::Result<serde_json::Value, serde_json::Value> {
+        let failed = |error: String| serde_json::json!({ "error": error });
+
+        let method = reqwest::Method::from_bytes(request.method.as_bytes()).map_err(|e| failed(e.to_string()))?;
+        let mut builder = self
+            .http
+            .request(method, &request.url)
+            .timeout(Duration::from_millis(request.timeout_ms));
+        for (name, value) in &request.headers {
+            builder = builder.header(name, value);
+        }
+        if let Some(body) = &request.body {
+            builder = builder.json(body);
+        }
+
+        let response = builder.send().await.map_err(|e| failed(e.to_string()))?;
+        let status = response.status();
+        // The body ends up in the workflow context, so it gets no larger
+        // than an RPC message
+        let text = read_body(response, self.limits.max_message_bytes())
+            .await
+            .map_err(failed)?;
+        let body = serde_json::from_str(&text).unwrap_or(serde_json::Value::String(text));
+
+        if status.is_success() {
+            Ok(serde_json::json!({ "status": status.as_u16(), "body": body }))
+        } else {
+            Err(serde_json::json!({
+                "status": status.as_u16(),
+                "error": format!("HTTP {}", status),
+                "body": body,
+            }))
+        }
+    }
+
+    /// Hand the final outcome of a request to its workflow
+    ///
+    /// The outcome is stored under the request's result key. While the
+    /// workflow is still in the state that made the request, the success or
+    /// failure event is fired with it. A failure without a failure event
+    /// fails a running workflow.
+    async fn finish_http_call(
+        &self,
+        call: &HttpCall,
+        outcome: std::result::Result<serde_json::Value, serde_json::Value>,
+    ) -> Result<()> {
+        let Some(instance) = self.persistence.workflows().get_instance(&call.workflow_id).await? else {
+            return Ok(());
+        };
+        let in_state = instance.status == WorkflowStatus::Running
+            && match &call.branch {
+                Some(branch) => instance
+                    .branches
+                    .iter()
+                    .any(|b| b.name == *branch && !b.joined && b.state == call.state),
+                None => instance.current_state == call.state,
+            };
+
+        let (event, value) = match &outcome {
+            Ok(response) => (call.request.on_success.as_deref(), response),
+            Err(error) => (call.request.on_failure.as_deref(), error),
+        };
+        let data = call.request.result_key.as_deref().map(|key| (key, value.clone()));
+
+        if let Some(event) = event
+            && in_state
+        {
+            match &call.branch {
+                Some(branch) => self.transition_branch_with_data(&call.workflow_id, branch, event, data).await?,
+                None => self.transition_with_data(&call.workflow_id, event, data).await?,
+            };
+            return Ok(());
+        }
+
+        if let Some((key, value)) = data
+            && matches!(instance.status, WorkflowStatus::Running | WorkflowStatus::Compensating)
+        {
+            self.persistence
+                .workflows()
+                .set_context_value(&call.workflow_id, key, value)
+                .await?;
+        }
+
+        match outcome {
+            Ok(_) => tracing::info!(
+                "{} {} for workflow {} succeeded",
+                call.request.method,
+                call.request.url,
+                call.workflow_id
+            ),
+            Err(error) if instance.status == WorkflowStatus::Running && call.request.on_failure.is_none() => {
+                let reason = format!(
+                    "{} {} in state '{}' failed: {}",
+                    call.request.method,
+                    call.request.url,
+                    call.state,
+                    error["error"].as_str().unwrap_or_default()
+                );
+                self.fail_workflow(&call.workflow_id, reason).await?;
+            }
+            Err(error) => tracing::warn!(
+                "{} {} for workflow {} failed: {}",
+                call.request.method,
+                call.request.url,
+                call.workflow_id,
+                error["error"].as_str().unwrap_or_default()
+            ),
+        }
+        Ok(())
+    }
+
+    /// Send due HTTP requests until the engine shuts down
+    async fn run_http_calls(self: Arc<Self>) {
+        let mut interval = tokio::time::interval(HTTP_POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(e) = self.send_due_http_calls().await {
+                tracing::error!("HTTP request poll failed: {}", e);
+            }
+        }
+    }
+
+    /// Enqueue a task for execution
+    async fn enqueue_task(&self, workflow_id: WorkflowId, definition: TaskDefinition) -> Result<(TaskId, Enqueued)> {
+        let task_id = TaskId::new();
+        let enqueued = self.enqueue_task_with_id(task_id, workflow_id, definition).await?;
+        Ok((task_id, enqueued))
+    }
+
+    /// Enqueue a task under a given ID
+    ///
+    /// An idempotent task with a cached result for its input is completed
+    /// with it instead of being queued; the caller handles its outcome. A
+    /// task no registered worker can run is rejected. The task carries the
+    /// trace context of its enqueue span, so the worker running it continues
+    /// the trace.
+    #[tracing::instrument(
+        name = "task.enqueue",
+        skip_all,
+        fields(task_id = %task_id, workflow_id = %workflow_id, task = %definition.name)
+    )]
+    async fn enqueue_task_with_id(
+        &self,
+        task_id: TaskId,
+        workflow_id: WorkflowId,
+        definition: TaskDefinition,
+    ) -> Result<Enqueued> {
+        // The input is fixed when the task is enqueued, so retries see the
+        // same data even after the context changed
+        let instance = self
+            .persistence
+            .workflows()
+            .get_instance(&workflow_id)
+            .await?
+            .ok_or_else(|| WorkflowError::NotFound(workflow_id.to_string()))?;
+        let input = serde_json::to_vec(&definition.input_from(&instance.context)?)
+            .map_err(|e| EngineError::Internal(format!("Failed to encode task input: {}", e)))?;
+
+        // Tasks wait in their definition's fair queue under its dispatch limit
+        let (max_dispatch_per_second, fair_queue) = self
+            .registry
+            .read()
+            .get(&instance.definition_id)
+            .map_or((None, None), |d| (d.limits.max_tasks_per_second, Some(d.fair_queue())));
+
+        let task = TaskExecution {
+            id: task_id,
+            workflow_id,
+            definition,
+            input,
+            status: TaskStatus::Pending,
+            assigned_worker: None,
+            attempt: 0,
+            created_at: Utc::now(),
+            started_at: None,
+            completed_at: None,
+            result: None,
+            history: Vec::new(),
+            retry_from: 0,
+            queued_at: None,
+            max_dispatch_per_second,
+            fair_queue,
+            trace_context: telemetry::current(),
+            lease_expires_at: None,
+        };
+
+        // Idempotent tasks reuse the result of an earlier run
+        if task.definition.cache.is_some()
+            && let Some(result) = self
+                .persistence
+                .result_cache()
+                .get(&task.definition.cache_key(&task.input))
+                .await?
+        {
+            self.persistence.tasks().complete_from_cache(task, result).await?;
+            tracing::info!("Completed task {} with a cached result", task_id);
+            return Ok(Enqueued::Cached);
+        }
+
+        let runtime = &task.definition.runtime_type;
+        if !self.scheduler.has_capable_worker(runtime).await? {
+            let reason = format!("No registered worker supports the '{}' runtime", runtime.as_str());
+            tracing::warn!("Rejected task {}: {}", task_id, reason);
+            self.persistence.tasks().reject(task, reason).await?;
+            return Ok(Enqueued::Rejected);
+        }
+
+        self.persistence
+            .tasks()
+            .enqueue(task)
+            .await
+            .map_err(EngineError::Persistence)?;
+        self.scheduler.notify_tasks_available();
+
+        tracing::info!("Enqueued task: {}", task_id);
+        Ok(Enqueued::Queued)
+    }
+
+    /// React to a task that finished for good
+    ///
+    /// A failed task fails its workflow, which then rolls back, unless the
+    /// completion policy of its state handles the failure. Completing the
+    /// compensation task a rollback waits for moves the rollback on.
+    pub async fn handle_task_outcome(&self, task_id: &TaskId, status: TaskStatus) -> Result<()> {
+        if !matches!(status, TaskStatus::Completed | TaskStatus::Failed) {
+            return Ok(());
+        }
+        let Some(task) = self.persistence.tasks().get(task_id).await? else {
+            return Ok(());
+        };
+        let workflows = self.persistence.workflows();
+        let Some(instance) = workflows.get_instance(&task.workflow_id).await? else {
+            return Ok(());
+        };
+
+        match instance.compensation {
+            Some(compensation) if compensation.awaiting_task == Some(*task_id) => {
+                if status == TaskStatus::Failed {
+                    let error = format!("Compensation task '{}' failed", task.definition.name);
+                    tracing::error!("Rollback of workflow {} aborted: {}", instance.id, error);
+                    workflows
+                        .update_compensation(&instance.id, instance.context, None, Some(error))
+                        .await?;
+                    return Ok(());
+                }
+                self.advance_compensation(
+                    &instance.id,
+                    &instance.current_state,
+                    instance.context,
+                    compensation,
+                )
+                .await
+            }
+            _ if status == TaskStatus::Failed && instance.status == WorkflowStatus::Running => {
+                if self.settle_task(&instance.id, task_id, false).await? {
+                    return Ok(());
+                }
+                let reason = format!("Task '{}' failed", task.definition.name);
+                self.fail_workflow(&instance.id, reason).await
+            }
+            _ if status == TaskStatus::Completed && instance.status == WorkflowStatus::Running => {
+                self.apply_task_output(&task, &instance).await?;
+                self.settle_task(&instance.id, task_id, true).await?;
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Store the output of a completed task and fire its completion event
+    ///
+    /// The event goes to the running branch or main state that enqueues the
+    /// task on entry, together with the output. When no such state is
+    /// active any more, only the output is stored.
+    async fn apply_task_output(&self, task: &TaskExecution, instance: &WorkflowInstance) -> Result<()> {
+        let definition = &task.definition;
+        let output = definition.result_path.as_deref().map(|path| {
+            let output = task.result.as_ref().map(|r| r.output.as_slice()).unwrap_or_default();
+            let value = serde_json::from_slice(output)
+                .unwrap_or_else(|_| serde_json::Value::String(String::from_utf8_lossy(output).into_owned()));
+            (path, value)
+        });
+
+        if let Some(event) = &definition.on_complete {
+            let workflow = self
+                .persistence
+                .workflows()
+                .get_definition(&instance.definition_id)
+                .await?
+                .ok_or_else(|| WorkflowError::NotFound(instance.definition_id.to_string()))?;
+            let enqueues = |state: &str| {
+                workflow.state_machine.get_state(state).is_some_and(|state| {
+                    state.on_enter_actions().iter().any(|action| {
+                        matches!(action, Action::ExecuteTask(t) if t.name == definition.name)
+                    })
+                })
+            };
+
+            let branch = instance.branches.iter().find(|b| !b.joined && enqueues(&b.state));
+            if let Some(branch) = branch {
+                self.transition_branch_with_data(&instance.id, &branch.name, event, output)
+                    .await?;
+                return Ok(());
+            }
+            if enqueues(&instance.current_state) {
+                self.transition_with_data(&instance.id, event, output).await?;
+                return Ok(());
+            }
+            tracing::debug!(
+                "Workflow {} left the state of task '{}', not firing '{}'",
+                instance.id,
+                definition.name,
+                event
+            );
+        }
+
+        if let Some((path, value)) = output {
+            self.persistence
+                .workflows()
+                .set_context_value(&instance.id, path, value)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Record the outcome of a task awaited by a completion policy
+    ///
+    /// Fires the policy's `done` event once all tasks of the state succeeded,
+    /// or its `failed` event on the first failure, provided the state is
+    /// still active. Returns whether an event was fired.
+    async fn settle_task(&self, workflow_id: &WorkflowId, task_id: &TaskId, succeeded: bool) -> Result<bool> {
+        let workflows = self.persistence.workflows();
+        let Some(settled) = workflows.settle_task(workflow_id, task_id, succeeded).await? else {
+            return Ok(false);
+        };
+
+        let instance = workflows
+            .get_instance(workflow_id)
+            .await?
+            .ok_or_else(|| WorkflowError::NotFound(workflow_id.to_string()))?;
+        let definition = workflows
+            .get_definition(&instance.definition_id)
+            .await?
+            .ok_or_else(|| WorkflowError::NotFound(instance.definition_id.to_string()))?;
+        let Some(completion) = definition
+            .state_machine
+            .get_state(&settled.state)
+            .and_then(|state| state.completion())
+        else {
+            return Ok(false);
+        };
+        let event = if succeeded {
+            completion.done_event()
+        } else {
+            match completion.failed_event() {
+                Some(event) => event,
+                None => return Ok(false),
+            }
+        };
+
+        let active = instance.status == WorkflowStatus::Running
+            && match &settled.branch {
+                Some(branch) => instance
+                    .branches
+                    .iter()
+                    .any(|b| &b.name == branch && !b.joined && b.state == settled.state),
+                None => instance.current_state == settled.state,
+            };
+        if !active {
+            tracing::debug!(
+                "Workflow {} left state '{}', not firing '{}'",
+                workflow_id,
+                settled.state,
+                event
+            );
+            return Ok(false);
+        }
+
+        match &settled.branch {
+            Some(branch) => self.transition_branch(workflow_id, branch, event).await?,
+            None => self.transition_workflow(workflow_id, event).await?,
+        };
+        Ok(true)
+    }
+
+    /// Fail a running workflow and roll back the states it completed
+    ///
+    /// The compensation actions of every state the instance entered before
+    /// its current one run in reverse order of entry, each state's actions in
+    /// declaration order. The current state never completed and is not
+    /// compensated, nor are the current states of running fork branches.
+    /// The instance is `Compensating` until the rollback ends and `Failed`
+    /// afterwards.
+    pub async fn fail_workflow(&self, workflow_id: &WorkflowId, reason: impl Into<String>) -> Result<()> {
+        let instance = self
+            .persistence
+            .workflows()
+            .get_instance(workflow_id)
+            .await?
+            .ok_or_else(|| WorkflowError::NotFound(workflow_id.to_string()))?;
+        let definition = self
+            .persistence
+            .workflows()
+            .get_definition(&instance.definition_id)
+            .await?
+            .ok_or_else(|| WorkflowError::NotFound(instance.definition_id.to_string()))?;
+
+        // The history is the authoritative record of the states entered
+        let history = self.get_history(workflow_id).await?;
+        let mut entered: Vec<&str> = history
+            .iter()
+            .flat_map(|event| match &event.kind {
+                HistoryEventKind::Started { state, .. } => vec![state.as_str()],
+                HistoryEventKind::Transitioned { to, .. } => vec![to.as_str()],
+                HistoryEventKind::Forked { branches } => branches.iter().map(|b| b.state.as_str()).collect(),
+                HistoryEventKind::BranchTransitioned { to, joined: false, .. } => vec![to.as_str()],
+                HistoryEventKind::Joined { state, .. } => vec![state.as_str()],
+                _ => Vec::new(),
+            })
+            .collect();
+        // The current state and the states of running branches never
+        // completed, there is nothing of them to undo
+        let running = instance.branches.iter().filter(|b| !b.joined).map(|b| b.state.as_str());
+        for state in std::iter::once(instance.current_state.as_str()).chain(running) {
+            if let Some(position) = entered.iter().rposition(|entered| *entered == state) {
+                entered.remove(position);
+            }
+        }
+
+        let pending = entered
+            .iter()
+            .rev()
+            .filter_map(|name| definition.state_machine.get_state(name))
+            .flat_map(|state| {
+                state.compensation_actions().iter().map(|action| CompensationStep {
+                    state: state.name().to_string(),
+                    action: action.clone(),
+                })
+            })
+            .collect();
+        let compensation = Compensation {
+            reason: reason.into(),
+            pending,
+            awaiting_task: None,
+        };
+
+        let Some(instance) = self
+            .persistence
+            .workflows()
+            .begin_compensation(workflow_id, compensation)
+            .await?
+        else {
+            tracing::debug!("Workflow {} is not running, not failing it", workflow_id);
+            return Ok(());
+        };
+        self.persistence.human_tasks().cancel_active(workflow_id, None).await?;
+        let Some(compensation) = instance.compensation else {
+            return Ok(());
+        };
+
+        tracing::warn!(
+            "Workflow {} failed, running {} compensation actions: {}",
+            workflow_id,
+            compensation.pending.len(),
+            compensation.reason
+        );
+        self.advance_compensation(workflow_id, &instance.current_state, instance.context, compensation)
+            .await
+    }
+
+    /// Run compensation actions up to the next compensation task
+    async fn advance_compensation(
+        &self,
+        workflow_id: &WorkflowId,
+        current_state: &str,
+        context: serde_json::Value,
+        mut compensation: Compensation,
+    ) -> Result<()> {
+        let mut ctx = Context::with_data(*workflow_id, current_state.to_string(), context);
+
+        while !compensation.pending.is_empty() {
+            let step = compensation.pending.remove(0);
+            let task_def = match step.action {
+                Action::ExecuteTask(task_def) => task_def,
+                Action::PublishEvent { topic, payload_key } => {
+                    let payload = match payload_key {
+                        Some(key) => ctx.get(&key).cloned().unwrap_or(serde_json::Value::Null),
+                        None => ctx.data().clone(),
+                    };
+                    self.persistence
+                        .event_bus()
+                        .publish(&topic, payload, Some(workflow_id))
+                        .await?;
+                    continue;
+                }
+                Action::HttpRequest(request) => {
+                    self.schedule_http_call(workflow_id, &step.state, None, &request, ctx.data())
+                        .await?;
+                    continue;
+                }
+                action => {
+                    action.execute(&mut ctx).await?;
+                    continue;
+                }
+            };
+
+            // Persisted before the task exists, so its completion always
+            // finds the rollback waiting for it
+            let task_id = TaskId::new();
+            let name = task_def.name.clone();
+            compensation.awaiting_task = Some(task_id);
+            self.persistence
+                .workflows()
+                .update_compensation(workflow_id, ctx.data().clone(), Some(compensation), None)
+                .await?;
+
+            match self.enqueue_task_with_id(task_id, *workflow_id, task_def).await? {
+                Enqueued::Queued => return Ok(()),
+                Enqueued::Cached => {
+                    // Boxed because the outcome moves the rollback on, which runs this again
+                    return Box::pin(self.handle_task_outcome(&task_id, TaskStatus::Completed)).await;
+                }
+                Enqueued::Rejected => {}
+            }
+            let error = format!(
+                "Compensation task '{}' of state '{}' cannot be scheduled",
+                name, step.state
+            );
+            tracing::error!("Rollback of workflow {} aborted: {}", workflow_id, error);
+            self.persistence
+                .workflows()
+                .update_compensation(workflow_id, ctx.data().clone(), None, Some(error))
+                .await?;
+            return Ok(());
+        }
+
+        self.persistence
+            .workflows()
+            .update_compensation(workflow_id, ctx.data().clone(), None, None)
+            .await?;
+        tracing::info!("Workflow {} rolled back: {}", workflow_id, compensation.reason);
+        Ok(())
+    }
+
+    /// Cancel a workflow instance that has not finished
+    ///
+    /// The instance is marked `Cancelled` without running compensation.
+    /// Its queued tasks are removed from the queue, and workers running one
+    /// of its tasks are told to abort it with their next heartbeat. Child
+    /// workflows the instance waits for are cancelled as well; a parent
+    /// waiting for this instance is not resumed. Returns the number of tasks
+    /// cancelled.
+    pub async fn cancel_workflow(&self, workflow_id: &WorkflowId, reason: impl Into<String>) -> Result<usize> {
+        let reason = reason.into();
+        let instance = self
+            .persistence
+            .workflows()
+            .cancel(workflow_id, &reason)
+            .await?
+            .ok_or_else(|| WorkflowError::InvalidState(format!("workflow {} has already finished", workflow_id)))?;
+        self.persistence.human_tasks().cancel_active(workflow_id, None).await?;
+
+        // Every task of the instance was enqueued on the record
+        let mut cancelled = 0;
+        for event in self.get_history(workflow_id).await? {
+            let HistoryEventKind::TaskEnqueued { task_id, .. } = event.kind else {
+                continue;
+            };
+            if self.persistence.tasks().cancel(&task_id).await? {
+                cancelled += 1;
+            }
+        }
+        tracing::warn!("Workflow {} cancelled with {} tasks: {}", workflow_id, cancelled, reason);
+
+        for child in &instance.awaiting_children {
+            let child_reason = format!("Parent workflow {} was cancelled", workflow_id);
+            match Box::pin(self.cancel_workflow(child, child_reason)).await {
+                Ok(tasks) => cancelled += tasks,
+                Err(e) => tracing::warn!("Failed to cancel child workflow {}: {}", child, e),
+            }
+        }
+
+        Ok(cancelled)
+    }
+
+    /// Pause a running workflow instance
+    ///
+    /// While paused, the instance's queued tasks are held back from workers
+    /// and its timers are suspended with the time they had left. Tasks
+    /// already running on a worker finish, but their outcome is only applied
+    /// on resume. Events and HTTP responses for the instance are rejected
+    /// until then.
+    pub async fn pause_workflow(&self, workflow_id: &WorkflowId, reason: impl Into<String>) -> Result<()> {
+        let reason = reason.into();
+        let instance = self
+            .persistence
+            .workflows()
+            .pause(workflow_id, &reason)
+            .await?
+            .ok_or_else(|| WorkflowError::InvalidState(format!("workflow {} is not running", workflow_id)))?;
+
+        let paused_at = instance.paused_at.unwrap_or_else(Utc::now);
+        let timers = self.persistence.timers();
+        for timer in timers.for_workflow(workflow_id).await? {
+            timers.suspend(&timer, paused_at).await?;
+        }
+
+        let mut held = Vec::new();
+        for event in self.get_history(workflow_id).await? {
+            if let HistoryEventKind::TaskEnqueued { task_id, .. } = event.kind
+                && self.persistence.tasks().hold(&task_id).await?
+            {
+                held.push(task_id);
+            }
+        }
+        self.persistence.workflows().set_held_tasks(workflow_id, held).await?;
+
+        tracing::info!("Workflow {} paused: {}", workflow_id, reason);
+        Ok(())
+    }
+
+    /// Resume a paused workflow instance
+    ///
+    /// Held tasks go back to the queue and suspended timers are re-armed
+    /// with the time they had left. Tasks and child workflows that finished
+    /// during the pause are handled now.
+    pub async fn resume_workflow(&self, workflow_id: &WorkflowId) -> Result<()> {
+        let instance = self
+            .persistence
+            .workflows()
+            .resume(workflow_id)
+            .await?
+            .ok_or_else(|| WorkflowError::InvalidState(format!("workflow {} is not paused", workflow_id)))?;
+        self.persistence.timers().resume(workflow_id, Utc::now()).await?;
+        for task_id in &instance.held_tasks {
+            self.persistence.tasks().unhold(task_id).await?;
+        }
+
+        // Outcomes recorded since the pause were not applied
+        let history = self.get_history(workflow_id).await?;
+        let paused_at = history
+            .iter()
+            .rposition(|e| matches!(e.kind, HistoryEventKind::Paused { .. }))
+            .unwrap_or(0);
+        let mut outcomes = Vec::new();
+        for (position, event) in history.into_iter().enumerate() {
+            if let HistoryEventKind::TaskCompleted { task_id, status, .. } = event.kind
+                && position > paused_at
+            {
+                outcomes.push((task_id, status));
+            }
+        }
+        for (task_id, status) in outcomes {
+            self.handle_task_outcome(&task_id, status).await?;
+        }
+
+        // A child that finished during the pause could not resume its parent
+        let definition = self
+            .persistence
+            .workflows()
+            .get_definition(&instance.definition_id)
+            .await?
+            .ok_or_else(|| WorkflowError::NotFound(instance.definition_id.to_string()))?;
+        let awaits_child = definition
+            .state_machine
+            .get_state(&instance.current_state)
+            .is_some_and(|state| {
+                state.on_enter_actions().iter().any(|action| {
+                    matches!(action, Action::StartChildWorkflow { await_completion: true, .. })
+                })
+            });
+        if awaits_child && instance.awaiting_children.is_empty() {
+            let current = self.persistence.workflows().get_instance(workflow_id).await?;
+            if current.is_some_and(|i| i.current_state == instance.current_state)
+                && let Err(e) = self.transition_workflow(workflow_id, CHILD_COMPLETED_EVENT).await
+            {
+                tracing::warn!(
+                    "Resumed workflow {} did not handle '{}': {}",
+                    workflow_id,
+                    CHILD_COMPLETED_EVENT,
+                    e
+                );
+            }
+        }
+
+        tracing::info!("Workflow {} resumed", workflow_id);
+        Ok(())
+    }
+
+    /// Add a comment or file reference to a workflow instance
+    ///
+    /// Annotations do not affect the state machine; they record the
+    /// collaborative case handling around it.
+    pub async fn annotate(
+        &self,
+        principal: &Principal,
+        workflow_id: &WorkflowId,
+        content: AnnotationContent,
+    ) -> Result<Annotation> {
+        let action = match &content {
+            AnnotationContent::Comment { body } => {
+                if body.trim().is_empty() {
+                    return Err(WorkflowError::InvalidAnnotation("comment is empty".to_string()).into());
+                }
+                if body.len() > MAX_COMMENT_BYTES {
+                    return Err(WorkflowError::InvalidAnnotation(format!(
+                        "comment exceeds {} bytes",
+                        MAX_COMMENT_BYTES
+                    ))
+                    .into());
+                }
+                InstanceAction::Comment
+            }
+            AnnotationContent::Attachment { name, blob, .. } => {
+                if name.trim().is_empty() || blob.trim().is_empty() {
+                    return Err(WorkflowError::InvalidAnnotation(
+                        "attachment needs a name and a blob handle".to_string(),
+                    )
+                    .into());
+                }
+                InstanceAction::Attach
+            }
+        };
+        self.authorize(principal, action, workflow_id).await?;
+
+        let annotation = Annotation {
+            id: AnnotationId::new(),
+            workflow_id: *workflow_id,
+            author: principal.id.clone(),
+            created_at: Utc::now(),
+            content,
+        };
+        self.persistence.annotations().add(&annotation).await?;
+
+        tracing::info!("{} annotated workflow {}", principal.id, workflow_id);
+        Ok(annotation)
+    }
+
+    /// List the comments and attachments of a workflow instance, oldest first
+    pub async fn annotations(&self, principal: &Principal, workflow_id: &WorkflowId) -> Result<Vec<Annotation>> {
+        self.authorize(principal, InstanceAction::ReadAnnotations, workflow_id)
+            .await?;
+        Ok(self.persistence.annotations().list(workflow_id).await?)
+    }
+
+    /// List the open and claimed human tasks `principal` may work on
+    ///
+    /// Tasks claimed by someone else are left out.
+    pub async fn human_tasks(&self, principal: &Principal, workflow_id: Option<&WorkflowId>) -> Result<Vec<HumanTask>> {
+        let tasks = self.persistence.human_tasks().list_active(workflow_id).await?;
+        Ok(tasks
+            .into_iter()
+            .filter(|task| task.is_eligible(principal))
+            .filter(|task| task.claimed_by.as_ref().is_none_or(|by| *by == principal.id))
+            .collect())
+    }
+
+    /// Claim an open human task
+    pub async fn claim_human_task(&self, principal: &Principal, task_id: &HumanTaskId) -> Result<HumanTask> {
+        let task = self.human_task(task_id).await?;
+        if !task.is_eligible(principal) {
+            return Err(EngineError::PermissionDenied(format!(
+                "{} may not claim human task {}",
+                principal.id, task_id
+            )));
+        }
+
+        let claimed = self
+            .persistence
+            .human_tasks()
+            .claim(task_id, &principal.id)
+            .await?
+            .ok_or_else(|| {
+                WorkflowError::InvalidState(format!("human task {} is no longer open", task_id))
+            })?;
+        tracing::info!("Human task {} claimed by {}", task_id, principal.id);
+        Ok(claimed)
+    }
+
+    /// Complete a claimed human task and resume its workflow
+    ///
+    /// The form is stored in the workflow context under the task's result key
+    /// before the completion event fires, so transition guards can branch on
+    /// it. A form no transition accepts is rejected and the task stays
+    /// claimed.
+    pub async fn complete_human_task(
+        &self,
+        principal: &Principal,
+        task_id: &HumanTaskId,
+        form: serde_json::Value,
+    ) -> Result<String> {
+        let task = self.human_task(task_id).await?;
+        if task.status != HumanTaskStatus::Claimed || task.claimed_by.as_deref() != Some(principal.id.as_str()) {
+            return Err(WorkflowError::InvalidState(format!(
+                "human task {} is not claimed by {}",
+                task_id, principal.id
+            ))
+            .into());
+        }
+
+        let instance = self
+            .persistence
+            .workflows()
+            .get_instance(&task.workflow_id)
+            .await?
+            .ok_or_else(|| WorkflowError::NotFound(task.workflow_id.to_string()))?;
+        let definition = self
+            .persistence
+            .workflows()
+            .get_definition(&instance.definition_id)
+            .await?
+            .ok_or_else(|| WorkflowError::NotFound(instance.definition_id.to_string()))?;
+
+        // A task of a fork branch completes that branch's state
+        let current_state = match &task.branch {
+            Some(branch) => instance
+                .branches
+                .iter()
+                .find(|b| b.name == *branch && !b.joined)
+                .map(|b| b.state.clone())
+                .unwrap_or_default(),
+            None => instance.current_state.clone(),
+        };
+
+        // Check the form against the guards before the task is closed
+        let mut ctx = Context::with_data(task.workflow_id, current_state.clone(), instance.context);
+        if let Some(key) = &task.result_key {
+            ctx.set(key, form.clone());
+        }
+        let accepted = definition
+            .state_machine
+            .find_transition(&task.state, &task.event, &ctx)
+            .is_some();
+        if current_state != task.state || !accepted {
+            return Err(WorkflowError::TransitionNotAllowed {
+                from: current_state,
+                event: task.event,
+            }
+            .into());
+        }
+
+        let completed = self
+            .persistence
+            .human_tasks()
+            .complete(task_id, &principal.id, form.clone())
+            .await?
+            .ok_or_else(|| {
+                WorkflowError::InvalidState(format!("human task {} is not claimed by {}", task_id, principal.id))
+            })?;
+        tracing::info!("Human task {} completed by {}", task_id, principal.id);
+
+        let data = completed.result_key.as_deref().map(|key| (key, form));
+        match &completed.branch {
+            Some(branch) => {
+                self.transition_branch_with_data(&completed.workflow_id, branch, &completed.event, data)
+                    .await
+            }
+            None => {
+                self.transition_with_data(&completed.workflow_id, &completed.event, data)
+                    .await
+            }
+        }
+    }
+
+    /// Get a human task by ID
+    async fn human_task(&self, task_id: &HumanTaskId) -> Result<HumanTask> {
+        Ok(self
+            .persistence
+            .human_tasks()
+            .get(task_id)
+            .await?
+            .ok_or_else(|| WorkflowError::NotFound(task_id.to_string()))?)
+    }
+
+    /// Check an action against the access policy
+    async fn authorize(&self, principal: &Principal, action: InstanceAction, workflow_id: &WorkflowId) -> Result<()> {
+        let instance = self
+            .persistence
+            .workflows()
+            .get_instance(workflow_id)
+            .await?
+            .ok_or_else(|| WorkflowError::NotFound(workflow_id.to_string()))?;
+
+        if !self.access.is_allowed(principal, action, &instance) {
+            return Err(EngineError::PermissionDenied(format!(
+                "{} may not {:?} on workflow {}",
+                principal.id, action, workflow_id
+            )));
+        }
+        Ok(())
+    }
+
+    /// Search workflow instances
+    ///
+    /// Results reflect the change feed as far as the indexer has processed it.
+    pub async fn search_instances(&self, query: &SearchQuery) -> Result<SearchResults> {
+        let (index, _) = self.search.as_ref().ok_or(SearchError::NotConfigured)?;
+        Ok(index.search(query).await?)
+    }
+
+    /// List workflow instances matching `filter`, oldest first
+    pub async fn list_instances(
+        &self,
+        filter: &InstanceFilter,
+        cursor: Option<&InstanceCursor>,
+        limit: usize,
+    ) -> Result<InstancePage> {
+        if let (Some(after), Some(before)) = (filter.created_after, filter.created_before)
+            && after > before
+        {
+            return Err(WorkflowError::InvalidFilter(
+                "created_after is later than created_before".to_string(),
+            )
+            .into());
+        }
+        Ok(self.persistence.workflows().list(filter, cursor, limit).await?)
+    }
+
+    /// Find the instances of a definition holding `value` at one of its
+    /// indexed context paths
+    pub async fn find_by_index(
+        &self,
+        definition_id: &WorkflowId,
+        path: &str,
+        value: &serde_json::Value,
+    ) -> Result<Vec<WorkflowInstance>> {
+        let indexed = self
+            .registry
+            .read()
+            .get(definition_id)
+            .map(|definition| definition.indexes.iter().any(|indexed| indexed == path))
+            .ok_or_else(|| WorkflowError::NotFound(definition_id.to_string()))?;
+        if !indexed {
+            return Err(WorkflowError::InvalidFilter(format!("'{}' is not an indexed path", path)).into());
+        }
+        Ok(self.persistence.workflows().find_by_index(definition_id, path, value).await?)
+    }
+
+    /// Get the full history of a workflow instance, oldest first
+    ///
+    /// The history outlives the instance, so deleted instances still have
+    /// one. Unknown instances have an empty history.
+    pub async fn get_history(&self, workflow_id: &WorkflowId) -> Result<Vec<HistoryEvent>> {
+        let mut events = Vec::new();
+        loop {
+            let after = events.last().map(|e: &HistoryEvent| e.sequence);
+            let page = self
+                .persistence
+                .history()
+                .read(workflow_id, after, HISTORY_PAGE_SIZE)
+                .await?;
+            let done = page.len() < HISTORY_PAGE_SIZE;
+            events.extend(page);
+            if done {
+                return Ok(events);
+            }
+        }
+    }
+
+    /// Get an archived workflow instance with its history and annotations
+    pub async fn archived_instance(&self, workflow_id: &WorkflowId) -> Result<ArchivedInstance> {
+        self.persistence
+            .workflows()
+            .get_archived(workflow_id)
+            .await?
+            .ok_or_else(|| WorkflowError::NotFound(workflow_id.to_string()).into())
+    }
+
+    /// Restore an archived workflow instance, for audit
+    ///
+    /// The instance comes back with its history and annotations, in the
+    /// status it finished with. A `Restored` event is added to its history.
+    /// It is archived again on the next pass once its retention period has
+    /// passed.
+    pub async fn restore_archived(&self, workflow_id: &WorkflowId) -> Result<WorkflowInstance> {
+        let instance = self
+            .persistence
+            .workflows()
+            .restore_archived(workflow_id)
+            .await?
+            .ok_or_else(|| WorkflowError::NotFound(workflow_id.to_string()))?;
+        tracing::info!("Restored archived workflow {}", workflow_id);
+        Ok(instance)
+    }
+
+    /// Take a snapshot of all workflow definitions
+    ///
+    /// With `include_instances`, all instances and their pending timers are
+    /// included as well. The snapshot is read page by page, not in a single
+    /// transaction, so instances changing meanwhile may be captured
+    /// mid-flight.
+    pub async fn snapshot(&self, include_instances: bool) -> Result<Snapshot> {
+        let workflows = self.persistence.workflows();
+        let mut snapshot = Snapshot::new();
+
+        loop {
+            let after = snapshot.definitions.last().map(|d| d.id);
+            let page = workflows.list_definitions(after.as_ref(), SNAPSHOT_PAGE_SIZE).await?;
+            let done = page.len() < SNAPSHOT_PAGE_SIZE;
+            snapshot.definitions.extend(page);
+            if done {
+                break;
+            }
+        }
+        if !include_instances {
+            return Ok(snapshot);
+        }
+
+        let filter = InstanceFilter::default();
+        let mut cursor = None;
+        loop {
+            let page = workflows.list(&filter, cursor.as_ref(), SNAPSHOT_PAGE_SIZE).await?;
+            snapshot.instances.extend(page.instances);
+            cursor = page.next;
+            if cursor.is_none() {
+                break;
+            }
+        }
+
+        // Only timers of captured instances, not of ones started since
+        let captured: HashSet<WorkflowId> = snapshot.instances.iter().map(|i| i.id).collect();
+        let mut after = None;
+        loop {
+            let page = self.persistence.timers().list(after.as_ref(), SNAPSHOT_PAGE_SIZE).await?;
+            let done = page.len() < SNAPSHOT_PAGE_SIZE;
+            after = page.last().cloned();
+            snapshot
+                .timers
+                .extend(page.into_iter().filter(|t| captured.contains(&t.workflow_id)));
+            if done {
+                break;
+            }
+        }
+
+        tracing::info!(
+            "Took snapshot of {} definitions, {} instances and {} timers",
+            snapshot.definitions.len(),
+            snapshot.instances.len(),
+            snapshot.timers.len()
+        );
+        Ok(snapshot)
+    }
+
+    /// Restore a snapshot, assigning IDs as set by `options`
+    ///
+    /// Definitions are registered like new ones, so they are validated and,
+    /// with trusted signers configured, must carry valid attestations.
+    /// Existing definitions and instances with the same IDs are overwritten.
+    pub async fn restore(&self, mut snapshot: Snapshot, options: &RestoreOptions) -> Result<RestoreReport> {
+        snapshot.check_version()?;
+        snapshot.remap(options);
+
+        let mut report = RestoreReport::default();
+        for definition in snapshot.definitions {
+            self.register_workflow(definition).await?;
+            report.definitions += 1;
+        }
+        for instance in &snapshot.instances {
+            self.persistence.workflows().save_instance(instance).await?;
+            report.instances += 1;
+        }
+        for timer in &snapshot.timers {
+            self.persistence.timers().schedule(timer).await?;
+            report.timers += 1;
+        }
+
+        tracing::info!(
+            "Restored {} definitions, {} instances and {} timers",
+            report.definitions,
+            report.instances,
+            report.timers
+        );
+        Ok(report)
+    }
+
+    /// Tell a worker to finish its active tasks and exit
+    ///
+    /// The worker gets no new tasks and learns about the drain with its next
+    /// heartbeat; it deregisters once its last task is done.
+    pub async fn drain_worker(&self, worker_id: &WorkerId) -> Result<()> {
+        if !self.persistence.workers().drain(worker_id).await? {
+            return Err(EngineError::WorkerNotFound(worker_id.to_string()));
+        }
+        self.scheduler.drain_worker(worker_id);
+        tracing::info!("Draining worker {}", worker_id);
+        Ok(())
+    }
+
+    /// Remove a worker that stopped taking tasks
+    pub async fn deregister_worker(&self, worker_id: &WorkerId) -> Result<()> {
+        self.scheduler.unregister_worker(worker_id);
+        self.persistence.workers().remove(worker_id).await?;
+        tracing::info!("Deregistered worker {}", worker_id);
+        Ok(())
+    }
+
+    /// Get the visibility timeout of task leases
+    pub fn visibility_timeout(&self) -> Duration {
+        self.heartbeat_timeout()
+    }
+
+    /// Time after its last heartbeat at which a worker is considered dead
+    fn heartbeat_timeout(&self) -> Duration {
+        self.heartbeat_interval.saturating_mul(self.max_missed_heartbeats)
+    }
+
+    /// Number of heartbeats a worker last heard from at `last_heartbeat`
+    /// has missed by `now`
+    pub fn missed_heartbeats(&self, last_heartbeat: DateTime<Utc>, now: DateTime<Utc>) -> u32 {
+        let elapsed = (now - last_heartbeat).to_std().unwrap_or_default();
+        let missed = elapsed.as_millis() / self.heartbeat_interval.as_millis().max(1);
+        u32::try_from(missed).unwrap_or(u32::MAX)
+    }
+
+    /// List the registered workers with their health as of the last
+    /// recovery pass
+    pub async fn list_workers(&self) -> Result<Vec<WorkerInfo>> {
+        Ok(self.persistence.workers().list().await?)
+    }
+
+    /// Get the scheduler
+    pub fn scheduler(&self) -> &TaskScheduler {
+        &self.scheduler
+    }
+
+    /// Get the persistence layer
+    pub fn persistence(&self) -> &PersistenceLayer {
+        &self.persistence
+    }
+
+    /// Run the engine (start RPC server)
+    pub async fn run(self: Arc<Self>) -> Result<()> {
+        let bind_addr = self.bind_addr;
+        tracing::info!("Starting workflow engine on {}", bind_addr);
+
+        self.start_background_tasks();
+
+        // Start the RPC server
+        server::run_server(self, bind_addr).await
+    }
+
+    /// Spawn the loops firing timers, delivering events, sending HTTP
+    /// calls, admitting queued instances, recovering and archiving
+    fn start_background_tasks(self: &Arc<Self>) {
+        // Timers are persisted, so any that expired while the engine was down
+        // fire on the first poll
+        tokio::spawn(self.clone().run_timers());
+
+        // Topics are persisted, so events published while the engine was
+        // down are delivered once it is back
+        tokio::spawn(self.clone().run_event_bus());
+
+        // Pending requests are persisted, so requests due while the engine
+        // was down are sent on the first poll
+        tokio::spawn(self.clone().run_http_calls());
+
+        // Queued instances are persisted, so they are admitted once slots
+        // free up, whichever engine started them
+        tokio::spawn(self.clone().run_admissions());
+
+        // Recovery runs right away to pick up tasks orphaned while the engine
+        // was down, then keeps watching worker heartbeats
+        tokio::spawn(self.clone().run_recovery());
+
+        // Finished instances are archived or deleted once their definition's
+        // retention period has passed
+        tokio::spawn(self.clone().run_archiver());
+
+        if let Some((index, schema)) = &self.search {
+            let indexer = Indexer::new(self.persistence.clone(), index.clone(), schema.clone());
+            tokio::spawn(indexer.run());
+        }
+    }
+
+    /// Recover from crashed workers and interrupted state entries
+    ///
+    /// Workers that missed a heartbeat are marked degraded. Those that missed
+    /// as many in a row as allowed are marked dead and evicted from the
+    /// scheduler, and the tasks assigned to them are returned to the queue.
+    /// Running instances that have been in their state for longer than
+    /// the timeout but have no record of a task their state enqueues on entry
+    /// (the engine stopped between saving the instance and enqueueing) get
+    /// those tasks enqueued again, under the IDs their state's completion
+    /// policy awaits.
+    pub async fn recover(&self) -> Result<RecoveryReport> {
+        tracing::debug!("Starting recovery process");
+
+        let now = Utc::now();
+        let timeout = chrono::Duration::from_std(self.heartbeat_timeout())
+            .map_err(|e| EngineError::Internal(format!("Invalid heartbeat timeout: {}", e)))?;
+        let cutoff = now - timeout;
+        let mut report = RecoveryReport::default();
+
+        // 1. Find workers that missed heartbeats
+        let workers = self.persistence.workers().list().await?;
+        let mut dead = HashSet::new();
+        for worker in &workers {
+            let missed = self.missed_heartbeats(worker.last_heartbeat, now);
+            let status = match missed {
+                0 => continue,
+                missed if missed >= self.max_missed_heartbeats => WorkerHealthStatus::Dead,
+                _ => WorkerHealthStatus::Degraded,
+            };
+
+            let changed = worker.status != status;
+            if changed
+                && !self
+                    .persistence
+                    .workers()
+                    .set_status(&worker.id, status, worker.last_heartbeat)
+                    .await?
+            {
+                // It sent a heartbeat since it was listed
+                continue;
+            }
+
+            if status == WorkerHealthStatus::Degraded {
+                if changed {
+                    tracing::info!("Worker {} missed a heartbeat, marking it degraded", worker.id);
+                    report.degraded_workers += 1;
+                }
+                continue;
+            }
+            if changed {
+                tracing::warn!("Worker {} missed {} heartbeats, marking it dead", worker.id, missed);
+                report.dead_workers += 1;
+            }
+            dead.insert(worker.id.clone());
+            // Its next poll re-registers it
+            self.scheduler.unregister_worker(&worker.id);
+        }
+        let known: HashSet<&WorkerId> = workers.iter().map(|w| &w.id).collect();
+
+        // 2. Return their tasks to the queue, remembering which tasks each
+        // instance has for the reconciliation below
+        let mut task_names: HashMap<WorkflowId, HashSet<String>> = HashMap::new();
+        let mut task_ids = HashSet::new();
+        let mut after = None;
+        loop {
+            let tasks = self.persistence.tasks().scan(after.as_ref(), RECOVERY_BATCH_SIZE).await?;
+            for task in &tasks {
+                task_ids.insert(task.id);
+                task_names
+                    .entry(task.workflow_id)
+                    .or_default()
+                    .insert(task.definition.name.clone());
+
+                let in_flight = matches!(task.status, TaskStatus::Assigned | TaskStatus::Running);
+                let Some(worker_id) = task.assigned_worker.as_ref().filter(|_| in_flight) else {
+                    continue;
+                };
+                // A worker missing from the listing may have registered since;
+                // only consider it lost once its task is older than the timeout
+                let lost = dead.contains(worker_id)
+                    || (!known.contains(worker_id) && task.started_at.is_none_or(|t| t < cutoff));
+                if lost && self.persistence.tasks().release(&task.id, worker_id).await? {
+                    tracing::warn!("Released task {} held by lost worker {}", task.id, worker_id);
+                    report.released_tasks += 1;
+                }
+            }
+
+            if tasks.len() < RECOVERY_BATCH_SIZE {
+                break;
+            }
+            after = tasks.last().map(|t| t.id);
+        }
+
+        // 3. Re-enqueue tasks missing for running instances
+        let filter = InstanceFilter {
+            status: Some(WorkflowStatus::Running),
+            ..Default::default()
+        };
+        let mut after = None;
+        loop {
+            let page = self
+                .persistence
+                .workflows()
+                .list(&filter, after.as_ref(), RECOVERY_BATCH_SIZE)
+                .await?;
+            for instance in page.instances.iter().filter(|i| i.updated_at < cutoff) {
+                let definition = self.registry.read().get(&instance.definition_id).cloned();
+                let Some(state) = definition
+                    .as_ref()
+                    .and_then(|d| d.state_machine.get_state(&instance.current_state))
+                else {
+                    continue;
+                };
+
+                // A completion policy recorded the IDs of the tasks it awaits
+                // before they were enqueued; the lost tasks keep theirs, so
+                // their completion is still counted
+                let mut recorded = instance
+                    .awaited_tasks
+                    .iter()
+                    .find(|entry| entry.branch.is_none() && entry.state == instance.current_state)
+                    .map(|entry| {
+                        entry
+                            .pending
+                            .iter()
+                            .filter(|id| !task_ids.contains(id))
+                            .copied()
+                    });
+
+                let existing = task_names.get(&instance.id);
+                for action in state.on_enter_actions() {
+                    let Action::ExecuteTask(task_def) = action else {
+                        continue;
+                    };
+                    if existing.is_some_and(|names| names.contains(&task_def.name)) {
+                        continue;
+                    }
+                    let task_id = match recorded.as_mut().map(Iterator::next) {
+                        Some(Some(task_id)) => task_id,
+                        // Every awaited task was enqueued and has settled since
+                        Some(None) => continue,
+                        None => TaskId::new(),
+                    };
+                    tracing::warn!(
+                        "Workflow {} in state '{}' lost task '{}', enqueueing it again",
+                        instance.id,
+                        instance.current_state,
+                        task_def.name
+                    );
+                    let enqueued = self.enqueue_task_with_id(task_id, instance.id, task_def.clone()).await?;
+                    if enqueued == Enqueued::Cached {
+                        self.handle_task_outcome(&task_id, TaskStatus::Completed).await?;
+                    }
+                    report.restarted_tasks += 1;
+                }
+            }
+
+            after = page.next;
+            if after.is_none() {
+                break;
+            }
+        }
+
+        if report != RecoveryReport::default() {
+            tracing::info!(
+                "Recovery complete: {} degraded workers, {} dead workers, {} tasks released, {} tasks restarted",
+                report.degraded_workers,
+                report.dead_workers,
+                report.released_tasks,
+                report.restarted_tasks
+            );
+        }
+        Ok(report)
+    }
+
+    /// Run recovery periodically
+    async fn run_recovery(self: Arc<Self>) {
+        let mut interval = tokio::time::interval(RECOVERY_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(e) = self.recover().await {
+                tracing::error!("Recovery failed: {}", e);
+            }
+        }
+    }
+
+    /// Archive or delete the finished instances of definitions with a
+    /// retention policy once it has passed, returning how many were removed
+    ///
+    /// The retention period counts from when an instance finished.
+    pub async fn archive_expired(&self) -> Result<usize> {
+        let definitions: Vec<(WorkflowId, _)> = {
+            let registry = self.registry.read();
+            registry
+                .list()
+                .into_iter()
+                .filter_map(|id| registry.get(&id).and_then(|d| d.retention.clone()).map(|r| (id, r)))
+                .collect()
+        };
+
+        let workflows = self.persistence.workflows();
+        let mut removed = 0;
+        for (definition_id, retention) in definitions {
+            // Instances kept longer than dates reach back never expire
+            let Some(cutoff) = duration_ms(retention.keep_for_ms)
+                .and_then(|keep_for| Utc::now().checked_sub_signed(keep_for))
+            else {
+                continue;
+            };
+            for status in [WorkflowStatus::Completed, WorkflowStatus::Failed, WorkflowStatus::Cancelled] {
+                // Instances finish after they are created, so only those
+                // created before the cutoff can be due
+                let filter = InstanceFilter {
+                    definition_id: Some(definition_id),
+                    status: Some(status),
+                    created_before: Some(cutoff),
+                    ..Default::default()
+                };
+                let mut after = None;
+                loop {
+                    let page = workflows.list(&filter, after.as_ref(), ARCHIVE_BATCH_SIZE).await?;
+                    for instance in &page.instances {
+                        if instance.completed_at.unwrap_or(instance.updated_at) >= cutoff {
+                            continue;
+                        }
+                        let done = match retention.action {
+                            RetentionAction::Archive => workflows.archive_instance(&instance.id).await?,
+                            RetentionAction::Delete => workflows.purge_instance(&instance.id).await?,
+                        };
+                        if done {
+                            removed += 1;
+                        }
+                    }
+
+                    after = page.next;
+                    if after.is_none() {
+                        break;
+                    }
+                }
+            }
+        }
+
+        if removed > 0 {
+            tracing::info!("Retention pass removed {} finished instances", removed);
+        }
+        Ok(removed)
+    }
+
+    /// Remove the expired results of idempotent tasks and responses to
+    /// idempotent calls
+    async fn purge_cached_results(&self) -> Result<()> {
+        let now = Utc::now();
+        while self.persistence.result_cache().purge_expired(now, ARCHIVE_BATCH_SIZE).await? == ARCHIVE_BATCH_SIZE {}
+        if let Some((store, _)) = &self.idempotency {
+            while store.purge_expired(now, ARCHIVE_BATCH_SIZE).await? == ARCHIVE_BATCH_SIZE {}
+        }
+        Ok(())
+    }
+
+    /// Run the archiver periodically
+    async fn run_archiver(self: Arc<Self>) {
+        let mut interval = tokio::time::interval(ARCHIVE_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(e) = self.archive_expired().await {
+                tracing::error!("Archiving failed: {}", e);
+            }
+            if let Err(e) = self.purge_cached_results().await {
+                tracing::error!("Purging expired cached results failed: {}", e);
+            }
+        }
+    }
+}
+
+/// Read the body of a response as text, failing once it exceeds `limit`
+/// bytes
+async fn read_body(mut response: reqwest::Response, limit: usize) -> std::result::Result<String, String> {
+    let too_large = || format!("Response body exceeds {} bytes", limit);
+    if response.content_length().is_some_and(|len| len > limit as u64) {
+        return Err(too_large());
+    }
+
+    let mut body = Vec::new();
+    while let Some(chunk) = response.chunk().await.map_err(|e| e.to_string())? {
+        if body.len() + chunk.len() > limit {
+            return Err(too_large());
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(String::from_utf8_lossy(&body).into_owned())
+}
+
+/// Duration of `ms` milliseconds, `None` when it is out of chrono's range
+fn duration_ms(ms: u64) -> Option<chrono::Duration> {
+    i64::try_from(ms).ok().and_then(chrono::Duration::try_milliseconds)
+}
+
+/// What became of a task handed to the queue
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Enqueued {
+    /// Queued for a worker
+    Queued,
+    /// Rejected, no registered worker can run it
+    Rejected,
+    /// Completed with a cached result without running
+    Cached,
+}
+
+/// Outcome of a recovery pass
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RecoveryReport {
+    /// Workers newly marked degraded
+    pub degraded_workers: usize,
+    /// Workers newly marked dead
+    pub dead_workers: usize,
+    /// Tasks returned to the queue from lost workers
+    pub released_tasks: usize,
+    /// Tasks enqueued again for running instances
+    pub restarted_tasks: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::persistence::boot_network;
+    use crate::state_machine::{Completion, State, StateMachine, Transition};
+    use crate::types::{RuntimeType, WorkerStats};
+    use degov_crypto::StackKey;
+    use serde_json::json;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// Engine on the shared key space
+    async fn shared_engine() -> WorkflowEngine {
+        boot_network();
+        let db = Database::default().unwrap();
+        WorkflowEngine::new(db, "127.0.0.1:0".parse().unwrap()).await.unwrap()
+    }
+
+    /// Engine keeping its data in a tenant of its own, with a worker that
+    /// runs JavaScript tasks
+    async fn engine() -> WorkflowEngine {
+        let shared = shared_engine().await;
+        let engine = shared.for_tenant(&StackKey::from_seed(rand::random()).did()).await.unwrap();
+        engine.scheduler.register_worker(WorkerInfo {
+            id: WorkerId::new(),
+            capabilities: vec![RuntimeType::JavaScript],
+            hostname: "test".to_string(),
+            location: Default::default(),
+            registered_at: Utc::now(),
+            last_heartbeat: Utc::now(),
+            status: WorkerHealthStatus::Healthy,
+            stats: WorkerStats::default(),
+            draining: false,
+        });
+        engine
+    }
+
+    fn task(name: &str) -> TaskDefinition {
+        TaskDefinition {
+            name: name.to_string(),
+            code: b"export default () => null".to_vec(),
+            timeout_ms: 1000,
+            ..Default::default()
+        }
+    }
+
+    fn definition(state_machine: StateMachine) -> WorkflowDefinition {
+        WorkflowDefinition {
+            id: WorkflowId::new(),
+            name: "test".to_string(),
+            description: None,
+            state_machine,
+            created_at: Utc::now(),
+            subscriptions: Vec::new(),
+            timeout: None,
+            state_timeouts: Default::default(),
+            limits: Default::default(),
+            scheduling: Default::default(),
+            indexes: Vec::new(),
+            retention: None,
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a FoundationDB cluster"]
+    async fn test_recover_enqueues_awaited_tasks_under_their_ids() {
+        let engine = engine().await;
+        let state_machine = StateMachine::builder()
+            .initial_state("work")
+            .add_state(
+                State::new("work")
+                    .on_enter(Action::execute_task(task("a")))
+                    .on_enter(Action::execute_task(task("b")))
+                    .on_tasks_finished(Completion::new("done"))
+                    .add_transition(Transition::new("done", "end")),
+            )
+            .add_state(State::new("end"))
+            .build()
+            .unwrap();
+        let definition_id = engine.register_workflow(definition(state_machine)).await.unwrap();
+
+        // The engine stopped after recording the tasks "work" awaits and
+        // enqueueing the first of them
+        let awaited = vec![TaskId::new(), TaskId::new()];
+        let instance = WorkflowInstance {
+            id: WorkflowId::new(),
+            definition_id,
+            current_state: "work".to_string(),
+            context: json!({}),
+            status: WorkflowStatus::Running,
+            created_at: Utc::now(),
+            updated_at: Utc::now() - chrono::Duration::days(1),
+            completed_at: None,
+            parent: None,
+            awaiting_children: Vec::new(),
+            compensation: None,
+            branches: Vec::new(),
+            awaited_tasks: vec![AwaitedTasks {
+                branch: None,
+                state: "work".to_string(),
+                pending: awaited.clone(),
+            }],
+            idempotency_key: None,
+            state_entry: 1,
+            paused_at: None,
+            held_tasks: Vec::new(),
+        };
+        engine.persistence.workflows().save_instance(&instance).await.unwrap();
+        engine.enqueue_task_with_id(awaited[0], instance.id, task("a")).await.unwrap();
+
+        let report = engine.recover().await.unwrap();
+        assert_eq!(report.restarted_tasks, 1);
+        let restarted = engine.persistence.tasks().get(&awaited[1]).await.unwrap().unwrap();
+        assert_eq!(restarted.workflow_id, instance.id);
+        assert_eq!(restarted.definition.name, "b");
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a FoundationDB cluster"]
+    async fn test_tenants_resolve_shared_definitions() {
+        let state_machine = StateMachine::builder()
+            .initial_state("start")
+            .add_state(State::new("start").add_transition(Transition::new("go", "end")))
+            .add_state(State::new("end"))
+            .build()
+            .unwrap();
+        let shared = shared_engine().await;
+        let definition_id = shared.register_workflow(definition(state_machine)).await.unwrap();
+
+        let did = StackKey::from_seed(rand::random()).did();
+        let tenant = shared.for_tenant(&did).await.unwrap();
+        let instance = tenant.start_workflow(&definition_id, json!({})).await.unwrap();
+
+        // Another engine, as after a restart, drives the tenant's instance
+        // without the definition registered with it
+        let restarted = shared_engine().await.for_tenant(&did).await.unwrap();
+        assert_eq!(restarted.transition_workflow(&instance.id, "go").await.unwrap(), "end");
+        let definition = restarted.persistence().workflows().get_definition(&definition_id).await.unwrap();
+        assert!(definition.is_some());
+    }
+
+    /// The tasks enqueued for an instance so far, oldest first
+    async fn enqueued_tasks(engine: &WorkflowEngine, workflow_id: &WorkflowId) -> Vec<TaskId> {
+        let mut task_ids = Vec::new();
+        for event in engine.get_history(workflow_id).await.unwrap() {
+            if let HistoryEventKind::TaskEnqueued { task_id, .. } = event.kind {
+                task_ids.push(task_id);
+            }
+        }
+        task_ids
+    }
+
+    /// Take the next task for a worker running JavaScript
+    async fn dequeue(engine: &WorkflowEngine, worker_id: &WorkerId, lease: Duration) -> Option<TaskExecution> {
+        engine
+            .persistence
+            .tasks()
+            .dequeue(worker_id, &[RuntimeType::JavaScript], &Default::default(), &*engine.scheduler, lease)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a FoundationDB cluster"]
+    async fn test_pause_holds_tasks_and_timers_until_resumed() {
+        let engine = engine().await;
+        let state_machine = StateMachine::builder()
+            .initial_state("wait")
+            .add_state(
+                State::new("wait")
+                    .on_enter(Action::execute_task(task("a")))
+                    .after(Duration::from_millis(300), "late")
+                    .add_transition(Transition::new("late", "end")),
+            )
+            .add_state(State::new("end"))
+            .build()
+            .unwrap();
+        let definition_id = engine.register_workflow(definition(state_machine)).await.unwrap();
+        let instance = engine.start_workflow(&definition_id, json!({})).await.unwrap();
+        let task_id = enqueued_tasks(&engine, &instance.id).await[0];
+
+        engine.pause_workflow(&instance.id, "maintenance").await.unwrap();
+        let paused = engine.persistence.workflows().get_instance(&instance.id).await.unwrap().unwrap();
+        assert_eq!(paused.status, WorkflowStatus::Paused);
+        assert_eq!(paused.held_tasks, vec![task_id]);
+        assert!(engine.pause_workflow(&instance.id, "again").await.is_err());
+
+        // Events are rejected, queued tasks held back and timers suspended
+        assert!(engine.transition_workflow(&instance.id, "late").await.is_err());
+        let held = engine.persistence.tasks().get(&task_id).await.unwrap().unwrap();
+        assert_eq!(held.status, TaskStatus::Paused);
+        assert!(dequeue(&engine, &WorkerId::new(), Duration::from_secs(30)).await.is_none());
+        assert!(engine.persistence.timers().for_workflow(&instance.id).await.unwrap().is_empty());
+
+        // The timer's deadline passes while paused without firing it
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        assert_eq!(engine.fire_due_timers().await.unwrap(), 0);
+        let paused = engine.persistence.workflows().get_instance(&instance.id).await.unwrap().unwrap();
+        assert_eq!(paused.current_state, "wait");
+
+        // Resuming requeues the task and re-arms the timer with the time it
+        // had left
+        engine.resume_workflow(&instance.id).await.unwrap();
+        assert!(engine.resume_workflow(&instance.id).await.is_err());
+        let requeued = dequeue(&engine, &WorkerId::new(), Duration::from_secs(30)).await.unwrap();
+        assert_eq!(requeued.id, task_id);
+        let timers = engine.persistence.timers().for_workflow(&instance.id).await.unwrap();
+        assert_eq!(timers.len(), 1);
+        assert!(timers[0].fire_at > Utc::now());
+
+        tokio::time::sleep(Duration::from_millis(400)).await;
+        assert_eq!(engine.fire_due_timers().await.unwrap(), 1);
+        let resumed = engine.persistence.workflows().get_instance(&instance.id).await.unwrap().unwrap();
+        assert_eq!(resumed.status, WorkflowStatus::Completed);
+        assert_eq!(resumed.current_state, "end");
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a FoundationDB cluster"]
+    async fn test_expired_leases_are_reclaimed() {
+        let engine = engine().await;
+        let state_machine = StateMachine::builder()
+            .initial_state("work")
+            .add_state(
+                State::new("work")
+                    .on_enter(Action::execute_task(task("a")))
+                    .add_transition(Transition::new("done", "end")),
+            )
+            .add_state(State::new("end"))
+            .build()
+            .unwrap();
+        let definition_id = engine.register_workflow(definition(state_machine)).await.unwrap();
+        let instance = engine.start_workflow(&definition_id, json!({})).await.unwrap();
+        let task_id = enqueued_tasks(&engine, &instance.id).await[0];
+
+        let (first, second) = (WorkerId::new(), WorkerId::new());
+        let taken = dequeue(&engine, &first, Duration::from_millis(100)).await.unwrap();
+        assert_eq!(taken.id, task_id);
+        assert!(dequeue(&engine, &second, Duration::from_secs(30)).await.is_none());
+
+        // Once the lease runs out a dequeue queues the task again, even one
+        // that takes nothing itself
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        let tasks = engine.persistence.tasks();
+        let nothing = tasks
+            .dequeue(&second, &[], &Default::default(), &*engine.scheduler, Duration::from_secs(30))
+            .await
+            .unwrap();
+        assert!(nothing.is_none());
+        let requeued = tasks.get(&task_id).await.unwrap().unwrap();
+        assert_eq!(requeued.status, TaskStatus::Pending);
+        assert_eq!(requeued.attempt, taken.attempt + 1);
+
+        // The second worker takes it, and the first learns it lost it
+        let reclaimed = dequeue(&engine, &second, Duration::from_millis(100)).await.unwrap();
+        assert_eq!(reclaimed.id, task_id);
+        assert_eq!(reclaimed.assigned_worker.as_ref(), Some(&second));
+        let lost = tasks.extend_leases(&first, &[task_id], Duration::from_secs(30)).await.unwrap();
+        assert_eq!(lost, vec![task_id]);
+
+        // An extended lease outlives the timeout it was taken with
+        let lost = tasks.extend_leases(&second, &[task_id], Duration::from_secs(30)).await.unwrap();
+        assert!(lost.is_empty());
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(dequeue(&engine, &first, Duration::from_secs(30)).await.is_none());
+        let held = tasks.get(&task_id).await.unwrap().unwrap();
+        assert_eq!(held.assigned_worker.as_ref(), Some(&second));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a FoundationDB cluster"]
+    async fn test_start_with_key_is_idempotent() {
+        let engine = engine().await;
+        let state_machine = StateMachine::builder()
+            .initial_state("work")
+            .add_state(
+                State::new("work")
+                    .on_enter(Action::execute_task(task("a")))
+                    .add_transition(Transition::new("done", "end")),
+            )
+            .add_state(State::new("end"))
+            .build()
+            .unwrap();
+        let definition_id = engine.register_workflow(definition(state_machine)).await.unwrap();
+
+        // A retried start returns the first instance and runs nothing again
+        let first = engine
+            .start_workflow_with_key(&definition_id, json!({ "try": 1 }), "order-1")
+            .await
+            .unwrap();
+        let retried = engine
+            .start_workflow_with_key(&definition_id, json!({ "try": 2 }), "order-1")
+            .await
+            .unwrap();
+        assert_eq!(retried.id, first.id);
+        assert_eq!(retried.context, json!({ "try": 1 }));
+        assert_eq!(enqueued_tasks(&engine, &first.id).await.len(), 1);
+
+        let other = engine
+            .start_workflow_with_key(&definition_id, json!({}), "order-2")
+            .await
+            .unwrap();
+        assert_ne!(other.id, first.id);
+
+        let too_long = "k".repeat(MAX_IDEMPOTENCY_KEY_BYTES + 1);
+        for key in [String::new(), too_long] {
+            assert!(engine.start_workflow_with_key(&definition_id, json!({}), key).await.is_err());
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a FoundationDB cluster"]
+    async fn test_cancel_removes_queued_and_aborts_running_tasks() {
+        let engine = engine().await;
+        let state_machine = StateMachine::builder()
+            .initial_state("work")
+            .add_state(
+                State::new("work")
+                    .on_enter(Action::execute_task(task("a")))
+                    .on_enter(Action::execute_task(task("b")))
+                    .add_transition(Transition::new("done", "end")),
+            )
+            .add_state(State::new("end"))
+            .build()
+            .unwrap();
+        let definition_id = engine.register_workflow(definition(state_machine)).await.unwrap();
+        let instance = engine.start_workflow(&definition_id, json!({})).await.unwrap();
+        let worker_id = WorkerId::new();
+        let running = dequeue(&engine, &worker_id, Duration::from_secs(30)).await.unwrap();
+
+        assert_eq!(engine.cancel_workflow(&instance.id, "no longer needed").await.unwrap(), 2);
+        let cancelled = engine.persistence.workflows().get_instance(&instance.id).await.unwrap().unwrap();
+        assert_eq!(cancelled.status, WorkflowStatus::Cancelled);
+        for task_id in enqueued_tasks(&engine, &instance.id).await {
+            let task = engine.persistence.tasks().get(&task_id).await.unwrap().unwrap();
+            assert_eq!(task.status, TaskStatus::Cancelled);
+        }
+
+        // The queued task is gone and the worker is told to abort the other
+        assert!(dequeue(&engine, &WorkerId::new(), Duration::from_secs(30)).await.is_none());
+        let aborted = engine.persistence.tasks().cancelled(&[running.id]).await.unwrap();
+        assert_eq!(aborted, vec![running.id]);
+
+        // A cancelled instance takes no events and is not cancelled twice
+        assert!(engine.transition_workflow(&instance.id, "done").await.is_err());
+        assert!(engine.cancel_workflow(&instance.id, "again").await.is_err());
+    }
+
+    /// Serve one response with `body`, announcing its length or chunking it
+    async fn serve_once(body: &'static str, chunked: bool) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = [0; 1024];
+            let _ = socket.read(&mut request).await;
+            let response = if chunked {
+                format!(
+                    "HTTP/1.1 200 OK\r\ntransfer-encoding: chunked\r\n\r\n{:x}\r\n{}\r\n0\r\n\r\n",
+                    body.len(),
+                    body
+                )
+            } else {
+                format!("HTTP/1.1 200 OK\r\ncontent-length: {}\r\n\r\n{}", body.len(), body)
+            };
+            socket.write_all(response.as_bytes()).await.unwrap();
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn test_read_body_is_capped() {
+        let client = reqwest::Client::new();
+        for chunked in [false, true] {
+            let url = serve_once("0123456789", chunked).await;
+            let response = client.get(url).send().await.unwrap();
+            assert_eq!(read_body(response, 16).await, Ok("0123456789".to_string()));
+
+            let url = serve_once("0123456789", chunked).await;
+            let response = client.get(url).send().await.unwrap();
+            assert_eq!(read_body(response, 4).await, Err("Response body exceeds 4 bytes".to_string()));
+        }
+    }
+}
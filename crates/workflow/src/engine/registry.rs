@@ -1,6 +1,7 @@
 //! Workflow definition registry
 
-use crate::types::{WorkflowDefinition, WorkflowId};
+use crate::types::{EventSubscription, WorkflowDefinition, WorkflowId};
+use chrono::{DateTime, Utc};
 use std::collections::HashMap;
 
 /// In-memory registry of workflow definitions
@@ -36,6 +37,20 @@ impl WorkflowRegistry {
         self.definitions.remove(id)
     }
 
+    /// List the event subscriptions of all definitions with the ID and
+    /// registration time of the definition declaring them
+    pub fn subscriptions(&self) -> Vec<(WorkflowId, DateTime<Utc>, EventSubscription)> {
+        self.definitions
+            .values()
+            .flat_map(|definition| {
+                definition
+                    .subscriptions
+                    .iter()
+                    .map(|subscription| (definition.id, definition.created_at, subscription.clone()))
+            })
+            .collect()
+    }
+
     /// List all workflow IDs
     pub fn list(&self) -> Vec<WorkflowId> {
         self.definitions.keys().copied().collect()
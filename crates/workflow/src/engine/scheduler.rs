@@ -1,16 +1,37 @@
-//! Task scheduler with round-robin worker selection
+//! Task scheduler with round-robin worker selection and weighted
+//! round-robin between fair queues
 
-use crate::persistence::PersistenceLayer;
-use crate::types::{WorkerInfo, WorkerId};
-use parking_lot::RwLock;
+use crate::error::PersistenceResult;
+use crate::persistence::{PersistenceLayer, QueueOrder};
+use crate::types::{RuntimeType, WorkerHealthStatus, WorkerInfo, WorkerId, WorkerStats};
+use parking_lot::{Mutex, RwLock};
+use std::collections::HashMap;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use tokio::sync::Notify;
 
 /// Task scheduler for round-robin worker assignment
 pub struct TaskScheduler {
     persistence: Arc<PersistenceLayer>,
     workers: Arc<RwLock<Vec<WorkerInfo>>>,
     next_worker_idx: AtomicUsize,
+    /// Wakes task streams waiting for work
+    tasks_available: Arc<Notify>,
+    /// Service order of fair queues
+    queues: FairQueues,
+}
+
+/// Share of a fair queue in smooth weighted round-robin
+#[derive(Debug, Clone, Copy)]
+struct QueueShare {
+    weight: u32,
+    credit: i64,
+}
+
+impl Default for QueueShare {
+    fn default() -> Self {
+        Self { weight: 1, credit: 0 }
+    }
 }
 
 impl TaskScheduler {
@@ -20,6 +41,8 @@ impl TaskScheduler {
             persistence,
             workers: Arc::new(RwLock::new(Vec::new())),
             next_worker_idx: AtomicUsize::new(0),
+            tasks_available: Arc::new(Notify::new()),
+            queues: FairQueues::default(),
         }
     }
 
@@ -41,6 +64,14 @@ impl TaskScheduler {
         tracing::info!("Unregistered worker, total workers: {}", workers.len());
     }
 
+    /// Stop handing tasks to a worker
+    pub fn drain_worker(&self, worker_id: &WorkerId) {
+        let mut workers = self.workers.write();
+        if let Some(worker) = workers.iter_mut().find(|w| w.id == *worker_id) {
+            worker.draining = true;
+        }
+    }
+
     /// Get next worker using round-robin
     pub fn get_next_worker(&self) -> Option<WorkerId> {
         let workers = self.workers.read();
@@ -53,6 +84,25 @@ impl TaskScheduler {
         Some(workers[idx].id.clone())
     }
 
+    /// Signal that tasks were enqueued
+    pub fn notify_tasks_available(&self) {
+        self.tasks_available.notify_waiters();
+    }
+
+    /// Notification waking task streams when tasks are enqueued
+    ///
+    /// Only tasks enqueued through this engine are signalled; waiters look
+    /// again from time to time for retries coming due and tasks enqueued by
+    /// other engines.
+    pub fn tasks_available(&self) -> Arc<Notify> {
+        self.tasks_available.clone()
+    }
+
+    /// Set the weight of a fair queue, 1 for queues never set
+    pub fn set_queue_weight(&self, queue: &str, weight: u32) {
+        self.queues.set_weight(queue, weight);
+    }
+
     /// Get worker count
     pub fn worker_count(&self) -> usize {
         self.workers.read().len()
@@ -68,21 +118,120 @@ impl TaskScheduler {
         self.workers.read().iter().any(|w| w.id == *worker_id)
     }
 
+    /// Check whether any worker that is not unhealthy or dead can run
+    /// `runtime` tasks
+    ///
+    /// Falls back to persisted registrations so tasks are not rejected right
+    /// after an engine restart, before workers have registered again.
+    pub async fn has_capable_worker(&self, runtime: &RuntimeType) -> PersistenceResult<bool> {
+        let capable = |w: &WorkerInfo| {
+            !matches!(w.status, WorkerHealthStatus::Unhealthy | WorkerHealthStatus::Dead)
+                && w.capabilities.contains(runtime)
+        };
+
+        let registered = self.workers.read().iter().any(capable);
+        if registered {
+            return Ok(true);
+        }
+        Ok(self.persistence.workers().list().await?.iter().any(capable))
+    }
+
+    /// Get what a worker registered with, `None` if it is unknown
+    pub async fn worker_info(&self, worker_id: &WorkerId) -> PersistenceResult<Option<WorkerInfo>> {
+        let registered = self.workers.read().iter().find(|w| w.id == *worker_id).cloned();
+        if registered.is_some() {
+            return Ok(registered);
+        }
+        self.persistence.workers().get(worker_id).await
+    }
+
     /// Update worker statistics
-    pub fn update_worker_stats(
-        &self,
-        worker_id: &WorkerId,
-        active_tasks: u32,
-        total_completed: u64,
-        total_failed: u64,
-    ) {
+    pub fn update_worker_stats(&self, worker_id: &WorkerId, stats: &WorkerStats) {
         let mut workers = self.workers.write();
         if let Some(worker) = workers.iter_mut().find(|w| w.id == *worker_id) {
-            worker.stats.active_tasks = active_tasks;
-            worker.stats.total_tasks_completed = total_completed;
-            worker.stats.total_tasks_failed = total_failed;
+            worker.stats = stats.clone();
+        }
+    }
+}
+
+/// Smooth weighted round-robin: every queue with tasks earns its weight in
+/// credit per dispatch, the queue with the most credit is served first and
+/// pays for it with the weights of all queues. Credit is kept per engine, so
+/// dispatches are shared by weight among the tasks each engine hands out.
+#[derive(Default)]
+struct FairQueues {
+    shares: Mutex<HashMap<String, QueueShare>>,
+}
+
+impl FairQueues {
+    fn set_weight(&self, queue: &str, weight: u32) {
+        self.shares.lock().entry(queue.to_string()).or_default().weight = weight.max(1);
+    }
+}
+
+impl QueueOrder for FairQueues {
+    fn order(&self, queues: &[String]) -> Vec<String> {
+        let shares = self.shares.lock();
+        let priority = |queue: &String| {
+            let share = shares.get(queue).copied().unwrap_or_default();
+            share.credit + i64::from(share.weight)
+        };
+
+        let mut ordered = queues.to_vec();
+        ordered.sort_by_key(|queue| std::cmp::Reverse(priority(queue)));
+        ordered
+    }
+
+    fn served(&self, queue: &str, queues: &[String]) {
+        let mut shares = self.shares.lock();
+
+        // Queues that ran dry start over when they get tasks again
+        for (name, share) in shares.iter_mut() {
+            if !queues.contains(name) {
+                share.credit = 0;
+            }
         }
+
+        let mut total = 0;
+        for name in queues {
+            let share = shares.entry(name.clone()).or_default();
+            share.credit += i64::from(share.weight);
+            total += i64::from(share.weight);
+        }
+        shares.entry(queue.to_string()).or_default().credit -= total;
+    }
+}
+
+impl QueueOrder for TaskScheduler {
+    fn order(&self, queues: &[String]) -> Vec<String> {
+        self.queues.order(queues)
+    }
+
+    fn served(&self, queue: &str, queues: &[String]) {
+        self.queues.served(queue, queues)
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn take(fair: &FairQueues, queues: &[String]) -> String {
+        let queue = fair.order(queues).remove(0);
+        fair.served(&queue, queues);
+        queue
+    }
+
+    #[test]
+    fn test_queues_are_served_by_weight() {
+        let fair = FairQueues::default();
+        fair.set_weight("busy", 3);
+        let queues = vec!["busy".to_string(), "quiet".to_string()];
 
+        let served: Vec<String> = (0..8).map(|_| take(&fair, &queues)).collect();
+        assert_eq!(served.iter().filter(|q| *q == "busy").count(), 6);
+        assert_eq!(served.iter().filter(|q| *q == "quiet").count(), 2);
+        // The quiet queue is not left waiting behind the whole busy share
+        assert!(served[..4].contains(&"quiet".to_string()));
+    }
+}
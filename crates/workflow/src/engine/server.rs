@@ -1,16 +1,34 @@
 //! RPC server for worker communication
 
-use crate::engine::WorkflowEngine;
-use crate::error::Result;
-use crate::types::{RuntimeType, WorkerHealthStatus, WorkerInfo, WorkerId, WorkerStats};
+use crate::engine::tls::TlsListener;
+use crate::engine::{export, grpc, tenant, WorkflowEngine};
+use crate::error::{EngineError, PersistenceError, Result, SearchError, SearchResult};
+use crate::runtime::{MAX_KV_RANGE_LIMIT, check_kv_writes};
+use crate::paging::PageSize;
+use crate::search::{FieldFilter, SearchQuery};
+use crate::telemetry;
+use crate::transfer::chunk_at;
+use crate::types::{
+    InstanceCursor, InstanceFilter, Location, RuntimeType, TaskExecution, TaskStatus, WorkerHealthStatus, WorkerInfo, WorkerId, WorkerStats, WorkflowId,
+    WorkflowStatus,
+};
+use axum::body::Body;
+use axum::extract::Request;
+use axum::http::{header, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
 use axum::Router;
 use chrono::Utc;
 use connectare::prelude::*;
+use futures::{Stream, StreamExt};
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::Instrument;
 
 // Include generated protobuf code
-mod proto {
+pub(super) mod proto {
     include!(concat!(env!("OUT_DIR"), "/workflow.rs"));
 }
 
@@ -18,26 +36,228 @@ use proto::*;
 
 /// Run the RPC server using the generated service handlers
 pub async fn run_server(engine: Arc<WorkflowEngine>, bind_addr: SocketAddr) -> Result<()> {
+    let max_message_bytes = engine.message_limits().max_message_bytes();
+    let acceptor = engine.tls.as_ref().map(|tls| tls.acceptor()).transpose()?;
+
+    // Tenants with a key space get their timers and events served before
+    // their first call
+    let tenants = Arc::new(tenant::Tenants::new(engine.clone(), routes));
+    if let Err(e) = tenants.open_all().await {
+        tracing::warn!("Failed to open tenants: {}", e);
+    }
+
+    let app = routes(engine.clone())
+        // Innermost, so tenant calls pass the same checks as others
+        .layer(middleware::from_fn_with_state(tenants, tenant::select_tenant))
+        .layer(middleware::from_fn_with_state(engine.clone(), super::idempotency::replay_idempotent))
+        // Ahead of replay, so stored responses are only served to authorized callers
+        .layer(middleware::from_fn_with_state(engine.clone(), super::authorize::authorize_rpc))
+        .layer(middleware::from_fn_with_state(max_message_bytes, limit_request_size))
+        .layer(middleware::from_fn(enforce_deadline))
+        .layer(middleware::from_fn_with_state(engine, super::instrument::instrument_rpc))
+        // Outermost so gRPC calls pass through every layer as Connect calls
+        // and gRPC clients get gRPC status codes for every failure
+        .layer(middleware::from_fn_with_state(max_message_bytes, grpc::grpc_compat));
+
+    let listener = tokio::net::TcpListener::bind(bind_addr).await
+        .map_err(|e| crate::error::EngineError::Internal(format!("Failed to bind: {}", e)))?;
+
+    let served = match acceptor {
+        Some(acceptor) => {
+            let listener = TlsListener::new(listener, acceptor)
+                .map_err(|e| crate::error::EngineError::Internal(format!("Failed to bind: {}", e)))?;
+            tracing::info!("🚀 Workflow engine server started on {} with TLS", bind_addr);
+            axum::serve(listener, app).await
+        }
+        None => {
+            tracing::info!("🚀 Workflow engine server started on {}", bind_addr);
+            axum::serve(listener, app).await
+        }
+    };
+    served.map_err(|e| crate::error::EngineError::Internal(format!("Server error: {}", e)))?;
+
+    Ok(())
+}
+
+/// Build the router serving the workflow service and its companion
+/// endpoints with `engine`
+fn routes(engine: Arc<WorkflowEngine>) -> Router {
     // Use the generated RPC service methods
     let app = Router::new()
         .rpc(WorkflowService::register_worker(register_worker_handler))
         .rpc(WorkflowService::poll_task(poll_task_handler))
+        .rpc(WorkflowService::poll_tasks(poll_tasks_handler))
+        .rpc(WorkflowService::stream_tasks(stream_tasks_handler))
+        .rpc(WorkflowService::fetch_task_code(fetch_task_code_handler))
         .rpc(WorkflowService::complete_task(complete_task_handler))
+        .rpc(WorkflowService::kv_get(kv_get_handler))
+        .rpc(WorkflowService::kv_range(kv_range_handler))
         .rpc(WorkflowService::heartbeat(heartbeat_handler))
-        .with_state(engine);
+        .rpc(WorkflowService::drain_worker(drain_worker_handler))
+        .rpc(WorkflowService::deregister_worker(deregister_worker_handler))
+        .rpc(WorkflowService::list_workers(list_workers_handler))
+        .rpc(WorkflowService::search_instances(search_instances_handler))
+        .rpc(WorkflowService::add_annotation(add_annotation_handler))
+        .rpc(WorkflowService::list_annotations(list_annotations_handler))
+        .rpc(WorkflowService::start_workflow(start_workflow_handler))
+        .rpc(WorkflowService::cancel_workflow(cancel_workflow_handler))
+        .rpc(WorkflowService::pause_workflow(pause_workflow_handler))
+        .rpc(WorkflowService::resume_workflow(resume_workflow_handler))
+        .rpc(WorkflowService::list_workflow_instances(list_workflow_instances_handler))
+        .rpc(WorkflowService::find_workflow_instances(find_workflow_instances_handler))
+        .rpc(WorkflowService::get_history(get_history_handler))
+        .rpc(WorkflowService::watch_workflow(watch_workflow_handler))
+        .rpc(WorkflowService::get_workflow_graph(get_workflow_graph_handler))
+        .rpc(WorkflowService::list_human_tasks(list_human_tasks_handler))
+        .rpc(WorkflowService::claim_human_task(claim_human_task_handler))
+        .rpc(WorkflowService::complete_human_task(complete_human_task_handler))
+        .route("/export/instances", get(export::export_instances_handler));
+    #[cfg(feature = "graphql")]
+    let app = app.route("/graphql", axum::routing::post(super::graphql::graphql_handler));
+    #[cfg(feature = "websocket")]
+    let app = app.route(
+        "/ws/workflow.WorkflowService/{method}",
+        get(super::websocket::websocket_handler),
+    );
+    app.with_state(engine)
+}
 
-    let listener = tokio::net::TcpListener::bind(bind_addr).await
-        .map_err(|e| crate::error::EngineError::Internal(format!("Failed to bind: {}", e)))?;
+/// Reject requests above the message size limit with a Connect
+/// `resource_exhausted` error instead of failing mid-decode
+///
+/// Unary responses are held to the same limit, so a client gets the error
+/// instead of a message it would refuse to buffer.
+async fn limit_request_size(
+    axum::extract::State(max_message_bytes): axum::extract::State<usize>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let declared = request
+        .headers()
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<usize>().ok());
+    if let Some(len) = declared.filter(|len| *len > max_message_bytes) {
+        return resource_exhausted(len, max_message_bytes);
+    }
 
-    tracing::info!("🚀 Workflow engine server started on {}", bind_addr);
+    // Bodies without a declared length are buffered up to the limit
+    let (parts, body) = request.into_parts();
+    let bytes = match axum::body::to_bytes(body, max_message_bytes).await {
+        Ok(bytes) => bytes,
+        Err(_) => return resource_exhausted(max_message_bytes + 1, max_message_bytes),
+    };
 
-    axum::serve(listener, app).await
-        .map_err(|e| crate::error::EngineError::Internal(format!("Server error: {}", e)))?;
+    // Buffered bodies know their size; streamed ones are left alone
+    let response = next.run(Request::from_parts(parts, Body::from(bytes))).await;
+    let size = axum::body::HttpBody::size_hint(response.body()).exact();
+    match size.map(|len| len as usize).filter(|len| *len > max_message_bytes) {
+        Some(len) => connect_error(
+            StatusCode::TOO_MANY_REQUESTS,
+            "resource_exhausted",
+            format!("response message of {} bytes exceeds the {} byte limit", len, max_message_bytes),
+        ),
+        None => response,
+    }
+}
 
-    Ok(())
+/// Build a Connect protocol `resource_exhausted` error response
+fn resource_exhausted(len: usize, max_message_bytes: usize) -> Response {
+    connect_error(
+        StatusCode::TOO_MANY_REQUESTS,
+        "resource_exhausted",
+        format!("request message of {} bytes exceeds the {} byte limit", len, max_message_bytes),
+    )
 }
 
-async fn register_worker_handler(
+/// Header carrying the client's deadline for a Connect call
+const CONNECT_TIMEOUT_HEADER: &str = "connect-timeout-ms";
+
+/// Envelope flag marking the end-of-stream trailer of a streaming response
+pub(super) const END_STREAM_FLAG: u8 = 0x02;
+
+/// Enforce the deadline a Connect client sets with `connect-timeout-ms`
+///
+/// A unary call still running at the deadline is cancelled by dropping its
+/// handler future and answered with `deadline_exceeded`. A streaming
+/// response is cut off at the deadline with a `deadline_exceeded` trailer.
+pub(super) async fn enforce_deadline(request: Request, next: Next) -> Response {
+    let Some(value) = request.headers().get(CONNECT_TIMEOUT_HEADER) else {
+        return next.run(request).await;
+    };
+    let Some(timeout) = value.to_str().ok().and_then(parse_connect_timeout) else {
+        return connect_error(
+            StatusCode::BAD_REQUEST,
+            "invalid_argument",
+            format!("invalid {} header", CONNECT_TIMEOUT_HEADER),
+        );
+    };
+    let deadline = tokio::time::Instant::now() + timeout;
+
+    // Streaming calls announce their codec in the content type
+    let stream_content_type = request
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .filter(|v| v.to_str().is_ok_and(|v| v.starts_with("application/connect+")))
+        .cloned();
+
+    let response = match (tokio::time::timeout_at(deadline, next.run(request)).await, stream_content_type) {
+        (Ok(response), None) => return response,
+        (Ok(response), Some(_)) => response,
+        (Err(_), None) => {
+            return connect_error(StatusCode::GATEWAY_TIMEOUT, "deadline_exceeded", "deadline exceeded");
+        }
+        (Err(_), Some(content_type)) => {
+            return (StatusCode::OK, [(header::CONTENT_TYPE, content_type)], deadline_exceeded_trailer()).into_response();
+        }
+    };
+
+    // Bound the rest of the stream by the same deadline
+    let (parts, body) = response.into_parts();
+    let frames = Some(body.into_data_stream());
+    let body = futures::stream::unfold(frames, move |frames| async move {
+        let mut frames = frames?;
+        tokio::select! {
+            frame = frames.next() => frame.map(|frame| (frame, Some(frames))),
+            _ = tokio::time::sleep_until(deadline) => Some((Ok(deadline_exceeded_trailer()), None)),
+        }
+    });
+    Response::from_parts(parts, Body::from_stream(body))
+}
+
+/// Parse a `connect-timeout-ms` value: a positive integer of at most 10 digits
+fn parse_connect_timeout(value: &str) -> Option<Duration> {
+    if value.is_empty() || value.len() > 10 || !value.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    value.parse().ok().filter(|ms| *ms > 0).map(Duration::from_millis)
+}
+
+/// Build the end-of-stream envelope closing a stream past its deadline
+fn deadline_exceeded_trailer() -> axum::body::Bytes {
+    let trailer = serde_json::json!({
+        "error": { "code": "deadline_exceeded", "message": "deadline exceeded" },
+    })
+    .to_string();
+
+    let mut envelope = Vec::with_capacity(5 + trailer.len());
+    envelope.push(END_STREAM_FLAG);
+    envelope.extend_from_slice(&(trailer.len() as u32).to_be_bytes());
+    envelope.extend_from_slice(trailer.as_bytes());
+    envelope.into()
+}
+
+/// Build a Connect protocol error response
+pub(super) fn connect_error(status: StatusCode, code: &str, message: impl Into<String>) -> Response {
+    let body = serde_json::json!({
+        "code": code,
+        "message": message.into(),
+    });
+
+    (status, [(header::CONTENT_TYPE, "application/json")], body.to_string()).into_response()
+}
+
+pub(super) async fn register_worker_handler(
     axum::extract::State(engine): axum::extract::State<Arc<WorkflowEngine>>,
     request: RegisterWorkerRequest,
 ) -> RegisterWorkerResponse {
@@ -46,21 +266,22 @@ async fn register_worker_handler(
     let capabilities: Vec<RuntimeType> = request
         .capabilities
         .iter()
-        .filter_map(|c| match c.as_str() {
-            "javascript" => Some(RuntimeType::JavaScript),
-            "wasm" => Some(RuntimeType::Wasm),
-            _ => None,
-        })
+        .map(|c| RuntimeType::from_name(c))
         .collect();
 
     let worker = WorkerInfo {
         id: worker_id.clone(),
         capabilities,
         hostname: request.hostname,
+        location: Location {
+            region: request.region,
+            zone: request.zone,
+        },
         registered_at: Utc::now(),
         last_heartbeat: Utc::now(),
         status: WorkerHealthStatus::Healthy,
         stats: WorkerStats::default(),
+        draining: false,
     };
 
     // Register in scheduler
@@ -83,30 +304,32 @@ async fn register_worker_handler(
     }
 }
 
-async fn poll_task_handler(
+pub(super) async fn poll_task_handler(
     axum::extract::State(engine): axum::extract::State<Arc<WorkflowEngine>>,
     request: PollTaskRequest,
 ) -> PollTaskResponse {
     let worker_id = WorkerId::from_string(request.worker_id);
-
-    // Try to dequeue a task
-    match engine.persistence().tasks().dequeue(&worker_id).await {
-        Ok(Some(task)) => {
-            let payload = TaskPayload {
-                task_id: task.id.to_string(),
-                workflow_id: task.workflow_id.to_string(),
-                task_type: task.definition.runtime_type.as_str().to_string(),
-                code: task.definition.code,
-                input: task.input,
-                timeout_ms: task.definition.timeout_ms as i64,
-                metadata: std::collections::HashMap::new(),
-            };
-
-            PollTaskResponse {
-                task: Some(payload),
-                no_task_reason: None,
+    let worker = match pollable_worker(&engine, &worker_id).await {
+        Ok(worker) => worker,
+        Err(reason) => {
+            return PollTaskResponse {
+                task: None,
+                no_task_reason: Some(reason),
             }
         }
+    };
+
+    // Try to dequeue a task
+    let tasks = engine.persistence().tasks();
+    let visibility_timeout = engine.visibility_timeout();
+    match tasks
+        .dequeue(&worker_id, &worker.capabilities, &worker.location, engine.scheduler(), visibility_timeout)
+        .await
+    {
+        Ok(Some(task)) => PollTaskResponse {
+            task: Some(task_payload(&engine, task)),
+            no_task_reason: None,
+        },
         Ok(None) => {
             PollTaskResponse {
                 task: None,
@@ -123,7 +346,361 @@ async fn poll_task_handler(
     }
 }
 
-async fn complete_task_handler(
+/// Most tasks handed out by one PollTasks call
+const MAX_POLL_BATCH: u32 = 64;
+
+pub(super) async fn poll_tasks_handler(
+    axum::extract::State(engine): axum::extract::State<Arc<WorkflowEngine>>,
+    request: PollTasksRequest,
+) -> PollTasksResponse {
+    let worker_id = WorkerId::from_string(request.worker_id);
+    let worker = match pollable_worker(&engine, &worker_id).await {
+        Ok(worker) => worker,
+        Err(reason) => {
+            return PollTasksResponse {
+                tasks: Vec::new(),
+                no_task_reason: Some(reason),
+            }
+        }
+    };
+
+    let max = request.max_tasks.clamp(1, MAX_POLL_BATCH) as usize;
+    let tasks = engine.persistence().tasks();
+    let visibility_timeout = engine.visibility_timeout();
+    match tasks
+        .dequeue_batch(&worker_id, &worker.capabilities, &worker.location, max, engine.scheduler(), visibility_timeout)
+        .await
+    {
+        Ok(tasks) if tasks.is_empty() => PollTasksResponse {
+            tasks: Vec::new(),
+            no_task_reason: Some("no_pending_tasks".to_string()),
+        },
+        Ok(tasks) => PollTasksResponse {
+            tasks: tasks.into_iter().map(|task| task_payload(&engine, task)).collect(),
+            no_task_reason: None,
+        },
+        Err(e) => {
+            tracing::error!("Failed to dequeue tasks: {}", e);
+            PollTasksResponse {
+                tasks: Vec::new(),
+                no_task_reason: Some(format!("error: {}", e)),
+            }
+        }
+    }
+}
+
+/// How long a task stream waits for a wake-up before looking for tasks
+/// again, to pick up retries coming due and tasks enqueued by other engines
+const STREAM_RECHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long a task stream stays silent before sending an empty message, so
+/// proxies keep it open and a worker that went away is noticed
+const STREAM_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+pub(super) async fn stream_tasks_handler(
+    axum::extract::State(engine): axum::extract::State<Arc<WorkflowEngine>>,
+    request: StreamTasksRequest,
+) -> impl Stream<Item = StreamTasksResponse> {
+    let worker_id = WorkerId::from_string(request.worker_id);
+    let remaining = request.max_tasks.clamp(1, MAX_POLL_BATCH) as usize;
+
+    futures::stream::unfold(remaining, move |remaining| {
+        let engine = engine.clone();
+        let worker_id = worker_id.clone();
+        async move {
+            if remaining == 0 {
+                return None;
+            }
+            let (tasks, left) = next_streamed_tasks(&engine, &worker_id, remaining).await;
+            Some((tasks, left))
+        }
+    })
+}
+
+/// Wait for tasks to hand out on a stream, returning the next message and
+/// how many tasks the stream may still deliver
+async fn next_streamed_tasks(
+    engine: &WorkflowEngine,
+    worker_id: &WorkerId,
+    remaining: usize,
+) -> (StreamTasksResponse, usize) {
+    let tasks_available = engine.scheduler().tasks_available();
+    let idle_since = Instant::now();
+
+    loop {
+        // Listen before looking, so tasks enqueued in between wake the stream
+        let notified = tasks_available.notified();
+        tokio::pin!(notified);
+        notified.as_mut().enable();
+
+        // Drained and deregistered workers stop receiving tasks mid-stream
+        let worker = match pollable_worker(engine, worker_id).await {
+            Ok(worker) => worker,
+            Err(reason) => {
+                let response = StreamTasksResponse {
+                    tasks: Vec::new(),
+                    no_task_reason: Some(reason),
+                };
+                return (response, 0);
+            }
+        };
+
+        let tasks = engine.persistence().tasks();
+        let visibility_timeout = engine.visibility_timeout();
+        match tasks
+            .dequeue_batch(
+                worker_id,
+                &worker.capabilities,
+                &worker.location,
+                remaining,
+                engine.scheduler(),
+                visibility_timeout,
+            )
+            .await
+        {
+            Ok(tasks) if !tasks.is_empty() => {
+                let left = remaining - tasks.len();
+                let response = StreamTasksResponse {
+                    tasks: tasks.into_iter().map(|task| task_payload(engine, task)).collect(),
+                    no_task_reason: None,
+                };
+                return (response, left);
+            }
+            Ok(_) => {}
+            Err(e) => {
+                tracing::error!("Failed to dequeue tasks: {}", e);
+                let response = StreamTasksResponse {
+                    tasks: Vec::new(),
+                    no_task_reason: Some(format!("error: {}", e)),
+                };
+                return (response, 0);
+            }
+        }
+
+        if idle_since.elapsed() >= STREAM_KEEPALIVE_INTERVAL {
+            let response = StreamTasksResponse {
+                tasks: Vec::new(),
+                no_task_reason: None,
+            };
+            return (response, remaining);
+        }
+        let _ = tokio::time::timeout(STREAM_RECHECK_INTERVAL, notified).await;
+    }
+}
+
+/// Look up a worker that may be handed tasks, or the reason it may not
+///
+/// Only tasks for runtimes the worker registered with, placed where the
+/// worker runs, are handed out to it.
+async fn pollable_worker(engine: &WorkflowEngine, worker_id: &WorkerId) -> std::result::Result<WorkerInfo, String> {
+    match engine.scheduler().worker_info(worker_id).await {
+        Ok(Some(worker)) if worker.draining => Err("worker_draining".to_string()),
+        Ok(Some(worker)) => Ok(worker),
+        Ok(None) => Err("worker_not_registered".to_string()),
+        Err(e) => {
+            tracing::error!("Failed to look up worker {}: {}", worker_id, e);
+            Err(format!("error: {}", e))
+        }
+    }
+}
+
+/// Build the payload handing `task` to a worker
+fn task_payload(engine: &WorkflowEngine, task: TaskExecution) -> TaskPayload {
+    // Large code is fetched separately in chunks
+    let code_size = task.definition.code.len() as u64;
+    let code_chunked = engine
+        .message_limits()
+        .requires_chunking(task.definition.code.len());
+    let code = if code_chunked {
+        Vec::new()
+    } else {
+        task.definition.code
+    };
+
+    TaskPayload {
+        task_id: task.id.to_string(),
+        workflow_id: task.workflow_id.to_string(),
+        task_type: task.definition.runtime_type.as_str().to_string(),
+        code,
+        input: task.input,
+        timeout_ms: task.definition.timeout_ms as i64,
+        metadata: std::collections::HashMap::new(),
+        code_chunked,
+        code_size,
+        attempt: task.attempt,
+        attestation: task.definition.attestation,
+        trace_context: task.trace_context,
+        wasm_limits: task.definition.wasm_limits.map(|limits| WasmLimits {
+            fuel: limits.fuel,
+            max_memory_bytes: limits.max_memory_bytes,
+            interrupt_after_ms: limits.interrupt_after_ms,
+        }),
+        http: task.definition.http.map(|policy| HttpPolicy {
+            allowed_hosts: policy.allowed_hosts,
+            max_body_bytes: policy.max_body_bytes,
+            timeout_ms: policy.timeout_ms,
+        }),
+    }
+}
+
+fn running_task_proto(task: crate::types::RunningTask) -> RunningTask {
+    RunningTask {
+        task_id: task.task_id,
+        workflow_id: task.workflow_id,
+        runtime_type: task.runtime_type,
+        attempt: task.attempt,
+        started_at_ms: task.started_at.timestamp_millis(),
+    }
+}
+
+fn running_task_from_proto(task: RunningTask) -> crate::types::RunningTask {
+    crate::types::RunningTask {
+        task_id: task.task_id,
+        workflow_id: task.workflow_id,
+        runtime_type: task.runtime_type,
+        attempt: task.attempt,
+        started_at: chrono::DateTime::from_timestamp_millis(task.started_at_ms).unwrap_or_default(),
+    }
+}
+
+pub(super) async fn fetch_task_code_handler(
+    axum::extract::State(engine): axum::extract::State<Arc<WorkflowEngine>>,
+    request: FetchTaskCodeRequest,
+) -> FetchTaskCodeResponse {
+    let failed = |error: String| FetchTaskCodeResponse {
+        chunk: Vec::new(),
+        offset: request.offset,
+        total_size: 0,
+        error: Some(error),
+    };
+
+    let task_id = match uuid::Uuid::parse_str(&request.task_id) {
+        Ok(id) => crate::types::TaskId::from_uuid(id),
+        Err(e) => return failed(format!("Invalid task ID: {}", e)),
+    };
+
+    let task = match engine.persistence().tasks().get(&task_id).await {
+        Ok(Some(task)) => task,
+        Ok(None) => return failed(format!("Task not found: {}", task_id)),
+        Err(e) => {
+            tracing::error!("Failed to load task {}: {}", task_id, e);
+            return failed(format!("error: {}", e));
+        }
+    };
+
+    // Only the worker the task is assigned to may fetch its code
+    let worker_id = WorkerId::from_string(request.worker_id.clone());
+    if task.assigned_worker.as_ref() != Some(&worker_id) {
+        return failed(format!("Task {} is not assigned to worker {}", task_id, worker_id));
+    }
+
+    let code = &task.definition.code;
+    match chunk_at(code, request.offset, engine.message_limits().chunk_bytes()) {
+        Some(chunk) => FetchTaskCodeResponse {
+            chunk: chunk.to_vec(),
+            offset: request.offset,
+            total_size: code.len() as u64,
+            error: None,
+        },
+        None => failed(format!(
+            "Offset {} is past the end of the {} byte task code",
+            request.offset,
+            code.len()
+        )),
+    }
+}
+
+pub(super) async fn kv_get_handler(
+    axum::extract::State(engine): axum::extract::State<Arc<WorkflowEngine>>,
+    request: KvGetRequest,
+) -> KvGetResponse {
+    let result = match running_task_workflow(&engine, &request.worker_id, &request.task_id).await {
+        Ok(workflow_id) => engine
+            .persistence()
+            .kv()
+            .get(&workflow_id, &request.key)
+            .await
+            .map_err(|e| e.to_string()),
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok(value) => KvGetResponse { value, error: None },
+        Err(e) => {
+            tracing::warn!("Failed to read key of task {}: {}", request.task_id, e);
+            KvGetResponse {
+                value: None,
+                error: Some(e),
+            }
+        }
+    }
+}
+
+/// Page sizes of a key-value range read
+const KV_RANGE_PAGE: PageSize = PageSize::new(MAX_KV_RANGE_LIMIT as u32, MAX_KV_RANGE_LIMIT as u32);
+
+pub(super) async fn kv_range_handler(
+    axum::extract::State(engine): axum::extract::State<Arc<WorkflowEngine>>,
+    request: KvRangeRequest,
+) -> KvRangeResponse {
+    let limit = KV_RANGE_PAGE.clamp(request.limit);
+
+    let result = match running_task_workflow(&engine, &request.worker_id, &request.task_id).await {
+        Ok(workflow_id) => engine
+            .persistence()
+            .kv()
+            .range(&workflow_id, &request.start, &request.end, limit)
+            .await
+            .map_err(|e| e.to_string()),
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok(entries) => KvRangeResponse {
+            entries: entries
+                .into_iter()
+                .map(|(key, value)| KvEntry { key, value })
+                .collect(),
+            error: None,
+        },
+        Err(e) => {
+            tracing::warn!("Failed to read keys of task {}: {}", request.task_id, e);
+            KvRangeResponse {
+                entries: Vec::new(),
+                error: Some(e),
+            }
+        }
+    }
+}
+
+/// Look up the instance of a task running on a worker, whose key-value
+/// state the task may read
+async fn running_task_workflow(
+    engine: &WorkflowEngine,
+    worker_id: &str,
+    task_id: &str,
+) -> std::result::Result<WorkflowId, String> {
+    let task_id = uuid::Uuid::parse_str(task_id)
+        .map(crate::types::TaskId::from_uuid)
+        .map_err(|e| format!("Invalid task ID: {}", e))?;
+    let task = engine
+        .persistence()
+        .tasks()
+        .get(&task_id)
+        .await
+        .map_err(|e| format!("error: {}", e))?
+        .ok_or_else(|| format!("Task not found: {}", task_id))?;
+
+    let worker_id = WorkerId::from_string(worker_id.to_string());
+    if task.assigned_worker.as_ref() != Some(&worker_id)
+        || !matches!(task.status, TaskStatus::Assigned | TaskStatus::Running)
+    {
+        return Err(format!("Task {} is not running on worker {}", task_id, worker_id));
+    }
+    Ok(task.workflow_id)
+}
+
+pub(super) async fn complete_task_handler(
     axum::extract::State(engine): axum::extract::State<Arc<WorkflowEngine>>,
     request: CompleteTaskRequest,
 ) -> CompleteTaskResponse {
@@ -133,33 +710,97 @@ async fn complete_task_handler(
             tracing::error!("Invalid task ID: {}", e);
             return CompleteTaskResponse {
                 acknowledged: false,
+                rejected_reason: Some(format!("Invalid task ID: {}", e)),
             };
         }
     };
 
+    let worker_id = WorkerId::from_string(request.worker_id);
     let result_proto = request.result.unwrap_or_default();
-    let result = crate::types::TaskResult {
+    let kv_writes: Vec<_> = result_proto
+        .kv_writes
+        .into_iter()
+        .map(|write| match write.value {
+            Some(value) => crate::types::KvWrite::Set { key: write.key, value },
+            None => crate::types::KvWrite::Delete { key: write.key },
+        })
+        .collect();
+    let mut result = crate::types::TaskResult {
         success: result_proto.success,
         output: result_proto.output,
         error: result_proto.error,
         execution_time_ms: result_proto.execution_time_ms.max(0) as u64,
+        kv_writes,
     };
 
-    if let Err(e) = engine.persistence().tasks().complete(&task_id, result).await {
-        tracing::error!("Failed to complete task: {}", e);
-        return CompleteTaskResponse {
-            acknowledged: false,
-        };
+    // Workers hold tasks to the key-value budget; a task that got past it
+    // fails instead of committing its writes
+    if let Err(e) = check_kv_writes(&result.kv_writes) {
+        tracing::warn!("Task {} reported writes over budget: {}", task_id, e);
+        result.success = false;
+        result.error = Some(e.to_string());
+        result.kv_writes.clear();
+    }
+
+    // Completing the task continues the trace of the worker that ran it
+    let span = tracing::info_span!("task.complete", task_id = %task_id, worker_id = %worker_id);
+    telemetry::set_parent(&span, &request.trace_context);
+    complete_task(&engine, task_id, worker_id, request.attempt, result)
+        .instrument(span)
+        .await
+}
+
+/// Record a task result reported by a worker and act on the outcome
+async fn complete_task(
+    engine: &WorkflowEngine,
+    task_id: crate::types::TaskId,
+    worker_id: WorkerId,
+    attempt: u32,
+    result: crate::types::TaskResult,
+) -> CompleteTaskResponse {
+    match engine
+        .persistence()
+        .tasks()
+        .complete_fenced(&task_id, &worker_id, attempt, result)
+        .await
+    {
+        Ok(TaskStatus::Retrying) => {
+            tracing::info!("Task {} failed, retry scheduled", task_id);
+            return CompleteTaskResponse {
+                acknowledged: true,
+                rejected_reason: None,
+            };
+        }
+        Ok(status) => {
+            if let Err(e) = engine.handle_task_outcome(&task_id, status).await {
+                tracing::error!("Failed to handle outcome of task {}: {}", task_id, e);
+            }
+        }
+        Err(PersistenceError::Fenced(reason)) => {
+            tracing::warn!("Rejected stale completion from {}: {}", worker_id, reason);
+            return CompleteTaskResponse {
+                acknowledged: false,
+                rejected_reason: Some(reason),
+            };
+        }
+        Err(e) => {
+            tracing::error!("Failed to complete task: {}", e);
+            return CompleteTaskResponse {
+                acknowledged: false,
+                rejected_reason: None,
+            };
+        }
     }
 
     tracing::info!("Task {} completed", task_id);
 
     CompleteTaskResponse {
         acknowledged: true,
+        rejected_reason: None,
     }
 }
 
-async fn heartbeat_handler(
+pub(super) async fn heartbeat_handler(
     axum::extract::State(engine): axum::extract::State<Arc<WorkflowEngine>>,
     request: HeartbeatRequest,
 ) -> HeartbeatResponse {
@@ -170,18 +811,895 @@ async fn heartbeat_handler(
         tracing::error!("Failed to update heartbeat: {}", e);
     }
 
-    // Update stats in scheduler
+    // Update stats in scheduler, and in persistence for listing workers
     if let Some(status) = request.status {
-        engine.scheduler().update_worker_stats(
-            &worker_id,
-            status.active_tasks as u32,
-            status.total_tasks_completed as u64,
-            status.total_tasks_failed as u64,
-        );
+        let stats = WorkerStats {
+            active_tasks: status.active_tasks as u32,
+            total_tasks_completed: status.total_tasks_completed as u64,
+            total_tasks_failed: status.total_tasks_failed as u64,
+            max_concurrency: status.max_concurrency,
+            running_tasks: status.running_tasks.into_iter().map(running_task_from_proto).collect(),
+        };
+        engine.scheduler().update_worker_stats(&worker_id, &stats);
+        if let Err(e) = engine.persistence().workers().update_stats(&worker_id, &stats).await {
+            tracing::error!("Failed to update worker stats: {}", e);
+        }
     }
 
+    // Tell the worker which of its tasks to abort
+    let running: Vec<_> = request
+        .running_task_ids
+        .iter()
+        .filter_map(|id| uuid::Uuid::parse_str(id).ok())
+        .map(crate::types::TaskId::from_uuid)
+        .collect();
+    let mut cancelled_task_ids: Vec<String> = match engine.persistence().tasks().cancelled(&running).await {
+        Ok(cancelled) => cancelled.iter().map(|id| id.to_string()).collect(),
+        Err(e) => {
+            tracing::error!("Failed to look up cancelled tasks: {}", e);
+            Vec::new()
+        }
+    };
+
+    // Extend the leases of the tasks still running; tasks whose lease ran
+    // out have been queued again and are aborted as well
+    let visibility_timeout = engine.visibility_timeout();
+    match engine.persistence().tasks().extend_leases(&worker_id, &running, visibility_timeout).await {
+        Ok(lost) => cancelled_task_ids.extend(lost.iter().map(|id| id.to_string())),
+        Err(e) => tracing::error!("Failed to extend task leases: {}", e),
+    }
+
+    let drain = match engine.scheduler().worker_info(&worker_id).await {
+        Ok(worker) => worker.is_some_and(|w| w.draining),
+        Err(e) => {
+            tracing::error!("Failed to look up worker {}: {}", worker_id, e);
+            false
+        }
+    };
+
     HeartbeatResponse {
         active: true,
         message: Some("Heartbeat received".to_string()),
+        cancelled_task_ids,
+        drain,
+    }
+}
+
+pub(super) async fn drain_worker_handler(
+    axum::extract::State(engine): axum::extract::State<Arc<WorkflowEngine>>,
+    request: DrainWorkerRequest,
+) -> DrainWorkerResponse {
+    let worker_id = WorkerId::from_string(request.worker_id);
+    match engine.drain_worker(&worker_id).await {
+        Ok(()) => DrainWorkerResponse {
+            draining: true,
+            error: None,
+        },
+        Err(e) => {
+            tracing::warn!("Failed to drain worker {}: {}", worker_id, e);
+            DrainWorkerResponse {
+                draining: false,
+                error: Some(e.to_string()),
+            }
+        }
+    }
+}
+
+pub(super) async fn deregister_worker_handler(
+    axum::extract::State(engine): axum::extract::State<Arc<WorkflowEngine>>,
+    request: DeregisterWorkerRequest,
+) -> DeregisterWorkerResponse {
+    let worker_id = WorkerId::from_string(request.worker_id);
+    match engine.deregister_worker(&worker_id).await {
+        Ok(()) => DeregisterWorkerResponse {
+            success: true,
+            error: None,
+        },
+        Err(e) => {
+            tracing::warn!("Failed to deregister worker {}: {}", worker_id, e);
+            DeregisterWorkerResponse {
+                success: false,
+                error: Some(e.to_string()),
+            }
+        }
+    }
+}
+
+pub(super) async fn list_workers_handler(
+    axum::extract::State(engine): axum::extract::State<Arc<WorkflowEngine>>,
+    _request: ListWorkersRequest,
+) -> ListWorkersResponse {
+    match engine.list_workers().await {
+        Ok(workers) => {
+            let now = Utc::now();
+            ListWorkersResponse {
+                workers: workers
+                    .into_iter()
+                    .map(|worker| WorkerSummary {
+                        missed_heartbeats: engine.missed_heartbeats(worker.last_heartbeat, now),
+                        worker_id: worker.id.to_string(),
+                        hostname: worker.hostname,
+                        capabilities: worker.capabilities.iter().map(|c| c.as_str().to_string()).collect(),
+                        region: worker.location.region,
+                        zone: worker.location.zone,
+                        health: worker.status.as_str().to_string(),
+                        registered_at_ms: worker.registered_at.timestamp_millis(),
+                        last_heartbeat_ms: worker.last_heartbeat.timestamp_millis(),
+                        draining: worker.draining,
+                        active_tasks: worker.stats.active_tasks as i32,
+                        total_tasks_completed: worker.stats.total_tasks_completed as i64,
+                        total_tasks_failed: worker.stats.total_tasks_failed as i64,
+                        max_concurrency: worker.stats.max_concurrency,
+                        running_tasks: worker.stats.running_tasks.into_iter().map(running_task_proto).collect(),
+                    })
+                    .collect(),
+                error: None,
+            }
+        }
+        Err(e) => {
+            tracing::warn!("Failed to list workers: {}", e);
+            ListWorkersResponse {
+                workers: Vec::new(),
+                error: Some(e.to_string()),
+            }
+        }
+    }
+}
+
+/// Page sizes of a search
+const SEARCH_PAGE: PageSize = PageSize::new(20, 1000);
+
+pub(super) async fn search_instances_handler(
+    axum::extract::State(engine): axum::extract::State<Arc<WorkflowEngine>>,
+    request: SearchInstancesRequest,
+) -> SearchInstancesResponse {
+    let result = match search_query(request) {
+        Ok(query) => engine.search_instances(&query).await,
+        Err(e) => Err(e.into()),
+    };
+
+    match result {
+        Ok(results) => SearchInstancesResponse {
+            hits: results
+                .hits
+                .into_iter()
+                .map(|hit| SearchHit {
+                    workflow_id: hit.workflow_id.to_string(),
+                    score: hit.score,
+                })
+                .collect(),
+            total: results.total,
+            error: None,
+        },
+        Err(e) => {
+            tracing::warn!("Instance search failed: {}", e);
+            SearchInstancesResponse {
+                hits: Vec::new(),
+                total: 0,
+                error: Some(e.to_string()),
+            }
+        }
+    }
+}
+
+/// Convert a search request into an engine query
+fn search_query(request: SearchInstancesRequest) -> SearchResult<SearchQuery> {
+    let definition_id = request
+        .definition_id
+        .map(|id| {
+            uuid::Uuid::parse_str(&id)
+                .map(WorkflowId::from_uuid)
+                .map_err(|e| SearchError::InvalidQuery(format!("definition_id: {}", e)))
+        })
+        .transpose()?;
+
+    let status = request
+        .status
+        .map(|status| {
+            serde_json::from_value::<WorkflowStatus>(serde_json::Value::String(status.clone()))
+                .map_err(|_| SearchError::InvalidQuery(format!("Unknown status: {}", status)))
+        })
+        .transpose()?;
+
+    let mut filters: Vec<FieldFilter> = request
+        .equals
+        .into_iter()
+        .map(|(field, value)| FieldFilter::Equals { field, value })
+        .collect();
+    filters.extend(request.ranges.into_iter().map(|(field, range)| FieldFilter::Range {
+        field,
+        min: range.min,
+        max: range.max,
+    }));
+
+    Ok(SearchQuery {
+        text: request.text,
+        definition_id,
+        status,
+        filters,
+        limit: SEARCH_PAGE.clamp(request.limit),
+        offset: request.offset as usize,
+    })
+}
+
+/// Page sizes of an instance listing
+const LIST_PAGE: PageSize = PageSize::new(50, 1000);
+
+pub(super) async fn start_workflow_handler(
+    axum::extract::State(engine): axum::extract::State<Arc<WorkflowEngine>>,
+    request: StartWorkflowRequest,
+) -> StartWorkflowResponse {
+    let input = if request.input.is_empty() {
+        Ok(serde_json::json!({}))
+    } else {
+        serde_json::from_str(&request.input)
+            .map_err(|e| EngineError::Workflow(crate::error::WorkflowError::InvalidInput(e.to_string())))
+    };
+
+    // The start continues the caller's trace
+    let span = tracing::info_span!("workflow.start", definition_id = %request.definition_id);
+    telemetry::set_parent(&span, &request.trace_context);
+    let result = async {
+        match (parse_workflow_id(&request.definition_id), input) {
+            (Ok(definition_id), Ok(input)) => match request.idempotency_key {
+                Some(key) => engine.start_workflow_with_key(&definition_id, input, key).await,
+                None => engine.start_workflow(&definition_id, input).await,
+            },
+            (Err(e), _) | (_, Err(e)) => Err(e),
+        }
+    }
+    .instrument(span)
+    .await;
+
+    match result {
+        Ok(instance) => {
+            // Backpressure is advisory; failing to read it fails no start
+            let backpressure = match engine.backpressure(&instance.definition_id).await {
+                Ok(backpressure) => backpressure,
+                Err(e) => {
+                    tracing::warn!("Failed to read backpressure of {}: {}", instance.definition_id, e);
+                    None
+                }
+            };
+            StartWorkflowResponse {
+                instance: Some(instance_summary(instance)),
+                error: None,
+                backpressure: backpressure.map(|b| Backpressure {
+                    running_instances: b.running_instances,
+                    max_running_instances: b.max_running_instances,
+                    queued_instances: b.queued_instances,
+                }),
+            }
+        }
+        Err(e) => {
+            tracing::warn!("Failed to start workflow {}: {}", request.definition_id, e);
+            StartWorkflowResponse {
+                instance: None,
+                error: Some(e.to_string()),
+                backpressure: None,
+            }
+        }
+    }
+}
+
+pub(super) async fn cancel_workflow_handler(
+    axum::extract::State(engine): axum::extract::State<Arc<WorkflowEngine>>,
+    request: CancelWorkflowRequest,
+) -> CancelWorkflowResponse {
+    let reason = if request.reason.is_empty() {
+        "Cancelled by request".to_string()
+    } else {
+        request.reason
+    };
+
+    let result = match parse_workflow_id(&request.workflow_id) {
+        Ok(workflow_id) => engine.cancel_workflow(&workflow_id, reason).await,
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok(tasks) => CancelWorkflowResponse {
+            cancelled: true,
+            cancelled_tasks: tasks as i32,
+            error: None,
+        },
+        Err(e) => {
+            tracing::warn!("Failed to cancel workflow {}: {}", request.workflow_id, e);
+            CancelWorkflowResponse {
+                cancelled: false,
+                cancelled_tasks: 0,
+                error: Some(e.to_string()),
+            }
+        }
+    }
+}
+
+pub(super) async fn pause_workflow_handler(
+    axum::extract::State(engine): axum::extract::State<Arc<WorkflowEngine>>,
+    request: PauseWorkflowRequest,
+) -> PauseWorkflowResponse {
+    let reason = if request.reason.is_empty() {
+        "Paused by request".to_string()
+    } else {
+        request.reason
+    };
+
+    let result = match parse_workflow_id(&request.workflow_id) {
+        Ok(workflow_id) => engine.pause_workflow(&workflow_id, reason).await,
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok(()) => PauseWorkflowResponse {
+            paused: true,
+            error: None,
+        },
+        Err(e) => {
+            tracing::warn!("Failed to pause workflow {}: {}", request.workflow_id, e);
+            PauseWorkflowResponse {
+                paused: false,
+                error: Some(e.to_string()),
+            }
+        }
+    }
+}
+
+pub(super) async fn resume_workflow_handler(
+    axum::extract::State(engine): axum::extract::State<Arc<WorkflowEngine>>,
+    request: ResumeWorkflowRequest,
+) -> ResumeWorkflowResponse {
+    let result = match parse_workflow_id(&request.workflow_id) {
+        Ok(workflow_id) => engine.resume_workflow(&workflow_id).await,
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok(()) => ResumeWorkflowResponse {
+            resumed: true,
+            error: None,
+        },
+        Err(e) => {
+            tracing::warn!("Failed to resume workflow {}: {}", request.workflow_id, e);
+            ResumeWorkflowResponse {
+                resumed: false,
+                error: Some(e.to_string()),
+            }
+        }
+    }
+}
+
+pub(super) async fn list_workflow_instances_handler(
+    axum::extract::State(engine): axum::extract::State<Arc<WorkflowEngine>>,
+    request: ListWorkflowInstancesRequest,
+) -> ListWorkflowInstancesResponse {
+    let limit = LIST_PAGE.clamp(request.limit);
+
+    let result = match list_filter(&request) {
+        Ok((filter, cursor)) => engine.list_instances(&filter, cursor.as_ref(), limit).await,
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok(page) => ListWorkflowInstancesResponse {
+            instances: page
+                .instances
+                .into_iter()
+                .map(instance_summary)
+                .collect(),
+            next_cursor: page.next.map(|cursor| cursor.to_string()),
+            error: None,
+        },
+        Err(e) => {
+            tracing::warn!("Failed to list workflow instances: {}", e);
+            ListWorkflowInstancesResponse {
+                instances: Vec::new(),
+                next_cursor: None,
+                error: Some(e.to_string()),
+            }
+        }
+    }
+}
+
+/// Convert a listing request into an instance filter and cursor
+fn list_filter(request: &ListWorkflowInstancesRequest) -> Result<(InstanceFilter, Option<InstanceCursor>)> {
+    let invalid = |message: String| EngineError::Workflow(crate::error::WorkflowError::InvalidFilter(message));
+    let timestamp = |field: &str, ms: Option<i64>| {
+        ms.map(|ms| {
+            chrono::DateTime::from_timestamp_millis(ms)
+                .ok_or_else(|| invalid(format!("{} out of range: {}", field, ms)))
+        })
+        .transpose()
+    };
+
+    let definition_id = request
+        .definition_id
+        .as_deref()
+        .map(|id| {
+            uuid::Uuid::parse_str(id)
+                .map(WorkflowId::from_uuid)
+                .map_err(|e| invalid(format!("definition_id: {}", e)))
+        })
+        .transpose()?;
+
+    let status = request
+        .status
+        .as_deref()
+        .map(|status| {
+            serde_json::from_value::<WorkflowStatus>(serde_json::Value::String(status.to_string()))
+                .map_err(|_| invalid(format!("Unknown status: {}", status)))
+        })
+        .transpose()?;
+
+    let cursor = request
+        .cursor
+        .as_deref()
+        .map(|cursor| cursor.parse::<InstanceCursor>().map_err(invalid))
+        .transpose()?;
+
+    let filter = InstanceFilter {
+        definition_id,
+        status,
+        created_after: timestamp("created_after_ms", request.created_after_ms)?,
+        created_before: timestamp("created_before_ms", request.created_before_ms)?,
+    };
+    Ok((filter, cursor))
+}
+
+pub(super) async fn find_workflow_instances_handler(
+    axum::extract::State(engine): axum::extract::State<Arc<WorkflowEngine>>,
+    request: FindWorkflowInstancesRequest,
+) -> FindWorkflowInstancesResponse {
+    // Values that are not JSON are looked up as strings
+    let value = serde_json::from_str(&request.value).unwrap_or_else(|_| serde_json::Value::String(request.value.clone()));
+    let result = match parse_workflow_id(&request.definition_id) {
+        Ok(definition_id) => engine.find_by_index(&definition_id, &request.path, &value).await,
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok(instances) => FindWorkflowInstancesResponse {
+            instances: instances.into_iter().map(instance_summary).collect(),
+            error: None,
+        },
+        Err(e) => {
+            tracing::warn!("Failed to find workflow instances by {}: {}", request.path, e);
+            FindWorkflowInstancesResponse {
+                instances: Vec::new(),
+                error: Some(e.to_string()),
+            }
+        }
+    }
+}
+
+/// Page sizes of a history read
+const HISTORY_PAGE: PageSize = PageSize::new(500, 500);
+
+pub(super) async fn get_history_handler(
+    axum::extract::State(engine): axum::extract::State<Arc<WorkflowEngine>>,
+    request: GetHistoryRequest,
+) -> GetHistoryResponse {
+    let limit = HISTORY_PAGE.clamp(request.limit);
+
+    let result = match parse_workflow_id(&request.workflow_id) {
+        Ok(workflow_id) => engine
+            .persistence()
+            .history()
+            .read(&workflow_id, request.after_sequence, limit)
+            .await
+            .map_err(EngineError::Persistence),
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok(events) => GetHistoryResponse {
+            events: events.into_iter().map(history_event_proto).collect(),
+            error: None,
+        },
+        Err(e) => {
+            tracing::warn!("Failed to read history of {}: {}", request.workflow_id, e);
+            GetHistoryResponse {
+                events: Vec::new(),
+                error: Some(e.to_string()),
+            }
+        }
+    }
+}
+
+/// How often a watch stream looks for new history
+const WATCH_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Position of a watch stream in the history of its instance
+struct WatchCursor {
+    after_sequence: Option<u64>,
+    started: bool,
+    done: bool,
+}
+
+pub(super) async fn watch_workflow_handler(
+    axum::extract::State(engine): axum::extract::State<Arc<WorkflowEngine>>,
+    request: WatchWorkflowRequest,
+) -> impl Stream<Item = WatchWorkflowResponse> {
+    let cursor = WatchCursor {
+        after_sequence: request.after_sequence,
+        started: false,
+        done: false,
+    };
+
+    futures::stream::unfold(cursor, move |cursor| {
+        let engine = engine.clone();
+        let workflow_id = request.workflow_id.clone();
+        async move {
+            if cursor.done {
+                return None;
+            }
+            Some(next_workflow_changes(&engine, &workflow_id, cursor).await)
+        }
+    })
+}
+
+/// Wait for an instance to change, returning the next message and where the
+/// stream continues from
+///
+/// The first message reports the current status right away; later ones are
+/// sent as history is recorded.
+async fn next_workflow_changes(
+    engine: &WorkflowEngine,
+    workflow_id: &str,
+    cursor: WatchCursor,
+) -> (WatchWorkflowResponse, WatchCursor) {
+    let failed = |e: String| {
+        tracing::warn!("Failed to watch workflow {}: {}", workflow_id, e);
+        let response = WatchWorkflowResponse {
+            status: String::new(),
+            current_state: String::new(),
+            events: Vec::new(),
+            error: Some(e),
+        };
+        let cursor = WatchCursor {
+            after_sequence: cursor.after_sequence,
+            started: true,
+            done: true,
+        };
+        (response, cursor)
+    };
+    let id = match parse_workflow_id(workflow_id) {
+        Ok(id) => id,
+        Err(e) => return failed(e.to_string()),
+    };
+    let idle_since = Instant::now();
+
+    loop {
+        // Read the instance first, so history written together with a final
+        // status is included below
+        let instance = match engine.persistence().workflows().get_instance(&id).await {
+            Ok(Some(instance)) => instance,
+            Ok(None) => return failed(format!("Workflow instance not found: {}", id)),
+            Err(e) => return failed(e.to_string()),
+        };
+        let events = match engine
+            .persistence()
+            .history()
+            .read(&id, cursor.after_sequence, MAX_HISTORY_LIMIT as usize)
+            .await
+        {
+            Ok(events) => events,
+            Err(e) => return failed(e.to_string()),
+        };
+
+        let finished = matches!(
+            instance.status,
+            WorkflowStatus::Completed | WorkflowStatus::Failed | WorkflowStatus::Cancelled
+        );
+        let caught_up = events.len() < MAX_HISTORY_LIMIT as usize;
+        if !events.is_empty() || !cursor.started || finished || idle_since.elapsed() >= STREAM_KEEPALIVE_INTERVAL {
+            let cursor = WatchCursor {
+                after_sequence: events.last().map(|e| e.sequence).or(cursor.after_sequence),
+                started: true,
+                done: finished && caught_up,
+            };
+            let response = WatchWorkflowResponse {
+                status: instance.status.as_str().to_string(),
+                current_state: instance.current_state,
+                events: events.into_iter().map(history_event_proto).collect(),
+                error: None,
+            };
+            return (response, cursor);
+        }
+        tokio::time::sleep(WATCH_POLL_INTERVAL).await;
+    }
+}
+
+fn history_event_proto(event: crate::types::HistoryEvent) -> HistoryEvent {
+    // The kind travels separately, the details are the remaining fields
+    let mut details = serde_json::to_value(&event.kind).unwrap_or_default();
+    if let serde_json::Value::Object(fields) = &mut details {
+        fields.remove("type");
+    }
+    HistoryEvent {
+        sequence: event.sequence,
+        recorded_at_ms: event.recorded_at.timestamp_millis(),
+        kind: event.kind.as_str().to_string(),
+        details: details.to_string(),
+    }
+}
+
+pub(super) async fn get_workflow_graph_handler(
+    axum::extract::State(engine): axum::extract::State<Arc<WorkflowEngine>>,
+    request: GetWorkflowGraphRequest,
+) -> GetWorkflowGraphResponse {
+    let definition = match parse_workflow_id(&request.definition_id) {
+        Ok(definition_id) => engine
+            .persistence()
+            .workflows()
+            .get_definition(&definition_id)
+            .await
+            .map_err(EngineError::Persistence)
+            .and_then(|definition| {
+                definition.ok_or_else(|| {
+                    EngineError::Workflow(crate::error::WorkflowError::NotFound(request.definition_id.clone()))
+                })
+            }),
+        Err(e) => Err(e),
+    };
+
+    let result = definition.and_then(|definition| match request.format.as_str() {
+        "" | "dot" => Ok(definition.state_machine.to_dot()),
+        "mermaid" => Ok(definition.state_machine.to_mermaid()),
+        format => Err(EngineError::Workflow(crate::error::WorkflowError::InvalidInput(format!(
+            "Unknown graph format '{}'",
+            format
+        )))),
+    });
+
+    match result {
+        Ok(graph) => GetWorkflowGraphResponse { graph, error: None },
+        Err(e) => {
+            tracing::warn!("Failed to render graph of {}: {}", request.definition_id, e);
+            GetWorkflowGraphResponse {
+                graph: String::new(),
+                error: Some(e.to_string()),
+            }
+        }
+    }
+}
+
+pub(super) async fn add_annotation_handler(
+    axum::extract::State(engine): axum::extract::State<Arc<WorkflowEngine>>,
+    request: AddAnnotationRequest,
+) -> AddAnnotationResponse {
+    let content = match request.content {
+        Some(add_annotation_request::Content::Comment(comment)) => {
+            crate::types::AnnotationContent::Comment { body: comment.body }
+        }
+        Some(add_annotation_request::Content::Attachment(attachment)) => {
+            crate::types::AnnotationContent::Attachment {
+                name: attachment.name,
+                content_type: attachment.content_type,
+                size: attachment.size,
+                blob: attachment.blob,
+            }
+        }
+        None => {
+            return AddAnnotationResponse {
+                annotation: None,
+                error: Some("Missing annotation content".to_string()),
+            }
+        }
+    };
+
+    let result = match parse_workflow_id(&request.workflow_id) {
+        Ok(workflow_id) => {
+            engine
+                .annotate(&principal(request.principal), &workflow_id, content)
+                .await
+        }
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok(annotation) => AddAnnotationResponse {
+            annotation: Some(annotation_proto(annotation)),
+            error: None,
+        },
+        Err(e) => {
+            tracing::warn!("Failed to annotate workflow {}: {}", request.workflow_id, e);
+            AddAnnotationResponse {
+                annotation: None,
+                error: Some(e.to_string()),
+            }
+        }
+    }
+}
+
+pub(super) async fn list_annotations_handler(
+    axum::extract::State(engine): axum::extract::State<Arc<WorkflowEngine>>,
+    request: ListAnnotationsRequest,
+) -> ListAnnotationsResponse {
+    let result = match parse_workflow_id(&request.workflow_id) {
+        Ok(workflow_id) => {
+            engine
+                .annotations(&principal(request.principal), &workflow_id)
+                .await
+        }
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok(annotations) => ListAnnotationsResponse {
+            annotations: annotations.into_iter().map(annotation_proto).collect(),
+            error: None,
+        },
+        Err(e) => {
+            tracing::warn!("Failed to list annotations of {}: {}", request.workflow_id, e);
+            ListAnnotationsResponse {
+                annotations: Vec::new(),
+                error: Some(e.to_string()),
+            }
+        }
+    }
+}
+
+pub(super) async fn list_human_tasks_handler(
+    axum::extract::State(engine): axum::extract::State<Arc<WorkflowEngine>>,
+    request: ListHumanTasksRequest,
+) -> ListHumanTasksResponse {
+    let result = match request.workflow_id.as_deref().map(parse_workflow_id).transpose() {
+        Ok(workflow_id) => {
+            engine
+                .human_tasks(&principal(request.principal), workflow_id.as_ref())
+                .await
+        }
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok(tasks) => ListHumanTasksResponse {
+            tasks: tasks.into_iter().map(human_task_proto).collect(),
+            error: None,
+        },
+        Err(e) => {
+            tracing::warn!("Failed to list human tasks: {}", e);
+            ListHumanTasksResponse {
+                tasks: Vec::new(),
+                error: Some(e.to_string()),
+            }
+        }
+    }
+}
+
+pub(super) async fn claim_human_task_handler(
+    axum::extract::State(engine): axum::extract::State<Arc<WorkflowEngine>>,
+    request: ClaimHumanTaskRequest,
+) -> ClaimHumanTaskResponse {
+    let result = match parse_human_task_id(&request.task_id) {
+        Ok(task_id) => {
+            engine
+                .claim_human_task(&principal(request.principal), &task_id)
+                .await
+        }
+        Err(e) => Err(e),
+    };
+
+    match result {
+        Ok(task) => ClaimHumanTaskResponse {
+            task: Some(human_task_proto(task)),
+            error: None,
+        },
+        Err(e) => {
+            tracing::warn!("Failed to claim human task {}: {}", request.task_id, e);
+            ClaimHumanTaskResponse {
+                task: None,
+                error: Some(e.to_string()),
+            }
+        }
+    }
+}
+
+pub(super) async fn complete_human_task_handler(
+    axum::extract::State(engine): axum::extract::State<Arc<WorkflowEngine>>,
+    request: CompleteHumanTaskRequest,
+) -> CompleteHumanTaskResponse {
+    let form = if request.form.is_empty() {
+        Ok(serde_json::Value::Null)
+    } else {
+        serde_json::from_str(&request.form)
+            .map_err(|e| EngineError::Workflow(crate::error::WorkflowError::InvalidForm(e.to_string())))
+    };
+
+    let result = match (parse_human_task_id(&request.task_id), form) {
+        (Ok(task_id), Ok(form)) => {
+            engine
+                .complete_human_task(&principal(request.principal), &task_id, form)
+                .await
+        }
+        (Err(e), _) | (_, Err(e)) => Err(e),
+    };
+
+    match result {
+        Ok(new_state) => CompleteHumanTaskResponse {
+            new_state,
+            error: None,
+        },
+        Err(e) => {
+            tracing::warn!("Failed to complete human task {}: {}", request.task_id, e);
+            CompleteHumanTaskResponse {
+                new_state: String::new(),
+                error: Some(e.to_string()),
+            }
+        }
+    }
+}
+
+fn instance_summary(instance: crate::types::WorkflowInstance) -> WorkflowInstanceSummary {
+    WorkflowInstanceSummary {
+        id: instance.id.to_string(),
+        definition_id: instance.definition_id.to_string(),
+        current_state: instance.current_state,
+        status: instance.status.as_str().to_string(),
+        created_at_ms: instance.created_at.timestamp_millis(),
+        updated_at_ms: instance.updated_at.timestamp_millis(),
+        completed_at_ms: instance.completed_at.map(|t| t.timestamp_millis()),
+    }
+}
+
+fn human_task_proto(task: crate::types::HumanTask) -> HumanTask {
+    HumanTask {
+        id: task.id.to_string(),
+        workflow_id: task.workflow_id.to_string(),
+        state: task.state,
+        title: task.title,
+        assignee: task.assignee,
+        role: task.role,
+        status: task.status.as_str().to_string(),
+        claimed_by: task.claimed_by,
+        created_at_ms: task.created_at.timestamp_millis(),
+    }
+}
+
+fn parse_human_task_id(id: &str) -> Result<crate::types::HumanTaskId> {
+    uuid::Uuid::parse_str(id)
+        .map(crate::types::HumanTaskId::from_uuid)
+        .map_err(|e| EngineError::Workflow(crate::error::WorkflowError::NotFound(format!("{}: {}", id, e))))
+}
+
+fn parse_workflow_id(id: &str) -> Result<WorkflowId> {
+    uuid::Uuid::parse_str(id)
+        .map(WorkflowId::from_uuid)
+        .map_err(|e| EngineError::Workflow(crate::error::WorkflowError::NotFound(format!("{}: {}", id, e))))
+}
+
+/// Convert the request principal; a missing principal has no id and no roles
+fn principal(principal: Option<Principal>) -> crate::access::Principal {
+    let principal = principal.unwrap_or_default();
+    crate::access::Principal {
+        id: principal.id,
+        roles: principal.roles.into_iter().collect(),
+    }
+}
+
+fn annotation_proto(annotation: crate::types::Annotation) -> Annotation {
+    let content = match annotation.content {
+        crate::types::AnnotationContent::Comment { body } => {
+            annotation::Content::Comment(Comment { body })
+        }
+        crate::types::AnnotationContent::Attachment {
+            name,
+            content_type,
+            size,
+            blob,
+        } => annotation::Content::Attachment(Attachment {
+            name,
+            content_type,
+            size,
+            blob,
+        }),
+    };
+
+    Annotation {
+        id: annotation.id.to_string(),
+        workflow_id: annotation.workflow_id.to_string(),
+        author: annotation.author,
+        created_at_ms: annotation.created_at.timestamp_millis(),
+        content: Some(content),
     }
 }
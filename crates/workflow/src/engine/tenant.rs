@@ -0,0 +1,120 @@
+//! Selection of the tenant an RPC call works on
+//!
+//! The engine does not authenticate callers itself. Whoever fronts it
+//! resolves the caller and names the tenant it acts for, by DID, in the
+//! `x-tenant-did` header. Calls carrying one are served by an engine on that
+//! tenant's key space, opened on first use with
+//! [`WorkflowEngine::for_tenant`], once the engine's
+//! [`TenantAuthorizer`](crate::access::TenantAuthorizer) admitted the caller
+//! to the tenant; calls without one work on the shared key space.
+
+use super::server::connect_error;
+use super::WorkflowEngine;
+use crate::error::Result;
+use axum::extract::{Request, State};
+use axum::http::StatusCode;
+use axum::middleware::Next;
+use axum::response::Response;
+use axum::Router;
+use degov_crypto::DidKey;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::sync::OnceCell;
+use tower::ServiceExt;
+
+/// Header naming the DID of the tenant a call acts for
+pub(super) const TENANT_HEADER: &str = "x-tenant-did";
+
+/// The routers of the tenants opened so far
+pub(super) struct Tenants {
+    engine: Arc<WorkflowEngine>,
+    /// Builds the router serving a tenant's engine
+    routes: fn(Arc<WorkflowEngine>) -> Router,
+    /// Routers by tenant DID, set once the tenant's engine is open
+    routers: Mutex<HashMap<String, Arc<OnceCell<Router>>>>,
+}
+
+impl Tenants {
+    pub(super) fn new(engine: Arc<WorkflowEngine>, routes: fn(Arc<WorkflowEngine>) -> Router) -> Self {
+        Self {
+            engine,
+            routes,
+            routers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Open every tenant that has a key space, up to the engine's limit
+    pub(super) async fn open_all(&self) -> Result<()> {
+        for did in self.engine.persistence().tenants().await? {
+            if self.router(&did).await?.is_none() {
+                tracing::warn!("Not opening tenant {}: {} tenants are open", did, self.engine.max_tenants);
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the router of a tenant, opening its engine and starting its
+    /// timers, event delivery and recovery on first use
+    ///
+    /// Returns `None` when the tenant is not open yet and the engine serves
+    /// as many tenants as it may. Tenants are opened outside the lock, so
+    /// opening one does not hold up calls to the others.
+    async fn router(&self, did: &DidKey) -> Result<Option<Router>> {
+        let cell = {
+            let mut routers = self.routers.lock();
+            let full = routers.len() >= self.engine.max_tenants;
+            match routers.get(&did.to_string()) {
+                Some(cell) => cell.clone(),
+                None if full => return Ok(None),
+                None => routers.entry(did.to_string()).or_default().clone(),
+            }
+        };
+
+        let router = cell
+            .get_or_try_init(|| async {
+                let tenant = Arc::new(self.engine.for_tenant(did).await?);
+                tenant.start_background_tasks();
+                tracing::info!("Opened tenant {}", did);
+                Ok::<_, crate::error::EngineError>((self.routes)(tenant))
+            })
+            .await?;
+        Ok(Some(router.clone()))
+    }
+}
+
+/// Serve calls naming a tenant the caller is admitted to with the tenant's
+/// engine, passing the others through to the shared one
+pub(super) async fn select_tenant(State(tenants): State<Arc<Tenants>>, request: Request, next: Next) -> Response {
+    let Some(value) = request.headers().get(TENANT_HEADER) else {
+        return next.run(request).await;
+    };
+    let Some(did) = value.to_str().ok().and_then(|v| v.parse::<DidKey>().ok()) else {
+        return connect_error(
+            StatusCode::BAD_REQUEST,
+            "invalid_argument",
+            format!("invalid {} header", TENANT_HEADER),
+        );
+    };
+
+    let admitted = match &tenants.engine.tenant_authorizer {
+        Some(authorizer) => authorizer.authorize(&did, request.headers()).await,
+        None => Err("calls naming a tenant are not accepted".to_string()),
+    };
+    if let Err(reason) = admitted {
+        return connect_error(StatusCode::FORBIDDEN, "permission_denied", reason);
+    }
+
+    match tenants.router(&did).await {
+        Ok(Some(router)) => router.oneshot(request).await.unwrap_or_else(|never| match never {}),
+        Ok(None) => connect_error(
+            StatusCode::TOO_MANY_REQUESTS,
+            "resource_exhausted",
+            "too many tenants are open",
+        ),
+        Err(e) => {
+            tracing::error!("Failed to open tenant {}: {}", did, e);
+            connect_error(StatusCode::SERVICE_UNAVAILABLE, "unavailable", format!("tenant {} is unavailable", did))
+        }
+    }
+}
@@ -0,0 +1,281 @@
+//! TLS for the RPC server
+//!
+//! With [`WorkflowEngine::with_tls`](super::WorkflowEngine::with_tls) the
+//! engine serves RPC calls over TLS instead of plaintext HTTP. Setting
+//! client roots turns on mutual TLS: only callers presenting a certificate
+//! issued by one of them, such as the engine's workers, can connect.
+
+use crate::error::{EngineError, Result};
+use axum::serve::Listener;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_rustls::rustls::crypto::ring;
+use tokio_rustls::rustls::pki_types::pem::PemObject;
+use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use tokio_rustls::rustls::server::WebPkiClientVerifier;
+use tokio_rustls::rustls::{RootCertStore, ServerConfig};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+
+/// Longest a client may take to complete the TLS handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Connections whose handshake completed that wait to be served
+const ACCEPT_BACKLOG: usize = 128;
+
+/// TLS settings of the RPC server
+pub struct ServerTls {
+    cert_chain: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+    /// Issuers of the certificates clients must present, if required
+    client_roots: Option<Arc<RootCertStore>>,
+}
+
+impl ServerTls {
+    /// Serve with the PEM encoded certificate chain and private key
+    pub fn from_pem(cert_chain: &[u8], key: &[u8]) -> Result<Self> {
+        let cert_chain = CertificateDer::pem_slice_iter(cert_chain)
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(|e| EngineError::Tls(format!("Invalid certificate chain: {}", e)))?;
+        if cert_chain.is_empty() {
+            return Err(EngineError::Tls("No certificate in certificate chain".to_string()));
+        }
+        let key = PrivateKeyDer::from_pem_slice(key)
+            .map_err(|e| EngineError::Tls(format!("Invalid private key: {}", e)))?;
+
+        Ok(Self {
+            cert_chain,
+            key,
+            client_roots: None,
+        })
+    }
+
+    /// Require clients to present a certificate issued by one of the PEM
+    /// encoded `roots`
+    pub fn with_client_roots(mut self, roots: &[u8]) -> Result<Self> {
+        let mut store = RootCertStore::empty();
+        for cert in CertificateDer::pem_slice_iter(roots) {
+            let cert = cert.map_err(|e| EngineError::Tls(format!("Invalid client root: {}", e)))?;
+            store
+                .add(cert)
+                .map_err(|e| EngineError::Tls(format!("Invalid client root: {}", e)))?;
+        }
+        if store.is_empty() {
+            return Err(EngineError::Tls("No certificate in client roots".to_string()));
+        }
+
+        self.client_roots = Some(Arc::new(store));
+        Ok(self)
+    }
+
+    /// Build the acceptor performing the server side of handshakes
+    ///
+    /// HTTP/2 is offered ahead of HTTP/1.1, as gRPC clients require it.
+    pub(super) fn acceptor(&self) -> Result<TlsAcceptor> {
+        let provider = Arc::new(ring::default_provider());
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(|e| EngineError::Tls(e.to_string()))?;
+        let builder = match &self.client_roots {
+            Some(roots) => {
+                let verifier = WebPkiClientVerifier::builder_with_provider(roots.clone(), provider)
+                    .build()
+                    .map_err(|e| EngineError::Tls(e.to_string()))?;
+                builder.with_client_cert_verifier(verifier)
+            }
+            None => builder.with_no_client_auth(),
+        };
+        let mut config = builder
+            .with_single_cert(self.cert_chain.clone(), self.key.clone_key())
+            .map_err(|e| EngineError::Tls(format!("Invalid certificate or key: {}", e)))?;
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
+
+/// Listener handing out connections once their TLS handshake completed
+///
+/// Handshakes run concurrently, so a client that stalls in one does not
+/// hold up the others; it is dropped after [`HANDSHAKE_TIMEOUT`].
+pub(super) struct TlsListener {
+    local_addr: SocketAddr,
+    connections: mpsc::Receiver<(TlsStream<TcpStream>, SocketAddr)>,
+}
+
+impl TlsListener {
+    pub(super) fn new(listener: TcpListener, acceptor: TlsAcceptor) -> io::Result<Self> {
+        let local_addr = listener.local_addr()?;
+        let (sender, connections) = mpsc::channel(ACCEPT_BACKLOG);
+
+        tokio::spawn(async move {
+            // Runs until the listener is dropped
+            while !sender.is_closed() {
+                let (stream, addr) = match listener.accept().await {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        tracing::warn!("Failed to accept connection: {}", e);
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        continue;
+                    }
+                };
+
+                let acceptor = acceptor.clone();
+                let sender = sender.clone();
+                tokio::spawn(async move {
+                    match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                        Ok(Ok(stream)) => {
+                            let _ = sender.send((stream, addr)).await;
+                        }
+                        Ok(Err(e)) => tracing::debug!("TLS handshake with {} failed: {}", addr, e),
+                        Err(_) => tracing::debug!("TLS handshake with {} timed out", addr),
+                    }
+                });
+            }
+        });
+
+        Ok(Self {
+            local_addr,
+            connections,
+        })
+    }
+}
+
+impl Listener for TlsListener {
+    type Io = TlsStream<TcpStream>;
+    type Addr = SocketAddr;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match self.connections.recv().await {
+            Some(connection) => connection,
+            // The accept loop holds a sender for as long as the listener lives
+            None => std::future::pending().await,
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        Ok(self.local_addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::routing::get;
+    use axum::Router;
+    use rcgen::{BasicConstraints, CertificateParams, CertifiedIssuer, IsCa, KeyPair};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_rustls::rustls::pki_types::ServerName;
+    use tokio_rustls::rustls::ClientConfig;
+    use tokio_rustls::TlsConnector;
+
+    /// A CA and the PEM encoded certificates and keys it issued to the
+    /// server and a client
+    struct Pki {
+        ca: String,
+        server: (String, String),
+        client: (String, String),
+    }
+
+    fn pki() -> Pki {
+        let mut params = CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        let ca = CertifiedIssuer::self_signed(params, KeyPair::generate().unwrap()).unwrap();
+        let issue = |name: &str| {
+            let key = KeyPair::generate().unwrap();
+            let cert = CertificateParams::new(vec![name.to_string()])
+                .unwrap()
+                .signed_by(&key, &*ca)
+                .unwrap();
+            (cert.pem(), key.serialize_pem())
+        };
+
+        Pki {
+            server: issue("localhost"),
+            client: issue("worker"),
+            ca: ca.pem(),
+        }
+    }
+
+    /// Serve a router answering `GET /` over TLS
+    async fn serve(tls: &ServerTls) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let listener = TlsListener::new(listener, tls.acceptor().unwrap()).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let router = Router::new().route("/", get(|| async { "ok" }));
+        tokio::spawn(async move { axum::serve(listener, router).await });
+        addr
+    }
+
+    /// Send `GET /` trusting `pki`'s CA, presenting `client` if given
+    async fn get_root(addr: SocketAddr, pki: &Pki, client: Option<&(String, String)>) -> io::Result<String> {
+        let mut roots = RootCertStore::empty();
+        roots.add(CertificateDer::from_pem_slice(pki.ca.as_bytes()).unwrap()).unwrap();
+        let builder = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots);
+        let config = match client {
+            Some((cert, key)) => builder
+                .with_client_auth_cert(
+                    vec![CertificateDer::from_pem_slice(cert.as_bytes()).unwrap()],
+                    PrivateKeyDer::from_pem_slice(key.as_bytes()).unwrap(),
+                )
+                .unwrap(),
+            None => builder.with_no_client_auth(),
+        };
+
+        let stream = TcpStream::connect(addr).await?;
+        let name = ServerName::try_from("localhost").unwrap();
+        let mut stream = TlsConnector::from(Arc::new(config)).connect(name, stream).await?;
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n")
+            .await?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await?;
+        Ok(response)
+    }
+
+    fn is_ok(response: &io::Result<String>) -> bool {
+        response.as_ref().is_ok_and(|r| r.starts_with("HTTP/1.1 200"))
+    }
+
+    #[tokio::test]
+    async fn test_serves_over_tls() {
+        let pki = pki();
+        let tls = ServerTls::from_pem(pki.server.0.as_bytes(), pki.server.1.as_bytes()).unwrap();
+        let addr = serve(&tls).await;
+
+        assert!(is_ok(&get_root(addr, &pki, None).await));
+    }
+
+    #[tokio::test]
+    async fn test_client_roots_require_client_certificates() {
+        let pki = pki();
+        let tls = ServerTls::from_pem(pki.server.0.as_bytes(), pki.server.1.as_bytes())
+            .unwrap()
+            .with_client_roots(pki.ca.as_bytes())
+            .unwrap();
+        let addr = serve(&tls).await;
+
+        assert!(is_ok(&get_root(addr, &pki, Some(&pki.client)).await));
+        assert!(!is_ok(&get_root(addr, &pki, None).await));
+
+        // A certificate from another CA is refused as well
+        let other = self::pki();
+        assert!(!is_ok(&get_root(addr, &pki, Some(&other.client)).await));
+    }
+
+    #[test]
+    fn test_rejects_invalid_pem() {
+        let pki = pki();
+        assert!(ServerTls::from_pem(b"", pki.server.1.as_bytes()).is_err());
+        assert!(ServerTls::from_pem(pki.server.0.as_bytes(), b"").is_err());
+        let tls = ServerTls::from_pem(pki.server.0.as_bytes(), pki.server.1.as_bytes()).unwrap();
+        assert!(tls.with_client_roots(b"").is_err());
+    }
+}
@@ -0,0 +1,170 @@
+//! WebSocket transport for streaming RPCs
+//!
+//! Some proxies buffer or cut long-lived HTTP responses, so streaming methods
+//! are also served over a WebSocket at `/ws/workflow.WorkflowService/<Method>`.
+//! The client sends the request in a Connect envelope as its first binary
+//! message and receives every response message in an envelope of its own,
+//! followed by the end-of-stream envelope carrying the JSON trailer. Only the
+//! protobuf codec is supported.
+
+use super::authorize;
+use super::server::{stream_tasks_handler, watch_workflow_handler, END_STREAM_FLAG};
+use super::WorkflowEngine;
+use crate::access::{RpcCall, RpcCodec};
+use axum::extract::ws::{Message as WsMessage, WebSocket, WebSocketUpgrade};
+use axum::extract::{Path, State};
+use axum::http::HeaderMap;
+use axum::response::Response;
+use futures::stream::{SplitSink, SplitStream};
+use futures::{SinkExt, Stream, StreamExt};
+use prost::Message;
+use std::sync::Arc;
+
+/// WebSocket subprotocol selecting Connect envelopes with protobuf messages
+const SUBPROTOCOL: &str = "connect+proto";
+
+const ENVELOPE_HEADER_LEN: usize = 5;
+
+/// A Connect error code and message ending a stream
+type StreamError = (&'static str, String);
+
+/// Upgrade a request to a WebSocket serving the streaming `method`
+pub(super) async fn websocket_handler(
+    State(engine): State<Arc<WorkflowEngine>>,
+    Path(method): Path<String>,
+    headers: HeaderMap,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    let max_message_bytes = engine.message_limits().max_message_bytes();
+    upgrade
+        .protocols([SUBPROTOCOL])
+        .max_message_size(max_message_bytes + ENVELOPE_HEADER_LEN)
+        .on_upgrade(move |socket| serve(engine, method, headers, socket))
+}
+
+async fn serve(engine: Arc<WorkflowEngine>, method: String, headers: HeaderMap, socket: WebSocket) {
+    let (mut sender, mut receiver) = socket.split();
+    let request = match receive_request(&mut receiver).await {
+        Ok(message) => {
+            let call = RpcCall {
+                method: &method,
+                headers: &headers,
+                message: &message,
+                codec: RpcCodec::Proto,
+            };
+            match authorize::check(&engine, &call).await {
+                Ok(()) => Ok(message),
+                Err(reason) => Err(("permission_denied", reason)),
+            }
+        }
+        Err(e) => Err(e),
+    };
+    let result = match request {
+        Ok(message) => match method.as_str() {
+            "WatchWorkflow" => {
+                relay(&mut sender, &mut receiver, &message, |req| watch_workflow_handler(State(engine), req)).await
+            }
+            "StreamTasks" => {
+                relay(&mut sender, &mut receiver, &message, |req| stream_tasks_handler(State(engine), req)).await
+            }
+            _ => Err(("unimplemented", format!("Unknown streaming method: {}", method))),
+        },
+        Err(e) => Err(e),
+    };
+
+    let trailer = match result {
+        Ok(()) => serde_json::json!({}),
+        Err((code, message)) => serde_json::json!({ "error": { "code": code, "message": message } }),
+    };
+    let _ = sender
+        .send(WsMessage::Binary(envelope(END_STREAM_FLAG, trailer.to_string().as_bytes()).into()))
+        .await;
+    let _ = sender.send(WsMessage::Close(None)).await;
+}
+
+/// Wait for the enveloped request message
+async fn receive_request(receiver: &mut SplitStream<WebSocket>) -> Result<Vec<u8>, StreamError> {
+    loop {
+        match receiver.next().await {
+            Some(Ok(WsMessage::Binary(data))) => return open_envelope(&data).map(<[u8]>::to_vec),
+            Some(Ok(WsMessage::Ping(_) | WsMessage::Pong(_))) => continue,
+            Some(Ok(_)) => return Err(("invalid_argument", "expected a binary request message".to_string())),
+            Some(Err(e)) => return Err(("unavailable", e.to_string())),
+            None => return Err(("canceled", "socket closed before the request".to_string())),
+        }
+    }
+}
+
+/// Decode a request, run a streaming handler and send its responses until
+/// the stream ends or the client goes away
+async fn relay<Req, Res, F, S>(
+    sender: &mut SplitSink<WebSocket, WsMessage>,
+    receiver: &mut SplitStream<WebSocket>,
+    message: &[u8],
+    handler: F,
+) -> Result<(), StreamError>
+where
+    Req: Message + Default,
+    Res: Message,
+    F: FnOnce(Req) -> S,
+    S: Stream<Item = Res>,
+{
+    let request = Req::decode(message).map_err(|e| ("invalid_argument", format!("Invalid request: {}", e)))?;
+    let responses = handler(request);
+    futures::pin_mut!(responses);
+
+    loop {
+        tokio::select! {
+            response = responses.next() => {
+                let Some(response) = response else {
+                    return Ok(());
+                };
+                let data = envelope(0, &response.encode_to_vec());
+                if sender.send(WsMessage::Binary(data.into())).await.is_err() {
+                    return Ok(());
+                }
+            }
+            incoming = receiver.next() => match incoming {
+                Some(Ok(WsMessage::Close(_))) | Some(Err(_)) | None => return Ok(()),
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+/// Extract the message of an uncompressed envelope
+fn open_envelope(data: &[u8]) -> Result<&[u8], StreamError> {
+    if data.len() < ENVELOPE_HEADER_LEN {
+        return Err(("invalid_argument", "truncated envelope".to_string()));
+    }
+    if data[0] != 0 {
+        return Err(("unimplemented", "compressed or flagged request envelopes are not supported".to_string()));
+    }
+    let len = u32::from_be_bytes([data[1], data[2], data[3], data[4]]) as usize;
+    let message = &data[ENVELOPE_HEADER_LEN..];
+    if message.len() != len {
+        return Err(("invalid_argument", "envelope length does not match its message".to_string()));
+    }
+    Ok(message)
+}
+
+fn envelope(flags: u8, message: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(ENVELOPE_HEADER_LEN + message.len());
+    data.push(flags);
+    data.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    data.extend_from_slice(message);
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_envelope_roundtrip() {
+        let data = envelope(0, b"request");
+        assert_eq!(open_envelope(&data).unwrap(), b"request");
+        assert_eq!(open_envelope(&envelope(END_STREAM_FLAG, b"{}")).unwrap_err().0, "unimplemented");
+        assert_eq!(open_envelope(&data[..6]).unwrap_err().0, "invalid_argument");
+    }
+}
@@ -17,12 +17,24 @@ pub enum EngineError {
     #[error("RPC error: {0}")]
     Rpc(#[from] RpcError),
     
+    #[error("Export error: {0}")]
+    Export(#[from] ExportError),
+    
+    #[error("Search error: {0}")]
+    Search(#[from] SearchError),
+    
     #[error("Scheduler error: {0}")]
     Scheduler(String),
     
     #[error("Worker not found: {0}")]
     WorkerNotFound(String),
     
+    #[error("Permission denied: {0}")]
+    PermissionDenied(String),
+    
+    #[error("TLS error: {0}")]
+    Tls(String),
+    
     #[error("Internal error: {0}")]
     Internal(String),
 }
@@ -47,6 +59,33 @@ pub enum WorkflowError {
     
     #[error("Task execution failed: {0}")]
     TaskFailed(String),
+    
+    #[error("Workflow {0} is waiting for child workflows to complete")]
+    AwaitingChildren(String),
+    
+    #[error("Invalid annotation: {0}")]
+    InvalidAnnotation(String),
+    
+    #[error("Invalid instance filter: {0}")]
+    InvalidFilter(String),
+    
+    #[error("Invalid human task form: {0}")]
+    InvalidForm(String),
+    
+    #[error("Untrusted code for task '{task}': {reason}")]
+    UntrustedCode { task: String, reason: String },
+    
+    #[error("Invalid event topic: '{0}'")]
+    InvalidTopic(String),
+    
+    #[error("Invalid workflow input: {0}")]
+    InvalidInput(String),
+    
+    #[error("Invalid idempotency key: {0}")]
+    InvalidIdempotencyKey(String),
+    
+    #[error("Unsupported snapshot version {found}, expected {expected}")]
+    UnsupportedSnapshot { found: u32, expected: u32 },
 }
 
 /// Persistence layer errors
@@ -69,6 +108,34 @@ pub enum PersistenceError {
     
     #[error("Transaction conflict")]
     Conflict,
+    
+    #[error("Stale write rejected: {0}")]
+    Fenced(String),
+
+    #[error("Directory layer error: {0}")]
+    Directory(String),
+}
+
+/// FoundationDB error code of a transaction that conflicted with another
+const NOT_COMMITTED: i32 = 1020;
+
+impl PersistenceError {
+    /// Whether the transaction failed because it conflicted with another one
+    pub fn is_conflict(&self) -> bool {
+        match self {
+            PersistenceError::Conflict => true,
+            PersistenceError::Fdb(e) => e.code() == NOT_COMMITTED,
+            PersistenceError::FdbCommit(e) => e.code() == NOT_COMMITTED,
+            _ => false,
+        }
+    }
+}
+
+impl EngineError {
+    /// Whether the error is a storage transaction conflict
+    pub fn is_conflict(&self) -> bool {
+        matches!(self, EngineError::Persistence(e) if e.is_conflict())
+    }
 }
 
 /// Runtime execution errors
@@ -82,6 +149,27 @@ pub enum RuntimeError {
     
     #[error("Timeout exceeded: {0}ms")]
     Timeout(u64),
+
+    #[error("Fuel exhausted: {0} units")]
+    FuelExhausted(u64),
+
+    #[error("Memory limit exceeded: {0} bytes")]
+    MemoryLimitExceeded(u64),
+
+    #[error("Interrupted after {0}ms")]
+    Interrupted(u64),
+
+    #[error("Key-value error: {0}")]
+    Kv(String),
+
+    #[error("Key-value budget exceeded: {0}")]
+    KvBudgetExceeded(String),
+
+    #[error("HTTP error: {0}")]
+    Http(String),
+
+    #[error("HTTP request not allowed: {0}")]
+    HttpNotAllowed(String),
     
     #[error("Invalid code: {0}")]
     InvalidCode(String),
@@ -89,6 +177,9 @@ pub enum RuntimeError {
     #[error("Runtime not available: {0}")]
     RuntimeNotAvailable(String),
     
+    #[error("Plugin error: {0}")]
+    Plugin(String),
+    
     #[error("Execution error: {0}")]
     Execution(String),
 }
@@ -110,6 +201,65 @@ pub enum RpcError {
     
     #[error("Protocol error: {0}")]
     Protocol(String),
+    
+    #[error("Resource exhausted: {0}")]
+    ResourceExhausted(String),
+
+    #[error("Permission denied: {0}")]
+    PermissionDenied(String),
+
+    #[error("{source} (after {attempts} attempts)")]
+    Retried {
+        attempts: u32,
+        source: Box<RpcError>,
+    },
+}
+
+/// Instance export errors
+#[derive(Error, Debug)]
+pub enum ExportError {
+    #[error("Unsupported export format: {0}")]
+    UnsupportedFormat(String),
+    
+    #[error("Invalid column: {0}")]
+    InvalidColumn(String),
+    
+    #[error("Invalid filter: {0}")]
+    InvalidFilter(String),
+    
+    #[error("Export has {0} rows, more than fit in one worksheet")]
+    TooManyRows(usize),
+    
+    #[error("CSV error: {0}")]
+    Csv(#[from] csv::Error),
+    
+    #[error("XLSX error: {0}")]
+    Xlsx(#[from] rust_xlsxwriter::XlsxError),
+    
+    #[error("Persistence error: {0}")]
+    Persistence(#[from] PersistenceError),
+}
+
+/// Search index errors
+#[derive(Error, Debug)]
+pub enum SearchError {
+    #[error("Search is not configured")]
+    NotConfigured,
+    
+    #[error("Invalid query: {0}")]
+    InvalidQuery(String),
+    
+    #[error("Search backend error: {0}")]
+    Backend(String),
+    
+    #[error("HTTP error: {0}")]
+    Http(#[from] reqwest::Error),
+    
+    #[error("Serialization error: {0}")]
+    Serialization(#[from] serde_json::Error),
+    
+    #[error("Persistence error: {0}")]
+    Persistence(#[from] PersistenceError),
 }
 
 /// Convenience result types
@@ -118,4 +268,6 @@ pub type WorkflowResult<T> = std::result::Result<T, WorkflowError>;
 pub type PersistenceResult<T> = std::result::Result<T, PersistenceError>;
 pub type RuntimeResult<T> = std::result::Result<T, RuntimeError>;
 pub type RpcResult<T> = std::result::Result<T, RpcError>;
+pub type ExportResult<T> = std::result::Result<T, ExportError>;
+pub type SearchResult<T> = std::result::Result<T, SearchError>;
 
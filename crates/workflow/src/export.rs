@@ -0,0 +1,471 @@
+//! Spreadsheet export of workflow instance lists
+//!
+//! Instances are flattened into rows: a fixed set of instance columns
+//! followed by selected context fields. Context columns are addressed by
+//! dotted paths (`applicant.address.city`) and typed either from a
+//! `DataModel` or from explicit type hints, so numbers and booleans end up as
+//! typed cells in XLSX instead of text.
+
+use crate::error::{ExportError, ExportResult};
+use crate::types::WorkflowInstance;
+use dgv_core::v1::data_model::{DataModel, DataModelField};
+use rust_xlsxwriter::{Format, Workbook};
+use serde_json::Value;
+
+/// Largest number of data rows that fit in one XLSX worksheet
+pub const XLSX_MAX_ROWS: usize = 1_048_575;
+
+/// Columns every export starts with
+const INSTANCE_COLUMNS: &[&str] = &[
+    "id",
+    "definition_id",
+    "current_state",
+    "status",
+    "created_at",
+    "updated_at",
+    "completed_at",
+];
+
+/// Output format of an export
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv,
+    Xlsx,
+}
+
+impl ExportFormat {
+    /// Parse a format name (`csv` or `xlsx`)
+    pub fn parse(name: &str) -> ExportResult<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "csv" => Ok(Self::Csv),
+            "xlsx" | "excel" => Ok(Self::Xlsx),
+            other => Err(ExportError::UnsupportedFormat(other.to_string())),
+        }
+    }
+
+    /// MIME type of the exported file
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Csv => "text/csv; charset=utf-8",
+            Self::Xlsx => "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        }
+    }
+
+    /// File extension of the exported file
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Csv => "csv",
+            Self::Xlsx => "xlsx",
+        }
+    }
+}
+
+/// How a context value is turned into a cell
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnKind {
+    /// Follow the JSON type of the value
+    Auto,
+    Text,
+    Integer,
+    Float,
+    Boolean,
+    /// Nested values serialized as JSON text
+    Json,
+}
+
+impl ColumnKind {
+    /// Parse a type hint as used in export field selections
+    pub fn parse(name: &str) -> ExportResult<Self> {
+        match name {
+            "auto" => Ok(Self::Auto),
+            "string" | "text" => Ok(Self::Text),
+            "integer" | "int" => Ok(Self::Integer),
+            "float" | "number" => Ok(Self::Float),
+            "boolean" | "bool" => Ok(Self::Boolean),
+            "json" => Ok(Self::Json),
+            other => Err(ExportError::InvalidColumn(format!("unknown column type '{}'", other))),
+        }
+    }
+}
+
+/// A single cell of an exported row
+#[derive(Debug, Clone, PartialEq)]
+pub enum Cell {
+    Empty,
+    Text(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+}
+
+impl Cell {
+    /// Render the cell for CSV output
+    ///
+    /// Text starting with a formula character is prefixed with `'` so
+    /// spreadsheet applications do not evaluate user supplied context data.
+    fn to_csv_field(&self) -> String {
+        match self {
+            Cell::Empty => String::new(),
+            Cell::Text(text) if text.starts_with(['=', '+', '-', '@', '\t', '\r']) => {
+                format!("'{}", text)
+            }
+            Cell::Text(text) => text.clone(),
+            Cell::Integer(n) => n.to_string(),
+            Cell::Float(n) => n.to_string(),
+            Cell::Boolean(b) => b.to_string(),
+        }
+    }
+}
+
+/// A context field exported as a column
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExportColumn {
+    pub header: String,
+    pub path: Vec<String>,
+    pub kind: ColumnKind,
+}
+
+impl ExportColumn {
+    /// Create a column for a dotted context path
+    pub fn new(path: &str, kind: ColumnKind) -> ExportResult<Self> {
+        let segments: Vec<String> = path.split('.').map(str::to_string).collect();
+        if segments.iter().any(String::is_empty) {
+            return Err(ExportError::InvalidColumn(format!("invalid field path '{}'", path)));
+        }
+
+        Ok(Self {
+            header: path.to_string(),
+            path: segments,
+            kind,
+        })
+    }
+
+    /// Parse a field selection of the form `path` or `path:type`
+    pub fn parse(selection: &str) -> ExportResult<Self> {
+        match selection.split_once(':') {
+            Some((path, kind)) => Self::new(path, ColumnKind::parse(kind)?),
+            None => Self::new(selection, ColumnKind::Auto),
+        }
+    }
+
+    /// Extract this column's cell from a context
+    fn cell(&self, context: &Value) -> Cell {
+        let value = self
+            .path
+            .iter()
+            .try_fold(context, |value, segment| value.get(segment.as_str()));
+        match value {
+            None | Some(Value::Null) => Cell::Empty,
+            Some(value) => convert(value, self.kind),
+        }
+    }
+}
+
+/// Maps workflow instances to export rows
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ColumnMapping {
+    columns: Vec<ExportColumn>,
+}
+
+impl ColumnMapping {
+    /// Create a mapping with only the instance columns
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a context column
+    pub fn with_column(mut self, column: ExportColumn) -> Self {
+        self.columns.push(column);
+        self
+    }
+
+    /// Build a mapping from field selections (`path` or `path:type`)
+    pub fn from_selections<'a>(selections: impl IntoIterator<Item = &'a str>) -> ExportResult<Self> {
+        let columns = selections
+            .into_iter()
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(ExportColumn::parse)
+            .collect::<ExportResult<_>>()?;
+        Ok(Self { columns })
+    }
+
+    /// Build a mapping with one column per scalar field of a data model
+    ///
+    /// Nested objects are flattened into dotted paths, arrays are exported
+    /// as JSON text.
+    pub fn from_data_model(model: &DataModel<'_>) -> Self {
+        let mut columns = Vec::new();
+        for field in &model.fields {
+            flatten_field(field, &[], &mut columns);
+        }
+        Self { columns }
+    }
+
+    /// Get the context columns
+    pub fn columns(&self) -> &[ExportColumn] {
+        &self.columns
+    }
+
+    /// Get the header row
+    pub fn headers(&self) -> Vec<String> {
+        INSTANCE_COLUMNS
+            .iter()
+            .map(|c| c.to_string())
+            .chain(self.columns.iter().map(|c| c.header.clone()))
+            .collect()
+    }
+
+    /// Flatten an instance into a row matching `headers`
+    pub fn row(&self, instance: &WorkflowInstance) -> Vec<Cell> {
+        let mut row = vec![
+            Cell::Text(instance.id.to_string()),
+            Cell::Text(instance.definition_id.to_string()),
+            Cell::Text(instance.current_state.clone()),
+            Cell::Text(format!("{:?}", instance.status)),
+            Cell::Text(instance.created_at.to_rfc3339()),
+            Cell::Text(instance.updated_at.to_rfc3339()),
+            instance.completed_at.map_or(Cell::Empty, |t| Cell::Text(t.to_rfc3339())),
+        ];
+        row.extend(self.columns.iter().map(|c| c.cell(&instance.context)));
+        row
+    }
+}
+
+fn flatten_field(field: &DataModelField<'_>, prefix: &[String], columns: &mut Vec<ExportColumn>) {
+    let (name, kind) = match field {
+        DataModelField::Object { name, fields, .. } => {
+            let mut path = prefix.to_vec();
+            if let Some(name) = name {
+                path.push(name.to_string());
+            }
+            for field in fields {
+                flatten_field(field, &path, columns);
+            }
+            return;
+        }
+        DataModelField::Array { name, .. } => (name, ColumnKind::Json),
+        DataModelField::String { name, .. } => (name, ColumnKind::Text),
+        DataModelField::Integer { name, .. } => (name, ColumnKind::Integer),
+        DataModelField::Float { name, .. } => (name, ColumnKind::Float),
+        DataModelField::Boolean { name, .. } => (name, ColumnKind::Boolean),
+    };
+
+    // Scalar fields without a name cannot be addressed in the context
+    let Some(name) = name else { return };
+    let mut path = prefix.to_vec();
+    path.push(name.to_string());
+    columns.push(ExportColumn {
+        header: path.join("."),
+        path,
+        kind,
+    });
+}
+
+/// Convert a JSON value into a cell of the given kind, falling back to text
+/// when the value does not have the declared type
+fn convert(value: &Value, kind: ColumnKind) -> Cell {
+    let as_text = || match value {
+        Value::String(s) => Cell::Text(s.clone()),
+        other => Cell::Text(other.to_string()),
+    };
+
+    match kind {
+        ColumnKind::Auto => match value {
+            Value::Bool(b) => Cell::Boolean(*b),
+            Value::Number(n) => n
+                .as_i64()
+                .map(Cell::Integer)
+                .or_else(|| n.as_f64().map(Cell::Float))
+                .unwrap_or_else(as_text),
+            _ => as_text(),
+        },
+        ColumnKind::Text | ColumnKind::Json => as_text(),
+        ColumnKind::Integer => value
+            .as_i64()
+            .or_else(|| value.as_str().and_then(|s| s.trim().parse().ok()))
+            .map(Cell::Integer)
+            .unwrap_or_else(as_text),
+        ColumnKind::Float => value
+            .as_f64()
+            .or_else(|| value.as_str().and_then(|s| s.trim().parse().ok()))
+            .map(Cell::Float)
+            .unwrap_or_else(as_text),
+        ColumnKind::Boolean => value
+            .as_bool()
+            .or_else(|| value.as_str().and_then(|s| s.trim().parse().ok()))
+            .map(Cell::Boolean)
+            .unwrap_or_else(as_text),
+    }
+}
+
+/// Encode CSV records incrementally so large exports can be streamed
+pub struct CsvEncoder {
+    writer: csv::Writer<Vec<u8>>,
+}
+
+impl CsvEncoder {
+    pub fn new() -> Self {
+        Self {
+            writer: csv::Writer::from_writer(Vec::new()),
+        }
+    }
+
+    /// Append the header row
+    pub fn write_headers(&mut self, headers: &[String]) -> ExportResult<()> {
+        self.writer.write_record(headers)?;
+        Ok(())
+    }
+
+    /// Append a data row
+    pub fn write_row(&mut self, row: &[Cell]) -> ExportResult<()> {
+        self.writer.write_record(row.iter().map(Cell::to_csv_field))?;
+        Ok(())
+    }
+
+    /// Take the bytes encoded since the last call
+    pub fn take(&mut self) -> ExportResult<Vec<u8>> {
+        let writer = std::mem::replace(&mut self.writer, csv::Writer::from_writer(Vec::new()));
+        writer
+            .into_inner()
+            .map_err(|e| ExportError::Csv(csv::Error::from(e.into_error())))
+    }
+}
+
+impl Default for CsvEncoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Build an XLSX workbook with a single worksheet
+pub fn write_xlsx(headers: &[String], rows: &[Vec<Cell>]) -> ExportResult<Vec<u8>> {
+    if rows.len() > XLSX_MAX_ROWS {
+        return Err(ExportError::TooManyRows(rows.len()));
+    }
+
+    let mut workbook = Workbook::new();
+    let worksheet = workbook.add_worksheet();
+    worksheet.set_name("Instances")?;
+
+    let bold = Format::new().set_bold();
+    for (col, header) in headers.iter().enumerate() {
+        worksheet.write_string_with_format(0, col as u16, header, &bold)?;
+    }
+
+    for (index, row) in rows.iter().enumerate() {
+        let row_num = index as u32 + 1;
+        for (col, cell) in row.iter().enumerate() {
+            let col = col as u16;
+            match cell {
+                Cell::Empty => {}
+                Cell::Text(text) => {
+                    worksheet.write_string(row_num, col, text)?;
+                }
+                Cell::Integer(n) => {
+                    worksheet.write_number(row_num, col, *n as f64)?;
+                }
+                Cell::Float(n) => {
+                    worksheet.write_number(row_num, col, *n)?;
+                }
+                Cell::Boolean(b) => {
+                    worksheet.write_boolean(row_num, col, *b)?;
+                }
+            }
+        }
+    }
+
+    Ok(workbook.save_to_buffer()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::borrow::Cow;
+
+    #[test]
+    fn test_parse_selections() {
+        let mapping = ColumnMapping::from_selections("applicant.name, amount:float".split(',')).unwrap();
+        let columns = mapping.columns();
+        assert_eq!(columns[0].path, ["applicant", "name"]);
+        assert_eq!(columns[0].kind, ColumnKind::Auto);
+        assert_eq!(columns[1].kind, ColumnKind::Float);
+
+        assert!(ColumnMapping::from_selections(["a..b"]).is_err());
+        assert!(ColumnMapping::from_selections(["a:date"]).is_err());
+    }
+
+    #[test]
+    fn test_column_cells() {
+        let context = json!({
+            "applicant": { "name": "Erika", "age": "42" },
+            "approved": true,
+            "fee": 12.5,
+        });
+
+        let cell = |selection: &str| ExportColumn::parse(selection).unwrap().cell(&context);
+        assert_eq!(cell("applicant.name"), Cell::Text("Erika".into()));
+        assert_eq!(cell("applicant.age:integer"), Cell::Integer(42));
+        assert_eq!(cell("applicant.age"), Cell::Text("42".into()));
+        assert_eq!(cell("approved"), Cell::Boolean(true));
+        assert_eq!(cell("fee"), Cell::Float(12.5));
+        assert_eq!(cell("applicant"), Cell::Text(r#"{"age":"42","name":"Erika"}"#.into()));
+        assert_eq!(cell("missing.field"), Cell::Empty);
+    }
+
+    #[test]
+    fn test_data_model_mapping() {
+        let model = DataModel {
+            name: Some(Cow::Borrowed("application")),
+            fields: vec![
+                DataModelField::Object {
+                    name: Some(Cow::Borrowed("applicant")),
+                    description: None,
+                    fields: vec![DataModelField::Integer {
+                        name: Some(Cow::Borrowed("age")),
+                        description: None,
+                    }],
+                },
+                DataModelField::Array {
+                    name: Some(Cow::Borrowed("documents")),
+                    description: None,
+                    items: Box::new(DataModelField::String { name: None, description: None }),
+                },
+            ],
+        };
+
+        let mapping = ColumnMapping::from_data_model(&model);
+        let headers: Vec<_> = mapping.columns().iter().map(|c| c.header.as_str()).collect();
+        assert_eq!(headers, ["applicant.age", "documents"]);
+        assert_eq!(mapping.columns()[0].kind, ColumnKind::Integer);
+        assert_eq!(mapping.columns()[1].kind, ColumnKind::Json);
+    }
+
+    #[test]
+    fn test_csv_escapes_formulas() {
+        let mut encoder = CsvEncoder::new();
+        encoder.write_headers(&["a".into(), "b".into(), "c".into()]).unwrap();
+        encoder
+            .write_row(&[Cell::Text("=1+1".into()), Cell::Integer(-3), Cell::Text("x,y".into())])
+            .unwrap();
+        let csv = String::from_utf8(encoder.take().unwrap()).unwrap();
+        assert_eq!(csv, "a,b,c\n'=1+1,-3,\"x,y\"\n");
+        assert!(encoder.take().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_xlsx_workbook() {
+        let headers = vec!["name".to_string(), "fee".to_string()];
+        let rows = vec![vec![Cell::Text("Erika".into()), Cell::Float(12.5)]];
+        let workbook = write_xlsx(&headers, &rows).unwrap();
+        assert!(workbook.starts_with(b"PK"));
+    }
+
+    #[test]
+    fn test_format_parse() {
+        assert_eq!(ExportFormat::parse("CSV").unwrap(), ExportFormat::Csv);
+        assert_eq!(ExportFormat::parse("xlsx").unwrap(), ExportFormat::Xlsx);
+        assert!(ExportFormat::parse("ods").is_err());
+    }
+}
@@ -0,0 +1,236 @@
+//! Replay of responses to retried requests
+//!
+//! Clients retrying a unary call send the same `Idempotency-Key` header with
+//! every attempt. Once the engine is configured with an [`IdempotencyStore`],
+//! the first response to a key is stored and replayed to later attempts
+//! until it expires, so a retry after a lost response does not repeat the
+//! call. While the first call runs its key is reserved, so a retry arriving
+//! in the meantime waits for its response instead of running the call a
+//! second time. `PersistenceLayer::idempotency_keys` provides a FoundationDB
+//! backed store; [`MemoryIdempotencyStore`] keeps responses in memory.
+
+use crate::error::Result;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// A response stored under an idempotency key
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoredResponse {
+    /// SHA-256 of the request, to detect a key reused for another request
+    pub request_digest: String,
+    pub status: u16,
+    pub content_type: Option<String>,
+    pub body: Vec<u8>,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// A call holding an idempotency key while it runs
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PendingCall {
+    /// SHA-256 of the request, to detect a key reused for another request
+    pub request_digest: String,
+    /// When the reservation lapses if the call never finishes, e.g. because
+    /// the engine stopped
+    pub expires_at: DateTime<Utc>,
+}
+
+/// State of an idempotency key a call tried to reserve
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reservation {
+    /// The key was free and is now held by the call
+    Reserved,
+    /// Another call holds the key and has not finished yet
+    Pending(PendingCall),
+    /// A response is stored under the key
+    Stored(StoredResponse),
+}
+
+/// Value kept under an idempotency key
+///
+/// Responses stored before reservations existed read as [`Entry::Stored`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub(crate) enum Entry {
+    Stored(StoredResponse),
+    Pending(PendingCall),
+}
+
+impl Entry {
+    pub(crate) fn expires_at(&self) -> DateTime<Utc> {
+        match self {
+            Self::Stored(response) => response.expires_at,
+            Self::Pending(call) => call.expires_at,
+        }
+    }
+
+    /// What a call trying to reserve the key finds, `None` if the entry
+    /// expired and the key is free
+    pub(crate) fn reservation(self, now: DateTime<Utc>) -> Option<Reservation> {
+        if self.expires_at() <= now {
+            return None;
+        }
+        Some(match self {
+            Self::Stored(response) => Reservation::Stored(response),
+            Self::Pending(call) => Reservation::Pending(call),
+        })
+    }
+}
+
+/// Backend storing responses by idempotency key
+#[async_trait]
+pub trait IdempotencyStore: Send + Sync {
+    /// Get the response stored under a key, unless it expired
+    async fn get(&self, key: &str) -> Result<Option<StoredResponse>>;
+
+    /// Reserve a key for a call about to run, unless a response is stored
+    /// under it or another call holds it
+    ///
+    /// Checking and reserving the key is atomic, so of calls racing for a
+    /// key only one is told [`Reservation::Reserved`].
+    async fn reserve(&self, key: &str, call: PendingCall) -> Result<Reservation>;
+
+    /// Store a response under a key, replacing an earlier one or the
+    /// reservation
+    async fn put(&self, key: &str, response: StoredResponse) -> Result<()>;
+
+    /// Drop the reservation of a key without storing a response, so the
+    /// call can be retried
+    async fn release(&self, key: &str) -> Result<()>;
+
+    /// Remove up to `limit` responses that expired at or before `now`,
+    /// returning how many were removed
+    async fn purge_expired(&self, now: DateTime<Utc>, limit: usize) -> Result<usize>;
+}
+
+/// Store keeping responses in memory, for single engine deployments and
+/// tests
+#[derive(Default)]
+pub struct MemoryIdempotencyStore {
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+impl MemoryIdempotencyStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl IdempotencyStore for MemoryIdempotencyStore {
+    async fn get(&self, key: &str) -> Result<Option<StoredResponse>> {
+        match self.entries.lock().get(key) {
+            Some(Entry::Stored(response)) if response.expires_at > Utc::now() => Ok(Some(response.clone())),
+            _ => Ok(None),
+        }
+    }
+
+    async fn reserve(&self, key: &str, call: PendingCall) -> Result<Reservation> {
+        let mut entries = self.entries.lock();
+        if let Some(found) = entries.get(key).cloned().and_then(|entry| entry.reservation(Utc::now())) {
+            return Ok(found);
+        }
+        entries.insert(key.to_string(), Entry::Pending(call));
+        Ok(Reservation::Reserved)
+    }
+
+    async fn put(&self, key: &str, response: StoredResponse) -> Result<()> {
+        self.entries.lock().insert(key.to_string(), Entry::Stored(response));
+        Ok(())
+    }
+
+    async fn release(&self, key: &str) -> Result<()> {
+        let mut entries = self.entries.lock();
+        if matches!(entries.get(key), Some(Entry::Pending(_))) {
+            entries.remove(key);
+        }
+        Ok(())
+    }
+
+    async fn purge_expired(&self, now: DateTime<Utc>, limit: usize) -> Result<usize> {
+        let mut entries = self.entries.lock();
+        let expired: Vec<String> = entries
+            .iter()
+            .filter(|(_, entry)| entry.expires_at() <= now)
+            .map(|(key, _)| key.clone())
+            .take(limit)
+            .collect();
+        for key in &expired {
+            entries.remove(key);
+        }
+        Ok(expired.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(expires_at: DateTime<Utc>) -> StoredResponse {
+        StoredResponse {
+            request_digest: "digest".to_string(),
+            status: 200,
+            content_type: Some("application/proto".to_string()),
+            body: b"response".to_vec(),
+            expires_at,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_memory_store_expiry() {
+        let store = MemoryIdempotencyStore::new();
+        let now = Utc::now();
+        store.put("live", response(now + chrono::Duration::minutes(5))).await.unwrap();
+        store.put("expired", response(now - chrono::Duration::minutes(5))).await.unwrap();
+
+        assert!(store.get("live").await.unwrap().is_some());
+        assert!(store.get("expired").await.unwrap().is_none());
+        assert_eq!(store.purge_expired(now, 10).await.unwrap(), 1);
+        assert_eq!(store.purge_expired(now, 10).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_memory_store_reservation() {
+        let store = MemoryIdempotencyStore::new();
+        let now = Utc::now();
+        let call = |expires_at| PendingCall {
+            request_digest: "digest".to_string(),
+            expires_at,
+        };
+
+        let lease = now + chrono::Duration::minutes(1);
+        assert_eq!(store.reserve("key", call(lease)).await.unwrap(), Reservation::Reserved);
+        assert_eq!(store.reserve("key", call(lease)).await.unwrap(), Reservation::Pending(call(lease)));
+        assert!(store.get("key").await.unwrap().is_none());
+
+        let stored = response(now + chrono::Duration::minutes(5));
+        store.put("key", stored.clone()).await.unwrap();
+        assert_eq!(store.reserve("key", call(lease)).await.unwrap(), Reservation::Stored(stored.clone()));
+
+        // Releasing only drops reservations
+        store.release("key").await.unwrap();
+        assert_eq!(store.get("key").await.unwrap(), Some(stored));
+
+        // A lapsed reservation frees the key
+        store.reserve("other", call(now - chrono::Duration::minutes(1))).await.unwrap();
+        assert_eq!(store.reserve("other", call(lease)).await.unwrap(), Reservation::Reserved);
+        store.release("other").await.unwrap();
+        assert_eq!(store.reserve("other", call(lease)).await.unwrap(), Reservation::Reserved);
+    }
+
+    #[test]
+    fn test_entry_reads_stored_responses() {
+        let stored = response(Utc::now());
+        let entry: Entry = serde_json::from_slice(&serde_json::to_vec(&stored).unwrap()).unwrap();
+        assert!(matches!(entry, Entry::Stored(response) if response == stored));
+
+        let call = PendingCall {
+            request_digest: "digest".to_string(),
+            expires_at: Utc::now(),
+        };
+        let entry: Entry = serde_json::from_slice(&serde_json::to_vec(&call).unwrap()).unwrap();
+        assert!(matches!(entry, Entry::Pending(pending) if pending == call));
+    }
+}
@@ -44,28 +44,54 @@
 //! ```
 
 // Core modules
+pub mod access;
+pub mod dgl;
 pub mod engine;
 pub mod error;
+pub mod export;
+pub mod idempotency;
+pub mod paging;
 pub mod persistence;
 pub mod runtime;
+pub mod search;
+pub mod snapshot;
 pub mod state_machine;
+pub mod telemetry;
+pub mod transfer;
 pub mod types;
 pub mod worker;
 
 // Re-exports for public API
-pub use engine::{TaskScheduler, WorkflowEngine, WorkflowRegistry};
+pub use access::{
+    AccessPolicy, AllowAll, InstanceAction, Principal, RolePolicy, RpcAuthorizer, RpcCall, RpcCodec, TenantAllowlist,
+    TenantAuthorizer,
+};
+pub use engine::{RecoveryReport, ServerTls, TaskScheduler, WorkflowEngine, WorkflowRegistry, CHILD_COMPLETED_EVENT};
+#[cfg(feature = "graphql")]
+pub use engine::{FieldPolicy, FieldRolePolicy, GraphQlGateway};
 pub use error::{
-    EngineError, PersistenceError, Result, RpcError, RuntimeError, WorkflowError, WorkflowResult,
+    EngineError, ExportError, PersistenceError, Result, RpcError, RuntimeError, SearchError,
+    WorkflowError, WorkflowResult,
 };
+pub use export::{ColumnMapping, ExportFormat};
+pub use idempotency::{IdempotencyStore, MemoryIdempotencyStore, PendingCall, Reservation, StoredResponse};
+pub use paging::PageSize;
 pub use persistence::PersistenceLayer;
-pub use runtime::{JavaScriptRuntime, Runtime, WasmRuntime};
-pub use state_machine::{Action, Context, Guard, State, StateMachine, Transition};
+pub use runtime::{ExecRuntime, JavaScriptRuntime, KvReader, PluginManifest, Runtime, TaskKv, WasmRuntime};
+pub use search::{MemoryIndex, OpenSearchIndex, SearchIndex, SearchQuery, SearchSchema};
+pub use snapshot::{RestoreOptions, RestoreReport, Snapshot};
+pub use state_machine::{
+    Action, Branch, Completion, Context, Expression, ExpressionError, Fork, Guard, HttpRequest, HumanTaskSpec,
+    State, StateChange, StateMachine, Timer, Transition,
+};
+pub use transfer::MessageLimits;
 pub use types::{
-    RetryPolicy, RuntimeType, TaskDefinition, TaskExecution, TaskId, TaskResult, TaskStatus,
-    WorkerHealthStatus, WorkerInfo, WorkerId, WorkerStats, WorkflowDefinition, WorkflowId,
-    WorkflowInstance, WorkflowStatus,
+    Annotation, AnnotationContent, AnnotationId, ArchivedInstance, AwaitedTasks, Backpressure, BranchState, CachePolicy, ChangeEvent, DeadLetter, Deadline, DefinitionLimits, EventReaction, EventSubscription, HistoryEvent, HistoryEventKind, HttpCall, HttpPolicy, HumanTask, HumanTaskId, HumanTaskStatus, InstanceCursor, InstanceFilter, InstancePage, KvWrite, Location, ParentLink, Placement, PlacementMatch, PublishedEvent, RetentionAction, RetentionPolicy, RetryPolicy, RunningTask, RuntimeType, ScheduledTimer, Scheduling, TaskAttempt,
+    TaskDefinition, TaskExecution, TaskId, TaskResult, TaskStatus, WasmLimits, WorkerHealthStatus,
+    WorkerInfo, WorkerId, WorkerStats, WorkflowDefinition, WorkflowId, WorkflowInstance,
+    WorkflowStatus,
 };
-pub use worker::{TaskExecutor, Worker};
+pub use worker::{EngineClient, RpcRetry, TaskExecutor, Worker};
 
 // Re-export foundationdb for convenience
 pub use foundationdb;
@@ -0,0 +1,109 @@
+//! Page sizes and cursors of list RPCs
+//!
+//! Every list endpoint treats a limit of 0 as "use the default" and caps
+//! larger ones, and hands out cursors clients pass back unchanged. Cursors
+//! are encoded opaquely, so their contents can change without breaking
+//! clients, and carry a checksum, so altered or truncated ones are rejected
+//! instead of resuming a listing somewhere else.
+
+use base64::Engine as _;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+
+/// Default and largest page size of a list endpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageSize {
+    pub default: u32,
+    pub max: u32,
+}
+
+impl PageSize {
+    pub const fn new(default: u32, max: u32) -> Self {
+        Self { default, max }
+    }
+
+    /// Resolve a requested page size, 0 selecting the default
+    pub fn clamp(&self, requested: u32) -> usize {
+        match requested {
+            0 => self.default,
+            requested => requested.min(self.max),
+        } as usize
+    }
+}
+
+/// Length of the checksum ending an encoded cursor
+const CHECKSUM_LEN: usize = 16;
+
+/// Encode a cursor as an opaque URL-safe string
+pub fn encode_cursor<T: Serialize>(cursor: &T) -> String {
+    // Serializing plain cursor structs cannot fail
+    let mut bytes = serde_json::to_vec(cursor).unwrap_or_default();
+    let checksum = checksum(&bytes);
+    bytes.extend_from_slice(checksum.as_bytes());
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Decode a cursor produced by [`encode_cursor`], rejecting it if it was
+/// altered
+pub fn decode_cursor<T: DeserializeOwned>(cursor: &str) -> Result<T, String> {
+    let invalid = || format!("Invalid cursor: {}", cursor);
+    let bytes = URL_SAFE_NO_PAD.decode(cursor).map_err(|_| invalid())?;
+    let (payload, sum) = bytes
+        .split_at_checked(bytes.len().saturating_sub(CHECKSUM_LEN))
+        .ok_or_else(invalid)?;
+    if sum != checksum(payload).as_bytes() {
+        return Err(invalid());
+    }
+    serde_json::from_slice(payload).map_err(|_| invalid())
+}
+
+fn checksum(payload: &[u8]) -> String {
+    let mut digest = degov_crypto::sha256_hex(payload);
+    digest.truncate(CHECKSUM_LEN);
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[test]
+    fn test_page_size_clamp() {
+        let size = PageSize::new(50, 1000);
+        assert_eq!(size.clamp(0), 50);
+        assert_eq!(size.clamp(10), 10);
+        assert_eq!(size.clamp(5000), 1000);
+    }
+
+    #[test]
+    fn test_cursor_roundtrip() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Cursor {
+            after: u64,
+        }
+
+        let encoded = encode_cursor(&Cursor { after: 42 });
+        assert!(encoded.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+        assert_eq!(decode_cursor::<Cursor>(&encoded), Ok(Cursor { after: 42 }));
+        assert!(decode_cursor::<Cursor>("not a cursor").is_err());
+    }
+
+    #[test]
+    fn test_altered_cursor_rejected() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Cursor {
+            after: u64,
+        }
+
+        let encoded = encode_cursor(&Cursor { after: 42 });
+        let mut bytes = URL_SAFE_NO_PAD.decode(&encoded).unwrap();
+        let altered = String::from_utf8(bytes.clone()).unwrap().replace("42", "43");
+        assert!(decode_cursor::<Cursor>(&URL_SAFE_NO_PAD.encode(altered)).is_err());
+
+        bytes.truncate(bytes.len() - 1);
+        assert!(decode_cursor::<Cursor>(&URL_SAFE_NO_PAD.encode(bytes)).is_err());
+        assert!(decode_cursor::<Cursor>(&URL_SAFE_NO_PAD.encode(br#"{"after":42}"#)).is_err());
+    }
+}
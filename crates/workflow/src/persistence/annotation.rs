@@ -0,0 +1,98 @@
+//! Instance annotation persistence
+
+use super::{keys, Keyspace};
+use crate::error::PersistenceResult;
+use crate::types::{Annotation, WorkflowId};
+use foundationdb::{Database, RangeOption, Transaction};
+use std::sync::Arc;
+
+/// Annotation storage operations
+///
+/// Annotations are keyed by instance and creation time, so an instance's
+/// annotations are read back in the order they were added with one range scan.
+#[derive(Clone)]
+pub struct AnnotationStore {
+    db: Arc<Database>,
+    keyspace: Keyspace,
+}
+
+impl AnnotationStore {
+    pub fn new(db: Arc<Database>, keyspace: Keyspace) -> Self {
+        Self { db, keyspace }
+    }
+
+    /// Add an annotation
+    pub async fn add(&self, annotation: &Annotation) -> PersistenceResult<()> {
+        let tx = self.db.create_trx()?;
+
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+        tx.set_option(foundationdb::options::TransactionOption::RetryLimit(5))?;
+
+        self.add_tx(&tx, annotation)?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Add an annotation within a transaction
+    pub fn add_tx(&self, tx: &Transaction, annotation: &Annotation) -> PersistenceResult<()> {
+        let mut key = self.instance_prefix(&annotation.workflow_id);
+        key.extend_from_slice(&annotation.created_at.timestamp_millis().to_be_bytes());
+        key.extend_from_slice(annotation.id.to_string().as_bytes());
+
+        let value = serde_json::to_vec(annotation)?;
+        tx.set(&key, &value);
+        Ok(())
+    }
+
+    /// List the annotations of an instance, oldest first
+    pub async fn list(&self, workflow_id: &WorkflowId) -> PersistenceResult<Vec<Annotation>> {
+        let tx = self.db.create_trx()?;
+
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+
+        let annotations = self.list_tx(&tx, workflow_id).await?;
+        tx.cancel();
+        Ok(annotations)
+    }
+
+    /// List the annotations of an instance within a transaction
+    pub async fn list_tx(&self, tx: &Transaction, workflow_id: &WorkflowId) -> PersistenceResult<Vec<Annotation>> {
+        let begin_key = self.instance_prefix(workflow_id);
+        let mut end_key = begin_key.clone();
+        end_key.push(0xff);
+
+        let range = RangeOption {
+            begin: foundationdb::KeySelector::first_greater_or_equal(begin_key),
+            end: foundationdb::KeySelector::first_greater_or_equal(end_key),
+            mode: foundationdb::options::StreamingMode::WantAll,
+            reverse: false,
+            ..Default::default()
+        };
+
+        let results = tx.get_range(&range, 1, false).await?;
+
+        let mut annotations = Vec::with_capacity(results.len());
+        for kv in results.iter() {
+            annotations.push(serde_json::from_slice(kv.value())?);
+        }
+        Ok(annotations)
+    }
+
+    /// Remove all annotations of an instance within a transaction
+    pub fn clear_tx(&self, tx: &Transaction, workflow_id: &WorkflowId) {
+        let begin_key = self.instance_prefix(workflow_id);
+        let mut end_key = begin_key.clone();
+        end_key.push(0xff);
+        tx.clear_range(&begin_key, &end_key);
+    }
+
+    /// Build the key prefix shared by an instance's annotations
+    fn instance_prefix(&self, workflow_id: &WorkflowId) -> Vec<u8> {
+        let mut key = self.keyspace.prefix(keys::ANNOTATION_PREFIX);
+        key.extend_from_slice(workflow_id.to_string().as_bytes());
+        key.push(b':');
+        key
+    }
+}
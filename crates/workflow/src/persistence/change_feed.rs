@@ -0,0 +1,111 @@
+//! Change feed of workflow instance writes
+//!
+//! Every instance write appends an entry keyed by the commit versionstamp,
+//! so consumers such as the search indexer can tail the feed in commit order
+//! and resume from a persisted cursor after a restart.
+
+use super::{keys, Keyspace};
+use crate::error::PersistenceResult;
+use crate::types::{ChangeEvent, WorkflowId};
+use foundationdb::options::MutationType;
+use foundationdb::{Database, RangeOption, Transaction};
+use std::sync::Arc;
+
+/// Length of an FDB versionstamp
+const VERSIONSTAMP_LEN: usize = 10;
+
+/// Change feed storage operations
+#[derive(Clone)]
+pub struct ChangeFeedStore {
+    db: Arc<Database>,
+    keyspace: Keyspace,
+}
+
+impl ChangeFeedStore {
+    pub fn new(db: Arc<Database>, keyspace: Keyspace) -> Self {
+        Self { db, keyspace }
+    }
+
+    /// Record a change to an instance within a transaction
+    pub fn record_tx(
+        &self,
+        tx: &Transaction,
+        workflow_id: &WorkflowId,
+        deleted: bool,
+    ) -> PersistenceResult<()> {
+        // The placeholder is replaced with the commit versionstamp; the
+        // trailing little-endian offset tells FDB where it is
+        let prefix = self.keyspace.prefix(keys::CHANGE_FEED_PREFIX);
+        let mut key = prefix.clone();
+        key.extend_from_slice(&[0u8; VERSIONSTAMP_LEN]);
+        key.extend_from_slice(workflow_id.to_string().as_bytes());
+        key.extend_from_slice(&(prefix.len() as u32).to_le_bytes());
+
+        let value = serde_json::to_vec(&ChangeEvent {
+            cursor: Vec::new(),
+            workflow_id: *workflow_id,
+            deleted,
+        })?;
+        tx.atomic_op(&key, &value, MutationType::SetVersionstampedKey);
+        Ok(())
+    }
+
+    /// Read up to `limit` changes committed after `cursor`, oldest first
+    pub async fn read(
+        &self,
+        cursor: Option<&[u8]>,
+        limit: usize,
+    ) -> PersistenceResult<Vec<ChangeEvent>> {
+        let tx = self.db.create_trx()?;
+
+        let begin_key = self.keyspace.prefix(keys::CHANGE_FEED_PREFIX);
+        let begin = match cursor {
+            Some(cursor) => foundationdb::KeySelector::first_greater_than(cursor.to_vec()),
+            None => foundationdb::KeySelector::first_greater_or_equal(begin_key.clone()),
+        };
+        let mut end_key = begin_key;
+        end_key.push(0xff);
+        let range = RangeOption {
+            begin,
+            end: foundationdb::KeySelector::first_greater_or_equal(end_key),
+            mode: foundationdb::options::StreamingMode::WantAll,
+            limit: Some(limit),
+            reverse: false,
+            ..Default::default()
+        };
+
+        let results = tx.get_range(&range, 1, false).await?;
+        tx.cancel();
+
+        let mut events = Vec::with_capacity(results.len());
+        for kv in results.iter() {
+            let mut event: ChangeEvent = serde_json::from_slice(kv.value())?;
+            event.cursor = kv.key().to_vec();
+            events.push(event);
+        }
+        Ok(events)
+    }
+
+    /// Get the cursor a named consumer has processed the feed up to
+    pub async fn cursor(&self, consumer: &str) -> PersistenceResult<Option<Vec<u8>>> {
+        let tx = self.db.create_trx()?;
+        let key = self.keyspace.key(keys::CHANGE_FEED_CURSOR_PREFIX, consumer);
+        let cursor = tx.get(&key, false).await?.map(|v| v.to_vec());
+        tx.cancel();
+        Ok(cursor)
+    }
+
+    /// Persist the cursor of a named consumer
+    pub async fn save_cursor(&self, consumer: &str, cursor: &[u8]) -> PersistenceResult<()> {
+        let tx = self.db.create_trx()?;
+
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+        tx.set_option(foundationdb::options::TransactionOption::RetryLimit(5))?;
+
+        let key = self.keyspace.key(keys::CHANGE_FEED_CURSOR_PREFIX, consumer);
+        tx.set(&key, cursor);
+        tx.commit().await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,162 @@
+//! Event bus topic persistence
+//!
+//! Published events are appended to their topic keyed by the commit
+//! versionstamp, so subscribers read a topic in publish order. Every
+//! subscriber keeps its own cursor into a topic, which is advanced with a
+//! compare-and-set before an event is delivered.
+
+use super::{keys, HistoryStore, Keyspace};
+use crate::error::PersistenceResult;
+use crate::types::{HistoryEventKind, PublishedEvent, WorkflowId};
+use chrono::Utc;
+use foundationdb::options::MutationType;
+use foundationdb::{Database, RangeOption};
+use std::sync::Arc;
+
+/// Length of an FDB versionstamp
+const VERSIONSTAMP_LEN: usize = 10;
+
+/// Event bus storage operations
+#[derive(Clone)]
+pub struct EventBusStore {
+    db: Arc<Database>,
+    keyspace: Keyspace,
+    history: HistoryStore,
+}
+
+impl EventBusStore {
+    pub fn new(db: Arc<Database>, keyspace: Keyspace) -> Self {
+        Self {
+            history: HistoryStore::new(db.clone(), keyspace.clone()),
+            db,
+            keyspace,
+        }
+    }
+
+    /// Publish an event on a topic
+    ///
+    /// Events published by an instance are recorded in its history.
+    pub async fn publish(
+        &self,
+        topic: &str,
+        payload: serde_json::Value,
+        source: Option<&WorkflowId>,
+    ) -> PersistenceResult<()> {
+        let tx = self.db.create_trx()?;
+
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+        tx.set_option(foundationdb::options::TransactionOption::RetryLimit(5))?;
+
+        if let Some(source) = source {
+            let published = HistoryEventKind::EventPublished {
+                topic: topic.to_string(),
+            };
+            self.history.append_tx(&tx, source, published).await?;
+        }
+
+        // The placeholder is replaced with the commit versionstamp; the
+        // trailing little-endian offset tells FDB where it is
+        let prefix = self.topic_prefix(topic);
+        let mut key = prefix.clone();
+        key.extend_from_slice(&[0u8; VERSIONSTAMP_LEN]);
+        key.extend_from_slice(&(prefix.len() as u32).to_le_bytes());
+
+        let value = serde_json::to_vec(&PublishedEvent {
+            cursor: Vec::new(),
+            topic: topic.to_string(),
+            payload,
+            source: source.copied(),
+            published_at: Utc::now(),
+        })?;
+        tx.atomic_op(&key, &value, MutationType::SetVersionstampedKey);
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Read up to `limit` events of a topic published after `cursor`, oldest
+    /// first
+    pub async fn read(
+        &self,
+        topic: &str,
+        cursor: Option<&[u8]>,
+        limit: usize,
+    ) -> PersistenceResult<Vec<PublishedEvent>> {
+        let tx = self.db.create_trx()?;
+
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+
+        let prefix = self.topic_prefix(topic);
+        let begin = match cursor {
+            Some(cursor) => foundationdb::KeySelector::first_greater_than(cursor.to_vec()),
+            None => foundationdb::KeySelector::first_greater_or_equal(prefix.clone()),
+        };
+        let mut end_key = prefix;
+        end_key.push(0xff);
+        let range = RangeOption {
+            begin,
+            end: foundationdb::KeySelector::first_greater_or_equal(end_key),
+            mode: foundationdb::options::StreamingMode::WantAll,
+            limit: Some(limit),
+            reverse: false,
+            ..Default::default()
+        };
+
+        let results = tx.get_range(&range, 1, false).await?;
+        tx.cancel();
+
+        let mut events = Vec::with_capacity(results.len());
+        for kv in results.iter() {
+            let mut event: PublishedEvent = serde_json::from_slice(kv.value())?;
+            event.cursor = kv.key().to_vec();
+            events.push(event);
+        }
+        Ok(events)
+    }
+
+    /// Get the cursor a subscriber has consumed a topic up to
+    pub async fn cursor(&self, subscriber: &str) -> PersistenceResult<Option<Vec<u8>>> {
+        let tx = self.db.create_trx()?;
+        let key = self.keyspace.key(keys::EVENT_CURSOR_PREFIX, subscriber);
+        let cursor = tx.get(&key, false).await?.map(|v| v.to_vec());
+        tx.cancel();
+        Ok(cursor)
+    }
+
+    /// Move a subscriber's cursor from `previous` to `next`, returning whether
+    /// it was still at `previous`
+    ///
+    /// Only the caller that observes `true` may deliver the event at `next`,
+    /// so multiple engines consuming the same topic never deliver it twice.
+    pub async fn claim(
+        &self,
+        subscriber: &str,
+        previous: Option<&[u8]>,
+        next: &[u8],
+    ) -> PersistenceResult<bool> {
+        let tx = self.db.create_trx()?;
+
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+        tx.set_option(foundationdb::options::TransactionOption::RetryLimit(5))?;
+
+        let key = self.keyspace.key(keys::EVENT_CURSOR_PREFIX, subscriber);
+        let current = tx.get(&key, false).await?;
+        if current.as_deref() != previous {
+            tx.cancel();
+            return Ok(false);
+        }
+
+        tx.set(&key, next);
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    /// Build the key prefix shared by a topic's events
+    fn topic_prefix(&self, topic: &str) -> Vec<u8> {
+        let mut key = self.keyspace.key(keys::EVENT_PREFIX, topic);
+        key.push(b':');
+        key
+    }
+}
@@ -0,0 +1,161 @@
+//! Workflow instance history persistence
+
+use super::{keys, Keyspace};
+use crate::error::{PersistenceError, PersistenceResult};
+use crate::types::{HistoryEvent, HistoryEventKind, WorkflowId};
+use chrono::Utc;
+use foundationdb::{Database, RangeOption, Transaction};
+use std::sync::Arc;
+
+/// History storage operations
+///
+/// Events are keyed by instance and sequence number. The next sequence
+/// number is derived from the last event in the transaction that appends,
+/// so concurrent appends to one instance conflict instead of interleaving.
+#[derive(Clone)]
+pub struct HistoryStore {
+    db: Arc<Database>,
+    keyspace: Keyspace,
+}
+
+impl HistoryStore {
+    pub fn new(db: Arc<Database>, keyspace: Keyspace) -> Self {
+        Self { db, keyspace }
+    }
+
+    /// Append an event to an instance history within a transaction
+    pub async fn append_tx(
+        &self,
+        tx: &Transaction,
+        workflow_id: &WorkflowId,
+        kind: HistoryEventKind,
+    ) -> PersistenceResult<u64> {
+        let prefix = self.instance_prefix(workflow_id);
+        let mut end_key = prefix.clone();
+        end_key.push(0xff);
+
+        let range = RangeOption {
+            begin: foundationdb::KeySelector::first_greater_or_equal(prefix.clone()),
+            end: foundationdb::KeySelector::first_greater_or_equal(end_key),
+            limit: Some(1),
+            reverse: true,
+            ..Default::default()
+        };
+        let last = tx.get_range(&range, 1, false).await?;
+        let sequence = match last.first() {
+            Some(kv) => parse_sequence(&kv.key()[prefix.len()..])? + 1,
+            None => 1,
+        };
+
+        let event = HistoryEvent {
+            sequence,
+            workflow_id: *workflow_id,
+            recorded_at: Utc::now(),
+            kind,
+        };
+        let mut key = prefix;
+        key.extend_from_slice(&sequence.to_be_bytes());
+        tx.set(&key, &serde_json::to_vec(&event)?);
+        Ok(sequence)
+    }
+
+    /// Read up to `limit` events of an instance after sequence number `after`,
+    /// oldest first
+    pub async fn read(
+        &self,
+        workflow_id: &WorkflowId,
+        after: Option<u64>,
+        limit: usize,
+    ) -> PersistenceResult<Vec<HistoryEvent>> {
+        let tx = self.db.create_trx()?;
+
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+
+        let prefix = self.instance_prefix(workflow_id);
+        let begin = match after {
+            Some(after) => {
+                let mut key = prefix.clone();
+                key.extend_from_slice(&after.to_be_bytes());
+                foundationdb::KeySelector::first_greater_than(key)
+            }
+            None => foundationdb::KeySelector::first_greater_or_equal(prefix.clone()),
+        };
+        let mut end_key = prefix;
+        end_key.push(0xff);
+
+        let range = RangeOption {
+            begin,
+            end: foundationdb::KeySelector::first_greater_or_equal(end_key),
+            mode: foundationdb::options::StreamingMode::WantAll,
+            limit: Some(limit),
+            reverse: false,
+            ..Default::default()
+        };
+
+        let results = tx.get_range(&range, 1, false).await?;
+        tx.cancel();
+
+        let mut events = Vec::with_capacity(results.len());
+        for kv in results.iter() {
+            events.push(serde_json::from_slice(kv.value())?);
+        }
+        Ok(events)
+    }
+
+    /// Read the whole history of an instance within a transaction
+    pub async fn read_all_tx(&self, tx: &Transaction, workflow_id: &WorkflowId) -> PersistenceResult<Vec<HistoryEvent>> {
+        let begin_key = self.instance_prefix(workflow_id);
+        let mut end_key = begin_key.clone();
+        end_key.push(0xff);
+
+        let range = RangeOption {
+            begin: foundationdb::KeySelector::first_greater_or_equal(begin_key),
+            end: foundationdb::KeySelector::first_greater_or_equal(end_key),
+            mode: foundationdb::options::StreamingMode::WantAll,
+            reverse: false,
+            ..Default::default()
+        };
+
+        let results = tx.get_range(&range, 1, false).await?;
+        let mut events = Vec::with_capacity(results.len());
+        for kv in results.iter() {
+            events.push(serde_json::from_slice(kv.value())?);
+        }
+        Ok(events)
+    }
+
+    /// Write back events read from an instance history within a
+    /// transaction, keeping their sequence numbers
+    pub fn restore_tx(&self, tx: &Transaction, events: &[HistoryEvent]) -> PersistenceResult<()> {
+        for event in events {
+            let mut key = self.instance_prefix(&event.workflow_id);
+            key.extend_from_slice(&event.sequence.to_be_bytes());
+            tx.set(&key, &serde_json::to_vec(event)?);
+        }
+        Ok(())
+    }
+
+    /// Remove the whole history of an instance within a transaction
+    pub fn clear_tx(&self, tx: &Transaction, workflow_id: &WorkflowId) {
+        let begin_key = self.instance_prefix(workflow_id);
+        let mut end_key = begin_key.clone();
+        end_key.push(0xff);
+        tx.clear_range(&begin_key, &end_key);
+    }
+
+    /// Build the key prefix shared by an instance's history
+    fn instance_prefix(&self, workflow_id: &WorkflowId) -> Vec<u8> {
+        let mut key = self.keyspace.prefix(keys::HISTORY_PREFIX);
+        key.extend_from_slice(workflow_id.to_string().as_bytes());
+        key.push(b':');
+        key
+    }
+}
+
+fn parse_sequence(suffix: &[u8]) -> PersistenceResult<u64> {
+    suffix
+        .try_into()
+        .map(u64::from_be_bytes)
+        .map_err(|_| PersistenceError::Corruption("Invalid history key".to_string()))
+}
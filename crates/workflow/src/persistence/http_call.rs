@@ -0,0 +1,100 @@
+//! Pending HTTP request persistence
+
+use super::{keys, Keyspace};
+use crate::error::PersistenceResult;
+use crate::types::HttpCall;
+use chrono::{DateTime, Utc};
+use foundationdb::{Database, RangeOption};
+use std::sync::Arc;
+
+/// Storage of HTTP requests waiting to be sent
+///
+/// Requests are keyed by the time they are due, like timers, so a retry is
+/// scheduled by storing the request again with a later due time.
+#[derive(Clone)]
+pub struct HttpCallStore {
+    db: Arc<Database>,
+    keyspace: Keyspace,
+}
+
+impl HttpCallStore {
+    pub fn new(db: Arc<Database>, keyspace: Keyspace) -> Self {
+        Self { db, keyspace }
+    }
+
+    /// Schedule a request
+    pub async fn schedule(&self, call: &HttpCall) -> PersistenceResult<()> {
+        let tx = self.db.create_trx()?;
+
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+        tx.set_option(foundationdb::options::TransactionOption::RetryLimit(5))?;
+
+        let key = self.build_call_key(call);
+        let value = serde_json::to_vec(call)?;
+        tx.set(&key, &value);
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// List requests due at or before `now`, oldest first
+    pub async fn due(&self, now: DateTime<Utc>, limit: usize) -> PersistenceResult<Vec<HttpCall>> {
+        let tx = self.db.create_trx()?;
+
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+
+        // Everything strictly below the first key of the next millisecond is due
+        let begin_key = self.keyspace.prefix(keys::HTTP_CALL_PREFIX);
+        let mut end_key = begin_key.clone();
+        end_key.extend_from_slice(&(now.timestamp_millis() + 1).to_be_bytes());
+
+        let range = RangeOption {
+            begin: foundationdb::KeySelector::first_greater_or_equal(&begin_key),
+            end: foundationdb::KeySelector::first_greater_or_equal(&end_key),
+            mode: foundationdb::options::StreamingMode::WantAll,
+            limit: Some(limit),
+            reverse: false,
+            ..Default::default()
+        };
+
+        let results = tx.get_range(&range, 1, false).await?;
+        tx.cancel();
+
+        let mut calls = Vec::with_capacity(results.len());
+        for kv in results.iter() {
+            calls.push(serde_json::from_slice(kv.value())?);
+        }
+        Ok(calls)
+    }
+
+    /// Remove a request, returning whether it was still scheduled
+    ///
+    /// Only the caller that observes `true` may send the request, so multiple
+    /// engines polling the same keyspace never send an attempt twice.
+    pub async fn claim(&self, call: &HttpCall) -> PersistenceResult<bool> {
+        let tx = self.db.create_trx()?;
+
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+        tx.set_option(foundationdb::options::TransactionOption::RetryLimit(5))?;
+
+        let key = self.build_call_key(call);
+        if tx.get(&key, false).await?.is_none() {
+            tx.cancel();
+            return Ok(false);
+        }
+
+        tx.clear(&key);
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    /// Build request key ordered by due time
+    fn build_call_key(&self, call: &HttpCall) -> Vec<u8> {
+        let mut key = self.keyspace.prefix(keys::HTTP_CALL_PREFIX);
+        key.extend_from_slice(&call.due_at.timestamp_millis().to_be_bytes());
+        key.extend_from_slice(call.id.to_string().as_bytes());
+        key
+    }
+}
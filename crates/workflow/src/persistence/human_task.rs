@@ -0,0 +1,241 @@
+//! Human task persistence
+
+use super::{keys, HistoryStore, Keyspace};
+use crate::error::{PersistenceError, PersistenceResult};
+use crate::types::{HistoryEventKind, HumanTask, HumanTaskId, HumanTaskStatus, WorkflowId};
+use chrono::Utc;
+use foundationdb::{Database, RangeOption, Transaction};
+use std::sync::Arc;
+
+/// Human task storage operations
+///
+/// Every task is stored by ID. Open and claimed tasks are also kept in an
+/// active index keyed by instance, which holds a copy of the task so that
+/// work lists are served from one range scan. A task leaves the index when it
+/// is completed or cancelled.
+#[derive(Clone)]
+pub struct HumanTaskStore {
+    db: Arc<Database>,
+    keyspace: Keyspace,
+    history: HistoryStore,
+}
+
+impl HumanTaskStore {
+    pub fn new(db: Arc<Database>, keyspace: Keyspace) -> Self {
+        Self {
+            history: HistoryStore::new(db.clone(), keyspace.clone()),
+            db,
+            keyspace,
+        }
+    }
+
+    /// Create a task
+    pub async fn create(&self, task: &HumanTask) -> PersistenceResult<()> {
+        let tx = self.db.create_trx()?;
+
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+        tx.set_option(foundationdb::options::TransactionOption::RetryLimit(5))?;
+
+        let created = HistoryEventKind::HumanTaskCreated {
+            task_id: task.id,
+            title: task.title.clone(),
+        };
+        self.history.append_tx(&tx, &task.workflow_id, created).await?;
+
+        self.save_tx(&tx, task)?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Get a task by ID
+    pub async fn get(&self, id: &HumanTaskId) -> PersistenceResult<Option<HumanTask>> {
+        let tx = self.db.create_trx()?;
+
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+
+        let task = self.get_tx(&tx, id).await?;
+        tx.cancel();
+        Ok(task)
+    }
+
+    /// Get a task by ID within a transaction
+    pub async fn get_tx(&self, tx: &Transaction, id: &HumanTaskId) -> PersistenceResult<Option<HumanTask>> {
+        let key = self.keyspace.key(keys::HUMAN_TASK_PREFIX, &id.to_string());
+        match tx.get(&key, false).await? {
+            Some(value) => Ok(Some(serde_json::from_slice(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// List open and claimed tasks, of one instance or of all instances
+    pub async fn list_active(&self, workflow_id: Option<&WorkflowId>) -> PersistenceResult<Vec<HumanTask>> {
+        let tx = self.db.create_trx()?;
+
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+
+        let results = tx.get_range(&self.active_range(workflow_id), 1, false).await?;
+        tx.cancel();
+
+        let mut tasks = Vec::with_capacity(results.len());
+        for kv in results.iter() {
+            tasks.push(serde_json::from_slice(kv.value())?);
+        }
+        Ok(tasks)
+    }
+
+    /// Claim an open task for `by`
+    ///
+    /// Returns `None` when the task is no longer open, so of two concurrent
+    /// claims exactly one succeeds.
+    pub async fn claim(&self, id: &HumanTaskId, by: &str) -> PersistenceResult<Option<HumanTask>> {
+        let tx = self.db.create_trx()?;
+
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+        tx.set_option(foundationdb::options::TransactionOption::RetryLimit(5))?;
+
+        let mut task = self
+            .get_tx(&tx, id)
+            .await?
+            .ok_or_else(|| PersistenceError::NotFound(id.to_string()))?;
+        if task.status != HumanTaskStatus::Open {
+            tx.cancel();
+            return Ok(None);
+        }
+
+        let claimed = HistoryEventKind::HumanTaskClaimed {
+            task_id: task.id,
+            by: by.to_string(),
+        };
+        self.history.append_tx(&tx, &task.workflow_id, claimed).await?;
+
+        task.status = HumanTaskStatus::Claimed;
+        task.claimed_by = Some(by.to_string());
+        task.claimed_at = Some(Utc::now());
+        self.save_tx(&tx, &task)?;
+        tx.commit().await?;
+        Ok(Some(task))
+    }
+
+    /// Complete a task claimed by `by` with the submitted form
+    ///
+    /// Returns `None` when the task is not claimed by `by`.
+    pub async fn complete(
+        &self,
+        id: &HumanTaskId,
+        by: &str,
+        form: serde_json::Value,
+    ) -> PersistenceResult<Option<HumanTask>> {
+        let tx = self.db.create_trx()?;
+
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+        tx.set_option(foundationdb::options::TransactionOption::RetryLimit(5))?;
+
+        let mut task = self
+            .get_tx(&tx, id)
+            .await?
+            .ok_or_else(|| PersistenceError::NotFound(id.to_string()))?;
+        if task.status != HumanTaskStatus::Claimed || task.claimed_by.as_deref() != Some(by) {
+            tx.cancel();
+            return Ok(None);
+        }
+
+        let completed = HistoryEventKind::HumanTaskCompleted {
+            task_id: task.id,
+            by: by.to_string(),
+        };
+        self.history.append_tx(&tx, &task.workflow_id, completed).await?;
+
+        task.status = HumanTaskStatus::Completed;
+        task.form = Some(form);
+        task.completed_at = Some(Utc::now());
+        self.save_tx(&tx, &task)?;
+        tx.commit().await?;
+        Ok(Some(task))
+    }
+
+    /// Cancel the active tasks of an instance, or only those of one fork
+    /// branch, returning how many were cancelled
+    pub async fn cancel_active(&self, workflow_id: &WorkflowId, branch: Option<&str>) -> PersistenceResult<usize> {
+        let tx = self.db.create_trx()?;
+
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+        tx.set_option(foundationdb::options::TransactionOption::RetryLimit(5))?;
+
+        let results = tx.get_range(&self.active_range(Some(workflow_id)), 1, false).await?;
+        let mut tasks = Vec::with_capacity(results.len());
+        for kv in results.iter() {
+            let task: HumanTask = serde_json::from_slice(kv.value())?;
+            if branch.is_none() || task.branch.as_deref() == branch {
+                tasks.push(task);
+            }
+        }
+        if tasks.is_empty() {
+            tx.cancel();
+            return Ok(0);
+        }
+
+        let cancelled_count = tasks.len();
+        for mut task in tasks {
+            let cancelled = HistoryEventKind::HumanTaskCancelled { task_id: task.id };
+            self.history.append_tx(&tx, workflow_id, cancelled).await?;
+
+            task.status = HumanTaskStatus::Cancelled;
+            task.completed_at = Some(Utc::now());
+            self.save_tx(&tx, &task)?;
+        }
+
+        tx.commit().await?;
+        Ok(cancelled_count)
+    }
+
+    /// Write a task and keep the active index in step with its status
+    fn save_tx(&self, tx: &Transaction, task: &HumanTask) -> PersistenceResult<()> {
+        let value = serde_json::to_vec(task)?;
+        tx.set(&self.keyspace.key(keys::HUMAN_TASK_PREFIX, &task.id.to_string()), &value);
+
+        let active_key = self.active_key(task);
+        if task.is_active() {
+            tx.set(&active_key, &value);
+        } else {
+            tx.clear(&active_key);
+        }
+        Ok(())
+    }
+
+    /// Build the active index key of a task
+    fn active_key(&self, task: &HumanTask) -> Vec<u8> {
+        let mut key = self.active_prefix(Some(&task.workflow_id));
+        key.extend_from_slice(task.id.to_string().as_bytes());
+        key
+    }
+
+    /// Build the active index prefix of an instance, or of all instances
+    fn active_prefix(&self, workflow_id: Option<&WorkflowId>) -> Vec<u8> {
+        let mut key = self.keyspace.prefix(keys::HUMAN_TASK_ACTIVE_PREFIX);
+        if let Some(workflow_id) = workflow_id {
+            key.extend_from_slice(workflow_id.to_string().as_bytes());
+            key.push(b':');
+        }
+        key
+    }
+
+    fn active_range(&self, workflow_id: Option<&WorkflowId>) -> RangeOption<'static> {
+        let begin_key = self.active_prefix(workflow_id);
+        let mut end_key = begin_key.clone();
+        end_key.push(0xff);
+
+        RangeOption {
+            begin: foundationdb::KeySelector::first_greater_or_equal(begin_key),
+            end: foundationdb::KeySelector::first_greater_or_equal(end_key),
+            mode: foundationdb::options::StreamingMode::WantAll,
+            reverse: false,
+            ..Default::default()
+        }
+    }
+}
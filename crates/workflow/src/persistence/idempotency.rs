@@ -0,0 +1,185 @@
+//! Responses stored by idempotency key
+
+use super::{keys, Keyspace};
+use crate::error::{PersistenceResult, Result};
+use crate::idempotency::{Entry, IdempotencyStore, PendingCall, Reservation, StoredResponse};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use foundationdb::{Database, RangeOption};
+use std::sync::Arc;
+
+/// Responses to idempotent requests, and reservations of the calls still
+/// running, by idempotency key
+///
+/// Each entry is indexed by its expiry as well, so expired entries and
+/// lapsed reservations can be purged with a single range scan.
+#[derive(Clone)]
+pub struct IdempotencyKeyStore {
+    db: Arc<Database>,
+    keyspace: Keyspace,
+}
+
+impl IdempotencyKeyStore {
+    pub fn new(db: Arc<Database>, keyspace: Keyspace) -> Self {
+        Self { db, keyspace }
+    }
+
+    /// Get the response stored under a key, unless it expired
+    pub async fn load(&self, key: &str) -> PersistenceResult<Option<StoredResponse>> {
+        let tx = self.db.create_trx()?;
+
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+
+        let entry_key = self.keyspace.key(keys::IDEMPOTENT_RESPONSE_PREFIX, key);
+        let entry = match tx.get(&entry_key, false).await? {
+            Some(bytes) => Some(serde_json::from_slice::<Entry>(bytes.as_ref())?),
+            None => None,
+        };
+        tx.cancel();
+
+        match entry {
+            Some(Entry::Stored(response)) if response.expires_at > Utc::now() => Ok(Some(response)),
+            _ => Ok(None),
+        }
+    }
+
+    /// Reserve a key for a call about to run, unless a response is stored
+    /// under it or another call holds it
+    ///
+    /// Of two calls reserving a key at once, one transaction conflicts and
+    /// fails rather than both being told the key is theirs.
+    pub async fn reserve_key(&self, key: &str, call: &PendingCall) -> PersistenceResult<Reservation> {
+        let tx = self.db.create_trx()?;
+
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+        tx.set_option(foundationdb::options::TransactionOption::RetryLimit(5))?;
+
+        let entry_key = self.keyspace.key(keys::IDEMPOTENT_RESPONSE_PREFIX, key);
+        if let Some(bytes) = tx.get(&entry_key, false).await? {
+            let entry: Entry = serde_json::from_slice(bytes.as_ref())?;
+            let expires_at = entry.expires_at();
+            if let Some(found) = entry.reservation(Utc::now()) {
+                tx.cancel();
+                return Ok(found);
+            }
+            tx.clear(&self.expiry_key(expires_at, key));
+        }
+
+        tx.set(&entry_key, &serde_json::to_vec(&Entry::Pending(call.clone()))?);
+        tx.set(&self.expiry_key(call.expires_at, key), b"");
+
+        tx.commit().await?;
+        Ok(Reservation::Reserved)
+    }
+
+    /// Store a response under a key, replacing an earlier one or the
+    /// reservation
+    pub async fn store(&self, key: &str, response: &StoredResponse) -> PersistenceResult<()> {
+        let tx = self.db.create_trx()?;
+
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+        tx.set_option(foundationdb::options::TransactionOption::RetryLimit(5))?;
+
+        let entry_key = self.keyspace.key(keys::IDEMPOTENT_RESPONSE_PREFIX, key);
+        if let Some(bytes) = tx.get(&entry_key, false).await? {
+            let previous: Entry = serde_json::from_slice(bytes.as_ref())?;
+            tx.clear(&self.expiry_key(previous.expires_at(), key));
+        }
+
+        tx.set(&entry_key, &serde_json::to_vec(response)?);
+        tx.set(&self.expiry_key(response.expires_at, key), b"");
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Drop the reservation of a key, leaving a stored response in place
+    pub async fn release_key(&self, key: &str) -> PersistenceResult<()> {
+        let tx = self.db.create_trx()?;
+
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+        tx.set_option(foundationdb::options::TransactionOption::RetryLimit(5))?;
+
+        let entry_key = self.keyspace.key(keys::IDEMPOTENT_RESPONSE_PREFIX, key);
+        if let Some(bytes) = tx.get(&entry_key, false).await? {
+            if let Entry::Pending(call) = serde_json::from_slice(bytes.as_ref())? {
+                tx.clear(&entry_key);
+                tx.clear(&self.expiry_key(call.expires_at, key));
+            }
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Remove up to `limit` responses that expired at or before `now`,
+    /// returning how many were removed
+    pub async fn purge(&self, now: DateTime<Utc>, limit: usize) -> PersistenceResult<usize> {
+        let tx = self.db.create_trx()?;
+
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+        tx.set_option(foundationdb::options::TransactionOption::RetryLimit(5))?;
+
+        let begin_key = self.keyspace.prefix(keys::IDEMPOTENT_RESPONSE_EXPIRY_PREFIX);
+        let mut end_key = begin_key.clone();
+        end_key.extend_from_slice(&(now.timestamp_millis() + 1).to_be_bytes());
+
+        let range = RangeOption {
+            begin: foundationdb::KeySelector::first_greater_or_equal(&begin_key),
+            end: foundationdb::KeySelector::first_greater_or_equal(&end_key),
+            mode: foundationdb::options::StreamingMode::WantAll,
+            limit: Some(limit),
+            reverse: false,
+            ..Default::default()
+        };
+
+        let results = tx.get_range(&range, 1, false).await?;
+        for kv in results.iter() {
+            // The expiry takes 8 bytes after the prefix, the key the rest
+            let key = &kv.key()[begin_key.len() + 8..];
+            let mut entry_key = self.keyspace.prefix(keys::IDEMPOTENT_RESPONSE_PREFIX);
+            entry_key.extend_from_slice(key);
+            tx.clear(&entry_key);
+            tx.clear(kv.key());
+        }
+
+        tx.commit().await?;
+        Ok(results.len())
+    }
+
+    /// Build the index key ordering an entry by its expiry
+    fn expiry_key(&self, expires_at: DateTime<Utc>, key: &str) -> Vec<u8> {
+        let mut index_key = self.keyspace.prefix(keys::IDEMPOTENT_RESPONSE_EXPIRY_PREFIX);
+        index_key.extend_from_slice(&expires_at.timestamp_millis().to_be_bytes());
+        index_key.extend_from_slice(key.as_bytes());
+        index_key
+    }
+}
+
+#[async_trait]
+impl IdempotencyStore for IdempotencyKeyStore {
+    async fn get(&self, key: &str) -> Result<Option<StoredResponse>> {
+        Ok(self.load(key).await?)
+    }
+
+    async fn reserve(&self, key: &str, call: PendingCall) -> Result<Reservation> {
+        Ok(self.reserve_key(key, &call).await?)
+    }
+
+    async fn put(&self, key: &str, response: StoredResponse) -> Result<()> {
+        Ok(self.store(key, &response).await?)
+    }
+
+    async fn release(&self, key: &str) -> Result<()> {
+        Ok(self.release_key(key).await?)
+    }
+
+    async fn purge_expired(&self, now: DateTime<Utc>, limit: usize) -> Result<usize> {
+        Ok(self.purge(now, limit).await?)
+    }
+}
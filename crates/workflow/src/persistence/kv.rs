@@ -0,0 +1,121 @@
+//! Task key-value state persistence
+
+use super::{keys, Keyspace};
+use crate::error::PersistenceResult;
+use crate::types::{KvWrite, WorkflowId};
+use foundationdb::{Database, RangeOption, Transaction};
+use std::sync::Arc;
+
+/// Key-value state tasks keep per workflow instance
+///
+/// Keys are arbitrary bytes under the instance's prefix, so a range scan
+/// returns them in byte order.
+#[derive(Clone)]
+pub struct KvStore {
+    db: Arc<Database>,
+    keyspace: Keyspace,
+}
+
+impl KvStore {
+    pub fn new(db: Arc<Database>, keyspace: Keyspace) -> Self {
+        Self { db, keyspace }
+    }
+
+    /// Get the value of a key of an instance
+    pub async fn get(&self, workflow_id: &WorkflowId, key: &[u8]) -> PersistenceResult<Option<Vec<u8>>> {
+        let tx = self.db.create_trx()?;
+
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+
+        let mut full_key = self.instance_prefix(workflow_id);
+        full_key.extend_from_slice(key);
+        let value = tx.get(&full_key, false).await?.map(|v| v.to_vec());
+        tx.cancel();
+        Ok(value)
+    }
+
+    /// List up to `limit` entries of an instance with keys from `start` up
+    /// to, but excluding, `end`, in key order
+    ///
+    /// An empty `end` reads to the last key of the instance.
+    pub async fn range(
+        &self,
+        workflow_id: &WorkflowId,
+        start: &[u8],
+        end: &[u8],
+        limit: usize,
+    ) -> PersistenceResult<Vec<(Vec<u8>, Vec<u8>)>> {
+        let tx = self.db.create_trx()?;
+
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+
+        let prefix = self.instance_prefix(workflow_id);
+        let mut begin_key = prefix.clone();
+        begin_key.extend_from_slice(start);
+        let end_key = if end.is_empty() {
+            prefix_end(&prefix)
+        } else {
+            let mut end_key = prefix.clone();
+            end_key.extend_from_slice(end);
+            end_key
+        };
+
+        let range = RangeOption {
+            begin: foundationdb::KeySelector::first_greater_or_equal(begin_key),
+            end: foundationdb::KeySelector::first_greater_or_equal(end_key),
+            mode: foundationdb::options::StreamingMode::WantAll,
+            limit: Some(limit),
+            reverse: false,
+            ..Default::default()
+        };
+
+        let results = tx.get_range(&range, 1, false).await?;
+        tx.cancel();
+
+        Ok(results
+            .iter()
+            .map(|kv| (kv.key()[prefix.len()..].to_vec(), kv.value().to_vec()))
+            .collect())
+    }
+
+    /// Apply writes to the keys of an instance within a transaction
+    pub fn apply_tx(&self, tx: &Transaction, workflow_id: &WorkflowId, writes: &[KvWrite]) {
+        let prefix = self.instance_prefix(workflow_id);
+        for write in writes {
+            let mut key = prefix.clone();
+            key.extend_from_slice(write.key());
+            match write {
+                KvWrite::Set { value, .. } => tx.set(&key, value),
+                KvWrite::Delete { .. } => tx.clear(&key),
+            }
+        }
+    }
+
+    /// Remove all keys of an instance within a transaction
+    pub fn clear_tx(&self, tx: &Transaction, workflow_id: &WorkflowId) {
+        let prefix = self.instance_prefix(workflow_id);
+        tx.clear_range(&prefix, &prefix_end(&prefix));
+    }
+
+    /// Build the key prefix shared by an instance's keys
+    fn instance_prefix(&self, workflow_id: &WorkflowId) -> Vec<u8> {
+        let mut key = self.keyspace.prefix(keys::KV_PREFIX);
+        key.extend_from_slice(workflow_id.to_string().as_bytes());
+        key.push(b':');
+        key
+    }
+}
+
+/// First key after all keys starting with an instance prefix
+///
+/// Task keys may start with any byte, so the range cannot end at the prefix
+/// followed by `0xff`; the prefix's trailing `:` is bumped instead.
+fn prefix_end(prefix: &[u8]) -> Vec<u8> {
+    let mut end = prefix.to_vec();
+    if let Some(last) = end.last_mut() {
+        *last += 1;
+    }
+    end
+}
@@ -1,48 +1,201 @@
 //! Persistence layer using FoundationDB
 
+mod annotation;
+mod change_feed;
+mod event_bus;
+mod history;
+mod http_call;
+mod human_task;
+mod idempotency;
+mod kv;
+mod result_cache;
 mod task;
+mod timer;
 mod worker;
 mod workflow;
 
-pub use task::TaskStore;
+pub use annotation::AnnotationStore;
+pub use change_feed::ChangeFeedStore;
+pub use event_bus::EventBusStore;
+pub use history::HistoryStore;
+pub use http_call::HttpCallStore;
+pub use human_task::HumanTaskStore;
+pub use idempotency::IdempotencyKeyStore;
+pub use kv::KvStore;
+pub use result_cache::ResultCacheStore;
+pub use task::{QueueOrder, TaskStore};
+pub use timer::TimerStore;
 pub use worker::WorkerStore;
 pub use workflow::WorkflowStore;
 
-use crate::error::PersistenceResult;
-use foundationdb::Database;
+use crate::error::{PersistenceError, PersistenceResult};
+use degov_crypto::DidKey;
+use foundationdb::directory::{Directory, DirectoryLayer};
+use foundationdb::{Database, Transaction};
 use std::sync::Arc;
 
+/// Directory holding one subdirectory per tenant, named after its DID
+const TENANT_DIRECTORY: [&str; 2] = ["degov", "tenants"];
+
 /// Main persistence layer coordinator
+///
+/// The layer created with [`PersistenceLayer::new`] reads and writes the
+/// shared key space. [`PersistenceLayer::for_tenant`] opens a layer whose
+/// keys all live under the tenant's directory, so its reads, writes and
+/// list operations never touch another tenant's data. Workflow definitions
+/// are the exception: tenants share those of the shared key space.
 #[derive(Clone)]
 pub struct PersistenceLayer {
     db: Arc<Database>,
+    tenant: Option<DidKey>,
     workflow_store: WorkflowStore,
+    change_feed: ChangeFeedStore,
+    event_bus: EventBusStore,
+    annotation_store: AnnotationStore,
+    history_store: HistoryStore,
+    http_call_store: HttpCallStore,
+    human_task_store: HumanTaskStore,
+    idempotency_key_store: IdempotencyKeyStore,
+    kv_store: KvStore,
+    result_cache_store: ResultCacheStore,
     task_store: TaskStore,
+    timer_store: TimerStore,
     worker_store: WorkerStore,
 }
 
 impl PersistenceLayer {
     /// Create a new persistence layer
     pub fn new(db: Database) -> Self {
-        let db = Arc::new(db);
+        Self::with_keyspace(Arc::new(db), None, Keyspace::default())
+    }
+
+    fn with_keyspace(db: Arc<Database>, tenant: Option<DidKey>, keyspace: Keyspace) -> Self {
         Self {
-            workflow_store: WorkflowStore::new(db.clone()),
-            task_store: TaskStore::new(db.clone()),
-            worker_store: WorkerStore::new(db.clone()),
+            workflow_store: WorkflowStore::new(db.clone(), keyspace.clone()),
+            change_feed: ChangeFeedStore::new(db.clone(), keyspace.clone()),
+            event_bus: EventBusStore::new(db.clone(), keyspace.clone()),
+            annotation_store: AnnotationStore::new(db.clone(), keyspace.clone()),
+            history_store: HistoryStore::new(db.clone(), keyspace.clone()),
+            http_call_store: HttpCallStore::new(db.clone(), keyspace.clone()),
+            human_task_store: HumanTaskStore::new(db.clone(), keyspace.clone()),
+            idempotency_key_store: IdempotencyKeyStore::new(db.clone(), keyspace.clone()),
+            kv_store: KvStore::new(db.clone(), keyspace.clone()),
+            result_cache_store: ResultCacheStore::new(db.clone(), keyspace.clone()),
+            task_store: TaskStore::new(db.clone(), keyspace.clone()),
+            timer_store: TimerStore::new(db.clone(), keyspace.clone()),
+            worker_store: WorkerStore::new(db.clone(), keyspace),
+            tenant,
             db,
         }
     }
 
+    /// Open the persistence layer of the tenant identified by `did`
+    ///
+    /// The tenant's keys live under the prefix the FDB directory layer
+    /// allocates to `degov/tenants/<did>`, which is created on first use.
+    pub async fn for_tenant(&self, did: &DidKey) -> PersistenceResult<PersistenceLayer> {
+        let tx = self.db.create_trx()?;
+
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+        tx.set_option(foundationdb::options::TransactionOption::RetryLimit(5))?;
+
+        let mut path = tenant_directory();
+        path.push(did.to_string());
+        let directory = DirectoryLayer::default()
+            .create_or_open(&tx, &path, None, None)
+            .await
+            .map_err(directory_error)?;
+        let prefix = directory.bytes().map_err(directory_error)?.to_vec();
+        tx.commit().await?;
+
+        let mut layer = Self::with_keyspace(self.db.clone(), Some(*did), Keyspace::new(prefix));
+        layer.workflow_store = layer.workflow_store.with_definitions(Keyspace::default());
+        Ok(layer)
+    }
+
+    /// List the tenants that have a key space
+    pub async fn tenants(&self) -> PersistenceResult<Vec<DidKey>> {
+        let tx = self.db.create_trx()?;
+        let names = list_directory(&tx, &tenant_directory()).await;
+        tx.cancel();
+
+        let mut tenants = Vec::new();
+        for name in names? {
+            let did = name
+                .parse()
+                .map_err(|_| PersistenceError::Corruption(format!("Invalid tenant directory '{}'", name)))?;
+            tenants.push(did);
+        }
+        Ok(tenants)
+    }
+
+    /// Get the tenant this layer is scoped to, `None` for the shared key
+    /// space
+    pub fn tenant(&self) -> Option<&DidKey> {
+        self.tenant.as_ref()
+    }
+
     /// Get the workflow store
     pub fn workflows(&self) -> &WorkflowStore {
         &self.workflow_store
     }
 
+    /// Get the instance change feed
+    pub fn change_feed(&self) -> &ChangeFeedStore {
+        &self.change_feed
+    }
+
+    /// Get the event bus
+    pub fn event_bus(&self) -> &EventBusStore {
+        &self.event_bus
+    }
+
+    /// Get the instance annotation store
+    pub fn annotations(&self) -> &AnnotationStore {
+        &self.annotation_store
+    }
+
+    /// Get the instance history store
+    pub fn history(&self) -> &HistoryStore {
+        &self.history_store
+    }
+
+    /// Get the store of pending HTTP requests
+    pub fn http_calls(&self) -> &HttpCallStore {
+        &self.http_call_store
+    }
+
+    /// Get the human task store
+    pub fn human_tasks(&self) -> &HumanTaskStore {
+        &self.human_task_store
+    }
+
+    /// Get the store of responses by idempotency key
+    pub fn idempotency_keys(&self) -> &IdempotencyKeyStore {
+        &self.idempotency_key_store
+    }
+
+    /// Get the store of task key-value state
+    pub fn kv(&self) -> &KvStore {
+        &self.kv_store
+    }
+
+    /// Get the store of cached task results
+    pub fn result_cache(&self) -> &ResultCacheStore {
+        &self.result_cache_store
+    }
+
     /// Get the task store
     pub fn tasks(&self) -> &TaskStore {
         &self.task_store
     }
 
+    /// Get the timer store
+    pub fn timers(&self) -> &TimerStore {
+        &self.timer_store
+    }
+
     /// Get the worker store
     pub fn workers(&self) -> &WorkerStore {
         &self.worker_store
@@ -73,16 +226,159 @@ pub(crate) mod keys {
     pub const WORKFLOW_DEF_PREFIX: &[u8] = b"wfd:";
     pub const TASK_PREFIX: &[u8] = b"tk:";
     pub const TASK_QUEUE_PREFIX: &[u8] = b"tq:";
+    pub const FAIR_QUEUE_PREFIX: &[u8] = b"fq:";
+    pub const FAIR_QUEUE_INDEX_PREFIX: &[u8] = b"fqi:";
+    pub const TASK_SESSION_PREFIX: &[u8] = b"ts:";
+    pub const TASK_LEASE_PREFIX: &[u8] = b"tl:";
+    pub const DISPATCH_RATE_PREFIX: &[u8] = b"dr:";
     pub const WORKER_PREFIX: &[u8] = b"wr:";
     pub const WORKER_HEARTBEAT_PREFIX: &[u8] = b"wh:";
+    pub const TIMER_PREFIX: &[u8] = b"tm:";
+    pub const TIMER_BY_WORKFLOW_PREFIX: &[u8] = b"tmw:";
+    pub const SUSPENDED_TIMER_PREFIX: &[u8] = b"pt:";
+    pub const DEAD_LETTER_PREFIX: &[u8] = b"dl:";
+    pub const CHANGE_FEED_PREFIX: &[u8] = b"cf:";
+    pub const CHANGE_FEED_CURSOR_PREFIX: &[u8] = b"cfc:";
+    pub const ANNOTATION_PREFIX: &[u8] = b"an:";
+    pub const INSTANCE_BY_CREATED_PREFIX: &[u8] = b"ic:";
+    pub const INSTANCE_BY_DEFINITION_PREFIX: &[u8] = b"id:";
+    pub const INSTANCE_BY_STATUS_PREFIX: &[u8] = b"is:";
+    pub const INSTANCE_BY_DEFINITION_STATUS_PREFIX: &[u8] = b"ids:";
+    pub const HISTORY_PREFIX: &[u8] = b"hs:";
+    pub const ARCHIVE_PREFIX: &[u8] = b"ar:";
+    pub const HUMAN_TASK_PREFIX: &[u8] = b"ht:";
+    pub const HUMAN_TASK_ACTIVE_PREFIX: &[u8] = b"hta:";
+    pub const EVENT_PREFIX: &[u8] = b"ev:";
+    pub const EVENT_CURSOR_PREFIX: &[u8] = b"evc:";
+    pub const HTTP_CALL_PREFIX: &[u8] = b"hc:";
+    pub const IDEMPOTENCY_KEY_PREFIX: &[u8] = b"ik:";
+    pub const CONTEXT_INDEX_PREFIX: &[u8] = b"cx:";
+    pub const KV_PREFIX: &[u8] = b"kv:";
+    pub const RESULT_CACHE_PREFIX: &[u8] = b"rc:";
+    pub const RESULT_CACHE_EXPIRY_PREFIX: &[u8] = b"rce:";
+    pub const IDEMPOTENT_RESPONSE_PREFIX: &[u8] = b"ir:";
+    pub const IDEMPOTENT_RESPONSE_EXPIRY_PREFIX: &[u8] = b"ire:";
+    pub const META_PREFIX: &[u8] = b"meta:";
 }
 
-/// Helper to build FDB keys
-pub(crate) fn build_key(prefix: &[u8], id: &str) -> Vec<u8> {
-    let mut key = Vec::with_capacity(prefix.len() + id.len());
-    key.extend_from_slice(prefix);
-    key.extend_from_slice(id.as_bytes());
-    key
+/// Key space of a persistence layer
+///
+/// Every key a store builds starts with the key space's prefix: empty for
+/// the shared key space, the directory prefix of a tenant otherwise.
+#[derive(Debug, Clone, Default)]
+pub struct Keyspace {
+    prefix: Arc<[u8]>,
 }
 
+impl Keyspace {
+    fn new(prefix: Vec<u8>) -> Self {
+        Self { prefix: prefix.into() }
+    }
+
+    /// Build the key of `id` under a key prefix
+    pub(crate) fn key(&self, prefix: &[u8], id: &str) -> Vec<u8> {
+        let mut key = self.prefix(prefix);
+        key.extend_from_slice(id.as_bytes());
+        key
+    }
+
+    /// Get the start of the keys under a key prefix
+    pub(crate) fn prefix(&self, prefix: &[u8]) -> Vec<u8> {
+        let mut key = Vec::with_capacity(self.prefix.len() + prefix.len());
+        key.extend_from_slice(&self.prefix);
+        key.extend_from_slice(prefix);
+        key
+    }
+}
+
+fn tenant_directory() -> Vec<String> {
+    TENANT_DIRECTORY.iter().map(|name| name.to_string()).collect()
+}
+
+/// List a directory's subdirectories, none if it does not exist yet
+async fn list_directory(tx: &Transaction, path: &[String]) -> PersistenceResult<Vec<String>> {
+    let layer = DirectoryLayer::default();
+    if !layer.exists(tx, path).await.map_err(directory_error)? {
+        return Ok(Vec::new());
+    }
+    layer.list(tx, path).await.map_err(directory_error)
+}
 
+fn directory_error(e: foundationdb::directory::DirectoryError) -> PersistenceError {
+    PersistenceError::Directory(format!("{:?}", e))
+}
+
+/// Start the FoundationDB network for the tests of the crate
+///
+/// The network can only be started once per process, so it is left running
+/// until the test binary exits.
+#[cfg(test)]
+pub(crate) fn boot_network() {
+    static NETWORK: std::sync::Once = std::sync::Once::new();
+    NETWORK.call_once(|| std::mem::forget(unsafe { foundationdb::boot() }));
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{InstanceFilter, WorkflowId, WorkflowInstance, WorkflowStatus};
+    use chrono::Utc;
+    use degov_crypto::StackKey;
+
+    #[test]
+    fn test_tenant_keys_stay_in_their_keyspace() {
+        // Directory prefixes as allocated for two tenants
+        let a = Keyspace::new(vec![0x15, 0x01]);
+        let b = Keyspace::new(vec![0x15, 0x02]);
+        let key = a.key(keys::WORKFLOW_PREFIX, "instance");
+
+        assert!(key.starts_with(&a.prefix(keys::WORKFLOW_PREFIX)));
+        assert!(!key.starts_with(&b.prefix(keys::WORKFLOW_PREFIX)));
+        assert!(!key.starts_with(&Keyspace::default().prefix(keys::WORKFLOW_PREFIX)));
+    }
+
+    fn instance() -> WorkflowInstance {
+        WorkflowInstance {
+            id: WorkflowId::new(),
+            definition_id: WorkflowId::new(),
+            current_state: "start".to_string(),
+            context: serde_json::json!({}),
+            status: WorkflowStatus::Running,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            completed_at: None,
+            parent: None,
+            awaiting_children: Vec::new(),
+            compensation: None,
+            branches: Vec::new(),
+            awaited_tasks: Vec::new(),
+            idempotency_key: None,
+            state_entry: 0,
+            paused_at: None,
+            held_tasks: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a FoundationDB cluster"]
+    async fn test_tenants_do_not_see_each_others_instances() {
+        boot_network();
+        let shared = PersistenceLayer::new(Database::default().unwrap());
+        let a = shared.for_tenant(&StackKey::from_seed([1; 32]).did()).await.unwrap();
+        let b = shared.for_tenant(&StackKey::from_seed([2; 32]).did()).await.unwrap();
+
+        let instance = instance();
+        a.workflows().save_instance(&instance).await.unwrap();
+
+        let listed = |layer: PersistenceLayer| async move {
+            let page = layer.workflows().list(&InstanceFilter::default(), None, 100).await.unwrap();
+            page.instances.into_iter().map(|i| i.id).collect::<Vec<_>>()
+        };
+        assert!(listed(a.clone()).await.contains(&instance.id));
+        assert!(!listed(b.clone()).await.contains(&instance.id));
+        assert!(!listed(shared).await.contains(&instance.id));
+        assert!(b.workflows().get_instance(&instance.id).await.unwrap().is_none());
+        assert!(a.workflows().get_instance(&instance.id).await.unwrap().is_some());
+    }
+}
@@ -0,0 +1,119 @@
+//! Cached results of idempotent tasks
+
+use super::{keys, Keyspace};
+use crate::error::PersistenceResult;
+use crate::types::TaskResult;
+use chrono::{DateTime, Utc};
+use foundationdb::{Database, RangeOption, Transaction};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// A cached result with the time it stops being reused
+#[derive(Serialize, Deserialize)]
+struct CachedResult {
+    result: TaskResult,
+    expires_at: DateTime<Utc>,
+}
+
+/// Results of idempotent tasks, by cache key
+///
+/// Each entry is indexed by its expiry as well, so expired entries can be
+/// purged with a single range scan.
+#[derive(Clone)]
+pub struct ResultCacheStore {
+    db: Arc<Database>,
+    keyspace: Keyspace,
+}
+
+impl ResultCacheStore {
+    pub fn new(db: Arc<Database>, keyspace: Keyspace) -> Self {
+        Self { db, keyspace }
+    }
+
+    /// Get the result cached under a key, unless it expired
+    pub async fn get(&self, cache_key: &str) -> PersistenceResult<Option<TaskResult>> {
+        let tx = self.db.create_trx()?;
+
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+
+        let key = self.keyspace.key(keys::RESULT_CACHE_PREFIX, cache_key);
+        let cached = match tx.get(&key, false).await? {
+            Some(bytes) => Some(serde_json::from_slice::<CachedResult>(bytes.as_ref())?),
+            None => None,
+        };
+        tx.cancel();
+
+        Ok(cached
+            .filter(|cached| cached.expires_at > Utc::now())
+            .map(|cached| cached.result))
+    }
+
+    /// Cache a result under a key until `expires_at` within a transaction,
+    /// replacing an earlier one
+    pub async fn put_tx(
+        &self,
+        tx: &Transaction,
+        cache_key: &str,
+        result: &TaskResult,
+        expires_at: DateTime<Utc>,
+    ) -> PersistenceResult<()> {
+        let key = self.keyspace.key(keys::RESULT_CACHE_PREFIX, cache_key);
+        if let Some(bytes) = tx.get(&key, false).await? {
+            let previous: CachedResult = serde_json::from_slice(bytes.as_ref())?;
+            tx.clear(&self.expiry_key(previous.expires_at, cache_key));
+        }
+
+        let cached = CachedResult {
+            result: result.clone(),
+            expires_at,
+        };
+        tx.set(&key, &serde_json::to_vec(&cached)?);
+        tx.set(&self.expiry_key(expires_at, cache_key), b"");
+        Ok(())
+    }
+
+    /// Remove up to `limit` entries that expired at or before `now`,
+    /// returning how many were removed
+    pub async fn purge_expired(&self, now: DateTime<Utc>, limit: usize) -> PersistenceResult<usize> {
+        let tx = self.db.create_trx()?;
+
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+        tx.set_option(foundationdb::options::TransactionOption::RetryLimit(5))?;
+
+        let begin_key = self.keyspace.prefix(keys::RESULT_CACHE_EXPIRY_PREFIX);
+        let mut end_key = begin_key.clone();
+        end_key.extend_from_slice(&(now.timestamp_millis() + 1).to_be_bytes());
+
+        let range = RangeOption {
+            begin: foundationdb::KeySelector::first_greater_or_equal(&begin_key),
+            end: foundationdb::KeySelector::first_greater_or_equal(&end_key),
+            mode: foundationdb::options::StreamingMode::WantAll,
+            limit: Some(limit),
+            reverse: false,
+            ..Default::default()
+        };
+
+        let results = tx.get_range(&range, 1, false).await?;
+        for kv in results.iter() {
+            // The expiry takes 8 bytes after the prefix, the cache key the rest
+            let cache_key = &kv.key()[begin_key.len() + 8..];
+            let mut key = self.keyspace.prefix(keys::RESULT_CACHE_PREFIX);
+            key.extend_from_slice(cache_key);
+            tx.clear(&key);
+            tx.clear(kv.key());
+        }
+
+        tx.commit().await?;
+        Ok(results.len())
+    }
+
+    /// Build the index key ordering an entry by its expiry
+    fn expiry_key(&self, expires_at: DateTime<Utc>, cache_key: &str) -> Vec<u8> {
+        let mut key = self.keyspace.prefix(keys::RESULT_CACHE_EXPIRY_PREFIX);
+        key.extend_from_slice(&expires_at.timestamp_millis().to_be_bytes());
+        key.extend_from_slice(cache_key.as_bytes());
+        key
+    }
+}
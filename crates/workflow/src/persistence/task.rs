@@ -1,24 +1,67 @@
 //! Task persistence
 
-use super::{build_key, keys};
+use super::{keys, HistoryStore, Keyspace, KvStore, ResultCacheStore};
 use crate::error::{PersistenceError, PersistenceResult};
-use crate::types::{TaskExecution, TaskId, TaskResult, TaskStatus, WorkerId};
-use chrono::Utc;
+use crate::types::{
+    DeadLetter, HistoryEventKind, Location, RuntimeType, TaskAttempt, TaskExecution, TaskId, TaskResult, TaskStatus,
+    WorkerHealthStatus, WorkerId, WorkerInfo, WorkflowInstance, WorkflowStatus,
+};
+use chrono::{DateTime, Utc};
 use foundationdb::{Database, RangeOption, Transaction};
 use std::sync::Arc;
+use std::time::Duration;
+
+/// Counter of tasks moved to the dead-letter store
+const DEAD_LETTERED_METRIC: &str = "workflow_tasks_dead_lettered_total";
+
+/// Counter of tasks rejected because no worker can run them
+const UNSCHEDULABLE_METRIC: &str = "workflow_tasks_unschedulable_total";
+
+/// Counter of tasks completed with a cached result instead of running
+const CACHED_METRIC: &str = "workflow_tasks_cached_total";
+
+/// Due tasks looked at per runtime partition when dequeuing, so tasks
+/// placed elsewhere do not starve the ones behind them
+const DEQUEUE_SCAN_LIMIT: usize = 16;
+
+/// Fair queues looked up per runtime partition when dequeuing
+const FAIR_QUEUE_SCAN_LIMIT: usize = 1000;
+
+/// Expired leases reclaimed per dequeue
+const LEASE_SCAN_LIMIT: usize = 16;
+
+/// Order in which fair queues are served
+pub trait QueueOrder: Send + Sync {
+    /// Sort `queues` into the order they should be tried in
+    fn order(&self, queues: &[String]) -> Vec<String>;
+
+    /// Record that a task was taken from `queue` while `queues` had tasks
+    fn served(&self, queue: &str, queues: &[String]);
+}
 
 /// Task storage operations
 #[derive(Clone)]
 pub struct TaskStore {
     db: Arc<Database>,
+    keyspace: Keyspace,
+    history: HistoryStore,
+    kv: KvStore,
+    result_cache: ResultCacheStore,
 }
 
 impl TaskStore {
-    pub fn new(db: Arc<Database>) -> Self {
-        Self { db }
+    pub fn new(db: Arc<Database>, keyspace: Keyspace) -> Self {
+        Self {
+            history: HistoryStore::new(db.clone(), keyspace.clone()),
+            kv: KvStore::new(db.clone(), keyspace.clone()),
+            result_cache: ResultCacheStore::new(db.clone(), keyspace.clone()),
+            db,
+            keyspace,
+        }
     }
 
     /// Enqueue a task for execution
+    #[tracing::instrument(level = "debug", name = "persistence.task.enqueue", skip_all)]
     pub async fn enqueue(&self, task: TaskExecution) -> PersistenceResult<()> {
         let tx = self.db.create_trx()?;
         
@@ -32,74 +75,380 @@ impl TaskStore {
     }
 
     /// Enqueue a task within a transaction
-    pub async fn enqueue_tx(&self, tx: &Transaction, task: TaskExecution) -> PersistenceResult<()> {
+    pub async fn enqueue_tx(&self, tx: &Transaction, mut task: TaskExecution) -> PersistenceResult<()> {
+        let queued_at = Utc::now();
+        task.queued_at = Some(queued_at);
+
         // Save task data
-        let task_key = build_key(keys::TASK_PREFIX, &task.id.to_string());
+        let task_key = self.keyspace.key(keys::TASK_PREFIX, &task.id.to_string());
         let task_value = serde_json::to_vec(&task)?;
         tx.set(&task_key, &task_value);
 
         // Add to pending queue with timestamp for ordering
-        let queue_key = self.build_queue_key(&task.id);
-        tx.set(&queue_key, &task.id.to_string().as_bytes());
+        self.queue_tx(tx, &task, queued_at);
+
+        let enqueued = HistoryEventKind::TaskEnqueued {
+            task_id: task.id,
+            name: task.definition.name.clone(),
+        };
+        self.history.append_tx(tx, &task.workflow_id, enqueued).await?;
+
+        Ok(())
+    }
+
+    /// Reject a task that no registered worker is able to run
+    ///
+    /// The task is stored as `Unschedulable` and placed in the dead-letter
+    /// store with `reason`, so it can be requeued once a capable worker exists.
+    pub async fn reject(&self, mut task: TaskExecution, reason: String) -> PersistenceResult<()> {
+        let tx = self.db.create_trx()?;
+        
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+        tx.set_option(foundationdb::options::TransactionOption::RetryLimit(5))?;
+        
+        let now = Utc::now();
+        task.status = TaskStatus::Unschedulable;
+        task.completed_at = Some(now);
+
+        let task_key = self.keyspace.key(keys::TASK_PREFIX, &task.id.to_string());
+        tx.set(&task_key, &serde_json::to_vec(&task)?);
+
+        let rejected = HistoryEventKind::TaskRejected {
+            task_id: task.id,
+            name: task.definition.name.clone(),
+            reason: reason.clone(),
+        };
+        self.history.append_tx(&tx, &task.workflow_id, rejected).await?;
+
+        let dead_letter_key = self.keyspace.key(keys::DEAD_LETTER_PREFIX, &task.id.to_string());
+        let dead_letter = DeadLetter {
+            task,
+            error: Some(reason),
+            failed_at: now,
+        };
+        tx.set(&dead_letter_key, &serde_json::to_vec(&dead_letter)?);
 
+        tx.commit().await?;
+        metrics::counter!(UNSCHEDULABLE_METRIC).increment(1);
         Ok(())
     }
 
-    /// Dequeue next pending task (atomic operation)
-    pub async fn dequeue(&self, worker_id: &WorkerId) -> PersistenceResult<Option<TaskExecution>> {
+    /// Record a task as completed with a cached result, without queueing it
+    pub async fn complete_from_cache(&self, mut task: TaskExecution, result: TaskResult) -> PersistenceResult<()> {
         let tx = self.db.create_trx()?;
         
         // Set transaction timeout to 2 seconds
         tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
         tx.set_option(foundationdb::options::TransactionOption::RetryLimit(5))?;
         
-        let result = self.dequeue_tx(&tx, worker_id).await?;
+        let now = Utc::now();
+        task.status = TaskStatus::Completed;
+        task.completed_at = Some(now);
+        task.history.push(TaskAttempt {
+            attempt: task.attempt,
+            worker: None,
+            started_at: None,
+            finished_at: now,
+            success: true,
+            error: None,
+            retry_at: None,
+        });
+        task.result = Some(result);
+
+        let task_key = self.keyspace.key(keys::TASK_PREFIX, &task.id.to_string());
+        tx.set(&task_key, &serde_json::to_vec(&task)?);
+
+        let completed = HistoryEventKind::TaskCompleted {
+            task_id: task.id,
+            name: task.definition.name.clone(),
+            status: task.status,
+            error: None,
+        };
+        self.history.append_tx(&tx, &task.workflow_id, completed).await?;
+
+        tx.commit().await?;
+        metrics::counter!(CACHED_METRIC).increment(1);
+        Ok(())
+    }
+
+    /// Dequeue the next pending task the worker is capable of running
+    /// (atomic operation)
+    ///
+    /// The worker holds the task under a lease of `visibility_timeout`; the
+    /// task is queued again once the lease runs out without being extended.
+    #[tracing::instrument(level = "debug", name = "persistence.task.dequeue", skip_all)]
+    pub async fn dequeue(
+        &self,
+        worker_id: &WorkerId,
+        capabilities: &[RuntimeType],
+        location: &Location,
+        order: &dyn QueueOrder,
+        visibility_timeout: Duration,
+    ) -> PersistenceResult<Option<TaskExecution>> {
+        let tx = self.db.create_trx()?;
+        
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+        tx.set_option(foundationdb::options::TransactionOption::RetryLimit(5))?;
+        
+        let result = self
+            .dequeue_tx(&tx, worker_id, capabilities, location, order, visibility_timeout)
+            .await?;
         tx.commit().await?;
         Ok(result)
     }
 
+    /// Dequeue up to `max` pending tasks the worker is capable of running
+    /// in one transaction
+    #[tracing::instrument(level = "debug", name = "persistence.task.dequeue_batch", skip_all)]
+    pub async fn dequeue_batch(
+        &self,
+        worker_id: &WorkerId,
+        capabilities: &[RuntimeType],
+        location: &Location,
+        max: usize,
+        order: &dyn QueueOrder,
+        visibility_timeout: Duration,
+    ) -> PersistenceResult<Vec<TaskExecution>> {
+        let tx = self.db.create_trx()?;
+        
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+        tx.set_option(foundationdb::options::TransactionOption::RetryLimit(5))?;
+        
+        // Tasks taken earlier in the transaction are no longer in the queue
+        // when the next one is looked for
+        let mut tasks = Vec::new();
+        while tasks.len() < max {
+            let dequeued = self
+                .dequeue_tx(&tx, worker_id, capabilities, location, order, visibility_timeout)
+                .await?;
+            match dequeued {
+                Some(task) => tasks.push(task),
+                None => break,
+            }
+        }
+
+        tx.commit().await?;
+        Ok(tasks)
+    }
+
     /// Dequeue next pending task within a transaction
+    ///
+    /// Each runtime has its own queue partition, split into fair queues that
+    /// are tried in the order given by `order`, followed by the queue of
+    /// tasks enqueued before fair scheduling. Within a queue the oldest due
+    /// task across the partitions of `capabilities` that may run at
+    /// `location` is taken. Tasks placed elsewhere are skipped until their
+    /// spill time has passed, and the placement of the task taken is recorded
+    /// in its history. Tasks of a session are left to the worker the session
+    /// is bound to while it can take them, and bind the session to the worker
+    /// taking them. Tasks whose lease ran out are queued again first.
     pub async fn dequeue_tx(
         &self,
         tx: &Transaction,
         worker_id: &WorkerId,
+        capabilities: &[RuntimeType],
+        location: &Location,
+        order: &dyn QueueOrder,
+        visibility_timeout: Duration,
     ) -> PersistenceResult<Option<TaskExecution>> {
-        // Get first pending task from queue
-        let end_key = self.queue_end_key();
-        let range = RangeOption {
-            begin: foundationdb::KeySelector::first_greater_or_equal(keys::TASK_QUEUE_PREFIX),
-            end: foundationdb::KeySelector::first_greater_or_equal(&end_key),
-            mode: foundationdb::options::StreamingMode::Small,
-            limit: Some(1),
-            reverse: false,
-            ..Default::default()
-        };
+        let now = Utc::now();
+        self.reclaim_expired_tx(tx, now).await?;
+        let lease_expires_at = lease_until(now, visibility_timeout);
 
-        let results = tx.get_range(&range, 1, false).await?;
-        
-        if results.is_empty() {
-            return Ok(None);
+        // Queues are listed as a snapshot so dequeues do not conflict with
+        // every enqueue opening a queue
+        let mut indexed: Vec<(RuntimeType, String)> = Vec::new();
+        for runtime in capabilities {
+            let begin_key = self.fair_queue_index_prefix(runtime);
+            let mut end_key = begin_key.clone();
+            end_key.push(0xff);
+            let range = RangeOption {
+                begin: foundationdb::KeySelector::first_greater_or_equal(&begin_key),
+                end: foundationdb::KeySelector::first_greater_or_equal(&end_key),
+                mode: foundationdb::options::StreamingMode::WantAll,
+                limit: Some(FAIR_QUEUE_SCAN_LIMIT),
+                reverse: false,
+                ..Default::default()
+            };
+
+            let results = tx.get_range(&range, 1, true).await?;
+            for kv in results.iter() {
+                let queue = String::from_utf8_lossy(&kv.key()[begin_key.len()..]).into_owned();
+                indexed.push((runtime.clone(), queue));
+            }
         }
 
-        let queue_key = &results[0].key();
-        let task_id_bytes = results[0].value();
-        let task_id_str = String::from_utf8_lossy(task_id_bytes.as_ref());
+        let mut queues: Vec<String> = indexed.iter().map(|(_, queue)| queue.clone()).collect();
+        queues.sort();
+        queues.dedup();
+
+        let ordered = order.order(&queues).into_iter().map(Some).chain(std::iter::once(None));
+        for queue in ordered {
+            let mut candidates: Vec<(usize, Vec<u8>, Vec<u8>)> = Vec::new();
+
+            for runtime in capabilities {
+                // Get the first pending tasks from the runtime's queue,
+                // skipping retries whose backoff has not elapsed yet
+                let begin_key = self.queue_prefix(runtime, queue.as_deref());
+                let end_key = self.queue_due_key(runtime, queue.as_deref(), now);
+                let range = RangeOption {
+                    begin: foundationdb::KeySelector::first_greater_or_equal(&begin_key),
+                    end: foundationdb::KeySelector::first_greater_or_equal(&end_key),
+                    mode: foundationdb::options::StreamingMode::Small,
+                    limit: Some(DEQUEUE_SCAN_LIMIT),
+                    reverse: false,
+                    ..Default::default()
+                };
+
+                let results = tx.get_range(&range, 1, false).await?;
+                if results.is_empty()
+                    && let Some(queue) = &queue
+                    && indexed.iter().any(|(r, q)| r == runtime && q == queue)
+                {
+                    self.close_drained_queue(tx, runtime, queue).await?;
+                }
+                for kv in results.iter() {
+                    candidates.push((begin_key.len(), kv.key().to_vec(), kv.value().to_vec()));
+                }
+            }
+
+            // Keys of different partitions compare by timestamp and task ID
+            candidates.sort_by(|(a_len, a, _), (b_len, b, _)| a[*a_len..].cmp(&b[*b_len..]));
+
+            for (_, queue_key, task_id_bytes) in candidates {
+                let taken = self
+                    .take_tx(tx, &queue_key, &task_id_bytes, worker_id, location, now, lease_expires_at)
+                    .await?;
+                if let Some(task) = taken {
+                    if let Some(queue) = &queue {
+                        order.served(queue, &queues);
+                    }
+                    return Ok(Some(task));
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Hand a queued task to a worker, `None` if it is not to be taken
+    async fn take_tx(
+        &self,
+        tx: &Transaction,
+        queue_key: &[u8],
+        task_id_bytes: &[u8],
+        worker_id: &WorkerId,
+        location: &Location,
+        now: DateTime<Utc>,
+        lease_expires_at: DateTime<Utc>,
+    ) -> PersistenceResult<Option<TaskExecution>> {
+        let task_id_str = String::from_utf8_lossy(task_id_bytes);
         let task_id = TaskId::from_uuid(
             uuid::Uuid::parse_str(&task_id_str)
                 .map_err(|e| PersistenceError::Corruption(format!("Invalid task ID: {}", e)))?
         );
 
         // Get task data
-        let task_key = build_key(keys::TASK_PREFIX, &task_id.to_string());
+        let task_key = self.keyspace.key(keys::TASK_PREFIX, &task_id.to_string());
         let task_bytes = tx.get(&task_key, false).await?
             .ok_or_else(|| PersistenceError::Corruption("Task data not found".to_string()))?;
         
         let mut task: TaskExecution = serde_json::from_slice(task_bytes.as_ref())?;
 
+        // Tasks cancelled without their queue time on record are dropped here
+        if task.status == TaskStatus::Cancelled {
+            tx.clear(queue_key);
+            return Ok(None);
+        }
+
+        // Tasks of paused workflows that got queued after the pause are held
+        // here; read as a snapshot so dequeues do not conflict with every
+        // change to the instance
+        let instance_key = self.keyspace.key(keys::WORKFLOW_PREFIX, &task.workflow_id.to_string());
+        let mut definition_id = None;
+        if let Some(bytes) = tx.get(&instance_key, true).await? {
+            let instance: WorkflowInstance = serde_json::from_slice(bytes.as_ref())?;
+            if instance.status == WorkflowStatus::Paused {
+                task.status = TaskStatus::Paused;
+                task.queued_at = None;
+                tx.set(&task_key, &serde_json::to_vec(&task)?);
+                tx.clear(queue_key);
+                return Ok(None);
+            }
+            definition_id = Some(instance.definition_id);
+        }
+
+        // Hold tasks back once their definition dispatched its share of
+        // the current second
+        let dispatch = match (task.max_dispatch_per_second, definition_id) {
+            (Some(limit), Some(definition_id)) => {
+                let rate_key = self.keyspace.key(keys::DISPATCH_RATE_PREFIX, &definition_id.to_string());
+                let second = now.timestamp();
+                let dispatched = tx
+                    .get(&rate_key, false)
+                    .await?
+                    .and_then(|bytes| parse_dispatch_window(&bytes))
+                    .filter(|(window, _)| *window == second)
+                    .map_or(0, |(_, count)| count);
+                if dispatched >= limit {
+                    return Ok(None);
+                }
+                Some((rate_key, second, dispatched + 1))
+            }
+            _ => None,
+        };
+
+        // Leave tasks placed elsewhere to workers there
+        let placed = match &task.definition.placement {
+            Some(placement) => {
+                let waited = task.queued_at.map_or(0, |at| (now - at).num_milliseconds().max(0) as u64);
+                match placement.decide(location, waited) {
+                    Some(placed) => Some(placed),
+                    None => return Ok(None),
+                }
+            }
+            None => None,
+        };
+
+        // Leave session tasks to the worker holding the session
+        if let Some(session) = &task.definition.session {
+            let bound = self.session_worker(tx, session, &task.definition.runtime_type).await?;
+            if bound.as_ref().is_some_and(|bound| bound != worker_id) {
+                return Ok(None);
+            }
+            if bound.is_none() {
+                let session_key = self.keyspace.key(keys::TASK_SESSION_PREFIX, session);
+                tx.set(&session_key, worker_id.as_str().as_bytes());
+            }
+        }
+
+        if let Some((rate_key, second, dispatched)) = dispatch {
+            let mut window = second.to_be_bytes().to_vec();
+            window.extend_from_slice(&dispatched.to_be_bytes());
+            tx.set(&rate_key, &window);
+        }
+
+        if let Some(placement) = placed {
+            let placed = HistoryEventKind::TaskPlaced {
+                task_id,
+                name: task.definition.name.clone(),
+                worker_id: worker_id.clone(),
+                location: location.clone(),
+                placement,
+            };
+            self.history.append_tx(tx, &task.workflow_id, placed).await?;
+        }
+
         // Update task status
         task.status = TaskStatus::Assigned;
         task.assigned_worker = Some(worker_id.clone());
         task.started_at = Some(Utc::now());
+        task.queued_at = None;
+        task.lease_expires_at = Some(lease_expires_at);
+        tx.set(&self.lease_key(lease_expires_at, &task_id), task_id.to_string().as_bytes());
 
         // Save updated task
         let updated_value = serde_json::to_vec(&task)?;
@@ -111,21 +460,166 @@ impl TaskStore {
         Ok(Some(task))
     }
 
+    /// Queue the tasks whose lease ran out by `now` again
+    ///
+    /// Leases are listed as a snapshot so dequeues do not conflict with every
+    /// task handed out; the tasks themselves are read normally, so a
+    /// completion or lease extension racing with the reclaim conflicts with
+    /// it.
+    async fn reclaim_expired_tx(&self, tx: &Transaction, now: DateTime<Utc>) -> PersistenceResult<()> {
+        let begin_key = self.keyspace.prefix(keys::TASK_LEASE_PREFIX);
+        let mut end_key = begin_key.clone();
+        end_key.extend_from_slice(&(now.timestamp_millis() + 1).to_be_bytes());
+        let range = RangeOption {
+            begin: foundationdb::KeySelector::first_greater_or_equal(&begin_key),
+            end: foundationdb::KeySelector::first_greater_or_equal(&end_key),
+            mode: foundationdb::options::StreamingMode::Small,
+            limit: Some(LEASE_SCAN_LIMIT),
+            reverse: false,
+            ..Default::default()
+        };
+
+        let results = tx.get_range(&range, 1, true).await?;
+        for kv in results.iter() {
+            tx.clear(kv.key());
+            let task_id_str = String::from_utf8_lossy(kv.value());
+            let task_id = TaskId::from_uuid(
+                uuid::Uuid::parse_str(&task_id_str)
+                    .map_err(|e| PersistenceError::Corruption(format!("Invalid task ID: {}", e)))?
+            );
+
+            // Leases extended since are kept under a later key
+            let Some(mut task) = self.get_tx(tx, &task_id).await? else {
+                continue;
+            };
+            let current = task.lease_expires_at.map(|at| self.lease_key(at, &task_id));
+            if current.as_deref() != Some(kv.key()) {
+                continue;
+            }
+            task.lease_expires_at = None;
+
+            if matches!(task.status, TaskStatus::Assigned | TaskStatus::Running) {
+                let error = match &task.assigned_worker {
+                    Some(worker_id) => format!("lease of worker {} expired", worker_id),
+                    None => "lease expired".to_string(),
+                };
+                self.requeue_lost_tx(tx, &mut task, error);
+            }
+
+            let task_key = self.keyspace.key(keys::TASK_PREFIX, &task_id.to_string());
+            tx.set(&task_key, &serde_json::to_vec(&task)?);
+        }
+        Ok(())
+    }
+
+    /// Return a task whose worker was lost to the queue
+    ///
+    /// The attempt counter is advanced to fence off a late completion from
+    /// the lost worker, without using up the task's retry budget.
+    fn requeue_lost_tx(&self, tx: &Transaction, task: &mut TaskExecution, error: String) {
+        self.clear_lease_tx(tx, task);
+        task.history.push(TaskAttempt {
+            attempt: task.attempt,
+            worker: task.assigned_worker.take(),
+            started_at: task.started_at.take(),
+            finished_at: Utc::now(),
+            success: false,
+            error: Some(error),
+            retry_at: None,
+        });
+        task.status = TaskStatus::Pending;
+        task.attempt += 1;
+        task.retry_from += 1;
+        let queued_at = Utc::now();
+        task.queued_at = Some(queued_at);
+
+        self.queue_tx(tx, task, queued_at);
+    }
+
+    /// Drop the lease of a task leaving its worker
+    fn clear_lease_tx(&self, tx: &Transaction, task: &mut TaskExecution) {
+        if let Some(expires_at) = task.lease_expires_at.take() {
+            tx.clear(&self.lease_key(expires_at, &task.id));
+        }
+    }
+
+    /// Drop a fair queue from the index once it has no tasks left
+    async fn close_drained_queue(
+        &self,
+        tx: &Transaction,
+        runtime: &RuntimeType,
+        queue: &str,
+    ) -> PersistenceResult<()> {
+        let begin_key = self.queue_prefix(runtime, Some(queue));
+        let mut end_key = begin_key.clone();
+        end_key.push(0xff);
+        let range = RangeOption {
+            begin: foundationdb::KeySelector::first_greater_or_equal(&begin_key),
+            end: foundationdb::KeySelector::first_greater_or_equal(&end_key),
+            mode: foundationdb::options::StreamingMode::Small,
+            limit: Some(1),
+            reverse: false,
+            ..Default::default()
+        };
+
+        if tx.get_range(&range, 1, false).await?.is_empty() {
+            let mut index_key = self.fair_queue_index_prefix(runtime);
+            index_key.extend_from_slice(queue.as_bytes());
+            tx.clear(&index_key);
+        }
+        Ok(())
+    }
+
+    /// Worker a session is bound to, if it can still take the session's tasks
+    ///
+    /// Sessions fall back to other workers once their worker is unhealthy,
+    /// draining, gone, or unable to run `runtime`.
+    async fn session_worker(
+        &self,
+        tx: &Transaction,
+        session: &str,
+        runtime: &RuntimeType,
+    ) -> PersistenceResult<Option<WorkerId>> {
+        let session_key = self.keyspace.key(keys::TASK_SESSION_PREFIX, session);
+        let Some(bytes) = tx.get(&session_key, false).await? else {
+            return Ok(None);
+        };
+        let worker_id = WorkerId::from_string(String::from_utf8_lossy(&bytes).to_string());
+
+        // Read as a snapshot so dequeues do not conflict with heartbeats
+        let worker_key = self.keyspace.key(keys::WORKER_PREFIX, worker_id.as_str());
+        let Some(worker_bytes) = tx.get(&worker_key, true).await? else {
+            return Ok(None);
+        };
+        let worker: WorkerInfo = serde_json::from_slice(worker_bytes.as_ref())?;
+        let available = worker.status == WorkerHealthStatus::Healthy
+            && !worker.draining
+            && worker.capabilities.contains(runtime);
+
+        Ok(available.then_some(worker_id))
+    }
+
     /// Mark task as completed
+    ///
+    /// A failed task whose retry policy allows another attempt is re-enqueued
+    /// after its backoff instead; the returned status tells which happened.
+    /// Failed tasks without retries left are moved to the dead-letter store.
+    #[tracing::instrument(level = "debug", name = "persistence.task.complete", skip_all)]
     pub async fn complete(
         &self,
         task_id: &TaskId,
         result: TaskResult,
-    ) -> PersistenceResult<()> {
+    ) -> PersistenceResult<TaskStatus> {
         let tx = self.db.create_trx()?;
         
         // Set transaction timeout to 2 seconds
         tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
         tx.set_option(foundationdb::options::TransactionOption::RetryLimit(5))?;
         
-        self.complete_tx(&tx, task_id, result).await?;
+        let status = self.complete_tx(&tx, task_id, result).await?;
         tx.commit().await?;
-        Ok(())
+        record_outcome(status);
+        Ok(status)
     }
 
     /// Mark task as completed within a transaction
@@ -134,28 +628,433 @@ impl TaskStore {
         tx: &Transaction,
         task_id: &TaskId,
         result: TaskResult,
-    ) -> PersistenceResult<()> {
-        let task_key = build_key(keys::TASK_PREFIX, &task_id.to_string());
+    ) -> PersistenceResult<TaskStatus> {
+        let task_key = self.keyspace.key(keys::TASK_PREFIX, &task_id.to_string());
         let task_bytes = tx.get(&task_key, false).await?
             .ok_or_else(|| PersistenceError::NotFound(task_id.to_string()))?;
         
         let mut task: TaskExecution = serde_json::from_slice(task_bytes.as_ref())?;
+        let finished_at = Utc::now();
+        let success = result.success;
 
-        task.status = if result.success {
-            TaskStatus::Completed
-        } else {
-            TaskStatus::Failed
+        // Work out when the next attempt may run, if the policy allows one
+        let retries = task.attempt.saturating_sub(task.retry_from);
+        let retry_at = match &task.definition.retry_policy {
+            Some(policy) if !success && policy.should_retry(retries) => {
+                let delay = policy.delay_with_jitter(retries, rand::random::<f64>());
+                let delay = chrono::Duration::from_std(delay).unwrap_or(chrono::Duration::MAX);
+                Some(finished_at.checked_add_signed(delay).unwrap_or(DateTime::<Utc>::MAX_UTC))
+            }
+            _ => None,
         };
-        task.completed_at = Some(Utc::now());
+
+        self.clear_lease_tx(tx, &mut task);
+        task.history.push(TaskAttempt {
+            attempt: task.attempt,
+            worker: task.assigned_worker.clone(),
+            started_at: task.started_at,
+            finished_at,
+            success,
+            error: result.error.clone(),
+            retry_at,
+        });
         task.result = Some(result);
 
+        if let Some(retry_at) = retry_at {
+            task.status = TaskStatus::Retrying;
+            task.assigned_worker = None;
+            task.started_at = None;
+            task.attempt += 1;
+            task.queued_at = Some(retry_at);
+
+            self.queue_tx(tx, &task, retry_at);
+        } else {
+            task.status = if success {
+                TaskStatus::Completed
+            } else {
+                TaskStatus::Failed
+            };
+            task.completed_at = Some(finished_at);
+        }
+
+        // Idempotent tasks leave their result for repeats to reuse
+        if task.status == TaskStatus::Completed
+            && let (Some(cache), Some(result)) = (&task.definition.cache, &task.result)
+        {
+            let expires_at = finished_at + chrono::Duration::milliseconds(cache.ttl_ms.min(i64::MAX as u64) as i64);
+            let cache_key = task.definition.cache_key(&task.input);
+            self.result_cache.put_tx(tx, &cache_key, result, expires_at).await?;
+        }
+
+        if task.status == TaskStatus::Failed {
+            let dead_letter = DeadLetter {
+                error: task.result.as_ref().and_then(|r| r.error.clone()),
+                failed_at: finished_at,
+                task: task.clone(),
+            };
+            let dead_letter_key = self.keyspace.key(keys::DEAD_LETTER_PREFIX, &task_id.to_string());
+            tx.set(&dead_letter_key, &serde_json::to_vec(&dead_letter)?);
+        }
+
+        let completed = HistoryEventKind::TaskCompleted {
+            task_id: *task_id,
+            name: task.definition.name.clone(),
+            status: task.status,
+            error: task.result.as_ref().and_then(|r| r.error.clone()),
+        };
+        self.history.append_tx(tx, &task.workflow_id, completed).await?;
+
         let updated_value = serde_json::to_vec(&task)?;
         tx.set(&task_key, &updated_value);
 
+        Ok(task.status)
+    }
+
+    /// Mark task as completed if `worker_id` still owns `attempt`
+    ///
+    /// Completions replayed by a worker that was offline are rejected with
+    /// `PersistenceError::Fenced` when the task has since been reassigned,
+    /// retried or finished. The key-value writes of a successful task are
+    /// committed with its completion; those of a failed one are dropped.
+    #[tracing::instrument(level = "debug", name = "persistence.task.complete_fenced", skip_all)]
+    pub async fn complete_fenced(
+        &self,
+        task_id: &TaskId,
+        worker_id: &WorkerId,
+        attempt: u32,
+        mut result: TaskResult,
+    ) -> PersistenceResult<TaskStatus> {
+        let tx = self.db.create_trx()?;
+        
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+        tx.set_option(foundationdb::options::TransactionOption::RetryLimit(5))?;
+        
+        let task = self.get_tx(&tx, task_id).await?
+            .ok_or_else(|| PersistenceError::NotFound(task_id.to_string()))?;
+
+        if task.assigned_worker.as_ref() != Some(worker_id) {
+            return Err(PersistenceError::Fenced(format!(
+                "task {} is not assigned to worker {}",
+                task_id, worker_id
+            )));
+        }
+        if task.attempt != attempt {
+            return Err(PersistenceError::Fenced(format!(
+                "task {} is on attempt {}, completion is for attempt {}",
+                task_id, task.attempt, attempt
+            )));
+        }
+        if !matches!(task.status, TaskStatus::Assigned | TaskStatus::Running) {
+            return Err(PersistenceError::Fenced(format!(
+                "task {} is already {:?}",
+                task_id, task.status
+            )));
+        }
+
+        let kv_writes = std::mem::take(&mut result.kv_writes);
+        if result.success {
+            self.kv.apply_tx(&tx, &task.workflow_id, &kv_writes);
+        }
+
+        let status = self.complete_tx(&tx, task_id, result).await?;
+        tx.commit().await?;
+        record_outcome(status);
+        Ok(status)
+    }
+
+    /// List permanently failed tasks, in task ID order
+    pub async fn list_dead_letter(&self, limit: usize) -> PersistenceResult<Vec<DeadLetter>> {
+        let tx = self.db.create_trx()?;
+
+        let begin_key = self.keyspace.prefix(keys::DEAD_LETTER_PREFIX);
+        let mut end_key = begin_key.clone();
+        end_key.push(0xff);
+        let range = RangeOption {
+            begin: foundationdb::KeySelector::first_greater_or_equal(&begin_key),
+            end: foundationdb::KeySelector::first_greater_or_equal(&end_key),
+            mode: foundationdb::options::StreamingMode::WantAll,
+            limit: Some(limit),
+            reverse: false,
+            ..Default::default()
+        };
+
+        let results = tx.get_range(&range, 1, false).await?;
+        tx.cancel();
+
+        let mut dead_letters = Vec::with_capacity(results.len());
+        for kv in results.iter() {
+            dead_letters.push(serde_json::from_slice(kv.value())?);
+        }
+        Ok(dead_letters)
+    }
+
+    /// Move a dead-lettered task back into the queue with a fresh retry budget
+    ///
+    /// The attempt counter keeps increasing so completions from earlier
+    /// attempts stay fenced off.
+    pub async fn requeue(&self, task_id: &TaskId) -> PersistenceResult<()> {
+        let tx = self.db.create_trx()?;
+        
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+        tx.set_option(foundationdb::options::TransactionOption::RetryLimit(5))?;
+        
+        let dead_letter_key = self.keyspace.key(keys::DEAD_LETTER_PREFIX, &task_id.to_string());
+        if tx.get(&dead_letter_key, false).await?.is_none() {
+            return Err(PersistenceError::NotFound(format!("dead letter {}", task_id)));
+        }
+
+        let mut task = self.get_tx(&tx, task_id).await?
+            .ok_or_else(|| PersistenceError::NotFound(task_id.to_string()))?;
+
+        task.status = TaskStatus::Pending;
+        task.assigned_worker = None;
+        task.started_at = None;
+        task.completed_at = None;
+        task.result = None;
+        task.attempt += 1;
+        task.retry_from = task.attempt;
+        let queued_at = Utc::now();
+        task.queued_at = Some(queued_at);
+
+        let task_key = self.keyspace.key(keys::TASK_PREFIX, &task_id.to_string());
+        tx.set(&task_key, &serde_json::to_vec(&task)?);
+        tx.clear(&dead_letter_key);
+
+        self.queue_tx(&tx, &task, queued_at);
+
+        tx.commit().await?;
         Ok(())
     }
 
+    /// List tasks ordered by ID, starting after `after`
+    pub async fn scan(&self, after: Option<&TaskId>, limit: usize) -> PersistenceResult<Vec<TaskExecution>> {
+        let tx = self.db.create_trx()?;
+
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+
+        let begin_key = self.keyspace.prefix(keys::TASK_PREFIX);
+        let begin = match after {
+            Some(id) => foundationdb::KeySelector::first_greater_than(self.keyspace.key(keys::TASK_PREFIX, &id.to_string())),
+            None => foundationdb::KeySelector::first_greater_or_equal(begin_key.clone()),
+        };
+        let mut end_key = begin_key;
+        end_key.push(0xff);
+        let range = RangeOption {
+            begin,
+            end: foundationdb::KeySelector::first_greater_or_equal(end_key),
+            mode: foundationdb::options::StreamingMode::WantAll,
+            limit: Some(limit),
+            reverse: false,
+            ..Default::default()
+        };
+
+        let results = tx.get_range(&range, 1, false).await?;
+        tx.cancel();
+
+        let mut tasks = Vec::with_capacity(results.len());
+        for kv in results.iter() {
+            tasks.push(serde_json::from_slice(kv.value())?);
+        }
+        Ok(tasks)
+    }
+
+    /// Return a task held by a lost worker to the queue
+    ///
+    /// Nothing happens unless the task is still assigned to `worker_id`, so a
+    /// completion racing with recovery wins. Returns whether the task was
+    /// released.
+    pub async fn release(&self, task_id: &TaskId, worker_id: &WorkerId) -> PersistenceResult<bool> {
+        let tx = self.db.create_trx()?;
+        
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+        tx.set_option(foundationdb::options::TransactionOption::RetryLimit(5))?;
+        
+        let Some(mut task) = self.get_tx(&tx, task_id).await? else {
+            return Ok(false);
+        };
+        let in_flight = matches!(task.status, TaskStatus::Assigned | TaskStatus::Running);
+        if !in_flight || task.assigned_worker.as_ref() != Some(worker_id) {
+            return Ok(false);
+        }
+
+        let error = format!("worker {} stopped sending heartbeats", worker_id);
+        self.requeue_lost_tx(&tx, &mut task, error);
+
+        let task_key = self.keyspace.key(keys::TASK_PREFIX, &task_id.to_string());
+        tx.set(&task_key, &serde_json::to_vec(&task)?);
+
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    /// Cancel a task that has not finished
+    ///
+    /// A queued task is taken out of the queue and an unschedulable one out of
+    /// the dead-letter store. A task held by a worker is only marked; the
+    /// worker learns of it with its next heartbeat and the engine rejects a
+    /// late completion. Returns whether the task was cancelled.
+    pub async fn cancel(&self, task_id: &TaskId) -> PersistenceResult<bool> {
+        let tx = self.db.create_trx()?;
+        
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+        tx.set_option(foundationdb::options::TransactionOption::RetryLimit(5))?;
+        
+        let Some(mut task) = self.get_tx(&tx, task_id).await? else {
+            return Ok(false);
+        };
+        match task.status {
+            TaskStatus::Pending | TaskStatus::Retrying => {
+                if let Some(queued_at) = task.queued_at.take() {
+                    tx.clear(&self.build_queue_key(&task, queued_at));
+                }
+            }
+            TaskStatus::Unschedulable => {
+                tx.clear(&self.keyspace.key(keys::DEAD_LETTER_PREFIX, &task_id.to_string()));
+            }
+            TaskStatus::Assigned | TaskStatus::Running | TaskStatus::Paused => {}
+            TaskStatus::Completed | TaskStatus::Failed | TaskStatus::Cancelled => return Ok(false),
+        }
+        task.status = TaskStatus::Cancelled;
+        task.completed_at = Some(Utc::now());
+
+        let task_key = self.keyspace.key(keys::TASK_PREFIX, &task_id.to_string());
+        tx.set(&task_key, &serde_json::to_vec(&task)?);
+
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    /// Take a queued task out of the queue while its workflow is paused
+    ///
+    /// Tasks held by a worker are left to finish. Returns whether the task
+    /// was held.
+    pub async fn hold(&self, task_id: &TaskId) -> PersistenceResult<bool> {
+        let tx = self.db.create_trx()?;
+        
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+        tx.set_option(foundationdb::options::TransactionOption::RetryLimit(5))?;
+        
+        let Some(mut task) = self.get_tx(&tx, task_id).await? else {
+            return Ok(false);
+        };
+        if !matches!(task.status, TaskStatus::Pending | TaskStatus::Retrying) {
+            return Ok(false);
+        }
+        // Queued before the queue time was recorded; dequeue holds it instead
+        let Some(queued_at) = task.queued_at.take() else {
+            return Ok(false);
+        };
+        tx.clear(&self.build_queue_key(&task, queued_at));
+        task.status = TaskStatus::Paused;
+
+        let task_key = self.keyspace.key(keys::TASK_PREFIX, &task_id.to_string());
+        tx.set(&task_key, &serde_json::to_vec(&task)?);
+
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    /// Return a held task to the queue, returning whether it was held
+    pub async fn unhold(&self, task_id: &TaskId) -> PersistenceResult<bool> {
+        let tx = self.db.create_trx()?;
+        
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+        tx.set_option(foundationdb::options::TransactionOption::RetryLimit(5))?;
+        
+        let Some(mut task) = self.get_tx(&tx, task_id).await? else {
+            return Ok(false);
+        };
+        if task.status != TaskStatus::Paused {
+            return Ok(false);
+        }
+        let queued_at = Utc::now();
+        task.status = TaskStatus::Pending;
+        task.queued_at = Some(queued_at);
+
+        let task_key = self.keyspace.key(keys::TASK_PREFIX, &task_id.to_string());
+        tx.set(&task_key, &serde_json::to_vec(&task)?);
+
+        self.queue_tx(&tx, &task, queued_at);
+
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    /// Pick the cancelled tasks out of `task_ids`
+    pub async fn cancelled(&self, task_ids: &[TaskId]) -> PersistenceResult<Vec<TaskId>> {
+        let tx = self.db.create_trx()?;
+
+        let mut cancelled = Vec::new();
+        for task_id in task_ids {
+            let task = self.get_tx(&tx, task_id).await?;
+            if task.is_some_and(|task| task.status == TaskStatus::Cancelled) {
+                cancelled.push(*task_id);
+            }
+        }
+        tx.cancel();
+        Ok(cancelled)
+    }
+
+    /// Extend the leases of the tasks a worker reports running
+    ///
+    /// Returns the tasks the worker no longer holds because their lease ran
+    /// out and they were queued again, so the worker can abort them.
+    #[tracing::instrument(level = "debug", name = "persistence.task.extend_leases", skip_all)]
+    pub async fn extend_leases(
+        &self,
+        worker_id: &WorkerId,
+        task_ids: &[TaskId],
+        visibility_timeout: Duration,
+    ) -> PersistenceResult<Vec<TaskId>> {
+        if task_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let tx = self.db.create_trx()?;
+        
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+        tx.set_option(foundationdb::options::TransactionOption::RetryLimit(5))?;
+        
+        let expires_at = lease_until(Utc::now(), visibility_timeout);
+        let mut lost = Vec::new();
+        for task_id in task_ids {
+            let Some(mut task) = self.get_tx(&tx, task_id).await? else {
+                continue;
+            };
+            let in_flight = matches!(task.status, TaskStatus::Assigned | TaskStatus::Running);
+            if !in_flight || task.assigned_worker.as_ref() != Some(worker_id) {
+                // Finished and cancelled tasks are not the lease's doing
+                if matches!(
+                    task.status,
+                    TaskStatus::Pending | TaskStatus::Retrying | TaskStatus::Assigned | TaskStatus::Running
+                ) {
+                    lost.push(*task_id);
+                }
+                continue;
+            }
+
+            self.clear_lease_tx(&tx, &mut task);
+            task.lease_expires_at = Some(expires_at);
+            tx.set(&self.lease_key(expires_at, task_id), task_id.to_string().as_bytes());
+
+            let task_key = self.keyspace.key(keys::TASK_PREFIX, &task_id.to_string());
+            tx.set(&task_key, &serde_json::to_vec(&task)?);
+        }
+
+        tx.commit().await?;
+        Ok(lost)
+    }
+
     /// Get a task by ID
+    #[tracing::instrument(level = "debug", name = "persistence.task.get", skip_all)]
     pub async fn get(&self, task_id: &TaskId) -> PersistenceResult<Option<TaskExecution>> {
         let tx = self.db.create_trx()?;
         let result = self.get_tx(&tx, task_id).await?;
@@ -169,7 +1068,7 @@ impl TaskStore {
         tx: &Transaction,
         task_id: &TaskId,
     ) -> PersistenceResult<Option<TaskExecution>> {
-        let task_key = build_key(keys::TASK_PREFIX, &task_id.to_string());
+        let task_key = self.keyspace.key(keys::TASK_PREFIX, &task_id.to_string());
         let bytes = tx.get(&task_key, false).await?;
         
         match bytes {
@@ -189,42 +1088,104 @@ impl TaskStore {
         tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
         tx.set_option(foundationdb::options::TransactionOption::RetryLimit(5))?;
         
-        let task_key = build_key(keys::TASK_PREFIX, &task_id.to_string());
+        let task_key = self.keyspace.key(keys::TASK_PREFIX, &task_id.to_string());
         let task_bytes = tx.get(&task_key, false).await?
             .ok_or_else(|| PersistenceError::NotFound(task_id.to_string()))?;
         
         let mut task: TaskExecution = serde_json::from_slice(task_bytes.as_ref())?;
 
+        self.clear_lease_tx(&tx, &mut task);
         task.status = TaskStatus::Pending;
         task.assigned_worker = None;
         task.attempt += 1;
+        let queued_at = Utc::now();
+        task.queued_at = Some(queued_at);
 
         let updated_value = serde_json::to_vec(&task)?;
         tx.set(&task_key, &updated_value);
 
         // Re-add to queue
-        let queue_key = self.build_queue_key(task_id);
-        tx.set(&queue_key, &task_id.to_string().as_bytes());
+        self.queue_tx(&tx, &task, queued_at);
 
         tx.commit().await?;
         Ok(())
     }
 
-    /// Build queue key with timestamp for ordering
-    fn build_queue_key(&self, task_id: &TaskId) -> Vec<u8> {
-        let timestamp = Utc::now().timestamp_millis();
-        let mut key = Vec::new();
-        key.extend_from_slice(keys::TASK_QUEUE_PREFIX);
+    /// Get the prefix of a queue in a runtime's partition, the shared
+    /// queue of tasks enqueued before fair scheduling for `None`
+    fn queue_prefix(&self, runtime: &RuntimeType, fair_queue: Option<&str>) -> Vec<u8> {
+        let mut key = match fair_queue {
+            Some(_) => self.keyspace.prefix(keys::FAIR_QUEUE_PREFIX),
+            None => self.keyspace.prefix(keys::TASK_QUEUE_PREFIX),
+        };
+        key.extend_from_slice(runtime.as_str().as_bytes());
+        key.push(b':');
+        if let Some(queue) = fair_queue {
+            key.extend_from_slice(queue.as_bytes());
+            key.push(0);
+        }
+        key
+    }
+
+    /// Get the prefix of the index of a runtime's fair queues
+    fn fair_queue_index_prefix(&self, runtime: &RuntimeType) -> Vec<u8> {
+        let mut key = self.keyspace.prefix(keys::FAIR_QUEUE_INDEX_PREFIX);
+        key.extend_from_slice(runtime.as_str().as_bytes());
+        key.push(b':');
+        key
+    }
+
+    /// Build queue key ordered by the time the task becomes available
+    fn build_queue_key(&self, task: &TaskExecution, available_at: DateTime<Utc>) -> Vec<u8> {
+        let timestamp = available_at.timestamp_millis();
+        let mut key = self.queue_prefix(&task.definition.runtime_type, task.fair_queue.as_deref());
         key.extend_from_slice(&timestamp.to_be_bytes());
+        key.extend_from_slice(task.id.to_string().as_bytes());
+        key
+    }
+
+    /// Queue a task to become available at `available_at`
+    fn queue_tx(&self, tx: &Transaction, task: &TaskExecution, available_at: DateTime<Utc>) {
+        tx.set(&self.build_queue_key(task, available_at), task.id.to_string().as_bytes());
+        if let Some(queue) = &task.fair_queue {
+            let mut index_key = self.fair_queue_index_prefix(&task.definition.runtime_type);
+            index_key.extend_from_slice(queue.as_bytes());
+            tx.set(&index_key, b"");
+        }
+    }
+
+    /// Build lease key ordered by the time the lease runs out
+    fn lease_key(&self, expires_at: DateTime<Utc>, task_id: &TaskId) -> Vec<u8> {
+        let mut key = self.keyspace.prefix(keys::TASK_LEASE_PREFIX);
+        key.extend_from_slice(&expires_at.timestamp_millis().to_be_bytes());
         key.extend_from_slice(task_id.to_string().as_bytes());
         key
     }
 
-    /// Get the end key for queue range scans
-    fn queue_end_key(&self) -> Vec<u8> {
-        let mut key = keys::TASK_QUEUE_PREFIX.to_vec();
-        key.push(0xff);
+    /// Get the end key for scans over a queue's tasks available at `now`
+    fn queue_due_key(&self, runtime: &RuntimeType, fair_queue: Option<&str>, now: DateTime<Utc>) -> Vec<u8> {
+        let mut key = self.queue_prefix(runtime, fair_queue);
+        key.extend_from_slice(&(now.timestamp_millis() + 1).to_be_bytes());
         key
     }
 }
 
+/// Time a lease of `visibility_timeout` taken at `now` runs out
+fn lease_until(now: DateTime<Utc>, visibility_timeout: Duration) -> DateTime<Utc> {
+    let timeout = chrono::Duration::from_std(visibility_timeout).unwrap_or(chrono::Duration::MAX);
+    now.checked_add_signed(timeout).unwrap_or(DateTime::<Utc>::MAX_UTC)
+}
+
+/// Split a dispatch rate entry into its second and the tasks dispatched in it
+fn parse_dispatch_window(bytes: &[u8]) -> Option<(i64, u32)> {
+    let (second, count) = bytes.split_first_chunk::<8>()?;
+    let count = <[u8; 4]>::try_from(count).ok()?;
+    Some((i64::from_be_bytes(*second), u32::from_be_bytes(count)))
+}
+
+/// Emit metrics for a committed task completion
+fn record_outcome(status: TaskStatus) {
+    if status == TaskStatus::Failed {
+        metrics::counter!(DEAD_LETTERED_METRIC).increment(1);
+    }
+}
@@ -0,0 +1,315 @@
+//! Durable timer persistence
+
+use super::{keys, Keyspace};
+use crate::error::PersistenceResult;
+use crate::types::{ScheduledTimer, WorkflowId};
+use chrono::{DateTime, Duration, Utc};
+use foundationdb::{Database, RangeOption, Transaction};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// A timer taken off the schedule while its workflow is paused
+#[derive(Serialize, Deserialize)]
+struct Suspended {
+    timer: ScheduledTimer,
+    /// Time left until the deadline when the timer was suspended
+    remaining_ms: i64,
+}
+
+/// Timer storage operations
+///
+/// Timers are keyed by their deadline so that due timers can be found with a
+/// single range scan from the start of the keyspace, and indexed by workflow
+/// so that the timers of one instance are found without scanning the rest.
+#[derive(Clone)]
+pub struct TimerStore {
+    db: Arc<Database>,
+    keyspace: Keyspace,
+}
+
+impl TimerStore {
+    pub fn new(db: Arc<Database>, keyspace: Keyspace) -> Self {
+        Self { db, keyspace }
+    }
+
+    /// Schedule a timer
+    pub async fn schedule(&self, timer: &ScheduledTimer) -> PersistenceResult<()> {
+        let tx = self.db.create_trx()?;
+
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+        tx.set_option(foundationdb::options::TransactionOption::RetryLimit(5))?;
+
+        self.schedule_tx(&tx, timer).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Schedule a timer within a transaction
+    pub async fn schedule_tx(&self, tx: &Transaction, timer: &ScheduledTimer) -> PersistenceResult<()> {
+        let value = serde_json::to_vec(timer)?;
+        tx.set(&self.build_timer_key(timer), &value);
+        tx.set(&self.build_workflow_key(timer), &value);
+        Ok(())
+    }
+
+    /// Take a timer off the schedule within a transaction
+    fn unschedule_tx(&self, tx: &Transaction, timer: &ScheduledTimer) {
+        tx.clear(&self.build_timer_key(timer));
+        tx.clear(&self.build_workflow_key(timer));
+    }
+
+    /// List the scheduled timers of a workflow
+    ///
+    /// Timers scheduled before the workflow index existed are not listed;
+    /// they are still suspended when they come due while the workflow is
+    /// paused.
+    pub async fn for_workflow(&self, workflow_id: &WorkflowId) -> PersistenceResult<Vec<ScheduledTimer>> {
+        let tx = self.db.create_trx()?;
+
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+
+        let begin_key = self.build_workflow_prefix(workflow_id);
+        let mut end_key = begin_key.clone();
+        end_key.push(0xff);
+        let range = RangeOption {
+            begin: foundationdb::KeySelector::first_greater_or_equal(&begin_key),
+            end: foundationdb::KeySelector::first_greater_or_equal(&end_key),
+            mode: foundationdb::options::StreamingMode::WantAll,
+            reverse: false,
+            ..Default::default()
+        };
+
+        let results = tx.get_range(&range, 1, false).await?;
+        tx.cancel();
+
+        let mut timers = Vec::with_capacity(results.len());
+        for kv in results.iter() {
+            timers.push(serde_json::from_slice(kv.value())?);
+        }
+        Ok(timers)
+    }
+
+    /// List timers whose deadline is at or before `now`, oldest first
+    pub async fn due(&self, now: DateTime<Utc>, limit: usize) -> PersistenceResult<Vec<ScheduledTimer>> {
+        let tx = self.db.create_trx()?;
+
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+
+        // Everything strictly below the first key of the next millisecond is due
+        let begin_key = self.keyspace.prefix(keys::TIMER_PREFIX);
+        let mut end_key = begin_key.clone();
+        end_key.extend_from_slice(&(now.timestamp_millis() + 1).to_be_bytes());
+
+        let range = RangeOption {
+            begin: foundationdb::KeySelector::first_greater_or_equal(&begin_key),
+            end: foundationdb::KeySelector::first_greater_or_equal(&end_key),
+            mode: foundationdb::options::StreamingMode::WantAll,
+            limit: Some(limit),
+            reverse: false,
+            ..Default::default()
+        };
+
+        let results = tx.get_range(&range, 1, false).await?;
+        tx.cancel();
+
+        let mut timers = Vec::with_capacity(results.len());
+        for kv in results.iter() {
+            timers.push(serde_json::from_slice(kv.value())?);
+        }
+        Ok(timers)
+    }
+
+    /// List all timers in deadline order, starting after `after`
+    pub async fn list(&self, after: Option<&ScheduledTimer>, limit: usize) -> PersistenceResult<Vec<ScheduledTimer>> {
+        let tx = self.db.create_trx()?;
+
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+
+        let begin_key = self.keyspace.prefix(keys::TIMER_PREFIX);
+        let begin = match after {
+            Some(timer) => foundationdb::KeySelector::first_greater_than(self.build_timer_key(timer)),
+            None => foundationdb::KeySelector::first_greater_or_equal(begin_key.clone()),
+        };
+        let mut end_key = begin_key;
+        end_key.push(0xff);
+        let range = RangeOption {
+            begin,
+            end: foundationdb::KeySelector::first_greater_or_equal(end_key),
+            mode: foundationdb::options::StreamingMode::WantAll,
+            limit: Some(limit),
+            reverse: false,
+            ..Default::default()
+        };
+
+        let results = tx.get_range(&range, 1, false).await?;
+        tx.cancel();
+
+        let mut timers = Vec::with_capacity(results.len());
+        for kv in results.iter() {
+            timers.push(serde_json::from_slice(kv.value())?);
+        }
+        Ok(timers)
+    }
+
+    /// Lease a due timer until `until`, returning the leased timer when it
+    /// was still scheduled
+    ///
+    /// The timer is moved to fire again at `until`, so only the caller that
+    /// gets it back fires it, and a timer whose firing never finished, e.g.
+    /// because the engine crashed, fires again once the lease runs out. The
+    /// caller removes the leased timer once the firing has been applied.
+    pub async fn claim(
+        &self,
+        timer: &ScheduledTimer,
+        until: DateTime<Utc>,
+    ) -> PersistenceResult<Option<ScheduledTimer>> {
+        let tx = self.db.create_trx()?;
+
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+        tx.set_option(foundationdb::options::TransactionOption::RetryLimit(5))?;
+
+        let key = self.build_timer_key(timer);
+        if tx.get(&key, false).await?.is_none() {
+            tx.cancel();
+            return Ok(None);
+        }
+        self.unschedule_tx(&tx, timer);
+
+        let mut leased = timer.clone();
+        leased.fire_at = until;
+        self.schedule_tx(&tx, &leased).await?;
+
+        tx.commit().await?;
+        Ok(Some(leased))
+    }
+
+    /// Remove a timer
+    pub async fn remove(&self, timer: &ScheduledTimer) -> PersistenceResult<()> {
+        let tx = self.db.create_trx()?;
+
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+        tx.set_option(foundationdb::options::TransactionOption::RetryLimit(5))?;
+
+        self.unschedule_tx(&tx, timer);
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Take a timer off the schedule, keeping the time it had left at `at`
+    ///
+    /// Returns whether the timer was still scheduled.
+    pub async fn suspend(&self, timer: &ScheduledTimer, at: DateTime<Utc>) -> PersistenceResult<bool> {
+        let tx = self.db.create_trx()?;
+
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+        tx.set_option(foundationdb::options::TransactionOption::RetryLimit(5))?;
+
+        let key = self.build_timer_key(timer);
+        if tx.get(&key, false).await?.is_none() {
+            tx.cancel();
+            return Ok(false);
+        }
+        self.unschedule_tx(&tx, timer);
+
+        let suspended = Suspended {
+            timer: timer.clone(),
+            remaining_ms: (timer.fire_at - at).num_milliseconds().max(0),
+        };
+        tx.set(&self.build_suspended_key(timer), &serde_json::to_vec(&suspended)?);
+
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    /// Put the suspended timers of a workflow back on the schedule
+    ///
+    /// Each timer fires once the time it had left when suspended has passed
+    /// again from `now`. Returns the number of timers re-armed.
+    pub async fn resume(&self, workflow_id: &WorkflowId, now: DateTime<Utc>) -> PersistenceResult<usize> {
+        let tx = self.db.create_trx()?;
+
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+        tx.set_option(foundationdb::options::TransactionOption::RetryLimit(5))?;
+
+        let mut begin_key = self.keyspace.prefix(keys::SUSPENDED_TIMER_PREFIX);
+        begin_key.extend_from_slice(workflow_id.to_string().as_bytes());
+        begin_key.push(b':');
+        let mut end_key = begin_key.clone();
+        end_key.push(0xff);
+        let range = RangeOption {
+            begin: foundationdb::KeySelector::first_greater_or_equal(&begin_key),
+            end: foundationdb::KeySelector::first_greater_or_equal(&end_key),
+            mode: foundationdb::options::StreamingMode::WantAll,
+            reverse: false,
+            ..Default::default()
+        };
+
+        let results = tx.get_range(&range, 1, false).await?;
+        for kv in results.iter() {
+            let suspended: Suspended = serde_json::from_slice(kv.value())?;
+            let mut timer = suspended.timer;
+            timer.fire_at = now + Duration::milliseconds(suspended.remaining_ms);
+            self.schedule_tx(&tx, &timer).await?;
+        }
+        tx.clear_range(&begin_key, &end_key);
+
+        tx.commit().await?;
+        Ok(results.len())
+    }
+
+    /// Build the key of a suspended timer, grouped by workflow
+    fn build_suspended_key(&self, timer: &ScheduledTimer) -> Vec<u8> {
+        let mut key = self.keyspace.prefix(keys::SUSPENDED_TIMER_PREFIX);
+        key.extend_from_slice(timer.workflow_id.to_string().as_bytes());
+        key.push(b':');
+        key.extend_from_slice(&timer.fire_at.timestamp_millis().to_be_bytes());
+        if let Some(branch) = &timer.branch {
+            key.extend_from_slice(branch.as_bytes());
+            key.push(b':');
+        }
+        key.extend_from_slice(timer.event.as_bytes());
+        key
+    }
+
+    /// Build timer key ordered by deadline
+    fn build_timer_key(&self, timer: &ScheduledTimer) -> Vec<u8> {
+        let mut key = self.keyspace.prefix(keys::TIMER_PREFIX);
+        key.extend_from_slice(&timer.fire_at.timestamp_millis().to_be_bytes());
+        key.extend_from_slice(timer.workflow_id.to_string().as_bytes());
+        key.push(b':');
+        if let Some(branch) = &timer.branch {
+            key.extend_from_slice(branch.as_bytes());
+            key.push(b':');
+        }
+        key.extend_from_slice(timer.event.as_bytes());
+        key
+    }
+
+    /// Build the start of the index keys of a workflow's timers
+    fn build_workflow_prefix(&self, workflow_id: &WorkflowId) -> Vec<u8> {
+        let mut key = self.keyspace.prefix(keys::TIMER_BY_WORKFLOW_PREFIX);
+        key.extend_from_slice(workflow_id.to_string().as_bytes());
+        key.push(b':');
+        key
+    }
+
+    /// Build the index key of a timer, grouped by workflow
+    fn build_workflow_key(&self, timer: &ScheduledTimer) -> Vec<u8> {
+        let mut key = self.build_workflow_prefix(&timer.workflow_id);
+        key.extend_from_slice(&timer.fire_at.timestamp_millis().to_be_bytes());
+        if let Some(branch) = &timer.branch {
+            key.extend_from_slice(branch.as_bytes());
+            key.push(b':');
+        }
+        key.extend_from_slice(timer.event.as_bytes());
+        key
+    }
+}
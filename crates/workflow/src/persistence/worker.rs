@@ -1,21 +1,22 @@
 //! Worker persistence
 
-use super::{build_key, keys};
+use super::{keys, Keyspace};
 use crate::error::PersistenceResult;
-use crate::types::{WorkerHealthStatus, WorkerInfo, WorkerId};
-use chrono::Utc;
-use foundationdb::{Database, Transaction};
+use crate::types::{WorkerHealthStatus, WorkerInfo, WorkerId, WorkerStats};
+use chrono::{DateTime, Utc};
+use foundationdb::{Database, RangeOption, Transaction};
 use std::sync::Arc;
 
 /// Worker storage operations
 #[derive(Clone)]
 pub struct WorkerStore {
     db: Arc<Database>,
+    keyspace: Keyspace,
 }
 
 impl WorkerStore {
-    pub fn new(db: Arc<Database>) -> Self {
-        Self { db }
+    pub fn new(db: Arc<Database>, keyspace: Keyspace) -> Self {
+        Self { db, keyspace }
     }
 
     /// Register a worker
@@ -35,12 +36,12 @@ impl WorkerStore {
 
     /// Register a worker within a transaction
     pub async fn register_tx(&self, tx: &Transaction, worker: WorkerInfo) -> PersistenceResult<()> {
-        let key = build_key(keys::WORKER_PREFIX, worker.id.as_str());
+        let key = self.keyspace.key(keys::WORKER_PREFIX, worker.id.as_str());
         let value = serde_json::to_vec(&worker)?;
         tx.set(&key, &value);
 
         // Set heartbeat timestamp
-        let heartbeat_key = build_key(keys::WORKER_HEARTBEAT_PREFIX, worker.id.as_str());
+        let heartbeat_key = self.keyspace.key(keys::WORKER_HEARTBEAT_PREFIX, worker.id.as_str());
         let timestamp = Utc::now().timestamp_millis().to_be_bytes();
         tx.set(&heartbeat_key, &timestamp);
 
@@ -61,7 +62,7 @@ impl WorkerStore {
         tx: &Transaction,
         worker_id: &WorkerId,
     ) -> PersistenceResult<Option<WorkerInfo>> {
-        let key = build_key(keys::WORKER_PREFIX, worker_id.as_str());
+        let key = self.keyspace.key(keys::WORKER_PREFIX, worker_id.as_str());
         let bytes = tx.get(&key, false).await?;
         
         match bytes {
@@ -81,12 +82,12 @@ impl WorkerStore {
         tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
         tx.set_option(foundationdb::options::TransactionOption::RetryLimit(3))?;
         
-        let heartbeat_key = build_key(keys::WORKER_HEARTBEAT_PREFIX, worker_id.as_str());
+        let heartbeat_key = self.keyspace.key(keys::WORKER_HEARTBEAT_PREFIX, worker_id.as_str());
         let timestamp = Utc::now().timestamp_millis().to_be_bytes();
         tx.set(&heartbeat_key, &timestamp);
 
         // Update worker record
-        let worker_key = build_key(keys::WORKER_PREFIX, worker_id.as_str());
+        let worker_key = self.keyspace.key(keys::WORKER_PREFIX, worker_id.as_str());
         if let Some(worker_bytes) = tx.get(&worker_key, false).await? {
             let mut worker: WorkerInfo = serde_json::from_slice(worker_bytes.as_ref())?;
             worker.last_heartbeat = Utc::now();
@@ -100,26 +101,112 @@ impl WorkerStore {
         Ok(())
     }
 
-    /// Update worker statistics
-    pub async fn update_stats(
+    /// Set a worker's health status unless it sent a heartbeat after
+    /// `last_heartbeat`, returning whether it was set
+    ///
+    /// The worker's next heartbeat marks it healthy again.
+    pub async fn set_status(
         &self,
         worker_id: &WorkerId,
-        active_tasks: u32,
-        total_completed: u64,
-        total_failed: u64,
-    ) -> PersistenceResult<()> {
+        status: WorkerHealthStatus,
+        last_heartbeat: DateTime<Utc>,
+    ) -> PersistenceResult<bool> {
+        let tx = self.db.create_trx()?;
+        
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+        tx.set_option(foundationdb::options::TransactionOption::RetryLimit(3))?;
+        
+        let worker_key = self.keyspace.key(keys::WORKER_PREFIX, worker_id.as_str());
+        let Some(worker_bytes) = tx.get(&worker_key, false).await? else {
+            return Ok(false);
+        };
+        let mut worker: WorkerInfo = serde_json::from_slice(worker_bytes.as_ref())?;
+        if worker.last_heartbeat != last_heartbeat {
+            return Ok(false);
+        }
+        worker.status = status;
+
+        let updated_value = serde_json::to_vec(&worker)?;
+        tx.set(&worker_key, &updated_value);
+
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    /// Mark a worker as draining, returning whether it is registered
+    pub async fn drain(&self, worker_id: &WorkerId) -> PersistenceResult<bool> {
+        let tx = self.db.create_trx()?;
+        
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+        tx.set_option(foundationdb::options::TransactionOption::RetryLimit(3))?;
+        
+        let worker_key = self.keyspace.key(keys::WORKER_PREFIX, worker_id.as_str());
+        let Some(worker_bytes) = tx.get(&worker_key, false).await? else {
+            tx.cancel();
+            return Ok(false);
+        };
+        let mut worker: WorkerInfo = serde_json::from_slice(worker_bytes.as_ref())?;
+        worker.draining = true;
+        tx.set(&worker_key, &serde_json::to_vec(&worker)?);
+
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    /// Remove a worker's registration
+    pub async fn remove(&self, worker_id: &WorkerId) -> PersistenceResult<()> {
+        let tx = self.db.create_trx()?;
+        
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+        tx.set_option(foundationdb::options::TransactionOption::RetryLimit(3))?;
+        
+        tx.clear(&self.keyspace.key(keys::WORKER_PREFIX, worker_id.as_str()));
+        tx.clear(&self.keyspace.key(keys::WORKER_HEARTBEAT_PREFIX, worker_id.as_str()));
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// List all registered workers
+    pub async fn list(&self) -> PersistenceResult<Vec<WorkerInfo>> {
+        let tx = self.db.create_trx()?;
+
+        let begin_key = self.keyspace.prefix(keys::WORKER_PREFIX);
+        let mut end_key = begin_key.clone();
+        end_key.push(0xff);
+        let range = RangeOption {
+            begin: foundationdb::KeySelector::first_greater_or_equal(&begin_key),
+            end: foundationdb::KeySelector::first_greater_or_equal(&end_key),
+            mode: foundationdb::options::StreamingMode::WantAll,
+            reverse: false,
+            ..Default::default()
+        };
+
+        let results = tx.get_range(&range, 1, false).await?;
+        tx.cancel();
+
+        let mut workers = Vec::with_capacity(results.len());
+        for kv in results.iter() {
+            workers.push(serde_json::from_slice(kv.value())?);
+        }
+        Ok(workers)
+    }
+
+    /// Update worker statistics
+    pub async fn update_stats(&self, worker_id: &WorkerId, stats: &WorkerStats) -> PersistenceResult<()> {
         let tx = self.db.create_trx()?;
         
         // Set transaction timeout to 2 seconds
         tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
         tx.set_option(foundationdb::options::TransactionOption::RetryLimit(3))?;
         
-        let worker_key = build_key(keys::WORKER_PREFIX, worker_id.as_str());
+        let worker_key = self.keyspace.key(keys::WORKER_PREFIX, worker_id.as_str());
         if let Some(worker_bytes) = tx.get(&worker_key, false).await? {
             let mut worker: WorkerInfo = serde_json::from_slice(worker_bytes.as_ref())?;
-            worker.stats.active_tasks = active_tasks;
-            worker.stats.total_tasks_completed = total_completed;
-            worker.stats.total_tasks_failed = total_failed;
+            worker.stats = stats.clone();
             
             let updated_value = serde_json::to_vec(&worker)?;
             tx.set(&worker_key, &updated_value);
@@ -137,10 +224,10 @@ impl WorkerStore {
         tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
         tx.set_option(foundationdb::options::TransactionOption::RetryLimit(3))?;
         
-        let worker_key = build_key(keys::WORKER_PREFIX, worker_id.as_str());
+        let worker_key = self.keyspace.key(keys::WORKER_PREFIX, worker_id.as_str());
         tx.clear(&worker_key);
         
-        let heartbeat_key = build_key(keys::WORKER_HEARTBEAT_PREFIX, worker_id.as_str());
+        let heartbeat_key = self.keyspace.key(keys::WORKER_HEARTBEAT_PREFIX, worker_id.as_str());
         tx.clear(&heartbeat_key);
 
         tx.commit().await?;
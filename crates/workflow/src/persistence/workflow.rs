@@ -1,21 +1,72 @@
 //! Workflow persistence
 
-use super::{build_key, keys};
+use super::{keys, AnnotationStore, ChangeFeedStore, HistoryStore, Keyspace, KvStore};
 use crate::error::{PersistenceError, PersistenceResult};
-use crate::types::{WorkflowDefinition, WorkflowId, WorkflowInstance, WorkflowStatus};
+use crate::types::{
+    ArchivedInstance, AwaitedTasks, Backpressure, BranchState, Compensation, HistoryEventKind, InstanceCursor,
+    InstanceFilter, InstancePage, TaskId, WorkflowDefinition, WorkflowId, WorkflowInstance, WorkflowStatus,
+};
 use chrono::Utc;
-use foundationdb::{Database, Transaction};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use foundationdb::{Database, RangeOption, Transaction};
+use std::io::{Read, Write};
 use std::sync::Arc;
 
+/// Version of the instance index layout, bumped when it changes so existing
+/// instances are re-indexed
+const INSTANCE_INDEX_VERSION: &[u8] = b"1";
+
+/// Number of instances re-indexed per transaction
+const REINDEX_BATCH_SIZE: usize = 200;
+
+/// Statuses of instances holding one of their definition's running slots
+const SLOT_STATUSES: [WorkflowStatus; 3] = [
+    WorkflowStatus::Running,
+    WorkflowStatus::Compensating,
+    WorkflowStatus::Paused,
+];
+
+/// Most queued starts counted when reporting backpressure
+const QUEUED_COUNT_LIMIT: usize = 1000;
+
+/// Most instances returned by a context index lookup
+const INDEX_LOOKUP_LIMIT: usize = 1000;
+
+/// Size of the chunks archived instances are split into, below the FDB
+/// value size limit
+const ARCHIVE_CHUNK_BYTES: usize = 90_000;
+
 /// Workflow storage operations
 #[derive(Clone)]
 pub struct WorkflowStore {
     db: Arc<Database>,
+    keyspace: Keyspace,
+    /// Key space of the definitions, which tenants share
+    definitions: Keyspace,
+    change_feed: ChangeFeedStore,
+    annotations: AnnotationStore,
+    history: HistoryStore,
+    kv: KvStore,
 }
 
 impl WorkflowStore {
-    pub fn new(db: Arc<Database>) -> Self {
-        Self { db }
+    pub fn new(db: Arc<Database>, keyspace: Keyspace) -> Self {
+        Self {
+            change_feed: ChangeFeedStore::new(db.clone(), keyspace.clone()),
+            annotations: AnnotationStore::new(db.clone(), keyspace.clone()),
+            history: HistoryStore::new(db.clone(), keyspace.clone()),
+            kv: KvStore::new(db.clone(), keyspace.clone()),
+            definitions: keyspace.clone(),
+            db,
+            keyspace,
+        }
+    }
+
+    /// Keep definitions in `keyspace` instead of the store's own key space
+    pub(crate) fn with_definitions(mut self, keyspace: Keyspace) -> Self {
+        self.definitions = keyspace;
+        self
     }
 
     /// Save a workflow definition
@@ -37,13 +88,14 @@ impl WorkflowStore {
         tx: &Transaction,
         definition: &WorkflowDefinition,
     ) -> PersistenceResult<()> {
-        let key = build_key(keys::WORKFLOW_DEF_PREFIX, &definition.id.to_string());
+        let key = self.definitions.key(keys::WORKFLOW_DEF_PREFIX, &definition.id.to_string());
         let value = serde_json::to_vec(definition)?;
         tx.set(&key, &value);
         Ok(())
     }
 
     /// Get a workflow definition
+    #[tracing::instrument(level = "debug", name = "persistence.workflow.get_definition", skip_all)]
     pub async fn get_definition(&self, id: &WorkflowId) -> PersistenceResult<Option<WorkflowDefinition>> {
         let tx = self.db.create_trx()?;
         let result = self.get_definition_tx(&tx, id).await?;
@@ -57,7 +109,7 @@ impl WorkflowStore {
         tx: &Transaction,
         id: &WorkflowId,
     ) -> PersistenceResult<Option<WorkflowDefinition>> {
-        let key = build_key(keys::WORKFLOW_DEF_PREFIX, &id.to_string());
+        let key = self.definitions.key(keys::WORKFLOW_DEF_PREFIX, &id.to_string());
         let bytes = tx.get(&key, false).await?;
         
         match bytes {
@@ -69,7 +121,48 @@ impl WorkflowStore {
         }
     }
 
+    /// List workflow definitions in ID order, starting after `after`
+    pub async fn list_definitions(
+        &self,
+        after: Option<&WorkflowId>,
+        limit: usize,
+    ) -> PersistenceResult<Vec<WorkflowDefinition>> {
+        let tx = self.db.create_trx()?;
+
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+
+        let begin_key = self.definitions.prefix(keys::WORKFLOW_DEF_PREFIX);
+        let begin = match after {
+            Some(id) => foundationdb::KeySelector::first_greater_than(self.definitions.key(
+                keys::WORKFLOW_DEF_PREFIX,
+                &id.to_string(),
+            )),
+            None => foundationdb::KeySelector::first_greater_or_equal(begin_key.clone()),
+        };
+        let mut end_key = begin_key;
+        end_key.push(0xff);
+        let range = RangeOption {
+            begin,
+            end: foundationdb::KeySelector::first_greater_or_equal(end_key),
+            mode: foundationdb::options::StreamingMode::WantAll,
+            limit: Some(limit),
+            reverse: false,
+            ..Default::default()
+        };
+
+        let results = tx.get_range(&range, 1, false).await?;
+        tx.cancel();
+
+        let mut definitions = Vec::with_capacity(results.len());
+        for kv in results.iter() {
+            definitions.push(serde_json::from_slice(kv.value())?);
+        }
+        Ok(definitions)
+    }
+
     /// Save a workflow instance
+    #[tracing::instrument(level = "debug", name = "persistence.workflow.save_instance", skip_all)]
     pub async fn save_instance(&self, instance: &WorkflowInstance) -> PersistenceResult<()> {
         let tx = self.db.create_trx()?;
         
@@ -83,18 +176,217 @@ impl WorkflowStore {
     }
 
     /// Save a workflow instance within a transaction
+    ///
+    /// Creating the instance and replacing its context are recorded in the
+    /// instance history.
     pub async fn save_instance_tx(
         &self,
         tx: &Transaction,
         instance: &WorkflowInstance,
     ) -> PersistenceResult<()> {
-        let key = build_key(keys::WORKFLOW_PREFIX, &instance.id.to_string());
+        let indexes = self.indexes_tx(tx, &instance.definition_id).await?;
+        match self.get_instance_tx(tx, &instance.id).await? {
+            None => {
+                let started = HistoryEventKind::Started {
+                    definition_id: instance.definition_id,
+                    state: instance.current_state.clone(),
+                    context: instance.context.clone(),
+                };
+                self.history.append_tx(tx, &instance.id, started).await?;
+                if let Some(key) = &instance.idempotency_key {
+                    let key = idempotency_key(&self.keyspace, &instance.definition_id, key);
+                    tx.set(&key, instance.id.to_string().as_bytes());
+                }
+            }
+            Some(previous) => {
+                // Status index entries move when the status changes
+                if previous.status != instance.status {
+                    for key in instance_index_keys(&self.keyspace, &previous) {
+                        tx.clear(&key);
+                    }
+                }
+                if previous.context != instance.context {
+                    for key in context_index_keys(&self.keyspace, &indexes, &previous) {
+                        tx.clear(&key);
+                    }
+                    let changed = HistoryEventKind::ContextChanged {
+                        context: instance.context.clone(),
+                    };
+                    self.history.append_tx(tx, &instance.id, changed).await?;
+                }
+            }
+        }
+        let index_keys = instance_index_keys(&self.keyspace, instance)
+            .into_iter()
+            .chain(context_index_keys(&self.keyspace, &indexes, instance));
+        for key in index_keys {
+            tx.set(&key, b"");
+        }
+
+        let key = self.keyspace.key(keys::WORKFLOW_PREFIX, &instance.id.to_string());
         let value = serde_json::to_vec(instance)?;
         tx.set(&key, &value);
+        self.change_feed.record_tx(tx, &instance.id, false)?;
         Ok(())
     }
 
+    /// Save a new instance unless another one holds its idempotency key
+    ///
+    /// Returns the instance holding the key, in which case nothing is saved.
+    /// Concurrent inserts with the same key conflict, so only one of them
+    /// creates an instance. When `max_running` instances of the definition
+    /// hold a running slot already, the instance is saved as `Pending`
+    /// instead, to be admitted once a slot frees up.
+    #[tracing::instrument(level = "debug", name = "persistence.workflow.insert_instance", skip_all)]
+    pub async fn insert_instance(
+        &self,
+        instance: &mut WorkflowInstance,
+        max_running: Option<u32>,
+    ) -> PersistenceResult<Option<WorkflowInstance>> {
+        let tx = self.db.create_trx()?;
+
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+        tx.set_option(foundationdb::options::TransactionOption::RetryLimit(5))?;
+
+        if let Some(key) = &instance.idempotency_key
+            && let Some(existing) = tx
+                .get(&idempotency_key(&self.keyspace, &instance.definition_id, key), false)
+                .await?
+        {
+            let id = std::str::from_utf8(&existing)
+                .ok()
+                .and_then(|id| uuid::Uuid::parse_str(id).ok())
+                .map(WorkflowId::from_uuid)
+                .ok_or_else(|| PersistenceError::Corruption("Invalid idempotency key entry".to_string()))?;
+            // A key whose instance was deleted is free again
+            if let Some(existing) = self.get_instance_tx(&tx, &id).await? {
+                tx.cancel();
+                return Ok(Some(existing));
+            }
+        }
+
+        // Concurrent starts read each other's slots and conflict
+        if let Some(max) = max_running {
+            let running = self
+                .count_tx(&tx, &instance.definition_id, &SLOT_STATUSES, max as usize)
+                .await?;
+            if running >= max as usize {
+                instance.status = WorkflowStatus::Pending;
+            }
+        }
+
+        self.save_instance_tx(&tx, instance).await?;
+        if instance.status == WorkflowStatus::Pending {
+            self.history.append_tx(&tx, &instance.id, HistoryEventKind::Queued).await?;
+        }
+        tx.commit().await?;
+        Ok(None)
+    }
+
+    /// Move the oldest queued instance of a definition to `Running` if fewer
+    /// than `max_running` instances hold a running slot
+    ///
+    /// Returns the admitted instance, which has yet to enter its initial
+    /// state.
+    pub async fn admit_next(
+        &self,
+        definition_id: &WorkflowId,
+        max_running: u32,
+    ) -> PersistenceResult<Option<WorkflowInstance>> {
+        let tx = self.db.create_trx()?;
+        
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+        tx.set_option(foundationdb::options::TransactionOption::RetryLimit(5))?;
+        
+        let running = self
+            .count_tx(&tx, definition_id, &SLOT_STATUSES, max_running as usize)
+            .await?;
+        if running >= max_running as usize {
+            tx.cancel();
+            return Ok(None);
+        }
+
+        let prefix = index_prefix(&self.keyspace, Some(definition_id), Some(WorkflowStatus::Pending));
+        let mut end_key = prefix.clone();
+        end_key.push(0xff);
+        let range = RangeOption {
+            begin: foundationdb::KeySelector::first_greater_or_equal(&prefix),
+            end: foundationdb::KeySelector::first_greater_or_equal(&end_key),
+            mode: foundationdb::options::StreamingMode::Small,
+            limit: Some(1),
+            reverse: false,
+            ..Default::default()
+        };
+        let results = tx.get_range(&range, 1, false).await?;
+        let Some(kv) = results.iter().next() else {
+            tx.cancel();
+            return Ok(None);
+        };
+        let (_, id) = parse_index_key(&kv.key()[prefix.len()..])?;
+        let mut instance = self.get_instance_tx(&tx, &id).await?
+            .ok_or_else(|| PersistenceError::Corruption(format!("Indexed instance {} not found", id)))?;
+
+        self.history.append_tx(&tx, &id, HistoryEventKind::Admitted).await?;
+        instance.status = WorkflowStatus::Running;
+        instance.updated_at = Utc::now();
+
+        self.save_instance_tx(&tx, &instance).await?;
+        tx.commit().await?;
+        Ok(Some(instance))
+    }
+
+    /// Count the running and queued instances of a definition limited to
+    /// `max_running` running instances
+    pub async fn backpressure(&self, definition_id: &WorkflowId, max_running: u32) -> PersistenceResult<Backpressure> {
+        let tx = self.db.create_trx()?;
+        let running = self
+            .count_tx(&tx, definition_id, &SLOT_STATUSES, max_running as usize)
+            .await?;
+        let queued = self
+            .count_tx(&tx, definition_id, &[WorkflowStatus::Pending], QUEUED_COUNT_LIMIT)
+            .await?;
+        tx.cancel();
+
+        Ok(Backpressure {
+            running_instances: running as u32,
+            max_running_instances: max_running,
+            queued_instances: queued as u32,
+        })
+    }
+
+    /// Count the instances of a definition in `statuses`, up to `limit`
+    async fn count_tx(
+        &self,
+        tx: &Transaction,
+        definition_id: &WorkflowId,
+        statuses: &[WorkflowStatus],
+        limit: usize,
+    ) -> PersistenceResult<usize> {
+        let mut count = 0;
+        for status in statuses {
+            if count >= limit {
+                break;
+            }
+            let prefix = index_prefix(&self.keyspace, Some(definition_id), Some(*status));
+            let mut end_key = prefix.clone();
+            end_key.push(0xff);
+            let range = RangeOption {
+                begin: foundationdb::KeySelector::first_greater_or_equal(&prefix),
+                end: foundationdb::KeySelector::first_greater_or_equal(&end_key),
+                mode: foundationdb::options::StreamingMode::WantAll,
+                limit: Some(limit - count),
+                reverse: false,
+                ..Default::default()
+            };
+            count += tx.get_range(&range, 1, false).await?.len();
+        }
+        Ok(count)
+    }
+
     /// Get a workflow instance
+    #[tracing::instrument(level = "debug", name = "persistence.workflow.get_instance", skip_all)]
     pub async fn get_instance(&self, id: &WorkflowId) -> PersistenceResult<Option<WorkflowInstance>> {
         let tx = self.db.create_trx()?;
         let result = self.get_instance_tx(&tx, id).await?;
@@ -108,7 +400,7 @@ impl WorkflowStore {
         tx: &Transaction,
         id: &WorkflowId,
     ) -> PersistenceResult<Option<WorkflowInstance>> {
-        let key = build_key(keys::WORKFLOW_PREFIX, &id.to_string());
+        let key = self.keyspace.key(keys::WORKFLOW_PREFIX, &id.to_string());
         let bytes = tx.get(&key, false).await?;
         
         match bytes {
@@ -120,13 +412,20 @@ impl WorkflowStore {
         }
     }
 
-    /// Update workflow state
-    pub async fn update_state(
+    /// Move a workflow to a new state in response to `event`
+    ///
+    /// The state, status and the context the transition produced are written
+    /// in one transaction together with their history entries. Returns the
+    /// number of the state entry, which timers of the new state carry.
+    #[tracing::instrument(level = "debug", name = "persistence.workflow.transition", skip_all)]
+    pub async fn transition(
         &self,
         id: &WorkflowId,
+        event: &str,
         state: &str,
         status: WorkflowStatus,
-    ) -> PersistenceResult<()> {
+        context: serde_json::Value,
+    ) -> PersistenceResult<u64> {
         let tx = self.db.create_trx()?;
         
         // Set transaction timeout to 2 seconds
@@ -136,14 +435,311 @@ impl WorkflowStore {
         let mut instance = self.get_instance_tx(&tx, id).await?
             .ok_or_else(|| PersistenceError::NotFound(id.to_string()))?;
         
+        let transitioned = HistoryEventKind::Transitioned {
+            event: event.to_string(),
+            from: instance.current_state.clone(),
+            to: state.to_string(),
+            status,
+        };
+        self.history.append_tx(&tx, id, transitioned).await?;
+
         instance.current_state = state.to_string();
+        instance.state_entry += 1;
         instance.status = status;
+        instance.context = context;
         instance.updated_at = Utc::now();
+        // Leaving a forked state drops its branches
+        instance.branches.clear();
+        instance.awaited_tasks.clear();
         
         if matches!(status, WorkflowStatus::Completed | WorkflowStatus::Failed | WorkflowStatus::Cancelled) {
             instance.completed_at = Some(Utc::now());
         }
         
+        self.save_instance_tx(&tx, &instance).await?;
+        tx.commit().await?;
+        Ok(instance.state_entry)
+    }
+
+    /// Start the branches of the forked state an instance entered
+    pub async fn fork(&self, id: &WorkflowId, branches: Vec<BranchState>) -> PersistenceResult<()> {
+        let tx = self.db.create_trx()?;
+        
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+        tx.set_option(foundationdb::options::TransactionOption::RetryLimit(5))?;
+        
+        let mut instance = self.get_instance_tx(&tx, id).await?
+            .ok_or_else(|| PersistenceError::NotFound(id.to_string()))?;
+
+        let forked = HistoryEventKind::Forked {
+            branches: branches.clone(),
+        };
+        self.history.append_tx(&tx, id, forked).await?;
+
+        instance.branches = branches;
+        instance.updated_at = Utc::now();
+        
+        self.save_instance_tx(&tx, &instance).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Move one branch of a forked instance to a new state
+    ///
+    /// Returns the updated instance, so the caller can tell how many branches
+    /// have joined.
+    #[tracing::instrument(level = "debug", name = "persistence.workflow.transition_branch", skip_all)]
+    pub async fn transition_branch(
+        &self,
+        id: &WorkflowId,
+        branch: &str,
+        event: &str,
+        state: &str,
+        joined: bool,
+        context: serde_json::Value,
+    ) -> PersistenceResult<WorkflowInstance> {
+        let tx = self.db.create_trx()?;
+        
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+        tx.set_option(foundationdb::options::TransactionOption::RetryLimit(5))?;
+        
+        let mut instance = self.get_instance_tx(&tx, id).await?
+            .ok_or_else(|| PersistenceError::NotFound(id.to_string()))?;
+        let entry = instance
+            .branches
+            .iter_mut()
+            .find(|b| b.name == branch && !b.joined)
+            .ok_or_else(|| PersistenceError::NotFound(format!("{} branch {}", id, branch)))?;
+
+        let transitioned = HistoryEventKind::BranchTransitioned {
+            branch: branch.to_string(),
+            event: event.to_string(),
+            from: entry.state.clone(),
+            to: state.to_string(),
+            joined,
+        };
+        entry.state = state.to_string();
+        entry.state_entry += 1;
+        entry.joined = joined;
+        self.history.append_tx(&tx, id, transitioned).await?;
+        instance
+            .awaited_tasks
+            .retain(|awaited| awaited.branch.as_deref() != Some(branch));
+
+        instance.context = context;
+        instance.updated_at = Utc::now();
+        
+        self.save_instance_tx(&tx, &instance).await?;
+        tx.commit().await?;
+        Ok(instance)
+    }
+
+    /// Continue a forked instance in its join state, dropping its branches
+    ///
+    /// Returns `None` without changes when the branches already joined, so
+    /// branches arriving concurrently join only once.
+    pub async fn join(
+        &self,
+        id: &WorkflowId,
+        state: &str,
+        status: WorkflowStatus,
+        context: serde_json::Value,
+    ) -> PersistenceResult<Option<WorkflowInstance>> {
+        let tx = self.db.create_trx()?;
+        
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+        tx.set_option(foundationdb::options::TransactionOption::RetryLimit(5))?;
+        
+        let mut instance = self.get_instance_tx(&tx, id).await?
+            .ok_or_else(|| PersistenceError::NotFound(id.to_string()))?;
+        if instance.branches.is_empty() || instance.status != WorkflowStatus::Running {
+            return Ok(None);
+        }
+
+        let joined = HistoryEventKind::Joined {
+            state: state.to_string(),
+            branches: instance
+                .branches
+                .iter()
+                .filter(|b| b.joined)
+                .map(|b| b.name.clone())
+                .collect(),
+            status,
+        };
+        self.history.append_tx(&tx, id, joined).await?;
+
+        instance.current_state = state.to_string();
+        instance.state_entry += 1;
+        instance.status = status;
+        instance.context = context;
+        instance.branches.clear();
+        instance.awaited_tasks.clear();
+        instance.updated_at = Utc::now();
+        if status == WorkflowStatus::Completed {
+            instance.completed_at = Some(Utc::now());
+        }
+        
+        self.save_instance_tx(&tx, &instance).await?;
+        tx.commit().await?;
+        Ok(Some(instance))
+    }
+
+    /// Fail a running workflow and start rolling it back
+    ///
+    /// Returns the updated instance, or `None` without changes when the
+    /// instance is not running, so a failure reported twice starts only one
+    /// rollback.
+    pub async fn begin_compensation(
+        &self,
+        id: &WorkflowId,
+        compensation: Compensation,
+    ) -> PersistenceResult<Option<WorkflowInstance>> {
+        let tx = self.db.create_trx()?;
+        
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+        tx.set_option(foundationdb::options::TransactionOption::RetryLimit(5))?;
+        
+        let mut instance = self.get_instance_tx(&tx, id).await?
+            .ok_or_else(|| PersistenceError::NotFound(id.to_string()))?;
+        if instance.status != WorkflowStatus::Running {
+            return Ok(None);
+        }
+
+        let started = HistoryEventKind::CompensationStarted {
+            reason: compensation.reason.clone(),
+        };
+        self.history.append_tx(&tx, id, started).await?;
+
+        instance.status = WorkflowStatus::Compensating;
+        instance.compensation = Some(compensation);
+        instance.updated_at = Utc::now();
+        
+        self.save_instance_tx(&tx, &instance).await?;
+        tx.commit().await?;
+        Ok(Some(instance))
+    }
+
+    /// Mark an instance that has not finished as `Cancelled`
+    ///
+    /// A rollback in progress is abandoned. Returns the instance as it was
+    /// before, or `None` when it had already finished.
+    pub async fn cancel(&self, id: &WorkflowId, reason: &str) -> PersistenceResult<Option<WorkflowInstance>> {
+        let tx = self.db.create_trx()?;
+        
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+        tx.set_option(foundationdb::options::TransactionOption::RetryLimit(5))?;
+        
+        let instance = self.get_instance_tx(&tx, id).await?
+            .ok_or_else(|| PersistenceError::NotFound(id.to_string()))?;
+        if matches!(instance.status, WorkflowStatus::Completed | WorkflowStatus::Failed | WorkflowStatus::Cancelled) {
+            return Ok(None);
+        }
+
+        let cancelled = HistoryEventKind::Cancelled {
+            reason: reason.to_string(),
+        };
+        self.history.append_tx(&tx, id, cancelled).await?;
+
+        let mut updated = instance.clone();
+        updated.status = WorkflowStatus::Cancelled;
+        updated.compensation = None;
+        updated.awaited_tasks.clear();
+        updated.updated_at = Utc::now();
+        updated.completed_at = Some(Utc::now());
+
+        self.save_instance_tx(&tx, &updated).await?;
+        tx.commit().await?;
+        Ok(Some(instance))
+    }
+
+    /// Mark a running instance as `Paused`
+    ///
+    /// Returns the paused instance, or `None` when it was not running.
+    pub async fn pause(&self, id: &WorkflowId, reason: &str) -> PersistenceResult<Option<WorkflowInstance>> {
+        let paused = HistoryEventKind::Paused {
+            reason: reason.to_string(),
+        };
+        self.change_status(id, WorkflowStatus::Running, WorkflowStatus::Paused, paused).await
+    }
+
+    /// Mark a paused instance as `Running` again
+    ///
+    /// Returns the resumed instance, still listing the tasks the pause held,
+    /// or `None` when it was not paused.
+    pub async fn resume(&self, id: &WorkflowId) -> PersistenceResult<Option<WorkflowInstance>> {
+        self.change_status(id, WorkflowStatus::Paused, WorkflowStatus::Running, HistoryEventKind::Resumed)
+            .await
+    }
+
+    /// Move an instance from status `from` to `to`, recording `event`
+    async fn change_status(
+        &self,
+        id: &WorkflowId,
+        from: WorkflowStatus,
+        to: WorkflowStatus,
+        event: HistoryEventKind,
+    ) -> PersistenceResult<Option<WorkflowInstance>> {
+        let tx = self.db.create_trx()?;
+        
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+        tx.set_option(foundationdb::options::TransactionOption::RetryLimit(5))?;
+        
+        let mut instance = self.get_instance_tx(&tx, id).await?
+            .ok_or_else(|| PersistenceError::NotFound(id.to_string()))?;
+        if instance.status != from {
+            return Ok(None);
+        }
+
+        self.history.append_tx(&tx, id, event).await?;
+
+        instance.status = to;
+        instance.paused_at = (to == WorkflowStatus::Paused).then(Utc::now);
+        let held_tasks = std::mem::take(&mut instance.held_tasks);
+        instance.updated_at = Utc::now();
+        
+        self.save_instance_tx(&tx, &instance).await?;
+        tx.commit().await?;
+        instance.held_tasks = held_tasks;
+        Ok(Some(instance))
+    }
+
+    /// Record rollback progress
+    ///
+    /// Passing no `compensation` ends the rollback and marks the instance
+    /// `Failed`; `error` tells why it ended before all actions ran.
+    pub async fn update_compensation(
+        &self,
+        id: &WorkflowId,
+        context: serde_json::Value,
+        compensation: Option<Compensation>,
+        error: Option<String>,
+    ) -> PersistenceResult<()> {
+        let tx = self.db.create_trx()?;
+        
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+        tx.set_option(foundationdb::options::TransactionOption::RetryLimit(5))?;
+        
+        let mut instance = self.get_instance_tx(&tx, id).await?
+            .ok_or_else(|| PersistenceError::NotFound(id.to_string()))?;
+        
+        instance.context = context;
+        instance.updated_at = Utc::now();
+        if compensation.is_none() {
+            let finished = HistoryEventKind::CompensationFinished { error };
+            self.history.append_tx(&tx, id, finished).await?;
+
+            instance.status = WorkflowStatus::Failed;
+            instance.completed_at = Some(Utc::now());
+        }
+        instance.compensation = compensation;
+        
         self.save_instance_tx(&tx, &instance).await?;
         tx.commit().await?;
         Ok(())
@@ -172,7 +768,376 @@ impl WorkflowStore {
         Ok(())
     }
 
+    /// Record the queued tasks a pause took out of the queue, for the resume
+    /// to put back
+    pub async fn set_held_tasks(&self, id: &WorkflowId, task_ids: Vec<TaskId>) -> PersistenceResult<()> {
+        let tx = self.db.create_trx()?;
+
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+        tx.set_option(foundationdb::options::TransactionOption::RetryLimit(5))?;
+
+        let mut instance = self.get_instance_tx(&tx, id).await?
+            .ok_or_else(|| PersistenceError::NotFound(id.to_string()))?;
+
+        instance.held_tasks = task_ids;
+        instance.updated_at = Utc::now();
+
+        self.save_instance_tx(&tx, &instance).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Block a parent workflow until the given child completes
+    pub async fn add_awaited_child(
+        &self,
+        parent_id: &WorkflowId,
+        child_id: &WorkflowId,
+    ) -> PersistenceResult<()> {
+        let tx = self.db.create_trx()?;
+        
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+        tx.set_option(foundationdb::options::TransactionOption::RetryLimit(5))?;
+        
+        let mut instance = self.get_instance_tx(&tx, parent_id).await?
+            .ok_or_else(|| PersistenceError::NotFound(parent_id.to_string()))?;
+        
+        if !instance.awaiting_children.contains(child_id) {
+            instance.awaiting_children.push(*child_id);
+        }
+        instance.updated_at = Utc::now();
+        
+        self.save_instance_tx(&tx, &instance).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Set a single context value of an instance, leaving the rest of the
+    /// context as it is
+    ///
+    /// `key` is a top-level field or a path such as `$.steps.verify`.
+    pub async fn set_context_value(
+        &self,
+        workflow_id: &WorkflowId,
+        key: &str,
+        value: serde_json::Value,
+    ) -> PersistenceResult<()> {
+        let tx = self.db.create_trx()?;
+
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+        tx.set_option(foundationdb::options::TransactionOption::RetryLimit(5))?;
+
+        let mut instance = self.get_instance_tx(&tx, workflow_id).await?
+            .ok_or_else(|| PersistenceError::NotFound(workflow_id.to_string()))?;
+
+        crate::state_machine::set_path(&mut instance.context, key, value);
+        instance.updated_at = Utc::now();
+
+        self.save_instance_tx(&tx, &instance).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Wait for tasks before firing a state's completion events
+    ///
+    /// Replaces the tasks awaited by the same branch. Must be stored before
+    /// the tasks are enqueued, so a task finishing right away is found.
+    pub async fn await_tasks(&self, workflow_id: &WorkflowId, awaited: AwaitedTasks) -> PersistenceResult<()> {
+        let tx = self.db.create_trx()?;
+
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+        tx.set_option(foundationdb::options::TransactionOption::RetryLimit(5))?;
+
+        let mut instance = self.get_instance_tx(&tx, workflow_id).await?
+            .ok_or_else(|| PersistenceError::NotFound(workflow_id.to_string()))?;
+
+        instance.awaited_tasks.retain(|entry| entry.branch != awaited.branch);
+        instance.awaited_tasks.push(awaited);
+        instance.updated_at = Utc::now();
+
+        self.save_instance_tx(&tx, &instance).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Record the outcome of an awaited task
+    ///
+    /// Returns the awaited tasks once they are settled: when the last one
+    /// succeeded, or on the first failure. The entry is removed then, so
+    /// only one caller sees it settle and later outcomes are ignored.
+    pub async fn settle_task(
+        &self,
+        workflow_id: &WorkflowId,
+        task_id: &TaskId,
+        succeeded: bool,
+    ) -> PersistenceResult<Option<AwaitedTasks>> {
+        let tx = self.db.create_trx()?;
+
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+        tx.set_option(foundationdb::options::TransactionOption::RetryLimit(5))?;
+
+        let mut instance = self.get_instance_tx(&tx, workflow_id).await?
+            .ok_or_else(|| PersistenceError::NotFound(workflow_id.to_string()))?;
+        let Some(index) = instance
+            .awaited_tasks
+            .iter()
+            .position(|entry| entry.pending.contains(task_id))
+        else {
+            tx.cancel();
+            return Ok(None);
+        };
+
+        let entry = &mut instance.awaited_tasks[index];
+        entry.pending.retain(|id| id != task_id);
+        let settled = if !succeeded || entry.pending.is_empty() {
+            Some(instance.awaited_tasks.remove(index))
+        } else {
+            None
+        };
+        instance.updated_at = Utc::now();
+
+        self.save_instance_tx(&tx, &instance).await?;
+        tx.commit().await?;
+        Ok(settled)
+    }
+
+    /// Record a completed child in its parent
+    ///
+    /// Stores the child's result under `result_key` (if any) and unblocks the
+    /// parent from the child. Returns the updated parent instance.
+    pub async fn resolve_child(
+        &self,
+        parent_id: &WorkflowId,
+        child_id: &WorkflowId,
+        result_key: Option<&str>,
+        result: serde_json::Value,
+    ) -> PersistenceResult<WorkflowInstance> {
+        let tx = self.db.create_trx()?;
+        
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+        tx.set_option(foundationdb::options::TransactionOption::RetryLimit(5))?;
+        
+        let mut instance = self.get_instance_tx(&tx, parent_id).await?
+            .ok_or_else(|| PersistenceError::NotFound(parent_id.to_string()))?;
+        
+        if let (Some(key), serde_json::Value::Object(map)) = (result_key, &mut instance.context) {
+            map.insert(key.to_string(), result);
+        }
+        instance.awaiting_children.retain(|id| id != child_id);
+        instance.updated_at = Utc::now();
+        
+        self.save_instance_tx(&tx, &instance).await?;
+        tx.commit().await?;
+        Ok(instance)
+    }
+
+    /// List workflow instances matching `filter`, oldest first
+    ///
+    /// Served from a secondary index chosen by the filter (definition,
+    /// status, both or neither, each ordered by creation time), so every
+    /// returned instance matches and pages are full until the last one.
+    pub async fn list(
+        &self,
+        filter: &InstanceFilter,
+        cursor: Option<&InstanceCursor>,
+        limit: usize,
+    ) -> PersistenceResult<InstancePage> {
+        let tx = self.db.create_trx()?;
+
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+
+        let prefix = index_prefix(&self.keyspace, filter.definition_id.as_ref(), filter.status);
+        let begin = match (cursor, filter.created_after) {
+            (Some(cursor), _) => {
+                let mut key = prefix.clone();
+                key.extend_from_slice(&cursor.created_at_ms.to_be_bytes());
+                key.extend_from_slice(cursor.id.to_string().as_bytes());
+                foundationdb::KeySelector::first_greater_than(key)
+            }
+            (None, Some(after)) => {
+                let mut key = prefix.clone();
+                key.extend_from_slice(&after.timestamp_millis().to_be_bytes());
+                foundationdb::KeySelector::first_greater_or_equal(key)
+            }
+            (None, None) => foundationdb::KeySelector::first_greater_or_equal(prefix.clone()),
+        };
+        let end_key = match filter.created_before {
+            Some(before) => {
+                let mut key = prefix.clone();
+                key.extend_from_slice(&before.timestamp_millis().to_be_bytes());
+                key
+            }
+            None => {
+                let mut key = prefix.clone();
+                key.push(0xff);
+                key
+            }
+        };
+        let range = RangeOption {
+            begin,
+            end: foundationdb::KeySelector::first_greater_or_equal(end_key),
+            mode: foundationdb::options::StreamingMode::WantAll,
+            limit: Some(limit),
+            reverse: false,
+            ..Default::default()
+        };
+
+        let results = tx.get_range(&range, 1, false).await?;
+
+        let mut ids = Vec::with_capacity(results.len());
+        for kv in results.iter() {
+            ids.push(parse_index_key(&kv.key()[prefix.len()..])?);
+        }
+
+        let lookups = ids.iter().map(|(_, id)| self.get_instance_tx(&tx, id));
+        let instances = futures::future::try_join_all(lookups).await?;
+        tx.cancel();
+
+        let next = if ids.len() < limit {
+            None
+        } else {
+            ids.last().map(|(created_at_ms, id)| InstanceCursor {
+                created_at_ms: *created_at_ms,
+                id: *id,
+            })
+        };
+
+        Ok(InstancePage {
+            instances: instances.into_iter().flatten().collect(),
+            next,
+        })
+    }
+
+    /// Find the instances of a definition holding `value` at an indexed
+    /// context path
+    ///
+    /// Only the paths the definition declares in its `indexes` are indexed,
+    /// each time an instance is saved; instances not saved since a path was
+    /// declared are not found by it.
+    pub async fn find_by_index(
+        &self,
+        definition_id: &WorkflowId,
+        path: &str,
+        value: &serde_json::Value,
+    ) -> PersistenceResult<Vec<WorkflowInstance>> {
+        let Some(prefix) = context_index_prefix(&self.keyspace, definition_id, path, value) else {
+            return Ok(Vec::new());
+        };
+        let tx = self.db.create_trx()?;
+
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+
+        let mut end_key = prefix.clone();
+        end_key.push(0xff);
+        let range = RangeOption {
+            begin: foundationdb::KeySelector::first_greater_or_equal(prefix.clone()),
+            end: foundationdb::KeySelector::first_greater_or_equal(end_key),
+            mode: foundationdb::options::StreamingMode::WantAll,
+            limit: Some(INDEX_LOOKUP_LIMIT),
+            reverse: false,
+            ..Default::default()
+        };
+
+        let results = tx.get_range(&range, 1, false).await?;
+        let corrupt = || PersistenceError::Corruption("Invalid context index key".to_string());
+        let mut ids = Vec::with_capacity(results.len());
+        for kv in results.iter() {
+            let id = std::str::from_utf8(&kv.key()[prefix.len()..]).map_err(|_| corrupt())?;
+            ids.push(WorkflowId::from_uuid(uuid::Uuid::parse_str(id).map_err(|_| corrupt())?));
+        }
+
+        let lookups = ids.iter().map(|id| self.get_instance_tx(&tx, id));
+        let instances = futures::future::try_join_all(lookups).await?;
+        tx.cancel();
+        Ok(instances.into_iter().flatten().collect())
+    }
+
+    /// Get the context paths a definition indexes its instances by
+    async fn indexes_tx(&self, tx: &Transaction, definition_id: &WorkflowId) -> PersistenceResult<Vec<String>> {
+        Ok(self
+            .get_definition_tx(tx, definition_id)
+            .await?
+            .map(|definition| definition.indexes)
+            .unwrap_or_default())
+    }
+
+    /// Build the instance indexes for instances saved before they existed
+    ///
+    /// Runs once per index layout version; later calls return immediately.
+    pub async fn ensure_indexes(&self) -> PersistenceResult<()> {
+        let version_key = self.keyspace.key(keys::META_PREFIX, "instance_index_version");
+        let tx = self.db.create_trx()?;
+        let current = tx.get(&version_key, false).await?;
+        tx.cancel();
+        if current.as_deref() == Some(INSTANCE_INDEX_VERSION) {
+            return Ok(());
+        }
+
+        tracing::info!("Building workflow instance indexes");
+        let mut after: Option<WorkflowId> = None;
+        let mut indexed = 0;
+        loop {
+            let tx = self.db.create_trx()?;
+
+            // Set transaction timeout to 2 seconds
+            tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+            tx.set_option(foundationdb::options::TransactionOption::RetryLimit(5))?;
+
+            let begin_key = self.keyspace.prefix(keys::WORKFLOW_PREFIX);
+            let begin = match &after {
+                Some(id) => foundationdb::KeySelector::first_greater_than(self.keyspace.key(
+                    keys::WORKFLOW_PREFIX,
+                    &id.to_string(),
+                )),
+                None => foundationdb::KeySelector::first_greater_or_equal(begin_key.clone()),
+            };
+            let mut end_key = begin_key;
+            end_key.push(0xff);
+            let range = RangeOption {
+                begin,
+                end: foundationdb::KeySelector::first_greater_or_equal(end_key),
+                mode: foundationdb::options::StreamingMode::WantAll,
+                limit: Some(REINDEX_BATCH_SIZE),
+                reverse: false,
+                ..Default::default()
+            };
+
+            // Reading the instances makes the batch conflict with concurrent
+            // saves, so an index entry is never written for a stale status
+            let results = tx.get_range(&range, 1, false).await?;
+            for kv in results.iter() {
+                let instance: WorkflowInstance = serde_json::from_slice(kv.value())?;
+                for key in instance_index_keys(&self.keyspace, &instance) {
+                    tx.set(&key, b"");
+                }
+                after = Some(instance.id);
+            }
+            tx.commit().await?;
+
+            indexed += results.len();
+            if results.len() < REINDEX_BATCH_SIZE {
+                break;
+            }
+        }
+
+        let tx = self.db.create_trx()?;
+        tx.set(&version_key, INSTANCE_INDEX_VERSION);
+        tx.commit().await?;
+
+        tracing::info!("Indexed {} workflow instances", indexed);
+        Ok(())
+    }
+
     /// Delete a workflow instance
+    ///
+    /// The instance history is kept and ends with a `Deleted` entry.
     pub async fn delete_instance(&self, id: &WorkflowId) -> PersistenceResult<()> {
         let tx = self.db.create_trx()?;
         
@@ -180,11 +1145,307 @@ impl WorkflowStore {
         tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
         tx.set_option(foundationdb::options::TransactionOption::RetryLimit(3))?;
         
-        let key = build_key(keys::WORKFLOW_PREFIX, &id.to_string());
+        if let Some(instance) = self.get_instance_tx(&tx, id).await? {
+            let indexes = self.indexes_tx(&tx, &instance.definition_id).await?;
+            let index_keys = instance_index_keys(&self.keyspace, &instance)
+                .into_iter()
+                .chain(context_index_keys(&self.keyspace, &indexes, &instance));
+            for key in index_keys {
+                tx.clear(&key);
+            }
+            if let Some(key) = &instance.idempotency_key {
+                tx.clear(&idempotency_key(&self.keyspace, &instance.definition_id, key));
+            }
+            self.history.append_tx(&tx, id, HistoryEventKind::Deleted).await?;
+        }
+
+        let key = self.keyspace.key(keys::WORKFLOW_PREFIX, &id.to_string());
         tx.clear(&key);
+        self.annotations.clear_tx(&tx, id);
+        self.kv.clear_tx(&tx, id);
+        self.change_feed.record_tx(&tx, id, true)?;
         tx.commit().await?;
         Ok(())
     }
+
+    /// Move a finished instance with its history and annotations to the
+    /// archive, returning whether it was archived
+    ///
+    /// The archived copy is stored as gzip-compressed JSON, split into
+    /// chunks; the key-value state of its tasks is dropped. Instances that
+    /// have not completed, failed or been cancelled are left alone.
+    pub async fn archive_instance(&self, id: &WorkflowId) -> PersistenceResult<bool> {
+        self.remove_finished(id, true).await
+    }
+
+    /// Delete a finished instance with its history and annotations,
+    /// returning whether it was deleted
+    ///
+    /// Instances that have not completed, failed or been cancelled are left
+    /// alone.
+    pub async fn purge_instance(&self, id: &WorkflowId) -> PersistenceResult<bool> {
+        self.remove_finished(id, false).await
+    }
+
+    /// Remove a finished instance from the live key space, archiving it
+    /// when `archive` is set
+    async fn remove_finished(&self, id: &WorkflowId, archive: bool) -> PersistenceResult<bool> {
+        let tx = self.db.create_trx()?;
+
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+        tx.set_option(foundationdb::options::TransactionOption::RetryLimit(3))?;
+
+        let Some(instance) = self.get_instance_tx(&tx, id).await? else {
+            return Ok(false);
+        };
+        let finished = matches!(
+            instance.status,
+            WorkflowStatus::Completed | WorkflowStatus::Failed | WorkflowStatus::Cancelled
+        );
+        if !finished {
+            return Ok(false);
+        }
+
+        if archive {
+            let archived = ArchivedInstance {
+                history: self.history.read_all_tx(&tx, id).await?,
+                annotations: self.annotations.list_tx(&tx, id).await?,
+                archived_at: Utc::now(),
+                instance: instance.clone(),
+            };
+            let compressed = compress(&serde_json::to_vec(&archived)?)?;
+            let prefix = self.archive_prefix(id);
+            for (index, chunk) in compressed.chunks(ARCHIVE_CHUNK_BYTES).enumerate() {
+                let mut key = prefix.clone();
+                key.extend_from_slice(&(index as u32).to_be_bytes());
+                tx.set(&key, chunk);
+            }
+        }
+
+        let indexes = self.indexes_tx(&tx, &instance.definition_id).await?;
+        let index_keys = instance_index_keys(&self.keyspace, &instance)
+            .into_iter()
+            .chain(context_index_keys(&self.keyspace, &indexes, &instance));
+        for key in index_keys {
+            tx.clear(&key);
+        }
+        if let Some(key) = &instance.idempotency_key {
+            tx.clear(&idempotency_key(&self.keyspace, &instance.definition_id, key));
+        }
+        tx.clear(&self.keyspace.key(keys::WORKFLOW_PREFIX, &id.to_string()));
+        self.history.clear_tx(&tx, id);
+        self.annotations.clear_tx(&tx, id);
+        self.kv.clear_tx(&tx, id);
+        self.change_feed.record_tx(&tx, id, true)?;
+
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    /// Read an archived instance
+    pub async fn get_archived(&self, id: &WorkflowId) -> PersistenceResult<Option<ArchivedInstance>> {
+        let tx = self.db.create_trx()?;
+
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+
+        let archived = self.get_archived_tx(&tx, id).await?;
+        tx.cancel();
+        Ok(archived)
+    }
+
+    async fn get_archived_tx(&self, tx: &Transaction, id: &WorkflowId) -> PersistenceResult<Option<ArchivedInstance>> {
+        let begin_key = self.archive_prefix(id);
+        let mut end_key = begin_key.clone();
+        end_key.push(0xff);
+
+        let range = RangeOption {
+            begin: foundationdb::KeySelector::first_greater_or_equal(begin_key),
+            end: foundationdb::KeySelector::first_greater_or_equal(end_key),
+            mode: foundationdb::options::StreamingMode::WantAll,
+            reverse: false,
+            ..Default::default()
+        };
+
+        let results = tx.get_range(&range, 1, false).await?;
+        if results.is_empty() {
+            return Ok(None);
+        }
+        let compressed: Vec<u8> = results.iter().flat_map(|kv| kv.value().iter().copied()).collect();
+        Ok(Some(serde_json::from_slice(&decompress(&compressed)?)?))
+    }
+
+    /// Bring an archived instance back with its history and annotations
+    ///
+    /// The restore is recorded in the instance history. Returns `None` when
+    /// the instance is not archived.
+    pub async fn restore_archived(&self, id: &WorkflowId) -> PersistenceResult<Option<WorkflowInstance>> {
+        let tx = self.db.create_trx()?;
+
+        // Set transaction timeout to 2 seconds
+        tx.set_option(foundationdb::options::TransactionOption::Timeout(2000))?;
+        tx.set_option(foundationdb::options::TransactionOption::RetryLimit(3))?;
+
+        let Some(archived) = self.get_archived_tx(&tx, id).await? else {
+            return Ok(None);
+        };
+        let instance = archived.instance;
+        if self.get_instance_tx(&tx, id).await?.is_some() {
+            return Err(PersistenceError::Corruption(format!(
+                "Instance {} is both live and archived",
+                id
+            )));
+        }
+
+        let indexes = self.indexes_tx(&tx, &instance.definition_id).await?;
+        let index_keys = instance_index_keys(&self.keyspace, &instance)
+            .into_iter()
+            .chain(context_index_keys(&self.keyspace, &indexes, &instance));
+        for key in index_keys {
+            tx.set(&key, b"");
+        }
+        // The key may have been taken by a newer instance in the meantime
+        if let Some(key) = &instance.idempotency_key {
+            let key = idempotency_key(&self.keyspace, &instance.definition_id, key);
+            if tx.get(&key, false).await?.is_none() {
+                tx.set(&key, id.to_string().as_bytes());
+            }
+        }
+        let key = self.keyspace.key(keys::WORKFLOW_PREFIX, &id.to_string());
+        tx.set(&key, &serde_json::to_vec(&instance)?);
+
+        self.history.restore_tx(&tx, &archived.history)?;
+        let restored = HistoryEventKind::Restored {
+            archived_at: archived.archived_at,
+        };
+        self.history.append_tx(&tx, id, restored).await?;
+        for annotation in &archived.annotations {
+            self.annotations.add_tx(&tx, annotation)?;
+        }
+
+        let begin_key = self.archive_prefix(id);
+        let mut end_key = begin_key.clone();
+        end_key.push(0xff);
+        tx.clear_range(&begin_key, &end_key);
+        self.change_feed.record_tx(&tx, id, false)?;
+
+        tx.commit().await?;
+        Ok(Some(instance))
+    }
+
+    /// Build the key prefix shared by the chunks of an archived instance
+    fn archive_prefix(&self, id: &WorkflowId) -> Vec<u8> {
+        let mut key = self.keyspace.key(keys::ARCHIVE_PREFIX, &id.to_string());
+        key.push(b':');
+        key
+    }
 }
 
+/// Get the prefix of the index serving a definition and status filter
+fn index_prefix(keyspace: &Keyspace, definition_id: Option<&WorkflowId>, status: Option<WorkflowStatus>) -> Vec<u8> {
+    let mut key = match (definition_id, status) {
+        (Some(_), Some(_)) => keyspace.prefix(keys::INSTANCE_BY_DEFINITION_STATUS_PREFIX),
+        (Some(_), None) => keyspace.prefix(keys::INSTANCE_BY_DEFINITION_PREFIX),
+        (None, Some(_)) => keyspace.prefix(keys::INSTANCE_BY_STATUS_PREFIX),
+        (None, None) => keyspace.prefix(keys::INSTANCE_BY_CREATED_PREFIX),
+    };
+    if let Some(definition_id) = definition_id {
+        key.extend_from_slice(definition_id.to_string().as_bytes());
+        key.push(b':');
+    }
+    if let Some(status) = status {
+        key.extend_from_slice(status.as_str().as_bytes());
+        key.push(b':');
+    }
+    key
+}
+
+/// Build the keys of all index entries of an instance
+fn instance_index_keys(keyspace: &Keyspace, instance: &WorkflowInstance) -> [Vec<u8>; 4] {
+    let definition = Some(&instance.definition_id);
+    let status = Some(instance.status);
+    [
+        index_prefix(keyspace, None, None),
+        index_prefix(keyspace, definition, None),
+        index_prefix(keyspace, None, status),
+        index_prefix(keyspace, definition, status),
+    ]
+    .map(|mut key| {
+        key.extend_from_slice(&instance.created_at.timestamp_millis().to_be_bytes());
+        key.extend_from_slice(instance.id.to_string().as_bytes());
+        key
+    })
+}
 
+/// Get the prefix of the context index entries of instances holding
+/// `value` at `path`
+///
+/// Only strings, numbers and booleans are indexed, by their JSON encoding;
+/// other values have no entries.
+fn context_index_prefix(
+    keyspace: &Keyspace,
+    definition_id: &WorkflowId,
+    path: &str,
+    value: &serde_json::Value,
+) -> Option<Vec<u8>> {
+    if !(value.is_string() || value.is_number() || value.is_boolean()) {
+        return None;
+    }
+    let mut key = keyspace.key(keys::CONTEXT_INDEX_PREFIX, &definition_id.to_string());
+    key.push(b':');
+    key.extend_from_slice(path.as_bytes());
+    key.push(0);
+    key.extend_from_slice(value.to_string().as_bytes());
+    key.push(0);
+    Some(key)
+}
+
+/// Build the keys of the context index entries of an instance
+fn context_index_keys(keyspace: &Keyspace, indexes: &[String], instance: &WorkflowInstance) -> Vec<Vec<u8>> {
+    indexes
+        .iter()
+        .filter_map(|path| {
+            let value = crate::state_machine::get_path(&instance.context, path)?;
+            let mut key = context_index_prefix(keyspace, &instance.definition_id, path, value)?;
+            key.extend_from_slice(instance.id.to_string().as_bytes());
+            Some(key)
+        })
+        .collect()
+}
+
+/// Get the key mapping an idempotency key of a definition to its instance
+fn idempotency_key(keyspace: &Keyspace, definition_id: &WorkflowId, key: &str) -> Vec<u8> {
+    let mut entry = keyspace.key(keys::IDEMPOTENCY_KEY_PREFIX, &definition_id.to_string());
+    entry.push(b':');
+    entry.extend_from_slice(key.as_bytes());
+    entry
+}
+
+/// Gzip-compress an archived instance
+fn compress(bytes: &[u8]) -> PersistenceResult<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder
+        .write_all(bytes)
+        .and_then(|()| encoder.finish())
+        .map_err(|e| PersistenceError::Corruption(format!("Failed to compress archive: {}", e)))
+}
+
+/// Decompress an archived instance
+fn decompress(bytes: &[u8]) -> PersistenceResult<Vec<u8>> {
+    let mut decompressed = Vec::new();
+    GzDecoder::new(bytes)
+        .read_to_end(&mut decompressed)
+        .map_err(|e| PersistenceError::Corruption(format!("Invalid archive: {}", e)))?;
+    Ok(decompressed)
+}
+
+/// Split the part of an index key after its prefix into creation time and ID
+fn parse_index_key(suffix: &[u8]) -> PersistenceResult<(i64, WorkflowId)> {
+    let corrupt = || PersistenceError::Corruption("Invalid instance index key".to_string());
+
+    let (created_at, id) = suffix.split_first_chunk::<8>().ok_or_else(corrupt)?;
+    let id = std::str::from_utf8(id).map_err(|_| corrupt())?;
+    let id = uuid::Uuid::parse_str(id).map_err(|_| corrupt())?;
+    Ok((i64::from_be_bytes(*created_at), WorkflowId::from_uuid(id)))
+}
@@ -0,0 +1,195 @@
+//! Outbound HTTP host API for tasks
+//!
+//! Tasks with an `HttpPolicy` may make requests to the hosts it allows,
+//! within its body size and time limits. Redirects are followed only to
+//! allowed hosts.
+
+use crate::error::{RuntimeError, RuntimeResult};
+use crate::types::HttpPolicy;
+use reqwest::Url;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Default largest request or response body
+const DEFAULT_MAX_BODY_BYTES: u64 = 10 * 1024 * 1024;
+
+/// Default time one request may take
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Most redirects followed for one request
+const MAX_REDIRECTS: usize = 5;
+
+/// A request made by a task
+#[derive(Debug, Clone, Default)]
+pub struct FetchRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: BTreeMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+/// The response to a task's request
+#[derive(Debug, Clone)]
+pub struct FetchResponse {
+    pub status: u16,
+    pub headers: BTreeMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+/// Outbound HTTP of a task, held to its policy
+#[derive(Clone)]
+pub struct TaskHttp {
+    client: reqwest::Client,
+    allowed_hosts: Arc<Vec<String>>,
+    max_body_bytes: u64,
+}
+
+impl TaskHttp {
+    /// Create the outbound HTTP of a task with `policy`
+    pub fn new(policy: &HttpPolicy) -> RuntimeResult<Self> {
+        let allowed_hosts: Arc<Vec<String>> =
+            Arc::new(policy.allowed_hosts.iter().map(|host| host.to_ascii_lowercase()).collect());
+
+        let redirect_hosts = allowed_hosts.clone();
+        let redirect = reqwest::redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() >= MAX_REDIRECTS {
+                attempt.error(format!("more than {} redirects", MAX_REDIRECTS))
+            } else if let Err(e) = check_url(&redirect_hosts, attempt.url()) {
+                attempt.error(e.to_string())
+            } else {
+                attempt.follow()
+            }
+        });
+
+        let client = reqwest::Client::builder()
+            .redirect(redirect)
+            .timeout(policy.timeout_ms.map_or(DEFAULT_TIMEOUT, Duration::from_millis))
+            .build()
+            .map_err(|e| RuntimeError::Http(format!("Failed to create client: {}", e)))?;
+
+        Ok(Self {
+            client,
+            allowed_hosts,
+            max_body_bytes: policy.max_body_bytes.unwrap_or(DEFAULT_MAX_BODY_BYTES),
+        })
+    }
+
+    /// Make a request, reading the whole response
+    ///
+    /// Responses are returned whatever their status.
+    pub async fn fetch(&self, request: FetchRequest) -> RuntimeResult<FetchResponse> {
+        let url = Url::parse(&request.url)
+            .map_err(|e| RuntimeError::HttpNotAllowed(format!("invalid URL {}: {}", request.url, e)))?;
+        check_url(&self.allowed_hosts, &url)?;
+        if request.body.len() as u64 > self.max_body_bytes {
+            return Err(RuntimeError::Http(format!(
+                "request body exceeds {} bytes",
+                self.max_body_bytes
+            )));
+        }
+
+        let method = if request.method.is_empty() { "GET" } else { &request.method };
+        let method = reqwest::Method::from_bytes(method.to_ascii_uppercase().as_bytes())
+            .map_err(|e| RuntimeError::Http(format!("Invalid method {}: {}", request.method, e)))?;
+        let mut builder = self.client.request(method, url);
+        for (name, value) in &request.headers {
+            builder = builder.header(name, value);
+        }
+        if !request.body.is_empty() {
+            builder = builder.body(request.body);
+        }
+
+        let mut response = builder.send().await.map_err(|e| RuntimeError::Http(e.to_string()))?;
+        let status = response.status().as_u16();
+        let headers = response
+            .headers()
+            .iter()
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect();
+
+        // Read in chunks, so a large response is refused before it is held
+        let mut body = Vec::new();
+        while let Some(chunk) = response.chunk().await.map_err(|e| RuntimeError::Http(e.to_string()))? {
+            if (body.len() + chunk.len()) as u64 > self.max_body_bytes {
+                return Err(RuntimeError::Http(format!(
+                    "response body exceeds {} bytes",
+                    self.max_body_bytes
+                )));
+            }
+            body.extend_from_slice(&chunk);
+        }
+
+        Ok(FetchResponse { status, headers, body })
+    }
+}
+
+/// Check that a URL is HTTP(S) to an allowed host
+fn check_url(allowed_hosts: &[String], url: &Url) -> RuntimeResult<()> {
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(RuntimeError::HttpNotAllowed(format!("scheme {} of {}", url.scheme(), url)));
+    }
+    let host = url.host_str().unwrap_or_default();
+    if !host_allowed(allowed_hosts, host) {
+        return Err(RuntimeError::HttpNotAllowed(format!("host {}", host)));
+    }
+    Ok(())
+}
+
+/// Whether a host matches an entry of the allowlist
+///
+/// `*.example.org` matches the subdomains of `example.org`, but not
+/// `example.org` itself.
+fn host_allowed(allowed_hosts: &[String], host: &str) -> bool {
+    let host = host.to_ascii_lowercase();
+    allowed_hosts.iter().any(|allowed| match allowed.strip_prefix("*.") {
+        Some(domain) => host
+            .strip_suffix(domain)
+            .is_some_and(|sub| sub.len() > 1 && sub.ends_with('.')),
+        None => *allowed == host,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_host_allowed() {
+        let allowed = vec!["registry.example.org".to_string(), "*.gov.example".to_string()];
+
+        assert!(host_allowed(&allowed, "registry.example.org"));
+        assert!(host_allowed(&allowed, "Registry.Example.org"));
+        assert!(host_allowed(&allowed, "land.gov.example"));
+        assert!(host_allowed(&allowed, "a.land.gov.example"));
+        assert!(!host_allowed(&allowed, "gov.example"));
+        assert!(!host_allowed(&allowed, "evilgov.example"));
+        assert!(!host_allowed(&allowed, "registry.example.org.evil"));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_refuses_disallowed_urls() {
+        let http = TaskHttp::new(&HttpPolicy {
+            allowed_hosts: vec!["registry.example.org".to_string()],
+            max_body_bytes: Some(4),
+            timeout_ms: None,
+        })
+        .unwrap();
+
+        for url in ["https://other.example.org/", "file:///etc/passwd", "not a url"] {
+            let request = FetchRequest {
+                url: url.to_string(),
+                ..Default::default()
+            };
+            assert!(matches!(http.fetch(request).await, Err(RuntimeError::HttpNotAllowed(_))));
+        }
+
+        let request = FetchRequest {
+            method: "POST".to_string(),
+            url: "https://registry.example.org/".to_string(),
+            body: b"too long".to_vec(),
+            ..Default::default()
+        };
+        assert!(matches!(http.fetch(request).await, Err(RuntimeError::Http(_))));
+    }
+}
@@ -1,72 +1,148 @@
 //! JavaScript runtime using rquickjs
+//!
+//! Tasks run on a pool of long-lived QuickJS runtimes, each owned by its own
+//! thread. Every task gets a fresh context, a separate realm with its own
+//! globals, so tasks cannot see each other's state while the cost of
+//! creating a runtime is paid once per thread. Runtimes are recycled after a
+//! number of tasks, when they hold too much memory, or when a task timed out
+//! or panicked in them.
 
+use super::{FetchRequest, MAX_KV_RANGE_LIMIT, TaskHttp, TaskKv};
 use crate::error::{RuntimeError, RuntimeResult};
 use crate::types::{RuntimeType, TaskDefinition};
 use async_trait::async_trait;
-use rquickjs::{Context, Runtime as QjsRuntime};
-use std::time::Duration;
-use tokio::time::timeout;
+use parking_lot::Mutex;
+use rquickjs::function::Opt;
+use rquickjs::{Context, Ctx, Exception, Function, Object, Runtime as QjsRuntime};
+use std::panic::AssertUnwindSafe;
+use std::sync::Arc;
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+use tokio::runtime::Handle;
+use tokio::sync::oneshot;
+
+/// Tasks a runtime executes before it is replaced
+const MAX_EXECUTIONS_PER_RUNTIME: u32 = 1000;
+
+/// Memory a runtime may hold after a task before it is replaced
+const RECYCLE_MEMORY_BYTES: u64 = 32 * 1024 * 1024;
+
+/// Memory a single task may allocate in its runtime
+const MAX_RUNTIME_MEMORY_BYTES: usize = 256 * 1024 * 1024;
+
+/// Time past a task's deadline before its caller stops waiting for the
+/// interrupted script to return
+const TIMEOUT_GRACE: Duration = Duration::from_millis(500);
 
 /// JavaScript runtime implementation using rquickjs
 pub struct JavaScriptRuntime {
     timeout_duration: Duration,
+    /// Tasks waiting for a pooled runtime
+    jobs: mpsc::Sender<Job>,
+}
+
+/// Host APIs installed as globals of a task
+struct HostApis {
+    kv: Option<TaskKv>,
+    http: Option<TaskHttp>,
+    /// Handle the host calls block on
+    handle: Handle,
+}
+
+/// A task waiting for a pooled runtime
+struct Job {
+    code: String,
+    input: Vec<u8>,
+    host: Option<HostApis>,
+    /// When the script is interrupted
+    deadline: Instant,
+    timeout_ms: u64,
+    reply: oneshot::Sender<RuntimeResult<Vec<u8>>>,
+}
+
+/// A runtime owned by a pool thread
+struct PooledRuntime {
+    runtime: QjsRuntime,
+    /// Deadline of the running task, checked by the interrupt handler
+    deadline: Arc<Mutex<Option<Instant>>>,
+    executions: u32,
 }
 
 impl JavaScriptRuntime {
-    /// Create a new JavaScript runtime
+    /// Create a new JavaScript runtime with one pooled runtime per CPU
     pub fn new() -> Self {
-        Self {
-            timeout_duration: Duration::from_secs(30),
-        }
+        let size = std::thread::available_parallelism().map_or(4, |n| n.get());
+        Self::with_pool_size(size)
     }
 
     /// Create a new JavaScript runtime with custom timeout
     pub fn with_timeout(timeout_ms: u64) -> Self {
+        let mut runtime = Self::new();
+        runtime.timeout_duration = Duration::from_millis(timeout_ms);
+        runtime
+    }
+
+    /// Create a new JavaScript runtime running up to `size` tasks at once
+    ///
+    /// Further tasks wait for a free runtime, and that wait counts towards
+    /// their timeout; workers should run no more tasks at once than this.
+    pub fn with_pool_size(size: usize) -> Self {
+        let (jobs, receiver) = mpsc::channel();
+        let receiver = Arc::new(Mutex::new(receiver));
+        for i in 0..size.max(1) {
+            let receiver = receiver.clone();
+            let spawned = std::thread::Builder::new()
+                .name(format!("js-runtime-{}", i))
+                .spawn(move || pool_thread(receiver));
+            if let Err(e) = spawned {
+                tracing::error!("Failed to start JavaScript runtime thread: {}", e);
+            }
+        }
+
         Self {
-            timeout_duration: Duration::from_millis(timeout_ms),
+            timeout_duration: Duration::from_secs(30),
+            jobs,
         }
     }
 
-    /// Execute JavaScript code synchronously (internal)
-    fn execute_sync(&self, code: &str, input: &[u8]) -> RuntimeResult<Vec<u8>> {
-        // Create a new runtime for each execution (isolation)
-        let runtime = QjsRuntime::new().map_err(|e| {
-            RuntimeError::JavaScript(format!("Failed to create runtime: {}", e))
-        })?;
-
-        let context = Context::full(&runtime).map_err(|e| {
-            RuntimeError::JavaScript(format!("Failed to create context: {}", e))
+    /// Run a task on a pooled runtime, within its timeout
+    async fn run(&self, task: &TaskDefinition, input: &[u8], kv: Option<TaskKv>) -> RuntimeResult<Vec<u8>> {
+        let code = String::from_utf8(task.code.clone()).map_err(|e| {
+            RuntimeError::InvalidCode(format!("Invalid UTF-8 in JavaScript code: {}", e))
         })?;
 
-        context.with(|ctx| {
-            // Convert input bytes to JSON string
-            let input_str = String::from_utf8_lossy(input);
-            
-            // Inject input as global variable
-            let input_code = format!("globalThis.input = {};", input_str);
-            ctx.eval::<(), _>(input_code).map_err(|e| {
-                RuntimeError::JavaScript(format!("Failed to inject input: {}", e))
-            })?;
-
-            // Execute the user code
-            let result: rquickjs::Value = ctx.eval(code).map_err(|e| {
-                RuntimeError::JavaScript(format!("Execution error: {}", e))
-            })?;
+        let timeout_duration = if task.timeout_ms > 0 {
+            Duration::from_millis(task.timeout_ms)
+        } else {
+            self.timeout_duration
+        };
 
-            // Convert result to JSON
-            let json_result: Option<rquickjs::String> = ctx
-                .json_stringify(result)
-                .map_err(|e| {
-                    RuntimeError::JavaScript(format!("Failed to stringify result: {}", e))
-                })?;
+        let http = task.http.as_ref().map(TaskHttp::new).transpose()?;
+        let host = (kv.is_some() || http.is_some()).then(|| HostApis {
+            kv,
+            http,
+            handle: Handle::current(),
+        });
 
-            let json_str = json_result
-                .map(|s| s.to_string().map_err(|e| RuntimeError::JavaScript(format!("Failed to convert result: {}", e))))
-                .transpose()?
-                .unwrap_or_else(|| "null".to_string());
+        let deadline = Instant::now() + timeout_duration;
+        let (reply, result) = oneshot::channel();
+        self.jobs
+            .send(Job {
+                code,
+                input: input.to_vec(),
+                host,
+                deadline,
+                timeout_ms: task.timeout_ms,
+                reply,
+            })
+            .map_err(|_| RuntimeError::RuntimeNotAvailable("JavaScript runtime pool stopped".to_string()))?;
 
-            Ok(json_str.into_bytes())
-        })
+        // The interrupt handler stops the script at its deadline; this only
+        // covers a task stuck in a host call
+        tokio::time::timeout_at((deadline + TIMEOUT_GRACE).into(), result)
+            .await
+            .map_err(|_| RuntimeError::Timeout(task.timeout_ms))?
+            .map_err(|_| RuntimeError::JavaScript("Task execution error: runtime thread stopped".to_string()))?
     }
 }
 
@@ -76,32 +152,240 @@ impl Default for JavaScriptRuntime {
     }
 }
 
-#[async_trait]
-impl super::Runtime for JavaScriptRuntime {
-    async fn execute(&self, task: &TaskDefinition, input: &[u8]) -> RuntimeResult<Vec<u8>> {
-        let code = String::from_utf8(task.code.clone()).map_err(|e| {
-            RuntimeError::InvalidCode(format!("Invalid UTF-8 in JavaScript code: {}", e))
+impl PooledRuntime {
+    fn new() -> RuntimeResult<Self> {
+        let runtime = QjsRuntime::new().map_err(|e| {
+            RuntimeError::JavaScript(format!("Failed to create runtime: {}", e))
         })?;
+        runtime.set_memory_limit(MAX_RUNTIME_MEMORY_BYTES);
 
-        let timeout_duration = if task.timeout_ms > 0 {
-            Duration::from_millis(task.timeout_ms)
-        } else {
-            self.timeout_duration
+        let deadline: Arc<Mutex<Option<Instant>>> = Arc::new(Mutex::new(None));
+        let handler_deadline = deadline.clone();
+        runtime.set_interrupt_handler(Some(Box::new(move || {
+            handler_deadline.lock().is_some_and(|deadline| Instant::now() >= deadline)
+        })));
+
+        Ok(Self {
+            runtime,
+            deadline,
+            executions: 0,
+        })
+    }
+
+    /// Whether the runtime may take another task
+    fn healthy(&self) -> bool {
+        self.executions < MAX_EXECUTIONS_PER_RUNTIME
+            && (self.runtime.memory_usage().memory_used_size as u64) < RECYCLE_MEMORY_BYTES
+    }
+}
+
+/// Execute tasks on one pooled runtime until the pool is dropped
+fn pool_thread(jobs: Arc<Mutex<mpsc::Receiver<Job>>>) {
+    let mut pooled: Option<PooledRuntime> = None;
+    loop {
+        let Ok(job) = jobs.lock().recv() else {
+            return;
+        };
+        // The caller stopped waiting, e.g. after its timeout
+        if job.reply.is_closed() {
+            continue;
+        }
+
+        let mut runtime = match pooled.take() {
+            Some(runtime) => runtime,
+            None => match PooledRuntime::new() {
+                Ok(runtime) => runtime,
+                Err(e) => {
+                    let _ = job.reply.send(Err(e));
+                    continue;
+                }
+            },
+        };
+
+        *runtime.deadline.lock() = Some(job.deadline);
+        let executed = std::panic::catch_unwind(AssertUnwindSafe(|| {
+            execute_in(&runtime.runtime, &job.code, &job.input, job.host)
+        }));
+        *runtime.deadline.lock() = None;
+        runtime.executions += 1;
+
+        let timed_out = Instant::now() >= job.deadline;
+        let recycle = timed_out || executed.is_err();
+        let result = match executed {
+            Ok(Err(_)) if timed_out => Err(RuntimeError::Timeout(job.timeout_ms)),
+            Ok(result) => result,
+            Err(_) => Err(RuntimeError::JavaScript("Task execution error: task panicked".to_string())),
+        };
+        let _ = job.reply.send(result);
+
+        // Keep the runtime unless the task may have left it in a bad state
+        runtime.runtime.run_gc();
+        if !recycle && runtime.healthy() {
+            pooled = Some(runtime);
+        }
+    }
+}
+
+/// Execute JavaScript code in a fresh context of `runtime`
+///
+/// With `host`, the code can reach the key-value state of its instance
+/// through the `kv` global and make requests through `fetch`.
+fn execute_in(runtime: &QjsRuntime, code: &str, input: &[u8], host: Option<HostApis>) -> RuntimeResult<Vec<u8>> {
+    let context = Context::full(runtime).map_err(|e| {
+        RuntimeError::JavaScript(format!("Failed to create context: {}", e))
+    })?;
+
+    context.with(|ctx| {
+        // Convert input bytes to JSON string
+        let input_str = String::from_utf8_lossy(input);
+        
+        // Inject input as global variable
+        let input_code = format!("globalThis.input = {};", input_str);
+        ctx.eval::<(), _>(input_code).map_err(|e| {
+            RuntimeError::JavaScript(format!("Failed to inject input: {}", e))
+        })?;
+
+        if let Some(host) = host {
+            if let Some(kv) = host.kv {
+                install_kv(&ctx, kv, host.handle.clone()).map_err(|e| {
+                    RuntimeError::JavaScript(format!("Failed to install kv API: {}", e))
+                })?;
+            }
+            if let Some(http) = host.http {
+                install_fetch(&ctx, http, host.handle).map_err(|e| {
+                    RuntimeError::JavaScript(format!("Failed to install fetch: {}", e))
+                })?;
+            }
+        }
+
+        // Execute the user code
+        let result: rquickjs::Value = ctx.eval(code).map_err(|e| {
+            RuntimeError::JavaScript(format!("Execution error: {}", e))
+        })?;
+
+        // Convert result to JSON
+        let json_result: Option<rquickjs::String> = ctx
+            .json_stringify(result)
+            .map_err(|e| {
+                RuntimeError::JavaScript(format!("Failed to stringify result: {}", e))
+            })?;
+
+        let json_str = json_result
+            .map(|s| s.to_string().map_err(|e| RuntimeError::JavaScript(format!("Failed to convert result: {}", e))))
+            .transpose()?
+            .unwrap_or_else(|| "null".to_string());
+
+        Ok(json_str.into_bytes())
+    })
+}
+
+/// Install the `kv` global with `get`, `set`, `delete` and `range`
+///
+/// Keys and values are strings; `range(start, end, limit)` returns
+/// `[key, value]` pairs.
+fn install_kv<'js>(ctx: &Ctx<'js>, kv: TaskKv, handle: Handle) -> rquickjs::Result<()> {
+    let object = Object::new(ctx.clone())?;
+
+    let (get_kv, get_handle) = (kv.clone(), handle.clone());
+    let get = move |ctx: Ctx<'js>, key: String| -> rquickjs::Result<Option<String>> {
+        let value = get_handle.block_on(get_kv.get(key.as_bytes())).map_err(|e| throw(&ctx, e))?;
+        Ok(value.map(|v| String::from_utf8_lossy(&v).into_owned()))
+    };
+    object.set("get", Function::new(ctx.clone(), get)?)?;
+
+    let set_kv = kv.clone();
+    let set = move |ctx: Ctx<'js>, key: String, value: String| -> rquickjs::Result<()> {
+        set_kv.set(key.as_bytes(), value.as_bytes()).map_err(|e| throw(&ctx, e))
+    };
+    object.set("set", Function::new(ctx.clone(), set)?)?;
+
+    let delete_kv = kv.clone();
+    let delete = move |ctx: Ctx<'js>, key: String| -> rquickjs::Result<()> {
+        delete_kv.delete(key.as_bytes()).map_err(|e| throw(&ctx, e))
+    };
+    object.set("delete", Function::new(ctx.clone(), delete)?)?;
+
+    let range = move |ctx: Ctx<'js>, start: String, end: Opt<String>, limit: Opt<u32>| {
+        let end = end.0.unwrap_or_default();
+        let limit = limit.0.map_or(MAX_KV_RANGE_LIMIT, |limit| limit as usize);
+        let entries = handle
+            .block_on(kv.range(start.as_bytes(), end.as_bytes(), limit))
+            .map_err(|e| throw(&ctx, e))?;
+        let pairs: Vec<Vec<String>> = entries
+            .into_iter()
+            .map(|(key, value)| {
+                vec![
+                    String::from_utf8_lossy(&key).into_owned(),
+                    String::from_utf8_lossy(&value).into_owned(),
+                ]
+            })
+            .collect();
+        rquickjs::Result::Ok(pairs)
+    };
+    object.set("range", Function::new(ctx.clone(), range)?)?;
+
+    ctx.globals().set("kv", object)
+}
+
+/// Install the `fetch` global, which makes requests allowed by the task's
+/// HTTP policy
+///
+/// Unlike the browser `fetch`, it returns the response rather than a
+/// promise: `fetch(url, { method, headers, body })` gives `status`, `ok`,
+/// `headers`, `text()` and `json()`. Bodies are strings.
+fn install_fetch<'js>(ctx: &Ctx<'js>, http: TaskHttp, handle: Handle) -> rquickjs::Result<()> {
+    let fetch = move |ctx: Ctx<'js>, url: String, options: Opt<Object<'js>>| -> rquickjs::Result<Object<'js>> {
+        let mut request = FetchRequest {
+            url,
+            ..Default::default()
         };
+        if let Some(options) = options.0 {
+            request.method = options.get::<_, Option<String>>("method")?.unwrap_or_default();
+            if let Some(headers) = options.get::<_, Option<Object>>("headers")? {
+                for header in headers.props::<String, String>() {
+                    let (name, value) = header?;
+                    request.headers.insert(name, value);
+                }
+            }
+            request.body = options.get::<_, Option<String>>("body")?.unwrap_or_default().into_bytes();
+        }
+
+        let response = handle.block_on(http.fetch(request)).map_err(|e| throw(&ctx, e))?;
+
+        let object = Object::new(ctx.clone())?;
+        object.set("status", i32::from(response.status))?;
+        object.set("ok", (200..300).contains(&response.status))?;
+        let headers = Object::new(ctx.clone())?;
+        for (name, value) in response.headers {
+            headers.set(name, value)?;
+        }
+        object.set("headers", headers)?;
 
-        let input = input.to_vec();
-        let code_clone = code.clone();
+        let body = String::from_utf8_lossy(&response.body).into_owned();
+        let text = body.clone();
+        object.set("text", Function::new(ctx.clone(), move || text.clone())?)?;
+        let json = move |ctx: Ctx<'js>| ctx.json_parse(body.clone());
+        object.set("json", Function::new(ctx.clone(), json)?)?;
 
-        // Execute in a blocking task with timeout
-        let result = timeout(timeout_duration, tokio::task::spawn_blocking(move || {
-            let rt = JavaScriptRuntime::new();
-            rt.execute_sync(&code_clone, &input)
-        }))
-        .await
-        .map_err(|_| RuntimeError::Timeout(task.timeout_ms))?
-        .map_err(|e| RuntimeError::JavaScript(format!("Task execution error: {}", e)))?;
+        Ok(object)
+    };
+
+    ctx.globals().set("fetch", Function::new(ctx.clone(), fetch)?)
+}
+
+/// Raise a runtime error as a JavaScript exception
+fn throw(ctx: &Ctx<'_>, error: RuntimeError) -> rquickjs::Error {
+    Exception::throw_message(ctx, &error.to_string())
+}
 
-        result
+#[async_trait]
+impl super::Runtime for JavaScriptRuntime {
+    async fn execute(&self, task: &TaskDefinition, input: &[u8]) -> RuntimeResult<Vec<u8>> {
+        self.run(task, input, None).await
+    }
+
+    async fn execute_with_kv(&self, task: &TaskDefinition, input: &[u8], kv: TaskKv) -> RuntimeResult<Vec<u8>> {
+        self.run(task, input, Some(kv)).await
     }
 
     fn runtime_type(&self) -> RuntimeType {
@@ -122,7 +406,7 @@ mod tests {
             runtime_type: RuntimeType::JavaScript,
             code: b"input.value * 2".to_vec(),
             timeout_ms: 5000,
-            retry_policy: None,
+            ..Default::default()
         };
 
         let input = br#"{"value": 21}"#;
@@ -141,7 +425,7 @@ mod tests {
             runtime_type: RuntimeType::JavaScript,
             code: b"while(true) {}".to_vec(),
             timeout_ms: 100,
-            retry_policy: None,
+            ..Default::default()
         };
 
         let input = br#"{}"#;
@@ -150,5 +434,102 @@ mod tests {
         assert!(result.is_err());
         assert!(matches!(result.unwrap_err(), RuntimeError::Timeout(_)));
     }
+
+    #[tokio::test]
+    async fn test_pooled_runtime_isolation_and_recycling() {
+        use super::super::Runtime as _;
+        let runtime = JavaScriptRuntime::with_pool_size(1);
+        let task = |code: &str, timeout_ms: u64| TaskDefinition {
+            name: "test".to_string(),
+            runtime_type: RuntimeType::JavaScript,
+            code: code.as_bytes().to_vec(),
+            timeout_ms,
+            ..Default::default()
+        };
+
+        // Globals of one task are gone in the next one on the same runtime
+        runtime.execute(&task("globalThis.leak = 1; 1", 5000), b"{}").await.unwrap();
+        let result = runtime.execute(&task("typeof leak", 5000), b"{}").await.unwrap();
+        assert_eq!(result, br#""undefined""#);
+
+        // A script stuck in a loop is interrupted and its runtime replaced
+        let result = runtime.execute(&task("while(true) {}", 100), b"{}").await;
+        assert!(matches!(result, Err(RuntimeError::Timeout(100))));
+        let result = runtime.execute(&task("1 + 1", 5000), b"{}").await.unwrap();
+        assert_eq!(result, b"2");
+    }
+
+    #[tokio::test]
+    async fn test_kv_api() {
+        use super::super::{KvReader, Runtime as _};
+        use std::sync::Arc;
+
+        struct Empty;
+
+        #[async_trait]
+        impl KvReader for Empty {
+            async fn get(&self, _key: &[u8]) -> RuntimeResult<Option<Vec<u8>>> {
+                Ok(None)
+            }
+
+            async fn range(&self, _start: &[u8], _end: &[u8], _limit: usize) -> RuntimeResult<Vec<(Vec<u8>, Vec<u8>)>> {
+                Ok(Vec::new())
+            }
+        }
+
+        let runtime = JavaScriptRuntime::new();
+        let task = TaskDefinition {
+            name: "test".to_string(),
+            runtime_type: RuntimeType::JavaScript,
+            code: br#"
+                kv.set("count", String(Number(kv.get("count") || 0) + input.by));
+                kv.set("other", "x");
+                kv.delete("other");
+                [kv.get("count"), kv.range("").length]
+            "#
+            .to_vec(),
+            timeout_ms: 5000,
+            ..Default::default()
+        };
+
+        let kv = TaskKv::new(Arc::new(Empty));
+        let result = runtime.execute_with_kv(&task, br#"{"by": 2}"#, kv.clone()).await.unwrap();
+
+        assert_eq!(String::from_utf8(result).unwrap(), r#"["2",1]"#);
+        assert_eq!(kv.take_writes().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_outside_allowlist() {
+        use super::super::Runtime as _;
+        use crate::types::HttpPolicy;
+
+        let runtime = JavaScriptRuntime::new();
+        let mut task = TaskDefinition {
+            name: "test".to_string(),
+            runtime_type: RuntimeType::JavaScript,
+            code: br#"
+                try {
+                    fetch("https://elsewhere.example/").status
+                } catch (e) {
+                    String(e.message)
+                }
+            "#
+            .to_vec(),
+            timeout_ms: 5000,
+            ..Default::default()
+        };
+
+        // Without a policy there is no fetch at all
+        let result = runtime.execute(&task, b"{}").await.unwrap();
+        assert!(String::from_utf8(result).unwrap().contains("not defined"));
+
+        task.http = Some(HttpPolicy {
+            allowed_hosts: vec!["registry.example".to_string()],
+            ..Default::default()
+        });
+        let result = runtime.execute(&task, b"{}").await.unwrap();
+        assert!(String::from_utf8(result).unwrap().contains("not allowed"));
+    }
 }
 
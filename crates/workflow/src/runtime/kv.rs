@@ -0,0 +1,276 @@
+//! Key-value state host API for tasks
+//!
+//! Tasks keep key-value state scoped to their workflow instance. Reads go to
+//! the engine, while writes are buffered and committed in the transaction
+//! that records the task's completion, so a task that fails, times out or is
+//! retried leaves no partial writes behind.
+
+use crate::error::{RuntimeError, RuntimeResult};
+use crate::types::KvWrite;
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// Longest key a task may use
+pub const MAX_KV_KEY_BYTES: usize = 1024;
+
+/// Largest value a task may write, below the FDB value size limit
+pub const MAX_KV_VALUE_BYTES: usize = 90_000;
+
+/// Most key and value bytes one task may write, keeping its completion well
+/// within the FDB transaction size limit
+pub const MAX_KV_WRITE_BYTES: usize = 1_000_000;
+
+/// Most entries returned by one range read
+pub const MAX_KV_RANGE_LIMIT: usize = 1000;
+
+/// Committed key-value state of the instance a task runs for
+#[async_trait]
+pub trait KvReader: Send + Sync {
+    /// Get the value of a key
+    async fn get(&self, key: &[u8]) -> RuntimeResult<Option<Vec<u8>>>;
+
+    /// List up to `limit` entries with keys from `start` up to, but
+    /// excluding, `end` in key order; an empty `end` reads to the last key
+    async fn range(&self, start: &[u8], end: &[u8], limit: usize) -> RuntimeResult<Vec<(Vec<u8>, Vec<u8>)>>;
+}
+
+/// Key-value state of a task's instance, as the task sees it
+///
+/// Reads see the task's own writes.
+#[derive(Clone)]
+pub struct TaskKv {
+    reader: Arc<dyn KvReader>,
+    /// Buffered writes by key, `None` for deletes
+    writes: Arc<Mutex<BTreeMap<Vec<u8>, Option<Vec<u8>>>>>,
+}
+
+impl TaskKv {
+    /// Create the key-value state of a task reading through `reader`
+    pub fn new(reader: Arc<dyn KvReader>) -> Self {
+        Self {
+            reader,
+            writes: Arc::new(Mutex::new(BTreeMap::new())),
+        }
+    }
+
+    /// Get the value of a key
+    pub async fn get(&self, key: &[u8]) -> RuntimeResult<Option<Vec<u8>>> {
+        check_key(key)?;
+        if let Some(value) = self.writes.lock().get(key) {
+            return Ok(value.clone());
+        }
+        self.reader.get(key).await
+    }
+
+    /// Set the value of a key
+    pub fn set(&self, key: &[u8], value: &[u8]) -> RuntimeResult<()> {
+        check_key(key)?;
+        check_value(value)?;
+        self.write(key, Some(value.to_vec()))
+    }
+
+    /// Delete a key
+    pub fn delete(&self, key: &[u8]) -> RuntimeResult<()> {
+        check_key(key)?;
+        self.write(key, None)
+    }
+
+    /// List up to `limit` entries with keys from `start` up to, but
+    /// excluding, `end` in key order; an empty `end` reads to the last key
+    pub async fn range(&self, start: &[u8], end: &[u8], limit: usize) -> RuntimeResult<Vec<(Vec<u8>, Vec<u8>)>> {
+        let limit = limit.clamp(1, MAX_KV_RANGE_LIMIT);
+        let in_range = |key: &[u8]| key >= start && (end.is_empty() || key < end);
+        let buffered: Vec<_> = self
+            .writes
+            .lock()
+            .iter()
+            .filter(|(key, _)| in_range(key))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+
+        // Read past the limit by as many entries as deletes may remove
+        let fetch = limit + buffered.len();
+        let committed = self.reader.range(start, end, fetch).await?;
+        let last = (committed.len() >= fetch)
+            .then(|| committed.last().map(|(key, _)| key.clone()))
+            .flatten();
+
+        let mut entries: BTreeMap<Vec<u8>, Vec<u8>> = committed.into_iter().collect();
+        for (key, value) in buffered {
+            match value {
+                Some(value) => entries.insert(key, value),
+                None => entries.remove(&key),
+            };
+        }
+
+        // Past the last committed key read, committed keys may be missing
+        Ok(entries
+            .into_iter()
+            .filter(|(key, _)| last.as_ref().is_none_or(|last| key <= last))
+            .take(limit)
+            .collect())
+    }
+
+    /// Take the buffered writes, in key order
+    pub fn take_writes(&self) -> Vec<KvWrite> {
+        std::mem::take(&mut *self.writes.lock())
+            .into_iter()
+            .map(|(key, value)| match value {
+                Some(value) => KvWrite::Set { key, value },
+                None => KvWrite::Delete { key },
+            })
+            .collect()
+    }
+
+    fn write(&self, key: &[u8], value: Option<Vec<u8>>) -> RuntimeResult<()> {
+        let mut writes = self.writes.lock();
+        let written: usize = writes
+            .iter()
+            .filter(|(k, _)| k.as_slice() != key)
+            .map(|(k, v)| k.len() + v.as_ref().map_or(0, Vec::len))
+            .sum();
+        if written + key.len() + value.as_ref().map_or(0, Vec::len) > MAX_KV_WRITE_BYTES {
+            return Err(RuntimeError::KvBudgetExceeded(format!(
+                "writes exceed {} bytes",
+                MAX_KV_WRITE_BYTES
+            )));
+        }
+        writes.insert(key.to_vec(), value);
+        Ok(())
+    }
+}
+
+/// Check writes reported by a worker against the limits tasks are held to
+pub fn check_kv_writes(writes: &[KvWrite]) -> RuntimeResult<()> {
+    for write in writes {
+        check_key(write.key())?;
+        if let KvWrite::Set { value, .. } = write {
+            check_value(value)?;
+        }
+    }
+    let written: usize = writes.iter().map(KvWrite::size).sum();
+    if written > MAX_KV_WRITE_BYTES {
+        return Err(RuntimeError::KvBudgetExceeded(format!(
+            "writes exceed {} bytes",
+            MAX_KV_WRITE_BYTES
+        )));
+    }
+    Ok(())
+}
+
+fn check_key(key: &[u8]) -> RuntimeResult<()> {
+    if key.is_empty() || key.len() > MAX_KV_KEY_BYTES {
+        return Err(RuntimeError::KvBudgetExceeded(format!(
+            "keys must be 1 to {} bytes long",
+            MAX_KV_KEY_BYTES
+        )));
+    }
+    Ok(())
+}
+
+fn check_value(value: &[u8]) -> RuntimeResult<()> {
+    if value.len() > MAX_KV_VALUE_BYTES {
+        return Err(RuntimeError::KvBudgetExceeded(format!(
+            "values must be at most {} bytes long",
+            MAX_KV_VALUE_BYTES
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reader over a fixed set of committed entries
+    struct Committed(BTreeMap<Vec<u8>, Vec<u8>>);
+
+    #[async_trait]
+    impl KvReader for Committed {
+        async fn get(&self, key: &[u8]) -> RuntimeResult<Option<Vec<u8>>> {
+            Ok(self.0.get(key).cloned())
+        }
+
+        async fn range(&self, start: &[u8], end: &[u8], limit: usize) -> RuntimeResult<Vec<(Vec<u8>, Vec<u8>)>> {
+            Ok(self
+                .0
+                .iter()
+                .filter(|(key, _)| key.as_slice() >= start && (end.is_empty() || key.as_slice() < end))
+                .take(limit)
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect())
+        }
+    }
+
+    fn kv(entries: &[(&str, &str)]) -> TaskKv {
+        let committed = entries
+            .iter()
+            .map(|(key, value)| (key.as_bytes().to_vec(), value.as_bytes().to_vec()))
+            .collect();
+        TaskKv::new(Arc::new(Committed(committed)))
+    }
+
+    #[tokio::test]
+    async fn test_reads_see_own_writes() {
+        let kv = kv(&[("a", "1"), ("b", "2")]);
+        kv.set(b"a", b"3").unwrap();
+        kv.delete(b"b").unwrap();
+
+        assert_eq!(kv.get(b"a").await.unwrap(), Some(b"3".to_vec()));
+        assert_eq!(kv.get(b"b").await.unwrap(), None);
+        assert_eq!(
+            kv.take_writes(),
+            vec![
+                KvWrite::Set { key: b"a".to_vec(), value: b"3".to_vec() },
+                KvWrite::Delete { key: b"b".to_vec() },
+            ]
+        );
+        assert!(kv.take_writes().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_range_merges_buffered_writes() {
+        let kv = kv(&[("a", "1"), ("b", "2"), ("c", "3"), ("d", "4")]);
+        kv.delete(b"a").unwrap();
+        kv.set(b"bb", b"5").unwrap();
+
+        let keys: Vec<Vec<u8>> = kv.range(b"", b"", 3).await.unwrap().into_iter().map(|(k, _)| k).collect();
+        assert_eq!(keys, vec![b"b".to_vec(), b"bb".to_vec(), b"c".to_vec()]);
+
+        let keys: Vec<Vec<u8>> = kv.range(b"b", b"c", 10).await.unwrap().into_iter().map(|(k, _)| k).collect();
+        assert_eq!(keys, vec![b"b".to_vec(), b"bb".to_vec()]);
+    }
+
+    #[test]
+    fn test_write_budget() {
+        let kv = kv(&[]);
+        assert!(kv.set(b"", b"value").is_err());
+        assert!(kv.set(b"key", &vec![0; MAX_KV_VALUE_BYTES + 1]).is_err());
+
+        let value = vec![0; MAX_KV_VALUE_BYTES];
+        let mut written = 0;
+        let mut i = 0;
+        while written + value.len() + 8 <= MAX_KV_WRITE_BYTES {
+            kv.set(format!("key{:05}", i).as_bytes(), &value).unwrap();
+            written += value.len() + 8;
+            i += 1;
+        }
+        assert!(matches!(
+            kv.set(b"one more", &value),
+            Err(RuntimeError::KvBudgetExceeded(_))
+        ));
+        // Overwriting a key does not count its old value
+        kv.set(b"key00000", &value).unwrap();
+    }
+
+    #[test]
+    fn test_check_kv_writes() {
+        let writes = vec![KvWrite::Set { key: b"a".to_vec(), value: b"1".to_vec() }];
+        assert!(check_kv_writes(&writes).is_ok());
+
+        let writes = vec![KvWrite::Delete { key: vec![0; MAX_KV_KEY_BYTES + 1] }];
+        assert!(check_kv_writes(&writes).is_err());
+    }
+}
@@ -1,9 +1,19 @@
 //! Runtime abstraction for task execution
 
+mod http;
 mod javascript;
+mod kv;
+mod module_cache;
+mod plugin;
 mod wasm;
 
+pub use http::{FetchRequest, FetchResponse, TaskHttp};
 pub use javascript::JavaScriptRuntime;
+pub use kv::{
+    KvReader, MAX_KV_KEY_BYTES, MAX_KV_RANGE_LIMIT, MAX_KV_VALUE_BYTES, MAX_KV_WRITE_BYTES, TaskKv, check_kv_writes,
+};
+pub use module_cache::DEFAULT_MODULE_CACHE_SIZE;
+pub use plugin::{ExecRuntime, PluginManifest, load_plugin_dir};
 pub use wasm::WasmRuntime;
 
 use crate::error::RuntimeResult;
@@ -16,6 +26,14 @@ pub trait Runtime: Send + Sync {
     /// Execute a task and return the output
     async fn execute(&self, task: &TaskDefinition, input: &[u8]) -> RuntimeResult<Vec<u8>>;
 
+    /// Execute a task with access to the key-value state of its instance
+    ///
+    /// Runtimes without a key-value host API run the task without it.
+    async fn execute_with_kv(&self, task: &TaskDefinition, input: &[u8], kv: TaskKv) -> RuntimeResult<Vec<u8>> {
+        let _ = kv;
+        self.execute(task, input).await
+    }
+
     /// Get the runtime type
     fn runtime_type(&self) -> RuntimeType;
 
@@ -0,0 +1,176 @@
+//! Cache of compiled WASM modules
+//!
+//! Modules are keyed by the SHA-256 digest of their code. The most recently
+//! used ones are kept in memory; with a cache directory, compiled modules are
+//! also serialized to disk, so a restarted worker skips compiling code it has
+//! seen before.
+
+use crate::error::{RuntimeError, RuntimeResult};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use wasmtime::{Engine, Module};
+
+/// Default number of compiled modules kept in memory
+pub const DEFAULT_MODULE_CACHE_SIZE: usize = 64;
+
+/// Compiled modules by code digest
+pub(super) struct ModuleCache {
+    entries: Mutex<Entries>,
+    capacity: usize,
+    dir: Option<PathBuf>,
+}
+
+/// Modules kept in memory, with the tick they were last used at
+#[derive(Default)]
+struct Entries {
+    modules: HashMap<String, (Module, u64)>,
+    tick: u64,
+}
+
+impl ModuleCache {
+    /// Create a cache keeping up to `capacity` modules in memory
+    pub(super) fn new(capacity: usize) -> Self {
+        Self {
+            entries: Mutex::new(Entries::default()),
+            capacity: capacity.max(1),
+            dir: None,
+        }
+    }
+
+    /// Also keep compiled modules in `dir`
+    pub(super) fn with_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.dir = Some(dir.into());
+        self
+    }
+
+    /// Get the compiled module of `code`, compiling it on a miss
+    pub(super) async fn get_or_compile(&self, engine: &Engine, code: &[u8]) -> RuntimeResult<Module> {
+        let digest = degov_crypto::sha256_hex(code);
+        if let Some(module) = self.get(&digest) {
+            return Ok(module);
+        }
+
+        let module = match &self.dir {
+            Some(dir) => load_or_compile(engine, code, &dir.join(format!("{}.cwasm", digest))).await?,
+            None => compile(engine, code)?,
+        };
+        self.insert(digest, module.clone());
+        Ok(module)
+    }
+
+    fn get(&self, digest: &str) -> Option<Module> {
+        let mut entries = self.entries.lock();
+        entries.tick += 1;
+        let tick = entries.tick;
+        let (module, last_used) = entries.modules.get_mut(digest)?;
+        *last_used = tick;
+        Some(module.clone())
+    }
+
+    /// Keep a module, evicting the least recently used one when full
+    fn insert(&self, digest: String, module: Module) {
+        let mut entries = self.entries.lock();
+        entries.tick += 1;
+        let tick = entries.tick;
+        if entries.modules.len() >= self.capacity && !entries.modules.contains_key(&digest) {
+            let oldest = entries
+                .modules
+                .iter()
+                .min_by_key(|(_, (_, last_used))| *last_used)
+                .map(|(digest, _)| digest.clone());
+            if let Some(oldest) = oldest {
+                entries.modules.remove(&oldest);
+            }
+        }
+        entries.modules.insert(digest, (module, tick));
+    }
+
+    /// Number of modules kept in memory
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.entries.lock().modules.len()
+    }
+}
+
+fn compile(engine: &Engine, code: &[u8]) -> RuntimeResult<Module> {
+    Module::new(engine, code).map_err(|e| RuntimeError::Wasm(format!("Failed to load module: {}", e)))
+}
+
+/// Load a module serialized at `path`, or compile it and write it there
+///
+/// A file that cannot be loaded, e.g. one written by another wasmtime
+/// version, is replaced. Failing to write the file only costs the next
+/// worker a compilation.
+async fn load_or_compile(engine: &Engine, code: &[u8], path: &Path) -> RuntimeResult<Module> {
+    if tokio::fs::try_exists(path).await.unwrap_or(false) {
+        // SAFETY: the file was serialized by this cache, and wasmtime refuses
+        // files compiled for a different engine configuration
+        match unsafe { Module::deserialize_file(engine, path) } {
+            Ok(module) => return Ok(module),
+            Err(e) => tracing::warn!("Recompiling cached module {}: {}", path.display(), e),
+        }
+    }
+
+    let module = compile(engine, code)?;
+    if let Err(e) = write_serialized(&module, path).await {
+        tracing::warn!("Failed to cache compiled module {}: {}", path.display(), e);
+    }
+    Ok(module)
+}
+
+async fn write_serialized(module: &Module, path: &Path) -> Result<(), String> {
+    let bytes = module.serialize().map_err(|e| e.to_string())?;
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir).await.map_err(|e| e.to_string())?;
+    }
+    // Write under a temporary name so concurrent workers never load a
+    // partial file
+    let partial = path.with_extension(format!("{}.partial", uuid::Uuid::new_v4()));
+    tokio::fs::write(&partial, bytes).await.map_err(|e| e.to_string())?;
+    tokio::fs::rename(&partial, path).await.map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn module(result: i32) -> Vec<u8> {
+        format!(r#"(module (func (export "execute") (param i32 i32) (result i32) (i32.const {})))"#, result).into_bytes()
+    }
+
+    #[tokio::test]
+    async fn test_evicts_least_recently_used() {
+        let engine = Engine::default();
+        let cache = ModuleCache::new(2);
+
+        cache.get_or_compile(&engine, &module(1)).await.unwrap();
+        cache.get_or_compile(&engine, &module(2)).await.unwrap();
+        cache.get_or_compile(&engine, &module(1)).await.unwrap();
+        cache.get_or_compile(&engine, &module(3)).await.unwrap();
+
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get(&degov_crypto::sha256_hex(&module(1))).is_some());
+        assert!(cache.get(&degov_crypto::sha256_hex(&module(2))).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_disk_cache() {
+        let engine = Engine::default();
+        let dir = std::env::temp_dir().join(format!("degov-module-cache-test-{}", uuid::Uuid::new_v4()));
+        let code = module(7);
+        let path = dir.join(format!("{}.cwasm", degov_crypto::sha256_hex(&code)));
+
+        ModuleCache::new(1).with_dir(&dir).get_or_compile(&engine, &code).await.unwrap();
+        assert!(path.exists());
+
+        // A fresh cache loads the serialized module, and replaces a corrupt one
+        ModuleCache::new(1).with_dir(&dir).get_or_compile(&engine, &code).await.unwrap();
+        std::fs::write(&path, b"corrupt").unwrap();
+        ModuleCache::new(1).with_dir(&dir).get_or_compile(&engine, &code).await.unwrap();
+        assert!(unsafe { Module::deserialize_file(&engine, &path) }.is_ok());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
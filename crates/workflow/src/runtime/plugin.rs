@@ -0,0 +1,266 @@
+//! Runtimes provided by external executables
+//!
+//! A plugin directory holds one JSON manifest per runtime:
+//!
+//! ```json
+//! {
+//!   "name": "container",
+//!   "command": "docker",
+//!   "args": ["run", "--rm", "-i", "-v", "{code}:/task:ro", "degov/task-runner", "/task"]
+//! }
+//! ```
+//!
+//! For every task the command is spawned with the task input on stdin and its
+//! stdout is taken as the output. The task code is written to a file named by
+//! its digest, passed as `{code}` in the arguments and as `DEGOV_TASK_CODE` in
+//! the environment. A non-zero exit status fails the task with its stderr.
+
+use super::Runtime;
+use crate::error::{RuntimeError, RuntimeResult};
+use crate::types::{RuntimeType, TaskDefinition};
+use async_trait::async_trait;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::time::timeout;
+
+/// Manifest describing a plugin runtime
+#[derive(Debug, Clone, Deserialize)]
+pub struct PluginManifest {
+    /// Runtime name advertised to the engine
+    pub name: String,
+    /// Executable to run for each task
+    pub command: String,
+    /// Arguments, with `{code}` replaced by the path of the task code
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Extra environment variables
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+}
+
+impl PluginManifest {
+    /// Read a manifest file
+    pub fn load(path: &Path) -> RuntimeResult<Self> {
+        let content = std::fs::read(path)
+            .map_err(|e| RuntimeError::Plugin(format!("Failed to read {}: {}", path.display(), e)))?;
+        let manifest: Self = serde_json::from_slice(&content)
+            .map_err(|e| RuntimeError::Plugin(format!("Invalid manifest {}: {}", path.display(), e)))?;
+        manifest.validate()?;
+        Ok(manifest)
+    }
+
+    fn validate(&self) -> RuntimeResult<()> {
+        let valid = !self.name.is_empty()
+            && self
+                .name
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_');
+        if !valid {
+            return Err(RuntimeError::Plugin(format!(
+                "Invalid runtime name '{}': use lowercase letters, digits, '-' and '_'",
+                self.name
+            )));
+        }
+        if !matches!(RuntimeType::from_name(&self.name), RuntimeType::Plugin(_)) {
+            return Err(RuntimeError::Plugin(format!(
+                "Runtime name '{}' is reserved for a built-in runtime",
+                self.name
+            )));
+        }
+        if self.command.is_empty() {
+            return Err(RuntimeError::Plugin(format!("Runtime '{}' has no command", self.name)));
+        }
+        Ok(())
+    }
+}
+
+/// Runtime that executes tasks by spawning a plugin command
+pub struct ExecRuntime {
+    manifest: PluginManifest,
+    code_dir: PathBuf,
+    timeout_duration: Duration,
+}
+
+impl ExecRuntime {
+    /// Create a runtime from its manifest
+    pub fn new(manifest: PluginManifest) -> Self {
+        let code_dir = std::env::temp_dir().join("degov-plugins").join(&manifest.name);
+        Self {
+            manifest,
+            code_dir,
+            timeout_duration: Duration::from_secs(30),
+        }
+    }
+
+    /// Store task code in `dir` instead of the system temp directory
+    pub fn with_code_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.code_dir = dir.into();
+        self
+    }
+
+    /// Get the plugin manifest
+    pub fn manifest(&self) -> &PluginManifest {
+        &self.manifest
+    }
+
+    /// Write the task code once per digest and return its path
+    async fn code_path(&self, code: &[u8]) -> RuntimeResult<PathBuf> {
+        let path = self.code_dir.join(degov_crypto::sha256_hex(code));
+        if tokio::fs::try_exists(&path).await.unwrap_or(false) {
+            return Ok(path);
+        }
+
+        tokio::fs::create_dir_all(&self.code_dir)
+            .await
+            .map_err(|e| RuntimeError::Plugin(format!("Failed to create code directory: {}", e)))?;
+        // Write under a temporary name so concurrent tasks never see a partial file
+        let partial = path.with_extension(format!("{}.partial", uuid::Uuid::new_v4()));
+        tokio::fs::write(&partial, code)
+            .await
+            .map_err(|e| RuntimeError::Plugin(format!("Failed to write task code: {}", e)))?;
+        tokio::fs::rename(&partial, &path)
+            .await
+            .map_err(|e| RuntimeError::Plugin(format!("Failed to write task code: {}", e)))?;
+        Ok(path)
+    }
+
+    async fn run(&self, task: &TaskDefinition, code: &Path, input: &[u8]) -> RuntimeResult<Vec<u8>> {
+        let code = code.to_string_lossy();
+        let mut child = Command::new(&self.manifest.command)
+            .args(self.manifest.args.iter().map(|arg| arg.replace("{code}", &code)))
+            .envs(&self.manifest.env)
+            .env("DEGOV_TASK_CODE", code.as_ref())
+            .env("DEGOV_TASK_NAME", &task.name)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| {
+                RuntimeError::Plugin(format!("Failed to spawn '{}': {}", self.manifest.command, e))
+            })?;
+
+        if let Some(mut stdin) = child.stdin.take() {
+            // A plugin may exit without reading its input; its exit status tells
+            stdin.write_all(input).await.ok();
+        }
+
+        let output = child
+            .wait_with_output()
+            .await
+            .map_err(|e| RuntimeError::Plugin(format!("Failed to wait for plugin: {}", e)))?;
+        if !output.status.success() {
+            return Err(RuntimeError::Execution(format!(
+                "Plugin '{}' exited with {}: {}",
+                self.manifest.name,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        Ok(output.stdout)
+    }
+}
+
+#[async_trait]
+impl Runtime for ExecRuntime {
+    async fn execute(&self, task: &TaskDefinition, input: &[u8]) -> RuntimeResult<Vec<u8>> {
+        let timeout_duration = if task.timeout_ms > 0 {
+            Duration::from_millis(task.timeout_ms)
+        } else {
+            self.timeout_duration
+        };
+
+        let code = self.code_path(&task.code).await?;
+        timeout(timeout_duration, self.run(task, &code, input))
+            .await
+            .map_err(|_| RuntimeError::Timeout(timeout_duration.as_millis() as u64))?
+    }
+
+    fn runtime_type(&self) -> RuntimeType {
+        RuntimeType::Plugin(self.manifest.name.clone())
+    }
+}
+
+/// Load a runtime for every `*.json` manifest in `dir`, ordered by file name
+pub fn load_plugin_dir(dir: &Path) -> RuntimeResult<Vec<ExecRuntime>> {
+    let entries = std::fs::read_dir(dir)
+        .map_err(|e| RuntimeError::Plugin(format!("Failed to read {}: {}", dir.display(), e)))?;
+
+    let mut manifests = Vec::new();
+    for entry in entries {
+        let path = entry
+            .map_err(|e| RuntimeError::Plugin(format!("Failed to read {}: {}", dir.display(), e)))?
+            .path();
+        if path.extension().is_some_and(|ext| ext == "json") {
+            manifests.push(path);
+        }
+    }
+    manifests.sort();
+
+    manifests
+        .iter()
+        .map(|path| PluginManifest::load(path).map(ExecRuntime::new))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn task(code: &[u8]) -> TaskDefinition {
+        TaskDefinition {
+            name: "echo".to_string(),
+            runtime_type: RuntimeType::Plugin("shell".to_string()),
+            code: code.to_vec(),
+            timeout_ms: 5000,
+            ..Default::default()
+        }
+    }
+
+    fn shell_runtime(dir: &Path) -> ExecRuntime {
+        ExecRuntime::new(PluginManifest {
+            name: "shell".to_string(),
+            command: "sh".to_string(),
+            args: vec!["{code}".to_string()],
+            env: BTreeMap::new(),
+        })
+        .with_code_dir(dir)
+    }
+
+    #[tokio::test]
+    async fn test_exec_runtime() {
+        let dir = std::env::temp_dir().join(format!("degov-plugin-test-{}", uuid::Uuid::new_v4()));
+        let runtime = shell_runtime(&dir);
+        assert_eq!(runtime.runtime_type().as_str(), "shell");
+
+        let output = runtime.execute(&task(b"tr a-z A-Z"), b"hello").await.unwrap();
+        assert_eq!(output, b"HELLO");
+
+        let error = runtime.execute(&task(b"echo broken >&2; exit 3"), b"").await.unwrap_err();
+        assert!(error.to_string().contains("broken"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_load_plugin_dir() {
+        let dir = std::env::temp_dir().join(format!("degov-plugin-test-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("container.json"), r#"{"name": "container", "command": "docker"}"#).unwrap();
+        std::fs::write(dir.join("README.md"), "not a manifest").unwrap();
+
+        let runtimes = load_plugin_dir(&dir).unwrap();
+        assert_eq!(runtimes.len(), 1);
+        assert_eq!(runtimes[0].runtime_type(), RuntimeType::Plugin("container".to_string()));
+
+        std::fs::write(dir.join("wasm.json"), r#"{"name": "wasm", "command": "wasmer"}"#).unwrap();
+        assert!(load_plugin_dir(&dir).is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
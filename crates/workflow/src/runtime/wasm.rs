@@ -1,17 +1,55 @@
 //! WASM runtime using wasmtime
 
+use super::module_cache::{DEFAULT_MODULE_CACHE_SIZE, ModuleCache};
+use super::{FetchRequest, TaskHttp, TaskKv};
 use crate::error::{RuntimeError, RuntimeResult};
-use crate::types::{RuntimeType, TaskDefinition};
+use crate::types::{RuntimeType, TaskDefinition, WasmLimits};
 use async_trait::async_trait;
+use std::path::PathBuf;
 use std::time::Duration;
 use tokio::time::timeout;
 use wasmtime::*;
-use wasmtime_wasi::WasiCtxBuilder;
+use wasmtime_wasi::{WasiCtx, WasiCtxBuilder};
+
+/// Interval at which the engine epoch advances; interruption deadlines are
+/// rounded up to it
+const EPOCH_TICK: Duration = Duration::from_millis(10);
 
 /// WASM runtime implementation using wasmtime
 pub struct WasmRuntime {
     engine: Engine,
     timeout_duration: Duration,
+    /// Compiled modules, so repeated tasks skip compilation
+    modules: ModuleCache,
+}
+
+/// Data of the store a task runs in
+struct TaskState {
+    _wasi: WasiCtx,
+    memory: MemoryLimiter,
+    kv: Option<TaskKv>,
+    http: Option<TaskHttp>,
+}
+
+/// Refuses memory growth past a task's limit, remembering that it did so
+struct MemoryLimiter {
+    max_bytes: Option<u64>,
+    exceeded: bool,
+}
+
+impl ResourceLimiter for MemoryLimiter {
+    fn memory_growing(&mut self, _current: usize, desired: usize, _maximum: Option<usize>) -> Result<bool> {
+        if self.max_bytes.is_some_and(|max| desired as u64 > max) {
+            // Trap rather than fail the grow, so the task cannot carry on
+            self.exceeded = true;
+            return Err(Error::msg(format!("memory would grow to {} bytes", desired)));
+        }
+        Ok(true)
+    }
+
+    fn table_growing(&mut self, _current: usize, _desired: usize, _maximum: Option<usize>) -> Result<bool> {
+        Ok(true)
+    }
 }
 
 impl WasmRuntime {
@@ -20,13 +58,30 @@ impl WasmRuntime {
         let mut config = Config::new();
         config.async_support(true);
         config.wasm_component_model(false); // Enable when ready for component model
+        config.consume_fuel(true);
+        config.epoch_interruption(true);
 
         let engine = Engine::new(&config)
             .map_err(|e| RuntimeError::Wasm(format!("Failed to create engine: {}", e)))?;
 
+        // Advance the epoch for as long as the engine lives, so running tasks
+        // can be interrupted
+        let weak = engine.weak();
+        std::thread::Builder::new()
+            .name("wasm-epoch".to_string())
+            .spawn(move || {
+                while let Some(engine) = weak.upgrade() {
+                    engine.increment_epoch();
+                    drop(engine);
+                    std::thread::sleep(EPOCH_TICK);
+                }
+            })
+            .map_err(|e| RuntimeError::Wasm(format!("Failed to start epoch thread: {}", e)))?;
+
         Ok(Self {
             engine,
             timeout_duration: Duration::from_secs(30),
+            modules: ModuleCache::new(DEFAULT_MODULE_CACHE_SIZE),
         })
     }
 
@@ -37,10 +92,43 @@ impl WasmRuntime {
         Ok(runtime)
     }
 
-    /// Execute WASM module
-    async fn execute_wasm(&self, wasm_bytes: &[u8], input: &[u8]) -> RuntimeResult<Vec<u8>> {
+    /// Keep up to `capacity` compiled modules in memory, and with `dir`
+    /// also serialize them there
+    ///
+    /// Modules in `dir` are loaded without being validated again, so it must
+    /// only be writable by the worker.
+    pub fn with_module_cache(mut self, capacity: usize, dir: Option<PathBuf>) -> Self {
+        self.modules = ModuleCache::new(capacity);
+        if let Some(dir) = dir {
+            self.modules = self.modules.with_dir(dir);
+        }
+        self
+    }
+
+    /// Execute WASM module within `limits`, interrupting it after
+    /// `interrupt_after`
+    ///
+    /// With `kv`, the module may import the `kv` host functions, and with
+    /// `http` the `http` ones.
+    async fn execute_wasm(
+        &self,
+        wasm_bytes: &[u8],
+        input: &[u8],
+        limits: &WasmLimits,
+        interrupt_after: Duration,
+        kv: Option<TaskKv>,
+        http: Option<TaskHttp>,
+    ) -> RuntimeResult<Vec<u8>> {
         // Create a new store for each execution
         let mut linker = Linker::new(&self.engine);
+        if kv.is_some() {
+            link_kv(&mut linker)
+                .map_err(|e| RuntimeError::Wasm(format!("Failed to link kv functions: {}", e)))?;
+        }
+        if http.is_some() {
+            link_http(&mut linker)
+                .map_err(|e| RuntimeError::Wasm(format!("Failed to link http functions: {}", e)))?;
+        }
         
         // Add WASI support (wasmtime 37 API)
         // Note: For wasmtime 37, WASI linker setup is done differently
@@ -50,17 +138,31 @@ impl WasmRuntime {
             .inherit_stdio()
             .build();
 
-        let mut store = Store::new(&self.engine, wasi);
+        let state = TaskState {
+            _wasi: wasi,
+            memory: MemoryLimiter {
+                max_bytes: limits.max_memory_bytes,
+                exceeded: false,
+            },
+            kv,
+            http,
+        };
+        let mut store = Store::new(&self.engine, state);
+        store.limiter(|state| &mut state.memory);
+        store
+            .set_fuel(limits.fuel.unwrap_or(u64::MAX))
+            .map_err(|e| RuntimeError::Wasm(format!("Failed to set fuel: {}", e)))?;
+        store.set_epoch_deadline(epoch_ticks(interrupt_after));
+        store.epoch_deadline_trap();
 
-        // Load the WASM module
-        let module = Module::new(&self.engine, wasm_bytes)
-            .map_err(|e| RuntimeError::Wasm(format!("Failed to load module: {}", e)))?;
+        // Load the WASM module, compiling it unless cached
+        let module = self.modules.get_or_compile(&self.engine, wasm_bytes).await?;
 
         // Instantiate the module
         let instance = linker
             .instantiate_async(&mut store, &module)
             .await
-            .map_err(|e| RuntimeError::Wasm(format!("Failed to instantiate: {}", e)))?;
+            .map_err(|e| limit_error(&store, limits, interrupt_after, e, "Failed to instantiate"))?;
 
         // Look for the execute function
         let execute_func = instance
@@ -77,39 +179,250 @@ impl WasmRuntime {
         let result_ptr = execute_func
             .call_async(&mut store, (input_ptr, input_len))
             .await
-            .map_err(|e| RuntimeError::Wasm(format!("Execution error: {}", e)))?;
+            .map_err(|e| limit_error(&store, limits, interrupt_after, e, "Execution error"))?;
 
         // For now, return a simple result
         // Real implementation would read from WASM memory
         Ok(vec![result_ptr as u8])
     }
-}
-
-impl Default for WasmRuntime {
-    fn default() -> Self {
-        Self::new().expect("Failed to create default WASM runtime")
-    }
-}
 
-#[async_trait]
-impl super::Runtime for WasmRuntime {
-    async fn execute(&self, task: &TaskDefinition, input: &[u8]) -> RuntimeResult<Vec<u8>> {
+    /// Run a task within its limits and timeout
+    async fn run(&self, task: &TaskDefinition, input: &[u8], kv: Option<TaskKv>) -> RuntimeResult<Vec<u8>> {
         let timeout_duration = if task.timeout_ms > 0 {
             Duration::from_millis(task.timeout_ms)
         } else {
             self.timeout_duration
         };
 
+        // The epoch deadline stops tasks stuck in a loop, which never yield
+        // to the timeout below
+        let limits = task.wasm_limits.clone().unwrap_or_default();
+        let http = task.http.as_ref().map(TaskHttp::new).transpose()?;
+        let interrupt_after = limits
+            .interrupt_after_ms
+            .map(Duration::from_millis)
+            .unwrap_or(timeout_duration);
+
         // Execute with timeout
         let result = timeout(
             timeout_duration,
-            self.execute_wasm(&task.code, input),
+            self.execute_wasm(&task.code, input, &limits, interrupt_after, kv, http),
         )
         .await
         .map_err(|_| RuntimeError::Timeout(task.timeout_ms))??;
 
         Ok(result)
     }
+}
+
+/// Link the `kv` host functions, which reach the key-value state of the
+/// task's instance
+///
+/// Keys and values are passed as pointer and length into the module's
+/// exported `memory`:
+///
+/// - `get(key_ptr, key_len, out_ptr, out_cap) -> i32`
+/// - `set(key_ptr, key_len, value_ptr, value_len)`
+/// - `delete(key_ptr, key_len)`
+/// - `range(start_ptr, start_len, end_ptr, end_len, limit, out_ptr, out_cap) -> i32`
+///
+/// `get` and `range` write their result to `out_ptr` when it fits in
+/// `out_cap` bytes and return its length either way, so the module can
+/// retry with a larger buffer; `get` returns `-1` for a key that is not set.
+/// `range` encodes each entry as a little-endian `u32` key length, the key,
+/// a `u32` value length and the value.
+fn link_kv(linker: &mut Linker<TaskState>) -> Result<()> {
+    linker.func_wrap_async(
+        "kv",
+        "get",
+        |mut caller: Caller<'_, TaskState>, (key_ptr, key_len, out_ptr, out_cap): (i32, i32, i32, i32)| {
+            Box::new(async move {
+                let kv = task_kv(&caller)?;
+                let key = read_guest(&mut caller, key_ptr, key_len)?;
+                match kv.get(&key).await? {
+                    Some(value) => write_guest(&mut caller, out_ptr, out_cap, &value),
+                    None => Ok(-1),
+                }
+            })
+        },
+    )?;
+
+    linker.func_wrap(
+        "kv",
+        "set",
+        |mut caller: Caller<'_, TaskState>, key_ptr: i32, key_len: i32, value_ptr: i32, value_len: i32| -> Result<()> {
+            let kv = task_kv(&caller)?;
+            let key = read_guest(&mut caller, key_ptr, key_len)?;
+            let value = read_guest(&mut caller, value_ptr, value_len)?;
+            Ok(kv.set(&key, &value)?)
+        },
+    )?;
+
+    linker.func_wrap(
+        "kv",
+        "delete",
+        |mut caller: Caller<'_, TaskState>, key_ptr: i32, key_len: i32| -> Result<()> {
+            let kv = task_kv(&caller)?;
+            let key = read_guest(&mut caller, key_ptr, key_len)?;
+            Ok(kv.delete(&key)?)
+        },
+    )?;
+
+    linker.func_wrap_async(
+        "kv",
+        "range",
+        |mut caller: Caller<'_, TaskState>,
+         (start_ptr, start_len, end_ptr, end_len, limit, out_ptr, out_cap): (i32, i32, i32, i32, i32, i32, i32)| {
+            Box::new(async move {
+                let kv = task_kv(&caller)?;
+                let start = read_guest(&mut caller, start_ptr, start_len)?;
+                let end = read_guest(&mut caller, end_ptr, end_len)?;
+                let limit = usize::try_from(limit).unwrap_or_default();
+
+                let mut encoded = Vec::new();
+                for (key, value) in kv.range(&start, &end, limit).await? {
+                    encoded.extend_from_slice(&(key.len() as u32).to_le_bytes());
+                    encoded.extend_from_slice(&key);
+                    encoded.extend_from_slice(&(value.len() as u32).to_le_bytes());
+                    encoded.extend_from_slice(&value);
+                }
+                write_guest(&mut caller, out_ptr, out_cap, &encoded)
+            })
+        },
+    )?;
+
+    Ok(())
+}
+
+/// Link the `http` host functions, which make the requests allowed by the
+/// task's HTTP policy
+///
+/// The runtime runs core modules, so tasks use this import rather than
+/// `wasi:http`:
+///
+/// - `fetch(head_ptr, head_len, body_ptr, body_len, out_ptr, out_cap) -> i32`
+///
+/// The request head is JSON, `{"method": "GET", "url": "...", "headers":
+/// {...}}`. The response is written to `out_ptr` as a little-endian `u32`
+/// head length, the JSON head `{"status": 200, "headers": {...}}` and the
+/// body, and its length is returned either way. A response that does not fit
+/// in `out_cap` bytes is dropped, so the buffer should cover the policy's
+/// body limit.
+fn link_http(linker: &mut Linker<TaskState>) -> Result<()> {
+    linker.func_wrap_async(
+        "http",
+        "fetch",
+        |mut caller: Caller<'_, TaskState>,
+         (head_ptr, head_len, body_ptr, body_len, out_ptr, out_cap): (i32, i32, i32, i32, i32, i32)| {
+            Box::new(async move {
+                let http = caller
+                    .data()
+                    .http
+                    .clone()
+                    .ok_or_else(|| Error::msg("no HTTP policy for this task"))?;
+                let head: serde_json::Value = serde_json::from_slice(&read_guest(&mut caller, head_ptr, head_len)?)?;
+                let request = FetchRequest {
+                    method: head["method"].as_str().unwrap_or_default().to_string(),
+                    url: head["url"].as_str().unwrap_or_default().to_string(),
+                    headers: serde_json::from_value(head["headers"].clone()).unwrap_or_default(),
+                    body: read_guest(&mut caller, body_ptr, body_len)?,
+                };
+
+                let response = http.fetch(request).await?;
+                let head = serde_json::to_vec(&serde_json::json!({
+                    "status": response.status,
+                    "headers": response.headers,
+                }))?;
+                let mut encoded = Vec::with_capacity(4 + head.len() + response.body.len());
+                encoded.extend_from_slice(&(head.len() as u32).to_le_bytes());
+                encoded.extend_from_slice(&head);
+                encoded.extend_from_slice(&response.body);
+                write_guest(&mut caller, out_ptr, out_cap, &encoded)
+            })
+        },
+    )?;
+
+    Ok(())
+}
+
+fn task_kv(caller: &Caller<'_, TaskState>) -> Result<TaskKv> {
+    caller
+        .data()
+        .kv
+        .clone()
+        .ok_or_else(|| Error::msg("no key-value state for this task"))
+}
+
+fn guest_memory(caller: &mut Caller<'_, TaskState>) -> Result<Memory> {
+    caller
+        .get_export("memory")
+        .and_then(Extern::into_memory)
+        .ok_or_else(|| Error::msg("module does not export its memory"))
+}
+
+/// Copy bytes out of the module's memory
+fn read_guest(caller: &mut Caller<'_, TaskState>, ptr: i32, len: i32) -> Result<Vec<u8>> {
+    let memory = guest_memory(caller)?;
+    let mut bytes = vec![0; usize::try_from(len)?];
+    memory.read(&*caller, usize::try_from(ptr)?, &mut bytes)?;
+    Ok(bytes)
+}
+
+/// Copy a result into the module's memory if it fits, returning its length
+fn write_guest(caller: &mut Caller<'_, TaskState>, ptr: i32, cap: i32, bytes: &[u8]) -> Result<i32> {
+    let len = i32::try_from(bytes.len())?;
+    if len <= cap {
+        let memory = guest_memory(caller)?;
+        memory.write(&mut *caller, usize::try_from(ptr)?, bytes)?;
+    }
+    Ok(len)
+}
+
+/// Convert an error raised while running a task into the limit it hit, if
+/// any
+fn limit_error(
+    store: &Store<TaskState>,
+    limits: &WasmLimits,
+    interrupt_after: Duration,
+    error: Error,
+    context: &str,
+) -> RuntimeError {
+    if store.data().memory.exceeded {
+        return RuntimeError::MemoryLimitExceeded(limits.max_memory_bytes.unwrap_or_default());
+    }
+    // Errors of the kv and http host functions are passed through as they
+    // are
+    let error = match error.downcast::<RuntimeError>() {
+        Ok(error) => return error,
+        Err(error) => error,
+    };
+    match error.downcast_ref::<Trap>() {
+        Some(Trap::OutOfFuel) => RuntimeError::FuelExhausted(limits.fuel.unwrap_or(u64::MAX)),
+        Some(Trap::Interrupt) => RuntimeError::Interrupted(interrupt_after.as_millis() as u64),
+        _ => RuntimeError::Wasm(format!("{}: {}", context, error)),
+    }
+}
+
+/// Number of epoch ticks covering `duration`, at least one
+fn epoch_ticks(duration: Duration) -> u64 {
+    duration.as_millis().div_ceil(EPOCH_TICK.as_millis()).max(1) as u64
+}
+
+impl Default for WasmRuntime {
+    fn default() -> Self {
+        Self::new().expect("Failed to create default WASM runtime")
+    }
+}
+
+#[async_trait]
+impl super::Runtime for WasmRuntime {
+    async fn execute(&self, task: &TaskDefinition, input: &[u8]) -> RuntimeResult<Vec<u8>> {
+        self.run(task, input, None).await
+    }
+
+    async fn execute_with_kv(&self, task: &TaskDefinition, input: &[u8], kv: TaskKv) -> RuntimeResult<Vec<u8>> {
+        self.run(task, input, Some(kv)).await
+    }
 
     fn runtime_type(&self) -> RuntimeType {
         RuntimeType::Wasm
@@ -125,5 +438,134 @@ mod tests {
         let runtime = WasmRuntime::new();
         assert!(runtime.is_ok());
     }
+
+    fn wasm_task(wat: &str, limits: WasmLimits) -> TaskDefinition {
+        TaskDefinition {
+            name: "test".to_string(),
+            runtime_type: RuntimeType::Wasm,
+            code: wat.as_bytes().to_vec(),
+            timeout_ms: 5000,
+            wasm_limits: Some(limits),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fuel_exhausted() {
+        use super::super::Runtime as _;
+        let runtime = WasmRuntime::new().unwrap();
+        let task = wasm_task(
+            r#"(module (func (export "execute") (param i32 i32) (result i32) (loop (br 0)) (i32.const 0)))"#,
+            WasmLimits {
+                fuel: Some(10_000),
+                ..Default::default()
+            },
+        );
+
+        let result = runtime.execute(&task, b"{}").await;
+        assert!(matches!(result.unwrap_err(), RuntimeError::FuelExhausted(10_000)));
+    }
+
+    #[tokio::test]
+    async fn test_memory_limit_exceeded() {
+        use super::super::Runtime as _;
+        let runtime = WasmRuntime::new().unwrap();
+        let task = wasm_task(
+            r#"(module (memory 1) (func (export "execute") (param i32 i32) (result i32) (memory.grow (i32.const 4))))"#,
+            WasmLimits {
+                max_memory_bytes: Some(2 * 65536),
+                ..Default::default()
+            },
+        );
+
+        let result = runtime.execute(&task, b"{}").await;
+        assert!(matches!(result.unwrap_err(), RuntimeError::MemoryLimitExceeded(131072)));
+    }
+
+    #[tokio::test]
+    async fn test_interrupted() {
+        use super::super::Runtime as _;
+        let runtime = WasmRuntime::new().unwrap();
+        let task = wasm_task(
+            r#"(module (func (export "execute") (param i32 i32) (result i32) (loop (br 0)) (i32.const 0)))"#,
+            WasmLimits {
+                interrupt_after_ms: Some(50),
+                ..Default::default()
+            },
+        );
+
+        let result = runtime.execute(&task, b"{}").await;
+        assert!(matches!(result.unwrap_err(), RuntimeError::Interrupted(50)));
+    }
+
+    #[tokio::test]
+    async fn test_kv_set() {
+        use super::super::{KvReader, Runtime as _};
+        use crate::types::KvWrite;
+        use std::sync::Arc;
+
+        struct Empty;
+
+        #[async_trait]
+        impl KvReader for Empty {
+            async fn get(&self, _key: &[u8]) -> RuntimeResult<Option<Vec<u8>>> {
+                Ok(None)
+            }
+
+            async fn range(&self, _start: &[u8], _end: &[u8], _limit: usize) -> RuntimeResult<Vec<(Vec<u8>, Vec<u8>)>> {
+                Ok(Vec::new())
+            }
+        }
+
+        let runtime = WasmRuntime::new().unwrap();
+        let task = wasm_task(
+            r#"(module
+                (import "kv" "set" (func $set (param i32 i32 i32 i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 16) "kv")
+                (func (export "execute") (param i32 i32) (result i32)
+                    (call $set (i32.const 16) (i32.const 1) (i32.const 17) (i32.const 1))
+                    (i32.const 0)))"#,
+            WasmLimits::default(),
+        );
+
+        let kv = TaskKv::new(Arc::new(Empty));
+        runtime.execute_with_kv(&task, b"{}", kv.clone()).await.unwrap();
+        assert_eq!(
+            kv.take_writes(),
+            vec![KvWrite::Set { key: b"k".to_vec(), value: b"v".to_vec() }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetch_outside_allowlist() {
+        use super::super::Runtime as _;
+        use crate::types::HttpPolicy;
+
+        let runtime = WasmRuntime::new().unwrap();
+        let mut task = wasm_task(
+            r#"(module
+                (import "http" "fetch" (func $fetch (param i32 i32 i32 i32 i32 i32) (result i32)))
+                (memory (export "memory") 1)
+                (data (i32.const 16) "{\"url\":\"https://elsewhere.example/\"}")
+                (func (export "execute") (param i32 i32) (result i32)
+                    (call $fetch (i32.const 16) (i32.const 36) (i32.const 0) (i32.const 0) (i32.const 1024) (i32.const 1024))))"#,
+            WasmLimits::default(),
+        );
+        task.http = Some(HttpPolicy {
+            allowed_hosts: vec!["registry.example".to_string()],
+            ..Default::default()
+        });
+
+        let result = runtime.execute(&task, b"{}").await;
+        assert!(matches!(result, Err(RuntimeError::HttpNotAllowed(_))));
+    }
+
+    #[test]
+    fn test_epoch_ticks_round_up() {
+        assert_eq!(epoch_ticks(Duration::ZERO), 1);
+        assert_eq!(epoch_ticks(Duration::from_millis(10)), 1);
+        assert_eq!(epoch_ticks(Duration::from_millis(11)), 2);
+    }
 }
 
@@ -0,0 +1,99 @@
+//! Change-feed consumer keeping the search index up to date
+
+use super::{SearchIndex, SearchSchema};
+use crate::error::SearchResult;
+use crate::persistence::PersistenceLayer;
+use crate::types::WorkflowId;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Name under which the indexer persists its change-feed cursor
+const INDEXER_CONSUMER: &str = "search-indexer";
+
+/// How often the indexer polls for new changes once caught up
+const INDEXER_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Maximum number of changes applied per batch
+const INDEXER_BATCH_SIZE: usize = 256;
+
+/// Tails the change feed and applies instance writes to a search index
+///
+/// Each change is applied by re-reading the current instance, so replaying
+/// part of the feed after a crash is harmless. The cursor is saved after
+/// every batch.
+pub struct Indexer {
+    persistence: Arc<PersistenceLayer>,
+    index: Arc<dyn SearchIndex>,
+    schema: Arc<SearchSchema>,
+}
+
+impl Indexer {
+    pub fn new(
+        persistence: Arc<PersistenceLayer>,
+        index: Arc<dyn SearchIndex>,
+        schema: Arc<SearchSchema>,
+    ) -> Self {
+        Self {
+            persistence,
+            index,
+            schema,
+        }
+    }
+
+    /// Index changes forever
+    pub async fn run(self) {
+        let mut interval = tokio::time::interval(INDEXER_POLL_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Err(e) = self.catch_up().await {
+                tracing::error!("Search indexing failed: {}", e);
+            }
+        }
+    }
+
+    /// Apply all changes committed since the saved cursor
+    ///
+    /// Returns the number of changes read.
+    pub async fn catch_up(&self) -> SearchResult<usize> {
+        let feed = self.persistence.change_feed();
+        let mut cursor = feed.cursor(INDEXER_CONSUMER).await?;
+        let mut processed = 0;
+
+        loop {
+            let events = feed.read(cursor.as_deref(), INDEXER_BATCH_SIZE).await?;
+            let Some(last) = events.last() else {
+                return Ok(processed);
+            };
+            let next = last.cursor.clone();
+
+            // An instance written several times in a batch is indexed once
+            let mut seen = HashSet::new();
+            for event in events.iter().rev() {
+                if seen.insert(event.workflow_id) {
+                    self.apply(event.workflow_id, event.deleted).await?;
+                }
+            }
+
+            processed += events.len();
+            feed.save_cursor(INDEXER_CONSUMER, &next).await?;
+            cursor = Some(next);
+        }
+    }
+
+    async fn apply(&self, workflow_id: WorkflowId, deleted: bool) -> SearchResult<()> {
+        let instance = if deleted {
+            None
+        } else {
+            self.persistence
+                .workflows()
+                .get_instance(&workflow_id)
+                .await?
+        };
+
+        match instance.and_then(|instance| self.schema.document(&instance)) {
+            Some(document) => self.index.upsert(document).await,
+            None => self.index.remove(&workflow_id).await,
+        }
+    }
+}
@@ -0,0 +1,237 @@
+//! Embedded in-memory search index
+
+use super::{
+    FieldFilter, SearchDocument, SearchHit, SearchIndex, SearchQuery, SearchResults, SearchValue,
+    tokenize,
+};
+use crate::error::SearchResult;
+use crate::types::WorkflowId;
+use async_trait::async_trait;
+use parking_lot::RwLock;
+use std::collections::HashMap;
+
+/// An indexed document with its term frequencies
+struct Entry {
+    document: SearchDocument,
+    terms: HashMap<String, u32>,
+}
+
+/// Search index held in engine memory
+///
+/// Suited to single-node deployments and tests; the index is rebuilt from
+/// the change feed when the indexer starts without a saved cursor.
+#[derive(Default)]
+pub struct MemoryIndex {
+    entries: RwLock<HashMap<WorkflowId, Entry>>,
+}
+
+impl MemoryIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of indexed documents
+    pub fn len(&self) -> usize {
+        self.entries.read().len()
+    }
+
+    /// Whether the index is empty
+    pub fn is_empty(&self) -> bool {
+        self.entries.read().is_empty()
+    }
+}
+
+#[async_trait]
+impl SearchIndex for MemoryIndex {
+    async fn upsert(&self, document: SearchDocument) -> SearchResult<()> {
+        let mut terms = HashMap::new();
+        for value in document.fields.values() {
+            if let SearchValue::Text(text) = value {
+                for term in tokenize(text) {
+                    *terms.entry(term).or_insert(0) += 1;
+                }
+            }
+        }
+
+        self.entries
+            .write()
+            .insert(document.workflow_id, Entry { document, terms });
+        Ok(())
+    }
+
+    async fn remove(&self, workflow_id: &WorkflowId) -> SearchResult<()> {
+        self.entries.write().remove(workflow_id);
+        Ok(())
+    }
+
+    async fn search(&self, query: &SearchQuery) -> SearchResult<SearchResults> {
+        let terms: Vec<String> = query
+            .text
+            .as_deref()
+            .map(|t| tokenize(t).collect())
+            .unwrap_or_default();
+
+        let entries = self.entries.read();
+        let mut matches: Vec<(&Entry, f32)> = entries
+            .values()
+            .filter(|entry| matches_structured(&entry.document, query))
+            .filter_map(|entry| {
+                let mut score = 0;
+                for term in &terms {
+                    score += *entry.terms.get(term)?;
+                }
+                Some((entry, score as f32))
+            })
+            .collect();
+
+        // Best score first, most recently updated first among equal scores
+        matches.sort_by(|(a, a_score), (b, b_score)| {
+            b_score
+                .total_cmp(a_score)
+                .then_with(|| b.document.updated_at.cmp(&a.document.updated_at))
+        });
+
+        Ok(SearchResults {
+            total: matches.len() as u64,
+            hits: matches
+                .into_iter()
+                .skip(query.offset)
+                .take(query.limit)
+                .map(|(entry, score)| SearchHit {
+                    workflow_id: entry.document.workflow_id,
+                    score,
+                })
+                .collect(),
+        })
+    }
+}
+
+fn matches_structured(document: &SearchDocument, query: &SearchQuery) -> bool {
+    query
+        .definition_id
+        .is_none_or(|id| document.definition_id == id)
+        && query.status.is_none_or(|status| document.status == status)
+        && query
+            .filters
+            .iter()
+            .all(|filter| matches_filter(document, filter))
+}
+
+fn matches_filter(document: &SearchDocument, filter: &FieldFilter) -> bool {
+    match filter {
+        FieldFilter::Equals { field, value } => match document.fields.get(field) {
+            Some(SearchValue::Keyword(v)) | Some(SearchValue::Text(v)) => v == value,
+            Some(SearchValue::Integer(n)) => value.parse() == Ok(*n),
+            None => false,
+        },
+        FieldFilter::Range { field, min, max } => match document.fields.get(field) {
+            Some(SearchValue::Integer(n)) => {
+                min.is_none_or(|min| *n >= min) && max.is_none_or(|max| *n <= max)
+            }
+            _ => false,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::{SearchField, SearchSchema};
+    use crate::types::{WorkflowInstance, WorkflowStatus};
+    use chrono::Utc;
+    use serde_json::json;
+
+    fn instance(definition_id: WorkflowId, context: serde_json::Value) -> WorkflowInstance {
+        WorkflowInstance {
+            id: WorkflowId::new(),
+            definition_id,
+            current_state: "review".to_string(),
+            context,
+            status: WorkflowStatus::Running,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            completed_at: None,
+            parent: None,
+            awaiting_children: Vec::new(),
+            compensation: None,
+            branches: Vec::new(),
+            awaited_tasks: Vec::new(),
+            idempotency_key: None,
+            state_entry: 0,
+            paused_at: None,
+            held_tasks: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_search() {
+        let definition_id = WorkflowId::new();
+        let schema = SearchSchema::new().with_fields(
+            definition_id,
+            vec![
+                SearchField::text("applicant.name"),
+                SearchField::keyword("district"),
+                SearchField::integer("employees"),
+            ],
+        );
+
+        let index = MemoryIndex::new();
+        let bakery = instance(
+            definition_id,
+            json!({ "applicant": { "name": "Erika's Bakery" }, "district": "mitte", "employees": 4 }),
+        );
+        let brewery = instance(
+            definition_id,
+            json!({ "applicant": { "name": "Spree Brewery" }, "district": "kreuzberg", "employees": "40" }),
+        );
+        index
+            .upsert(schema.document(&bakery).unwrap())
+            .await
+            .unwrap();
+        index
+            .upsert(schema.document(&brewery).unwrap())
+            .await
+            .unwrap();
+
+        let text = SearchQuery {
+            text: Some("BAKERY".to_string()),
+            ..Default::default()
+        };
+        let results = index.search(&text).await.unwrap();
+        assert_eq!(results.total, 1);
+        assert_eq!(results.hits[0].workflow_id, bakery.id);
+
+        let structured = SearchQuery {
+            filters: vec![FieldFilter::Range {
+                field: "employees".to_string(),
+                min: Some(10),
+                max: None,
+            }],
+            ..Default::default()
+        };
+        let results = index.search(&structured).await.unwrap();
+        assert_eq!(results.hits[0].workflow_id, brewery.id);
+
+        let keyword = SearchQuery {
+            filters: vec![FieldFilter::Equals {
+                field: "district".to_string(),
+                value: "mitte".to_string(),
+            }],
+            ..Default::default()
+        };
+        assert_eq!(index.search(&keyword).await.unwrap().total, 1);
+
+        index.remove(&bakery.id).await.unwrap();
+        assert_eq!(index.search(&text).await.unwrap().total, 0);
+    }
+
+    #[test]
+    fn test_unsearchable_definition() {
+        let schema = SearchSchema::new();
+        assert!(
+            schema
+                .document(&instance(WorkflowId::new(), json!({})))
+                .is_none()
+        );
+    }
+}
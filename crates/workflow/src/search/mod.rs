@@ -0,0 +1,224 @@
+//! Search over workflow instances
+//!
+//! An [`Indexer`] tails the persistence change feed and keeps a
+//! [`SearchIndex`] up to date with the context fields each workflow
+//! definition declares searchable (`searchable #true` on a DGL model field).
+//! The index itself is pluggable: [`MemoryIndex`] is embedded in the engine,
+//! [`OpenSearchIndex`] talks to an external cluster.
+
+mod indexer;
+mod memory;
+mod opensearch;
+
+pub use indexer::Indexer;
+pub use memory::MemoryIndex;
+pub use opensearch::OpenSearchIndex;
+
+use crate::error::SearchResult;
+use crate::types::{WorkflowId, WorkflowInstance, WorkflowStatus};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// How a searchable field is indexed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SearchFieldKind {
+    /// Tokenized for full-text queries
+    Text,
+    /// Matched exactly in filters
+    Keyword,
+    /// Matched by range in filters
+    Integer,
+}
+
+/// A context field declared searchable
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SearchField {
+    /// Dotted path into the instance context
+    pub path: String,
+    pub kind: SearchFieldKind,
+}
+
+impl SearchField {
+    pub fn text(path: impl Into<String>) -> Self {
+        Self {
+            path: path.into(),
+            kind: SearchFieldKind::Text,
+        }
+    }
+
+    pub fn keyword(path: impl Into<String>) -> Self {
+        Self {
+            path: path.into(),
+            kind: SearchFieldKind::Keyword,
+        }
+    }
+
+    pub fn integer(path: impl Into<String>) -> Self {
+        Self {
+            path: path.into(),
+            kind: SearchFieldKind::Integer,
+        }
+    }
+
+    /// Extract this field's value from a context
+    fn extract(&self, context: &serde_json::Value) -> Option<SearchValue> {
+        let value = self
+            .path
+            .split('.')
+            .try_fold(context, |value, segment| value.get(segment))?;
+
+        match (self.kind, value) {
+            (_, serde_json::Value::Null) => None,
+            (SearchFieldKind::Integer, value) => value
+                .as_i64()
+                .or_else(|| value.as_str().and_then(|s| s.trim().parse().ok()))
+                .map(SearchValue::Integer),
+            (kind, serde_json::Value::String(s)) => Some(SearchValue::string(kind, s.clone())),
+            (kind, value) => Some(SearchValue::string(kind, value.to_string())),
+        }
+    }
+}
+
+/// Searchable fields per workflow definition
+#[derive(Debug, Clone, Default)]
+pub struct SearchSchema {
+    definitions: HashMap<WorkflowId, Vec<SearchField>>,
+}
+
+impl SearchSchema {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Declare the searchable fields of a workflow definition
+    pub fn with_fields(mut self, definition_id: WorkflowId, fields: Vec<SearchField>) -> Self {
+        self.definitions.insert(definition_id, fields);
+        self
+    }
+
+    /// Get the searchable fields of a workflow definition
+    pub fn fields(&self, definition_id: &WorkflowId) -> Option<&[SearchField]> {
+        self.definitions.get(definition_id).map(Vec::as_slice)
+    }
+
+    /// Build the index document of an instance
+    ///
+    /// Returns `None` for instances of definitions without searchable fields.
+    pub fn document(&self, instance: &WorkflowInstance) -> Option<SearchDocument> {
+        let fields = self.fields(&instance.definition_id)?;
+        Some(SearchDocument {
+            workflow_id: instance.id,
+            definition_id: instance.definition_id,
+            status: instance.status,
+            current_state: instance.current_state.clone(),
+            updated_at: instance.updated_at,
+            fields: fields
+                .iter()
+                .filter_map(|f| Some((f.path.clone(), f.extract(&instance.context)?)))
+                .collect(),
+        })
+    }
+}
+
+/// Indexed value of a searchable field
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SearchValue {
+    Text(String),
+    Keyword(String),
+    Integer(i64),
+}
+
+impl SearchValue {
+    fn string(kind: SearchFieldKind, value: String) -> Self {
+        match kind {
+            SearchFieldKind::Keyword => SearchValue::Keyword(value),
+            _ => SearchValue::Text(value),
+        }
+    }
+}
+
+/// A workflow instance as stored in the search index
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SearchDocument {
+    pub workflow_id: WorkflowId,
+    pub definition_id: WorkflowId,
+    pub status: WorkflowStatus,
+    pub current_state: String,
+    pub updated_at: DateTime<Utc>,
+    pub fields: BTreeMap<String, SearchValue>,
+}
+
+/// Structured condition on a searchable field
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FieldFilter {
+    /// Keyword or integer field equals the value
+    Equals { field: String, value: String },
+    /// Integer field lies within the inclusive bounds
+    Range {
+        field: String,
+        min: Option<i64>,
+        max: Option<i64>,
+    },
+}
+
+/// A search over workflow instances
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchQuery {
+    /// Free text matched against text fields; all terms must match
+    pub text: Option<String>,
+    pub definition_id: Option<WorkflowId>,
+    pub status: Option<WorkflowStatus>,
+    pub filters: Vec<FieldFilter>,
+    pub limit: usize,
+    pub offset: usize,
+}
+
+impl Default for SearchQuery {
+    fn default() -> Self {
+        Self {
+            text: None,
+            definition_id: None,
+            status: None,
+            filters: Vec::new(),
+            limit: 20,
+            offset: 0,
+        }
+    }
+}
+
+/// A matching instance
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchHit {
+    pub workflow_id: WorkflowId,
+    pub score: f32,
+}
+
+/// One page of search results
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SearchResults {
+    pub hits: Vec<SearchHit>,
+    /// Number of matches across all pages
+    pub total: u64,
+}
+
+/// Backend storing and querying instance documents
+#[async_trait]
+pub trait SearchIndex: Send + Sync {
+    /// Insert or replace a document
+    async fn upsert(&self, document: SearchDocument) -> SearchResult<()>;
+
+    /// Remove the document of an instance, if indexed
+    async fn remove(&self, workflow_id: &WorkflowId) -> SearchResult<()>;
+
+    /// Run a query
+    async fn search(&self, query: &SearchQuery) -> SearchResult<SearchResults>;
+}
+
+/// Split text into lowercase alphanumeric terms
+pub(crate) fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|term| !term.is_empty())
+        .map(str::to_lowercase)
+}
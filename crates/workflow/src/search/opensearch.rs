@@ -0,0 +1,195 @@
+//! Search index backed by an external OpenSearch cluster
+
+use super::{FieldFilter, SearchDocument, SearchHit, SearchIndex, SearchQuery, SearchResults};
+use crate::error::{SearchError, SearchResult};
+use crate::types::WorkflowId;
+use async_trait::async_trait;
+use reqwest::StatusCode;
+use serde::Deserialize;
+use serde_json::{Value, json};
+
+/// Search index stored in an OpenSearch (or Elasticsearch) index
+///
+/// Documents are indexed with dynamic mapping: string fields get a `text`
+/// mapping with a `.keyword` sub-field, integers a `long` mapping.
+pub struct OpenSearchIndex {
+    client: reqwest::Client,
+    base_url: String,
+    index: String,
+}
+
+impl OpenSearchIndex {
+    /// Create an index client for `index` on the cluster at `base_url`
+    pub fn new(base_url: impl Into<String>, index: impl Into<String>) -> Self {
+        Self::with_client(reqwest::Client::new(), base_url, index)
+    }
+
+    /// Create an index client using a preconfigured HTTP client, e.g. with
+    /// authentication headers or custom TLS roots
+    pub fn with_client(
+        client: reqwest::Client,
+        base_url: impl Into<String>,
+        index: impl Into<String>,
+    ) -> Self {
+        Self {
+            client,
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            index: index.into(),
+        }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}/{}/{}", self.base_url, self.index, path)
+    }
+}
+
+#[async_trait]
+impl SearchIndex for OpenSearchIndex {
+    async fn upsert(&self, document: SearchDocument) -> SearchResult<()> {
+        let response = self
+            .client
+            .put(self.url(&format!("_doc/{}", document.workflow_id)))
+            .json(&document)
+            .send()
+            .await?;
+        check_status(response).await?;
+        Ok(())
+    }
+
+    async fn remove(&self, workflow_id: &WorkflowId) -> SearchResult<()> {
+        let response = self
+            .client
+            .delete(self.url(&format!("_doc/{}", workflow_id)))
+            .send()
+            .await?;
+
+        // Removing a document that was never indexed is not an error
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(());
+        }
+        check_status(response).await?;
+        Ok(())
+    }
+
+    async fn search(&self, query: &SearchQuery) -> SearchResult<SearchResults> {
+        let response = self
+            .client
+            .post(self.url("_search"))
+            .json(&search_body(query))
+            .send()
+            .await?;
+        let body: SearchResponse = check_status(response).await?.json().await?;
+
+        let hits = body
+            .hits
+            .hits
+            .into_iter()
+            .map(|hit| {
+                let workflow_id = uuid::Uuid::parse_str(&hit.id)
+                    .map(WorkflowId::from_uuid)
+                    .map_err(|e| {
+                        SearchError::Backend(format!("Invalid document id {}: {}", hit.id, e))
+                    })?;
+                Ok(SearchHit {
+                    workflow_id,
+                    score: hit.score.unwrap_or_default(),
+                })
+            })
+            .collect::<SearchResult<_>>()?;
+
+        Ok(SearchResults {
+            hits,
+            total: body.hits.total.value,
+        })
+    }
+}
+
+/// Build the `_search` request body of a query
+fn search_body(query: &SearchQuery) -> Value {
+    let mut must = Vec::new();
+    if let Some(text) = query.text.as_deref().filter(|t| !t.trim().is_empty()) {
+        must.push(json!({
+            "simple_query_string": {
+                "query": text,
+                "fields": ["fields.*"],
+                "default_operator": "and",
+                "lenient": true,
+            }
+        }));
+    }
+
+    let mut filter = Vec::new();
+    if let Some(definition_id) = &query.definition_id {
+        filter.push(json!({ "term": { "definition_id.keyword": definition_id.to_string() } }));
+    }
+    if let Some(status) = &query.status {
+        filter.push(json!({ "term": { "status.keyword": status } }));
+    }
+    for condition in &query.filters {
+        filter.push(match condition {
+            FieldFilter::Equals { field, value } => {
+                let mut should =
+                    vec![json!({ "term": { format!("fields.{}.keyword", field): value } })];
+                // A numeric term against a string mapping would fail the
+                // whole query, so only match the long mapping on numbers
+                if let Ok(number) = value.parse::<i64>() {
+                    should.push(json!({ "term": { format!("fields.{}", field): number } }));
+                }
+                json!({ "bool": { "should": should, "minimum_should_match": 1 } })
+            }
+            FieldFilter::Range { field, min, max } => {
+                let mut bounds = serde_json::Map::new();
+                if let Some(min) = min {
+                    bounds.insert("gte".to_string(), json!(min));
+                }
+                if let Some(max) = max {
+                    bounds.insert("lte".to_string(), json!(max));
+                }
+                json!({ "range": { format!("fields.{}", field): bounds } })
+            }
+        });
+    }
+
+    json!({
+        "from": query.offset,
+        "size": query.limit,
+        "track_total_hits": true,
+        "_source": false,
+        "query": { "bool": { "must": must, "filter": filter } },
+        "sort": ["_score", { "updated_at": "desc" }],
+    })
+}
+
+async fn check_status(response: reqwest::Response) -> SearchResult<reqwest::Response> {
+    if response.status().is_success() {
+        return Ok(response);
+    }
+
+    let status = response.status();
+    let body = response.text().await.unwrap_or_default();
+    Err(SearchError::Backend(format!("{}: {}", status, body)))
+}
+
+#[derive(Deserialize)]
+struct SearchResponse {
+    hits: SearchResponseHits,
+}
+
+#[derive(Deserialize)]
+struct SearchResponseHits {
+    total: SearchResponseTotal,
+    hits: Vec<SearchResponseHit>,
+}
+
+#[derive(Deserialize)]
+struct SearchResponseTotal {
+    value: u64,
+}
+
+#[derive(Deserialize)]
+struct SearchResponseHit {
+    #[serde(rename = "_id")]
+    id: String,
+    #[serde(rename = "_score")]
+    score: Option<f32>,
+}
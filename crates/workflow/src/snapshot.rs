@@ -0,0 +1,311 @@
+//! Portable snapshots of engine state
+//!
+//! A snapshot holds every workflow definition and, optionally, every
+//! instance together with its pending timers, as a single JSON document.
+//! Restoring one into another environment clones production-shaped data
+//! into staging in one step.
+//!
+//! Snapshots do not carry instance history, tasks, human tasks or pending
+//! HTTP requests. Restored instances waiting for a task get it enqueued
+//! again by recovery once the heartbeat timeout has passed.
+
+use crate::error::{WorkflowError, WorkflowResult};
+use crate::state_machine::Action;
+use crate::types::{ScheduledTimer, WorkflowDefinition, WorkflowId, WorkflowInstance};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Version of the snapshot layout, bumped on incompatible changes
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// Definitions and instances of an engine at one point in time
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u32,
+    pub created_at: DateTime<Utc>,
+    pub definitions: Vec<WorkflowDefinition>,
+    /// Empty unless instances were included
+    #[serde(default)]
+    pub instances: Vec<WorkflowInstance>,
+    /// Pending timers of the included instances
+    #[serde(default)]
+    pub timers: Vec<ScheduledTimer>,
+}
+
+impl Snapshot {
+    /// Create an empty snapshot
+    pub fn new() -> Self {
+        Self {
+            version: SNAPSHOT_VERSION,
+            created_at: Utc::now(),
+            definitions: Vec::new(),
+            instances: Vec::new(),
+            timers: Vec::new(),
+        }
+    }
+
+    /// Check that the snapshot can be restored by this version
+    pub fn check_version(&self) -> WorkflowResult<()> {
+        if self.version != SNAPSHOT_VERSION {
+            return Err(WorkflowError::UnsupportedSnapshot {
+                found: self.version,
+                expected: SNAPSHOT_VERSION,
+            });
+        }
+        Ok(())
+    }
+
+    /// Rewrite definition and instance IDs as asked for by `options`
+    ///
+    /// References follow the IDs they point to: instances to their
+    /// definition, parent and children, timers to their instance and child
+    /// workflow actions to the definition they start. References to
+    /// definitions or instances outside the snapshot are left as they are
+    /// unless mapped explicitly.
+    pub fn remap(&mut self, options: &RestoreOptions) {
+        let mut definitions = options.definition_ids.clone();
+        for definition in &self.definitions {
+            definitions
+                .entry(definition.id)
+                .or_insert_with(|| options.fresh_id(definition.id));
+        }
+        let instances: HashMap<WorkflowId, WorkflowId> = self
+            .instances
+            .iter()
+            .map(|instance| (instance.id, options.fresh_id(instance.id)))
+            .collect();
+
+        let definition_id = |id: &mut WorkflowId| {
+            if let Some(to) = definitions.get(id) {
+                *id = *to;
+            }
+        };
+        let instance_id = |id: &mut WorkflowId| {
+            if let Some(to) = instances.get(id) {
+                *id = *to;
+            }
+        };
+        let action = |action: &mut Action| {
+            if let Action::StartChildWorkflow { definition_id: id, .. } = action {
+                definition_id(id);
+            }
+        };
+
+        for definition in &mut self.definitions {
+            definition_id(&mut definition.id);
+            definition.state_machine.actions_mut().for_each(action);
+        }
+        for instance in &mut self.instances {
+            instance_id(&mut instance.id);
+            definition_id(&mut instance.definition_id);
+            if let Some(parent) = &mut instance.parent {
+                instance_id(&mut parent.workflow_id);
+            }
+            instance.awaiting_children.iter_mut().for_each(instance_id);
+            if let Some(compensation) = &mut instance.compensation {
+                for step in &mut compensation.pending {
+                    action(&mut step.action);
+                }
+            }
+        }
+        for timer in &mut self.timers {
+            instance_id(&mut timer.workflow_id);
+        }
+    }
+}
+
+impl Default for Snapshot {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// How IDs are assigned when restoring a snapshot
+#[derive(Debug, Clone, Default)]
+pub struct RestoreOptions {
+    fresh_ids: bool,
+    definition_ids: HashMap<WorkflowId, WorkflowId>,
+}
+
+impl RestoreOptions {
+    /// Keep all IDs of the snapshot
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Give every definition and instance a new ID, so a snapshot can be
+    /// restored next to the data it was taken from
+    pub fn with_fresh_ids(mut self) -> Self {
+        self.fresh_ids = true;
+        self
+    }
+
+    /// Restore the definition `from` under the ID `to`
+    ///
+    /// Takes precedence over fresh IDs, e.g. to keep a definition ID that
+    /// clients of the target environment already use.
+    pub fn map_definition(mut self, from: WorkflowId, to: WorkflowId) -> Self {
+        self.definition_ids.insert(from, to);
+        self
+    }
+
+    fn fresh_id(&self, id: WorkflowId) -> WorkflowId {
+        if self.fresh_ids { WorkflowId::new() } else { id }
+    }
+}
+
+/// Outcome of restoring a snapshot
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RestoreReport {
+    pub definitions: usize,
+    pub instances: usize,
+    pub timers: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state_machine::{State, StateMachine, Transition};
+    use crate::types::{ParentLink, WorkflowStatus};
+    use serde_json::json;
+
+    fn definition(child: WorkflowId) -> WorkflowDefinition {
+        let state_machine = StateMachine::builder()
+            .initial_state("start")
+            .add_state(
+                State::new("start")
+                    .on_enter(Action::start_child_workflow(child, json!({}), true, None))
+                    .add_transition(Transition::new("child_completed", "end")),
+            )
+            .add_state(State::new("end"))
+            .build()
+            .unwrap();
+        WorkflowDefinition {
+            id: WorkflowId::new(),
+            name: "parent".to_string(),
+            description: None,
+            state_machine,
+            created_at: Utc::now(),
+            subscriptions: Vec::new(),
+            timeout: None,
+            state_timeouts: Default::default(),
+            limits: Default::default(),
+            scheduling: Default::default(),
+            indexes: Vec::new(),
+            retention: None,
+        }
+    }
+
+    fn instance(definition_id: WorkflowId, parent: Option<WorkflowId>) -> WorkflowInstance {
+        WorkflowInstance {
+            id: WorkflowId::new(),
+            definition_id,
+            current_state: "start".to_string(),
+            context: json!({}),
+            status: WorkflowStatus::Running,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            completed_at: None,
+            parent: parent.map(|workflow_id| ParentLink {
+                workflow_id,
+                result_key: None,
+                await_completion: true,
+            }),
+            awaiting_children: Vec::new(),
+            compensation: None,
+            branches: Vec::new(),
+            awaited_tasks: Vec::new(),
+            idempotency_key: None,
+            state_entry: 0,
+            paused_at: None,
+            held_tasks: Vec::new(),
+        }
+    }
+
+    /// A parent definition starting a child definition, with one instance of
+    /// each and a timer on the parent
+    fn snapshot() -> Snapshot {
+        let mut child = definition(WorkflowId::new());
+        child.name = "child".to_string();
+        let parent = definition(child.id);
+
+        let mut parent_instance = instance(parent.id, None);
+        let child_instance = instance(child.id, Some(parent_instance.id));
+        parent_instance.awaiting_children.push(child_instance.id);
+        let timer = ScheduledTimer {
+            workflow_id: parent_instance.id,
+            state: "start".to_string(),
+            branch: None,
+            event: "timeout".to_string(),
+            fire_at: Utc::now(),
+            state_entry: 0,
+            instance_wide: false,
+            fails: false,
+        };
+
+        Snapshot {
+            definitions: vec![parent, child],
+            instances: vec![parent_instance, child_instance],
+            timers: vec![timer],
+            ..Snapshot::new()
+        }
+    }
+
+    fn child_definition_of(definition: &WorkflowDefinition) -> WorkflowId {
+        match &definition.state_machine.get_state("start").unwrap().on_enter_actions()[0] {
+            Action::StartChildWorkflow { definition_id, .. } => *definition_id,
+            other => panic!("unexpected action {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_remap_keeps_ids() {
+        let original = snapshot();
+        let mut restored = original.clone();
+        restored.remap(&RestoreOptions::new());
+
+        assert_eq!(restored.definitions[0].id, original.definitions[0].id);
+        assert_eq!(restored.instances[1].id, original.instances[1].id);
+        assert_eq!(restored.timers[0].workflow_id, original.timers[0].workflow_id);
+    }
+
+    #[test]
+    fn test_remap_fresh_ids() {
+        let original = snapshot();
+        let mut restored = original.clone();
+        restored.remap(&RestoreOptions::new().with_fresh_ids());
+
+        let [parent, child] = &restored.definitions[..] else { panic!() };
+        let [parent_instance, child_instance] = &restored.instances[..] else { panic!() };
+        assert_ne!(parent.id, original.definitions[0].id);
+        assert_ne!(parent_instance.id, original.instances[0].id);
+
+        assert_eq!(child_definition_of(parent), child.id);
+        assert_eq!(parent_instance.definition_id, parent.id);
+        assert_eq!(child_instance.definition_id, child.id);
+        assert_eq!(child_instance.parent.as_ref().unwrap().workflow_id, parent_instance.id);
+        assert_eq!(parent_instance.awaiting_children, vec![child_instance.id]);
+        assert_eq!(restored.timers[0].workflow_id, parent_instance.id);
+    }
+
+    #[test]
+    fn test_remap_explicit_definition() {
+        let original = snapshot();
+        let target = WorkflowId::new();
+        let mut restored = original.clone();
+        restored.remap(&RestoreOptions::new().with_fresh_ids().map_definition(original.definitions[1].id, target));
+
+        assert_eq!(restored.definitions[1].id, target);
+        assert_eq!(child_definition_of(&restored.definitions[0]), target);
+        assert_eq!(restored.instances[1].definition_id, target);
+    }
+
+    #[test]
+    fn test_check_version() {
+        let mut snapshot = Snapshot::new();
+        assert!(snapshot.check_version().is_ok());
+        snapshot.version = SNAPSHOT_VERSION + 1;
+        assert!(snapshot.check_version().is_err());
+    }
+}
@@ -0,0 +1,42 @@
+//! Events fired when the tasks of a state finish
+
+use serde::{Deserialize, Serialize};
+
+/// Policy firing an event once the tasks a state enqueues on entry finish
+///
+/// `done` is fired when every task enqueued by the state's on_enter actions
+/// completed successfully. The first task that fails or is rejected fires
+/// `failed` instead; without a `failed` event the workflow fails as it would
+/// without a policy. Tasks that finish after the state was left are ignored.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Completion {
+    done: String,
+    #[serde(default)]
+    failed: Option<String>,
+}
+
+impl Completion {
+    /// Fire `done` once all tasks completed
+    pub fn new(done: impl Into<String>) -> Self {
+        Self {
+            done: done.into(),
+            failed: None,
+        }
+    }
+
+    /// Fire `event` instead of failing the workflow when a task fails
+    pub fn or_failed(mut self, event: impl Into<String>) -> Self {
+        self.failed = Some(event.into());
+        self
+    }
+
+    /// Get the event fired when all tasks completed
+    pub fn done_event(&self) -> &str {
+        &self.done
+    }
+
+    /// Get the event fired when a task failed
+    pub fn failed_event(&self) -> Option<&str> {
+        self.failed.as_deref()
+    }
+}
@@ -110,3 +110,85 @@ impl Context {
 }
 
 
+
+/// Whether `path` is a JSONPath of object fields such as `$.steps.verify`
+pub(crate) fn is_valid_result_path(path: &str) -> bool {
+    path.strip_prefix("$.")
+        .is_some_and(|fields| fields.split('.').all(|field| !field.is_empty()))
+}
+
+/// Get the value at a path of object fields such as `$.steps.verify`
+pub(crate) fn get_path<'a>(data: &'a serde_json::Value, path: &str) -> Option<&'a serde_json::Value> {
+    path.strip_prefix("$.")?
+        .split('.')
+        .try_fold(data, |value, field| value.get(field))
+}
+
+/// Store `value` in context data at `key`
+///
+/// A key starting with `$.` is a path of object fields, along which missing
+/// or non-object values are replaced by objects. Any other key names a
+/// top-level field.
+pub(crate) fn set_path(data: &mut serde_json::Value, key: &str, value: serde_json::Value) {
+    let mut fields: Vec<&str> = match key.strip_prefix("$.") {
+        Some(path) => path.split('.').collect(),
+        None => vec![key],
+    };
+    let Some(last) = fields.pop() else {
+        return;
+    };
+
+    let mut map = match data {
+        serde_json::Value::Object(map) => map,
+        _ => return,
+    };
+    for field in fields {
+        let entry = map
+            .entry(field.to_string())
+            .or_insert_with(|| serde_json::Value::Object(serde_json::Map::new()));
+        if !entry.is_object() {
+            *entry = serde_json::Value::Object(serde_json::Map::new());
+        }
+        let serde_json::Value::Object(next) = entry else {
+            unreachable!("entry was replaced by an object");
+        };
+        map = next;
+    }
+    map.insert(last.to_string(), value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_set_path() {
+        let mut data = json!({"steps": {"submit": true}, "status": "open"});
+        set_path(&mut data, "$.steps.verify", json!({"valid": true}));
+        set_path(&mut data, "$.status.code", json!(2));
+        set_path(&mut data, "review", json!("ok"));
+        assert_eq!(
+            data,
+            json!({
+                "steps": {"submit": true, "verify": {"valid": true}},
+                "status": {"code": 2},
+                "review": "ok",
+            })
+        );
+
+        assert!(is_valid_result_path("$.steps.verify"));
+        assert!(!is_valid_result_path("$"));
+        assert!(!is_valid_result_path("$.steps..verify"));
+        assert!(!is_valid_result_path("steps.verify"));
+    }
+
+    #[test]
+    fn test_get_path() {
+        let data = json!({"applicant": {"id": 12345}, "status": "open"});
+        assert_eq!(get_path(&data, "$.applicant.id"), Some(&json!(12345)));
+        assert_eq!(get_path(&data, "$.status"), Some(&json!("open")));
+        assert_eq!(get_path(&data, "$.status.code"), None);
+        assert_eq!(get_path(&data, "status"), None);
+    }
+}
@@ -0,0 +1,472 @@
+//! Guard expressions
+//!
+//! A small boolean language evaluated against the workflow context, so that
+//! guards can be stored with a workflow definition:
+//!
+//! ```text
+//! amount > 1000 && applicant.verified
+//! status in ['submitted', 'resubmitted'] || !documents[0].checked
+//! ```
+//!
+//! Paths name values in the context data; missing values are `null`.
+//! Numbers compare numerically and strings lexicographically, other values
+//! only compare equal or not. `in` tests membership in an array, a substring
+//! or an object key. A value used as a condition is false when it is `null`,
+//! `false`, `0`, or an empty string, array or object.
+
+use serde_json::Value;
+use std::cmp::Ordering;
+
+/// Maximum nesting of parentheses, lists and negations
+const MAX_DEPTH: usize = 64;
+
+/// A parsed guard expression
+#[derive(Debug, Clone)]
+pub struct Expression {
+    source: String,
+    root: Node,
+}
+
+impl Expression {
+    /// Parse an expression
+    pub fn parse(source: &str) -> Result<Self, ExpressionError> {
+        let tokens = lex(source)?;
+        let mut parser = Parser {
+            tokens,
+            position: 0,
+            depth: 0,
+            end: source.len(),
+        };
+        let root = parser.or()?;
+        if let Some((_, offset)) = parser.tokens.get(parser.position) {
+            return Err(ExpressionError::new("Unexpected input", *offset));
+        }
+        Ok(Self {
+            source: source.to_string(),
+            root,
+        })
+    }
+
+    /// Get the expression as written
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Evaluate the expression against context data
+    pub fn evaluate(&self, data: &Value) -> Value {
+        self.root.evaluate(data)
+    }
+
+    /// Evaluate the expression as a condition
+    pub fn is_satisfied(&self, data: &Value) -> bool {
+        truthy(&self.evaluate(data))
+    }
+}
+
+/// Error in the syntax of an expression
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("{message} at offset {offset}")]
+pub struct ExpressionError {
+    pub message: String,
+    /// Byte offset in the source
+    pub offset: usize,
+}
+
+impl ExpressionError {
+    fn new(message: impl Into<String>, offset: usize) -> Self {
+        Self {
+            message: message.into(),
+            offset,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(f64),
+    Str(String),
+    LParen,
+    RParen,
+    LBracket,
+    RBracket,
+    Dot,
+    Comma,
+    Not,
+    And,
+    Or,
+    Op(Op),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    In,
+}
+
+#[derive(Debug, Clone)]
+enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+#[derive(Debug, Clone)]
+enum Node {
+    Literal(Value),
+    Path(Vec<Segment>),
+    List(Vec<Node>),
+    Not(Box<Node>),
+    And(Box<Node>, Box<Node>),
+    Or(Box<Node>, Box<Node>),
+    Compare(Op, Box<Node>, Box<Node>),
+}
+
+impl Node {
+    fn evaluate(&self, data: &Value) -> Value {
+        match self {
+            Node::Literal(value) => value.clone(),
+            Node::Path(segments) => {
+                let mut value = data;
+                for segment in segments {
+                    let next = match segment {
+                        Segment::Key(key) => value.get(key),
+                        Segment::Index(index) => value.get(index),
+                    };
+                    match next {
+                        Some(next) => value = next,
+                        None => return Value::Null,
+                    }
+                }
+                value.clone()
+            }
+            Node::List(items) => Value::Array(items.iter().map(|item| item.evaluate(data)).collect()),
+            Node::Not(inner) => Value::Bool(!truthy(&inner.evaluate(data))),
+            Node::And(left, right) => Value::Bool(truthy(&left.evaluate(data)) && truthy(&right.evaluate(data))),
+            Node::Or(left, right) => Value::Bool(truthy(&left.evaluate(data)) || truthy(&right.evaluate(data))),
+            Node::Compare(op, left, right) => {
+                let (left, right) = (left.evaluate(data), right.evaluate(data));
+                Value::Bool(compare(*op, &left, &right))
+            }
+        }
+    }
+}
+
+fn compare(op: Op, left: &Value, right: &Value) -> bool {
+    match op {
+        Op::Eq => equal(left, right),
+        Op::Ne => !equal(left, right),
+        Op::Lt => order(left, right) == Some(Ordering::Less),
+        Op::Le => matches!(order(left, right), Some(Ordering::Less | Ordering::Equal)),
+        Op::Gt => order(left, right) == Some(Ordering::Greater),
+        Op::Ge => matches!(order(left, right), Some(Ordering::Greater | Ordering::Equal)),
+        Op::In => match (left, right) {
+            (_, Value::Array(items)) => items.iter().any(|item| equal(left, item)),
+            (Value::String(needle), Value::String(haystack)) => haystack.contains(needle.as_str()),
+            (Value::String(key), Value::Object(map)) => map.contains_key(key),
+            _ => false,
+        },
+    }
+}
+
+/// JSON equality, except that numbers are equal by value (`1 == 1.0`)
+fn equal(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (Value::Number(l), Value::Number(r)) => l.as_f64() == r.as_f64(),
+        _ => left == right,
+    }
+}
+
+fn order(left: &Value, right: &Value) -> Option<Ordering> {
+    match (left, right) {
+        (Value::Number(l), Value::Number(r)) => l.as_f64()?.partial_cmp(&r.as_f64()?),
+        (Value::String(l), Value::String(r)) => Some(l.cmp(r)),
+        _ => None,
+    }
+}
+
+fn truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(b) => *b,
+        Value::Number(n) => n.as_f64().is_some_and(|n| n != 0.0),
+        Value::String(s) => !s.is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        Value::Object(map) => !map.is_empty(),
+    }
+}
+
+fn lex(source: &str) -> Result<Vec<(Token, usize)>, ExpressionError> {
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+
+    while let Some(&(offset, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        let token = if c.is_ascii_alphabetic() || c == '_' {
+            let mut ident = String::new();
+            while let Some(&(_, c)) = chars.peek() {
+                if !(c.is_ascii_alphanumeric() || c == '_') {
+                    break;
+                }
+                ident.push(c);
+                chars.next();
+            }
+            match ident.as_str() {
+                "in" => Token::Op(Op::In),
+                _ => Token::Ident(ident),
+            }
+        } else if c.is_ascii_digit() {
+            let mut number = String::new();
+            while let Some(&(_, c)) = chars.peek() {
+                if !(c.is_ascii_digit() || c == '.') {
+                    break;
+                }
+                number.push(c);
+                chars.next();
+            }
+            let value = number
+                .parse()
+                .map_err(|_| ExpressionError::new(format!("Invalid number '{}'", number), offset))?;
+            Token::Number(value)
+        } else if c == '\'' || c == '"' {
+            chars.next();
+            let mut string = String::new();
+            loop {
+                match chars.next() {
+                    Some((_, '\\')) => match chars.next() {
+                        Some((_, escaped)) => string.push(escaped),
+                        None => return Err(ExpressionError::new("Unterminated string", offset)),
+                    },
+                    Some((_, end)) if end == c => break,
+                    Some((_, c)) => string.push(c),
+                    None => return Err(ExpressionError::new("Unterminated string", offset)),
+                }
+            }
+            Token::Str(string)
+        } else {
+            chars.next();
+            let next = chars.peek().map(|&(_, c)| c);
+            let mut pair = |token| {
+                chars.next();
+                token
+            };
+            match (c, next) {
+                ('=', Some('=')) => pair(Token::Op(Op::Eq)),
+                ('!', Some('=')) => pair(Token::Op(Op::Ne)),
+                ('<', Some('=')) => pair(Token::Op(Op::Le)),
+                ('>', Some('=')) => pair(Token::Op(Op::Ge)),
+                ('&', Some('&')) => pair(Token::And),
+                ('|', Some('|')) => pair(Token::Or),
+                ('<', _) => Token::Op(Op::Lt),
+                ('>', _) => Token::Op(Op::Gt),
+                ('!', _) => Token::Not,
+                ('(', _) => Token::LParen,
+                (')', _) => Token::RParen,
+                ('[', _) => Token::LBracket,
+                (']', _) => Token::RBracket,
+                ('.', _) => Token::Dot,
+                (',', _) => Token::Comma,
+                _ => return Err(ExpressionError::new(format!("Unexpected character '{}'", c), offset)),
+            }
+        };
+        tokens.push((token, offset));
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    position: usize,
+    depth: usize,
+    /// Offset reported for errors at the end of the input
+    end: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|(token, _)| token)
+    }
+
+    fn offset(&self) -> usize {
+        self.tokens.get(self.position).map_or(self.end, |(_, offset)| *offset)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).map(|(token, _)| token.clone());
+        self.position += 1;
+        token
+    }
+
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.peek() == Some(token) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: &Token, what: &str) -> Result<(), ExpressionError> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(ExpressionError::new(format!("Expected {}", what), self.offset()))
+        }
+    }
+
+    fn nested<T>(&mut self, parse: impl FnOnce(&mut Self) -> Result<T, ExpressionError>) -> Result<T, ExpressionError> {
+        if self.depth >= MAX_DEPTH {
+            return Err(ExpressionError::new("Expression is nested too deeply", self.offset()));
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn or(&mut self) -> Result<Node, ExpressionError> {
+        let mut node = self.and()?;
+        while self.eat(&Token::Or) {
+            node = Node::Or(Box::new(node), Box::new(self.and()?));
+        }
+        Ok(node)
+    }
+
+    fn and(&mut self) -> Result<Node, ExpressionError> {
+        let mut node = self.not()?;
+        while self.eat(&Token::And) {
+            node = Node::And(Box::new(node), Box::new(self.not()?));
+        }
+        Ok(node)
+    }
+
+    fn not(&mut self) -> Result<Node, ExpressionError> {
+        if self.eat(&Token::Not) {
+            return self.nested(|parser| Ok(Node::Not(Box::new(parser.not()?))));
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Node, ExpressionError> {
+        let left = self.primary()?;
+        if let Some(Token::Op(op)) = self.peek() {
+            let op = *op;
+            self.position += 1;
+            let right = self.primary()?;
+            return Ok(Node::Compare(op, Box::new(left), Box::new(right)));
+        }
+        Ok(left)
+    }
+
+    fn primary(&mut self) -> Result<Node, ExpressionError> {
+        let offset = self.offset();
+        match self.next() {
+            Some(Token::Number(n)) => serde_json::Number::from_f64(n)
+                .map(|n| Node::Literal(Value::Number(n)))
+                .ok_or_else(|| ExpressionError::new("Invalid number", offset)),
+            Some(Token::Str(s)) => Ok(Node::Literal(Value::String(s))),
+            Some(Token::Ident(ident)) => match ident.as_str() {
+                "true" => Ok(Node::Literal(Value::Bool(true))),
+                "false" => Ok(Node::Literal(Value::Bool(false))),
+                "null" => Ok(Node::Literal(Value::Null)),
+                _ => self.path(ident),
+            },
+            Some(Token::LParen) => self.nested(|parser| {
+                let node = parser.or()?;
+                parser.expect(&Token::RParen, "')'")?;
+                Ok(node)
+            }),
+            Some(Token::LBracket) => self.nested(|parser| {
+                let mut items = Vec::new();
+                if !parser.eat(&Token::RBracket) {
+                    loop {
+                        items.push(parser.primary()?);
+                        if parser.eat(&Token::RBracket) {
+                            break;
+                        }
+                        parser.expect(&Token::Comma, "',' or ']'")?;
+                    }
+                }
+                Ok(Node::List(items))
+            }),
+            Some(_) => Err(ExpressionError::new("Expected a value", offset)),
+            None => Err(ExpressionError::new("Unexpected end of expression", offset)),
+        }
+    }
+
+    fn path(&mut self, first: String) -> Result<Node, ExpressionError> {
+        let mut segments = vec![Segment::Key(first)];
+        loop {
+            if self.eat(&Token::Dot) {
+                let offset = self.offset();
+                match self.next() {
+                    Some(Token::Ident(key)) => segments.push(Segment::Key(key)),
+                    _ => return Err(ExpressionError::new("Expected a field name", offset)),
+                }
+            } else if self.eat(&Token::LBracket) {
+                let offset = self.offset();
+                match self.next() {
+                    Some(Token::Number(n)) if n >= 0.0 && n.fract() == 0.0 => {
+                        segments.push(Segment::Index(n as usize))
+                    }
+                    Some(Token::Str(key)) => segments.push(Segment::Key(key)),
+                    _ => return Err(ExpressionError::new("Expected an index or quoted key", offset)),
+                }
+                self.expect(&Token::RBracket, "']'")?;
+            } else {
+                return Ok(Node::Path(segments));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn eval(source: &str, data: &Value) -> bool {
+        Expression::parse(source).unwrap().is_satisfied(data)
+    }
+
+    #[test]
+    fn test_evaluation() {
+        let data = json!({
+            "amount": 1500,
+            "status": "submitted",
+            "applicant": {"verified": true, "name": "Alice"},
+            "documents": [{"checked": false}],
+            "tags": ["urgent"],
+        });
+
+        assert!(eval("amount > 1000 && applicant.verified", &data));
+        assert!(eval("amount == 1500.0", &data));
+        assert!(!eval("amount < 1000 || !applicant.verified", &data));
+        assert!(eval("status in ['submitted', 'resubmitted']", &data));
+        assert!(eval("!documents[0].checked", &data));
+        assert!(eval("'urgent' in tags && 'name' in applicant", &data));
+        assert!(eval("applicant['name'] == \"Alice\"", &data));
+        assert!(eval("missing.field == null && !missing", &data));
+        assert!(eval("(amount >= 1500) && (status != 'rejected')", &data));
+        assert!(!eval("status > 10", &data));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(Expression::parse("amount >").unwrap_err().offset, 8);
+        assert_eq!(Expression::parse("amount = 1").unwrap_err().offset, 7);
+        assert!(Expression::parse("(amount > 1").is_err());
+        assert!(Expression::parse("status == 'open").is_err());
+        assert!(Expression::parse("a b").is_err());
+        assert!(Expression::parse(&"!".repeat(MAX_DEPTH + 1)).is_err());
+    }
+}
@@ -0,0 +1,340 @@
+//! Graph rendering of state machines
+//!
+//! Definitions are rendered as Graphviz DOT or Mermaid state diagrams so they
+//! can be reviewed visually. States list their actions, timers, human tasks
+//! and completion policies; transitions are labelled with their event and
+//! guard, and forks with dashed edges to the states their branches start in.
+
+use super::{Action, State, StateMachine};
+use std::fmt::Write;
+use std::time::Duration;
+
+impl StateMachine {
+    /// Render the state machine as a Graphviz DOT digraph
+    ///
+    /// Composite states are drawn as clusters around their children.
+    /// Transitions to and from them connect to their initial leaf state and
+    /// are clipped at the cluster.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph workflow {\n");
+        dot.push_str("    rankdir=LR;\n");
+        dot.push_str("    compound=true;\n");
+        dot.push_str("    node [shape=box, style=rounded];\n");
+        dot.push_str("    __start [shape=point];\n");
+        let _ = writeln!(dot, "    __start -> {};", dot_quote(self.initial_state()));
+
+        let states = self.ordered_states();
+        for state in self.nested_states(None) {
+            self.write_dot_state(&mut dot, state, 1);
+        }
+
+        // Edges into or out of a composite state end at its cluster
+        let endpoint = |name: &str, clip: &str| {
+            let leaf = self.leaf(name);
+            let attribute = if leaf == name {
+                String::new()
+            } else {
+                format!(", {}={}", clip, dot_quote(&format!("cluster_{}", name)))
+            };
+            (dot_quote(leaf), attribute)
+        };
+        for state in &states {
+            for transition in state.transitions() {
+                let (from, ltail) = endpoint(state.name(), "ltail");
+                let (to, lhead) = endpoint(transition.target_state(), "lhead");
+                let _ = writeln!(
+                    dot,
+                    "    {} -> {} [label={}{}{}];",
+                    from,
+                    to,
+                    dot_quote(&transition_label(transition)),
+                    ltail,
+                    lhead,
+                );
+            }
+            if let Some(fork) = state.fork_spec() {
+                for branch in fork.branches() {
+                    let _ = writeln!(
+                        dot,
+                        "    {} -> {} [style=dashed, label={}];",
+                        dot_quote(state.name()),
+                        dot_quote(branch.initial_state()),
+                        dot_quote(&format!("branch {}", branch.name())),
+                    );
+                }
+            }
+        }
+
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Write a state node, or a cluster holding its children
+    fn write_dot_state(&self, dot: &mut String, state: &State, depth: usize) {
+        let indent = "    ".repeat(depth);
+        let mut lines = vec![state.name().to_string()];
+        lines.extend(state_notes(state));
+        let label = lines.iter().map(|line| dot_escape(line)).collect::<Vec<_>>().join("\\n");
+
+        if !state.is_composite() {
+            let periphery = if self.is_final(state.name()) { ", peripheries=2" } else { "" };
+            let _ = writeln!(dot, "{}{} [label=\"{}\"{}];", indent, dot_quote(state.name()), label, periphery);
+            return;
+        }
+
+        let _ = writeln!(dot, "{}subgraph {} {{", indent, dot_quote(&format!("cluster_{}", state.name())));
+        let _ = writeln!(dot, "{}    label=\"{}\";", indent, label);
+        for child in self.nested_states(Some(state)) {
+            self.write_dot_state(dot, child, depth + 1);
+        }
+        let _ = writeln!(dot, "{}}}", indent);
+    }
+
+    /// Render the state machine as a Mermaid state diagram
+    pub fn to_mermaid(&self) -> String {
+        let states = self.ordered_states();
+        // State names may hold characters Mermaid does not allow in IDs
+        let id = |name: &str| {
+            states
+                .iter()
+                .position(|state| state.name() == name)
+                .map_or_else(|| mermaid_text(name), |index| format!("s{}", index))
+        };
+
+        let mut mermaid = String::from("stateDiagram-v2\n");
+        for (index, state) in states.iter().enumerate() {
+            let _ = writeln!(mermaid, "    state \"{}\" as s{}", mermaid_text(state.name()), index);
+            for note in state_notes(state) {
+                let _ = writeln!(mermaid, "    s{} : {}", index, mermaid_text(&note));
+            }
+        }
+        for state in &states {
+            let Some(initial) = state.initial_child_state() else {
+                continue;
+            };
+            let _ = writeln!(mermaid, "    state {} {{", id(state.name()));
+            let _ = writeln!(mermaid, "        [*] --> {}", id(initial));
+            for child in self.nested_states(Some(state)) {
+                let _ = writeln!(mermaid, "        {}", id(child.name()));
+            }
+            mermaid.push_str("    }\n");
+        }
+
+        let _ = writeln!(mermaid, "    [*] --> {}", id(&self.initial_state));
+        for state in &states {
+            for transition in state.transitions() {
+                let _ = writeln!(
+                    mermaid,
+                    "    {} --> {} : {}",
+                    id(state.name()),
+                    id(transition.target_state()),
+                    mermaid_text(&transition_label(transition)),
+                );
+            }
+            if let Some(fork) = state.fork_spec() {
+                for branch in fork.branches() {
+                    let _ = writeln!(
+                        mermaid,
+                        "    {} --> {} : branch {}",
+                        id(state.name()),
+                        id(branch.initial_state()),
+                        mermaid_text(branch.name()),
+                    );
+                }
+            }
+            if !state.is_composite() && self.is_final(state.name()) {
+                let _ = writeln!(mermaid, "    {} --> [*]", id(state.name()));
+            }
+        }
+
+        mermaid
+    }
+
+    /// States in a stable order, each followed by its children, the initial
+    /// state first
+    fn ordered_states(&self) -> Vec<&State> {
+        let mut states = Vec::new();
+        let mut pending = self.nested_states(None);
+        pending.reverse();
+        while let Some(state) = pending.pop() {
+            states.push(state);
+            pending.extend(self.nested_states(Some(state)).into_iter().rev());
+        }
+        states
+    }
+
+    /// States directly nested in `parent`, or the top-level states, the
+    /// initial one first
+    fn nested_states(&self, parent: Option<&State>) -> Vec<&State> {
+        let initial = parent.map_or(Some(self.initial_state.as_str()), State::initial_child_state);
+        let parent = parent.map(State::name);
+        let mut states: Vec<&State> = self.states.values().filter(|state| state.parent() == parent).collect();
+        let key = |state: &State| (Some(state.name()) != initial, state.name().to_string());
+        states.sort_by_cached_key(|state| key(state));
+        states
+    }
+}
+
+/// Lines describing what happens in a state
+fn state_notes(state: &State) -> Vec<String> {
+    let mut notes = Vec::new();
+    let mut actions = |trigger: &str, actions: &[Action]| {
+        for action in actions {
+            if let Some(action) = describe_action(action) {
+                notes.push(format!("{} / {}", trigger, action));
+            }
+        }
+    };
+    actions("entry", state.on_enter_actions());
+    actions("exit", state.on_exit_actions());
+    actions("compensate", state.compensation_actions());
+
+    for timer in state.timers() {
+        notes.push(format!("after {} / {}", format_duration(timer.after()), timer.event()));
+    }
+    if let Some(task) = state.human_task_spec() {
+        notes.push(format!("human task \"{}\" / {}", task.title(), task.event()));
+    }
+    if let Some(completion) = state.completion() {
+        notes.push(format!("tasks done / {}", completion.done_event()));
+        if let Some(event) = completion.failed_event() {
+            notes.push(format!("task failed / {}", event));
+        }
+    }
+    if let Some(fork) = state.fork_spec() {
+        notes.push(format!(
+            "fork {} of {}, join in {}",
+            fork.required(),
+            fork.branches().len(),
+            fork.join_state()
+        ));
+    }
+    notes
+}
+
+/// Short description of an action, `None` for no-ops
+fn describe_action(action: &Action) -> Option<String> {
+    let description = match action {
+        Action::ExecuteTask(task) => format!("task {}", task.name),
+        Action::SetData { key, .. } => format!("set {}", key),
+        Action::MergeData { .. } => "merge data".to_string(),
+        Action::Log { .. } => "log".to_string(),
+        Action::StartChildWorkflow {
+            definition_id,
+            await_completion,
+            ..
+        } => {
+            let awaited = if *await_completion { " and await" } else { "" };
+            format!("start child {}{}", definition_id, awaited)
+        }
+        Action::PublishEvent { topic, .. } => format!("publish {}", topic),
+        Action::HttpRequest(request) => format!("{} {}", request.method, request.url),
+        Action::NoOp => return None,
+    };
+    Some(description)
+}
+
+/// Event of a transition followed by its guard
+fn transition_label(transition: &super::Transition) -> String {
+    match transition.guard() {
+        Some(guard) => match guard.expression() {
+            Some(expression) => format!("{} [{}]", transition.event(), expression.source()),
+            None => format!("{} [guard]", transition.event()),
+        },
+        None => transition.event().to_string(),
+    }
+}
+
+/// Format a timer delay in the largest whole unit
+fn format_duration(duration: Duration) -> String {
+    let millis = duration.as_millis();
+    match millis {
+        0 => "0s".to_string(),
+        _ if millis % 3_600_000 == 0 => format!("{}h", millis / 3_600_000),
+        _ if millis % 60_000 == 0 => format!("{}m", millis / 60_000),
+        _ if millis % 1_000 == 0 => format!("{}s", millis / 1_000),
+        _ => format!("{}ms", millis),
+    }
+}
+
+fn dot_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+fn dot_quote(text: &str) -> String {
+    format!("\"{}\"", dot_escape(text))
+}
+
+/// Replace characters that end Mermaid statements or strings with entities
+fn mermaid_text(text: &str) -> String {
+    text.replace('"', "#quot;").replace(';', "#59;").replace('\n', " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{Guard, Transition};
+    use super::*;
+
+    fn review_machine() -> StateMachine {
+        StateMachine::builder()
+            .initial_state("submitted")
+            .add_state(
+                State::new("submitted")
+                    .on_enter(Action::log("received"))
+                    .add_transition(
+                        Transition::new("review", "approved").with_guard(Guard::expr("amount < 1000").unwrap()),
+                    )
+                    .add_transition(Transition::new("review", "rejected"))
+                    .after(Duration::from_secs(3600), "review"),
+            )
+            .add_state(State::new("approved"))
+            .add_state(State::new("rejected"))
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_to_dot() {
+        let dot = review_machine().to_dot();
+        assert!(dot.starts_with("digraph workflow {"));
+        assert!(dot.contains("__start -> \"submitted\";"));
+        assert!(dot.contains("\"submitted\" [label=\"submitted\\nentry / log\\nafter 1h / review\"];"));
+        assert!(dot.contains("\"submitted\" -> \"approved\" [label=\"review [amount < 1000]\"];"));
+        assert!(dot.contains("\"rejected\" [label=\"rejected\", peripheries=2];"));
+    }
+
+    #[test]
+    fn test_to_mermaid() {
+        let mermaid = review_machine().to_mermaid();
+        assert!(mermaid.starts_with("stateDiagram-v2\n"));
+        assert!(mermaid.contains("    state \"submitted\" as s0\n"));
+        assert!(mermaid.contains("    s0 : entry / log\n"));
+        assert!(mermaid.contains("    [*] --> s0\n"));
+        assert!(mermaid.contains("    s0 --> s1 : review [amount < 1000]\n"));
+        assert!(mermaid.contains("    s2 --> [*]\n"));
+    }
+
+    #[test]
+    fn test_nested_states() {
+        let sm = StateMachine::builder()
+            .initial_state("order")
+            .add_state(
+                State::new("order")
+                    .add_transition(Transition::new("cancel", "cancelled"))
+                    .child(State::new("cart").add_transition(Transition::new("checkout", "payment")))
+                    .child(State::new("payment")),
+            )
+            .add_state(State::new("cancelled"))
+            .build()
+            .unwrap();
+
+        let dot = sm.to_dot();
+        assert!(dot.contains("__start -> \"order/cart\";"));
+        assert!(dot.contains("    subgraph \"cluster_order\" {\n        label=\"order\";\n"));
+        assert!(dot.contains("\"order/cart\" -> \"cancelled\" [label=\"cancel\", ltail=\"cluster_order\"];"));
+
+        let mermaid = sm.to_mermaid();
+        assert!(mermaid.contains("    state s0 {\n        [*] --> s1\n        s1\n        s2\n    }\n"));
+        assert!(mermaid.contains("    s0 --> s3 : cancel\n"));
+    }
+}
@@ -0,0 +1,189 @@
+//! Outbound HTTP requests made by workflows
+//!
+//! The URL, header values and string values in the body are templates, filled
+//! in from the context when the state is entered:
+//!
+//! ```text
+//! https://registry.example/citizens/{{ applicant.id }}
+//! ```
+//!
+//! Values are not escaped for the URL.
+
+use super::template::{render_string, render_value};
+use crate::error::{WorkflowError, WorkflowResult};
+use crate::types::RetryPolicy;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// Default time to wait for a response
+const DEFAULT_TIMEOUT_MS: u64 = 30_000;
+
+/// Methods accepted for a request
+const METHODS: &[&str] = &["GET", "POST", "PUT", "PATCH", "DELETE", "HEAD"];
+
+/// An HTTP request made by the engine when a state is entered
+///
+/// The response is stored in the context under `result_key` as
+/// `{"status": 200, "body": ...}`, with the body parsed as JSON where
+/// possible. A request that still fails after its retries stores
+/// `{"status": 503, "error": "...", "body": ...}` instead, without status and
+/// body when no response was received.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HttpRequest {
+    pub method: String,
+    pub url: String,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    #[serde(default)]
+    pub body: Option<Value>,
+    /// Context key receiving the response
+    #[serde(default)]
+    pub result_key: Option<String>,
+    /// Event fired when the request succeeds
+    #[serde(default)]
+    pub on_success: Option<String>,
+    /// Event fired when the request fails for good; without it the workflow
+    /// fails
+    #[serde(default)]
+    pub on_failure: Option<String>,
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+    #[serde(default)]
+    pub retry_policy: Option<RetryPolicy>,
+}
+
+fn default_timeout_ms() -> u64 {
+    DEFAULT_TIMEOUT_MS
+}
+
+impl HttpRequest {
+    /// Create a request
+    pub fn new(method: impl Into<String>, url: impl Into<String>) -> Self {
+        Self {
+            method: method.into().to_ascii_uppercase(),
+            url: url.into(),
+            headers: BTreeMap::new(),
+            body: None,
+            result_key: None,
+            on_success: None,
+            on_failure: None,
+            timeout_ms: DEFAULT_TIMEOUT_MS,
+            retry_policy: None,
+        }
+    }
+
+    /// Create a GET request
+    pub fn get(url: impl Into<String>) -> Self {
+        Self::new("GET", url)
+    }
+
+    /// Create a POST request
+    pub fn post(url: impl Into<String>) -> Self {
+        Self::new("POST", url)
+    }
+
+    /// Add a header
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.insert(name.into(), value.into());
+        self
+    }
+
+    /// Send a JSON body
+    pub fn with_body(mut self, body: Value) -> Self {
+        self.body = Some(body);
+        self
+    }
+
+    /// Store the response in the context under `key`
+    pub fn with_result_key(mut self, key: impl Into<String>) -> Self {
+        self.result_key = Some(key.into());
+        self
+    }
+
+    /// Fire `event` when the request succeeds
+    pub fn on_success(mut self, event: impl Into<String>) -> Self {
+        self.on_success = Some(event.into());
+        self
+    }
+
+    /// Fire `event` instead of failing the workflow when the request fails
+    pub fn on_failure(mut self, event: impl Into<String>) -> Self {
+        self.on_failure = Some(event.into());
+        self
+    }
+
+    /// Set the time to wait for a response
+    pub fn with_timeout_ms(mut self, timeout_ms: u64) -> Self {
+        self.timeout_ms = timeout_ms;
+        self
+    }
+
+    /// Retry failed requests
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = Some(policy);
+        self
+    }
+
+    /// Check the method and the syntax of all templates
+    pub fn validate(&self) -> WorkflowResult<()> {
+        if !METHODS.contains(&self.method.as_str()) {
+            return Err(WorkflowError::InvalidDefinition(format!(
+                "Unsupported HTTP method '{}'",
+                self.method
+            )));
+        }
+        self.render(&Value::Null).map(|_| ())
+    }
+
+    /// Fill in the templates from context data
+    pub fn render(&self, data: &Value) -> WorkflowResult<HttpRequest> {
+        let mut rendered = self.clone();
+        rendered.url = render_string(&self.url, data)?;
+        for value in rendered.headers.values_mut() {
+            *value = render_string(value, data)?;
+        }
+        if let Some(body) = &mut rendered.body {
+            render_value(body, data)?;
+        }
+        Ok(rendered)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_render_templates() {
+        let data = json!({"applicant": {"id": "a-7", "name": "Ada"}, "amount": 1200, "missing": null});
+        let request = HttpRequest::post("https://registry.example/citizens/{{ applicant.id }}")
+            .with_header("X-Case", "case {{amount}}")
+            .with_body(json!({
+                "amount": "{{ amount }}",
+                "greeting": "Dear {{ applicant.name }},{{ missing }}",
+                "large": "{{ amount > 1000 }}",
+                "fixed": 1,
+            }));
+
+        let rendered = request.render(&data).unwrap();
+        assert_eq!(rendered.url, "https://registry.example/citizens/a-7");
+        assert_eq!(rendered.headers["X-Case"], "case 1200");
+        assert_eq!(
+            rendered.body.unwrap(),
+            json!({"amount": 1200, "greeting": "Dear Ada,", "large": true, "fixed": 1})
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(HttpRequest::get("https://example.org/{{ id }}").validate().is_ok());
+        assert!(HttpRequest::new("brew", "https://example.org").validate().is_err());
+        assert!(HttpRequest::get("https://example.org/{{ id").validate().is_err());
+        assert!(HttpRequest::post("https://example.org")
+            .with_body(json!({"a": "{{ a && }}"}))
+            .validate()
+            .is_err());
+    }
+}
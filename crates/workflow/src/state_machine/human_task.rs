@@ -0,0 +1,76 @@
+//! Manual steps performed by people
+
+use serde::{Deserialize, Serialize};
+
+/// A task a person completes before the workflow moves on
+///
+/// Entering a state with a human task creates a task record that an eligible
+/// person claims and completes with a form. Completion fires `event` on the
+/// workflow, with the form stored in the context under `result_key`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HumanTaskSpec {
+    title: String,
+    event: String,
+    #[serde(default)]
+    assignee: Option<String>,
+    #[serde(default)]
+    role: Option<String>,
+    #[serde(default)]
+    result_key: Option<String>,
+}
+
+impl HumanTaskSpec {
+    /// Create a task anyone may claim, firing `event` when completed
+    pub fn new(title: impl Into<String>, event: impl Into<String>) -> Self {
+        Self {
+            title: title.into(),
+            event: event.into(),
+            assignee: None,
+            role: None,
+            result_key: None,
+        }
+    }
+
+    /// Restrict the task to one person
+    pub fn assign_to(mut self, assignee: impl Into<String>) -> Self {
+        self.assignee = Some(assignee.into());
+        self
+    }
+
+    /// Restrict the task to holders of a role
+    pub fn for_role(mut self, role: impl Into<String>) -> Self {
+        self.role = Some(role.into());
+        self
+    }
+
+    /// Store the submitted form in the context under `key`
+    pub fn result_key(mut self, key: impl Into<String>) -> Self {
+        self.result_key = Some(key.into());
+        self
+    }
+
+    /// Get the task title shown to people
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    /// Get the event fired when the task is completed
+    pub fn event(&self) -> &str {
+        &self.event
+    }
+
+    /// Get the person the task is assigned to
+    pub fn assignee(&self) -> Option<&str> {
+        self.assignee.as_deref()
+    }
+
+    /// Get the role allowed to work on the task
+    pub fn role(&self) -> Option<&str> {
+        self.role.as_deref()
+    }
+
+    /// Get the context key receiving the submitted form
+    pub fn form_key(&self) -> Option<&str> {
+        self.result_key.as_deref()
+    }
+}
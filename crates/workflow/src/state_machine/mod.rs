@@ -1,16 +1,33 @@
 //! State machine implementation for workflows
 
+mod completion;
 mod context;
+mod expr;
+mod graph;
+mod http;
+mod human_task;
+mod parallel;
 mod state;
+mod template;
+mod timer;
 mod transition;
 
+pub use completion::Completion;
 pub use context::Context;
+pub(crate) use context::{get_path, is_valid_result_path, set_path};
+pub use expr::{Expression, ExpressionError};
+pub use http::HttpRequest;
+pub use human_task::HumanTaskSpec;
+pub use parallel::{Branch, Fork};
 pub use state::{Action, State};
+pub(crate) use template::render_value;
+pub use timer::Timer;
 pub use transition::{Guard, Transition};
 
 use crate::error::{WorkflowError, WorkflowResult};
+use crate::types::{TaskDefinition, WorkflowStatus, is_valid_topic};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 /// State machine that defines workflow behavior
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -25,9 +42,9 @@ impl StateMachine {
         StateMachineBuilder::new()
     }
 
-    /// Get the initial state
+    /// Get the initial state, descending into composite states
     pub fn initial_state(&self) -> &str {
-        &self.initial_state
+        self.leaf(&self.initial_state)
     }
 
     /// Get a state by name
@@ -35,44 +52,192 @@ impl StateMachine {
         self.states.get(name)
     }
 
+    /// The state a workflow is in when it enters `name`, descending through
+    /// the initial children of composite states
+    pub fn leaf<'a>(&'a self, name: &'a str) -> &'a str {
+        let mut name = name;
+        while let Some(child) = self.states.get(name).and_then(State::initial_child_state) {
+            name = child;
+        }
+        name
+    }
+
+    /// A state followed by the composite states it is nested in, innermost
+    /// first
+    pub fn ancestry<'a>(&'a self, name: &str) -> impl Iterator<Item = &'a State> + use<'a> {
+        std::iter::successors(self.states.get(name), |state| {
+            state.parent().and_then(|parent| self.states.get(parent))
+        })
+    }
+
+    /// Whether a workflow in `state` has a transition on `event`, its own or
+    /// one of an enclosing composite state
+    pub fn accepts(&self, state: &str, event: &str) -> bool {
+        self.ancestry(state)
+            .any(|state| state.transitions().iter().any(|t| t.event() == event))
+    }
+
+    /// Find the transition a workflow in `state` takes on `event`
+    ///
+    /// Transitions of the state itself take precedence over those of the
+    /// composite states it is nested in. Returns the state owning the
+    /// transition along with it.
+    pub fn find_transition(&self, state: &str, event: &str, ctx: &Context) -> Option<(&State, &Transition)> {
+        self.ancestry(state)
+            .find_map(|state| state.find_transition(event, ctx).map(|transition| (state, transition)))
+    }
+
+    /// Whether the workflow ends when it enters `name`
+    ///
+    /// A nested state is only final if the composite states around it are.
+    pub fn is_final(&self, name: &str) -> bool {
+        self.states.contains_key(name) && self.ancestry(name).all(State::is_final)
+    }
+
+    /// Status of an instance that entered `name`: completed in a final
+    /// state, running otherwise
+    pub fn status_in(&self, name: &str) -> WorkflowStatus {
+        if self.is_final(name) {
+            WorkflowStatus::Completed
+        } else {
+            WorkflowStatus::Running
+        }
+    }
+
+    /// All tasks the state machine can execute, including compensation
+    pub fn tasks(&self) -> impl Iterator<Item = &TaskDefinition> {
+        self.states.values().flat_map(|state| {
+            state
+                .on_enter_actions()
+                .iter()
+                .chain(state.on_exit_actions())
+                .chain(state.compensation_actions())
+                .filter_map(|action| match action {
+                    Action::ExecuteTask(task) => Some(task),
+                    _ => None,
+                })
+        })
+    }
+
+    /// All actions of all states, for rewriting
+    pub(crate) fn actions_mut(&mut self) -> impl Iterator<Item = &mut Action> {
+        self.states.values_mut().flat_map(State::actions_mut)
+    }
+
+    /// Whether any state has a transition on `event`
+    pub fn handles_event(&self, event: &str) -> bool {
+        self.states
+            .values()
+            .any(|state| state.transitions().iter().any(|t| t.event() == event))
+    }
+
     /// Attempt a state transition based on an event
     pub async fn transition(&self, ctx: &mut Context, event: &str) -> WorkflowResult<String> {
-        let current_state_name = ctx.current_state();
-        let current_state = self
-            .states
-            .get(current_state_name)
-            .ok_or_else(|| WorkflowError::InvalidState(current_state_name.to_string()))?;
-
-        // Find a matching transition
-        let transition = current_state
-            .find_transition(event, ctx)
+        Ok(self.transition_inner(ctx, event, None).await?.state)
+    }
+
+    /// Attempt a state transition, reporting every state entered
+    pub async fn transition_states(&self, ctx: &mut Context, event: &str) -> WorkflowResult<StateChange> {
+        self.transition_inner(ctx, event, None).await
+    }
+
+    /// Attempt a transition of a fork branch
+    ///
+    /// A branch entering `join_state` has ended; the join state's on_enter
+    /// actions run once, when the branches join, not for every branch.
+    pub async fn transition_branch(
+        &self,
+        ctx: &mut Context,
+        event: &str,
+        join_state: &str,
+    ) -> WorkflowResult<String> {
+        Ok(self.transition_inner(ctx, event, Some(join_state)).await?.state)
+    }
+
+    /// Attempt a transition of a fork branch, reporting every state entered
+    pub async fn transition_branch_states(
+        &self,
+        ctx: &mut Context,
+        event: &str,
+        join_state: &str,
+    ) -> WorkflowResult<StateChange> {
+        self.transition_inner(ctx, event, Some(join_state)).await
+    }
+
+    async fn transition_inner(
+        &self,
+        ctx: &mut Context,
+        event: &str,
+        join_state: Option<&str>,
+    ) -> WorkflowResult<StateChange> {
+        let current_state_name = ctx.current_state().to_string();
+        if !self.states.contains_key(&current_state_name) {
+            return Err(WorkflowError::InvalidState(current_state_name));
+        }
+
+        // Find a matching transition, on the state or a composite state
+        // around it
+        let (owner, transition) = self
+            .find_transition(&current_state_name, event, ctx)
             .ok_or_else(|| WorkflowError::TransitionNotAllowed {
-                from: current_state_name.to_string(),
+                from: current_state_name.clone(),
                 event: event.to_string(),
             })?;
 
-        let target_state_name = transition.target_state();
-
         // Verify target state exists
-        let target_state = self
-            .states
-            .get(target_state_name)
-            .ok_or_else(|| WorkflowError::InvalidState(target_state_name.to_string()))?;
+        if !self.states.contains_key(transition.target_state()) {
+            return Err(WorkflowError::InvalidState(transition.target_state().to_string()));
+        }
+        let target_state_name = self.leaf(transition.target_state());
+
+        // States are left up to the innermost composite state enclosing both
+        // the owner of the transition and its target
+        let enclosing: HashSet<&str> = owner
+            .parent()
+            .map(|parent| self.ancestry(parent).map(State::name).collect())
+            .unwrap_or_default();
+        let scope = self
+            .ancestry(target_state_name)
+            .map(State::name)
+            .find(|name| enclosing.contains(name));
 
-        // Execute on_exit actions for current state
-        for action in current_state.on_exit_actions() {
-            action.execute(ctx).await?;
+        // Execute on_exit actions, innermost state first
+        let exited: Vec<&State> = self
+            .ancestry(&current_state_name)
+            .take_while(|state| Some(state.name()) != scope)
+            .collect();
+        for state in exited {
+            for action in state.on_exit_actions() {
+                action.execute(ctx).await?;
+            }
         }
 
         // Update context state
         ctx.set_state(target_state_name.to_string());
 
-        // Execute on_enter actions for target state
-        for action in target_state.on_enter_actions() {
-            action.execute(ctx).await?;
+        if join_state == Some(target_state_name) {
+            return Ok(StateChange {
+                state: target_state_name.to_string(),
+                entered: Vec::new(),
+            });
+        }
+
+        // Execute on_enter actions, outermost state first
+        let mut entered: Vec<&State> = self
+            .ancestry(target_state_name)
+            .take_while(|state| Some(state.name()) != scope)
+            .collect();
+        entered.reverse();
+        for state in &entered {
+            for action in state.on_enter_actions() {
+                action.execute(ctx).await?;
+            }
         }
 
-        Ok(target_state_name.to_string())
+        Ok(StateChange {
+            state: target_state_name.to_string(),
+            entered: entered.iter().map(|state| state.name().to_string()).collect(),
+        })
     }
 
     /// Validate that the state machine is well-formed
@@ -87,6 +252,78 @@ impl StateMachine {
 
         // Validate all transition targets exist
         for (state_name, state) in &self.states {
+            self.validate_nesting(state_name, state)?;
+
+            // Compensation runs after the fact, it cannot fan out into new
+            // workflows
+            if state
+                .compensation_actions()
+                .iter()
+                .any(|action| matches!(action, Action::StartChildWorkflow { .. }))
+            {
+                return Err(WorkflowError::InvalidDefinition(format!(
+                    "State '{}' starts a child workflow on failure",
+                    state_name
+                )));
+            }
+
+            for action in state
+                .on_enter_actions()
+                .iter()
+                .chain(state.on_exit_actions())
+                .chain(state.compensation_actions())
+            {
+                if let Action::PublishEvent { topic, .. } = action
+                    && !is_valid_topic(topic)
+                {
+                    return Err(WorkflowError::InvalidDefinition(format!(
+                        "State '{}' publishes on invalid topic '{}'",
+                        state_name, topic
+                    )));
+                }
+                match action {
+                    Action::HttpRequest(request) => request.validate()?,
+                    Action::ExecuteTask(task) => {
+                        task.input_from(&serde_json::Value::Null)?;
+                        if let Some(path) = &task.result_path
+                            && !is_valid_result_path(path)
+                        {
+                            return Err(WorkflowError::InvalidDefinition(format!(
+                                "Task '{}' in state '{}' has invalid result path '{}'",
+                                task.name, state_name, path
+                            )));
+                        }
+                    }
+                    _ => {}
+                }
+            }
+            self.validate_http_requests(state_name, state)?;
+            self.validate_task_events(state_name, state)?;
+
+            for timer in state.timers() {
+                if !self.accepts(state_name, timer.event()) {
+                    return Err(WorkflowError::InvalidDefinition(format!(
+                        "State '{}' has a timer for event '{}' without a matching transition",
+                        state_name,
+                        timer.event()
+                    )));
+                }
+            }
+
+            if let Some(fork) = state.fork_spec() {
+                self.validate_fork(state_name, fork)?;
+            }
+
+            if let Some(task) = state.human_task_spec()
+                && !self.accepts(state_name, task.event())
+            {
+                return Err(WorkflowError::InvalidDefinition(format!(
+                    "State '{}' has a human task completing with event '{}' without a matching transition",
+                    state_name,
+                    task.event()
+                )));
+            }
+
             for transition in state.transitions() {
                 if !self.states.contains_key(transition.target_state()) {
                     return Err(WorkflowError::InvalidDefinition(format!(
@@ -100,6 +337,217 @@ impl StateMachine {
 
         Ok(())
     }
+
+    /// Check that composite states and the states nested in them are linked
+    ///
+    /// A composite state is never the current state of a workflow, so it
+    /// cannot wait for anything itself; its entry actions may only start work
+    /// whose outcome nothing waits for.
+    fn validate_nesting(&self, state_name: &str, state: &State) -> WorkflowResult<()> {
+        let invalid = |message: &str| {
+            Err(WorkflowError::InvalidDefinition(format!("State '{}' {}", state_name, message)))
+        };
+
+        if !state.children().is_empty() {
+            return invalid("has nested states that were not built into the state machine");
+        }
+        if let Some(parent) = state.parent()
+            && !self.states.get(parent).is_some_and(State::is_composite)
+        {
+            return invalid("is nested in a state that is not composite");
+        }
+        let Some(child) = state.initial_child_state() else {
+            return Ok(());
+        };
+        if self.states.get(child).and_then(State::parent) != Some(state_name) {
+            return invalid("starts in a state that is not nested in it");
+        }
+
+        if !state.timers().is_empty() || state.human_task_spec().is_some() {
+            return invalid("is composite and has timers or a human task");
+        }
+        if state.fork_spec().is_some() || state.completion().is_some() {
+            return invalid("is composite and forks or waits for its tasks");
+        }
+        if !state.compensation_actions().is_empty() {
+            return invalid("is composite and has actions on failure");
+        }
+        for action in state.on_enter_actions() {
+            let waits = match action {
+                Action::ExecuteTask(task) => task.on_complete.is_some(),
+                Action::StartChildWorkflow { await_completion, .. } => *await_completion,
+                Action::HttpRequest(request) => request.on_success.is_some() || request.on_failure.is_some(),
+                _ => false,
+            };
+            if waits {
+                return invalid("is composite and waits for the outcome of an entry action");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check that HTTP requests are made where their outcome can be handled
+    ///
+    /// Requests run when a state is entered, so only they can fire events
+    /// into it. Requests made during compensation have no state left to
+    /// report to.
+    fn validate_http_requests(&self, state_name: &str, state: &State) -> WorkflowResult<()> {
+        if state
+            .on_exit_actions()
+            .iter()
+            .any(|action| matches!(action, Action::HttpRequest(_)))
+        {
+            return Err(WorkflowError::InvalidDefinition(format!(
+                "State '{}' makes an HTTP request on exit",
+                state_name
+            )));
+        }
+
+        for action in state.compensation_actions() {
+            if let Action::HttpRequest(request) = action
+                && (request.on_success.is_some() || request.on_failure.is_some())
+            {
+                return Err(WorkflowError::InvalidDefinition(format!(
+                    "State '{}' fires events from an HTTP request on failure",
+                    state_name
+                )));
+            }
+        }
+
+        for action in state.on_enter_actions() {
+            let Action::HttpRequest(request) = action else {
+                continue;
+            };
+            for event in request.on_success.iter().chain(&request.on_failure) {
+                if !self.accepts(state_name, event) {
+                    return Err(WorkflowError::InvalidDefinition(format!(
+                        "State '{}' has an HTTP request firing event '{}' without a matching transition",
+                        state_name, event
+                    )));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check that tasks completing with an event run where it is handled
+    ///
+    /// Only tasks enqueued when a state is entered can move it on.
+    fn validate_task_events(&self, state_name: &str, state: &State) -> WorkflowResult<()> {
+        for action in state.on_exit_actions().iter().chain(state.compensation_actions()) {
+            if let Action::ExecuteTask(task) = action
+                && task.on_complete.is_some()
+            {
+                return Err(WorkflowError::InvalidDefinition(format!(
+                    "Task '{}' of state '{}' fires an event but does not run on entry",
+                    task.name, state_name
+                )));
+            }
+        }
+
+        for action in state.on_enter_actions() {
+            if let Action::ExecuteTask(task) = action
+                && let Some(event) = &task.on_complete
+                && !self.accepts(state_name, event)
+            {
+                return Err(WorkflowError::InvalidDefinition(format!(
+                    "Task '{}' of state '{}' completes with event '{}' without a matching transition",
+                    task.name, state_name, event
+                )));
+            }
+        }
+
+        if let Some(completion) = state.completion() {
+            if !state
+                .on_enter_actions()
+                .iter()
+                .any(|action| matches!(action, Action::ExecuteTask(_)))
+            {
+                return Err(WorkflowError::InvalidDefinition(format!(
+                    "State '{}' waits for its tasks but enqueues none on entry",
+                    state_name
+                )));
+            }
+            let events = std::iter::once(completion.done_event()).chain(completion.failed_event());
+            for event in events {
+                if !self.accepts(state_name, event) {
+                    return Err(WorkflowError::InvalidDefinition(format!(
+                        "State '{}' fires event '{}' when its tasks finish without a matching transition",
+                        state_name, event
+                    )));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check that a fork's branches start and join in existing states
+    fn validate_fork(&self, state_name: &str, fork: &Fork) -> WorkflowResult<()> {
+        let invalid = |message: String| {
+            Err(WorkflowError::InvalidDefinition(format!("Fork in state '{}' {}", state_name, message)))
+        };
+
+        if fork.branches().is_empty() {
+            return invalid("has no branches".to_string());
+        }
+        if fork.required() == 0 || fork.required() > fork.branches().len() {
+            return invalid(format!(
+                "requires {} of {} branches",
+                fork.required(),
+                fork.branches().len()
+            ));
+        }
+        if !self.states.contains_key(fork.join_state()) {
+            return invalid(format!("joins in non-existent state '{}'", fork.join_state()));
+        }
+        if self.states.get(fork.join_state()).is_some_and(State::is_composite) {
+            return invalid(format!("joins in composite state '{}'", fork.join_state()));
+        }
+
+        let mut names = HashSet::new();
+        for branch in fork.branches() {
+            if !names.insert(branch.name()) {
+                return invalid(format!("has duplicate branch '{}'", branch.name()));
+            }
+            match self.states.get(branch.initial_state()) {
+                None => {
+                    return invalid(format!(
+                        "starts branch '{}' in non-existent state '{}'",
+                        branch.name(),
+                        branch.initial_state()
+                    ));
+                }
+                Some(initial) if initial.fork_spec().is_some() || initial.name() == fork.join_state() => {
+                    return invalid(format!(
+                        "starts branch '{}' in a fork or join state",
+                        branch.name()
+                    ));
+                }
+                Some(initial) if initial.is_composite() => {
+                    return invalid(format!(
+                        "starts branch '{}' in composite state '{}'",
+                        branch.name(),
+                        initial.name()
+                    ));
+                }
+                Some(_) => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Outcome of a transition
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateChange {
+    /// State the workflow is in after the transition
+    pub state: String,
+    /// States whose on_enter actions ran, outermost first
+    pub entered: Vec<String>,
 }
 
 /// Builder for constructing state machines
@@ -140,10 +588,47 @@ impl StateMachineBuilder {
             ));
         }
 
-        let machine = StateMachine {
-            states: self.states,
-            initial_state,
-        };
+        // Move nested states into the machine under their qualified names
+        let mut states = HashMap::new();
+        let mut pending: Vec<(Option<String>, State)> = self.states.into_values().map(|state| (None, state)).collect();
+        while let Some((parent, mut state)) = pending.pop() {
+            if state.name().contains('/') {
+                return Err(WorkflowError::InvalidDefinition(format!(
+                    "State name '{}' contains '/'",
+                    state.name()
+                )));
+            }
+            let children = state.nest(parent.as_deref());
+            pending.extend(children.into_iter().map(|child| (Some(state.name().to_string()), child)));
+            if let Some(duplicate) = states.insert(state.name().to_string(), state) {
+                return Err(WorkflowError::InvalidDefinition(format!(
+                    "State '{}' is defined more than once",
+                    duplicate.name()
+                )));
+            }
+        }
+
+        // Names in nested states refer to the innermost state with that name
+        let names: HashSet<String> = states.keys().cloned().collect();
+        for state in states.values_mut() {
+            let Some(parent) = state.parent().map(str::to_string) else {
+                continue;
+            };
+            let resolve = |name: &str| {
+                let mut scope = Some(parent.as_str());
+                while let Some(prefix) = scope {
+                    let qualified = format!("{}/{}", prefix, name);
+                    if names.contains(&qualified) {
+                        return qualified;
+                    }
+                    scope = prefix.rsplit_once('/').map(|(outer, _)| outer);
+                }
+                name.to_string()
+            };
+            state.resolve_targets(&resolve);
+        }
+
+        let machine = StateMachine { states, initial_state };
 
         machine.validate()?;
         Ok(machine)
@@ -201,4 +686,428 @@ mod tests {
 
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_validation_timer_without_transition() {
+        let result = StateMachine::builder()
+            .initial_state("waiting")
+            .add_state(
+                State::new("waiting")
+                    .after(std::time::Duration::from_secs(30), "timeout")
+                    .add_transition(Transition::new("approve", "end")),
+            )
+            .add_state(State::new("end"))
+            .build();
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_timer_with_transition() {
+        let sm = StateMachine::builder()
+            .initial_state("waiting")
+            .add_state(
+                State::new("waiting")
+                    .after(std::time::Duration::from_secs(30), "timeout")
+                    .add_transition(Transition::new("timeout", "end")),
+            )
+            .add_state(State::new("end"))
+            .build()
+            .unwrap();
+
+        let timers = sm.get_state("waiting").unwrap().timers();
+        assert_eq!(timers.len(), 1);
+        assert_eq!(timers[0].after(), std::time::Duration::from_secs(30));
+        assert_eq!(timers[0].event(), "timeout");
+    }
+
+    #[test]
+    fn test_final_state() {
+        let sm = StateMachine::builder()
+            .initial_state("start")
+            .add_state(State::new("start").add_transition(Transition::new("done", "end")))
+            .add_state(State::new("end"))
+            .build()
+            .unwrap();
+
+        assert!(!sm.get_state("start").unwrap().is_final());
+        assert!(sm.get_state("end").unwrap().is_final());
+    }
+
+    #[test]
+    fn test_compensation_actions() {
+        let sm = StateMachine::builder()
+            .initial_state("reserve")
+            .add_state(
+                State::new("reserve")
+                    .on_failure(Action::set_data("reservation", serde_json::Value::Null))
+                    .add_transition(Transition::new("reserved", "end")),
+            )
+            .add_state(State::new("end"))
+            .build()
+            .unwrap();
+        assert_eq!(sm.get_state("reserve").unwrap().compensation_actions().len(), 1);
+
+        let result = StateMachine::builder()
+            .initial_state("start")
+            .add_state(State::new("start").on_failure(Action::start_child_workflow(
+                crate::types::WorkflowId::new(),
+                serde_json::json!({}),
+                false,
+                None,
+            )))
+            .build();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_start_child_workflow_action_serde() {
+        let definition_id = crate::types::WorkflowId::new();
+        let action = Action::start_child_workflow(
+            definition_id,
+            serde_json::json!({ "applicant": "alice" }),
+            true,
+            Some("review".to_string()),
+        );
+
+        let json = serde_json::to_value(&action).unwrap();
+        assert_eq!(json["StartChildWorkflow"]["await"], serde_json::json!(true));
+
+        let parsed: Action = serde_json::from_value(json).unwrap();
+        match parsed {
+            Action::StartChildWorkflow {
+                definition_id: id,
+                await_completion,
+                result_key,
+                ..
+            } => {
+                assert_eq!(id, definition_id);
+                assert!(await_completion);
+                assert_eq!(result_key.as_deref(), Some("review"));
+            }
+            other => panic!("unexpected action: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_publish_event_topic_validation() {
+        let machine = |topic: &str| {
+            StateMachine::builder()
+                .initial_state("moved")
+                .add_state(State::new("moved").on_enter(Action::publish_event(topic, Some("address".to_string()))))
+                .build()
+        };
+
+        assert!(machine("citizen.address_changed").is_ok());
+        assert!(machine("citizen:address").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_merge_data() {
+        let mut ctx = Context::with_data(
+            crate::types::WorkflowId::new(),
+            "start".to_string(),
+            serde_json::json!({"applicant": {"name": "Ada", "phone": "555"}, "status": "new"}),
+        );
+        Action::merge_data(serde_json::json!({"applicant": {"phone": null, "email": "ada@example.org"}, "status": "open"}))
+            .execute(&mut ctx)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            ctx.data(),
+            &serde_json::json!({"applicant": {"name": "Ada", "email": "ada@example.org"}, "status": "open"})
+        );
+    }
+
+    #[test]
+    fn test_http_request_validation() {
+        let machine = |state: State| {
+            StateMachine::builder()
+                .initial_state("notify")
+                .add_state(state.add_transition(Transition::new("notified", "end")))
+                .add_state(State::new("end"))
+                .build()
+        };
+        let request = HttpRequest::post("https://registry.example/cases/{{ case.id }}")
+            .with_body(serde_json::json!({"status": "{{ status }}"}));
+
+        assert!(machine(State::new("notify").on_enter(Action::http_request(request.clone().on_success("notified")))).is_ok());
+        assert!(machine(State::new("notify").on_enter(Action::http_request(request.clone().on_failure("rejected")))).is_err());
+        assert!(machine(State::new("notify").on_exit(Action::http_request(request.clone()))).is_err());
+        assert!(machine(State::new("notify").on_failure(Action::http_request(request.clone()))).is_ok());
+        assert!(machine(State::new("notify").on_failure(Action::http_request(request.on_success("notified")))).is_err());
+        assert!(machine(State::new("notify").on_enter(Action::http_request(HttpRequest::get("https://x/{{")))).is_err());
+    }
+
+    #[test]
+    fn test_task_output_validation() {
+        let task = |result_path: &str, on_complete: Option<&str>| TaskDefinition {
+            name: "verify".to_string(),
+            runtime_type: crate::types::RuntimeType::JavaScript,
+            code: b"input".to_vec(),
+            timeout_ms: 1000,
+            result_path: Some(result_path.to_string()),
+            on_complete: on_complete.map(str::to_string),
+            ..Default::default()
+        };
+        let machine = |state: State| {
+            StateMachine::builder()
+                .initial_state("verify")
+                .add_state(state.add_transition(Transition::new("verified", "end")))
+                .add_state(State::new("end"))
+                .build()
+        };
+
+        let verify = task("$.steps.verify", Some("verified"));
+        assert!(machine(State::new("verify").on_enter(Action::execute_task(verify.clone()))).is_ok());
+        assert!(machine(State::new("verify").on_failure(Action::execute_task(verify))).is_err());
+        assert!(machine(State::new("verify").on_enter(Action::execute_task(task("steps", None)))).is_err());
+        assert!(machine(State::new("verify").on_enter(Action::execute_task(task("$.steps", Some("done"))))).is_err());
+    }
+
+    #[test]
+    fn test_completion_validation() {
+        let task = TaskDefinition {
+            name: "verify".to_string(),
+            runtime_type: crate::types::RuntimeType::JavaScript,
+            code: b"input".to_vec(),
+            timeout_ms: 1000,
+            ..Default::default()
+        };
+        let machine = |state: State| {
+            StateMachine::builder()
+                .initial_state("verify")
+                .add_state(
+                    state
+                        .add_transition(Transition::new("done", "end"))
+                        .add_transition(Transition::new("failed", "end")),
+                )
+                .add_state(State::new("end"))
+                .build()
+        };
+
+        let policy = Completion::new("done").or_failed("failed");
+        let sm = machine(
+            State::new("verify")
+                .on_enter(Action::execute_task(task.clone()))
+                .on_tasks_finished(policy.clone()),
+        )
+        .unwrap();
+        assert_eq!(sm.get_state("verify").unwrap().completion(), Some(&policy));
+
+        assert!(machine(State::new("verify").on_tasks_finished(Completion::new("done"))).is_err());
+        assert!(machine(
+            State::new("verify")
+                .on_enter(Action::execute_task(task))
+                .on_tasks_finished(Completion::new("done").or_failed("rejected"))
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_human_task_requires_transition() {
+        let approval = HumanTaskSpec::new("Approve permit", "reviewed")
+            .for_role("clerk")
+            .result_key("review");
+        let sm = StateMachine::builder()
+            .initial_state("review")
+            .add_state(
+                State::new("review")
+                    .human_task(approval.clone())
+                    .add_transition(Transition::new("reviewed", "end")),
+            )
+            .add_state(State::new("end"))
+            .build()
+            .unwrap();
+        assert_eq!(sm.get_state("review").unwrap().human_task_spec(), Some(&approval));
+
+        let result = StateMachine::builder()
+            .initial_state("review")
+            .add_state(State::new("review").human_task(approval))
+            .build();
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_fork_validation() {
+        let signatures = Fork::new("signed")
+            .branch("finance", "finance_review")
+            .branch("legal", "legal_review")
+            .branch("it", "it_review");
+        let machine = |fork: Fork| {
+            StateMachine::builder()
+                .initial_state("collect")
+                .add_state(State::new("collect").fork(fork))
+                .add_state(State::new("finance_review").add_transition(Transition::new("signed", "signed")))
+                .add_state(State::new("legal_review").add_transition(Transition::new("signed", "signed")))
+                .add_state(State::new("it_review").add_transition(Transition::new("signed", "signed")))
+                .add_state(State::new("signed"))
+                .build()
+        };
+
+        let sm = machine(signatures.clone().require(2)).unwrap();
+        let collect = sm.get_state("collect").unwrap();
+        assert!(!collect.is_final());
+        assert_eq!(collect.fork_spec().unwrap().required(), 2);
+        assert_eq!(signatures.required(), 3);
+
+        assert!(machine(signatures.clone().require(4)).is_err());
+        assert!(machine(signatures.clone().branch("it", "it_review")).is_err());
+        assert!(machine(Fork::new("signed")).is_err());
+        assert!(machine(Fork::new("missing").branch("finance", "finance_review")).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_branch_entering_join_skips_on_enter() {
+        let sm = StateMachine::builder()
+            .initial_state("collect")
+            .add_state(State::new("collect").fork(Fork::new("signed").branch("finance", "review")))
+            .add_state(State::new("review").add_transition(Transition::new("sign", "signed")))
+            .add_state(State::new("signed").on_enter(Action::set_data("joined", serde_json::json!(true))))
+            .build()
+            .unwrap();
+
+        let mut ctx = Context::new(crate::types::WorkflowId::new(), "review".to_string());
+        let state = sm.transition_branch(&mut ctx, "sign", "signed").await.unwrap();
+        assert_eq!(state, "signed");
+        assert!(ctx.get("joined").is_none());
+    }
+
+    fn order_machine() -> StateMachine {
+        StateMachine::builder()
+            .initial_state("order")
+            .add_state(
+                State::new("order")
+                    .on_enter(Action::set_data("entered", serde_json::json!("order")))
+                    .on_exit(Action::set_data("left", serde_json::json!("order")))
+                    .add_transition(Transition::new("cancel", "cancelled"))
+                    .child(State::new("cart").add_transition(Transition::new("checkout", "payment")))
+                    .child(
+                        State::new("payment")
+                            .on_enter(Action::set_data("entered", serde_json::json!("payment")))
+                            .on_exit(Action::set_data("left", serde_json::json!("payment"))),
+                    ),
+            )
+            .add_state(State::new("cancelled"))
+            .build()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_transition_enters_target_and_completes_in_final_state() {
+        let sm = StateMachine::builder()
+            .initial_state("draft")
+            .add_state(State::new("draft").add_transition(Transition::new("submit", "review")))
+            .add_state(
+                State::new("review")
+                    .on_enter(Action::set_data("reviewing", serde_json::json!(true)))
+                    .add_transition(Transition::new("approve", "approved")),
+            )
+            .add_state(State::new("approved").on_enter(Action::set_data("approved", serde_json::json!(true))))
+            .build()
+            .unwrap();
+
+        // The engine runs the on_enter actions of every entered state
+        let mut ctx = Context::new(crate::types::WorkflowId::new(), "draft".to_string());
+        let change = sm.transition_states(&mut ctx, "submit").await.unwrap();
+        assert_eq!(change.entered, vec!["review".to_string()]);
+        assert_eq!(ctx.get("reviewing"), Some(&serde_json::json!(true)));
+        assert_eq!(sm.status_in(&change.state), WorkflowStatus::Running);
+
+        let change = sm.transition_states(&mut ctx, "approve").await.unwrap();
+        assert_eq!(change.entered, vec!["approved".to_string()]);
+        assert_eq!(ctx.get("approved"), Some(&serde_json::json!(true)));
+        assert_eq!(sm.status_in(&change.state), WorkflowStatus::Completed);
+    }
+
+    #[tokio::test]
+    async fn test_nested_states() {
+        let sm = order_machine();
+        assert_eq!(sm.initial_state(), "order/cart");
+        assert!(sm.accepts("order/cart", "cancel"));
+        assert!(!sm.is_final("order/payment"));
+        assert!(sm.is_final("cancelled"));
+
+        // Moving between children stays inside the composite state
+        let mut ctx = Context::new(crate::types::WorkflowId::new(), "order/cart".to_string());
+        let change = sm.transition_states(&mut ctx, "checkout").await.unwrap();
+        assert_eq!(change.state, "order/payment");
+        assert_eq!(change.entered, vec!["order/payment".to_string()]);
+        assert_eq!(ctx.get("entered"), Some(&serde_json::json!("payment")));
+        assert!(ctx.get("left").is_none());
+
+        // The composite state's transition leaves the child, then the parent
+        let change = sm.transition_states(&mut ctx, "cancel").await.unwrap();
+        assert_eq!(change.state, "cancelled");
+        assert_eq!(change.entered, vec!["cancelled".to_string()]);
+        assert_eq!(ctx.get("left"), Some(&serde_json::json!("order")));
+    }
+
+    #[tokio::test]
+    async fn test_entering_composite_state_enters_initial_child() {
+        let sm = StateMachine::builder()
+            .initial_state("draft")
+            .add_state(State::new("draft").add_transition(Transition::new("submit", "review")))
+            .add_state(
+                State::new("review")
+                    .initial_child("legal")
+                    .child(State::new("finance"))
+                    .child(State::new("legal").add_transition(Transition::new("approve", "finance"))),
+            )
+            .build()
+            .unwrap();
+
+        let mut ctx = Context::new(crate::types::WorkflowId::new(), "draft".to_string());
+        let change = sm.transition_states(&mut ctx, "submit").await.unwrap();
+        assert_eq!(change.state, "review/legal");
+        assert_eq!(change.entered, vec!["review".to_string(), "review/legal".to_string()]);
+        assert!(sm.is_final("review/finance"));
+    }
+
+    #[test]
+    fn test_nested_state_validation() {
+        let nested_name = StateMachine::builder()
+            .initial_state("a/b")
+            .add_state(State::new("a/b"))
+            .build();
+        assert!(nested_name.is_err());
+
+        let composite_timer = StateMachine::builder()
+            .initial_state("order")
+            .add_state(
+                State::new("order")
+                    .add_transition(Transition::new("expire", "expired"))
+                    .after(std::time::Duration::from_secs(60), "expire")
+                    .child(State::new("cart")),
+            )
+            .add_state(State::new("expired"))
+            .build();
+        assert!(composite_timer.is_err());
+
+        let duplicate_child = StateMachine::builder()
+            .initial_state("order")
+            .add_state(State::new("order").child(State::new("cart")).child(State::new("cart")))
+            .build();
+        assert!(duplicate_child.is_err());
+    }
+
+    #[test]
+    fn test_nested_targets_resolve_outwards() {
+        let sm = StateMachine::builder()
+            .initial_state("order")
+            .add_state(
+                State::new("order")
+                    .child(State::new("cart").add_transition(Transition::new("checkout", "payment")))
+                    .child(State::new("payment").add_transition(Transition::new("paid", "done"))),
+            )
+            .add_state(State::new("payment"))
+            .add_state(State::new("done"))
+            .build()
+            .unwrap();
+
+        let target = |state: &str| sm.get_state(state).unwrap().transitions()[0].target_state().to_string();
+        assert_eq!(target("order/cart"), "order/payment");
+        assert_eq!(target("order/payment"), "done");
+    }
 }
@@ -0,0 +1,86 @@
+//! Parallel branches for state machines
+
+use serde::{Deserialize, Serialize};
+
+/// Fans a state out into branches that run concurrently
+///
+/// Entering a forked state starts every branch in its initial state. Each
+/// branch then moves through the machine's states on its own events until a
+/// transition takes it into the join state. Once the required number of
+/// branches arrived there, the remaining branches are dropped and the
+/// workflow continues in the join state.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fork {
+    join: String,
+    branches: Vec<Branch>,
+    #[serde(default)]
+    required: Option<usize>,
+}
+
+impl Fork {
+    /// Create a fork whose branches meet in `join`
+    pub fn new(join: impl Into<String>) -> Self {
+        Self {
+            join: join.into(),
+            branches: Vec::new(),
+            required: None,
+        }
+    }
+
+    /// Add a branch starting in `initial_state`
+    pub fn branch(mut self, name: impl Into<String>, initial_state: impl Into<String>) -> Self {
+        self.branches.push(Branch {
+            name: name.into(),
+            initial_state: initial_state.into(),
+        });
+        self
+    }
+
+    /// Join once `count` branches arrived instead of all of them
+    pub fn require(mut self, count: usize) -> Self {
+        self.required = Some(count);
+        self
+    }
+
+    /// Get the state the branches meet in
+    pub fn join_state(&self) -> &str {
+        &self.join
+    }
+
+    /// Get the branches
+    pub fn branches(&self) -> &[Branch] {
+        &self.branches
+    }
+
+    /// Number of branches that have to arrive before the workflow continues
+    pub fn required(&self) -> usize {
+        self.required.unwrap_or(self.branches.len())
+    }
+
+    /// Replace the join and branch states with the states they name
+    pub(super) fn resolve(&mut self, resolve: &dyn Fn(&str) -> String) {
+        self.join = resolve(&self.join);
+        for branch in &mut self.branches {
+            branch.initial_state = resolve(&branch.initial_state);
+        }
+    }
+}
+
+/// One concurrently running branch of a fork
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Branch {
+    name: String,
+    initial_state: String,
+}
+
+impl Branch {
+    /// Get the branch name, unique within its fork
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get the state the branch starts in
+    pub fn initial_state(&self) -> &str {
+        &self.initial_state
+    }
+}
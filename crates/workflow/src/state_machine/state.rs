@@ -1,9 +1,10 @@
 //! State definition for state machines
 
-use super::{Context, Transition};
+use super::{Completion, Context, Fork, HttpRequest, HumanTaskSpec, Timer, Transition};
 use crate::error::WorkflowResult;
-use crate::types::TaskDefinition;
+use crate::types::{TaskDefinition, WorkflowId};
 use serde::{Deserialize, Serialize};
+use std::time::Duration;
 
 /// A state in the state machine
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -13,8 +14,32 @@ pub struct State {
     on_enter: Vec<Action>,
     #[serde(default)]
     on_exit: Vec<Action>,
+    /// Compensation run when the workflow fails after leaving this state
+    #[serde(default)]
+    on_failure: Vec<Action>,
     #[serde(default)]
     transitions: Vec<Transition>,
+    #[serde(default)]
+    timers: Vec<Timer>,
+    /// Manual task created when the state is entered
+    #[serde(default)]
+    human_task: Option<HumanTaskSpec>,
+    /// Branches started when the state is entered
+    #[serde(default)]
+    fork: Option<Fork>,
+    /// Events fired when the tasks enqueued on entry finish
+    #[serde(default)]
+    completion: Option<Completion>,
+    /// Nested states, moved into the machine under qualified names when it
+    /// is built
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    children: Vec<State>,
+    /// Child entered when a composite state is entered
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    initial_child: Option<String>,
+    /// Composite state this state is nested in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    parent: Option<String>,
 }
 
 impl State {
@@ -24,7 +49,15 @@ impl State {
             name: name.into(),
             on_enter: Vec::new(),
             on_exit: Vec::new(),
+            on_failure: Vec::new(),
             transitions: Vec::new(),
+            timers: Vec::new(),
+            human_task: None,
+            fork: None,
+            completion: None,
+            children: Vec::new(),
+            initial_child: None,
+            parent: None,
         }
     }
 
@@ -45,12 +78,62 @@ impl State {
         self
     }
 
+    /// Add a compensation action
+    ///
+    /// When the workflow later fails, the compensation actions of the states
+    /// it passed through run in reverse order to undo their effects.
+    pub fn on_failure(mut self, action: Action) -> Self {
+        self.on_failure.push(action);
+        self
+    }
+
     /// Add a transition
     pub fn add_transition(mut self, transition: Transition) -> Self {
         self.transitions.push(transition);
         self
     }
 
+    /// Fire `event` once this state has been active for `duration`
+    pub fn after(mut self, duration: Duration, event: impl Into<String>) -> Self {
+        self.timers.push(Timer::new(duration, event));
+        self
+    }
+
+    /// Wait in this state for a person to complete a task
+    pub fn human_task(mut self, spec: HumanTaskSpec) -> Self {
+        self.human_task = Some(spec);
+        self
+    }
+
+    /// Run branches in parallel while in this state
+    pub fn fork(mut self, fork: Fork) -> Self {
+        self.fork = Some(fork);
+        self
+    }
+
+    /// Fire events when the tasks enqueued on entry finish
+    pub fn on_tasks_finished(mut self, completion: Completion) -> Self {
+        self.completion = Some(completion);
+        self
+    }
+
+    /// Nest a state in this one, making it a composite state
+    ///
+    /// Children are named `parent/child` once the machine is built. States
+    /// named by their transitions are looked up among their siblings first,
+    /// then outwards. Transitions of the composite state apply in every
+    /// child, and its entry and exit actions run around the children's.
+    pub fn child(mut self, state: State) -> Self {
+        self.children.push(state);
+        self
+    }
+
+    /// Set the child entered first, the first child added by default
+    pub fn initial_child(mut self, name: impl Into<String>) -> Self {
+        self.initial_child = Some(name.into());
+        self
+    }
+
     /// Get on_enter actions
     pub fn on_enter_actions(&self) -> &[Action] {
         &self.on_enter
@@ -61,11 +144,100 @@ impl State {
         &self.on_exit
     }
 
+    /// Get compensation actions
+    pub fn compensation_actions(&self) -> &[Action] {
+        &self.on_failure
+    }
+
+    /// Get all actions for rewriting, compensation included
+    pub(crate) fn actions_mut(&mut self) -> impl Iterator<Item = &mut Action> {
+        self.on_enter
+            .iter_mut()
+            .chain(self.on_exit.iter_mut())
+            .chain(self.on_failure.iter_mut())
+    }
+
     /// Get all transitions
     pub fn transitions(&self) -> &[Transition] {
         &self.transitions
     }
 
+    /// Get all timers
+    pub fn timers(&self) -> &[Timer] {
+        &self.timers
+    }
+
+    /// Get the manual task of the state
+    pub fn human_task_spec(&self) -> Option<&HumanTaskSpec> {
+        self.human_task.as_ref()
+    }
+
+    /// Get the branches of the state
+    pub fn fork_spec(&self) -> Option<&Fork> {
+        self.fork.as_ref()
+    }
+
+    /// Get the completion policy of the state
+    pub fn completion(&self) -> Option<&Completion> {
+        self.completion.as_ref()
+    }
+
+    /// Get the composite state this state is nested in
+    pub fn parent(&self) -> Option<&str> {
+        self.parent.as_deref()
+    }
+
+    /// Get the child entered when this composite state is entered
+    pub fn initial_child_state(&self) -> Option<&str> {
+        self.initial_child.as_deref()
+    }
+
+    /// Replace the states named by transitions and forks with the states
+    /// they resolve to
+    pub(super) fn resolve_targets(&mut self, resolve: &dyn Fn(&str) -> String) {
+        for transition in &mut self.transitions {
+            transition.resolve(resolve);
+        }
+        if let Some(fork) = &mut self.fork {
+            fork.resolve(resolve);
+        }
+    }
+
+    /// Get the nested states not yet moved into a machine
+    pub fn children(&self) -> &[State] {
+        &self.children
+    }
+
+    /// Whether the state has child states
+    pub fn is_composite(&self) -> bool {
+        self.initial_child.is_some() || !self.children.is_empty()
+    }
+
+    /// Take the children out of the state, nesting it in `parent`
+    ///
+    /// The name is qualified with the parent's name.
+    pub(super) fn nest(&mut self, parent: Option<&str>) -> Vec<State> {
+        if let Some(parent) = parent {
+            self.name = format!("{}/{}", parent, self.name);
+            self.parent = Some(parent.to_string());
+        }
+
+        let children = std::mem::take(&mut self.children);
+        if let Some(first) = children.first() {
+            let initial = self.initial_child.take().unwrap_or_else(|| first.name.clone());
+            self.initial_child = Some(format!("{}/{}", self.name, initial));
+        }
+        children
+    }
+
+    /// Whether the workflow ends when it enters this state
+    ///
+    /// States nested in a composite state also need the composite state to
+    /// be final, see [`super::StateMachine::is_final`].
+    pub fn is_final(&self) -> bool {
+        self.transitions.is_empty() && self.timers.is_empty() && self.fork.is_none()
+    }
+
     /// Find a transition that matches the event and passes guards
     pub fn find_transition(&self, event: &str, ctx: &Context) -> Option<&Transition> {
         self.transitions
@@ -86,8 +258,45 @@ pub enum Action {
         value: serde_json::Value,
     },
     
+    /// Merge an object into the context
+    ///
+    /// Applied as a JSON merge patch: nested objects are merged, `null`
+    /// removes a field and any other value replaces it.
+    MergeData { value: serde_json::Value },
+
     /// Log a message (for debugging)
     Log { message: String },
+
+    /// Start a child workflow instance (handled by the engine)
+    ///
+    /// With `await` set, the state accepts no events until the child
+    /// completes, after which the engine fires `child_completed` on the
+    /// parent. The child's final context is stored under `result_key`.
+    StartChildWorkflow {
+        definition_id: WorkflowId,
+        #[serde(default)]
+        input: serde_json::Value,
+        #[serde(rename = "await", default)]
+        await_completion: bool,
+        #[serde(default)]
+        result_key: Option<String>,
+    },
+
+    /// Publish an event on the event bus (handled by the engine)
+    ///
+    /// The payload is the context value at `payload_key`, or the whole
+    /// context when unset.
+    PublishEvent {
+        topic: String,
+        #[serde(default)]
+        payload_key: Option<String>,
+    },
+    
+    /// Make an HTTP request (handled by the engine)
+    ///
+    /// The request is persisted before it is sent and retried according to
+    /// its retry policy, so it survives engine restarts.
+    HttpRequest(HttpRequest),
     
     /// No-op action
     NoOp,
@@ -106,10 +315,26 @@ impl Action {
                 ctx.set(key, value.clone());
                 Ok(())
             }
+            Action::MergeData { value } => {
+                merge(ctx.data_mut(), value);
+                Ok(())
+            }
             Action::Log { message } => {
                 tracing::info!("State action log: {}", message);
                 Ok(())
             }
+            Action::StartChildWorkflow { .. } => {
+                // Child workflows are started by the engine
+                Ok(())
+            }
+            Action::PublishEvent { .. } => {
+                // Events are published by the engine
+                Ok(())
+            }
+            Action::HttpRequest(_) => {
+                // Requests are made by the engine
+                Ok(())
+            }
             Action::NoOp => Ok(()),
         }
     }
@@ -127,6 +352,39 @@ impl Action {
         }
     }
 
+    /// Create a StartChildWorkflow action
+    pub fn start_child_workflow(
+        definition_id: WorkflowId,
+        input: serde_json::Value,
+        await_completion: bool,
+        result_key: Option<String>,
+    ) -> Self {
+        Action::StartChildWorkflow {
+            definition_id,
+            input,
+            await_completion,
+            result_key,
+        }
+    }
+
+    /// Create a PublishEvent action
+    pub fn publish_event(topic: impl Into<String>, payload_key: Option<String>) -> Self {
+        Action::PublishEvent {
+            topic: topic.into(),
+            payload_key,
+        }
+    }
+
+    /// Create a MergeData action
+    pub fn merge_data(value: serde_json::Value) -> Self {
+        Action::MergeData { value }
+    }
+
+    /// Create an HttpRequest action
+    pub fn http_request(request: HttpRequest) -> Self {
+        Action::HttpRequest(request)
+    }
+
     /// Create a Log action
     pub fn log(message: impl Into<String>) -> Self {
         Action::Log {
@@ -135,3 +393,23 @@ impl Action {
     }
 }
 
+
+/// Apply `patch` to `target` as a JSON merge patch (RFC 7396)
+fn merge(target: &mut serde_json::Value, patch: &serde_json::Value) {
+    let serde_json::Value::Object(patch) = patch else {
+        *target = patch.clone();
+        return;
+    };
+    if !target.is_object() {
+        *target = serde_json::Value::Object(serde_json::Map::new());
+    }
+    if let serde_json::Value::Object(map) = target {
+        for (key, value) in patch {
+            if value.is_null() {
+                map.remove(key);
+            } else {
+                merge(map.entry(key.clone()).or_insert(serde_json::Value::Null), value);
+            }
+        }
+    }
+}
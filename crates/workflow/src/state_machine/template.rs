@@ -0,0 +1,81 @@
+//! Templates filled in from the workflow context
+//!
+//! `{{ expr }}` in a string is replaced by the value of a guard expression
+//! evaluated against the context data:
+//!
+//! ```text
+//! Dear {{ applicant.name }}, your case {{ case.id }} was received
+//! ```
+//!
+//! A string consisting of a single placeholder keeps the JSON type of its
+//! value, so `{"amount": "{{ invoice.total }}"}` renders to a number.
+//! Otherwise strings are inserted as they are, `null` as nothing and other
+//! values as JSON.
+
+use super::Expression;
+use crate::error::{WorkflowError, WorkflowResult};
+use serde_json::Value;
+
+/// Render a template into a string
+pub(crate) fn render_string(template: &str, data: &Value) -> WorkflowResult<String> {
+    Ok(match render(template, data)? {
+        Value::String(s) => s,
+        Value::Null => String::new(),
+        value => value.to_string(),
+    })
+}
+
+/// Render the templates in all strings of a JSON value
+pub(crate) fn render_value(value: &mut Value, data: &Value) -> WorkflowResult<()> {
+    match value {
+        Value::String(template) => *value = render(template, data)?,
+        Value::Array(items) => {
+            for item in items {
+                render_value(item, data)?;
+            }
+        }
+        Value::Object(map) => {
+            for item in map.values_mut() {
+                render_value(item, data)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Render a template, keeping the value of a lone placeholder
+fn render(template: &str, data: &Value) -> WorkflowResult<Value> {
+    let invalid = |message: String| {
+        WorkflowError::InvalidDefinition(format!("Invalid template '{}': {}", template, message))
+    };
+
+    let mut parts = Vec::new();
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let end = rest[start..]
+            .find("}}")
+            .ok_or_else(|| invalid("unclosed '{{'".to_string()))?;
+        let expression = Expression::parse(rest[start + 2..start + end].trim())
+            .map_err(|e| invalid(e.to_string()))?;
+        parts.push((&rest[..start], expression.evaluate(data)));
+        rest = &rest[start + end + 2..];
+    }
+
+    if let [("", value)] = parts.as_slice()
+        && rest.is_empty()
+    {
+        return Ok(value.clone());
+    }
+    let mut rendered = String::new();
+    for (text, value) in parts {
+        rendered.push_str(text);
+        match value {
+            Value::String(s) => rendered.push_str(&s),
+            Value::Null => {}
+            value => rendered.push_str(&value.to_string()),
+        }
+    }
+    rendered.push_str(rest);
+    Ok(Value::String(rendered))
+}
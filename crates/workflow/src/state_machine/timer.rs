@@ -0,0 +1,31 @@
+//! Delayed transitions for state machines
+
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// A timer that fires an event once a state has been active for a given duration
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Timer {
+    after_ms: u64,
+    event: String,
+}
+
+impl Timer {
+    /// Create a new timer
+    pub fn new(after: Duration, event: impl Into<String>) -> Self {
+        Self {
+            after_ms: after.as_millis() as u64,
+            event: event.into(),
+        }
+    }
+
+    /// Get the delay after which the timer fires
+    pub fn after(&self) -> Duration {
+        Duration::from_millis(self.after_ms)
+    }
+
+    /// Get the event fired when the timer expires
+    pub fn event(&self) -> &str {
+        &self.event
+    }
+}
@@ -1,6 +1,7 @@
 //! Transition logic for state machines
 
-use super::Context;
+use super::{Context, Expression};
+use crate::error::{WorkflowError, WorkflowResult};
 use serde::{Deserialize, Serialize};
 
 /// A transition between states
@@ -8,7 +9,7 @@ use serde::{Deserialize, Serialize};
 pub struct Transition {
     event: String,
     target_state: String,
-    #[serde(skip)]
+    #[serde(default)]
     guard: Option<Guard>,
 }
 
@@ -38,6 +39,16 @@ impl Transition {
         &self.target_state
     }
 
+    /// Get the guard condition
+    pub fn guard(&self) -> Option<&Guard> {
+        self.guard.as_ref()
+    }
+
+    /// Replace the target with the state it names
+    pub(super) fn resolve(&mut self, resolve: &dyn Fn(&str) -> String) {
+        self.target_state = resolve(&self.target_state);
+    }
+
     /// Check if this transition matches the event and passes guards
     pub fn matches(&self, event: &str, ctx: &Context) -> bool {
         if self.event != event {
@@ -53,9 +64,18 @@ impl Transition {
 }
 
 /// Guard condition for transitions
+///
+/// Guards are either Rust functions or [`Expression`]s. Only expression guards
+/// are kept when a definition is serialized; function guards are dropped.
 #[derive(Clone)]
 pub struct Guard {
-    check_fn: std::sync::Arc<dyn Fn(&Context) -> bool + Send + Sync>,
+    kind: GuardKind,
+}
+
+#[derive(Clone)]
+enum GuardKind {
+    Fn(std::sync::Arc<dyn Fn(&Context) -> bool + Send + Sync>),
+    Expr(Expression),
 }
 
 impl Guard {
@@ -65,41 +85,102 @@ impl Guard {
         F: Fn(&Context) -> bool + Send + Sync + 'static,
     {
         Self {
-            check_fn: std::sync::Arc::new(f),
+            kind: GuardKind::Fn(std::sync::Arc::new(f)),
+        }
+    }
+
+    /// Create a guard from an expression over the context data, e.g.
+    /// `amount > 1000 && applicant.verified`
+    pub fn expr(source: &str) -> WorkflowResult<Self> {
+        let expression = Expression::parse(source).map_err(|e| {
+            WorkflowError::InvalidDefinition(format!("Invalid guard '{}': {}", source, e))
+        })?;
+        Ok(Self {
+            kind: GuardKind::Expr(expression),
+        })
+    }
+
+    /// Get the expression of an expression guard
+    pub fn expression(&self) -> Option<&Expression> {
+        match &self.kind {
+            GuardKind::Expr(expression) => Some(expression),
+            GuardKind::Fn(_) => None,
         }
     }
 
     /// Check if the guard passes for the given context
     pub fn check(&self, ctx: &Context) -> bool {
-        (self.check_fn)(ctx)
+        match &self.kind {
+            GuardKind::Fn(check_fn) => check_fn(ctx),
+            GuardKind::Expr(expression) => expression.is_satisfied(ctx.data()),
+        }
     }
 }
 
 impl std::fmt::Debug for Guard {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.debug_struct("Guard").finish()
+        let mut debug = f.debug_struct("Guard");
+        if let GuardKind::Expr(expression) = &self.kind {
+            debug.field("expr", &expression.source());
+        }
+        debug.finish()
     }
 }
 
-// Manual Serialize/Deserialize since we can't serialize closures
+// Function guards cannot be serialized; they are written as `null` and read
+// back as no guard
 impl Serialize for Guard {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: serde::Serializer,
     {
-        // Guards are not serializable, just serialize a placeholder
-        serializer.serialize_none()
+        match &self.kind {
+            GuardKind::Expr(expression) => serializer.serialize_str(expression.source()),
+            GuardKind::Fn(_) => serializer.serialize_none(),
+        }
     }
 }
 
 impl<'de> Deserialize<'de> for Guard {
-    fn deserialize<D>(_deserializer: D) -> Result<Self, D::Error>
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
         D: serde::Deserializer<'de>,
     {
-        // Create a default guard that always returns true
-        Ok(Guard::new(|_| true))
+        let source = String::deserialize(deserializer)?;
+        Guard::expr(&source).map_err(serde::de::Error::custom)
     }
 }
 
-
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::WorkflowId;
+
+    #[test]
+    fn test_expression_guard_roundtrip() {
+        let transition = Transition::new("approve", "approved")
+            .with_guard(Guard::expr("amount <= 1000 || reviewer.senior").unwrap());
+        let json = serde_json::to_value(&transition).unwrap();
+        assert_eq!(json["guard"], "amount <= 1000 || reviewer.senior");
+
+        let parsed: Transition = serde_json::from_value(json).unwrap();
+        let ctx = |data| Context::with_data(WorkflowId::new(), "review".to_string(), data);
+        assert!(parsed.matches("approve", &ctx(serde_json::json!({"amount": 500}))));
+        assert!(!parsed.matches("approve", &ctx(serde_json::json!({"amount": 5000}))));
+
+        let unguarded: Transition = serde_json::from_value(serde_json::json!({
+            "event": "approve",
+            "target_state": "approved",
+            "guard": null,
+        }))
+        .unwrap();
+        assert!(unguarded.matches("approve", &ctx(serde_json::json!({}))));
+
+        let invalid = serde_json::from_value::<Transition>(serde_json::json!({
+            "event": "approve",
+            "target_state": "approved",
+            "guard": "amount >",
+        }));
+        assert!(invalid.is_err());
+    }
+}
@@ -0,0 +1,72 @@
+//! Trace context propagation
+//!
+//! The engine and workers emit their spans through `tracing`. Once the
+//! process installs a `tracing_opentelemetry` layer, the W3C trace context of
+//! those spans travels with RPC requests and queued tasks, so starting a
+//! workflow, enqueueing its tasks, running them on a worker and completing
+//! them end up in one distributed trace.
+
+use opentelemetry::propagation::TextMapPropagator;
+use opentelemetry::trace::TraceContextExt;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use std::collections::HashMap;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// W3C trace context fields (`traceparent` and `tracestate`) of a span
+pub type TraceContext = HashMap<String, String>;
+
+/// Trace context of `span`, empty when the span is not exported
+pub fn context_of(span: &tracing::Span) -> TraceContext {
+    let mut carrier = TraceContext::new();
+    let context = span.context();
+    if context.span().span_context().is_valid() {
+        TraceContextPropagator::new().inject_context(&context, &mut carrier);
+    }
+    carrier
+}
+
+/// Trace context of the current span
+pub fn current() -> TraceContext {
+    context_of(&tracing::Span::current())
+}
+
+/// Continue the trace of `carrier` in `span`
+///
+/// Must be called before the span is entered, so its children join the
+/// trace as well. An empty carrier leaves the span a root.
+pub fn set_parent(span: &tracing::Span, carrier: &TraceContext) {
+    if carrier.is_empty() {
+        return;
+    }
+    span.set_parent(TraceContextPropagator::new().extract(carrier));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::trace::TracerProvider;
+    use opentelemetry_sdk::trace::SdkTracerProvider;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[test]
+    fn test_trace_context_round_trip() {
+        let provider = SdkTracerProvider::builder().build();
+        let layer = tracing_opentelemetry::layer().with_tracer(provider.tracer("test"));
+        let subscriber = tracing_subscriber::registry().with(layer);
+
+        tracing::subscriber::with_default(subscriber, || {
+            assert!(context_of(&tracing::Span::none()).is_empty());
+
+            let engine = tracing::info_span!("engine");
+            let carrier = context_of(&engine);
+            assert!(carrier.contains_key("traceparent"));
+
+            let worker = tracing::info_span!(parent: None, "worker");
+            set_parent(&worker, &carrier);
+            assert_eq!(
+                worker.context().span().span_context().trace_id(),
+                engine.context().span().span_context().trace_id()
+            );
+        });
+    }
+}
@@ -0,0 +1,231 @@
+//! Message size limits and chunked payload transfer
+//!
+//! Both sides of the worker RPC reject messages above a configured size with
+//! `RpcError::ResourceExhausted`. Payloads that may legitimately exceed that
+//! size (task code such as large WASM modules) are sent in chunks instead and
+//! reassembled with a `ChunkAssembler`.
+
+use crate::error::{RpcError, RpcResult};
+
+/// Default maximum size of a single RPC message (4 MiB)
+pub const DEFAULT_MAX_MESSAGE_BYTES: usize = 4 * 1024 * 1024;
+
+/// Default size of a single chunk of a chunked payload (1 MiB)
+pub const DEFAULT_CHUNK_BYTES: usize = 1024 * 1024;
+
+/// Default maximum size of a reassembled chunked payload (256 MiB)
+pub const DEFAULT_MAX_PAYLOAD_BYTES: usize = 256 * 1024 * 1024;
+
+/// Size limits for RPC messages and chunked payloads
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MessageLimits {
+    max_message_bytes: usize,
+    chunk_bytes: usize,
+    max_payload_bytes: usize,
+}
+
+impl MessageLimits {
+    /// Set the maximum size of a single RPC message
+    ///
+    /// The chunk size is lowered if needed so a chunk and its envelope always
+    /// fit in one message.
+    pub fn with_max_message_bytes(mut self, bytes: usize) -> Self {
+        self.max_message_bytes = bytes;
+        self.chunk_bytes = self.chunk_bytes.min(bytes / 2).max(1);
+        self
+    }
+
+    /// Set the size of a single chunk
+    pub fn with_chunk_bytes(mut self, bytes: usize) -> Self {
+        self.chunk_bytes = bytes.min(self.max_message_bytes / 2).max(1);
+        self
+    }
+
+    /// Set the maximum size of a reassembled chunked payload
+    pub fn with_max_payload_bytes(mut self, bytes: usize) -> Self {
+        self.max_payload_bytes = bytes;
+        self
+    }
+
+    /// Get the maximum size of a single RPC message
+    pub fn max_message_bytes(&self) -> usize {
+        self.max_message_bytes
+    }
+
+    /// Get the size of a single chunk
+    pub fn chunk_bytes(&self) -> usize {
+        self.chunk_bytes
+    }
+
+    /// Get the maximum size of a reassembled chunked payload
+    pub fn max_payload_bytes(&self) -> usize {
+        self.max_payload_bytes
+    }
+
+    /// Whether a payload must be transferred in chunks
+    pub fn requires_chunking(&self, payload_len: usize) -> bool {
+        payload_len > self.chunk_bytes
+    }
+
+    /// Reject a message larger than the configured maximum
+    pub fn check_message(&self, what: &str, len: usize) -> RpcResult<()> {
+        if len > self.max_message_bytes {
+            return Err(RpcError::ResourceExhausted(format!(
+                "{} is {} bytes, exceeding the {} byte message limit",
+                what, len, self.max_message_bytes
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl Default for MessageLimits {
+    fn default() -> Self {
+        Self {
+            max_message_bytes: DEFAULT_MAX_MESSAGE_BYTES,
+            chunk_bytes: DEFAULT_CHUNK_BYTES,
+            max_payload_bytes: DEFAULT_MAX_PAYLOAD_BYTES,
+        }
+    }
+}
+
+/// Get the chunk of `payload` starting at `offset`
+///
+/// Returns `None` when `offset` is past the end of the payload.
+pub fn chunk_at(payload: &[u8], offset: u64, chunk_bytes: usize) -> Option<&[u8]> {
+    let start = usize::try_from(offset).ok()?;
+    if start > payload.len() {
+        return None;
+    }
+    let end = start.saturating_add(chunk_bytes).min(payload.len());
+    Some(&payload[start..end])
+}
+
+/// Reassembles a chunked payload, verifying ordering and size
+#[derive(Debug)]
+pub struct ChunkAssembler {
+    total_size: usize,
+    buffer: Vec<u8>,
+}
+
+impl ChunkAssembler {
+    /// Start assembling a payload of `total_size` bytes
+    pub fn new(total_size: u64, limits: &MessageLimits) -> RpcResult<Self> {
+        let total_size = usize::try_from(total_size)
+            .ok()
+            .filter(|size| *size <= limits.max_payload_bytes())
+            .ok_or_else(|| {
+                RpcError::ResourceExhausted(format!(
+                    "payload of {} bytes exceeds the {} byte payload limit",
+                    total_size,
+                    limits.max_payload_bytes()
+                ))
+            })?;
+
+        Ok(Self {
+            total_size,
+            buffer: Vec::with_capacity(total_size),
+        })
+    }
+
+    /// Offset of the next expected chunk
+    pub fn next_offset(&self) -> u64 {
+        self.buffer.len() as u64
+    }
+
+    /// Append the chunk at `offset`
+    pub fn push(&mut self, offset: u64, chunk: &[u8]) -> RpcResult<()> {
+        if offset != self.next_offset() {
+            return Err(RpcError::Protocol(format!(
+                "expected chunk at offset {}, got {}",
+                self.next_offset(),
+                offset
+            )));
+        }
+        if self.buffer.len() + chunk.len() > self.total_size {
+            return Err(RpcError::Protocol(format!(
+                "chunk at offset {} overruns the declared size of {} bytes",
+                offset, self.total_size
+            )));
+        }
+        if chunk.is_empty() && !self.is_complete() {
+            return Err(RpcError::Protocol(format!(
+                "empty chunk at offset {} before the end of the payload",
+                offset
+            )));
+        }
+
+        self.buffer.extend_from_slice(chunk);
+        Ok(())
+    }
+
+    /// Whether all bytes have been received
+    pub fn is_complete(&self) -> bool {
+        self.buffer.len() == self.total_size
+    }
+
+    /// Get the reassembled payload
+    pub fn finish(self) -> RpcResult<Vec<u8>> {
+        if !self.is_complete() {
+            return Err(RpcError::Protocol(format!(
+                "payload truncated: received {} of {} bytes",
+                self.buffer.len(),
+                self.total_size
+            )));
+        }
+        Ok(self.buffer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunk_size_fits_message_limit() {
+        let limits = MessageLimits::default().with_max_message_bytes(1024);
+        assert_eq!(limits.chunk_bytes(), 512);
+        assert!(limits.check_message("request", 1024).is_ok());
+        assert!(matches!(
+            limits.check_message("request", 1025),
+            Err(RpcError::ResourceExhausted(_))
+        ));
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let limits = MessageLimits::default().with_chunk_bytes(3);
+        let payload: Vec<u8> = (0..10).collect();
+
+        let mut assembler = ChunkAssembler::new(payload.len() as u64, &limits).unwrap();
+        while !assembler.is_complete() {
+            let offset = assembler.next_offset();
+            let chunk = chunk_at(&payload, offset, limits.chunk_bytes()).unwrap();
+            assembler.push(offset, chunk).unwrap();
+        }
+
+        assert_eq!(assembler.finish().unwrap(), payload);
+    }
+
+    #[test]
+    fn test_out_of_order_chunk() {
+        let mut assembler = ChunkAssembler::new(10, &MessageLimits::default()).unwrap();
+        assert!(matches!(assembler.push(3, &[0; 3]), Err(RpcError::Protocol(_))));
+    }
+
+    #[test]
+    fn test_payload_limit() {
+        let limits = MessageLimits::default().with_max_payload_bytes(8);
+        assert!(matches!(
+            ChunkAssembler::new(9, &limits),
+            Err(RpcError::ResourceExhausted(_))
+        ));
+    }
+
+    #[test]
+    fn test_truncated_payload() {
+        let mut assembler = ChunkAssembler::new(4, &MessageLimits::default()).unwrap();
+        assembler.push(0, &[1, 2]).unwrap();
+        assert!(assembler.finish().is_err());
+    }
+}
@@ -1,7 +1,11 @@
 //! Core domain types for the workflow engine
 
+use crate::access::Principal;
 use chrono::{DateTime, Utc};
+use degov_crypto::{CryptoError, DidKey};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
 use uuid::Uuid;
 
 /// Unique identifier for a workflow definition
@@ -102,6 +106,230 @@ pub struct WorkflowDefinition {
     pub description: Option<String>,
     pub state_machine: crate::state_machine::StateMachine,
     pub created_at: DateTime<Utc>,
+    /// Event bus topics this workflow reacts to
+    #[serde(default)]
+    pub subscriptions: Vec<EventSubscription>,
+    /// Time limit of an instance, counted from its start
+    #[serde(default)]
+    pub timeout: Option<Deadline>,
+    /// Time limits of states by name, counted from entering the state
+    #[serde(default)]
+    pub state_timeouts: HashMap<String, Deadline>,
+    /// Limits on the load the definition's instances put on the cluster
+    #[serde(default)]
+    pub limits: DefinitionLimits,
+    /// Queue the definition's tasks are served from and its share of it
+    #[serde(default)]
+    pub scheduling: Scheduling,
+    /// Context paths instances are indexed by, such as `$.applicant_id`, to
+    /// find them by value
+    #[serde(default)]
+    pub indexes: Vec<String>,
+    /// How long finished instances are kept before they are archived or
+    /// deleted; kept forever when unset
+    #[serde(default)]
+    pub retention: Option<RetentionPolicy>,
+}
+
+impl WorkflowDefinition {
+    /// Queue the definition's tasks are enqueued on
+    pub fn fair_queue(&self) -> String {
+        self.scheduling.queue.clone().unwrap_or_else(|| self.id.to_string())
+    }
+}
+
+/// Fair scheduling settings of a definition
+///
+/// Every definition gets its own task queue unless several share one, such
+/// as the definitions of a tenant. Workers are served from the queues in
+/// weighted round-robin, so a queue of weight 2 gets twice the dispatches of
+/// a queue of weight 1 while both have tasks waiting.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Scheduling {
+    /// Queue shared with other definitions, instead of the definition's own
+    #[serde(default)]
+    pub queue: Option<String>,
+    /// Share of dispatches of the queue relative to other queues
+    #[serde(default = "default_queue_weight")]
+    pub weight: u32,
+}
+
+impl Default for Scheduling {
+    fn default() -> Self {
+        Self {
+            queue: None,
+            weight: default_queue_weight(),
+        }
+    }
+}
+
+fn default_queue_weight() -> u32 {
+    1
+}
+
+/// Limits on how many instances of a definition run and how fast their
+/// tasks are dispatched
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DefinitionLimits {
+    /// Most instances running at once, counting paused and compensating
+    /// ones; further starts are queued as `Pending` until one finishes
+    #[serde(default)]
+    pub max_running_instances: Option<u32>,
+    /// Most tasks of the definition's instances handed to workers per
+    /// second, applied to tasks enqueued while it is set
+    #[serde(default)]
+    pub max_tasks_per_second: Option<u32>,
+}
+
+/// Load of a definition with a running instance limit, as reported to
+/// clients starting instances
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Backpressure {
+    pub running_instances: u32,
+    pub max_running_instances: u32,
+    /// Starts waiting for a running slot, counted up to a cap
+    pub queued_instances: u32,
+}
+
+/// Retention of the finished instances of a definition
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RetentionPolicy {
+    /// Time after an instance finished at which it is removed
+    pub keep_for_ms: u64,
+    #[serde(default)]
+    pub action: RetentionAction,
+}
+
+/// What happens to a finished instance once its retention is up
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RetentionAction {
+    /// Move the instance with its history and annotations to the archive,
+    /// from where it can be restored
+    #[default]
+    Archive,
+    /// Delete the instance with its history and annotations
+    Delete,
+}
+
+/// A finished instance moved to the archive, as restored for audits
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchivedInstance {
+    pub instance: WorkflowInstance,
+    pub history: Vec<HistoryEvent>,
+    pub annotations: Vec<Annotation>,
+    pub archived_at: DateTime<Utc>,
+}
+
+/// Time limit of a workflow instance or of one of its states
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Deadline {
+    pub after_ms: u64,
+    /// Event fired when the time is up; the instance fails when unset
+    #[serde(default)]
+    pub on_timeout: Option<String>,
+}
+
+/// Reaction of a workflow definition to events published on a topic
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EventSubscription {
+    pub topic: String,
+    pub reaction: EventReaction,
+}
+
+impl EventSubscription {
+    /// Start an instance for every event, with the payload as input
+    pub fn start(topic: impl Into<String>) -> Self {
+        Self {
+            topic: topic.into(),
+            reaction: EventReaction::Start,
+        }
+    }
+
+    /// Fire `event` on running instances for every event
+    pub fn signal(topic: impl Into<String>, event: impl Into<String>) -> Self {
+        Self {
+            topic: topic.into(),
+            reaction: EventReaction::Signal {
+                event: event.into(),
+                data_key: None,
+                correlate_on: None,
+            },
+        }
+    }
+
+    /// Store the payload of signalling events under `key` in the context
+    pub fn with_data_key(mut self, key: impl Into<String>) -> Self {
+        if let EventReaction::Signal { data_key, .. } = &mut self.reaction {
+            *data_key = Some(key.into());
+        }
+        self
+    }
+
+    /// Only signal instances whose context value at `key` equals the
+    /// payload's
+    pub fn correlate_on(mut self, key: impl Into<String>) -> Self {
+        if let EventReaction::Signal { correlate_on, .. } = &mut self.reaction {
+            *correlate_on = Some(key.into());
+        }
+        self
+    }
+}
+
+/// What a subscribed workflow does with an event
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum EventReaction {
+    /// Start a new instance with the payload as input
+    Start,
+    /// Fire `event` on running instances that can take it
+    Signal {
+        event: String,
+        /// Context key receiving the payload before guards are checked
+        #[serde(default)]
+        data_key: Option<String>,
+        /// Context key whose value must equal the payload's
+        #[serde(default)]
+        correlate_on: Option<String>,
+    },
+}
+
+impl EventReaction {
+    /// Whether a signal with `payload` is meant for an instance with `context`
+    pub fn correlates(&self, context: &serde_json::Value, payload: &serde_json::Value) -> bool {
+        match self {
+            EventReaction::Signal {
+                correlate_on: Some(key),
+                ..
+            } => context
+                .get(key)
+                .is_some_and(|value| !value.is_null() && payload.get(key) == Some(value)),
+            _ => true,
+        }
+    }
+}
+
+/// Event published on the event bus
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PublishedEvent {
+    /// Position in the topic; filled in when read
+    #[serde(skip)]
+    pub cursor: Vec<u8>,
+    pub topic: String,
+    pub payload: serde_json::Value,
+    /// Instance that published the event, if any
+    pub source: Option<WorkflowId>,
+    pub published_at: DateTime<Utc>,
+}
+
+/// Check that a topic name can be used on the event bus
+///
+/// Topics are non-empty and made of letters, digits, `.`, `-` and `_`, e.g.
+/// `citizen.address_changed`.
+pub fn is_valid_topic(topic: &str) -> bool {
+    !topic.is_empty()
+        && topic
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'))
 }
 
 /// Running instance of a workflow
@@ -115,6 +343,462 @@ pub struct WorkflowInstance {
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
     pub completed_at: Option<DateTime<Utc>>,
+    /// Set when this instance was started by another workflow
+    #[serde(default)]
+    pub parent: Option<ParentLink>,
+    /// Child workflows the current state is blocked on
+    #[serde(default)]
+    pub awaiting_children: Vec<WorkflowId>,
+    /// Rollback in progress while the status is `Compensating`
+    #[serde(default)]
+    pub compensation: Option<Compensation>,
+    /// Branches running while the current state is forked
+    #[serde(default)]
+    pub branches: Vec<BranchState>,
+    /// Tasks whose completion moves a state on, per branch
+    #[serde(default)]
+    pub awaited_tasks: Vec<AwaitedTasks>,
+    /// Client-supplied key the instance was started with, unique per
+    /// definition
+    #[serde(default)]
+    pub idempotency_key: Option<String>,
+    /// Counts the states the instance entered, re-entries of the same state
+    /// included, so it tells entries of the current state apart
+    #[serde(default)]
+    pub state_entry: u64,
+    /// When the instance was paused, while its status is `Paused`
+    #[serde(default)]
+    pub paused_at: Option<DateTime<Utc>>,
+    /// Queued tasks the pause took out of the queue
+    #[serde(default)]
+    pub held_tasks: Vec<TaskId>,
+}
+
+/// Tasks a state with a completion policy is waiting for
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AwaitedTasks {
+    /// Branch the state belongs to, `None` for the main state
+    pub branch: Option<String>,
+    pub state: String,
+    /// Tasks that have not completed yet
+    pub pending: Vec<TaskId>,
+}
+
+/// Progress of one branch of a forked workflow instance
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BranchState {
+    pub name: String,
+    /// Current state of the branch; the join state once it arrived there
+    pub state: String,
+    /// Whether the branch reached the join state
+    pub joined: bool,
+    /// Counts the states the branch entered, like
+    /// [`WorkflowInstance::state_entry`]
+    #[serde(default)]
+    pub state_entry: u64,
+}
+
+/// Rollback of a failed workflow instance
+///
+/// Holds the compensation actions of the states the instance completed,
+/// most recently left state first. Actions run one at a time; a compensation
+/// task has to finish before the next action runs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Compensation {
+    /// Why the instance failed
+    pub reason: String,
+    /// Actions still to run, next first
+    pub pending: Vec<CompensationStep>,
+    /// Compensation task the rollback is waiting for
+    pub awaiting_task: Option<TaskId>,
+}
+
+/// One compensation action and the state that declared it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompensationStep {
+    pub state: String,
+    pub action: crate::state_machine::Action,
+}
+
+/// A committed change to a workflow instance, read from the change feed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChangeEvent {
+    /// Position in the change feed, filled in when the event is read
+    #[serde(skip)]
+    pub cursor: Vec<u8>,
+    pub workflow_id: WorkflowId,
+    pub deleted: bool,
+}
+
+/// Criteria for selecting workflow instances
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct InstanceFilter {
+    pub definition_id: Option<WorkflowId>,
+    pub status: Option<WorkflowStatus>,
+    /// Only instances created at or after this time
+    pub created_after: Option<DateTime<Utc>>,
+    /// Only instances created before this time
+    pub created_before: Option<DateTime<Utc>>,
+}
+
+impl InstanceFilter {
+    /// Check whether an instance matches all set criteria
+    pub fn matches(&self, instance: &WorkflowInstance) -> bool {
+        self.definition_id.is_none_or(|id| instance.definition_id == id)
+            && self.status.is_none_or(|status| instance.status == status)
+            && self.created_after.is_none_or(|after| instance.created_at >= after)
+            && self.created_before.is_none_or(|before| instance.created_at < before)
+    }
+}
+
+/// Position in an instance listing, ordered by creation time
+///
+/// Rendered as an opaque string for API clients.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InstanceCursor {
+    pub created_at_ms: i64,
+    pub id: WorkflowId,
+}
+
+impl std::fmt::Display for InstanceCursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&crate::paging::encode_cursor(self))
+    }
+}
+
+impl std::str::FromStr for InstanceCursor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        crate::paging::decode_cursor(s)
+    }
+}
+
+/// One page of a workflow instance listing
+#[derive(Debug, Clone)]
+pub struct InstancePage {
+    pub instances: Vec<WorkflowInstance>,
+    /// Cursor to continue the listing from, `None` on the last page
+    pub next: Option<InstanceCursor>,
+}
+
+/// Unique identifier for an instance annotation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct AnnotationId(pub Uuid);
+
+impl AnnotationId {
+    pub fn new() -> Self {
+        Self(Uuid::new_v4())
+    }
+    
+    pub fn from_uuid(uuid: Uuid) -> Self {
+        Self(uuid)
+    }
+}
+
+impl Default for AnnotationId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Display for AnnotationId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// A clerk's comment or file attached to a workflow instance
+///
+/// Annotations live beside the state machine: they never trigger transitions
+/// and are kept in the order they were added.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Annotation {
+    pub id: AnnotationId,
+    pub workflow_id: WorkflowId,
+    /// Principal that added the annotation
+    pub author: String,
+    pub created_at: DateTime<Utc>,
+    pub content: AnnotationContent,
+}
+
+/// Content of an instance annotation
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AnnotationContent {
+    Comment {
+        body: String,
+    },
+    /// Reference to a file held in a blob store; the engine never stores the
+    /// file itself
+    Attachment {
+        name: String,
+        content_type: String,
+        size: u64,
+        /// Blob store handle the file can be fetched with
+        blob: String,
+    },
+}
+
+/// Unique identifier for a human task
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct HumanTaskId(pub Uuid);
+
+impl HumanTaskId {
+    pub fn new() -> Self {
+        Self(Uuid::new_v4())
+    }
+    
+    pub fn from_uuid(uuid: Uuid) -> Self {
+        Self(uuid)
+    }
+}
+
+impl Default for HumanTaskId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl std::fmt::Display for HumanTaskId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// A manual step of a workflow instance, worked on by a person
+///
+/// Created when the instance enters a state with a human task. Eligible
+/// people claim it and complete it with a form, which resumes the instance.
+/// Leaving the state by other means, such as a timer, cancels the task.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HumanTask {
+    pub id: HumanTaskId,
+    pub workflow_id: WorkflowId,
+    /// State that created the task
+    pub state: String,
+    /// Fork branch the state was entered by
+    #[serde(default)]
+    pub branch: Option<String>,
+    pub title: String,
+    /// Only this person may work on the task
+    pub assignee: Option<String>,
+    /// Only holders of this role may work on the task
+    pub role: Option<String>,
+    /// Event fired on the instance when the task is completed
+    pub event: String,
+    /// Context key receiving the submitted form
+    pub result_key: Option<String>,
+    pub status: HumanTaskStatus,
+    pub claimed_by: Option<String>,
+    /// Form submitted on completion
+    pub form: Option<serde_json::Value>,
+    pub created_at: DateTime<Utc>,
+    pub claimed_at: Option<DateTime<Utc>>,
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+impl HumanTask {
+    /// Whether `principal` may claim the task
+    pub fn is_eligible(&self, principal: &Principal) -> bool {
+        self.assignee.as_ref().is_none_or(|assignee| *assignee == principal.id)
+            && self.role.as_ref().is_none_or(|role| principal.has_role(role))
+    }
+
+    /// Whether the task still waits for someone to finish it
+    pub fn is_active(&self) -> bool {
+        matches!(self.status, HumanTaskStatus::Open | HumanTaskStatus::Claimed)
+    }
+}
+
+/// Status of a human task
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HumanTaskStatus {
+    Open,
+    Claimed,
+    Completed,
+    /// The instance left the state before the task was completed
+    Cancelled,
+}
+
+impl HumanTaskStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HumanTaskStatus::Open => "Open",
+            HumanTaskStatus::Claimed => "Claimed",
+            HumanTaskStatus::Completed => "Completed",
+            HumanTaskStatus::Cancelled => "Cancelled",
+        }
+    }
+}
+
+/// Entry of the append-only history of a workflow instance
+///
+/// Every change to an instance is recorded in the transaction that makes it,
+/// so the history is complete and in commit order. Entries are never
+/// rewritten; deleting an instance appends a final entry and keeps the rest.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEvent {
+    /// Position in the instance history, starting at 1
+    pub sequence: u64,
+    pub workflow_id: WorkflowId,
+    pub recorded_at: DateTime<Utc>,
+    pub kind: HistoryEventKind,
+}
+
+/// What happened to a workflow instance
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum HistoryEventKind {
+    Started {
+        definition_id: WorkflowId,
+        state: String,
+        context: serde_json::Value,
+    },
+    Transitioned {
+        event: String,
+        from: String,
+        to: String,
+        status: WorkflowStatus,
+    },
+    /// The context was replaced; holds the full new context
+    ContextChanged {
+        context: serde_json::Value,
+    },
+    TaskEnqueued {
+        task_id: TaskId,
+        name: String,
+    },
+    /// No registered worker could run the task
+    TaskRejected {
+        task_id: TaskId,
+        name: String,
+        reason: String,
+    },
+    /// A task attempt finished; `status` is `Retrying` when another attempt
+    /// follows
+    TaskCompleted {
+        task_id: TaskId,
+        name: String,
+        status: TaskStatus,
+        error: Option<String>,
+    },
+    /// The instance failed and its completed states are being compensated
+    CompensationStarted {
+        reason: String,
+    },
+    /// The rollback ended; `error` is set when a compensation task failed
+    /// and the remaining actions were skipped
+    CompensationFinished {
+        error: Option<String>,
+    },
+    /// The instance entered a forked state and started its branches
+    Forked {
+        branches: Vec<BranchState>,
+    },
+    /// A fork branch moved; `joined` is set when it arrived in the join state
+    BranchTransitioned {
+        branch: String,
+        event: String,
+        from: String,
+        to: String,
+        joined: bool,
+    },
+    /// Enough branches arrived in the join state; `branches` names those that
+    /// did, the others were dropped
+    Joined {
+        state: String,
+        branches: Vec<String>,
+        status: WorkflowStatus,
+    },
+    HumanTaskCreated {
+        task_id: HumanTaskId,
+        title: String,
+    },
+    HumanTaskClaimed {
+        task_id: HumanTaskId,
+        by: String,
+    },
+    HumanTaskCompleted {
+        task_id: HumanTaskId,
+        by: String,
+    },
+    /// The instance left the state before the task was completed
+    HumanTaskCancelled {
+        task_id: HumanTaskId,
+    },
+    /// The instance published an event on the event bus
+    EventPublished {
+        topic: String,
+    },
+    /// A task with a placement was handed to a worker
+    TaskPlaced {
+        task_id: TaskId,
+        name: String,
+        worker_id: WorkerId,
+        location: Location,
+        placement: PlacementMatch,
+    },
+    /// The definition's running instance limit was reached; the instance
+    /// waits as `Pending` until it is admitted
+    Queued,
+    /// A queued instance got a running slot and entered its initial state
+    Admitted,
+    /// The instance was cancelled; its unfinished tasks were cancelled with it
+    Cancelled {
+        reason: String,
+    },
+    Paused {
+        reason: String,
+    },
+    Resumed,
+    Deleted,
+    /// The instance was brought back from the archive
+    Restored {
+        archived_at: DateTime<Utc>,
+    },
+}
+
+impl HistoryEventKind {
+    /// Name of the event kind, as used in its serialized `type` tag
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            HistoryEventKind::Started { .. } => "started",
+            HistoryEventKind::Transitioned { .. } => "transitioned",
+            HistoryEventKind::ContextChanged { .. } => "context_changed",
+            HistoryEventKind::TaskEnqueued { .. } => "task_enqueued",
+            HistoryEventKind::TaskRejected { .. } => "task_rejected",
+            HistoryEventKind::TaskCompleted { .. } => "task_completed",
+            HistoryEventKind::CompensationStarted { .. } => "compensation_started",
+            HistoryEventKind::CompensationFinished { .. } => "compensation_finished",
+            HistoryEventKind::Forked { .. } => "forked",
+            HistoryEventKind::BranchTransitioned { .. } => "branch_transitioned",
+            HistoryEventKind::Joined { .. } => "joined",
+            HistoryEventKind::HumanTaskCreated { .. } => "human_task_created",
+            HistoryEventKind::HumanTaskClaimed { .. } => "human_task_claimed",
+            HistoryEventKind::HumanTaskCompleted { .. } => "human_task_completed",
+            HistoryEventKind::HumanTaskCancelled { .. } => "human_task_cancelled",
+            HistoryEventKind::EventPublished { .. } => "event_published",
+            HistoryEventKind::TaskPlaced { .. } => "task_placed",
+            HistoryEventKind::Queued => "queued",
+            HistoryEventKind::Admitted => "admitted",
+            HistoryEventKind::Cancelled { .. } => "cancelled",
+            HistoryEventKind::Paused { .. } => "paused",
+            HistoryEventKind::Resumed => "resumed",
+            HistoryEventKind::Deleted => "deleted",
+            HistoryEventKind::Restored { .. } => "restored",
+        }
+    }
+}
+
+/// Link from a child workflow instance back to the parent that started it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ParentLink {
+    pub workflow_id: WorkflowId,
+    /// Key in the parent context that receives the child's final context
+    pub result_key: Option<String>,
+    /// Whether the parent state is blocked until the child completes
+    pub await_completion: bool,
 }
 
 /// Status of a workflow instance
@@ -125,30 +809,130 @@ pub enum WorkflowStatus {
     Completed,
     Failed,
     Cancelled,
+    /// Failed and rolling back; ends as `Failed`
+    Compensating,
+    /// Held until resumed; its tasks are not dispatched and its timers do
+    /// not run
+    Paused,
+}
+
+impl WorkflowStatus {
+    /// Name of the status, as serialized
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WorkflowStatus::Pending => "Pending",
+            WorkflowStatus::Running => "Running",
+            WorkflowStatus::Completed => "Completed",
+            WorkflowStatus::Failed => "Failed",
+            WorkflowStatus::Cancelled => "Cancelled",
+            WorkflowStatus::Compensating => "Compensating",
+            WorkflowStatus::Paused => "Paused",
+        }
+    }
 }
 
 /// Task definition within a workflow
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct TaskDefinition {
     pub name: String,
     pub runtime_type: RuntimeType,
     pub code: Vec<u8>,
     pub timeout_ms: u64,
     pub retry_policy: Option<RetryPolicy>,
+    /// DSSE envelope attesting `code`, as written by `degov build` when
+    /// signing artifacts
+    #[serde(default)]
+    pub attestation: Option<String>,
+    /// Template of the task input, with `{{ expr }}` placeholders filled in
+    /// from the workflow context; the task receives the whole context when
+    /// unset
+    #[serde(default)]
+    pub input_mapping: Option<serde_json::Value>,
+    /// Where the output is stored in the workflow context, as a path of
+    /// object fields such as `$.steps.verify`; the output is discarded when
+    /// unset
+    #[serde(default)]
+    pub result_path: Option<String>,
+    /// Event fired on the workflow when the task completes, in the same
+    /// transaction that stores its output
+    #[serde(default)]
+    pub on_complete: Option<String>,
+    /// Where the task should run; any worker may take it when unset
+    #[serde(default)]
+    pub placement: Option<Placement>,
+    /// Session key routing consecutive tasks with the same key to the same
+    /// worker, so they find its warmed instances and caches; another worker
+    /// takes over once that one is unhealthy, draining or gone
+    #[serde(default)]
+    pub session: Option<String>,
+    /// Resource limits applied when the task runs in the WASM runtime
+    #[serde(default)]
+    pub wasm_limits: Option<WasmLimits>,
+    /// Outbound HTTP the task may make; it cannot make any when unset
+    #[serde(default)]
+    pub http: Option<HttpPolicy>,
+    /// Marks the task idempotent, so a repeat with the same code and input
+    /// reuses the result of an earlier successful run
+    #[serde(default)]
+    pub cache: Option<CachePolicy>,
+}
+
+impl TaskDefinition {
+    /// Build the task input from the workflow context
+    pub fn input_from(&self, context: &serde_json::Value) -> crate::error::WorkflowResult<serde_json::Value> {
+        let Some(mapping) = &self.input_mapping else {
+            return Ok(context.clone());
+        };
+        let mut input = mapping.clone();
+        crate::state_machine::render_value(&mut input, context)?;
+        Ok(input)
+    }
+
+    /// Check that the code is attested by one of `trusted`
+    pub fn verify_code(&self, trusted: &[DidKey]) -> Result<(), CryptoError> {
+        let attestation = self.attestation.as_deref().ok_or(CryptoError::Untrusted)?;
+        degov_crypto::verify_artifact(attestation, &self.code, trusted)?;
+        Ok(())
+    }
+
+    /// Key the result of running the task on `input` is cached under
+    pub fn cache_key(&self, input: &[u8]) -> String {
+        format!(
+            "{}:{}:{}",
+            self.runtime_type.as_str(),
+            degov_crypto::sha256_hex(&self.code),
+            degov_crypto::sha256_hex(input)
+        )
+    }
 }
 
 /// Type of runtime for task execution
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum RuntimeType {
+    #[default]
     JavaScript,
     Wasm,
+    /// Runtime registered by a worker plugin, identified by its name
+    Plugin(String),
 }
 
 impl RuntimeType {
-    pub fn as_str(&self) -> &'static str {
+    pub fn as_str(&self) -> &str {
         match self {
             RuntimeType::JavaScript => "javascript",
             RuntimeType::Wasm => "wasm",
+            RuntimeType::Plugin(name) => name,
+        }
+    }
+
+    /// Resolve a runtime name as advertised by workers
+    ///
+    /// Names other than the built-in runtimes refer to plugins.
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            "javascript" => RuntimeType::JavaScript,
+            "wasm" => RuntimeType::Wasm,
+            _ => RuntimeType::Plugin(name.to_string()),
         }
     }
 }
@@ -162,6 +946,28 @@ pub struct RetryPolicy {
     pub backoff_multiplier: f64,
 }
 
+impl RetryPolicy {
+    /// Whether another attempt is allowed after the zero-based `attempt` failed
+    pub fn should_retry(&self, attempt: u32) -> bool {
+        attempt.saturating_add(1) < self.max_attempts
+    }
+
+    /// Exponential backoff after the zero-based `attempt` failed, capped at
+    /// `max_delay_ms`
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let delay = self.initial_delay_ms as f64 * self.backoff_multiplier.max(1.0).powi(attempt as i32);
+        Duration::from_millis(delay.min(self.max_delay_ms as f64) as u64)
+    }
+
+    /// Backoff with jitter so failed tasks do not retry in lockstep
+    ///
+    /// `jitter` is a random value in `[0, 1)`; the result lies between half
+    /// and the full backoff.
+    pub fn delay_with_jitter(&self, attempt: u32, jitter: f64) -> Duration {
+        self.backoff(attempt).mul_f64(0.5 + jitter.clamp(0.0, 1.0) / 2.0)
+    }
+}
+
 impl Default for RetryPolicy {
     fn default() -> Self {
         Self {
@@ -173,6 +979,103 @@ impl Default for RetryPolicy {
     }
 }
 
+/// Resource limits of a WASM task
+///
+/// A task exceeding one of them is stopped with a `RuntimeError` instead of
+/// holding its worker slot.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WasmLimits {
+    /// Fuel the task may consume, roughly one unit per instruction
+    #[serde(default)]
+    pub fuel: Option<u64>,
+    /// Largest size the task's linear memory may grow to
+    #[serde(default)]
+    pub max_memory_bytes: Option<u64>,
+    /// Time after which the running task is interrupted; defaults to the
+    /// task timeout
+    #[serde(default)]
+    pub interrupt_after_ms: Option<u64>,
+}
+
+/// How long the result of an idempotent task is reused
+///
+/// Results are cached per code and input across instances; key-value writes
+/// of the run that produced them are not repeated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CachePolicy {
+    pub ttl_ms: u64,
+}
+
+/// Outbound HTTP a task may make through the host `fetch`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HttpPolicy {
+    /// Hosts requests may go to; `*.example.org` also allows the
+    /// subdomains of `example.org`
+    pub allowed_hosts: Vec<String>,
+    /// Largest request or response body; defaults to 10 MiB
+    #[serde(default)]
+    pub max_body_bytes: Option<u64>,
+    /// Time one request may take, including its redirects and reading the
+    /// response; defaults to 10 seconds
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+}
+
+/// Region and zone a worker runs in
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Location {
+    pub region: Option<String>,
+    pub zone: Option<String>,
+}
+
+/// Where a task should run, usually where the data it handles lives
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Placement {
+    pub region: String,
+    /// Zone within `region` preferred over the rest of the region
+    #[serde(default)]
+    pub zone: Option<String>,
+    /// Never run the task outside `region`, for data residency
+    #[serde(default)]
+    pub residency: bool,
+    /// How long the task waits for a worker in `region` (or `zone`) before
+    /// workers elsewhere may take it
+    #[serde(default = "default_spill_after_ms")]
+    pub spill_after_ms: u64,
+}
+
+fn default_spill_after_ms() -> u64 {
+    5000
+}
+
+impl Placement {
+    /// Decide whether a worker at `location` may take the task after it
+    /// waited `waited_ms` in the queue
+    pub fn decide(&self, location: &Location, waited_ms: u64) -> Option<PlacementMatch> {
+        let spilled = waited_ms >= self.spill_after_ms;
+        if location.region.as_deref() != Some(self.region.as_str()) {
+            return (spilled && !self.residency).then_some(PlacementMatch::CrossRegion);
+        }
+        match &self.zone {
+            Some(zone) if location.zone.as_ref() != Some(zone) => spilled.then_some(PlacementMatch::Region),
+            Some(_) => Some(PlacementMatch::Zone),
+            None => Some(PlacementMatch::Region),
+        }
+    }
+}
+
+/// How the worker that took a task matched its placement
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlacementMatch {
+    /// The worker runs in the preferred zone
+    Zone,
+    /// The worker runs in the task's region
+    Region,
+    /// No worker in the region took the task in time
+    CrossRegion,
+}
+
 /// Task execution record
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TaskExecution {
@@ -187,6 +1090,55 @@ pub struct TaskExecution {
     pub started_at: Option<DateTime<Utc>>,
     pub completed_at: Option<DateTime<Utc>>,
     pub result: Option<TaskResult>,
+    /// Outcome of every finished attempt, oldest first
+    #[serde(default)]
+    pub history: Vec<TaskAttempt>,
+    /// Attempt at which the current retry budget started, moved forward when
+    /// a dead-lettered task is requeued
+    #[serde(default)]
+    pub retry_from: u32,
+    /// Time from which the task is due in the queue, unset while it is not
+    /// queued
+    #[serde(default)]
+    pub queued_at: Option<DateTime<Utc>>,
+    /// Most tasks of the workflow's definition dispatched per second, as
+    /// limited when the task was enqueued
+    #[serde(default)]
+    pub max_dispatch_per_second: Option<u32>,
+    /// Fair queue the task waits in, `None` for the shared queue tasks
+    /// enqueued before fair scheduling wait in
+    #[serde(default)]
+    pub fair_queue: Option<String>,
+    /// Trace context of the span that enqueued the task, continued by the
+    /// worker running it
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub trace_context: crate::telemetry::TraceContext,
+    /// Time the lease of the worker holding the task runs out, after which
+    /// the task is queued again unless the lease was extended
+    #[serde(default)]
+    pub lease_expires_at: Option<DateTime<Utc>>,
+}
+
+/// A task that failed permanently after exhausting its retries
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeadLetter {
+    pub task: TaskExecution,
+    /// Error reported by the final attempt
+    pub error: Option<String>,
+    pub failed_at: DateTime<Utc>,
+}
+
+/// Record of a single finished task attempt
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TaskAttempt {
+    pub attempt: u32,
+    pub worker: Option<WorkerId>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: DateTime<Utc>,
+    pub success: bool,
+    pub error: Option<String>,
+    /// When the next attempt becomes available, if the task is retried
+    pub retry_at: Option<DateTime<Utc>>,
 }
 
 /// Status of a task execution
@@ -198,6 +1150,12 @@ pub enum TaskStatus {
     Completed,
     Failed,
     Retrying,
+    /// No registered worker supports the task's runtime
+    Unschedulable,
+    /// The workflow was cancelled before the task finished
+    Cancelled,
+    /// Taken out of the queue while its workflow is paused
+    Paused,
 }
 
 /// Result of task execution
@@ -207,6 +1165,34 @@ pub struct TaskResult {
     pub output: Vec<u8>,
     pub error: Option<String>,
     pub execution_time_ms: u64,
+    /// Writes to the instance's key-value state, committed with the
+    /// completion of a successful task
+    #[serde(default)]
+    pub kv_writes: Vec<KvWrite>,
+}
+
+/// A write a task made to the key-value state of its instance
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum KvWrite {
+    Set { key: Vec<u8>, value: Vec<u8> },
+    Delete { key: Vec<u8> },
+}
+
+impl KvWrite {
+    /// Get the key written
+    pub fn key(&self) -> &[u8] {
+        match self {
+            KvWrite::Set { key, .. } | KvWrite::Delete { key } => key,
+        }
+    }
+
+    /// Number of key and value bytes the write adds to its transaction
+    pub fn size(&self) -> usize {
+        match self {
+            KvWrite::Set { key, value } => key.len() + value.len(),
+            KvWrite::Delete { key } => key.len(),
+        }
+    }
 }
 
 /// Worker information
@@ -215,18 +1201,38 @@ pub struct WorkerInfo {
     pub id: WorkerId,
     pub capabilities: Vec<RuntimeType>,
     pub hostname: String,
+    #[serde(default)]
+    pub location: Location,
     pub registered_at: DateTime<Utc>,
     pub last_heartbeat: DateTime<Utc>,
     pub status: WorkerHealthStatus,
     pub stats: WorkerStats,
+    /// Told to finish its tasks and exit; it is given no new tasks
+    #[serde(default)]
+    pub draining: bool,
 }
 
 /// Worker health status
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum WorkerHealthStatus {
     Healthy,
+    /// Missed at least one heartbeat
     Degraded,
     Unhealthy,
+    /// Missed too many heartbeats in a row; its tasks were reassigned
+    Dead,
+}
+
+impl WorkerHealthStatus {
+    /// Name of the status, as serialized
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WorkerHealthStatus::Healthy => "Healthy",
+            WorkerHealthStatus::Degraded => "Degraded",
+            WorkerHealthStatus::Unhealthy => "Unhealthy",
+            WorkerHealthStatus::Dead => "Dead",
+        }
+    }
 }
 
 /// Worker statistics
@@ -235,5 +1241,364 @@ pub struct WorkerStats {
     pub active_tasks: u32,
     pub total_tasks_completed: u64,
     pub total_tasks_failed: u64,
+    /// Most tasks the worker executes at once
+    #[serde(default)]
+    pub max_concurrency: u32,
+    /// Tasks the worker is executing
+    #[serde(default)]
+    pub running_tasks: Vec<RunningTask>,
+}
+
+/// A task a worker is executing
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunningTask {
+    pub task_id: String,
+    pub workflow_id: String,
+    pub runtime_type: String,
+    pub attempt: u32,
+    pub started_at: DateTime<Utc>,
+}
+
+
+/// A durable timer scheduled for a workflow instance
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduledTimer {
+    pub workflow_id: WorkflowId,
+    /// State the instance was in when the timer was scheduled
+    pub state: String,
+    /// Branch the state belongs to, when it was entered by a fork branch
+    #[serde(default)]
+    pub branch: Option<String>,
+    pub event: String,
+    pub fire_at: DateTime<Utc>,
+    /// State entry of the instance or branch the timer was scheduled in; a
+    /// timer of an earlier entry of the same state is stale
+    #[serde(default)]
+    pub state_entry: u64,
+    /// Whether the timer limits the whole instance rather than `state`
+    #[serde(default)]
+    pub instance_wide: bool,
+    /// Fail the instance instead of firing `event`
+    #[serde(default)]
+    pub fails: bool,
 }
 
+impl ScheduledTimer {
+    /// Whether the timer still applies to `instance`: it is running and,
+    /// unless the timer is instance-wide, still in the entry of the state the
+    /// timer was scheduled in
+    pub fn applies_to(&self, instance: &WorkflowInstance) -> bool {
+        let in_state = match &self.branch {
+            Some(branch) => instance.branches.iter().any(|b| {
+                b.name == *branch && !b.joined && b.state == self.state && b.state_entry == self.state_entry
+            }),
+            None => instance.current_state == self.state && instance.state_entry == self.state_entry,
+        };
+        instance.status == WorkflowStatus::Running && (self.instance_wide || in_state)
+    }
+}
+
+/// An HTTP request waiting to be sent for a workflow instance
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HttpCall {
+    pub id: Uuid,
+    pub workflow_id: WorkflowId,
+    /// State whose entry made the request, or that is being compensated
+    pub state: String,
+    /// Branch the state belongs to, when it was entered by a fork branch
+    #[serde(default)]
+    pub branch: Option<String>,
+    /// Request with its templates filled in
+    pub request: crate::state_machine::HttpRequest,
+    /// Zero-based attempt made next
+    pub attempt: u32,
+    pub due_at: DateTime<Utc>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_attempts() {
+        let policy = RetryPolicy::default();
+        assert!(policy.should_retry(0));
+        assert!(policy.should_retry(1));
+        assert!(!policy.should_retry(2));
+    }
+
+    #[test]
+    fn test_backoff_is_capped() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            initial_delay_ms: 100,
+            max_delay_ms: 1000,
+            backoff_multiplier: 2.0,
+        };
+        assert_eq!(policy.backoff(0), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(400));
+        assert_eq!(policy.backoff(8), Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn test_cache_key_covers_code_and_input() {
+        let task = TaskDefinition {
+            name: "lookup".to_string(),
+            runtime_type: RuntimeType::JavaScript,
+            code: b"return input;".to_vec(),
+            timeout_ms: 1000,
+            cache: Some(CachePolicy { ttl_ms: 60_000 }),
+            ..Default::default()
+        };
+        let changed = TaskDefinition {
+            code: b"return null;".to_vec(),
+            ..task.clone()
+        };
+
+        assert_eq!(task.cache_key(b"{}"), task.cache_key(b"{}"));
+        assert_ne!(task.cache_key(b"{}"), task.cache_key(b"{\"a\":1}"));
+        assert_ne!(task.cache_key(b"{}"), changed.cache_key(b"{}"));
+    }
+
+    #[test]
+    fn test_jitter_bounds() {
+        let policy = RetryPolicy::default();
+        assert_eq!(policy.delay_with_jitter(1, 0.0), Duration::from_millis(1000));
+        assert!(policy.delay_with_jitter(1, 0.999) < Duration::from_millis(2000));
+    }
+
+    #[test]
+    fn test_history_kind_names_match_tags() {
+        let kinds = [
+            HistoryEventKind::ContextChanged {
+                context: serde_json::json!({}),
+            },
+            HistoryEventKind::TaskEnqueued {
+                task_id: TaskId::new(),
+                name: "notify".to_string(),
+            },
+            HistoryEventKind::Cancelled {
+                reason: "stuck".to_string(),
+            },
+            HistoryEventKind::Paused {
+                reason: "legal hold".to_string(),
+            },
+            HistoryEventKind::Resumed,
+            HistoryEventKind::Queued,
+            HistoryEventKind::Admitted,
+            HistoryEventKind::TaskPlaced {
+                task_id: TaskId::new(),
+                name: "notify".to_string(),
+                worker_id: WorkerId::new(),
+                location: Location::default(),
+                placement: PlacementMatch::CrossRegion,
+            },
+            HistoryEventKind::Deleted,
+            HistoryEventKind::Restored { archived_at: Utc::now() },
+        ];
+        for kind in kinds {
+            let value = serde_json::to_value(&kind).unwrap();
+            assert_eq!(value["type"], kind.as_str());
+        }
+    }
+
+    #[test]
+    fn test_placement_decide() {
+        let at = |region: &str, zone: &str| Location {
+            region: Some(region.to_string()),
+            zone: Some(zone.to_string()),
+        };
+        let mut placement = Placement {
+            region: "eu-central".to_string(),
+            zone: Some("a".to_string()),
+            residency: false,
+            spill_after_ms: 1000,
+        };
+
+        assert_eq!(placement.decide(&at("eu-central", "a"), 0), Some(PlacementMatch::Zone));
+        assert_eq!(placement.decide(&at("eu-central", "b"), 0), None);
+        assert_eq!(placement.decide(&at("eu-central", "b"), 1000), Some(PlacementMatch::Region));
+        assert_eq!(placement.decide(&at("us-east", "a"), 0), None);
+        assert_eq!(placement.decide(&at("us-east", "a"), 1000), Some(PlacementMatch::CrossRegion));
+        assert_eq!(placement.decide(&Location::default(), 1000), Some(PlacementMatch::CrossRegion));
+
+        placement.residency = true;
+        assert_eq!(placement.decide(&at("us-east", "a"), u64::MAX), None);
+        assert_eq!(placement.decide(&Location::default(), u64::MAX), None);
+        assert_eq!(placement.decide(&at("eu-central", "b"), 1000), Some(PlacementMatch::Region));
+    }
+
+    #[test]
+    fn test_instance_cursor_roundtrip() {
+        let cursor = InstanceCursor {
+            created_at_ms: 1_760_000_000_000,
+            id: WorkflowId::new(),
+        };
+        assert_eq!(cursor.to_string().parse::<InstanceCursor>(), Ok(cursor));
+        assert!(!cursor.to_string().contains(&cursor.id.to_string()));
+        assert!(format!("{}.{}", cursor.created_at_ms, cursor.id)
+            .parse::<InstanceCursor>()
+            .is_err());
+        assert!("x.y".parse::<InstanceCursor>().is_err());
+    }
+
+    #[test]
+    fn test_verify_task_code() {
+        let key = degov_crypto::StackKey::from_seed([5; 32]);
+        let mut task = TaskDefinition {
+            name: "notify".to_string(),
+            runtime_type: RuntimeType::Wasm,
+            code: b"\0asm".to_vec(),
+            timeout_ms: 1000,
+            ..Default::default()
+        };
+        assert!(task.verify_code(&[key.did()]).is_err());
+
+        let statement = degov_crypto::Statement::new(
+            vec![degov_crypto::Subject::sha256("notify.wasm", &task.code)],
+            "https://slsa.dev/provenance/v1",
+            serde_json::json!({}),
+        );
+        let envelope = degov_crypto::Envelope::sign_statement(&statement, &key).unwrap();
+        task.attestation = Some(serde_json::to_string(&envelope).unwrap());
+        assert!(task.verify_code(&[key.did()]).is_ok());
+
+        task.code.push(0);
+        assert!(task.verify_code(&[key.did()]).is_err());
+    }
+
+    #[test]
+    fn test_task_input_mapping() {
+        let context = serde_json::json!({"applicant": {"id": "a-7", "income": 42000}, "notes": "private"});
+        let mut task = TaskDefinition {
+            name: "assess".to_string(),
+            runtime_type: RuntimeType::JavaScript,
+            code: b"input".to_vec(),
+            timeout_ms: 1000,
+            ..Default::default()
+        };
+        assert_eq!(task.input_from(&context).unwrap(), context);
+
+        task.input_mapping = Some(serde_json::json!({
+            "id": "{{ applicant.id }}",
+            "income": "{{ applicant.income }}",
+            "label": "case {{ applicant.id }}",
+        }));
+        assert_eq!(
+            task.input_from(&context).unwrap(),
+            serde_json::json!({"id": "a-7", "income": 42000, "label": "case a-7"})
+        );
+
+        task.input_mapping = Some(serde_json::json!("{{ applicant. }}"));
+        assert!(task.input_from(&context).is_err());
+    }
+
+    #[test]
+    fn test_human_task_eligibility() {
+        let mut task = HumanTask {
+            id: HumanTaskId::new(),
+            workflow_id: WorkflowId::new(),
+            state: "review".to_string(),
+            branch: None,
+            title: "Approve permit".to_string(),
+            assignee: None,
+            role: Some("clerk".to_string()),
+            event: "reviewed".to_string(),
+            result_key: Some("review".to_string()),
+            status: HumanTaskStatus::Open,
+            claimed_by: None,
+            form: None,
+            created_at: Utc::now(),
+            claimed_at: None,
+            completed_at: None,
+        };
+        let clerk = Principal::new("alice").with_role("clerk");
+        let citizen = Principal::new("bob");
+        assert!(task.is_eligible(&clerk));
+        assert!(!task.is_eligible(&citizen));
+
+        task.assignee = Some("carol".to_string());
+        assert!(!task.is_eligible(&clerk));
+        assert!(task.is_active());
+        task.status = HumanTaskStatus::Cancelled;
+        assert!(!task.is_active());
+    }
+
+    #[test]
+    fn test_signal_correlation() {
+        let subscription = EventSubscription::signal("citizen.address_changed", "address_changed")
+            .with_data_key("address")
+            .correlate_on("citizen_id");
+        let payload = serde_json::json!({"citizen_id": "c-1", "street": "Main St 1"});
+
+        let reaction = &subscription.reaction;
+        assert!(reaction.correlates(&serde_json::json!({"citizen_id": "c-1"}), &payload));
+        assert!(!reaction.correlates(&serde_json::json!({"citizen_id": "c-2"}), &payload));
+        assert!(!reaction.correlates(&serde_json::json!({}), &serde_json::json!({})));
+        assert!(EventReaction::Start.correlates(&serde_json::json!({}), &payload));
+
+        assert!(is_valid_topic("citizen.address_changed"));
+        assert!(!is_valid_topic("citizen:address"));
+        assert!(!is_valid_topic(""));
+    }
+
+    #[test]
+    fn test_timer_applies_to_current_state_entry() {
+        let mut instance = WorkflowInstance {
+            id: WorkflowId::new(),
+            definition_id: WorkflowId::new(),
+            current_state: "waiting".to_string(),
+            context: serde_json::json!({}),
+            status: WorkflowStatus::Running,
+            created_at: Utc::now(),
+            updated_at: Utc::now(),
+            completed_at: None,
+            parent: None,
+            awaiting_children: Vec::new(),
+            compensation: None,
+            branches: Vec::new(),
+            awaited_tasks: Vec::new(),
+            idempotency_key: None,
+            state_entry: 1,
+            paused_at: None,
+            held_tasks: Vec::new(),
+        };
+        let mut timer = ScheduledTimer {
+            workflow_id: instance.id,
+            state: "waiting".to_string(),
+            branch: None,
+            event: "remind".to_string(),
+            fire_at: Utc::now(),
+            state_entry: 1,
+            instance_wide: false,
+            fails: false,
+        };
+        assert!(timer.applies_to(&instance));
+
+        // A self-transition enters the same state again
+        instance.state_entry = 2;
+        assert!(!timer.applies_to(&instance));
+        timer.instance_wide = true;
+        assert!(timer.applies_to(&instance));
+        instance.status = WorkflowStatus::Paused;
+        assert!(!timer.applies_to(&instance));
+
+        instance.status = WorkflowStatus::Running;
+        instance.branches.push(BranchState {
+            name: "finance".to_string(),
+            state: "review".to_string(),
+            joined: false,
+            state_entry: 3,
+        });
+        let timer = ScheduledTimer {
+            state: "review".to_string(),
+            branch: Some("finance".to_string()),
+            state_entry: 3,
+            instance_wide: false,
+            ..timer
+        };
+        assert!(timer.applies_to(&instance));
+        instance.branches[0].state_entry = 4;
+        assert!(!timer.applies_to(&instance));
+    }
+}
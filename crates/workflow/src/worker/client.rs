@@ -0,0 +1,71 @@
+//! Transport of worker calls to the engine
+//!
+//! Workers talk to the engine through [`EngineClient`]. Remote workers use
+//! the generated RPC client; workers embedded in the engine process use the
+//! local transport in `crate::engine`. Other transports, or mocks in tests,
+//! can be passed to [`super::Worker::with_client`].
+
+use super::proto::*;
+use crate::error::RpcResult;
+use async_trait::async_trait;
+
+/// Calls a worker makes to the engine
+#[async_trait]
+pub trait EngineClient: Send + Sync {
+    async fn register_worker(&self, request: RegisterWorkerRequest) -> RpcResult<RegisterWorkerResponse>;
+
+    async fn poll_tasks(&self, request: PollTasksRequest) -> RpcResult<PollTasksResponse>;
+
+    async fn fetch_task_code(&self, request: FetchTaskCodeRequest) -> RpcResult<FetchTaskCodeResponse>;
+
+    async fn complete_task(&self, request: CompleteTaskRequest) -> RpcResult<CompleteTaskResponse>;
+
+    async fn heartbeat(&self, request: HeartbeatRequest) -> RpcResult<HeartbeatResponse>;
+
+    async fn deregister_worker(&self, request: DeregisterWorkerRequest) -> RpcResult<DeregisterWorkerResponse>;
+
+    async fn kv_get(&self, request: KvGetRequest) -> RpcResult<KvGetResponse>;
+
+    async fn kv_range(&self, request: KvRangeRequest) -> RpcResult<KvRangeResponse>;
+
+    /// Whether tasks can be received on a `StreamTasks` stream; without
+    /// one the worker polls
+    fn supports_streaming(&self) -> bool {
+        true
+    }
+}
+
+#[async_trait]
+impl EngineClient for WorkflowServiceClient {
+    async fn register_worker(&self, request: RegisterWorkerRequest) -> RpcResult<RegisterWorkerResponse> {
+        Ok(WorkflowServiceClient::register_worker(self, request).await?)
+    }
+
+    async fn poll_tasks(&self, request: PollTasksRequest) -> RpcResult<PollTasksResponse> {
+        Ok(WorkflowServiceClient::poll_tasks(self, request).await?)
+    }
+
+    async fn fetch_task_code(&self, request: FetchTaskCodeRequest) -> RpcResult<FetchTaskCodeResponse> {
+        Ok(WorkflowServiceClient::fetch_task_code(self, request).await?)
+    }
+
+    async fn complete_task(&self, request: CompleteTaskRequest) -> RpcResult<CompleteTaskResponse> {
+        Ok(WorkflowServiceClient::complete_task(self, request).await?)
+    }
+
+    async fn heartbeat(&self, request: HeartbeatRequest) -> RpcResult<HeartbeatResponse> {
+        Ok(WorkflowServiceClient::heartbeat(self, request).await?)
+    }
+
+    async fn deregister_worker(&self, request: DeregisterWorkerRequest) -> RpcResult<DeregisterWorkerResponse> {
+        Ok(WorkflowServiceClient::deregister_worker(self, request).await?)
+    }
+
+    async fn kv_get(&self, request: KvGetRequest) -> RpcResult<KvGetResponse> {
+        Ok(WorkflowServiceClient::kv_get(self, request).await?)
+    }
+
+    async fn kv_range(&self, request: KvRangeRequest) -> RpcResult<KvRangeResponse> {
+        Ok(WorkflowServiceClient::kv_range(self, request).await?)
+    }
+}
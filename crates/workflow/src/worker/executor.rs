@@ -1,9 +1,10 @@
 //! Task executor
 
 use crate::error::{EngineError, Result, RuntimeError};
-use crate::runtime::Runtime;
+use crate::runtime::{Runtime, TaskKv};
 use crate::types::{RuntimeType, TaskDefinition};
 use std::collections::HashMap;
+use tracing::Instrument;
 
 /// Task executor that manages different runtimes
 pub struct TaskExecutor {
@@ -23,8 +24,8 @@ impl TaskExecutor {
         self.runtimes.insert(runtime_type, runtime);
     }
 
-    /// Execute a task
-    pub async fn execute(&self, task: &TaskDefinition, input: &[u8]) -> Result<Vec<u8>> {
+    /// Execute a task with access to the key-value state of its instance
+    pub async fn execute(&self, task: &TaskDefinition, input: &[u8], kv: TaskKv) -> Result<Vec<u8>> {
         let runtime = self
             .runtimes
             .get(&task.runtime_type)
@@ -35,14 +36,15 @@ impl TaskExecutor {
             })?;
 
         runtime
-            .execute(task, input)
+            .execute_with_kv(task, input, kv)
+            .instrument(tracing::info_span!("runtime.execute", runtime = task.runtime_type.as_str()))
             .await
             .map_err(EngineError::Runtime)
     }
 
     /// Get supported runtime types
     pub fn supported_runtimes(&self) -> Vec<RuntimeType> {
-        self.runtimes.keys().copied().collect()
+        self.runtimes.keys().cloned().collect()
     }
 
     /// Check if a runtime is supported
@@ -0,0 +1,74 @@
+//! Key-value reads of running tasks, served by the engine
+
+use super::client::EngineClient;
+use super::proto::{KvGetRequest, KvRangeRequest};
+use super::RpcRetry;
+use crate::error::{RuntimeError, RuntimeResult};
+use crate::runtime::KvReader;
+use async_trait::async_trait;
+use std::sync::Arc;
+
+/// Reads the key-value state of a task's instance from the engine
+pub(super) struct EngineKv {
+    client: Arc<dyn EngineClient>,
+    retry: Arc<RpcRetry>,
+    worker_id: String,
+    task_id: String,
+}
+
+impl EngineKv {
+    pub(super) fn new(
+        client: Arc<dyn EngineClient>,
+        retry: Arc<RpcRetry>,
+        worker_id: String,
+        task_id: String,
+    ) -> Self {
+        Self {
+            client,
+            retry,
+            worker_id,
+            task_id,
+        }
+    }
+}
+
+#[async_trait]
+impl KvReader for EngineKv {
+    async fn get(&self, key: &[u8]) -> RuntimeResult<Option<Vec<u8>>> {
+        let request = KvGetRequest {
+            worker_id: self.worker_id.clone(),
+            task_id: self.task_id.clone(),
+            key: key.to_vec(),
+        };
+
+        let response = self
+            .retry
+            .call(true, || self.client.kv_get(request.clone()))
+            .await
+            .map_err(|e| RuntimeError::Kv(format!("Get failed: {}", e)))?;
+        match response.error {
+            Some(error) => Err(RuntimeError::Kv(error)),
+            None => Ok(response.value),
+        }
+    }
+
+    async fn range(&self, start: &[u8], end: &[u8], limit: usize) -> RuntimeResult<Vec<(Vec<u8>, Vec<u8>)>> {
+        let request = KvRangeRequest {
+            worker_id: self.worker_id.clone(),
+            task_id: self.task_id.clone(),
+            start: start.to_vec(),
+            end: end.to_vec(),
+            limit: limit.try_into().unwrap_or(u32::MAX),
+        };
+
+        let response = self
+            .retry
+            .call(true, || self.client.kv_range(request.clone()))
+            .await
+            .map_err(|e| RuntimeError::Kv(format!("Range failed: {}", e)))?;
+        match response.error {
+            Some(error) => Err(RuntimeError::Kv(error)),
+            None => Ok(response.entries.into_iter().map(|entry| (entry.key, entry.value)).collect()),
+        }
+    }
+}
@@ -0,0 +1,65 @@
+//! Worker-side Prometheus metrics
+
+use crate::error::{EngineError, Result};
+use crate::types::{RuntimeType, WorkerId};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// Counter of tasks executed, by runtime
+const TASKS_EXECUTED_METRIC: &str = "workflow_worker_tasks_executed_total";
+
+/// Counter of executed tasks that failed, by runtime
+const TASKS_FAILED_METRIC: &str = "workflow_worker_tasks_failed_total";
+
+/// Histogram of task execution time, by runtime
+const TASK_DURATION_METRIC: &str = "workflow_worker_task_duration_seconds";
+
+/// Counter of poll round-trips to the engine, by whether they returned a task
+const POLLS_METRIC: &str = "workflow_worker_polls_total";
+
+/// Histogram of poll round-trip time
+const POLL_DURATION_METRIC: &str = "workflow_worker_poll_duration_seconds";
+
+/// Gauge of tasks being executed
+const ACTIVE_TASKS_METRIC: &str = "workflow_worker_active_tasks";
+
+/// Histogram buckets in seconds, from a fast poll to a long task
+const DURATION_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0, 300.0,
+];
+
+/// Serve the metrics on `addr` under any path, labelled with the worker ID
+///
+/// Installs the process-wide metrics recorder, so it fails if one is
+/// installed already.
+pub(super) fn serve(addr: SocketAddr, worker_id: &WorkerId) -> Result<()> {
+    PrometheusBuilder::new()
+        .with_http_listener(addr)
+        .add_global_label("worker_id", worker_id.to_string())
+        .set_buckets_for_metric(Matcher::Suffix("duration_seconds".to_string()), DURATION_BUCKETS)
+        .and_then(|builder| builder.install())
+        .map_err(|e| EngineError::Internal(format!("Failed to serve metrics on {}: {}", addr, e)))
+}
+
+/// Record an executed task
+pub(super) fn record_task(runtime: &RuntimeType, success: bool, elapsed: Duration) {
+    let runtime = runtime.as_str().to_string();
+    metrics::counter!(TASKS_EXECUTED_METRIC, "runtime" => runtime.clone()).increment(1);
+    if !success {
+        metrics::counter!(TASKS_FAILED_METRIC, "runtime" => runtime.clone()).increment(1);
+    }
+    metrics::histogram!(TASK_DURATION_METRIC, "runtime" => runtime).record(elapsed.as_secs_f64());
+}
+
+/// Record a completed poll round-trip
+pub(super) fn record_poll(got_task: bool, elapsed: Duration) {
+    let outcome = if got_task { "task" } else { "empty" };
+    metrics::counter!(POLLS_METRIC, "outcome" => outcome).increment(1);
+    metrics::histogram!(POLL_DURATION_METRIC).record(elapsed.as_secs_f64());
+}
+
+/// Record the number of tasks being executed
+pub(super) fn record_active_tasks(active: u32) {
+    metrics::gauge!(ACTIVE_TASKS_METRIC).set(active as f64);
+}
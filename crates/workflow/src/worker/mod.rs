@@ -1,20 +1,49 @@
 //! Worker implementation
 
+mod client;
 mod executor;
+mod kv;
+mod metrics;
+mod offline;
+mod retry;
+mod stream;
 
 pub use executor::TaskExecutor;
+pub use offline::{BufferedCompletion, Outbox};
+pub use retry::RpcRetry;
 
-use crate::error::{EngineError, Result};
-use crate::runtime::{JavaScriptRuntime, WasmRuntime};
-use crate::types::{RuntimeType, WorkerId, WorkerStats};
+pub use client::EngineClient;
+
+use crate::engine::{LocalTransport, WorkflowEngine};
+use crate::error::{EngineError, Result, RpcError};
+use crate::runtime::{JavaScriptRuntime, Runtime, TaskKv, WasmRuntime, load_plugin_dir};
+use crate::telemetry;
+use crate::transfer::{ChunkAssembler, MessageLimits};
+use crate::types::{Location, RuntimeType, WorkerId, WorkerStats};
+use axum::http::HeaderMap;
+use chrono::Utc;
 use connectare::client::{RpcClient, RpcClientConfig};
+use degov_crypto::DidKey;
+use futures::StreamExt;
+use futures::stream::FuturesUnordered;
+use kv::EngineKv;
+use offline::OfflineState;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use std::time::Duration;
-use tokio::sync::broadcast;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use stream::TaskStream;
+use tokio::sync::{broadcast, oneshot};
 use tokio::time::{interval, sleep};
+use tracing::Instrument;
+
+/// How long a worker polls for tasks after its task stream failed
+const STREAM_RETRY_DELAY: Duration = Duration::from_secs(30);
 
-// Import the generated proto code
-mod proto {
+/// Messages of the workflow service, as exchanged through an [`EngineClient`]
+pub mod proto {
     include!(concat!(env!("OUT_DIR"), "/workflow.rs"));
 }
 
@@ -23,12 +52,31 @@ use proto::*;
 /// Worker that executes tasks
 pub struct Worker {
     id: WorkerId,
-    rpc_client: WorkflowServiceClient,
+    rpc_client: Arc<dyn EngineClient>,
+    /// Retries of failed calls to the engine
+    retry: Arc<RpcRetry>,
+    engine_url: String,
+    /// Client for task streams, which the RPC client does not cover
+    http: reqwest::Client,
+    /// Until when the worker polls instead of streaming tasks
+    stream_retry_at: parking_lot::Mutex<Option<Instant>>,
     executor: TaskExecutor,
     poll_interval: Duration,
     heartbeat_interval: Duration,
+    /// Most tasks executed at once
+    concurrency: usize,
     hostname: String,
+    location: Location,
     stats: Arc<parking_lot::RwLock<WorkerStats>>,
+    limits: MessageLimits,
+    offline: Option<Arc<OfflineState>>,
+    trusted_signers: Vec<DidKey>,
+    /// Tasks being executed, with the signal aborting them
+    running: Arc<parking_lot::Mutex<HashMap<String, oneshot::Sender<()>>>>,
+    /// Set once the engine asks the worker to drain
+    draining: Arc<AtomicBool>,
+    /// Local address serving Prometheus metrics
+    metrics_addr: Option<SocketAddr>,
 }
 
 impl Worker {
@@ -36,8 +84,31 @@ impl Worker {
     pub async fn new(engine_url: &str) -> Result<Self> {
         let client_config = RpcClientConfig::new(engine_url)
             .map_err(|e| EngineError::Internal(format!("Failed to create RPC config: {}", e)))?;
-        let rpc_client = WorkflowServiceClient::new(RpcClient::new(client_config));
+        let rpc_client = Arc::new(WorkflowServiceClient::new(RpcClient::new(client_config)));
+        Self::with_client(rpc_client, engine_url)
+    }
+
+    /// Create a worker running in the engine's process
+    ///
+    /// Its calls go straight to the engine's handlers instead of over a
+    /// socket, and it polls for tasks instead of streaming them. Useful for
+    /// single-process deployments and integration tests.
+    ///
+    /// Methods guarded by an RPC authorizer see calls without headers; use
+    /// [`Worker::local_with_headers`] to pass credentials.
+    pub fn local(engine: Arc<WorkflowEngine>) -> Result<Self> {
+        Self::local_with_headers(engine, HeaderMap::new())
+    }
+
+    /// Create a worker running in the engine's process whose calls show
+    /// `headers` to the engine's RPC authorizers
+    pub fn local_with_headers(engine: Arc<WorkflowEngine>, headers: HeaderMap) -> Result<Self> {
+        Self::with_client(Arc::new(LocalTransport::new(engine, headers)), "local")
+    }
 
+    /// Create a worker making its calls through `rpc_client`, e.g. a custom
+    /// transport or a mock engine in tests
+    pub fn with_client(rpc_client: Arc<dyn EngineClient>, engine_url: &str) -> Result<Self> {
         let mut executor = TaskExecutor::new();
         executor.register_runtime(RuntimeType::JavaScript, Box::new(JavaScriptRuntime::new()));
         executor.register_runtime(
@@ -52,11 +123,23 @@ impl Worker {
         Ok(Self {
             id: WorkerId::new(),
             rpc_client,
+            retry: Arc::new(RpcRetry::default()),
+            engine_url: engine_url.to_string(),
+            http: reqwest::Client::new(),
+            stream_retry_at: parking_lot::Mutex::new(None),
             executor,
             poll_interval: Duration::from_millis(500),
             heartbeat_interval: Duration::from_secs(10),
+            concurrency: 1,
             hostname,
+            location: Location::default(),
             stats: Arc::new(parking_lot::RwLock::new(WorkerStats::default())),
+            limits: MessageLimits::default(),
+            offline: None,
+            trusted_signers: Vec::new(),
+            running: Arc::new(parking_lot::Mutex::new(HashMap::new())),
+            draining: Arc::new(AtomicBool::new(false)),
+            metrics_addr: None,
         })
     }
 
@@ -65,6 +148,11 @@ impl Worker {
         &self.id
     }
 
+    /// Get the worker statistics, with the tasks it is executing
+    pub fn stats(&self) -> WorkerStats {
+        self.stats.read().clone()
+    }
+
     /// Set poll interval
     pub fn with_poll_interval(mut self, duration: Duration) -> Self {
         self.poll_interval = duration;
@@ -77,30 +165,144 @@ impl Worker {
         self
     }
 
+    /// Set how many tasks the worker executes at once
+    ///
+    /// An idle worker claims up to this many tasks in one poll and runs them
+    /// concurrently, polling again once all of them finished.
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Set how failed calls to the engine are retried
+    ///
+    /// By default calls safe to repeat are retried up to twice with backoff;
+    /// see [`RpcRetry`].
+    pub fn with_rpc_retry(mut self, retry: RpcRetry) -> Self {
+        self.retry = Arc::new(retry);
+        self
+    }
+
+    /// Set the RPC message size limits
+    pub fn with_message_limits(mut self, limits: MessageLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Only execute task code attested by one of `signers`
+    ///
+    /// Without trusted signers the worker executes any code it is given.
+    pub fn with_trusted_signers(mut self, signers: Vec<DidKey>) -> Self {
+        self.trusted_signers = signers;
+        self
+    }
+
+    /// Set the region and zone the worker runs in
+    ///
+    /// Tasks placed in a region go to workers there first; tasks bound to
+    /// their region for data residency only ever run there.
+    pub fn with_location(mut self, region: impl Into<String>, zone: Option<String>) -> Self {
+        self.location = Location {
+            region: Some(region.into()),
+            zone,
+        };
+        self
+    }
+
+    /// Serve Prometheus metrics on `addr` while the worker runs
+    ///
+    /// Exposes executed and failed tasks and execution latency per runtime,
+    /// poll round-trips and active tasks, for autoscalers to act on. The
+    /// worker installs the process-wide metrics recorder, so only one worker
+    /// per process can serve metrics.
+    pub fn with_metrics_addr(mut self, addr: SocketAddr) -> Self {
+        self.metrics_addr = Some(addr);
+        self
+    }
+
+    /// Register an additional runtime
+    ///
+    /// The runtime is advertised to the engine under its type when the worker
+    /// registers, replacing a runtime registered earlier with the same type.
+    /// Crates providing runtimes behind feature flags plug in here.
+    pub fn with_runtime(mut self, runtime: impl Runtime + 'static) -> Self {
+        self.executor.register_runtime(runtime.runtime_type(), Box::new(runtime));
+        self
+    }
+
+    /// Cache compiled WASM modules
+    ///
+    /// Up to `capacity` modules are kept in memory, keyed by the digest of
+    /// their code; with `dir`, compiled modules are also serialized there and
+    /// survive restarts. By default the worker keeps
+    /// [`crate::runtime::DEFAULT_MODULE_CACHE_SIZE`] modules in memory only.
+    pub fn with_wasm_module_cache(self, capacity: usize, dir: Option<PathBuf>) -> Result<Self> {
+        let runtime = WasmRuntime::new().map_err(EngineError::Runtime)?.with_module_cache(capacity, dir);
+        Ok(self.with_runtime(runtime))
+    }
+
+    /// Register a plugin runtime for every manifest in `dir`
+    ///
+    /// See [`crate::runtime::load_plugin_dir`] for the manifest format.
+    pub fn with_plugin_dir(mut self, dir: impl AsRef<Path>) -> Result<Self> {
+        for runtime in load_plugin_dir(dir.as_ref())? {
+            tracing::info!(
+                "Loaded runtime plugin '{}' ({})",
+                runtime.manifest().name,
+                runtime.manifest().command
+            );
+            self = self.with_runtime(runtime);
+        }
+        Ok(self)
+    }
+
+    /// Keep working while the engine is unreachable
+    ///
+    /// The worker prefetches up to `prefetch` tasks while connected and keeps
+    /// executing them when the engine goes away. Completions that cannot be
+    /// delivered are buffered in the outbox file at `outbox_path` and replayed
+    /// once the engine is reachable again; the engine drops any that are stale.
+    pub fn with_offline_mode(mut self, outbox_path: impl Into<PathBuf>, prefetch: usize) -> Result<Self> {
+        let outbox = Outbox::open(outbox_path)?;
+        if !outbox.is_empty() {
+            tracing::info!("Loaded {} buffered completion(s)", outbox.len());
+        }
+        self.offline = Some(Arc::new(OfflineState::new(outbox, prefetch.max(1))));
+        Ok(self)
+    }
+
     /// Run the worker
     pub async fn run(&self) -> Result<()> {
-        // Register with engine
-        self.register().await?;
+        if let Some(addr) = self.metrics_addr {
+            metrics::serve(addr, &self.id)?;
+            tracing::info!("Serving worker metrics on {}", addr);
+        }
+
+        // Register with engine; an offline worker may start disconnected
+        match self.register().await {
+            Ok(()) => {}
+            Err(e) if self.offline.is_some() => {
+                tracing::warn!("Registration failed, starting offline: {}", e);
+            }
+            Err(e) => return Err(e),
+        }
 
         tracing::info!("Worker {} started", self.id);
+        self.stats.write().max_concurrency = self.concurrency.try_into().unwrap_or(u32::MAX);
 
         // Create shutdown channel
         let (shutdown_tx, mut shutdown_rx) = broadcast::channel::<()>(1);
 
-        // Spawn heartbeat task
+        // Spawn heartbeat task; it keeps extending the leases of the tasks
+        // still running during shutdown, and is stopped once they finished
         let heartbeat_handle = {
             let worker = self.clone_for_heartbeat();
-            let mut heartbeat_shutdown = shutdown_tx.subscribe();
-            tokio::spawn(async move {
-                tokio::select! {
-                    _ = worker.heartbeat_loop() => {},
-                    _ = heartbeat_shutdown.recv() => {
-                        tracing::info!("Heartbeat loop shutting down");
-                    }
-                }
-            })
+            tokio::spawn(async move { worker.heartbeat_loop().await })
         };
 
+        // Task streams stop taking tasks on shutdown
+        let mut stream_shutdown = shutdown_tx.subscribe();
+
         // Spawn shutdown signal handler
         let shutdown_handle = tokio::spawn(async move {
             wait_for_shutdown_signal().await;
@@ -117,8 +319,13 @@ impl Worker {
                     if graceful_shutdown {
                         break;
                     }
+                    // A draining worker takes no new tasks
+                    if self.draining.load(Ordering::SeqCst) {
+                        graceful_shutdown = true;
+                        continue;
+                    }
                     
-                    match self.poll_and_execute().await {
+                    match self.poll_and_execute(&mut stream_shutdown).await {
                         Ok(true) => {
                             // Task executed, continue immediately
                         }
@@ -135,11 +342,11 @@ impl Worker {
                     tracing::info!("Shutdown signal received");
                     graceful_shutdown = true;
                     
-                    // Check if there's an active task
+                    // Check if there are active tasks
                     let active_tasks = self.stats.read().active_tasks;
                     if active_tasks > 0 {
                         tracing::info!("Waiting for {} active task(s) to complete...", active_tasks);
-                        // Continue the loop to finish the current task
+                        // Continue the loop to finish the current tasks
                     } else {
                         break;
                     }
@@ -156,8 +363,20 @@ impl Worker {
         
         // Abort heartbeat task
         heartbeat_handle.abort();
+        if self.draining.load(Ordering::SeqCst) {
+            // No signal is coming; the drain ends the worker instead
+            shutdown_handle.abort();
+        }
         let _ = shutdown_handle.await;
 
+        // Leave no registration behind for recovery to chase
+        let request = DeregisterWorkerRequest {
+            worker_id: self.id.to_string(),
+        };
+        if let Err(e) = self.rpc_client.deregister_worker(request).await {
+            tracing::warn!("Failed to deregister worker {}: {}", self.id, e);
+        }
+
         tracing::info!("Worker {} stopped", self.id);
         
         Ok(())
@@ -174,11 +393,13 @@ impl Worker {
             worker_id: self.id.to_string(),
             capabilities,
             hostname: self.hostname.clone(),
+            region: self.location.region.clone(),
+            zone: self.location.zone.clone(),
         };
 
         let response = self
-            .rpc_client
-            .register_worker(request)
+            .retry
+            .call(true, || self.rpc_client.register_worker(request.clone()))
             .await
             .map_err(|e| EngineError::Internal(format!("Registration failed: {}", e)))?;
 
@@ -193,117 +414,447 @@ impl Worker {
         Ok(())
     }
 
-    /// Poll for a task and execute it
-    async fn poll_and_execute(&self) -> Result<bool> {
-        let request = PollTaskRequest {
+    /// Receive tasks and execute them
+    ///
+    /// Tasks are streamed from the engine as they are enqueued; workers in
+    /// offline mode, or whose task stream failed recently, poll for them.
+    async fn poll_and_execute(&self, shutdown: &mut broadcast::Receiver<()>) -> Result<bool> {
+        let streaming = self.rpc_client.supports_streaming()
+            && self.stream_retry_at.lock().is_none_or(|at| Instant::now() >= at);
+        let tasks: Vec<TaskPayload> = match &self.offline {
+            Some(offline) => {
+                self.sync_offline(offline).await;
+                std::iter::from_fn(|| offline.pop_task()).take(self.concurrency).collect()
+            }
+            None if streaming => {
+                return self.stream_and_execute(shutdown).await;
+            }
+            None => self.fetch_tasks(self.concurrency).await?,
+        };
+
+        if tasks.is_empty() {
+            return Ok(false);
+        }
+
+        let results = futures::future::join_all(tasks.into_iter().map(|task| self.run_task(task))).await;
+        results.into_iter().collect::<Result<Vec<()>>>()?;
+
+        Ok(true)
+    }
+
+    /// Receive tasks on a task stream and execute them as they arrive
+    ///
+    /// The stream hands out at most as many tasks as the worker runs at once
+    /// and ends once it delivered all of them. Shutting down closes the stream
+    /// while the tasks received finish.
+    async fn stream_and_execute(&self, shutdown: &mut broadcast::Receiver<()>) -> Result<bool> {
+        let request = StreamTasksRequest {
+            worker_id: self.id.to_string(),
+            max_tasks: self.concurrency.try_into().unwrap_or(u32::MAX),
+        };
+        let opened = TaskStream::open(&self.http, &self.engine_url, &request, self.limits.max_message_bytes()).await;
+        let mut stream = match opened {
+            Ok(stream) => stream,
+            Err(e) => {
+                self.fall_back_to_polling(e);
+                return Ok(false);
+            }
+        };
+
+        let mut running = FuturesUnordered::new();
+        let mut open = true;
+        let mut executed = false;
+        loop {
+            tokio::select! {
+                message = stream.next(), if open => match message {
+                    Ok(Some(message)) => {
+                        // Workers that started offline register once the engine is reachable
+                        if message.no_task_reason.as_deref() == Some("worker_not_registered")
+                            && let Err(e) = self.register().await
+                        {
+                            tracing::warn!("Registration failed: {}", e);
+                        }
+                        match self.prepare_tasks(message.tasks).await {
+                            Ok(tasks) => {
+                                executed |= !tasks.is_empty();
+                                running.extend(tasks.into_iter().map(|task| self.run_task(task)));
+                            }
+                            Err(e) => tracing::error!("Error receiving tasks: {}", e),
+                        }
+                    }
+                    Ok(None) => open = false,
+                    Err(e) => {
+                        self.fall_back_to_polling(e);
+                        open = false;
+                    }
+                },
+                Some(result) = running.next() => {
+                    if let Err(e) = result {
+                        tracing::error!("Error executing task: {}", e);
+                    }
+                }
+                _ = shutdown.recv(), if open => open = false,
+                else => break,
+            }
+        }
+
+        Ok(executed)
+    }
+
+    /// Poll for tasks for a while after the task stream failed
+    fn fall_back_to_polling(&self, error: RpcError) {
+        tracing::warn!("Task stream failed, polling for tasks: {}", error);
+        *self.stream_retry_at.lock() = Some(Instant::now() + STREAM_RETRY_DELAY);
+    }
+
+    /// Execute a task and report its result, continuing the trace the task
+    /// was enqueued in
+    async fn run_task(&self, task_payload: TaskPayload) -> Result<()> {
+        let span = tracing::info_span!(
+            "task.run",
+            task_id = %task_payload.task_id,
+            workflow_id = %task_payload.workflow_id
+        );
+        telemetry::set_parent(&span, &task_payload.trace_context);
+        self.execute_and_report(task_payload).instrument(span).await
+    }
+
+    /// Execute a task and report its result
+    async fn execute_and_report(&self, task_payload: TaskPayload) -> Result<()> {
+        // Track the task as running
+        {
+            let mut stats = self.stats.write();
+            stats.active_tasks += 1;
+            stats.running_tasks.push(crate::types::RunningTask {
+                task_id: task_payload.task_id.clone(),
+                workflow_id: task_payload.workflow_id.clone(),
+                runtime_type: task_payload.task_type.clone(),
+                attempt: task_payload.attempt,
+                started_at: Utc::now(),
+            });
+            metrics::record_active_tasks(stats.active_tasks);
+        }
+
+        // Execute task until it finishes or the engine cancels it
+        let task_id = task_payload.task_id.clone();
+        let (cancel_tx, mut cancelled) = oneshot::channel();
+        self.running.lock().insert(task_id.clone(), cancel_tx);
+        let result = tokio::select! {
+            result = self.execute_task(task_payload) => Some(result),
+            Ok(()) = &mut cancelled => None,
+        };
+        self.running.lock().remove(&task_id);
+
+        // Update stats
+        {
+            let mut stats = self.stats.write();
+            stats.active_tasks = stats.active_tasks.saturating_sub(1);
+            stats.running_tasks.retain(|task| task.task_id != task_id);
+            metrics::record_active_tasks(stats.active_tasks);
+            match &result {
+                Some(result) if result.result.success => stats.total_tasks_completed += 1,
+                Some(_) => stats.total_tasks_failed += 1,
+                None => {}
+            }
+        }
+
+        let Some(result) = result else {
+            tracing::warn!("Task {} was cancelled by the engine, aborted it", task_id);
+            return Ok(());
+        };
+
+        // Report completion
+        self.report_completion(&result.task_id, result.attempt, result.result).await
+    }
+
+    /// Claim up to `max` tasks from the engine
+    async fn fetch_tasks(&self, max: usize) -> Result<Vec<TaskPayload>> {
+        let request = PollTasksRequest {
             worker_id: self.id.to_string(),
+            max_tasks: max.try_into().unwrap_or(u32::MAX),
         };
 
+        let start = Instant::now();
         let response = self
-            .rpc_client
-            .poll_task(request)
+            .retry
+            .call(false, || self.rpc_client.poll_tasks(request.clone()))
             .await
             .map_err(|e| EngineError::Internal(format!("Poll failed: {}", e)))?;
+        metrics::record_poll(!response.tasks.is_empty(), start.elapsed());
+
+        // Workers that started offline register once the engine is reachable
+        if response.no_task_reason.as_deref() == Some("worker_not_registered") {
+            self.register().await?;
+        }
+
+        self.prepare_tasks(response.tasks).await
+    }
 
-        match response.task {
-            Some(task_payload) => {
-                tracing::info!("Received task: {}", task_payload.task_id);
-                
-                // Increment active tasks
-                {
-                    let mut stats = self.stats.write();
-                    stats.active_tasks += 1;
+    /// Fetch the chunked code of received tasks
+    ///
+    /// Tasks whose code cannot be fetched are reported as failed and left out.
+    async fn prepare_tasks(&self, received: Vec<TaskPayload>) -> Result<Vec<TaskPayload>> {
+        let mut tasks = Vec::with_capacity(received.len());
+        for mut task_payload in received {
+            tracing::info!("Received task: {}", task_payload.task_id);
+
+            if task_payload.code_chunked {
+                match self.fetch_task_code(&task_payload).await {
+                    Ok(code) => task_payload.code = code,
+                    Err(e) => {
+                        tracing::error!("Failed to fetch code for task {}: {}", task_payload.task_id, e);
+                        let result = TaskResult {
+                            success: false,
+                            output: Vec::new(),
+                            error: Some(format!("Failed to fetch task code: {}", e)),
+                            execution_time_ms: 0,
+                            kv_writes: Vec::new(),
+                        };
+                        self.report_completion(&task_payload.task_id, task_payload.attempt, result)
+                            .await?;
+                        continue;
+                    }
                 }
+            }
+
+            tasks.push(task_payload);
+        }
+
+        Ok(tasks)
+    }
 
-                // Execute task
-                let result = self.execute_task(task_payload).await;
+    /// Replay buffered completions and top up the local task queue
+    async fn sync_offline(&self, offline: &OfflineState) {
+        // Deliver buffered results before taking on new work
+        for completion in offline.outbox.pending() {
+            let result = TaskResult {
+                success: completion.result.success,
+                output: completion.result.output.clone(),
+                error: completion.result.error.clone(),
+                execution_time_ms: completion.result.execution_time_ms as i64,
+                kv_writes: completion.result.kv_writes.iter().cloned().map(kv_write_proto).collect(),
+            };
 
-                // Update stats
-                {
-                    let mut stats = self.stats.write();
-                    stats.active_tasks = stats.active_tasks.saturating_sub(1);
-                    if result.result.success {
-                        stats.total_tasks_completed += 1;
+            match self.send_completion(&completion.task_id, completion.attempt, result).await {
+                Ok(response) if response.acknowledged || response.rejected_reason.is_some() => {
+                    if let Some(reason) = response.rejected_reason {
+                        tracing::warn!("Engine dropped buffered completion for task {}: {}", completion.task_id, reason);
                     } else {
-                        stats.total_tasks_failed += 1;
+                        tracing::info!("Replayed buffered completion for task {}", completion.task_id);
                     }
+                    if let Err(e) = offline.outbox.remove(&completion.task_id) {
+                        tracing::error!("Failed to update outbox: {}", e);
+                    }
+                }
+                Ok(_) | Err(_) => {
+                    tracing::debug!("Engine unreachable, {} completion(s) still buffered", offline.outbox.len());
+                    return;
                 }
+            }
+        }
 
-                // Report completion
-                self.report_completion(&result.task_id, result.result).await?;
+        let wanted = offline.prefetch().saturating_sub(offline.queued());
+        if wanted > 0 {
+            match self.fetch_tasks(wanted).await {
+                Ok(tasks) => tasks.into_iter().for_each(|task| offline.push_task(task)),
+                Err(e) => tracing::warn!("Engine unreachable, working offline: {}", e),
+            }
+        }
+    }
+
+    /// Fetch chunked task code from the engine
+    async fn fetch_task_code(&self, payload: &TaskPayload) -> Result<Vec<u8>> {
+        let mut assembler = ChunkAssembler::new(payload.code_size, &self.limits)?;
+
+        while !assembler.is_complete() {
+            let request = FetchTaskCodeRequest {
+                worker_id: self.id.to_string(),
+                task_id: payload.task_id.clone(),
+                offset: assembler.next_offset(),
+            };
 
-                Ok(true)
+            let response = self
+                .retry
+                .call(true, || self.rpc_client.fetch_task_code(request.clone()))
+                .await
+                .map_err(|e| EngineError::Internal(format!("Fetch task code failed: {}", e)))?;
+
+            if let Some(error) = response.error {
+                return Err(EngineError::Internal(format!("Fetch task code failed: {}", error)));
+            }
+            if response.total_size != payload.code_size {
+                return Err(RpcError::Protocol(format!(
+                    "task code size changed from {} to {} bytes",
+                    payload.code_size, response.total_size
+                ))
+                .into());
             }
-            None => Ok(false),
+
+            assembler.push(response.offset, &response.chunk)?;
         }
+
+        Ok(assembler.finish()?)
     }
 
     /// Execute a task
     async fn execute_task(&self, payload: TaskPayload) -> TaskExecutionResult {
-        let start = std::time::Instant::now();
+        let start = Instant::now();
 
-        let runtime_type = match payload.task_type.as_str() {
-            "javascript" => RuntimeType::JavaScript,
-            "wasm" => RuntimeType::Wasm,
-            _ => {
-                return TaskExecutionResult {
-                    task_id: payload.task_id,
-                    result: TaskResult {
-                        success: false,
-                        output: Vec::new(),
-                        error: Some(format!("Unknown runtime type: {}", payload.task_type)),
-                        execution_time_ms: 0,
-                    },
-                };
-            }
-        };
+        let runtime_type = RuntimeType::from_name(&payload.task_type);
+        if !self.executor.supports_runtime(&runtime_type) {
+            return TaskExecutionResult {
+                task_id: payload.task_id,
+                attempt: payload.attempt,
+                result: TaskResult {
+                    success: false,
+                    output: Vec::new(),
+                    error: Some(format!("Unknown runtime type: {}", payload.task_type)),
+                    execution_time_ms: 0,
+                    kv_writes: Vec::new(),
+                },
+            };
+        }
 
         let task_def = crate::types::TaskDefinition {
             name: "task".to_string(),
             runtime_type,
             code: payload.code,
             timeout_ms: payload.timeout_ms as u64,
-            retry_policy: None,
+            attestation: payload.attestation,
+            wasm_limits: payload.wasm_limits.map(|limits| crate::types::WasmLimits {
+                fuel: limits.fuel,
+                max_memory_bytes: limits.max_memory_bytes,
+                interrupt_after_ms: limits.interrupt_after_ms,
+            }),
+            http: payload.http.map(|policy| crate::types::HttpPolicy {
+                allowed_hosts: policy.allowed_hosts,
+                max_body_bytes: policy.max_body_bytes,
+                timeout_ms: policy.timeout_ms,
+            }),
+            ..Default::default()
         };
 
-        match self.executor.execute(&task_def, &payload.input).await {
+        // Refuse code the stack did not sign before it gets near a runtime
+        if !self.trusted_signers.is_empty()
+            && let Err(e) = task_def.verify_code(&self.trusted_signers)
+        {
+            tracing::error!("Rejected code of task {}: {}", payload.task_id, e);
+            return TaskExecutionResult {
+                task_id: payload.task_id,
+                attempt: payload.attempt,
+                result: TaskResult {
+                    success: false,
+                    output: Vec::new(),
+                    error: Some(format!("Untrusted task code: {}", e)),
+                    execution_time_ms: 0,
+                    kv_writes: Vec::new(),
+                },
+            };
+        }
+
+        // Writes are only kept if the task succeeds
+        let reader = EngineKv::new(self.rpc_client.clone(), self.retry.clone(), self.id.to_string(), payload.task_id.clone());
+        let kv = TaskKv::new(Arc::new(reader));
+        let outcome = self.executor.execute(&task_def, &payload.input, kv.clone()).await;
+        metrics::record_task(&task_def.runtime_type, outcome.is_ok(), start.elapsed());
+
+        match outcome {
             Ok(output) => TaskExecutionResult {
                     task_id: payload.task_id,
+                    attempt: payload.attempt,
                     result: TaskResult {
                         success: true,
                         output,
                         error: None,
                         execution_time_ms: start.elapsed().as_millis() as i64,
+                        kv_writes: kv.take_writes().into_iter().map(kv_write_proto).collect(),
                     },
                 },
             Err(e) => TaskExecutionResult {
                 task_id: payload.task_id,
+                attempt: payload.attempt,
                 result: TaskResult {
                     success: false,
                     output: Vec::new(),
                     error: Some(e.to_string()),
                     execution_time_ms: start.elapsed().as_millis() as i64,
+                    kv_writes: Vec::new(),
                 },
             },
         }
     }
 
-    /// Report task completion
-    async fn report_completion(&self, task_id: &str, result: TaskResult) -> Result<()> {
+    /// Report task completion, buffering it if the engine is unreachable
+    async fn report_completion(&self, task_id: &str, attempt: u32, mut result: TaskResult) -> Result<()> {
+        // Report oversized output as a task failure rather than letting the
+        // request be rejected by the engine
+        if let Err(e) = self.limits.check_message("task output", result.output.len()) {
+            tracing::error!("Task {} output rejected: {}", task_id, e);
+            result = TaskResult {
+                success: false,
+                output: Vec::new(),
+                error: Some(e.to_string()),
+                execution_time_ms: result.execution_time_ms,
+                kv_writes: Vec::new(),
+            };
+        }
+
+        let buffered = BufferedCompletion {
+            task_id: task_id.to_string(),
+            attempt,
+            result: crate::types::TaskResult {
+                success: result.success,
+                output: result.output.clone(),
+                error: result.error.clone(),
+                execution_time_ms: result.execution_time_ms.max(0) as u64,
+                kv_writes: result.kv_writes.iter().map(kv_write_from_proto).collect(),
+            },
+        };
+
+        let response = match self.send_completion(task_id, attempt, result).await {
+            Ok(response) => response,
+            Err(e) => match &self.offline {
+                Some(offline) => {
+                    tracing::warn!("Engine unreachable, buffering completion of task {}: {}", task_id, e);
+                    return offline.outbox.push(buffered);
+                }
+                None => return Err(e),
+            },
+        };
+
+        match response.rejected_reason {
+            Some(reason) => tracing::warn!("Engine rejected completion of task {}: {}", task_id, reason),
+            None if !response.acknowledged => {
+                if let Some(offline) = &self.offline {
+                    return offline.outbox.push(buffered);
+                }
+                tracing::error!("Engine failed to record completion of task {}", task_id);
+            }
+            None => tracing::info!("Task {} completion reported", task_id),
+        }
+
+        Ok(())
+    }
+
+    /// Send a completion to the engine
+    async fn send_completion(
+        &self,
+        task_id: &str,
+        attempt: u32,
+        result: TaskResult,
+    ) -> Result<CompleteTaskResponse> {
         let request = CompleteTaskRequest {
             worker_id: self.id.to_string(),
             task_id: task_id.to_string(),
             result: Some(result),
+            attempt,
+            trace_context: telemetry::current(),
         };
 
-        let _response = self
-            .rpc_client
-            .complete_task(request)
+        self.retry
+            .call(false, || self.rpc_client.complete_task(request.clone()))
             .await
-            .map_err(|e| EngineError::Internal(format!("Complete task failed: {}", e)))?;
-
-        tracing::info!("Task {} completion reported", task_id);
-        Ok(())
+            .map_err(|e| EngineError::Internal(format!("Complete task failed: {}", e)))
     }
 
     /// Heartbeat loop
@@ -326,19 +877,42 @@ impl Worker {
             active_tasks: stats.active_tasks as i32,
             total_tasks_completed: stats.total_tasks_completed as i64,
             total_tasks_failed: stats.total_tasks_failed as i64,
+            max_concurrency: stats.max_concurrency,
+            running_tasks: stats
+                .running_tasks
+                .into_iter()
+                .map(|task| RunningTask {
+                    task_id: task.task_id,
+                    workflow_id: task.workflow_id,
+                    runtime_type: task.runtime_type,
+                    attempt: task.attempt,
+                    started_at_ms: task.started_at.timestamp_millis(),
+                })
+                .collect(),
         };
 
         let request = HeartbeatRequest {
             worker_id: self.id.to_string(),
             status: Some(status),
+            running_task_ids: self.running.lock().keys().cloned().collect(),
         };
 
-        let _response = self
-            .rpc_client
-            .heartbeat(request)
+        let response = self
+            .retry
+            .call(true, || self.rpc_client.heartbeat(request.clone()))
             .await
             .map_err(|e| EngineError::Internal(format!("Heartbeat failed: {}", e)))?;
 
+        for task_id in response.cancelled_task_ids {
+            if let Some(cancel) = self.running.lock().remove(&task_id) {
+                let _ = cancel.send(());
+            }
+        }
+
+        if response.drain && !self.draining.swap(true, Ordering::SeqCst) {
+            tracing::info!("Engine asked worker {} to drain", self.id);
+        }
+
         Ok(())
     }
 
@@ -347,20 +921,50 @@ impl Worker {
         Self {
             id: self.id.clone(),
             rpc_client: self.rpc_client.clone(),
+            retry: self.retry.clone(),
+            engine_url: self.engine_url.clone(),
+            http: self.http.clone(),
+            stream_retry_at: parking_lot::Mutex::new(None),
             executor: TaskExecutor::new(), // Empty executor for heartbeat
             poll_interval: self.poll_interval,
             heartbeat_interval: self.heartbeat_interval,
+            concurrency: self.concurrency,
             hostname: self.hostname.clone(),
+            location: self.location.clone(),
             stats: self.stats.clone(),
+            limits: self.limits,
+            offline: self.offline.clone(),
+            trusted_signers: self.trusted_signers.clone(),
+            running: self.running.clone(),
+            draining: self.draining.clone(),
+            metrics_addr: self.metrics_addr,
         }
     }
 }
 
 struct TaskExecutionResult {
     task_id: String,
+    attempt: u32,
     result: TaskResult,
 }
 
+fn kv_write_proto(write: crate::types::KvWrite) -> KvWrite {
+    match write {
+        crate::types::KvWrite::Set { key, value } => KvWrite { key, value: Some(value) },
+        crate::types::KvWrite::Delete { key } => KvWrite { key, value: None },
+    }
+}
+
+fn kv_write_from_proto(write: &KvWrite) -> crate::types::KvWrite {
+    match &write.value {
+        Some(value) => crate::types::KvWrite::Set {
+            key: write.key.clone(),
+            value: value.clone(),
+        },
+        None => crate::types::KvWrite::Delete { key: write.key.clone() },
+    }
+}
+
 async fn wait_for_shutdown_signal() {
     use tokio::signal;
     
@@ -391,3 +995,75 @@ async fn wait_for_shutdown_signal() {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::RpcResult;
+    use async_trait::async_trait;
+
+    /// Engine answering heartbeats with a cancellation and a drain request
+    struct MockEngine {
+        heartbeats: parking_lot::Mutex<Vec<HeartbeatRequest>>,
+    }
+
+    fn unavailable<T>() -> RpcResult<T> {
+        Err(RpcError::Connection("unavailable".to_string()))
+    }
+
+    #[async_trait]
+    impl EngineClient for MockEngine {
+        async fn register_worker(&self, _: RegisterWorkerRequest) -> RpcResult<RegisterWorkerResponse> {
+            unavailable()
+        }
+
+        async fn poll_tasks(&self, _: PollTasksRequest) -> RpcResult<PollTasksResponse> {
+            unavailable()
+        }
+
+        async fn fetch_task_code(&self, _: FetchTaskCodeRequest) -> RpcResult<FetchTaskCodeResponse> {
+            unavailable()
+        }
+
+        async fn complete_task(&self, _: CompleteTaskRequest) -> RpcResult<CompleteTaskResponse> {
+            unavailable()
+        }
+
+        async fn heartbeat(&self, request: HeartbeatRequest) -> RpcResult<HeartbeatResponse> {
+            self.heartbeats.lock().push(request);
+            Ok(HeartbeatResponse {
+                cancelled_task_ids: vec!["running".to_string()],
+                drain: true,
+                ..Default::default()
+            })
+        }
+
+        async fn deregister_worker(&self, _: DeregisterWorkerRequest) -> RpcResult<DeregisterWorkerResponse> {
+            unavailable()
+        }
+
+        async fn kv_get(&self, _: KvGetRequest) -> RpcResult<KvGetResponse> {
+            unavailable()
+        }
+
+        async fn kv_range(&self, _: KvRangeRequest) -> RpcResult<KvRangeResponse> {
+            unavailable()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_heartbeat_applies_engine_requests() {
+        let engine = Arc::new(MockEngine {
+            heartbeats: parking_lot::Mutex::new(Vec::new()),
+        });
+        let worker = Worker::with_client(engine.clone(), "mock").unwrap();
+        let (cancel_tx, cancelled) = oneshot::channel();
+        worker.running.lock().insert("running".to_string(), cancel_tx);
+
+        worker.send_heartbeat().await.unwrap();
+
+        assert_eq!(engine.heartbeats.lock()[0].running_task_ids, vec!["running".to_string()]);
+        assert!(cancelled.await.is_ok());
+        assert!(worker.draining.load(Ordering::SeqCst));
+    }
+}
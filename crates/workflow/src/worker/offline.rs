@@ -0,0 +1,131 @@
+//! Offline mode support for workers
+//!
+//! Completions that cannot be delivered while the engine is unreachable are
+//! kept in an on-disk outbox, so they survive worker restarts and are replayed
+//! once the engine can be reached again.
+
+use super::proto::TaskPayload;
+use crate::error::{EngineError, Result};
+use crate::types::TaskResult;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+
+/// A task completion waiting to be delivered to the engine
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BufferedCompletion {
+    pub task_id: String,
+    /// Attempt the result belongs to, checked by the engine as a fencing token
+    pub attempt: u32,
+    pub result: TaskResult,
+}
+
+/// Durable queue of undelivered task completions
+pub struct Outbox {
+    path: PathBuf,
+    entries: Mutex<Vec<BufferedCompletion>>,
+}
+
+impl Outbox {
+    /// Open the outbox stored at `path`, loading any buffered completions
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let entries = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).map_err(|e| {
+                EngineError::Internal(format!("Corrupt outbox {}: {}", path.display(), e))
+            })?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => {
+                return Err(EngineError::Internal(format!(
+                    "Failed to read outbox {}: {}",
+                    path.display(),
+                    e
+                )))
+            }
+        };
+
+        Ok(Self {
+            path,
+            entries: Mutex::new(entries),
+        })
+    }
+
+    /// Buffer a completion
+    pub fn push(&self, completion: BufferedCompletion) -> Result<()> {
+        let mut entries = self.entries.lock();
+        entries.retain(|c| c.task_id != completion.task_id);
+        entries.push(completion);
+        persist(&self.path, &entries)
+    }
+
+    /// Get all buffered completions, oldest first
+    pub fn pending(&self) -> Vec<BufferedCompletion> {
+        self.entries.lock().clone()
+    }
+
+    /// Drop a completion once the engine has accepted or rejected it
+    pub fn remove(&self, task_id: &str) -> Result<()> {
+        let mut entries = self.entries.lock();
+        entries.retain(|c| c.task_id != task_id);
+        persist(&self.path, &entries)
+    }
+
+    /// Number of buffered completions
+    pub fn len(&self) -> usize {
+        self.entries.lock().len()
+    }
+
+    /// Whether the outbox is empty
+    pub fn is_empty(&self) -> bool {
+        self.entries.lock().is_empty()
+    }
+}
+
+/// Atomically replace the outbox file
+fn persist(path: &Path, entries: &[BufferedCompletion]) -> Result<()> {
+    let bytes = serde_json::to_vec(entries)
+        .map_err(|e| EngineError::Internal(format!("Failed to encode outbox: {}", e)))?;
+
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, bytes)
+        .and_then(|_| std::fs::rename(&tmp, path))
+        .map_err(|e| {
+            EngineError::Internal(format!("Failed to write outbox {}: {}", path.display(), e))
+        })
+}
+
+/// Local state of a worker running in offline mode
+pub(super) struct OfflineState {
+    pub(super) outbox: Outbox,
+    queue: Mutex<VecDeque<TaskPayload>>,
+    prefetch: usize,
+}
+
+impl OfflineState {
+    pub(super) fn new(outbox: Outbox, prefetch: usize) -> Self {
+        Self {
+            outbox,
+            queue: Mutex::new(VecDeque::new()),
+            prefetch,
+        }
+    }
+
+    /// Number of tasks to keep fetched ahead of execution
+    pub(super) fn prefetch(&self) -> usize {
+        self.prefetch
+    }
+
+    /// Number of fetched tasks not yet executed
+    pub(super) fn queued(&self) -> usize {
+        self.queue.lock().len()
+    }
+
+    pub(super) fn push_task(&self, task: TaskPayload) {
+        self.queue.lock().push_back(task);
+    }
+
+    pub(super) fn pop_task(&self) -> Option<TaskPayload> {
+        self.queue.lock().pop_front()
+    }
+}
@@ -0,0 +1,148 @@
+//! Retries of calls to the engine
+//!
+//! A failed call is retried with exponential backoff and jitter. Only calls
+//! that are safe to repeat are retried by default: registration, heartbeats,
+//! task code fetches and key-value reads. Polls and completions change state
+//! on the engine and are retried only when opted in.
+//!
+//! Retries draw from a budget refilled by every call, so a worker facing an
+//! unreachable engine falls back to failing fast instead of multiplying its
+//! load.
+
+use crate::error::{RpcError, RpcResult};
+use crate::types::RetryPolicy;
+use parking_lot::Mutex;
+use std::future::Future;
+
+/// Retries available before any call refilled the budget, and the most the
+/// budget holds
+const MAX_RETRY_TOKENS: f64 = 10.0;
+
+/// Retries each call adds to the budget
+const DEFAULT_RETRY_RATIO: f64 = 0.2;
+
+/// Retry behaviour of engine calls
+pub struct RpcRetry {
+    policy: RetryPolicy,
+    retry_ratio: f64,
+    retry_non_idempotent: bool,
+    tokens: Mutex<f64>,
+}
+
+impl RpcRetry {
+    /// Retry idempotent calls following `policy`
+    pub fn new(policy: RetryPolicy) -> Self {
+        Self {
+            policy,
+            retry_ratio: DEFAULT_RETRY_RATIO,
+            retry_non_idempotent: false,
+            tokens: Mutex::new(MAX_RETRY_TOKENS),
+        }
+    }
+
+    /// Never retry calls
+    pub fn disabled() -> Self {
+        Self::new(RetryPolicy {
+            max_attempts: 1,
+            ..RetryPolicy::default()
+        })
+    }
+
+    /// Set how many retries each call adds to the budget
+    ///
+    /// With the default of 0.2, retries make up at most a fifth of the calls
+    /// once the initial budget is spent.
+    pub fn with_retry_ratio(mut self, ratio: f64) -> Self {
+        self.retry_ratio = ratio.max(0.0);
+        self
+    }
+
+    /// Also retry polls and completions
+    ///
+    /// A retried poll whose first response was lost leaves the tasks it
+    /// claimed to be requeued when their lease expires; a retried completion
+    /// whose first attempt went through is rejected as stale.
+    pub fn with_non_idempotent(mut self) -> Self {
+        self.retry_non_idempotent = true;
+        self
+    }
+
+    /// Make a call, retrying failures while the policy and budget allow
+    ///
+    /// An error returned after retrying carries the number of attempts made.
+    pub(super) async fn call<T, F, Fut>(&self, idempotent: bool, mut call: F) -> RpcResult<T>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = RpcResult<T>>,
+    {
+        self.refill();
+
+        let mut attempt = 0;
+        loop {
+            let error = match call().await {
+                Ok(response) => return Ok(response),
+                Err(e) => e,
+            };
+
+            let retryable = idempotent || self.retry_non_idempotent;
+            if !retryable || !self.policy.should_retry(attempt) || !self.take_token() {
+                return Err(match attempt {
+                    0 => error,
+                    _ => RpcError::Retried {
+                        attempts: attempt + 1,
+                        source: Box::new(error),
+                    },
+                });
+            }
+
+            let delay = self.policy.delay_with_jitter(attempt, rand::random::<f64>());
+            tracing::debug!("Engine call failed, retrying in {:?}: {}", delay, error);
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    fn refill(&self) {
+        let mut tokens = self.tokens.lock();
+        *tokens = (*tokens + self.retry_ratio).min(MAX_RETRY_TOKENS);
+    }
+
+    fn take_token(&self) -> bool {
+        let mut tokens = self.tokens.lock();
+        if *tokens < 1.0 {
+            return false;
+        }
+        *tokens -= 1.0;
+        true
+    }
+}
+
+impl Default for RpcRetry {
+    fn default() -> Self {
+        Self::new(RetryPolicy {
+            max_attempts: 3,
+            initial_delay_ms: 100,
+            max_delay_ms: 2_000,
+            backoff_multiplier: 2.0,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_budget_limits_retries() {
+        let retry = RpcRetry::default().with_retry_ratio(0.5);
+        for _ in 0..MAX_RETRY_TOKENS as usize {
+            assert!(retry.take_token());
+        }
+        assert!(!retry.take_token());
+
+        retry.refill();
+        assert!(!retry.take_token());
+        retry.refill();
+        assert!(retry.take_token());
+    }
+}
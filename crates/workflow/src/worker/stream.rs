@@ -0,0 +1,191 @@
+//! Task stream client
+//!
+//! Server-streaming calls use the Connect streaming protocol: the request and
+//! every response message travel in an envelope of one flag byte and a
+//! big-endian length, and the stream closes with an end-of-stream envelope
+//! carrying a JSON trailer.
+
+use super::proto::{StreamTasksRequest, StreamTasksResponse};
+use crate::error::{RpcError, RpcResult};
+use prost::Message;
+
+const STREAM_TASKS_PATH: &str = "/workflow.WorkflowService/StreamTasks";
+
+/// Envelope flag marking the end-of-stream trailer
+const END_STREAM_FLAG: u8 = 0x02;
+
+/// Envelope flag marking a compressed message, which is never requested
+const COMPRESSED_FLAG: u8 = 0x01;
+
+const ENVELOPE_HEADER_LEN: usize = 5;
+
+/// Open task stream
+pub(super) struct TaskStream {
+    response: reqwest::Response,
+    decoder: EnvelopeDecoder,
+    max_message_bytes: usize,
+}
+
+impl TaskStream {
+    /// Open a task stream on the engine at `engine_url`
+    pub(super) async fn open(
+        http: &reqwest::Client,
+        engine_url: &str,
+        request: &StreamTasksRequest,
+        max_message_bytes: usize,
+    ) -> RpcResult<Self> {
+        let response = http
+            .post(format!("{}{}", engine_url.trim_end_matches('/'), STREAM_TASKS_PATH))
+            .header(reqwest::header::CONTENT_TYPE, "application/connect+proto")
+            .header("connect-protocol-version", "1")
+            .body(encode_envelope(0, &request.encode_to_vec()))
+            .send()
+            .await
+            .map_err(|e| RpcError::Connection(e.to_string()))?;
+
+        if !response.status().is_success() {
+            return Err(RpcError::InvalidResponse(format!(
+                "task stream refused with status {}",
+                response.status()
+            )));
+        }
+
+        Ok(Self {
+            response,
+            decoder: EnvelopeDecoder::default(),
+            max_message_bytes,
+        })
+    }
+
+    /// Receive the next message, `None` once the engine closed the stream
+    pub(super) async fn next(&mut self) -> RpcResult<Option<StreamTasksResponse>> {
+        loop {
+            if let Some((flags, data)) = self.decoder.next(self.max_message_bytes)? {
+                if flags & END_STREAM_FLAG != 0 {
+                    return end_of_stream(&data).map(|()| None);
+                }
+                if flags & COMPRESSED_FLAG != 0 {
+                    return Err(RpcError::Protocol("unexpected compressed message".to_string()));
+                }
+                let message = StreamTasksResponse::decode(data.as_slice())
+                    .map_err(|e| RpcError::InvalidResponse(e.to_string()))?;
+                return Ok(Some(message));
+            }
+
+            let chunk = self
+                .response
+                .chunk()
+                .await
+                .map_err(|e| RpcError::Connection(e.to_string()))?;
+            match chunk {
+                Some(chunk) => self.decoder.push(&chunk),
+                None if self.decoder.is_empty() => {
+                    return Err(RpcError::Protocol("stream closed without a trailer".to_string()));
+                }
+                None => return Err(RpcError::Protocol("stream closed mid-message".to_string())),
+            }
+        }
+    }
+}
+
+/// Check the end-of-stream trailer for an error
+fn end_of_stream(trailer: &[u8]) -> RpcResult<()> {
+    if trailer.is_empty() {
+        return Ok(());
+    }
+    let trailer: serde_json::Value =
+        serde_json::from_slice(trailer).map_err(|e| RpcError::InvalidResponse(e.to_string()))?;
+    match trailer.get("error") {
+        Some(error) => Err(RpcError::InvalidResponse(format!(
+            "task stream failed: {}",
+            error
+                .get("message")
+                .and_then(|m| m.as_str())
+                .unwrap_or("unknown error")
+        ))),
+        None => Ok(()),
+    }
+}
+
+fn encode_envelope(flags: u8, data: &[u8]) -> Vec<u8> {
+    let mut envelope = Vec::with_capacity(ENVELOPE_HEADER_LEN + data.len());
+    envelope.push(flags);
+    envelope.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    envelope.extend_from_slice(data);
+    envelope
+}
+
+/// Splits a stream body into envelopes as its chunks arrive
+#[derive(Default)]
+struct EnvelopeDecoder {
+    buffer: Vec<u8>,
+}
+
+impl EnvelopeDecoder {
+    fn push(&mut self, chunk: &[u8]) {
+        self.buffer.extend_from_slice(chunk);
+    }
+
+    fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    /// Take the next complete envelope, rejecting ones over `max_len` bytes
+    fn next(&mut self, max_len: usize) -> RpcResult<Option<(u8, Vec<u8>)>> {
+        if self.buffer.len() < ENVELOPE_HEADER_LEN {
+            return Ok(None);
+        }
+        let flags = self.buffer[0];
+        let len = u32::from_be_bytes([self.buffer[1], self.buffer[2], self.buffer[3], self.buffer[4]]) as usize;
+        if len > max_len {
+            return Err(RpcError::ResourceExhausted(format!(
+                "stream message is {} bytes, exceeding the {} byte message limit",
+                len, max_len
+            )));
+        }
+        if self.buffer.len() < ENVELOPE_HEADER_LEN + len {
+            return Ok(None);
+        }
+
+        let data = self.buffer[ENVELOPE_HEADER_LEN..ENVELOPE_HEADER_LEN + len].to_vec();
+        self.buffer.drain(..ENVELOPE_HEADER_LEN + len);
+        Ok(Some((flags, data)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decoder_reassembles_split_envelopes() {
+        let mut body = encode_envelope(0, b"first");
+        body.extend(encode_envelope(END_STREAM_FLAG, b"{}"));
+
+        let mut decoder = EnvelopeDecoder::default();
+        for byte in &body[..7] {
+            decoder.push(&[*byte]);
+            assert!(decoder.next(1024).unwrap().is_none());
+        }
+        decoder.push(&body[7..]);
+
+        assert_eq!(decoder.next(1024).unwrap(), Some((0, b"first".to_vec())));
+        assert_eq!(decoder.next(1024).unwrap(), Some((END_STREAM_FLAG, b"{}".to_vec())));
+        assert!(decoder.is_empty());
+    }
+
+    #[test]
+    fn test_decoder_rejects_oversized_envelopes() {
+        let mut decoder = EnvelopeDecoder::default();
+        decoder.push(&encode_envelope(0, &[0; 16]));
+        assert!(matches!(decoder.next(8), Err(RpcError::ResourceExhausted(_))));
+    }
+
+    #[test]
+    fn test_end_of_stream_trailer() {
+        assert!(end_of_stream(b"").is_ok());
+        assert!(end_of_stream(b"{}").is_ok());
+        let failed = end_of_stream(br#"{"error":{"code":"internal","message":"boom"}}"#);
+        assert!(matches!(failed, Err(RpcError::InvalidResponse(message)) if message.contains("boom")));
+    }
+}